@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS audit_log (
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    session TEXT,
+    username TEXT,
+    method TEXT NOT NULL,
+    params TEXT NOT NULL DEFAULT 'null',
+    success BOOLEAN NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_username_idx ON audit_log (username);
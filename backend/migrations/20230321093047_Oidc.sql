@@ -0,0 +1,25 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS oidc_provider (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    issuer TEXT NOT NULL,
+    client_id TEXT NOT NULL,
+    client_secret TEXT,
+    username_claim TEXT NOT NULL DEFAULT 'preferred_username',
+    default_role TEXT NOT NULL DEFAULT 'viewer' CHECK (default_role IN ('admin', 'operator', 'viewer'))
+);
+CREATE TABLE IF NOT EXISTS oidc_login (
+    state TEXT NOT NULL PRIMARY KEY,
+    nonce TEXT NOT NULL,
+    redirect_uri TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE IF NOT EXISTS oidc_identity (
+    issuer TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    username TEXT NOT NULL UNIQUE REFERENCES users (username) ON DELETE CASCADE ON UPDATE CASCADE,
+    PRIMARY KEY (issuer, subject)
+);
+ALTER TABLE
+    users
+ADD
+    COLUMN source TEXT NOT NULL DEFAULT 'local';
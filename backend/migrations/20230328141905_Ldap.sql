@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS ldap_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    url TEXT NOT NULL,
+    user_dn TEXT NOT NULL,
+    group_base TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS ldap_group_role (
+    group_dn TEXT NOT NULL PRIMARY KEY,
+    role TEXT NOT NULL CHECK (role IN ('admin', 'operator', 'viewer'))
+);
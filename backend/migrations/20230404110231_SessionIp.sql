@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE session ADD COLUMN ip TEXT;
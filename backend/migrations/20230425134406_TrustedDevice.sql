@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS trusted_device (
+    id TEXT NOT NULL PRIMARY KEY,
+    username TEXT REFERENCES users (username) ON DELETE CASCADE,
+    user_agent TEXT,
+    read_only BOOLEAN NOT NULL DEFAULT FALSE,
+    metadata TEXT NOT NULL DEFAULT 'null',
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
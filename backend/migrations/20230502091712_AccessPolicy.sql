@@ -0,0 +1,11 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS access_policy (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    scope TEXT NOT NULL CHECK (scope IN ('login', 'all')),
+    allow_lan BOOLEAN NOT NULL,
+    allow_tor BOOLEAN NOT NULL
+);
+CREATE TABLE IF NOT EXISTS access_rule (
+    cidr TEXT NOT NULL PRIMARY KEY,
+    allow BOOLEAN NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE session ADD COLUMN last_ip TEXT;
+ALTER TABLE session ADD COLUMN last_host TEXT;
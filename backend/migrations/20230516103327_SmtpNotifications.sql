@@ -0,0 +1,17 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS smtp_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    host TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    security TEXT NOT NULL CHECK (security IN ('tls', 'starttls', 'none')),
+    username TEXT,
+    password TEXT,
+    from_address TEXT NOT NULL,
+    subject_template TEXT NOT NULL,
+    body_template TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS email_route (
+    level TEXT NOT NULL CHECK (level IN ('success', 'info', 'warning', 'error')),
+    address TEXT NOT NULL,
+    PRIMARY KEY (level, address)
+);
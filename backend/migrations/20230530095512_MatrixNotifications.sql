@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS matrix_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    homeserver TEXT NOT NULL,
+    access_token TEXT NOT NULL,
+    room_id TEXT NOT NULL,
+    min_level TEXT NOT NULL CHECK (min_level IN ('success', 'info', 'warning', 'error'))
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS telegram_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    bot_token TEXT NOT NULL,
+    chat_id TEXT NOT NULL,
+    min_level TEXT NOT NULL CHECK (min_level IN ('success', 'info', 'warning', 'error'))
+);
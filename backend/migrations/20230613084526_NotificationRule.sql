@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS notification_rule (
+    package_id TEXT NOT NULL PRIMARY KEY,
+    -- NULL mutes the package entirely
+    min_level TEXT CHECK (min_level IN ('success', 'info', 'warning', 'error'))
+);
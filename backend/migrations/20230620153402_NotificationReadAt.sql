@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS read_at TIMESTAMP;
+-- there is no record of what was seen before this, so start from a clean slate
+UPDATE notifications SET read_at = CURRENT_TIMESTAMP WHERE read_at IS NULL;
+CREATE INDEX IF NOT EXISTS notifications_unread_idx ON notifications (id) WHERE read_at IS NULL;
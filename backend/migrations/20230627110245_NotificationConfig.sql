@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS notification_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    -- 0 disables the limit
+    retention_days INTEGER NOT NULL DEFAULT 90 CHECK (retention_days >= 0),
+    max_rows INTEGER NOT NULL DEFAULT 10000 CHECK (max_rows >= 0)
+);
+INSERT INTO notification_config (id) VALUES (0) ON CONFLICT (id) DO NOTHING;
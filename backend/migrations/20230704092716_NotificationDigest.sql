@@ -0,0 +1,7 @@
+-- Add migration script here
+ALTER TABLE notification_config ADD COLUMN IF NOT EXISTS digest BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE notification_config ADD COLUMN IF NOT EXISTS last_digest_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+CREATE TABLE IF NOT EXISTS notification_digest (
+    id SERIAL PRIMARY KEY,
+    notification TEXT NOT NULL
+);
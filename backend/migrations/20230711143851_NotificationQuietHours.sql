@@ -0,0 +1,9 @@
+-- Add migration script here
+-- minutes after midnight UTC, both NULL when quiet hours are off
+ALTER TABLE notification_config ADD COLUMN IF NOT EXISTS quiet_start INTEGER CHECK (quiet_start >= 0 AND quiet_start < 1440);
+ALTER TABLE notification_config ADD COLUMN IF NOT EXISTS quiet_end INTEGER CHECK (quiet_end >= 0 AND quiet_end < 1440);
+ALTER TABLE notification_config ADD COLUMN IF NOT EXISTS quiet_errors BOOLEAN NOT NULL DEFAULT TRUE;
+CREATE TABLE IF NOT EXISTS notification_outbox (
+    id SERIAL PRIMARY KEY,
+    notification TEXT NOT NULL
+);
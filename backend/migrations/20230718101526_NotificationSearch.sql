@@ -0,0 +1,2 @@
+-- Add migration script here
+CREATE INDEX IF NOT EXISTS notifications_search_idx ON notifications USING GIN (to_tsvector('english', title || ' ' || message));
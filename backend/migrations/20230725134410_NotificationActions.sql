@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS actions TEXT NOT NULL DEFAULT '[]';
@@ -0,0 +1,4 @@
+-- Add migration script here
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS occurrences INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE notifications ADD COLUMN IF NOT EXISTS last_seen TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP;
+UPDATE notifications SET last_seen = created_at;
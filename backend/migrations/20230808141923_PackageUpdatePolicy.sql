@@ -0,0 +1,13 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS package_update_policy (
+    package_id TEXT PRIMARY KEY,
+    policy TEXT NOT NULL DEFAULT 'notify-only' CHECK (policy IN ('auto', 'notify-only')),
+    notified_version TEXT
+);
+CREATE TABLE IF NOT EXISTS auto_update_config (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    -- minutes after midnight UTC, both NULL when updates may be installed at any time
+    window_start INTEGER CHECK (window_start >= 0 AND window_start < 1440),
+    window_end INTEGER CHECK (window_end >= 0 AND window_end < 1440)
+);
+INSERT INTO auto_update_config (id) VALUES (0) ON CONFLICT (id) DO NOTHING;
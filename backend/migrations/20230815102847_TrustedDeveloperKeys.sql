@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS trusted_developer_keys (
+    -- RFC4648 base32, as shown for installed packages
+    pubkey TEXT PRIMARY KEY,
+    name TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS package_hooks (
+    id SERIAL PRIMARY KEY,
+    event TEXT NOT NULL CHECK (event IN ('pre-install', 'post-install', 'pre-update', 'post-update', 'pre-uninstall', 'post-uninstall')),
+    -- an http(s) url to POST to, or the absolute path of an executable
+    target TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS registry_credentials (
+    url TEXT PRIMARY KEY,
+    -- sent as a bearer token, or else username and password as basic auth
+    token TEXT,
+    username TEXT,
+    password TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((token IS NULL) <> (username IS NULL))
+);
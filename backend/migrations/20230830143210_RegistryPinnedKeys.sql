@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS registry_pinned_keys (
+    -- without a trailing slash
+    registry TEXT NOT NULL,
+    -- RFC4648 base32, as shown for installed packages
+    pubkey TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (registry, pubkey)
+);
@@ -0,0 +1,19 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS acme_accounts (
+    -- the url of the directory
+    provider TEXT NOT NULL PRIMARY KEY,
+    -- PKCS8 PEM, P-256
+    key TEXT NOT NULL,
+    -- the account url requests are signed with
+    url TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE TABLE IF NOT EXISTS acme_certs (
+    domain TEXT NOT NULL PRIMARY KEY,
+    -- PKCS8 PEM
+    key TEXT NOT NULL,
+    -- PEM, leaf first
+    fullchain TEXT NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS acme_dns_providers (
+    zone TEXT NOT NULL PRIMARY KEY,
+    -- JSON, with the credentials to publish TXT records in the zone
+    provider TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS wireguard_server (
+    id INTEGER PRIMARY KEY,
+    -- base64 X25519
+    key TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS wireguard_peers (
+    name TEXT NOT NULL PRIMARY KEY,
+    -- base64 X25519, kept to export the config of the peer again
+    key TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
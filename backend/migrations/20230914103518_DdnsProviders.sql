@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS ddns_providers (
+    hostname TEXT NOT NULL PRIMARY KEY,
+    -- json of the DdnsProvider, with its credentials
+    provider TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
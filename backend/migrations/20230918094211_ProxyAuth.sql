@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS proxy_auth (
+    package TEXT NOT NULL,
+    interface TEXT NOT NULL,
+    username TEXT NOT NULL,
+    -- argon2
+    password TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (package, interface)
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS bandwidth_usage (
+    package TEXT NOT NULL,
+    -- empty for the container as a whole
+    interface TEXT NOT NULL,
+    hour TIMESTAMP NOT NULL,
+    received BIGINT NOT NULL DEFAULT 0,
+    sent BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (package, interface, hour)
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS egress_tunnel (
+    id INTEGER PRIMARY KEY,
+    -- base64 X25519, of this end
+    private_key TEXT NOT NULL,
+    preshared_key TEXT
+);
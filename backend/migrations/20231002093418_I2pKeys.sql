@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS i2p_keys (
+    package TEXT NOT NULL,
+    interface TEXT NOT NULL,
+    -- in the base64 of I2P, as given by the SAM bridge
+    destination TEXT NOT NULL,
+    key TEXT NOT NULL,
+    PRIMARY KEY (package, interface)
+);
@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS imported_ca (
+    id INTEGER PRIMARY KEY,
+    key_pem TEXT NOT NULL,
+    -- the certificate of the key, followed by the ones above it up to the root CA
+    chain_pem TEXT NOT NULL
+);
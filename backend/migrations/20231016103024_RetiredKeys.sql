@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS retired_keys (
+    id SERIAL PRIMARY KEY,
+    -- NULL for the keys of the server itself
+    package TEXT,
+    interface TEXT,
+    key BYTEA NOT NULL CHECK (length(key) = 32),
+    tor_key BYTEA NOT NULL CHECK (length(tor_key) = 64),
+    retired_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
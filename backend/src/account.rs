@@ -9,7 +9,7 @@ use crate::net::keys::Key;
 use crate::net::ssl::{generate_key, make_root_cert};
 use crate::Error;
 
-fn hash_password(password: &str) -> Result<String, Error> {
+pub fn hash_password(password: &str) -> Result<String, Error> {
     argon2::hash_encoded(
         password.as_bytes(),
         &rand::random::<[u8; 16]>()[..],
@@ -0,0 +1,410 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use http::header::HOST;
+use ipnet::IpNet;
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::RequestParts;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::utils::{ClientAddr, ViaTor};
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Methods that can open a session, which are restricted under either scope
+const LOGIN_METHODS: &[&str] = &[
+    "auth.login",
+    "auth.refresh",
+    "auth.recover",
+    "auth.ssh-challenge",
+    "auth.oidc.start",
+    "auth.oidc.callback",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccessScope {
+    Login,
+    All,
+}
+impl AccessScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessScope::Login => "login",
+            AccessScope::All => "all",
+        }
+    }
+}
+impl fmt::Display for AccessScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for AccessScope {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "login" => Ok(AccessScope::Login),
+            "all" => Ok(AccessScope::All),
+            s => Err(Error::new(
+                eyre!("Invalid Access Scope: {}", s),
+                ErrorKind::ParseDbField,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AccessRule {
+    pub cidr: IpNet,
+    pub allow: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AccessPolicy {
+    pub scope: AccessScope,
+    pub allow_lan: bool,
+    pub allow_tor: bool,
+    pub rules: Vec<AccessRule>,
+}
+
+/// Where a request came from, as far as access rules are concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Origin {
+    /// Loopback connections that were not accepted from Tor, e.g. the CLI on the server itself
+    Local,
+    Tor,
+    Lan(IpAddr),
+    Remote(IpAddr),
+}
+impl Origin {
+    /// Tor is told apart by the address the connection was accepted on, since the client
+    /// controls its headers
+    pub fn of(req: &RequestParts) -> Self {
+        Self::new(client_ip(req), req.extensions.get::<ViaTor>().is_some())
+    }
+
+    pub fn new(ip: Option<IpAddr>, tor: bool) -> Self {
+        let ip = match ip {
+            Some(IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip)),
+            Some(ip) => ip,
+            None => return Origin::Local,
+        };
+        if tor {
+            Origin::Tor
+        } else if ip.is_loopback() {
+            Origin::Local
+        } else if is_lan(ip) {
+            Origin::Lan(ip)
+        } else {
+            Origin::Remote(ip)
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Local => write!(f, "local"),
+            Origin::Tor => write!(f, "tor"),
+            Origin::Lan(ip) => write!(f, "lan {}", ip),
+            Origin::Remote(ip) => write!(f, "clearnet {}", ip),
+        }
+    }
+}
+
+pub fn client_ip(req: &RequestParts) -> Option<IpAddr> {
+    req.extensions.get::<ClientAddr>().map(|a| a.0.ip())
+}
+
+/// The host the client addressed, e.g. an `.onion`, `.local` or clearnet domain
+pub fn request_host(req: &RequestParts) -> Option<&str> {
+    req.headers
+        .get(HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri.authority().map(|a| a.as_str()))
+}
+
+pub fn is_onion(host: &str) -> bool {
+    host.split(':')
+        .next()
+        .unwrap_or_default()
+        .ends_with(".onion")
+}
+
+fn is_lan(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // unique local (fc00::/7) or link local (fe80::/10)
+            first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+impl AccessPolicy {
+    /// Local connections are always permitted, so that the server can't be locked out of itself.
+    /// Deny rules take precedence over everything else.
+    pub fn permits(&self, origin: Origin) -> bool {
+        let allowed_by_rule = |ip: IpAddr| {
+            self.rules
+                .iter()
+                .filter(|r| r.cidr.contains(&ip))
+                .map(|r| r.allow)
+                .min()
+        };
+        match origin {
+            Origin::Local => true,
+            Origin::Tor => self.allow_tor,
+            Origin::Lan(ip) => allowed_by_rule(ip).unwrap_or(self.allow_lan),
+            Origin::Remote(ip) => allowed_by_rule(ip).unwrap_or(false),
+        }
+    }
+}
+
+pub async fn load_policy<Ex>(secrets: &mut Ex) -> Result<Option<AccessPolicy>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let policy = if let Some(policy) =
+        sqlx::query!("SELECT scope, allow_lan, allow_tor FROM access_policy WHERE id = 0")
+            .fetch_optional(&mut *secrets)
+            .await?
+    {
+        policy
+    } else {
+        return Ok(None);
+    };
+    let rules = sqlx::query!("SELECT cidr, allow FROM access_rule ORDER BY cidr")
+        .fetch_all(&mut *secrets)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok(AccessRule {
+                cidr: r.cidr.parse().with_kind(ErrorKind::ParseDbField)?,
+                allow: r.allow,
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    Ok(Some(AccessPolicy {
+        scope: policy.scope.parse()?,
+        allow_lan: policy.allow_lan,
+        allow_tor: policy.allow_tor,
+        rules,
+    }))
+}
+
+/// Enforced by the auth middleware before any other checks, and on the `/ws/` and `/rest/` paths
+/// of the UI server, which pass the path as the `method`
+#[instrument(skip_all)]
+pub async fn check(secrets: &PgPool, req: &RequestParts, method: &str) -> Result<(), Error> {
+    let policy = if let Some(policy) = load_policy(&mut secrets.acquire().await?).await? {
+        policy
+    } else {
+        return Ok(());
+    };
+    if policy.scope == AccessScope::Login && !LOGIN_METHODS.contains(&method) {
+        return Ok(());
+    }
+    let origin = Origin::of(req);
+    if !policy.permits(origin) {
+        return Err(Error::new(
+            eyre!("Access from {} is not permitted", origin),
+            ErrorKind::Authorization,
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses a change to the policy that would lock out the caller making it
+async fn ensure_caller_permitted<Ex>(secrets: &mut Ex, req: &RequestParts) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    if let Some(policy) = load_policy(secrets).await? {
+        if !policy.permits(Origin::of(req)) {
+            return Err(Error::new(
+                eyre!("This change would deny access to your current connection"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_cidr(arg: &str, _: &ArgMatches) -> Result<IpNet, Error> {
+    arg.parse()
+        .or_else(|_| arg.parse::<IpAddr>().map(IpNet::from))
+        .with_kind(ErrorKind::ParseNetAddress)
+}
+
+#[command(subcommands(get, set, disable, allow, deny, remove_rule))]
+pub fn access() -> Result<(), Error> {
+    Ok(())
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<AccessPolicy>, Error> {
+    load_policy(&mut ctx.secret_store.acquire().await?).await
+}
+
+/// Enables enforcement. Unless allowed here, LAN connections need a matching rule and Tor
+/// connections are refused.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg] scope: AccessScope,
+    #[arg(rename = "allow-lan", long = "allow-lan", default)] allow_lan: bool,
+    #[arg(rename = "allow-tor", long = "allow-tor", default)] allow_tor: bool,
+) -> Result<(), Error> {
+    let scope = scope.as_str();
+    let mut tx = ctx.secret_store.begin().await?;
+    sqlx::query!(
+        "INSERT INTO access_policy (id, scope, allow_lan, allow_tor) VALUES (0, $1, $2, $3) ON CONFLICT (id) DO UPDATE SET scope = EXCLUDED.scope, allow_lan = EXCLUDED.allow_lan, allow_tor = EXCLUDED.allow_tor",
+        scope,
+        allow_lan,
+        allow_tor,
+    )
+    .execute(&mut tx)
+    .await?;
+    ensure_caller_permitted(&mut tx, req).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM access_policy")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+async fn add_rule(
+    ctx: &RpcContext,
+    req: &RequestParts,
+    cidr: IpNet,
+    allow: bool,
+) -> Result<(), Error> {
+    let cidr = cidr.trunc().to_string();
+    let mut tx = ctx.secret_store.begin().await?;
+    sqlx::query!(
+        "INSERT INTO access_rule (cidr, allow) VALUES ($1, $2) ON CONFLICT (cidr) DO UPDATE SET allow = EXCLUDED.allow",
+        cidr,
+        allow,
+    )
+    .execute(&mut tx)
+    .await?;
+    ensure_caller_permitted(&mut tx, req).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn allow(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg(parse(parse_cidr))] cidr: IpNet,
+) -> Result<(), Error> {
+    add_rule(&ctx, req, cidr, true).await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn deny(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg(parse(parse_cidr))] cidr: IpNet,
+) -> Result<(), Error> {
+    add_rule(&ctx, req, cidr, false).await
+}
+
+#[command(rename = "remove-rule", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove_rule(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg(parse(parse_cidr))] cidr: IpNet,
+) -> Result<(), Error> {
+    let cidr = cidr.trunc().to_string();
+    let mut tx = ctx.secret_store.begin().await?;
+    if sqlx::query!("DELETE FROM access_rule WHERE cidr = $1", cidr)
+        .execute(&mut tx)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("No rule for {}", cidr),
+            ErrorKind::NotFound,
+        ));
+    }
+    ensure_caller_permitted(&mut tx, req).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[test]
+fn policy_precedence() {
+    let policy = AccessPolicy {
+        scope: AccessScope::All,
+        allow_lan: true,
+        allow_tor: false,
+        rules: vec![
+            AccessRule {
+                cidr: "203.0.113.0/24".parse().unwrap(),
+                allow: true,
+            },
+            AccessRule {
+                cidr: "203.0.113.7/32".parse().unwrap(),
+                allow: false,
+            },
+            AccessRule {
+                cidr: "192.168.1.0/24".parse().unwrap(),
+                allow: false,
+            },
+        ],
+    };
+    assert!(policy.permits(Origin::Local));
+    assert!(!policy.permits(Origin::Tor));
+    assert!(policy.permits(Origin::Lan("10.0.0.2".parse().unwrap())));
+    assert!(!policy.permits(Origin::Lan("192.168.1.20".parse().unwrap())));
+    assert!(policy.permits(Origin::Remote("203.0.113.8".parse().unwrap())));
+    assert!(!policy.permits(Origin::Remote("203.0.113.7".parse().unwrap())));
+    assert!(!policy.permits(Origin::Remote("198.51.100.1".parse().unwrap())));
+}
+
+#[test]
+fn origin_ignores_host() {
+    use crate::net::utils::{accepted_via_tor, TOR_LOOPBACK};
+
+    let loopback = Some("127.0.0.1".parse().unwrap());
+    assert_eq!(Origin::new(loopback, false), Origin::Local);
+    assert_eq!(Origin::new(loopback, true), Origin::Tor);
+    assert!(accepted_via_tor((TOR_LOOPBACK, 80).into()));
+    assert!(accepted_via_tor("[::ffff:127.0.0.2]:443".parse().unwrap()));
+    assert!(!accepted_via_tor("[::ffff:127.0.0.1]:80".parse().unwrap()));
+}
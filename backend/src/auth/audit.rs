@@ -0,0 +1,166 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ResultExt};
+
+/// Any param whose name contains one of these is replaced before it is written to the audit log
+const SENSITIVE_PARAMS: &[&str] = &["password", "secret", "key", "token"];
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditEntry {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub session: Option<String>,
+    pub username: Option<String>,
+    pub method: String,
+    pub params: Value,
+    pub success: bool,
+}
+
+#[command(subcommands(list))]
+pub fn audit() -> Result<(), Error> {
+    Ok(())
+}
+
+pub fn sanitize_params(params: &Value) -> Value {
+    match params {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let lower = k.to_lowercase();
+                    if SENSITIVE_PARAMS.iter().any(|s| lower.contains(s)) {
+                        (k.clone(), Value::String("<REDACTED>".to_owned()))
+                    } else {
+                        (k.clone(), sanitize_params(v))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(a) => Value::Array(a.iter().map(sanitize_params).collect()),
+        a => a.clone(),
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn record(
+    secrets: &PgPool,
+    session: Option<&str>,
+    method: &str,
+    params: &Value,
+    success: bool,
+) -> Result<(), Error> {
+    let params = serde_json::to_string(&sanitize_params(params))
+        .with_kind(crate::ErrorKind::Serialization)?;
+    sqlx::query!(
+        "INSERT INTO audit_log (session, username, method, params, success) VALUES ($1, (SELECT username FROM session WHERE id = $1), $2, $3, $4)",
+        session,
+        method,
+        params,
+        success,
+    )
+    .execute(secrets)
+    .await?;
+    Ok(())
+}
+
+fn parse_datetime(arg: &str, _: &ArgMatches) -> Result<DateTime<Utc>, Error> {
+    Ok(DateTime::parse_from_rfc3339(arg)
+        .with_kind(crate::ErrorKind::ParseTimestamp)?
+        .with_timezone(&Utc))
+}
+
+fn display_audit_log(arg: Vec<AuditEntry>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc =>
+        "ID",
+        "TIME",
+        "USER",
+        "SESSION",
+        "METHOD",
+        "PARAMS",
+        "SUCCESS",
+    ]);
+    for entry in arg {
+        table.add_row(row![
+            &format!("{}", entry.id),
+            &format!("{}", entry.created_at),
+            entry.username.as_deref().unwrap_or("N/A"),
+            entry.session.as_deref().unwrap_or("local"),
+            &entry.method,
+            &format!("{}", entry.params),
+            &format!("{}", entry.success),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_audit_log), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[arg(short = 'B', long = "before")] before: Option<i32>,
+    #[arg(short = 'l', long = "limit")] limit: Option<u32>,
+    #[arg(short = 'u', long = "username")] username: Option<String>,
+    #[arg(short = 'm', long = "method")] method: Option<String>,
+    #[arg(long = "since", parse(parse_datetime))] since: Option<DateTime<Utc>>,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<AuditEntry>, Error> {
+    let limit = limit.unwrap_or(40) as i64;
+    let since = since.map(|s| s.naive_utc());
+    sqlx::query!(
+        "SELECT id, created_at, session, username, method, params, success FROM audit_log WHERE ($1::int4 IS NULL OR id < $1) AND ($2::text IS NULL OR username = $2) AND ($3::text IS NULL OR method LIKE $3 || '%') AND ($4::timestamp IS NULL OR created_at >= $4) ORDER BY id DESC LIMIT $5",
+        before,
+        username,
+        method,
+        since,
+        limit,
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(AuditEntry {
+            id: r.id,
+            created_at: DateTime::from_utc(r.created_at, Utc),
+            session: r.session,
+            username: r.username,
+            method: r.method,
+            params: serde_json::from_str(&r.params).with_kind(crate::ErrorKind::Database)?,
+            success: r.success,
+        })
+    })
+    .collect()
+}
+
+#[test]
+fn sanitize() {
+    let params = serde_json::json!({
+        "id": "bitcoind",
+        "old-password": "hunter2",
+        "nested": [{ "secretKey": "abc", "value": 3 }],
+    });
+    assert_eq!(
+        sanitize_params(&params),
+        serde_json::json!({
+            "id": "bitcoind",
+            "old-password": "<REDACTED>",
+            "nested": [{ "secretKey": "<REDACTED>", "value": 3 }],
+        })
+    );
+}
@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::{RequestParts, ResponseParts};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use super::open_session;
+use crate::context::RpcContext;
+use crate::middleware::auth::HashSessionToken;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+pub const REFRESH_COOKIE: &str = "refresh";
+/// A remembered device is forgotten once it has gone this long without refreshing its session
+const DEVICE_IDLE_TIMEOUT: &str = "30 days";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrustedDevice {
+    pub id: String,
+    pub username: Option<String>,
+    pub user_agent: Option<String>,
+    pub read_only: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_used: DateTime<Utc>,
+}
+
+#[command(subcommands(list, forget))]
+pub fn device() -> Result<(), Error> {
+    Ok(())
+}
+
+fn refresh_cookie(token: &HashSessionToken) -> Result<http::HeaderValue, Error> {
+    http::HeaderValue::from_str(&format!(
+        "{}={}; Path=/rpc/; SameSite=Strict; HttpOnly; Max-Age={};",
+        REFRESH_COOKIE,
+        token.token(),
+        30 * 24 * 60 * 60
+    ))
+    .with_kind(ErrorKind::Unknown)
+}
+
+/// Issues a refresh token bound to the user agent of the request, so the device can open new
+/// sessions with `auth.refresh` after its current one expires
+#[instrument(skip_all)]
+pub async fn remember(
+    ctx: &RpcContext,
+    req: &RequestParts,
+    res: &mut ResponseParts,
+    metadata: &Value,
+    username: Option<&str>,
+    read_only: bool,
+) -> Result<(), Error> {
+    let token = HashSessionToken::new();
+    let id = token.hashed();
+    let user_agent = req.headers.get("user-agent").and_then(|h| h.to_str().ok());
+    let metadata = serde_json::to_string(metadata).with_kind(ErrorKind::Database)?;
+    sqlx::query!(
+        "INSERT INTO trusted_device (id, username, user_agent, read_only, metadata) VALUES ($1, $2, $3, $4, $5)",
+        id,
+        username,
+        user_agent,
+        read_only,
+        metadata,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    res.headers.append("set-cookie", refresh_cookie(&token)?);
+    Ok(())
+}
+
+/// Drops the refresh token presented with the request, if any
+#[instrument(skip_all)]
+pub async fn forget_current(ctx: &RpcContext, req: &RequestParts) -> Result<(), Error> {
+    if let Ok(token) = HashSessionToken::from_request_cookie(req, REFRESH_COOKIE) {
+        let id = token.hashed();
+        sqlx::query!("DELETE FROM trusted_device WHERE id = $1", id)
+            .execute(&ctx.secret_store)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Opens a new session for a remembered device, rotating its refresh token.
+/// The token is only honoured from the user agent it was issued to.
+#[command(display(display_none), metadata(authenticated = false))]
+#[instrument(skip_all)]
+pub async fn refresh(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[response] res: &mut ResponseParts,
+) -> Result<(), Error> {
+    let old = HashSessionToken::from_request_cookie(req, REFRESH_COOKIE)?;
+    let old_id = old.hashed();
+    let new = HashSessionToken::new();
+    let new_id = new.hashed();
+    let user_agent = req.headers.get("user-agent").and_then(|h| h.to_str().ok());
+    let device = sqlx::query!(
+        "UPDATE trusted_device SET id = $1, last_used = CURRENT_TIMESTAMP WHERE id = $2 AND user_agent IS NOT DISTINCT FROM $3 AND last_used > CURRENT_TIMESTAMP - $4::text::interval RETURNING username, read_only, metadata",
+        new_id,
+        old_id,
+        user_agent,
+        DEVICE_IDLE_TIMEOUT,
+    )
+    .fetch_optional(&ctx.secret_store)
+    .await?
+    .ok_or_else(|| {
+        Error::new(
+            eyre!("This device is not remembered"),
+            ErrorKind::Authorization,
+        )
+    })?;
+    let metadata: Value = serde_json::from_str(&device.metadata).with_kind(ErrorKind::Database)?;
+    open_session(
+        &ctx,
+        req,
+        res,
+        &metadata,
+        device.username.as_deref(),
+        device.read_only,
+    )
+    .await?;
+    res.headers.append("set-cookie", refresh_cookie(&new)?);
+    Ok(())
+}
+
+fn display_devices(arg: Vec<TrustedDevice>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc =>
+        "ID",
+        "USER",
+        "USER AGENT",
+        "READ ONLY",
+        "CREATED AT",
+        "LAST USED",
+    ]);
+    for device in arg {
+        table.add_row(row![
+            &device.id,
+            device.username.as_deref().unwrap_or("N/A"),
+            device.user_agent.as_deref().unwrap_or("N/A"),
+            &format!("{}", device.read_only),
+            &format!("{}", device.created_at),
+            &format!("{}", device.last_used),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_devices), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<TrustedDevice>, Error> {
+    Ok(sqlx::query!(
+        "SELECT id, username, user_agent, read_only, created_at, last_used FROM trusted_device WHERE last_used > CURRENT_TIMESTAMP - $1::text::interval ORDER BY last_used DESC",
+        DEVICE_IDLE_TIMEOUT
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| TrustedDevice {
+        id: r.id,
+        username: r.username,
+        user_agent: r.user_agent,
+        read_only: r.read_only,
+        created_at: DateTime::from_utc(r.created_at, Utc),
+        last_used: DateTime::from_utc(r.last_used, Utc),
+    })
+    .collect())
+}
+
+#[command(display(display_none))]
+#[instrument(skip_all)]
+pub async fn forget(#[context] ctx: RpcContext, #[arg] id: String) -> Result<(), Error> {
+    if sqlx::query!("DELETE FROM trusted_device WHERE id = $1", id)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("Device {} Not Found", id),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
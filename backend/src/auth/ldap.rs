@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use tracing::instrument;
+
+use super::user::Role;
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const LDAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LdapConfig {
+    pub url: Url,
+    /// DN to bind as, with `{username}` standing in for the login name
+    pub user_dn: String,
+    /// Subtree searched for groups listing the user as a `member`
+    pub group_base: String,
+    pub groups: BTreeMap<String, Role>,
+}
+
+#[command(subcommands(get, set, remove, map_group, unmap_group))]
+pub fn ldap() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config<Ex>(secrets: &mut Ex) -> Result<Option<LdapConfig>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let config = if let Some(config) =
+        sqlx::query!("SELECT url, user_dn, group_base FROM ldap_config WHERE id = 0")
+            .fetch_optional(&mut *secrets)
+            .await?
+    {
+        config
+    } else {
+        return Ok(None);
+    };
+    let mut groups = BTreeMap::new();
+    for group in sqlx::query!("SELECT group_dn, role FROM ldap_group_role")
+        .fetch_all(&mut *secrets)
+        .await?
+    {
+        groups.insert(group.group_dn, group.role.parse()?);
+    }
+    Ok(Some(LdapConfig {
+        url: config.url.parse().with_kind(ErrorKind::ParseUrl)?,
+        user_dn: config.user_dn,
+        group_base: config.group_base,
+        groups,
+    }))
+}
+
+/// Binds to the directory as the user and resolves their role from the groups they belong to.
+/// The highest role granted by any mapped group wins.
+#[instrument(skip_all)]
+async fn bind(config: &LdapConfig, username: &str, password: &str) -> Result<Role, Error> {
+    // an empty password would make this an anonymous bind, which most servers accept
+    if password.is_empty() {
+        return Err(Error::new(
+            eyre!("Password Incorrect"),
+            ErrorKind::IncorrectPassword,
+        ));
+    }
+    let (conn, mut ldap) = LdapConnAsync::with_settings(
+        LdapConnSettings::new().set_conn_timeout(LDAP_TIMEOUT),
+        config.url.as_str(),
+    )
+    .await
+    .with_kind(ErrorKind::Network)?;
+    ldap3::drive!(conn);
+    ldap.with_timeout(LDAP_TIMEOUT);
+
+    let dn = config.user_dn.replace("{username}", &dn_escape(username));
+    ldap.simple_bind(&dn, password)
+        .await
+        .with_kind(ErrorKind::Network)?
+        .success()
+        .map_err(|_| Error::new(eyre!("Password Incorrect"), ErrorKind::IncorrectPassword))?;
+    let (entries, _) = ldap
+        .search(
+            &config.group_base,
+            Scope::Subtree,
+            &format!("(member={})", ldap_escape(&dn)),
+            vec!["dn"],
+        )
+        .await
+        .with_kind(ErrorKind::Network)?
+        .success()
+        .with_kind(ErrorKind::Network)?;
+    ldap.unbind().await.with_kind(ErrorKind::Network)?;
+
+    let member_of: Vec<String> = entries
+        .into_iter()
+        .map(|e| SearchEntry::construct(e).dn.to_lowercase())
+        .collect();
+    config
+        .groups
+        .iter()
+        .filter(|(group, _)| member_of.contains(&group.to_lowercase()))
+        .map(|(_, role)| *role)
+        .max()
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("{} is not a member of any group mapped to a role", username),
+                ErrorKind::Authorization,
+            )
+        })
+}
+
+/// Attempts to authenticate a named user against the directory, if one is configured.
+/// Returns `false` without contacting the directory when LDAP is not set up or the name belongs
+/// to an account the directory did not create, so that local passwords keep working when the
+/// directory is unreachable and other accounts cannot be claimed through it.
+/// On success the user's role is refreshed from their current group membership.
+#[instrument(skip_all)]
+pub async fn login<Ex>(secrets: &mut Ex, username: &str, password: &str) -> Result<bool, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let config = if let Some(config) = load_config(&mut *secrets).await? {
+        config
+    } else {
+        return Ok(false);
+    };
+    if sqlx::query!("SELECT source FROM users WHERE username = $1", username)
+        .fetch_optional(&mut *secrets)
+        .await?
+        .map_or(false, |u| u.source != "ldap")
+    {
+        return Ok(false);
+    }
+    let role = bind(&config, username, password).await?;
+    let role = role.as_str();
+    // "!" is never a valid argon2 hash, so directory users cannot log in with a local password
+    sqlx::query!(
+        "INSERT INTO users (username, password, role, source) VALUES ($1, '!', $2, 'ldap') ON CONFLICT (username) DO UPDATE SET role = EXCLUDED.role WHERE users.source = 'ldap'",
+        username,
+        role,
+    )
+    .execute(&mut *secrets)
+    .await?;
+    Ok(true)
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<LdapConfig>, Error> {
+    load_config(&mut ctx.secret_store.acquire().await?).await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] url: Url,
+    #[arg(rename = "user-dn")] user_dn: String,
+    #[arg(rename = "group-base")] group_base: String,
+) -> Result<(), Error> {
+    if !matches!(url.scheme(), "ldap" | "ldaps") {
+        return Err(Error::new(
+            eyre!("LDAP URL must use the ldap or ldaps scheme"),
+            ErrorKind::ParseUrl,
+        ));
+    }
+    if !user_dn.contains("{username}") {
+        return Err(Error::new(
+            eyre!("User DN must contain {{username}}"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let url = url.to_string();
+    sqlx::query!(
+        "INSERT INTO ldap_config (id, url, user_dn, group_base) VALUES (0, $1, $2, $3) ON CONFLICT (id) DO UPDATE SET url = EXCLUDED.url, user_dn = EXCLUDED.user_dn, group_base = EXCLUDED.group_base",
+        url,
+        user_dn,
+        group_base,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM ldap_config")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+#[command(rename = "map-group", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn map_group(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "group-dn")] group_dn: String,
+    #[arg] role: Role,
+) -> Result<(), Error> {
+    let role = role.as_str();
+    sqlx::query!(
+        "INSERT INTO ldap_group_role (group_dn, role) VALUES ($1, $2) ON CONFLICT (group_dn) DO UPDATE SET role = EXCLUDED.role",
+        group_dn,
+        role,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(rename = "unmap-group", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn unmap_group(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "group-dn")] group_dn: String,
+) -> Result<(), Error> {
+    if sqlx::query!("DELETE FROM ldap_group_role WHERE group_dn = $1", group_dn)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("Group {} is not mapped", group_dn),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use clap::ArgMatches;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::middleware::auth::{LOGIN_ATTEMPT_LIMIT, LOGIN_ATTEMPT_WINDOW};
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::Error;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LockoutStatus {
+    pub locked: bool,
+    pub failed_attempts: usize,
+    pub attempt_limit: usize,
+    pub window_secs: u64,
+    /// Seconds until login attempts are accepted again, while locked
+    pub retry_after: Option<u64>,
+}
+
+#[command(subcommands(status, clear))]
+pub fn lockout() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_status(arg: LockoutStatus, matches: &ArgMatches) {
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    match arg.retry_after {
+        Some(secs) => println!(
+            "Locked after {} failed login attempts, retry in {}s",
+            arg.failed_attempts, secs
+        ),
+        None => println!(
+            "Not locked ({} of {} failed login attempts in the last {}s)",
+            arg.failed_attempts, arg.attempt_limit, arg.window_secs
+        ),
+    }
+}
+
+/// Reports whether brute-force protection is currently refusing login attempts
+#[command(display(display_status), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn status(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<LockoutStatus, Error> {
+    let attempts = ctx.login_attempts.lock().await;
+    let retry_after = attempts.remaining().map(|d| d.as_secs() + 1);
+    Ok(LockoutStatus {
+        locked: retry_after.is_some(),
+        failed_attempts: if attempts.in_window() {
+            attempts.failures
+        } else {
+            0
+        },
+        attempt_limit: LOGIN_ATTEMPT_LIMIT,
+        window_secs: LOGIN_ATTEMPT_WINDOW.as_secs(),
+        retry_after,
+    })
+}
+
+/// Forgets recent failed login attempts, lifting the lockout immediately
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn clear(#[context] ctx: RpcContext) -> Result<(), Error> {
+    ctx.login_attempts.lock().await.clear();
+    tracing::info!("Login lockout cleared");
+    Ok(())
+}
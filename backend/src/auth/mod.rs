@@ -0,0 +1,691 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use josekit::jwk::Jwk;
+use patch_db::{DbHandle, LockReceipt};
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::{RequestParts, ResponseParts};
+use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Executor, Postgres};
+use tracing::instrument;
+
+use crate::context::{CliContext, RpcContext};
+use crate::middleware::auth::{AsLogoutSessionId, HasLoggedOutSessions, HashSessionToken};
+use crate::middleware::encrypt::EncryptedWire;
+use crate::notifications::{NotificationLevel, NotificationType};
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{ensure_code, Error, ResultExt};
+
+pub mod access;
+pub mod audit;
+pub mod device;
+pub mod ldap;
+pub mod lockout;
+pub mod oidc;
+pub mod recovery;
+pub mod ssh;
+pub mod user;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PasswordType {
+    EncryptedWire(EncryptedWire),
+    String(String),
+}
+impl PasswordType {
+    /// Decrypts against the keypair passwords are encrypted to by a single secret, as the CLI
+    /// and setup contexts hold
+    pub fn decrypt_with_key(self, current_secret: impl AsRef<Jwk>) -> Result<String, Error> {
+        match self {
+            PasswordType::String(x) => Ok(x),
+            PasswordType::EncryptedWire(x) => x.decrypt(current_secret).ok_or_else(|| {
+                Error::new(
+                    color_eyre::eyre::eyre!("Couldn't decode password"),
+                    crate::ErrorKind::Unknown,
+                )
+            }),
+        }
+    }
+
+    /// Decrypts against the current or, during its grace period, the previous wire key
+    pub fn decrypt(self, ctx: &RpcContext) -> Result<String, Error> {
+        match self {
+            PasswordType::String(x) => Ok(x),
+            PasswordType::EncryptedWire(x) => {
+                ctx.wire_keys.read().unwrap().decrypt(x).ok_or_else(|| {
+                    Error::new(
+                        color_eyre::eyre::eyre!("Couldn't decode password"),
+                        crate::ErrorKind::Unknown,
+                    )
+                })
+            }
+        }
+    }
+}
+impl Default for PasswordType {
+    fn default() -> Self {
+        PasswordType::String(String::default())
+    }
+}
+impl std::fmt::Debug for PasswordType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<REDACTED_PASSWORD>")?;
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for PasswordType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match serde_json::from_str(s) {
+            Ok(a) => a,
+            Err(_) => PasswordType::String(s.to_string()),
+        })
+    }
+}
+
+#[command(subcommands(
+    login,
+    logout,
+    session,
+    reset_password,
+    get_pubkey,
+    rotate_key,
+    user::user,
+    audit::audit,
+    recovery::recovery,
+    recovery::recover,
+    oidc::oidc,
+    ldap::ldap,
+    ssh::challenge,
+    device::device,
+    device::refresh,
+    access::access,
+    lockout::lockout,
+))]
+pub fn auth() -> Result<(), Error> {
+    Ok(())
+}
+
+pub fn cli_metadata() -> Value {
+    serde_json::json!({
+        "platforms": ["cli"],
+    })
+}
+
+pub fn parse_metadata(_: &str, _: &ArgMatches) -> Result<Value, Error> {
+    Ok(cli_metadata())
+}
+
+#[test]
+fn gen_pwd() {
+    println!(
+        "{:?}",
+        argon2::hash_encoded(
+            b"testing1234",
+            &rand::random::<[u8; 16]>()[..],
+            &argon2::Config::default()
+        )
+        .unwrap()
+    )
+}
+
+#[instrument(skip_all)]
+async fn cli_login(
+    ctx: CliContext,
+    username: Option<String>,
+    password: Option<PasswordType>,
+    read_only: bool,
+    remember_device: bool,
+    ssh_key: Option<PathBuf>,
+    _ssh_challenge: Option<String>,
+    _ssh_signature: Option<String>,
+    metadata: Value,
+) -> Result<(), RpcError> {
+    let params = if let Some(ssh_key) = ssh_key {
+        let (challenge, signature) = ssh::sign_challenge(&ctx, &ssh_key).await?;
+        serde_json::json!({
+            "read-only": read_only,
+            "remember-device": remember_device,
+            "ssh-challenge": challenge,
+            "ssh-signature": signature,
+            "metadata": metadata,
+        })
+    } else {
+        let password = if let Some(password) = password {
+            password.decrypt_with_key(&ctx)?
+        } else {
+            rpassword::prompt_password("Password: ")?
+        };
+        serde_json::json!({
+            "username": username,
+            "password": password,
+            "read-only": read_only,
+            "remember-device": remember_device,
+            "metadata": metadata,
+        })
+    };
+
+    rpc_toolkit::command_helpers::call_remote(ctx, "auth.login", params, PhantomData::<()>)
+        .await?
+        .result?;
+
+    Ok(())
+}
+
+pub fn check_password(hash: &str, password: &str) -> Result<(), Error> {
+    ensure_code!(
+        argon2::verify_encoded(&hash, password.as_bytes()).map_err(|_| {
+            Error::new(
+                eyre!("Password Incorrect"),
+                crate::ErrorKind::IncorrectPassword,
+            )
+        })?,
+        crate::ErrorKind::IncorrectPassword,
+        "Password Incorrect"
+    );
+    Ok(())
+}
+
+pub async fn check_password_against_db<Ex>(secrets: &mut Ex, password: &str) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let pw_hash = sqlx::query!("SELECT password FROM account")
+        .fetch_one(secrets)
+        .await?
+        .password;
+    check_password(&pw_hash, password)?;
+    Ok(())
+}
+
+#[command(
+    custom_cli(cli_login(async, context(CliContext))),
+    display(display_none),
+    metadata(authenticated = false)
+)]
+#[instrument(skip_all)]
+pub async fn login(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[response] res: &mut ResponseParts,
+    #[arg] username: Option<String>,
+    #[arg] password: Option<PasswordType>,
+    #[arg(rename = "read-only", long = "read-only", default)] read_only: bool,
+    #[arg(rename = "remember-device", long = "remember-device", default)] remember_device: bool,
+    #[allow(unused_variables)]
+    #[arg(
+        rename = "ssh-key",
+        long = "ssh-key",
+        help = "CLI Only: Log in by signing a challenge with this private key instead of a password"
+    )]
+    ssh_key: Option<PathBuf>,
+    #[arg(
+        rename = "ssh-challenge",
+        long = "ssh-challenge",
+        help = "RPC Only: This value is provided by --ssh-key"
+    )]
+    ssh_challenge: Option<String>,
+    #[arg(
+        rename = "ssh-signature",
+        long = "ssh-signature",
+        help = "RPC Only: This value is provided by --ssh-key"
+    )]
+    ssh_signature: Option<String>,
+    #[arg(
+        parse(parse_metadata),
+        default = "cli_metadata",
+        help = "RPC Only: This value cannot be overidden from the cli"
+    )]
+    metadata: Value,
+) -> Result<(), Error> {
+    if let Some(signature) = ssh_signature {
+        // ssh keys are authorized for the whole server, so they open a master session
+        let challenge = ssh_challenge.ok_or_else(|| {
+            Error::new(
+                eyre!("ssh-signature requires ssh-challenge"),
+                crate::ErrorKind::InvalidRequest,
+            )
+        })?;
+        ssh::verify(&ctx, &challenge, &signature).await?;
+        open_session(&ctx, req, res, &metadata, None, read_only).await?;
+        if remember_device {
+            device::remember(&ctx, req, res, &metadata, None, read_only).await?;
+        }
+        return Ok(());
+    }
+    let password = password.unwrap_or_default().decrypt(&ctx)?;
+    let mut handle = ctx.secret_store.acquire().await?;
+    if let Some(username) = &username {
+        if let Err(e) = user::check_password_against_user(&mut handle, username, &password).await {
+            if !ldap::login(&mut handle, username, &password).await? {
+                return Err(e);
+            }
+        }
+    } else {
+        check_password_against_db(&mut handle, &password).await?;
+    }
+
+    drop(handle);
+
+    open_session(&ctx, req, res, &metadata, username.as_deref(), read_only).await?;
+    if remember_device {
+        device::remember(&ctx, req, res, &metadata, username.as_deref(), read_only).await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NewDeviceLogin {
+    pub username: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+impl NotificationType for NewDeviceLogin {
+    const CODE: i32 = 2;
+    const NAME: &'static str = "new-device-login";
+    fn summary(&self) -> Option<String> {
+        Some(format!(
+            "{} from {} ({})",
+            self.username.as_deref().unwrap_or("unknown user"),
+            self.ip
+                .map_or_else(|| "unknown address".to_owned(), |ip| ip.to_string()),
+            self.user_agent.as_deref().unwrap_or("unknown user agent"),
+        ))
+    }
+}
+
+/// Records a new session for an already authenticated caller and hands its token back as a cookie.
+/// Logins from a user agent and address that the account has not used before raise a warning.
+/// Read-only sessions are limited to commands open to viewers, whatever the role of their user.
+#[instrument(skip_all)]
+pub async fn open_session(
+    ctx: &RpcContext,
+    req: &RequestParts,
+    res: &mut ResponseParts,
+    metadata: &Value,
+    username: Option<&str>,
+    read_only: bool,
+) -> Result<(), Error> {
+    let hash_token = HashSessionToken::new();
+    let user_agent = req.headers.get("user-agent").and_then(|h| h.to_str().ok());
+    let ip = access::client_ip(req);
+    let ip_str = ip.map(|ip| ip.to_string());
+    let host = access::request_host(req);
+    let metadata = serde_json::to_string(metadata).with_kind(crate::ErrorKind::Database)?;
+    let hash_token_hashed = hash_token.hashed();
+    let mut tx = ctx.secret_store.begin().await?;
+    let known = sqlx::query!(
+        r#"SELECT COUNT(*) FILTER (WHERE user_agent IS NOT DISTINCT FROM $2 AND ip IS NOT DISTINCT FROM $3) AS "matching!", COUNT(*) AS "total!" FROM session WHERE username IS NOT DISTINCT FROM $1"#,
+        username,
+        user_agent,
+        ip_str,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO session (id, user_agent, metadata, username, ip, read_only, last_ip, last_host) VALUES ($1, $2, $3, $4, $5, $6, $5, $7)",
+        hash_token_hashed,
+        user_agent,
+        metadata,
+        username,
+        ip_str,
+        read_only,
+        host,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    // the very first login on an account has nothing to compare against
+    if known.matching == 0 && known.total > 0 {
+        let message = format!(
+            "{} logged in from a device not seen before: {} at {}",
+            username.unwrap_or("The master account"),
+            user_agent.unwrap_or("unknown user agent"),
+            ip_str.as_deref().unwrap_or("unknown address"),
+        );
+        if let Err(e) = ctx
+            .notification_manager
+            .notify(
+                &mut ctx.db.handle(),
+                None,
+                NotificationLevel::Warning,
+                "New Device Login".to_owned(),
+                message,
+                NewDeviceLogin {
+                    username: username.map(|u| u.to_owned()),
+                    user_agent: user_agent.map(|u| u.to_owned()),
+                    ip,
+                },
+                None,
+            )
+            .await
+        {
+            tracing::error!("Failed to notify of new device login: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+    res.headers.insert(
+        "set-cookie",
+        hash_token.header_value()?, // Should be impossible, but don't want to panic
+    );
+
+    Ok(())
+}
+
+#[command(display(display_none), metadata(authenticated = false))]
+#[instrument(skip_all)]
+pub async fn logout(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+) -> Result<Option<HasLoggedOutSessions>, Error> {
+    device::forget_current(&ctx, req).await?;
+    let auth = match HashSessionToken::from_request_parts(req) {
+        Err(_) => return Ok(None),
+        Ok(a) => a,
+    };
+    Ok(Some(HasLoggedOutSessions::new(vec![auth], &ctx).await?))
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Session {
+    logged_in: DateTime<Utc>,
+    last_active: DateTime<Utc>,
+    user_agent: Option<String>,
+    username: Option<String>,
+    read_only: bool,
+    /// Where the session was last used from, e.g. `lan 192.168.1.4` or `tor`
+    origin: String,
+    /// The host the session last addressed the server by
+    last_host: Option<String>,
+    metadata: Value,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SessionList {
+    current: String,
+    sessions: BTreeMap<String, Session>,
+}
+
+#[command(subcommands(list, kill, kill_all, downgrade))]
+pub async fn session() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_sessions(arg: SessionList, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc =>
+        "ID",
+        "LOGGED IN",
+        "LAST ACTIVE",
+        "USER AGENT",
+        "USER",
+        "READ ONLY",
+        "ORIGIN",
+        "LAST HOST",
+        "METADATA",
+    ]);
+    for (id, session) in arg.sessions {
+        let mut row = row![
+            &id,
+            &format!("{}", session.logged_in),
+            &format!("{}", session.last_active),
+            session.user_agent.as_deref().unwrap_or("N/A"),
+            session.username.as_deref().unwrap_or("N/A"),
+            &format!("{}", session.read_only),
+            &session.origin,
+            session.last_host.as_deref().unwrap_or("N/A"),
+            &format!("{}", session.metadata),
+        ];
+        if id == arg.current {
+            row.iter_mut()
+                .map(|c| c.style(Attr::ForegroundColor(color::GREEN)))
+                .collect::<()>()
+        }
+        table.add_row(row);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_sessions), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<SessionList, Error> {
+    Ok(SessionList {
+        current: HashSessionToken::from_request_parts(req)?.as_hash(),
+        sessions: sqlx::query!(
+            "SELECT * FROM session WHERE logged_out IS NULL OR logged_out > CURRENT_TIMESTAMP"
+        )
+        .fetch_all(&mut ctx.secret_store.acquire().await?)
+        .await?
+        .into_iter()
+        .map(|row| {
+            Ok((
+                row.id,
+                Session {
+                    logged_in: DateTime::from_utc(row.logged_in, Utc),
+                    last_active: DateTime::from_utc(row.last_active, Utc),
+                    user_agent: row.user_agent,
+                    username: row.username,
+                    read_only: row.read_only,
+                    // only the address and host are stored, which is enough to describe the session
+                    origin: access::Origin::new(
+                        row.last_ip.or(row.ip).and_then(|ip| ip.parse().ok()),
+                        row.last_host.as_deref().map_or(false, access::is_onion),
+                    )
+                    .to_string(),
+                    last_host: row.last_host,
+                    metadata: serde_json::from_str(&row.metadata)
+                        .with_kind(crate::ErrorKind::Database)?,
+                },
+            ))
+        })
+        .collect::<Result<_, Error>>()?,
+    })
+}
+
+fn parse_comma_separated(arg: &str, _: &ArgMatches) -> Result<Vec<String>, RpcError> {
+    Ok(arg.split(",").map(|s| s.trim().to_owned()).collect())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KillSessionId(String);
+
+impl AsLogoutSessionId for KillSessionId {
+    fn as_logout_session_id(self) -> String {
+        self.0
+    }
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn kill(
+    #[context] ctx: RpcContext,
+    #[arg(parse(parse_comma_separated))] ids: Vec<String>,
+) -> Result<(), Error> {
+    HasLoggedOutSessions::new(ids.into_iter().map(KillSessionId), &ctx).await?;
+    Ok(())
+}
+
+#[command(rename = "kill-all", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn kill_all(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[arg(rename = "except-current", long = "except-current", default)] except_current: bool,
+) -> Result<(), Error> {
+    let current = if except_current {
+        Some(HashSessionToken::from_request_parts(req)?.as_hash())
+    } else {
+        None
+    };
+    HasLoggedOutSessions::all(current.as_deref(), &ctx).await?;
+    // a lost device must not be able to open new sessions either
+    let spared = if except_current {
+        HashSessionToken::from_request_cookie(req, device::REFRESH_COOKIE)
+            .ok()
+            .map(|t| t.as_hash())
+    } else {
+        None
+    };
+    sqlx::query!(
+        "DELETE FROM trusted_device WHERE $1::text IS NULL OR id <> $1",
+        spared
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Restricts the calling session to read-only commands for the rest of its lifetime
+#[command(display(display_none))]
+#[instrument(skip_all)]
+pub async fn downgrade(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+) -> Result<(), Error> {
+    let current = HashSessionToken::from_request_parts(req)?.as_hash();
+    sqlx::query!("UPDATE session SET read_only = TRUE WHERE id = $1", current)
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn cli_reset_password(
+    ctx: CliContext,
+    old_password: Option<PasswordType>,
+    new_password: Option<PasswordType>,
+) -> Result<(), RpcError> {
+    let old_password = if let Some(old_password) = old_password {
+        old_password.decrypt_with_key(&ctx)?
+    } else {
+        rpassword::prompt_password("Current Password: ")?
+    };
+
+    let new_password = if let Some(new_password) = new_password {
+        new_password.decrypt_with_key(&ctx)?
+    } else {
+        let new_password = rpassword::prompt_password("New Password: ")?;
+        if new_password != rpassword::prompt_password("Confirm: ")? {
+            return Err(Error::new(
+                eyre!("Passwords do not match"),
+                crate::ErrorKind::IncorrectPassword,
+            )
+            .into());
+        }
+        new_password
+    };
+
+    rpc_toolkit::command_helpers::call_remote(
+        ctx,
+        "auth.reset-password",
+        serde_json::json!({ "old-password": old_password, "new-password": new_password }),
+        PhantomData::<()>,
+    )
+    .await?
+    .result?;
+
+    Ok(())
+}
+
+pub struct SetPasswordReceipt(LockReceipt<String, ()>);
+impl SetPasswordReceipt {
+    pub async fn new<Db: DbHandle>(db: &mut Db) -> Result<Self, Error> {
+        let mut locks = Vec::new();
+
+        let setup = Self::setup(&mut locks);
+        Ok(setup(&db.lock_all(locks).await?)?)
+    }
+
+    pub fn setup(
+        locks: &mut Vec<patch_db::LockTargetId>,
+    ) -> impl FnOnce(&patch_db::Verifier) -> Result<Self, Error> {
+        let password_hash = crate::db::DatabaseModel::new()
+            .server_info()
+            .password_hash()
+            .make_locker(patch_db::LockType::Write)
+            .add_to_keys(locks);
+        move |skeleton_key| Ok(Self(password_hash.verify(skeleton_key)?))
+    }
+}
+
+#[command(
+    rename = "reset-password",
+    custom_cli(cli_reset_password(async, context(CliContext))),
+    display(display_none),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn reset_password(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "old-password")] old_password: Option<PasswordType>,
+    #[arg(rename = "new-password")] new_password: Option<PasswordType>,
+) -> Result<(), Error> {
+    let old_password = old_password.unwrap_or_default().decrypt(&ctx)?;
+    let new_password = new_password.unwrap_or_default().decrypt(&ctx)?;
+
+    let mut account = ctx.account.write().await;
+    if !argon2::verify_encoded(&account.password, old_password.as_bytes())
+        .with_kind(crate::ErrorKind::IncorrectPassword)?
+    {
+        return Err(Error::new(
+            eyre!("Incorrect Password"),
+            crate::ErrorKind::IncorrectPassword,
+        ));
+    }
+    account.set_password(&new_password)?;
+    account.save(&ctx.secret_store).await?;
+    crate::db::DatabaseModel::new()
+        .server_info()
+        .password_hash()
+        .put(&mut ctx.db.handle(), &account.password)
+        .await?;
+
+    Ok(())
+}
+
+#[command(
+    rename = "get-pubkey",
+    display(display_none),
+    metadata(authenticated = false)
+)]
+#[instrument(skip_all)]
+pub async fn get_pubkey(#[context] ctx: RpcContext) -> Result<Jwk, RpcError> {
+    let secret = ctx.wire_keys.read().unwrap().current();
+    let pub_key = secret.to_public_key()?;
+    Ok(pub_key)
+}
+
+/// Replaces the keypair passwords are encrypted to. The previous keypair is still accepted for
+/// a grace period so in-flight submissions from clients holding the old public key succeed.
+#[command(rename = "rotate-key", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn rotate_key(#[context] ctx: RpcContext) -> Result<Jwk, RpcError> {
+    let secret = ctx.wire_keys.write().unwrap().rotate()?;
+    let pub_key = secret.to_public_key()?;
+    Ok(pub_key)
+}
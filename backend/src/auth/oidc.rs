@@ -0,0 +1,449 @@
+use std::time::SystemTime;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use josekit::jwk::JwkSet;
+use josekit::jws::{JwsVerifier, ES256, ES384, PS256, RS256, RS384, RS512};
+use josekit::jwt::{JwtPayload, JwtPayloadValidator};
+use reqwest::{Client, Url};
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::{RequestParts, ResponseParts};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use super::user::Role;
+use super::{cli_metadata, open_session, parse_metadata};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+/// How long a user has to complete the login at their provider before the pending state expires
+const LOGIN_TIMEOUT: &str = "10 minutes";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct OidcConfig {
+    pub issuer: Url,
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>,
+    pub username_claim: String,
+    pub default_role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: Url,
+    token_endpoint: Url,
+    jwks_uri: Url,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[command(subcommands(get, set, remove, start, callback))]
+pub fn oidc() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config(ctx: &RpcContext) -> Result<Option<OidcConfig>, Error> {
+    sqlx::query!("SELECT issuer, client_id, client_secret, username_claim, default_role FROM oidc_provider WHERE id = 0")
+        .fetch_optional(&ctx.secret_store)
+        .await?
+        .map(|r| {
+            Ok(OidcConfig {
+                issuer: r.issuer.parse().with_kind(ErrorKind::ParseUrl)?,
+                client_id: r.client_id,
+                client_secret: r.client_secret,
+                username_claim: r.username_claim,
+                default_role: r.default_role.parse()?,
+            })
+        })
+        .transpose()
+}
+
+async fn discover(client: &Client, issuer: &Url) -> Result<ProviderMetadata, Error> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.as_str().trim_end_matches('/')
+    );
+    let metadata: ProviderMetadata = client
+        .get(url)
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .error_for_status()
+        .with_kind(ErrorKind::Network)?
+        .json()
+        .await
+        .with_kind(ErrorKind::Deserialization)?;
+    if metadata.issuer.trim_end_matches('/') != issuer.as_str().trim_end_matches('/') {
+        return Err(Error::new(
+            eyre!(
+                "OIDC provider reported a different issuer: {}",
+                metadata.issuer
+            ),
+            ErrorKind::Authorization,
+        ));
+    }
+    Ok(metadata)
+}
+
+fn random_token() -> String {
+    base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        &rand::random::<[u8; 16]>(),
+    )
+    .to_lowercase()
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<OidcConfig>, Error> {
+    load_config(&ctx).await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] issuer: Url,
+    #[arg(rename = "client-id")] client_id: String,
+    #[arg(rename = "client-secret")] client_secret: Option<String>,
+    #[arg(rename = "username-claim")] username_claim: Option<String>,
+    #[arg(rename = "default-role")] default_role: Option<Role>,
+) -> Result<(), Error> {
+    // fail early if the provider is unreachable or misconfigured
+    discover(&ctx.client, &issuer).await?;
+    let issuer = issuer.to_string();
+    let username_claim = username_claim.unwrap_or_else(|| "preferred_username".to_owned());
+    let default_role = default_role.unwrap_or(Role::Viewer);
+    let default_role = default_role.as_str();
+    sqlx::query!(
+        r#"
+        INSERT INTO oidc_provider (
+            id,
+            issuer,
+            client_id,
+            client_secret,
+            username_claim,
+            default_role
+        ) VALUES (
+            0, $1, $2, $3, $4, $5
+        ) ON CONFLICT (id) DO UPDATE SET
+            issuer = EXCLUDED.issuer,
+            client_id = EXCLUDED.client_id,
+            client_secret = EXCLUDED.client_secret,
+            username_claim = EXCLUDED.username_claim,
+            default_role = EXCLUDED.default_role
+        "#,
+        issuer,
+        client_id,
+        client_secret,
+        username_claim,
+        default_role,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM oidc_provider")
+        .execute(&ctx.secret_store)
+        .await?;
+    sqlx::query!("DELETE FROM oidc_login")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+fn display_url(arg: Url, _: &ArgMatches) {
+    println!("{}", arg)
+}
+
+/// Begins a login at the configured provider, returning the URL the browser should be sent to
+#[command(display(display_url), metadata(authenticated = false))]
+#[instrument(skip_all)]
+pub async fn start(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "redirect-uri")] redirect_uri: Url,
+) -> Result<Url, Error> {
+    let config = load_config(&ctx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("No OIDC provider is configured"), ErrorKind::NotFound))?;
+    let provider = discover(&ctx.client, &config.issuer).await?;
+    let state = random_token();
+    let nonce = random_token();
+    let redirect_uri_str = redirect_uri.as_str();
+    sqlx::query!(
+        "DELETE FROM oidc_login WHERE created_at < CURRENT_TIMESTAMP - $1::text::interval",
+        LOGIN_TIMEOUT
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO oidc_login (state, nonce, redirect_uri) VALUES ($1, $2, $3)",
+        state,
+        nonce,
+        redirect_uri_str,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let mut url = provider.authorization_endpoint;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", redirect_uri.as_str())
+        .append_pair("scope", "openid profile")
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce);
+    Ok(url)
+}
+
+fn verifier_for(alg: &str, jwk: &josekit::jwk::Jwk) -> Result<Box<dyn JwsVerifier>, Error> {
+    let verifier: Box<dyn JwsVerifier> = match alg {
+        "RS256" => Box::new(
+            RS256
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        "RS384" => Box::new(
+            RS384
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        "RS512" => Box::new(
+            RS512
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        "PS256" => Box::new(
+            PS256
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        "ES256" => Box::new(
+            ES256
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        "ES384" => Box::new(
+            ES384
+                .verifier_from_jwk(jwk)
+                .with_kind(ErrorKind::Authorization)?,
+        ),
+        alg => {
+            return Err(Error::new(
+                eyre!("Unsupported ID token algorithm: {}", alg),
+                ErrorKind::Authorization,
+            ))
+        }
+    };
+    Ok(verifier)
+}
+
+#[instrument(skip_all)]
+async fn verify_id_token(
+    client: &Client,
+    provider: &ProviderMetadata,
+    config: &OidcConfig,
+    id_token: &str,
+    nonce: &str,
+) -> Result<JwtPayload, Error> {
+    let jwks = JwkSet::from_bytes(
+        client
+            .get(provider.jwks_uri.clone())
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .error_for_status()
+            .with_kind(ErrorKind::Network)?
+            .bytes()
+            .await
+            .with_kind(ErrorKind::Network)?,
+    )
+    .with_kind(ErrorKind::Deserialization)?;
+    let header = josekit::jwt::decode_header(id_token).with_kind(ErrorKind::Authorization)?;
+    let alg = header
+        .claim("alg")
+        .and_then(|a| a.as_str())
+        .unwrap_or_default()
+        .to_owned();
+    let jwk = match header.claim("kid").and_then(|k| k.as_str()) {
+        Some(kid) => jwks.get(kid).into_iter().next(),
+        None => jwks.keys().into_iter().next(),
+    }
+    .ok_or_else(|| {
+        Error::new(
+            eyre!("ID token was not signed by a known provider key"),
+            ErrorKind::Authorization,
+        )
+    })?;
+    let verifier = verifier_for(&alg, jwk)?;
+    let (payload, _) = josekit::jwt::decode_with_verifier(id_token, &*verifier)
+        .with_kind(ErrorKind::Authorization)?;
+
+    let mut validator = JwtPayloadValidator::new();
+    validator.set_issuer(&provider.issuer);
+    validator.set_audience(&config.client_id);
+    validator.set_base_time(SystemTime::now());
+    validator
+        .validate(&payload)
+        .with_kind(ErrorKind::Authorization)?;
+    if payload.claim("nonce").and_then(|n| n.as_str()) != Some(nonce) {
+        return Err(Error::new(
+            eyre!("ID token nonce mismatch"),
+            ErrorKind::Authorization,
+        ));
+    }
+    Ok(payload)
+}
+
+/// Completes a login begun with `auth.oidc.start`, opening a session for the user linked to the
+/// issuer and subject the provider vouched for. Unknown subjects are given a new user, named by
+/// the username claim, with the configured default role and no local password. A subject is
+/// never linked to a user that has a local password or is already linked to another subject.
+#[command(display(display_none), metadata(authenticated = false))]
+#[instrument(skip_all)]
+pub async fn callback(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+    #[response] res: &mut ResponseParts,
+    #[arg] code: String,
+    #[arg] state: String,
+    #[arg(
+        parse(parse_metadata),
+        default = "cli_metadata",
+        help = "RPC Only: This value cannot be overidden from the cli"
+    )]
+    metadata: Value,
+) -> Result<(), Error> {
+    let config = load_config(&ctx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("No OIDC provider is configured"), ErrorKind::NotFound))?;
+    let pending = sqlx::query!(
+        "DELETE FROM oidc_login WHERE state = $1 AND created_at >= CURRENT_TIMESTAMP - $2::text::interval RETURNING nonce, redirect_uri",
+        state,
+        LOGIN_TIMEOUT
+    )
+    .fetch_optional(&ctx.secret_store)
+    .await?
+    .ok_or_else(|| {
+        Error::new(
+            eyre!("Unknown or expired login attempt"),
+            ErrorKind::Authorization,
+        )
+    })?;
+    let provider = discover(&ctx.client, &config.issuer).await?;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", pending.redirect_uri.as_str()),
+        ("client_id", config.client_id.as_str()),
+    ];
+    if let Some(secret) = &config.client_secret {
+        form.push(("client_secret", secret.as_str()));
+    }
+    let token: TokenResponse = ctx
+        .client
+        .post(provider.token_endpoint.clone())
+        .form(&form)
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .error_for_status()
+        .with_kind(ErrorKind::Authorization)?
+        .json()
+        .await
+        .with_kind(ErrorKind::Deserialization)?;
+    let payload = verify_id_token(
+        &ctx.client,
+        &provider,
+        &config,
+        &token.id_token,
+        &pending.nonce,
+    )
+    .await?;
+    let subject = payload.subject().ok_or_else(|| {
+        Error::new(
+            eyre!("ID token is missing the sub claim"),
+            ErrorKind::Authorization,
+        )
+    })?;
+    let username = match sqlx::query!(
+        "SELECT username FROM oidc_identity WHERE issuer = $1 AND subject = $2",
+        provider.issuer,
+        subject,
+    )
+    .fetch_optional(&ctx.secret_store)
+    .await?
+    {
+        Some(r) => r.username,
+        None => link(&ctx, &config, &provider.issuer, subject, &payload).await?,
+    };
+    open_session(&ctx, req, res, &metadata, Some(&username), false).await
+}
+
+/// Links a subject seen for the first time to a new user named by its username claim, or to the
+/// user of that name if an OIDC login created it and no subject is linked to it. Local and
+/// directory users are never taken over.
+async fn link(
+    ctx: &RpcContext,
+    config: &OidcConfig,
+    issuer: &str,
+    subject: &str,
+    payload: &JwtPayload,
+) -> Result<String, Error> {
+    let username = payload
+        .claim(&config.username_claim)
+        .and_then(|u| u.as_str())
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("ID token is missing the {} claim", config.username_claim),
+                ErrorKind::Authorization,
+            )
+        })?;
+    let role = config.default_role.as_str();
+    let mut tx = ctx.secret_store.begin().await?;
+    // "!" is never a valid argon2 hash, so these users cannot log in with a local password
+    sqlx::query!(
+        "INSERT INTO users (username, password, role, source) VALUES ($1, '!', $2, 'oidc') ON CONFLICT (username) DO NOTHING",
+        username,
+        role,
+    )
+    .execute(&mut tx)
+    .await?;
+    let linked = sqlx::query!(
+        "INSERT INTO oidc_identity (issuer, subject, username) SELECT $1, $2, username FROM users WHERE username = $3 AND source = 'oidc' AND NOT EXISTS (SELECT 1 FROM oidc_identity WHERE username = $3)",
+        issuer,
+        subject,
+        username,
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected();
+    if linked == 0 {
+        return Err(Error::new(
+            eyre!(
+                "The user {} already exists and is not linked to this account",
+                username
+            ),
+            ErrorKind::Authorization,
+        ));
+    }
+    tx.commit().await?;
+    Ok(username.to_owned())
+}
@@ -0,0 +1,202 @@
+use std::marker::PhantomData;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use digest::Digest;
+use rpc_toolkit::command;
+use rpc_toolkit::yajrc::RpcError;
+use sha2::Sha256;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::{KillSessionId, PasswordType};
+use crate::context::{CliContext, RpcContext};
+use crate::middleware::auth::HasLoggedOutSessions;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+#[command(subcommands(generate))]
+pub fn recovery() -> Result<(), Error> {
+    Ok(())
+}
+
+fn new_code() -> String {
+    let code = base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        &rand::random::<[u8; 10]>(),
+    )
+    .to_lowercase();
+    code.as_bytes()
+        .chunks(4)
+        .map(|c| std::str::from_utf8(c).unwrap())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Codes are compared ignoring case, whitespace and separators, since they are usually typed
+/// back in from a printout
+fn hash_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let mut hasher = Sha256::new();
+    hasher.update(normalized.as_bytes());
+    base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        hasher.finalize().as_slice(),
+    )
+    .to_lowercase()
+}
+
+/// Replaces any outstanding recovery codes with a fresh set, returning them in plaintext.
+/// Only their hashes are stored.
+#[instrument(skip_all)]
+pub async fn generate_codes(secrets: &PgPool) -> Result<Vec<String>, Error> {
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| new_code()).collect();
+    let mut tx = secrets.begin().await?;
+    sqlx::query!("DELETE FROM recovery_codes")
+        .execute(&mut tx)
+        .await?;
+    for code in &codes {
+        let hash = hash_code(code);
+        sqlx::query!("INSERT INTO recovery_codes (hash) VALUES ($1)", hash)
+            .execute(&mut tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(codes)
+}
+
+fn display_codes(arg: Vec<String>, matches: &ArgMatches) {
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+    for code in arg {
+        println!("{}", code);
+    }
+}
+
+#[command(display(display_codes), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn generate(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<String>, Error> {
+    generate_codes(&ctx.secret_store).await
+}
+
+#[instrument(skip_all)]
+async fn cli_recover(
+    ctx: CliContext,
+    code: String,
+    new_password: Option<PasswordType>,
+) -> Result<(), RpcError> {
+    let new_password = if let Some(new_password) = new_password {
+        new_password.decrypt_with_key(&ctx)?
+    } else {
+        let new_password = rpassword::prompt_password("New Password: ")?;
+        if new_password != rpassword::prompt_password("Confirm: ")? {
+            return Err(Error::new(
+                eyre!("Passwords do not match"),
+                crate::ErrorKind::IncorrectPassword,
+            )
+            .into());
+        }
+        new_password
+    };
+
+    rpc_toolkit::command_helpers::call_remote(
+        ctx,
+        "auth.recover",
+        serde_json::json!({ "code": code, "new-password": new_password }),
+        PhantomData::<()>,
+    )
+    .await?
+    .result?;
+
+    Ok(())
+}
+
+#[command(
+    custom_cli(cli_recover(async, context(CliContext))),
+    display(display_none),
+    metadata(authenticated = false)
+)]
+#[instrument(skip_all)]
+pub async fn recover(
+    #[context] ctx: RpcContext,
+    #[arg] code: String,
+    #[arg(rename = "new-password")] new_password: Option<PasswordType>,
+) -> Result<(), Error> {
+    // the CLI prompts for it, but an empty password must never reach this unauthenticated endpoint
+    let new_password = new_password
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("A new password is required"),
+                ErrorKind::InvalidRequest,
+            )
+        })?
+        .decrypt(&ctx)?;
+    if new_password.is_empty() {
+        return Err(Error::new(
+            eyre!("A new password is required"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let hash = hash_code(&code);
+
+    let mut account = ctx.account.write().await;
+    let mut tx = ctx.secret_store.begin().await?;
+    if sqlx::query!(
+        "UPDATE recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE hash = $1 AND used_at IS NULL",
+        hash
+    )
+    .execute(&mut tx)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("Invalid Recovery Code"),
+            ErrorKind::IncorrectPassword,
+        ));
+    }
+    account.set_password(&new_password)?;
+    account.save(&mut tx).await?;
+    tx.commit().await?;
+    crate::db::DatabaseModel::new()
+        .server_info()
+        .password_hash()
+        .put(&mut ctx.db.handle(), &account.password)
+        .await?;
+    drop(account);
+
+    // sessions opened with the old password should not outlive it
+    let sessions =
+        sqlx::query!("SELECT id FROM session WHERE logged_out IS NULL AND username IS NULL")
+            .fetch_all(&ctx.secret_store)
+            .await?;
+    HasLoggedOutSessions::new(sessions.into_iter().map(|s| KillSessionId(s.id)), &ctx).await?;
+    sqlx::query!("DELETE FROM trusted_device WHERE username IS NULL")
+        .execute(&ctx.secret_store)
+        .await?;
+
+    Ok(())
+}
+
+#[test]
+fn code_normalization() {
+    let code = new_code();
+    assert_eq!(code.len(), 19);
+    assert_eq!(
+        hash_code(&code),
+        hash_code(&code.to_uppercase().replace('-', " "))
+    );
+}
@@ -0,0 +1,156 @@
+use std::marker::PhantomData;
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+use rpc_toolkit::command;
+use rpc_toolkit::command_helpers::prelude::RequestParts;
+use rpc_toolkit::yajrc::RpcError;
+use ssh_key::{HashAlg, LineEnding, PrivateKey, PublicKey, SshSig};
+use tracing::instrument;
+
+use crate::auth::access;
+use crate::context::{CliContext, RpcContext};
+use crate::middleware::auth::LOGIN_ATTEMPT_LIMIT;
+use crate::util::display_none;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Signatures are made over this namespace so they cannot be replayed as signatures for any other
+/// purpose, such as git commits
+const NAMESPACE: &str = "startos-login";
+/// How long a challenge remains valid after it is issued
+const CHALLENGE_TIMEOUT: &str = "2 minutes";
+
+/// Issues a single-use challenge for `auth.login` to be signed with a key registered via
+/// `ssh.add`. Each client may only hold a few outstanding challenges at once.
+#[command(
+    rename = "ssh-challenge",
+    display(display_none),
+    metadata(authenticated = false)
+)]
+#[instrument(skip_all)]
+pub async fn challenge(
+    #[context] ctx: RpcContext,
+    #[request] req: &RequestParts,
+) -> Result<String, Error> {
+    let ip = access::client_ip(req).map(|ip| ip.to_string());
+    let challenge = base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        &rand::random::<[u8; 32]>(),
+    )
+    .to_lowercase();
+    sqlx::query!(
+        "DELETE FROM ssh_challenge WHERE created_at < CURRENT_TIMESTAMP - $1::text::interval",
+        CHALLENGE_TIMEOUT
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    // counted per client, so that one client requesting challenges endlessly cannot keep
+    // everyone else from logging in with their keys
+    if sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM ssh_challenge WHERE ip IS NOT DISTINCT FROM $1",
+        ip
+    )
+    .fetch_one(&ctx.secret_store)
+    .await?
+    .count
+        >= LOGIN_ATTEMPT_LIMIT as i64
+    {
+        return Err(Error::new(
+            eyre!(
+                "Please limit SSH login challenges to {} per {}.",
+                LOGIN_ATTEMPT_LIMIT,
+                CHALLENGE_TIMEOUT
+            ),
+            ErrorKind::RateLimited,
+        ));
+    }
+    sqlx::query!(
+        "INSERT INTO ssh_challenge (challenge, ip) VALUES ($1, $2)",
+        challenge,
+        ip
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(challenge)
+}
+
+/// Consumes the challenge and checks that the signature over it was made by one of the
+/// registered ssh keys
+#[instrument(skip_all)]
+pub async fn verify(ctx: &RpcContext, challenge: &str, signature: &str) -> Result<(), Error> {
+    let incorrect = || {
+        Error::new(
+            eyre!("Signature does not match any registered SSH key"),
+            ErrorKind::IncorrectPassword,
+        )
+    };
+    if sqlx::query!(
+        "DELETE FROM ssh_challenge WHERE challenge = $1 AND created_at >= CURRENT_TIMESTAMP - $2::text::interval",
+        challenge,
+        CHALLENGE_TIMEOUT
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("Unknown or expired challenge"),
+            ErrorKind::Authorization,
+        ));
+    }
+    let signature = SshSig::from_pem(signature).map_err(|_| incorrect())?;
+    for key in sqlx::query!("SELECT openssh_pubkey FROM ssh_keys")
+        .fetch_all(&ctx.secret_store)
+        .await?
+    {
+        let key = match PublicKey::from_openssh(&key.openssh_pubkey) {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::warn!("Skipping unparseable SSH key: {}", e);
+                continue;
+            }
+        };
+        if key.key_data() == signature.public_key()
+            && key
+                .verify(NAMESPACE, challenge.as_bytes(), &signature)
+                .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err(incorrect())
+}
+
+/// Fetches a challenge from the server and signs it with the private key at `path`, returning the
+/// challenge and the armored signature
+#[instrument(skip_all)]
+pub async fn sign_challenge(ctx: &CliContext, path: &Path) -> Result<(String, String), RpcError> {
+    let mut key = PrivateKey::from_openssh(
+        tokio::fs::read_to_string(path)
+            .await
+            .with_ctx(|_| (ErrorKind::Filesystem, path.display().to_string()))?,
+    )
+    .with_kind(ErrorKind::ParseSshKey)?;
+    if key.is_encrypted() {
+        let passphrase =
+            rpassword::prompt_password(format!("Enter passphrase for {}: ", path.display()))?;
+        key = key
+            .decrypt(passphrase)
+            .map_err(|_| Error::new(eyre!("Incorrect passphrase"), ErrorKind::IncorrectPassword))?;
+    }
+    let challenge: String = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "auth.ssh-challenge",
+        serde_json::json!({}),
+        PhantomData::<String>,
+    )
+    .await?
+    .result?;
+    let signature = key
+        .sign(NAMESPACE, HashAlg::Sha512, challenge.as_bytes())
+        .with_kind(ErrorKind::ParseSshKey)?
+        .to_pem(LineEnding::LF)
+        .with_kind(ErrorKind::ParseSshKey)?;
+    Ok((challenge, signature))
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use rpc_toolkit::{command, Metadata};
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use tracing::instrument;
+
+use super::{check_password, KillSessionId, PasswordType};
+use crate::account::hash_password;
+use crate::context::RpcContext;
+use crate::middleware::auth::HasLoggedOutSessions;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// Roles are ordered by privilege: a caller may run any command whose required role is at or
+/// below their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+    /// Commands declare their requirements through metadata:
+    /// `admin = true` restricts a command to admins, `read_only = true` opens it to viewers,
+    /// and everything else requires an operator.
+    pub fn required<M: Metadata>(metadata: &M, method: &str) -> Self {
+        if metadata.get(method, "admin").unwrap_or(false) {
+            Role::Admin
+        } else if metadata.get(method, "read_only").unwrap_or(false) {
+            Role::Viewer
+        } else {
+            Role::Operator
+        }
+    }
+}
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for Role {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            s => Err(Error::new(
+                eyre!("Invalid Role: {}", s),
+                ErrorKind::ParseDbField,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UserInfo {
+    pub username: String,
+    pub role: Role,
+    pub created_at: DateTime<Utc>,
+}
+
+#[command(subcommands(add, remove, list, set_role, set_password))]
+pub fn user() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Looks up the role of a named user, erroring if the user no longer exists
+pub async fn get_role<Ex>(secrets: &mut Ex, username: &str) -> Result<Role, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    sqlx::query!("SELECT role FROM users WHERE username = $1", username)
+        .fetch_optional(secrets)
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("User {} Not Found", username),
+                ErrorKind::Authorization,
+            )
+        })?
+        .role
+        .parse()
+}
+
+pub async fn check_password_against_user<Ex>(
+    secrets: &mut Ex,
+    username: &str,
+    password: &str,
+) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let pw_hash = sqlx::query!("SELECT password FROM users WHERE username = $1", username)
+        .fetch_optional(secrets)
+        .await?
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("Password Incorrect"),
+                crate::ErrorKind::IncorrectPassword,
+            )
+        })?
+        .password;
+    check_password(&pw_hash, password)?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] username: String,
+    #[arg] password: PasswordType,
+    #[arg] role: Role,
+) -> Result<(), Error> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(Error::new(
+            eyre!("Username cannot be empty"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let password = hash_password(&password.decrypt(&ctx)?)?;
+    let role = role.as_str();
+    if sqlx::query!(
+        "INSERT INTO users (username, password, role) VALUES ($1, $2, $3) ON CONFLICT (username) DO NOTHING",
+        username,
+        password,
+        role,
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("User {} already exists", username),
+            ErrorKind::Duplicate,
+        ));
+    }
+    Ok(())
+}
+
+async fn logout_user(ctx: &RpcContext, username: &str) -> Result<(), Error> {
+    let sessions = sqlx::query!(
+        "SELECT id FROM session WHERE username = $1 AND logged_out IS NULL",
+        username
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?;
+    HasLoggedOutSessions::new(sessions.into_iter().map(|s| KillSessionId(s.id)), ctx).await?;
+    sqlx::query!("DELETE FROM trusted_device WHERE username = $1", username)
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] username: String) -> Result<(), Error> {
+    logout_user(&ctx, &username).await?;
+    if sqlx::query!("DELETE FROM users WHERE username = $1", username)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("User {} Not Found", username),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+fn display_users(arg: Vec<UserInfo>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "USERNAME", "ROLE", "CREATED AT"]);
+    for user in arg {
+        table.add_row(row![
+            &user.username,
+            user.role.as_str(),
+            &format!("{}", user.created_at),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_users), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<UserInfo>, Error> {
+    sqlx::query!("SELECT username, role, created_at FROM users ORDER BY username")
+        .fetch_all(&ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok(UserInfo {
+                username: r.username,
+                role: r.role.parse()?,
+                created_at: DateTime::from_utc(r.created_at, Utc),
+            })
+        })
+        .collect()
+}
+
+#[command(rename = "set-role", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set_role(
+    #[context] ctx: RpcContext,
+    #[arg] username: String,
+    #[arg] role: Role,
+) -> Result<(), Error> {
+    let role = role.as_str();
+    if sqlx::query!(
+        "UPDATE users SET role = $1 WHERE username = $2",
+        role,
+        username
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("User {} Not Found", username),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+#[command(rename = "set-password", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set_password(
+    #[context] ctx: RpcContext,
+    #[arg] username: String,
+    #[arg] password: PasswordType,
+) -> Result<(), Error> {
+    let password = hash_password(&password.decrypt(&ctx)?)?;
+    if sqlx::query!(
+        "UPDATE users SET password = $1 WHERE username = $2",
+        password,
+        username
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("User {} Not Found", username),
+            ErrorKind::NotFound,
+        ));
+    }
+    logout_user(&ctx, &username).await
+}
+
+#[test]
+fn role_ordering() {
+    assert!(Role::Admin > Role::Operator);
+    assert!(Role::Operator > Role::Viewer);
+    for role in [Role::Viewer, Role::Operator, Role::Admin] {
+        assert_eq!(role.as_str().parse::<Role>().unwrap(), role);
+    }
+}
@@ -163,6 +163,40 @@ pub async fn backup_all(
     Ok(())
 }
 
+/// Backs up `id` alone to `target_id` and waits for it to finish, so that the package can be
+/// restored from the target once it has been uninstalled
+#[instrument(skip_all)]
+pub async fn backup_package(
+    ctx: &RpcContext,
+    target_id: BackupTargetId,
+    password: &str,
+    id: &PackageId,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    check_password_against_db(&mut ctx.secret_store.acquire().await?, password).await?;
+    let fs = target_id
+        .load(&mut ctx.secret_store.acquire().await?)
+        .await?;
+    let backup_guard =
+        BackupMountGuard::mount(TmpMountGuard::mount(&fs, ReadWrite).await?, password).await?;
+    let package_ids = BTreeSet::from([id.clone()]);
+    assure_backing_up(&mut db, &package_ids).await?;
+    let res = perform_backup(ctx, &mut db, backup_guard, &package_ids).await;
+    crate::db::DatabaseModel::new()
+        .server_info()
+        .status_info()
+        .backup_progress()
+        .delete(&mut db)
+        .await?;
+    if let Some(e) = res?.remove(id).and_then(|report| report.error) {
+        return Err(Error::new(
+            eyre!("Backup of {} failed: {}", id, e),
+            ErrorKind::Backup,
+        ));
+    }
+    Ok(())
+}
+
 #[instrument(skip_all)]
 async fn assure_backing_up(
     db: &mut PatchDbHandle,
@@ -229,6 +263,12 @@ async fn perform_backup<Db: DbHandle>(
         } else {
             continue;
         };
+        let label = installed_model
+            .clone()
+            .label()
+            .get(&mut tx)
+            .await?
+            .into_owned();
         let main_status_model = installed_model.clone().status().main();
 
         main_status_model.lock(&mut tx, LockType::Write).await?;
@@ -242,6 +282,7 @@ async fn perform_backup<Db: DbHandle>(
                 backup_report.insert(
                     package_id,
                     PackageBackupReport {
+                        label,
                         error: Some(
                             "Can't do backup because service is in a backing up state".to_owned(),
                         ),
@@ -293,6 +334,7 @@ async fn perform_backup<Db: DbHandle>(
         backup_report.insert(
             package_id.clone(),
             PackageBackupReport {
+                label,
                 error: res.as_ref().err().map(|e| e.to_string()),
             },
         );
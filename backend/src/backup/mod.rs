@@ -38,6 +38,22 @@ pub struct BackupReport {
     server: ServerBackupReport,
     packages: BTreeMap<PackageId, PackageBackupReport>,
 }
+impl BackupReport {
+    pub fn has_failures(&self) -> bool {
+        self.server.error.is_some() || self.packages.values().any(|p| p.error.is_some())
+    }
+    pub fn describe(&self) -> String {
+        if let Some(error) = &self.server.error {
+            return format!("Server backup failed: {}", error);
+        }
+        let failed = self.packages.values().filter(|p| p.error.is_some()).count();
+        if failed > 0 {
+            format!("{} of {} services failed", failed, self.packages.len())
+        } else {
+            format!("{} services backed up", self.packages.len())
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ServerBackupReport {
@@ -47,6 +63,9 @@ pub struct ServerBackupReport {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct PackageBackupReport {
+    /// The label of the package when it was backed up, see `package label`
+    #[serde(default)]
+    label: Option<String>,
     error: Option<String>,
 }
 
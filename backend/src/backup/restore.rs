@@ -335,7 +335,8 @@ async fn assure_restoring(
         let s9pk_path = Path::new(BACKUP_DIR).join(&id).join(format!("{}.s9pk", id));
         let mut rdr = S9pkReader::open(&s9pk_path, false).await?;
 
-        let manifest = rdr.manifest().await?;
+        let mut manifest = rdr.manifest().await?;
+        crate::install::instance::adopt(&mut manifest, &id);
         let version = manifest.version.clone();
         let progress = InstallProgress::new(Some(tokio::fs::metadata(&s9pk_path).await?.len()));
 
@@ -93,6 +93,13 @@ async fn setup_or_init(cfg_path: Option<PathBuf>) -> Result<(), Error> {
         )
         .await?;
 
+        let access_point = ctx.access_point_interface.clone().map(|interface| {
+            tokio::spawn(crate::net::access_point::launch_setup_task(
+                interface,
+                ctx.shutdown.subscribe(),
+            ))
+        });
+
         tokio::time::sleep(Duration::from_secs(1)).await; // let the record state that I hate this
         CHIME.play().await?;
         ctx.shutdown
@@ -102,6 +109,9 @@ async fn setup_or_init(cfg_path: Option<PathBuf>) -> Result<(), Error> {
             .expect("context dropped");
 
         server.shutdown().await;
+        if let Some(access_point) = access_point {
+            access_point.await.unwrap_or_default();
+        }
 
         tokio::task::yield_now().await;
         if let Err(e) = Command::new("killall")
@@ -8,7 +8,17 @@ use tokio::signal::unix::signal;
 use tracing::instrument;
 
 use crate::context::{DiagnosticContext, RpcContext};
+use crate::install::auto_update::launch_auto_update_task;
+use crate::install::disk_usage::launch_disk_usage_task;
+use crate::install::gc::launch_gc_task;
+use crate::marketplace::launch_mirror_check_task;
+use crate::net::access_point::launch_access_point_task;
+use crate::net::acme::launch_renewal_task;
+use crate::net::bandwidth::launch_bandwidth_task;
+use crate::net::ddns::launch_ddns_task;
+use crate::net::egress::launch_kill_switch_task;
 use crate::net::web_server::WebServer;
+use crate::notifications::launch_maintenance_task;
 use crate::shutdown::Shutdown;
 use crate::system::launch_metrics_task;
 use crate::util::logger::EmbassyLogger;
@@ -72,6 +82,56 @@ async fn inner_main(cfg_path: Option<PathBuf>) -> Result<Option<Shutdown>, Error
             .await
         });
 
+        let notifications_ctx = rpc_ctx.clone();
+        let notifications_task = tokio::spawn(async move {
+            launch_maintenance_task(&notifications_ctx, notifications_ctx.shutdown.subscribe())
+                .await
+        });
+
+        let auto_update_ctx = rpc_ctx.clone();
+        let auto_update_task = tokio::spawn(async move {
+            launch_auto_update_task(&auto_update_ctx, auto_update_ctx.shutdown.subscribe()).await
+        });
+
+        let gc_ctx = rpc_ctx.clone();
+        let gc_task =
+            tokio::spawn(async move { launch_gc_task(&gc_ctx, gc_ctx.shutdown.subscribe()).await });
+
+        let disk_usage_ctx = rpc_ctx.clone();
+        let disk_usage_task = tokio::spawn(async move {
+            launch_disk_usage_task(&disk_usage_ctx, disk_usage_ctx.shutdown.subscribe()).await
+        });
+
+        let mirror_ctx = rpc_ctx.clone();
+        let mirror_task = tokio::spawn(async move {
+            launch_mirror_check_task(&mirror_ctx, mirror_ctx.shutdown.subscribe()).await
+        });
+
+        let acme_ctx = rpc_ctx.clone();
+        let acme_task = tokio::spawn(async move {
+            launch_renewal_task(&acme_ctx, acme_ctx.shutdown.subscribe()).await
+        });
+
+        let ddns_ctx = rpc_ctx.clone();
+        let ddns_task = tokio::spawn(async move {
+            launch_ddns_task(&ddns_ctx, ddns_ctx.shutdown.subscribe()).await
+        });
+
+        let bandwidth_ctx = rpc_ctx.clone();
+        let bandwidth_task = tokio::spawn(async move {
+            launch_bandwidth_task(&bandwidth_ctx, bandwidth_ctx.shutdown.subscribe()).await
+        });
+
+        let access_point_ctx = rpc_ctx.clone();
+        let access_point_task = tokio::spawn(async move {
+            launch_access_point_task(&access_point_ctx, access_point_ctx.shutdown.subscribe()).await
+        });
+
+        let kill_switch_ctx = rpc_ctx.clone();
+        let kill_switch_task = tokio::spawn(async move {
+            launch_kill_switch_task(&kill_switch_ctx, kill_switch_ctx.shutdown.subscribe()).await
+        });
+
         crate::sound::CHIME.play().await?;
 
         metrics_task
@@ -84,6 +144,106 @@ async fn inner_main(cfg_path: Option<PathBuf>) -> Result<Option<Shutdown>, Error
             .map_ok(|_| tracing::debug!("Metrics daemon Shutdown"))
             .await?;
 
+        notifications_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Notification daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Notification daemon Shutdown"))
+            .await?;
+
+        auto_update_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Auto-update daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Auto-update daemon Shutdown"))
+            .await?;
+
+        gc_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Package GC daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Package GC daemon Shutdown"))
+            .await?;
+
+        disk_usage_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Disk usage daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Disk usage daemon Shutdown"))
+            .await?;
+
+        mirror_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Registry mirror daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Registry mirror daemon Shutdown"))
+            .await?;
+
+        acme_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("ACME renewal daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("ACME renewal daemon Shutdown"))
+            .await?;
+
+        ddns_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Dynamic DNS daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Dynamic DNS daemon Shutdown"))
+            .await?;
+
+        bandwidth_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Bandwidth daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Bandwidth daemon Shutdown"))
+            .await?;
+
+        access_point_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Access point daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Access point daemon Shutdown"))
+            .await?;
+
+        kill_switch_task
+            .map_err(|e| {
+                Error::new(
+                    eyre!("{}", e).wrap_err("Egress kill-switch daemon panicked!"),
+                    ErrorKind::Unknown,
+                )
+            })
+            .map_ok(|_| tracing::debug!("Egress kill-switch daemon Shutdown"))
+            .await?;
+
         let shutdown = shutdown_recv
             .recv()
             .await
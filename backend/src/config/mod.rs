@@ -213,7 +213,11 @@ impl ConfigGetReceipts {
     }
 }
 
-#[command(display(display_serializable))]
+/// Only for admins, as configs hold secrets such as the passwords of services
+#[command(
+    display(display_serializable),
+    metadata(read_only = true, admin = true)
+)]
 #[instrument(skip_all)]
 pub async fn get(
     #[context] ctx: RpcContext,
@@ -412,7 +416,11 @@ impl ConfigReceipts {
     }
 }
 
-#[command(rename = "dry", display(display_serializable))]
+#[command(
+    rename = "dry",
+    display(display_serializable),
+    metadata(read_only = true)
+)]
 #[instrument(skip_all)]
 pub async fn set_dry(
     #[context] ctx: RpcContext,
@@ -568,7 +576,7 @@ pub fn configure_rec<'a, Db: DbHandle>(
                 .0
                 .iter()
                 .filter_map(|(id, info)| {
-                    if info.requirement.required() {
+                    if info.requirement.tracked() {
                         Some((id.clone(), CurrentDependencyInfo::default()))
                     } else {
                         None
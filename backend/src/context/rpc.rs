@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
@@ -8,7 +8,6 @@ use std::time::Duration;
 
 use bollard::Docker;
 use helpers::to_tmp_path;
-use josekit::jwk::Jwk;
 use patch_db::json_ptr::JsonPointer;
 use patch_db::{DbHandle, LockReceipt, LockType, PatchDb};
 use reqwest::{Client, Proxy, Url};
@@ -26,12 +25,17 @@ use crate::db::model::{CurrentDependents, Database, InstalledPackageDataEntry, P
 use crate::disk::OsPartitionInfo;
 use crate::init::init_postgres;
 use crate::install::cleanup::{cleanup_failed, uninstall, CleanupFailedReceipts};
+use crate::install::devices::grandfathered;
+use crate::install::queue::InstallQueue;
 use crate::manager::ManagerMap;
-use crate::middleware::auth::HashSessionToken;
+use crate::marketplace::auth::{load_credentials, RegistryAuth};
+use crate::middleware::auth::{HashSessionToken, LoginAttempts};
+use crate::middleware::encrypt::WireKeys;
 use crate::net::net_controller::NetController;
 use crate::net::ssl::SslManager;
 use crate::net::wifi::WpaCli;
 use crate::notifications::NotificationManager;
+use crate::procedure::docker::{runtime, set_runtime, ContainerRuntimeKind};
 use crate::shutdown::Shutdown;
 use crate::status::{MainStatus, Status};
 use crate::system::get_mem_info;
@@ -54,10 +58,15 @@ pub struct RpcContextConfig {
     pub revision_cache_size: Option<usize>,
     pub datadir: Option<PathBuf>,
     pub log_server: Option<Url>,
+    pub container_runtime: Option<ContainerRuntimeKind>,
+    /// The socket of the API of the container runtime, e.g. that of a rootless Podman, see
+    /// `PodmanRuntime` for what packages cannot do then
+    pub container_socket: Option<PathBuf>,
 }
 impl RpcContextConfig {
+    /// Also selects the container runtime the config asks for
     pub async fn load<P: AsRef<Path> + Send + 'static>(path: Option<P>) -> Result<Self, Error> {
-        tokio::task::spawn_blocking(move || {
+        let cfg: Self = tokio::task::spawn_blocking(move || {
             load_config_from_paths(
                 path.as_ref()
                     .into_iter()
@@ -69,7 +78,13 @@ impl RpcContextConfig {
             )
         })
         .await
-        .unwrap()
+        .unwrap()?;
+        set_runtime(
+            cfg.container_runtime
+                .unwrap_or_default()
+                .runtime(cfg.container_socket.clone()),
+        );
+        Ok(cfg)
     }
     pub fn datadir(&self) -> &Path {
         self.datadir
@@ -118,11 +133,21 @@ pub struct RpcContextSeed {
     pub shutdown: broadcast::Sender<Option<Shutdown>>,
     pub tor_socks: SocketAddr,
     pub notification_manager: NotificationManager,
+    pub install_queue: InstallQueue,
     pub open_authed_websockets: Mutex<BTreeMap<HashSessionToken, Vec<oneshot::Sender<()>>>>,
+    pub login_attempts: Mutex<LoginAttempts>,
     pub rpc_stream_continuations: Mutex<BTreeMap<RequestGuid, RpcContinuation>>,
     pub wifi_manager: Option<Arc<RwLock<WpaCli>>>,
-    pub current_secret: Arc<Jwk>,
+    pub wire_keys: std::sync::RwLock<WireKeys>,
+    /// Where requests for each registry with mirrors go, see `marketplace mirror`
+    pub registry_routes: std::sync::RwLock<BTreeMap<String, String>>,
+    /// See `marketplace auth`
+    pub registry_credentials: std::sync::RwLock<BTreeMap<String, RegistryAuth>>,
+    /// See `marketplace tor-only`
+    pub tor_only_registries: std::sync::RwLock<BTreeSet<String>>,
     pub client: Client,
+    /// Sends every request over tor, rather than only those to onion services
+    pub tor_client: Client,
     pub hardware: Hardware,
 }
 
@@ -187,16 +212,24 @@ impl RpcContext {
         let secret_store = base.secret_store().await?;
         tracing::info!("Opened Pg DB");
         let account = AccountInfo::load(&secret_store).await?;
+        let registry_credentials = load_credentials(&secret_store).await?;
         let db = base.db(&account).await?;
         tracing::info!("Opened PatchDB");
-        let mut docker = Docker::connect_with_unix_defaults()?;
+        let mut docker = runtime().connect()?;
         docker.set_timeout(Duration::from_secs(600));
         tracing::info!("Connected to Docker");
+        let tor_bridges = crate::db::DatabaseModel::new()
+            .server_info()
+            .tor_bridges()
+            .get(&mut db.handle())
+            .await?
+            .into_owned();
         let net_controller = Arc::new(
             NetController::init(
                 base.tor_control
                     .unwrap_or(SocketAddr::from(([127, 0, 0, 1], 9051))),
                 tor_proxy,
+                tor_bridges,
                 base.dns_bind
                     .as_ref()
                     .map(|v| v.as_slice())
@@ -207,12 +240,70 @@ impl RpcContext {
             )
             .await?,
         );
+        crate::net::ca::load(&net_controller, &secret_store).await?;
+        crate::net::acme::load(&net_controller, &secret_store, &mut db.handle()).await?;
+        if let Err(e) =
+            crate::net::wireguard::load(&net_controller, &secret_store, &mut db.handle()).await
+        {
+            tracing::error!("Error Starting WireGuard: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        crate::net::proxy_auth::load(&net_controller, &secret_store).await?;
+        crate::net::proxy_directives::load(&net_controller, &mut db.handle()).await?;
+        crate::net::exposure::load(&net_controller, &mut db.handle()).await?;
+        crate::net::https::load(&net_controller, &mut db.handle()).await?;
+        crate::net::rate_limit::load(&net_controller, &mut db.handle()).await?;
+        crate::net::access_log::load(&net_controller, &mut db.handle()).await?;
+        if let Err(e) =
+            crate::net::egress::load(&net_controller, &secret_store, &mut db.handle()).await
+        {
+            tracing::error!("Error Starting Egress Tunnel: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        if let Err(e) = crate::net::tailscale::load(&net_controller, &mut db.handle()).await {
+            tracing::error!("Error Routing to the Tailnet: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        if let Err(e) = crate::net::firewall::load(&net_controller, &mut db.handle()).await {
+            tracing::error!("Error Starting Firewall: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        if let Err(e) = crate::net::encrypted_dns::load(&net_controller, &mut db.handle()).await {
+            tracing::error!("Error Starting Encrypted DNS Resolver: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        if let Err(e) = crate::net::mdns::load(&net_controller, &mut db.handle()).await {
+            tracing::error!("Error Advertising mDNS Aliases: {}", e);
+            tracing::debug!("{:?}", e);
+        }
         tracing::info!("Initialized Net Controller");
         let managers = ManagerMap::default();
         let metrics_cache = RwLock::new(None);
-        let notification_manager = NotificationManager::new(secret_store.clone());
-        tracing::info!("Initialized Notification Manager");
         let tor_proxy_url = format!("socks5h://{tor_proxy}");
+        let client = Client::builder()
+            .proxy(Proxy::custom(move |url| {
+                if url.host_str().map_or(false, |h| h.ends_with(".onion")) {
+                    Some(tor_proxy_url.clone())
+                } else {
+                    None
+                }
+            }))
+            .build()
+            .with_kind(crate::ErrorKind::ParseUrl)?;
+        let tor_client = Client::builder()
+            .proxy(Proxy::all(&tor_proxy_url).with_kind(crate::ErrorKind::ParseUrl)?)
+            .build()
+            .with_kind(crate::ErrorKind::ParseUrl)?;
+        let tor_only_registries = crate::db::DatabaseModel::new()
+            .server_info()
+            .tor_only_registries()
+            .get(&mut db.handle())
+            .await?
+            .iter()
+            .map(|url| crate::marketplace::base(url).to_owned())
+            .collect();
+        let notification_manager = NotificationManager::new(secret_store.clone(), client.clone());
+        tracing::info!("Initialized Notification Manager");
         let devices = lshw().await?;
         let ram = get_mem_info().await?.total.0 as u64 * 1024 * 1024;
         let seed = Arc::new(RpcContextSeed {
@@ -232,31 +323,19 @@ impl RpcContext {
             shutdown,
             tor_socks: tor_proxy,
             notification_manager,
+            install_queue: InstallQueue::new(),
             open_authed_websockets: Mutex::new(BTreeMap::new()),
+            login_attempts: Mutex::new(LoginAttempts::default()),
             rpc_stream_continuations: Mutex::new(BTreeMap::new()),
             wifi_manager: base
                 .wifi_interface
                 .map(|i| Arc::new(RwLock::new(WpaCli::init(i)))),
-            current_secret: Arc::new(
-                Jwk::generate_ec_key(josekit::jwk::alg::ec::EcCurve::P256).map_err(|e| {
-                    tracing::debug!("{:?}", e);
-                    tracing::error!("Couldn't generate ec key");
-                    Error::new(
-                        color_eyre::eyre::eyre!("Couldn't generate ec key"),
-                        crate::ErrorKind::Unknown,
-                    )
-                })?,
-            ),
-            client: Client::builder()
-                .proxy(Proxy::custom(move |url| {
-                    if url.host_str().map_or(false, |h| h.ends_with(".onion")) {
-                        Some(tor_proxy_url.clone())
-                    } else {
-                        None
-                    }
-                }))
-                .build()
-                .with_kind(crate::ErrorKind::ParseUrl)?,
+            wire_keys: std::sync::RwLock::new(WireKeys::new(CURRENT_SECRET.clone())),
+            registry_routes: std::sync::RwLock::new(BTreeMap::new()),
+            registry_credentials: std::sync::RwLock::new(registry_credentials),
+            tor_only_registries: std::sync::RwLock::new(tor_only_registries),
+            client,
+            tor_client,
             hardware: Hardware { devices, ram },
         });
 
@@ -376,9 +455,13 @@ impl RpcContext {
                             }
                             a => a.clone(),
                         };
+                        let approved_devices = installed
+                            .approved_devices
+                            .or_else(|| Some(grandfathered(&manifest)));
                         let new_package = PackageDataEntry::Installed {
                             installed: InstalledPackageDataEntry {
                                 status: Status { main, ..status },
+                                approved_devices,
                                 ..installed
                             },
                             static_files,
@@ -453,11 +536,6 @@ impl RpcContext {
         }
     }
 }
-impl AsRef<Jwk> for RpcContext {
-    fn as_ref(&self) -> &Jwk {
-        &*CURRENT_SECRET
-    }
-}
 impl Context for RpcContext {}
 impl Deref for RpcContext {
     type Target = RpcContextSeed;
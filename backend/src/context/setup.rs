@@ -36,6 +36,7 @@ pub struct SetupResult {
     pub tor_address: String,
     pub lan_address: String,
     pub root_ca: String,
+    pub recovery_codes: Option<Vec<String>>,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -47,6 +48,10 @@ pub struct SetupContextConfig {
     pub datadir: Option<PathBuf>,
     #[serde(default)]
     pub disable_encryption: bool,
+    pub wifi_interface: Option<String>,
+    /// Keeps the open access point of the setup from being brought up while offline
+    #[serde(default)]
+    pub disable_access_point: bool,
 }
 impl SetupContextConfig {
     #[instrument(skip_all)]
@@ -78,6 +83,8 @@ pub struct SetupContextSeed {
     pub migration_batch_rows: usize,
     pub migration_prefetch_rows: usize,
     pub disable_encryption: bool,
+    /// Where the access point of the setup is brought up, if it is not disabled
+    pub access_point_interface: Option<String>,
     pub shutdown: Sender<()>,
     pub datadir: PathBuf,
     pub selected_v2_drive: RwLock<Option<PathBuf>>,
@@ -106,6 +113,7 @@ impl SetupContext {
             migration_batch_rows: cfg.migration_batch_rows.unwrap_or(25000),
             migration_prefetch_rows: cfg.migration_prefetch_rows.unwrap_or(100_000),
             disable_encryption: cfg.disable_encryption,
+            access_point_interface: cfg.wifi_interface.filter(|_| !cfg.disable_access_point),
             shutdown,
             datadir,
             selected_v2_drive: RwLock::new(None),
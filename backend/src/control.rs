@@ -153,7 +153,11 @@ pub fn stop(#[arg] id: PackageId) -> Result<PackageId, Error> {
     Ok(id)
 }
 
-#[command(rename = "dry", display(display_serializable))]
+#[command(
+    rename = "dry",
+    display(display_serializable),
+    metadata(read_only = true)
+)]
 #[instrument(skip_all)]
 pub async fn stop_dry(
     #[context] ctx: RpcContext,
@@ -62,7 +62,7 @@ async fn ws_handler<
     Ok(())
 }
 
-async fn subscribe_to_session_kill(
+pub async fn subscribe_to_session_kill(
     ctx: &RpcContext,
     token: HashSessionToken,
 ) -> oneshot::Receiver<()> {
@@ -184,7 +184,7 @@ pub enum RevisionsRes {
     Dump(Dump),
 }
 
-#[command(display(display_serializable))]
+#[command(display(display_serializable), metadata(read_only = true))]
 pub async fn revisions(
     #[context] ctx: RpcContext,
     #[arg] since: u64,
@@ -198,7 +198,7 @@ pub async fn revisions(
     })
 }
 
-#[command(display(display_serializable))]
+#[command(display(display_serializable), metadata(read_only = true))]
 pub async fn dump(
     #[context] ctx: RpcContext,
     #[allow(unused_variables)]
@@ -259,7 +259,7 @@ fn apply_expr(input: jaq_core::Val, expr: &str) -> Result<jaq_core::Val, Error>
     Ok(res)
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn apply(#[context] ctx: RpcContext, #[arg] expr: String) -> Result<(), Error> {
     let mut db = ctx.db.handle();
 
@@ -19,7 +19,7 @@ use crate::account::AccountInfo;
 use crate::config::spec::{PackagePointerSpec, SystemPointerSpec};
 use crate::install::progress::InstallProgress;
 use crate::net::interface::InterfaceId;
-use crate::net::utils::{get_iface_ipv4_addr, get_iface_ipv6_addr};
+use crate::net::utils::{get_iface_ipv4_addr, get_iface_ipv6_addrs, is_ula};
 use crate::s9pk::manifest::{Manifest, ManifestModel, PackageId};
 use crate::status::health_check::HealthCheckId;
 use crate::status::Status;
@@ -57,6 +57,7 @@ impl Database {
                     backup_progress: None,
                     updated: false,
                     update_progress: None,
+                    vanity_progress: None,
                 },
                 wifi: WifiInfo {
                     ssids: Vec::new(),
@@ -81,6 +82,30 @@ impl Database {
                     .join(":"),
                 system_start_time: Utc::now().to_rfc3339(),
                 zram: false,
+                license_acceptances: Vec::new(),
+                registry_mirrors: BTreeMap::new(),
+                offline_registries: BTreeMap::new(),
+                tor_only_registries: BTreeSet::new(),
+                tor_bridges: Default::default(),
+                release_channels: Default::default(),
+                version_constraints: BTreeMap::new(),
+                registry_server: Default::default(),
+                acme: Default::default(),
+                wireguard: Default::default(),
+                tailscale: Default::default(),
+                ddns: Default::default(),
+                proxy_directives: BTreeMap::new(),
+                mdns: Default::default(),
+                ip_config: BTreeMap::new(),
+                firewall: Default::default(),
+                encrypted_dns: Default::default(),
+                exposure: BTreeMap::new(),
+                https: Default::default(),
+                egress: Default::default(),
+                rate_limits: BTreeMap::new(),
+                access_logs: BTreeMap::new(),
+                access_point: Default::default(),
+                isolation: Default::default(),
             },
             package_data: AllPackageData::default(),
             ui: serde_json::from_str(include_str!("../../../frontend/patchdb-ui-seed.json"))
@@ -121,6 +146,80 @@ pub struct ServerInfo {
     pub system_start_time: String,
     #[serde(default)]
     pub zram: bool,
+    /// See `package license history`
+    #[serde(default)]
+    pub license_acceptances: Vec<crate::install::license::LicenseAcceptance>,
+    /// Keyed by the url of the registry, see `marketplace mirror list`
+    #[serde(default)]
+    pub registry_mirrors: BTreeMap<Url, Vec<crate::marketplace::MirrorStatus>>,
+    /// Registries the marketplace is showing cached responses of, and when those were fetched
+    #[serde(default)]
+    pub offline_registries: BTreeMap<Url, DateTime<Utc>>,
+    /// See `marketplace tor-only`
+    #[serde(default)]
+    pub tor_only_registries: BTreeSet<Url>,
+    /// See `net tor bridges`
+    #[serde(default)]
+    pub tor_bridges: crate::net::tor::TorBridges,
+    /// See `marketplace channel`
+    #[model]
+    #[serde(default)]
+    pub release_channels: crate::marketplace::channel::ReleaseChannels,
+    /// See `package constraint`
+    #[serde(default)]
+    pub version_constraints: BTreeMap<PackageId, VersionRange>,
+    /// See `marketplace serve`
+    #[serde(default)]
+    pub registry_server: crate::marketplace::serve::RegistryServer,
+    /// See `net acme`
+    #[serde(default)]
+    pub acme: crate::net::acme::AcmeSettings,
+    /// See `net wireguard`
+    #[serde(default)]
+    pub wireguard: crate::net::wireguard::WireguardSettings,
+    /// See `net tailscale`
+    #[serde(default)]
+    pub tailscale: crate::net::tailscale::TailscaleSettings,
+    /// See `net ddns`
+    #[serde(default)]
+    pub ddns: crate::net::ddns::DdnsSettings,
+    /// See `net proxy`
+    #[serde(default)]
+    pub proxy_directives: crate::net::proxy_directives::ProxyDirectiveMap,
+    /// See `net mdns`
+    #[serde(default)]
+    pub mdns: crate::net::mdns::MdnsSettings,
+    /// See `net config`
+    #[serde(default)]
+    pub ip_config: BTreeMap<String, crate::net::ip_config::IpConfig>,
+    /// See `net firewall`
+    #[serde(default)]
+    pub firewall: crate::net::firewall::FirewallSettings,
+    /// See `net encrypted-dns`
+    #[serde(default)]
+    pub encrypted_dns: crate::net::encrypted_dns::EncryptedDnsSettings,
+    /// See `net exposure`
+    #[serde(default)]
+    pub exposure: crate::net::exposure::ExposureMap,
+    /// See `net https`
+    #[serde(default)]
+    pub https: crate::net::https::HttpsSettings,
+    /// See `net egress`
+    #[model]
+    #[serde(default)]
+    pub egress: crate::net::egress::EgressSettings,
+    /// See `net rate-limit`
+    #[serde(default)]
+    pub rate_limits: crate::net::rate_limit::RateLimitMap,
+    /// See `net access-log`
+    #[serde(default)]
+    pub access_logs: crate::net::access_log::AccessLogMap,
+    /// See `wifi access-point`
+    #[serde(default)]
+    pub access_point: crate::net::access_point::AccessPointSettings,
+    /// See `net isolation`
+    #[serde(default)]
+    pub isolation: crate::net::isolation::IsolationSettings,
 }
 
 #[derive(Debug, Deserialize, Serialize, HasModel)]
@@ -129,17 +228,29 @@ pub struct IpInfo {
     pub ipv4_range: Option<Ipv4Net>,
     pub ipv4: Option<Ipv4Addr>,
     pub ipv6_range: Option<Ipv6Net>,
+    /// A global address if the network has one, since it is reachable from other networks too
     pub ipv6: Option<Ipv6Addr>,
+    /// An address within the LAN that does not change with the prefix of the ISP
+    #[serde(default)]
+    pub ipv6_ula: Option<Ipv6Addr>,
 }
 impl IpInfo {
     pub async fn for_interface(iface: &str) -> Result<Self, Error> {
         let (ipv4, ipv4_range) = get_iface_ipv4_addr(iface).await?.unzip();
-        let (ipv6, ipv6_range) = get_iface_ipv6_addr(iface).await?.unzip();
+        let ipv6_addrs = get_iface_ipv6_addrs(iface).await?;
+        let (ipv6, ipv6_range) = ipv6_addrs
+            .iter()
+            .find(|(ip, _)| !is_ula(ip))
+            .or_else(|| ipv6_addrs.first())
+            .copied()
+            .unzip();
+        let ipv6_ula = ipv6_addrs.iter().map(|(ip, _)| *ip).find(is_ula);
         Ok(Self {
             ipv4_range,
             ipv4,
             ipv6_range,
             ipv6,
+            ipv6_ula,
         })
     }
 }
@@ -157,6 +268,10 @@ pub struct ServerStatus {
     pub updated: bool,
     #[model]
     pub update_progress: Option<UpdateProgress>,
+    /// See `net tor vanity-generate`
+    #[model]
+    #[serde(default)]
+    pub vanity_progress: Option<VanityProgress>,
 }
 
 #[derive(Debug, Deserialize, Serialize, HasModel)]
@@ -166,6 +281,18 @@ pub struct UpdateProgress {
     pub downloaded: u64,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize, HasModel)]
+#[serde(rename_all = "kebab-case")]
+pub struct VanityProgress {
+    pub package: PackageId,
+    pub interface: InterfaceId,
+    pub prefix: String,
+    pub attempts: u64,
+    /// On average, a key with the prefix is found after this many attempts
+    pub expected: u64,
+    pub started_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct WifiInfo {
@@ -314,6 +441,26 @@ pub struct InstalledPackageDataEntry {
     #[model]
     pub status: Status,
     pub marketplace_url: Option<Url>,
+    /// Updates are neither offered nor installed while set
+    #[serde(default)]
+    pub held: bool,
+    /// Refreshed in the background, see `package disk-usage`
+    #[serde(default)]
+    pub disk_usage: Option<crate::install::disk_usage::PackageDiskUsage>,
+    /// The package this was installed as another instance of, see `package install --instance`
+    #[serde(default)]
+    pub instance_of: Option<PackageId>,
+    /// Shown instead of the title of the package, see `package label`
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Overrides the limits the manifest declares, see `package resources`
+    #[serde(default)]
+    pub resource_limits: crate::install::resources::ResourceLimits,
+    /// The devices the package asks for that it is given, see `package devices`. `None` for
+    /// packages installed before devices had to be approved, until `RpcContext::cleanup` approves
+    /// those they were given then.
+    #[serde(default)]
+    pub approved_devices: Option<BTreeSet<crate::install::devices::Device>>,
     #[serde(default)]
     #[serde(with = "crate::util::serde::ed25519_pubkey")]
     pub developer_key: ed25519_dalek::PublicKey,
@@ -331,6 +478,12 @@ pub struct InstalledPackageDataEntry {
     #[model]
     pub interface_addresses: InterfaceAddressMap,
 }
+impl InstalledPackageDataEntry {
+    /// The label of the package if it has one, and its title otherwise
+    pub fn display_name(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.manifest.title)
+    }
+}
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct CurrentDependents(pub BTreeMap<PackageId, CurrentDependencyInfo>);
@@ -414,6 +567,14 @@ pub struct InterfaceAddresses {
     pub tor_address: Option<String>,
     #[model]
     pub lan_address: Option<String>,
+    /// See `net domain`
+    #[model]
+    #[serde(default)]
+    pub clearnet_addresses: Vec<String>,
+    /// See `net i2p`
+    #[model]
+    #[serde(default)]
+    pub i2p_address: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, HasModel)]
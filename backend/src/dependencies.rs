@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::Duration;
 
 use color_eyre::eyre::eyre;
@@ -18,13 +18,15 @@ use crate::config::action::{ConfigActions, ConfigRes};
 use crate::config::spec::PackagePointerSpec;
 use crate::config::{not_found, Config, ConfigReceipts, ConfigSpec};
 use crate::context::RpcContext;
-use crate::db::model::{CurrentDependencies, CurrentDependents, InstalledPackageDataEntry};
+use crate::db::model::{
+    CurrentDependencies, CurrentDependents, InstalledPackageDataEntry, PackageDataEntry,
+};
 use crate::procedure::docker::DockerContainers;
 use crate::procedure::{NoOutput, PackageProcedure, ProcedureName};
 use crate::s9pk::manifest::{Manifest, PackageId};
 use crate::status::health_check::{HealthCheckId, HealthCheckResult};
 use crate::status::{MainStatus, Status};
-use crate::util::serde::display_serializable;
+use crate::util::serde::{display_serializable, IoFormat};
 use crate::util::{display_none, Version};
 use crate::volume::Volumes;
 use crate::Error;
@@ -34,6 +36,97 @@ pub fn dependency() -> Result<(), Error> {
     Ok(())
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GraphNode {
+    pub title: String,
+    pub version: Version,
+    pub configured: bool,
+    pub main: MainStatus,
+}
+
+/// `dependent` currently depends on `dependency`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GraphEdge {
+    pub dependent: PackageId,
+    pub dependency: PackageId,
+    /// As declared in the manifest of the dependent
+    pub version: Option<VersionRange>,
+    pub requirement: Option<DependencyRequirement>,
+    /// The health checks of the dependency that the dependent relies on
+    pub health_checks: BTreeSet<HealthCheckId>,
+    /// Why the dependency is not satisfied, if it is not
+    pub error: Option<DependencyError>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyGraph {
+    /// Every installed package
+    pub nodes: BTreeMap<PackageId, GraphNode>,
+    /// An edge may point at a package that is not installed
+    pub edges: Vec<GraphEdge>,
+}
+
+/// The current dependencies between installed packages, and whether each of them is satisfied
+#[command(
+    rename = "dependency-graph",
+    display(display_serializable),
+    metadata(read_only = true)
+)]
+#[instrument(skip_all)]
+pub async fn dependency_graph(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<DependencyGraph, Error> {
+    let mut graph = DependencyGraph {
+        nodes: BTreeMap::new(),
+        edges: Vec::new(),
+    };
+    for (id, pde) in crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .0
+    {
+        let installed = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        let Status {
+            configured,
+            main,
+            mut dependency_errors,
+        } = installed.status;
+        let mut declared = installed.manifest.dependencies.0;
+        for (dep_id, info) in installed.current_dependencies.0 {
+            let declared = declared.remove(&dep_id);
+            graph.edges.push(GraphEdge {
+                dependent: id.clone(),
+                error: dependency_errors.0.remove(&dep_id),
+                version: declared.as_ref().map(|d| d.version.clone()),
+                requirement: declared.map(|d| d.requirement),
+                health_checks: info.health_checks,
+                dependency: dep_id,
+            });
+        }
+        graph.nodes.insert(
+            id,
+            GraphNode {
+                title: installed.manifest.title,
+                version: installed.manifest.version,
+                configured,
+                main,
+            },
+        );
+    }
+    Ok(graph)
+}
+
 #[derive(Clone, Debug, thiserror::Error, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
@@ -448,14 +541,45 @@ impl HasModel for Dependencies {
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
 pub enum DependencyRequirement {
-    OptIn { how: String },
-    OptOut { how: String },
+    OptIn {
+        how: String,
+    },
+    OptOut {
+        how: String,
+    },
     Required,
+    /// Used whenever it is installed, but the package runs without it
+    Optional,
+    /// Only recommended to the user
+    Suggests,
 }
 impl DependencyRequirement {
     pub fn required(&self) -> bool {
         matches!(self, &DependencyRequirement::Required)
     }
+    /// Whether the dependency is a current dependency from install on, rather than only once the
+    /// config points at it
+    pub fn tracked(&self) -> bool {
+        matches!(
+            self,
+            &DependencyRequirement::Required | &DependencyRequirement::Optional
+        )
+    }
+    /// Whether `error` is ignored rather than recorded against the dependent. A package can do
+    /// without an optional or suggested dependency, but not with one of the wrong version or
+    /// config.
+    pub fn tolerates(&self, error: &DependencyError) -> bool {
+        matches!(
+            self,
+            &DependencyRequirement::Optional | &DependencyRequirement::Suggests
+        ) && matches!(
+            error,
+            DependencyError::NotInstalled
+                | DependencyError::NotRunning
+                | DependencyError::HealthChecksFailed { .. }
+                | DependencyError::Transitive
+        )
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, HasModel)]
@@ -490,6 +614,7 @@ impl DepInfo {
                     receipts,
                 )
                 .await?
+                .filter(|err| !self.requirement.tolerates(err))
             {
                 Err(err)
             } else {
@@ -695,7 +820,11 @@ pub struct ConfigDryRes {
     pub spec: ConfigSpec,
 }
 
-#[command(rename = "dry", display(display_serializable))]
+#[command(
+    rename = "dry",
+    display(display_serializable),
+    metadata(read_only = true)
+)]
 #[instrument(skip_all)]
 pub async fn configure_dry(
     #[context] ctx: RpcContext,
@@ -933,6 +1062,17 @@ pub fn break_transitive<'a, Db: DbHandle>(
 ) -> BoxFuture<'a, Result<(), Error>> {
     async move {
         let mut tx = db.begin().await?;
+        if let Some(info) = receipts
+            .dependency_receipt
+            .dependency
+            .get(&mut tx, (id, dependency))
+            .await?
+        {
+            if info.requirement.tolerates(&error) {
+                tx.save().await?;
+                return Ok(());
+            }
+        }
         let mut dependency_errors = receipts
             .dependency_errors
             .get(&mut tx, id)
@@ -1033,6 +1173,7 @@ pub fn heal_transitive<'a, Db: DbHandle>(
             if let Some(new) = old
                 .try_heal(ctx, db, id, dependency, None, &info, &receipts.try_heal)
                 .await?
+                .filter(|new| !info.requirement.tolerates(new))
             {
                 status.dependency_errors.0.insert(dependency.clone(), new);
                 receipts.status.set(db, status, id).await?;
@@ -1130,3 +1271,16 @@ impl DependencyReceipt {
         }
     }
 }
+
+#[test]
+fn requirement_classes() {
+    let optional: DependencyRequirement = serde_json::from_str(r#"{"type":"optional"}"#).unwrap();
+    let suggests: DependencyRequirement = serde_json::from_str(r#"{"type":"suggests"}"#).unwrap();
+    assert!(optional.tracked() && !suggests.tracked());
+    assert!(optional.tolerates(&DependencyError::NotInstalled));
+    assert!(suggests.tolerates(&DependencyError::NotRunning));
+    assert!(!optional.tolerates(&DependencyError::ConfigUnsatisfied {
+        error: "pruning must be disabled".to_owned(),
+    }));
+    assert!(!DependencyRequirement::Required.tolerates(&DependencyError::NotInstalled));
+}
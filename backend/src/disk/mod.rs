@@ -101,7 +101,7 @@ fn display_disk_info(info: Vec<DiskInfo>, matches: &ArgMatches) {
     table.print_tty(false).unwrap();
 }
 
-#[command(display(display_disk_info))]
+#[command(display(display_disk_info), metadata(read_only = true))]
 pub async fn list(
     #[context] ctx: RpcContext,
     #[allow(unused_variables)]
@@ -111,7 +111,7 @@ pub async fn list(
     crate::disk::util::list(&ctx.os_partitions).await
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn repair() -> Result<(), Error> {
     tokio::fs::write(REPAIR_DISK_PATH, b"").await?;
     Ok(())
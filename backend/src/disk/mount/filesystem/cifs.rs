@@ -20,7 +20,7 @@ async fn resolve_hostname(hostname: &str) -> Result<IpAddr, Error> {
         return Ok(addr);
     }
     if hostname.ends_with(".local") {
-        return Ok(IpAddr::V4(crate::net::mdns::resolve_mdns(hostname).await?));
+        return crate::net::mdns::resolve_mdns(hostname).await;
     }
     Ok(String::from_utf8(
         Command::new("nmblookup")
@@ -51,14 +51,23 @@ pub async fn mount_cifs(
     cmd.arg("-t")
         .arg("cifs")
         .env("USER", username)
-        .env("PASSWD", password.unwrap_or_default())
-        .arg(format!("//{}{}", ip, absolute_path.display()))
-        .arg(mountpoint.as_ref());
+        .env("PASSWD", password.unwrap_or_default());
+    let mut options = vec!["noserverino".to_owned()];
     if mount_type == ReadOnly {
-        cmd.arg("-o").arg("ro,noserverino");
-    } else {
-        cmd.arg("-o").arg("noserverino");
+        options.insert(0, "ro".to_owned());
     }
+    match ip {
+        IpAddr::V4(ip) => {
+            cmd.arg(format!("//{}{}", ip, absolute_path.display()));
+        }
+        // an IPv6 literal is ambiguous in a UNC path, so the address is passed separately
+        IpAddr::V6(ip) => {
+            cmd.arg(format!("//{}{}", hostname, absolute_path.display()));
+            options.push(format!("ip={}", ip));
+        }
+    }
+    cmd.arg(mountpoint.as_ref());
+    cmd.arg("-o").arg(options.join(","));
     cmd.invoke(crate::ErrorKind::Filesystem).await?;
     Ok(())
 }
@@ -0,0 +1,352 @@
+use std::future::Future;
+use std::marker::PhantomData;
+use std::os::unix::io::AsRawFd;
+use std::time::Duration;
+
+use bollard::container::LogOutput;
+use bollard::exec::{CreateExecOptions, ResizeExecOptions, StartExecResults};
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hyper::upgrade::Upgraded;
+use hyper::Error as HyperError;
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+use rpc_toolkit::command;
+use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::instrument;
+
+use crate::context::{CliContext, RpcContext};
+use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
+use crate::procedure::docker::DockerProcedure;
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// What the client of `/ws/rpc/<guid>` sends besides the bytes of stdin, which go in binary
+/// messages
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExecControl {
+    /// The size of the terminal, with `--tty`
+    Resize { rows: u16, cols: u16 },
+    /// Closes stdin
+    Eof,
+}
+
+/// Sent as text once the command exits, after its output. The output itself is sent in binary
+/// messages, whose first byte is 1 for stdout and 2 for stderr, as with `--tty` everything is
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ExecExit {
+    pub exit_code: Option<i64>,
+}
+
+#[instrument(skip_all)]
+async fn ws_handler<
+    WSFut: Future<Output = Result<Result<WebSocketStream<Upgraded>, HyperError>, JoinError>>,
+>(
+    ctx: RpcContext,
+    exec: String,
+    ws_fut: WSFut,
+) -> Result<(), Error> {
+    let mut stream = ws_fut
+        .await
+        .with_kind(ErrorKind::Network)?
+        .with_kind(ErrorKind::Unknown)?;
+    let (mut output, mut input) = match ctx.docker.start_exec(&exec, None).await? {
+        StartExecResults::Attached { output, input } => (output, input),
+        StartExecResults::Detached => {
+            return Err(Error::new(eyre!("Exec is detached"), ErrorKind::Docker))
+        }
+    };
+    loop {
+        tokio::select! {
+            out = output.next() => {
+                let (fd, message) = match out.transpose()? {
+                    Some(LogOutput::StdErr { message }) => (2, message),
+                    Some(LogOutput::StdOut { message }) | Some(LogOutput::Console { message }) => {
+                        (1, message)
+                    }
+                    Some(LogOutput::StdIn { .. }) => continue,
+                    None => break,
+                };
+                let mut frame = Vec::with_capacity(message.len() + 1);
+                frame.push(fd);
+                frame.extend_from_slice(&message);
+                stream
+                    .send(Message::Binary(frame))
+                    .await
+                    .with_kind(ErrorKind::Network)?;
+            }
+            message = stream.next() => {
+                match message.transpose().with_kind(ErrorKind::Network)? {
+                    Some(Message::Binary(data)) => input.write_all(&data).await?,
+                    Some(Message::Text(control)) => {
+                        match serde_json::from_str(&control).with_kind(ErrorKind::Deserialization)? {
+                            ExecControl::Resize { rows, cols } => {
+                                ctx.docker
+                                    .resize_exec(
+                                        &exec,
+                                        ResizeExecOptions {
+                                            height: rows,
+                                            width: cols,
+                                        },
+                                    )
+                                    .await?
+                            }
+                            ExecControl::Eof => input.shutdown().await?,
+                        }
+                    }
+                    Some(_) => (),
+                    // the command gets a hangup once `input` is dropped
+                    None => return Ok(()),
+                }
+            }
+        }
+    }
+    let exit = ExecExit {
+        exit_code: ctx.docker.inspect_exec(&exec).await?.exit_code,
+    };
+    stream
+        .send(Message::Text(
+            serde_json::to_string(&exit).with_kind(ErrorKind::Serialization)?,
+        ))
+        .await
+        .with_kind(ErrorKind::Network)?;
+    stream
+        .close(Some(CloseFrame {
+            code: CloseCode::Normal,
+            reason: "Exited".into(),
+        }))
+        .await
+        .with_kind(ErrorKind::Network)?;
+    Ok(())
+}
+
+/// Runs `command` and its args in the main container of `id`, or an interactive `/bin/sh` with
+/// `--tty` and no command. Put `--` before a command taking flags of its own. Connect to `/ws/rpc/<guid>` within 30 seconds to start it and talk
+/// to it, see [`ExecControl`] and [`ExecExit`].
+#[command(
+    custom_cli(cli_exec(async, context(CliContext))),
+    display(display_none),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn exec(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg(short = 't', long = "tty", default)] tty: bool,
+    #[arg(default)] command: Vec<String>,
+) -> Result<RequestGuid, Error> {
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    if !matches!(status, MainStatus::Running { .. }) {
+        return Err(Error::new(
+            eyre!("{} is not running", id),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let cmd = match (command.is_empty(), tty) {
+        (false, _) => command,
+        (true, true) => vec!["/bin/sh".to_owned()],
+        (true, false) => {
+            return Err(Error::new(
+                eyre!("A command is required without --tty"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    let exec = ctx
+        .docker
+        .create_exec(
+            &DockerProcedure::container_name(&id, None),
+            CreateExecOptions {
+                attach_stdin: Some(true),
+                attach_stdout: Some(true),
+                attach_stderr: Some(true),
+                tty: Some(tty),
+                env: tty.then(|| vec!["TERM=xterm-256color".to_owned()]),
+                cmd: Some(cmd),
+                ..Default::default()
+            },
+        )
+        .await?
+        .id;
+    let guid = RequestGuid::new();
+    let handler_ctx = ctx.clone();
+    ctx.add_continuation(
+        guid.clone(),
+        RpcContinuation::ws(
+            Box::new(move |ws_fut| ws_handler(handler_ctx, exec, ws_fut).boxed()),
+            Duration::from_secs(30),
+        ),
+    )
+    .await;
+    Ok(guid)
+}
+
+/// The size of the terminal on stdout
+fn terminal_size() -> Option<ExecControl> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a winsize to the pointer
+    if unsafe { libc::ioctl(std::io::stdout().as_raw_fd(), libc::TIOCGWINSZ, &mut size) } != 0 {
+        return None;
+    }
+    Some(ExecControl::Resize {
+        rows: size.ws_row,
+        cols: size.ws_col,
+    })
+}
+
+async fn cli_exec(
+    ctx: CliContext,
+    id: PackageId,
+    tty: bool,
+    command: Vec<String>,
+) -> Result<(), RpcError> {
+    let guid = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.exec",
+        serde_json::json!({ "id": id, "tty": tty, "command": command }),
+        PhantomData::<RequestGuid>,
+    )
+    .await?
+    .result?;
+
+    let mut base_url = ctx.base_url.clone();
+    let ws_scheme = match base_url.scheme() {
+        "https" => "wss",
+        "http" => "ws",
+        _ => {
+            return Err(Error::new(
+                eyre!("Cannot parse scheme from base URL"),
+                crate::ErrorKind::ParseUrl,
+            )
+            .into())
+        }
+    };
+    base_url.set_scheme(ws_scheme).or_else(|_| {
+        Err(Error::new(
+            eyre!("Cannot set URL scheme"),
+            crate::ErrorKind::ParseUrl,
+        ))
+    })?;
+    // base_url is "http://127.0.0.1/", with a trailing slash, so we don't put a leading slash in this path:
+    let (mut stream, _) =
+        tokio_tungstenite::connect_async(format!("{}ws/rpc/{}", base_url, guid)).await?;
+
+    let stdin_fd = std::io::stdin().as_raw_fd();
+    let termios = if tty {
+        let termios = tcgetattr(stdin_fd).with_kind(ErrorKind::Filesystem)?;
+        let mut raw = termios.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(stdin_fd, SetArg::TCSANOW, &raw).with_kind(ErrorKind::Filesystem)?;
+        if let Some(size) = terminal_size() {
+            stream
+                .send(Message::Text(
+                    serde_json::to_string(&size).with_kind(ErrorKind::Serialization)?,
+                ))
+                .await
+                .with_kind(ErrorKind::Network)?;
+        }
+        Some(termios)
+    } else {
+        None
+    };
+
+    let res = async {
+        let mut stdin = tokio::io::stdin();
+        let mut stdout = tokio::io::stdout();
+        let mut stderr = tokio::io::stderr();
+        let mut resized = signal(SignalKind::window_change())?;
+        let mut buf = [0; 4096];
+        let mut stdin_open = true;
+        let mut exit = ExecExit { exit_code: None };
+        loop {
+            tokio::select! {
+                n = stdin.read(&mut buf), if stdin_open => {
+                    let message = match n? {
+                        0 => {
+                            stdin_open = false;
+                            Message::Text(
+                                serde_json::to_string(&ExecControl::Eof)
+                                    .with_kind(ErrorKind::Serialization)?,
+                            )
+                        }
+                        n => Message::Binary(buf[..n].to_vec()),
+                    };
+                    stream.send(message).await.with_kind(ErrorKind::Network)?;
+                }
+                _ = resized.recv(), if tty => {
+                    if let Some(size) = terminal_size() {
+                        stream
+                            .send(Message::Text(
+                                serde_json::to_string(&size).with_kind(ErrorKind::Serialization)?,
+                            ))
+                            .await
+                            .with_kind(ErrorKind::Network)?;
+                    }
+                }
+                message = stream.next() => match message.transpose().with_kind(ErrorKind::Network)? {
+                    Some(Message::Binary(frame)) => match frame.split_first() {
+                        Some((2, data)) => {
+                            stderr.write_all(data).await?;
+                            stderr.flush().await?;
+                        }
+                        Some((_, data)) => {
+                            stdout.write_all(data).await?;
+                            stdout.flush().await?;
+                        }
+                        None => (),
+                    },
+                    Some(Message::Text(text)) => {
+                        exit = serde_json::from_str(&text).with_kind(ErrorKind::Deserialization)?
+                    }
+                    Some(_) => (),
+                    None => break,
+                },
+            }
+        }
+        Ok::<_, Error>(exit)
+    }
+    .await;
+
+    if let Some(termios) = termios {
+        tcsetattr(stdin_fd, SetArg::TCSANOW, &termios).with_kind(ErrorKind::Filesystem)?;
+    }
+    let exit = res?;
+    // the read of stdin that may still be pending would keep the runtime from shutting down
+    std::process::exit(exit.exit_code.unwrap_or(1) as i32)
+}
+
+#[test]
+fn exec_control() {
+    assert_eq!(
+        serde_json::to_string(&ExecControl::Resize { rows: 24, cols: 80 }).unwrap(),
+        r#"{"resize":{"rows":24,"cols":80}}"#
+    );
+    assert!(matches!(
+        serde_json::from_str(r#""eof""#).unwrap(),
+        ExecControl::Eof
+    ));
+}
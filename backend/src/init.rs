@@ -18,6 +18,7 @@ use crate::db::model::{ServerInfo, ServerStatus};
 use crate::disk::mount::util::unmount;
 use crate::install::PKG_ARCHIVE_DIR;
 use crate::middleware::auth::LOCAL_AUTH_COOKIE_PATH;
+use crate::procedure::docker::runtime;
 use crate::sound::BEP;
 use crate::system::time;
 use crate::util::Invoke;
@@ -231,14 +232,7 @@ pub async fn init(cfg: &RpcContextConfig) -> Result<InitResult, Error> {
     let receipts = InitReceipts::new(&mut handle).await?;
 
     // write to ca cert store
-    tokio::fs::write(
-        "/usr/local/share/ca-certificates/startos-root-ca.crt",
-        account.root_ca_cert.to_pem()?,
-    )
-    .await?;
-    Command::new("update-ca-certificates")
-        .invoke(crate::ErrorKind::OpenSsl)
-        .await?;
+    crate::net::ca::trust(&crate::net::ca::root_ca(&secret_store, &account).await?).await?;
 
     if let Some(wifi_interface) = &cfg.wifi_interface {
         crate::net::wifi::synchronize_wpa_supplicant_conf(
@@ -294,27 +288,35 @@ pub async fn init(cfg: &RpcContextConfig) -> Result<InitResult, Error> {
     if should_rebuild && tmp_docker_exists {
         tokio::fs::remove_dir_all(&tmp_docker).await?;
     }
-    Command::new("systemctl")
-        .arg("stop")
-        .arg("docker")
-        .invoke(crate::ErrorKind::Docker)
-        .await?;
-    crate::disk::mount::util::bind(&tmp_docker, "/var/lib/docker", false).await?;
-    Command::new("systemctl")
-        .arg("reset-failed")
-        .arg("docker")
-        .invoke(crate::ErrorKind::Docker)
-        .await?;
-    Command::new("systemctl")
-        .arg("start")
-        .arg("docker")
-        .invoke(crate::ErrorKind::Docker)
-        .await?;
-    tracing::info!("Mounted Docker Data");
+    let runtime = runtime();
+    for service in runtime.services() {
+        Command::new("systemctl")
+            .arg("stop")
+            .arg(service)
+            .invoke(crate::ErrorKind::Docker)
+            .await?;
+    }
+    if let Some(data_dir) = runtime.data_dir() {
+        crate::disk::mount::util::bind(&tmp_docker, data_dir, false).await?;
+    }
+    for service in runtime.services() {
+        Command::new("systemctl")
+            .arg("reset-failed")
+            .arg(service)
+            .invoke(crate::ErrorKind::Docker)
+            .await?;
+        Command::new("systemctl")
+            .arg("start")
+            .arg(service)
+            .invoke(crate::ErrorKind::Docker)
+            .await?;
+    }
+    tracing::info!("Mounted {} Data", runtime.name());
 
     if should_rebuild || !tmp_docker_exists {
         tracing::info!("Creating Docker Network");
-        bollard::Docker::connect_with_unix_defaults()?
+        runtime
+            .connect()?
             .create_network(bollard::network::CreateNetworkOptions {
                 name: "start9",
                 driver: "bridge",
@@ -344,17 +346,26 @@ pub async fn init(cfg: &RpcContextConfig) -> Result<InitResult, Error> {
         tracing::info!("Loaded Package Docker Images");
     }
 
-    tracing::info!("Enabling Docker QEMU Emulation");
-    Command::new("docker")
-        .arg("run")
-        .arg("--privileged")
-        .arg("--rm")
-        .arg("start9/x_system/binfmt")
-        .arg("--install")
-        .arg("all")
-        .invoke(crate::ErrorKind::Docker)
-        .await?;
-    tracing::info!("Enabled Docker QEMU Emulation");
+    if runtime.rootless() {
+        // the handlers are registered with the kernel, which only root can do
+        tracing::warn!(
+            "Not Enabling Docker QEMU Emulation: {} Is Rootless",
+            runtime.name()
+        );
+    } else {
+        tracing::info!("Enabling Docker QEMU Emulation");
+        runtime
+            .command()
+            .arg("run")
+            .arg("--privileged")
+            .arg("--rm")
+            .arg("start9/x_system/binfmt")
+            .arg("--install")
+            .arg("all")
+            .invoke(crate::ErrorKind::Docker)
+            .await?;
+        tracing::info!("Enabled Docker QEMU Emulation");
+    }
 
     let mut warn_time_not_synced = true;
     for _ in 0..60 {
@@ -378,6 +389,7 @@ pub async fn init(cfg: &RpcContextConfig) -> Result<InitResult, Error> {
         updated: false,
         update_progress: None,
         backup_progress: None,
+        vanity_progress: None,
     };
 
     server_info.system_start_time = time().await?;
@@ -0,0 +1,491 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::eyre;
+use emver::VersionRange;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use super::constraint::{constrain, constraint_for, ensure_allowed};
+use super::MinMax;
+use crate::context::RpcContext;
+use crate::db::model::{InstalledPackageDataEntry, PackageDataEntry};
+use crate::marketplace::auth::registry_get;
+use crate::marketplace::channel::{channel_for, Channel};
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::shutdown::Shutdown;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::time::{parse_time, TimeWindow};
+use crate::{Error, ErrorKind, ResultExt};
+
+const AUTO_UPDATE_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpdatePolicy {
+    /// New versions are installed as soon as the maintenance window allows
+    Auto,
+    /// New versions are announced with a notification but left for the user to install
+    NotifyOnly,
+}
+impl Default for UpdatePolicy {
+    fn default() -> Self {
+        UpdatePolicy::NotifyOnly
+    }
+}
+impl fmt::Display for UpdatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdatePolicy::Auto => write!(f, "auto"),
+            UpdatePolicy::NotifyOnly => write!(f, "notify-only"),
+        }
+    }
+}
+impl FromStr for UpdatePolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(UpdatePolicy::Auto),
+            "notify-only" => Ok(UpdatePolicy::NotifyOnly),
+            _ => Err(Error::new(
+                eyre!("Must be one of \"auto\", \"notify-only\". Use `package hold` to hold a package."),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AutoUpdateSettings {
+    /// When set, automatic updates are only installed during this window
+    pub maintenance_window: Option<TimeWindow>,
+    pub policies: BTreeMap<PackageId, UpdatePolicy>,
+}
+
+struct PolicyEntry {
+    policy: UpdatePolicy,
+    /// The last version the user was told about, so each one is only announced once
+    notified_version: Option<String>,
+}
+
+async fn load_policies<Ex>(secrets: &mut Ex) -> Result<BTreeMap<PackageId, PolicyEntry>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let mut policies = BTreeMap::new();
+    for row in
+        sqlx::query!("SELECT package_id, policy, notified_version FROM package_update_policy")
+            .fetch_all(&mut *secrets)
+            .await?
+    {
+        policies.insert(
+            row.package_id.parse()?,
+            PolicyEntry {
+                policy: row.policy.parse()?,
+                notified_version: row.notified_version,
+            },
+        );
+    }
+    Ok(policies)
+}
+
+async fn load_window<Ex>(secrets: &mut Ex) -> Result<Option<TimeWindow>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let config =
+        sqlx::query!("SELECT window_start, window_end FROM auto_update_config WHERE id = 0")
+            .fetch_one(&mut *secrets)
+            .await?;
+    Ok(match (config.window_start, config.window_end) {
+        (Some(start), Some(end)) => Some(TimeWindow::from_minutes(start, end)),
+        _ => None,
+    })
+}
+
+/// Forgets the policy of a package that is being uninstalled
+pub async fn remove_policy<Ex>(secrets: &mut Ex, id: &PackageId) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let id = id.as_str();
+    sqlx::query!(
+        "DELETE FROM package_update_policy WHERE package_id = $1",
+        id
+    )
+    .execute(secrets)
+    .await?;
+    Ok(())
+}
+
+#[command(
+    rename = "auto-update",
+    subcommands(get, set, set_window, clear_window)
+)]
+pub fn auto_update() -> Result<(), Error> {
+    Ok(())
+}
+
+/// The policy of every installed package, and the maintenance window
+#[command(display(display_serializable), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<AutoUpdateSettings, Error> {
+    let mut secrets = ctx.secret_store.acquire().await?;
+    let policies = load_policies(&mut secrets).await?;
+    let installed = super::list(ctx.clone()).await?;
+    Ok(AutoUpdateSettings {
+        maintenance_window: load_window(&mut secrets).await?,
+        policies: installed
+            .into_iter()
+            .map(|(id, _)| {
+                let policy = policies.get(&id).map(|p| p.policy).unwrap_or_default();
+                (id, policy)
+            })
+            .collect(),
+    })
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] policy: UpdatePolicy,
+) -> Result<(), Error> {
+    let id = id.as_str();
+    let policy = policy.to_string();
+    sqlx::query!(
+        "INSERT INTO package_update_policy (package_id, policy) VALUES ($1, $2) ON CONFLICT (package_id) DO UPDATE SET policy = $2",
+        id,
+        policy,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Only installs automatic updates between `start` and `end` (`HH:MM`, UTC)
+#[command(rename = "set-window", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set_window(
+    #[context] ctx: RpcContext,
+    #[arg] start: String,
+    #[arg] end: String,
+) -> Result<(), Error> {
+    let start = parse_time(&start)?;
+    let end = parse_time(&end)?;
+    sqlx::query!(
+        "UPDATE auto_update_config SET window_start = $1, window_end = $2 WHERE id = 0",
+        start,
+        end,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Lets automatic updates be installed at any time of day
+#[command(rename = "clear-window", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn clear_window(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE auto_update_config SET window_start = NULL, window_end = NULL WHERE id = 0"
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// The newest version of the package published as `id` on `channel`, within `constraint`
+pub async fn latest_manifest(
+    ctx: &RpcContext,
+    marketplace_url: &Url,
+    id: &PackageId,
+    channel: Channel,
+    constraint: Option<&VersionRange>,
+) -> Result<Manifest, Error> {
+    registry_get(
+        ctx,
+        format!(
+            "{}/package/v0/manifest/{}?spec={}&version-priority={}&channel={}",
+            marketplace_url,
+            id,
+            constrain(VersionRange::Any, constraint),
+            MinMax::Max,
+            channel,
+        )
+        .parse()?,
+    )
+    .send()
+    .await
+    .with_kind(ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(ErrorKind::Registry)?
+    .json()
+    .await
+    .with_kind(ErrorKind::Registry)
+}
+
+async fn mark_notified(ctx: &RpcContext, id: &PackageId, manifest: &Manifest) -> Result<(), Error> {
+    let id = id.as_str();
+    let version = manifest.version.as_str();
+    sqlx::query!(
+        "INSERT INTO package_update_policy (package_id, notified_version) VALUES ($1, $2) ON CONFLICT (package_id) DO UPDATE SET notified_version = $2",
+        id,
+        version,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Looks for new versions of every installed marketplace package, and installs or announces
+/// them according to each package's policy
+#[instrument(skip_all)]
+pub async fn check_for_updates(ctx: &RpcContext) -> Result<(), Error> {
+    let (policies, window) = {
+        let mut secrets = ctx.secret_store.acquire().await?;
+        (
+            load_policies(&mut secrets).await?,
+            load_window(&mut secrets).await?,
+        )
+    };
+    let in_window = window.map_or(true, |w| w.contains(Utc::now().time()));
+    let mut announce = Vec::new();
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    for (id, pde) in package_data.0 {
+        let installed = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        if installed.held {
+            continue;
+        }
+        let marketplace_url = if let Some(url) = installed.marketplace_url.clone() {
+            url
+        } else {
+            continue; // sideloaded
+        };
+        let (policy, notified_version) = policies
+            .get(&id)
+            .map(|p| (p.policy, p.notified_version.as_deref()))
+            .unwrap_or_default();
+        if policy == UpdatePolicy::Auto && !in_window {
+            continue;
+        }
+        match update_package(
+            ctx,
+            &id,
+            &installed,
+            marketplace_url,
+            policy,
+            notified_version,
+        )
+        .await
+        {
+            Ok(Some(latest)) => announce.push((id, installed, latest)),
+            Ok(None) => (),
+            Err(e) => {
+                tracing::warn!("Failed to check for updates to {}: {}", id, e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    }
+    announce_updates(ctx, announce).await
+}
+
+/// Announces every update that was found, in a single notification
+async fn announce_updates(
+    ctx: &RpcContext,
+    updates: Vec<(PackageId, InstalledPackageDataEntry, Manifest)>,
+) -> Result<(), Error> {
+    let (title, package_id) = match updates.as_slice() {
+        [] => return Ok(()),
+        [(id, _, _)] => ("Update Available", Some(id.clone())),
+        _ => ("Updates Available", None),
+    };
+    let list = updates
+        .iter()
+        .map(|(_, installed, latest)| format!("{} {}", installed.display_name(), latest.version))
+        .collect::<Vec<_>>()
+        .join(", ");
+    ctx.notification_manager
+        .notify(
+            &mut ctx.db.handle(),
+            package_id,
+            NotificationLevel::Info,
+            String::from(title),
+            if updates.len() == 1 {
+                format!("{} is available", list)
+            } else {
+                format!("{} are available", list)
+            },
+            (),
+            None,
+        )
+        .await?;
+    for (id, _, latest) in &updates {
+        mark_notified(ctx, id, latest).await?;
+    }
+    Ok(())
+}
+
+/// Installs the newest version of `id` when its policy allows, and otherwise returns it when it
+/// should be announced
+async fn update_package(
+    ctx: &RpcContext,
+    id: &PackageId,
+    installed: &InstalledPackageDataEntry,
+    marketplace_url: Url,
+    policy: UpdatePolicy,
+    notified_version: Option<&str>,
+) -> Result<Option<Manifest>, Error> {
+    let published_id = installed.instance_of.as_ref().unwrap_or(id);
+    let channel = channel_for(&mut ctx.db.handle(), &marketplace_url, id, published_id).await?;
+    let constraint = constraint_for(&mut ctx.db.handle(), id, published_id).await?;
+    let latest = latest_manifest(
+        ctx,
+        &marketplace_url,
+        published_id,
+        channel,
+        constraint.as_ref(),
+    )
+    .await?;
+    ensure_allowed(id, &latest.version, constraint.as_ref())?;
+    if latest.version <= installed.manifest.version {
+        return Ok(None);
+    }
+    if notified_version == Some(latest.version.as_str()) {
+        // each version is installed or announced once, so a failed install is not retried and
+        // reported again on every check
+        return Ok(None);
+    }
+    if policy == UpdatePolicy::Auto {
+        let breakages = super::update::dry(ctx.clone(), id.clone(), latest.version.clone())
+            .await?
+            .0;
+        if breakages.is_empty() {
+            tracing::info!("Automatically updating {} to {}", id, latest.version);
+            mark_notified(ctx, id, &latest).await?;
+            let (level, title, message) = match super::install(
+                ctx.clone(),
+                id.to_string(),
+                Some(marketplace_url),
+                Some(format!("={}", latest.version)),
+                None,
+                false,
+                None,
+                false,
+            )
+            .await
+            {
+                Ok(_) => (
+                    NotificationLevel::Info,
+                    "Installing Update",
+                    format!(
+                        "{} is being updated to {} automatically",
+                        installed.display_name(),
+                        latest.version
+                    ),
+                ),
+                Err(e) => (
+                    NotificationLevel::Error,
+                    "Automatic Update Failed",
+                    format!(
+                        "{} {} could not be installed automatically: {}",
+                        installed.display_name(),
+                        latest.version,
+                        e
+                    ),
+                ),
+            };
+            ctx.notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    Some(id.clone()),
+                    level,
+                    String::from(title),
+                    message,
+                    (),
+                    None,
+                )
+                .await?;
+        } else {
+            ctx.notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    Some(id.clone()),
+                    NotificationLevel::Warning,
+                    String::from("Automatic Update Held"),
+                    format!(
+                        "{} {} was not installed automatically because it would break: {}",
+                        installed.display_name(),
+                        latest.version,
+                        breakages
+                            .keys()
+                            .map(|id| id.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    (),
+                    None,
+                )
+                .await?;
+            mark_notified(ctx, id, &latest).await?;
+        }
+    } else {
+        return Ok(Some(latest));
+    }
+    Ok(None)
+}
+
+/// Checks for package updates every half hour until the server shuts down
+pub async fn launch_auto_update_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(AUTO_UPDATE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = check_for_updates(ctx).await {
+                    tracing::error!("Error Checking for Package Updates: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[test]
+fn maintenance_window() {
+    let at = |h, m| chrono::NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    let overnight = TimeWindow::from_minutes(parse_time("02:00").unwrap(), 4 * 60);
+    assert!(overnight.contains(at(3, 15)));
+    assert!(!overnight.contains(at(4, 0)));
+    let wrapping = TimeWindow::from_minutes(23 * 60, 60);
+    assert!(wrapping.contains(at(0, 30)));
+    assert!(!wrapping.contains(at(12, 0)));
+    assert_eq!(
+        "notify-only".parse::<UpdatePolicy>().unwrap(),
+        UpdatePolicy::NotifyOnly
+    );
+    assert!("sometimes".parse::<UpdatePolicy>().is_err());
+}
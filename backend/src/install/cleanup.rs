@@ -346,6 +346,7 @@ pub async fn uninstall<Ex>(
     db: &mut PatchDbHandle,
     secrets: &mut Ex,
     id: &PackageId,
+    keep_data: bool,
 ) -> Result<(), Error>
 where
     for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
@@ -397,9 +398,26 @@ where
         .join(crate::volume::PKG_VOLUME_DIR)
         .join(&entry.manifest.id);
 
-    tracing::debug!("Cleaning up {:?} at {:?}", volumes, dependents_paths);
-    cleanup_folder(volumes, Arc::new(dependents_paths)).await;
+    if keep_data {
+        tracing::info!("Keeping {:?} for a future reinstall", volumes);
+    } else {
+        tracing::debug!("Cleaning up {:?} at {:?}", volumes, dependents_paths);
+        cleanup_folder(volumes, Arc::new(dependents_paths)).await;
+    }
+    if let Err(e) = super::rollback::discard(&ctx.datadir, id).await {
+        tracing::warn!("Failed to remove rollback snapshot of {}: {}", id, e);
+        tracing::debug!("{:?}", e);
+    }
+    if let Err(e) = crate::net::isolation::remove_network(&ctx.docker, id).await {
+        tracing::warn!("Failed to remove the network of {}: {}", id, e);
+        tracing::debug!("{:?}", e);
+    }
     remove_tor_keys(secrets, &entry.manifest.id).await?;
+    crate::net::proxy_auth::remove_credentials(&ctx.net_controller, secrets, &entry.manifest.id)
+        .await?;
+    super::auto_update::remove_policy(secrets, &entry.manifest.id).await?;
+    crate::net::bandwidth::remove_usage(secrets, &entry.manifest.id).await?;
+    crate::net::i2p::remove_keys(secrets, &entry.manifest.id).await?;
     tx.commit().await?;
     Ok(())
 }
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use emver::VersionRange;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Version};
+use crate::{Error, ErrorKind};
+
+/// The range the operator restricted the package installed as `id`, that was published as
+/// `published_id`, to
+pub async fn constraint_for<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    published_id: &PackageId,
+) -> Result<Option<VersionRange>, Error> {
+    let constraints = crate::db::DatabaseModel::new()
+        .server_info()
+        .version_constraints()
+        .get(db)
+        .await?
+        .into_owned();
+    Ok(constraints
+        .get(id)
+        .or_else(|| constraints.get(published_id))
+        .cloned())
+}
+
+/// Narrows `spec` to the versions `constraint` allows
+pub fn constrain(spec: VersionRange, constraint: Option<&VersionRange>) -> VersionRange {
+    match constraint {
+        Some(constraint) => VersionRange::Conj(Box::new(spec), Box::new(constraint.clone())),
+        None => spec,
+    }
+}
+
+/// Fails unless `version` of `id` is allowed by `constraint`
+pub fn ensure_allowed(
+    id: &PackageId,
+    version: &Version,
+    constraint: Option<&VersionRange>,
+) -> Result<(), Error> {
+    match constraint {
+        Some(constraint) if !version.satisfies(constraint) => Err(Error::new(
+            eyre!(
+                "{} {} is not allowed by its version constraint {}. Clear it with `package constraint clear {}` to install it.",
+                id,
+                version,
+                constraint,
+                id
+            ),
+            ErrorKind::InvalidRequest,
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[command(subcommands(set, clear, list))]
+pub fn constraint() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Only installs, updates or resolves `id` to versions within `range`, e.g. `<26.0.0`
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] range: VersionRange,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut constraints = crate::db::DatabaseModel::new()
+        .server_info()
+        .version_constraints()
+        .get_mut(&mut db)
+        .await?;
+    constraints.insert(id, range);
+    constraints.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn clear(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut constraints = crate::db::DatabaseModel::new()
+        .server_info()
+        .version_constraints()
+        .get_mut(&mut db)
+        .await?;
+    if constraints.remove(&id).is_none() {
+        return Err(Error::new(
+            eyre!("{} has no version constraint", id),
+            ErrorKind::NotFound,
+        ));
+    }
+    constraints.save(&mut db).await?;
+    Ok(())
+}
+
+fn display_constraints(arg: BTreeMap<PackageId, VersionRange>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "ALLOWED VERSIONS"]);
+    for (id, range) in &arg {
+        table.add_row(row![&**id, range.to_string()]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_constraints), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<PackageId, VersionRange>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .version_constraints()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn constraints() {
+    let id: PackageId = "bitcoind".parse().unwrap();
+    let below: VersionRange = "<26.0.0".parse().unwrap();
+    let spec = constrain("*".parse().unwrap(), Some(&below));
+    assert!(emver::Version::new(25, 1, 0, 0).satisfies(&spec));
+    assert!(!emver::Version::new(26, 0, 0, 0).satisfies(&spec));
+    assert!(ensure_allowed(&id, &"25.1.0".parse().unwrap(), Some(&below)).is_ok());
+    assert!(ensure_allowed(&id, &"26.0.0".parse().unwrap(), Some(&below)).is_err());
+    assert!(ensure_allowed(&id, &"26.0.0".parse().unwrap(), None).is_ok());
+}
@@ -0,0 +1,331 @@
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::os::unix::prelude::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::notifications::NotificationLevel;
+use crate::procedure::docker::runtime;
+use crate::procedure::PackageProcedure;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+const DRI_PATH: &str = "/dev/dri";
+const NVIDIA_PATH: &str = "/dev/nvidiactl";
+
+/// Hardware a package can ask its containers be given, which they are once approved with
+/// `package devices approve`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Device {
+    /// The GPUs under `/dev/dri`, for VA-API and the like
+    Dri,
+    /// Every NVIDIA GPU, through the NVIDIA container runtime
+    Nvidia,
+}
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Dri => write!(f, "dri"),
+            Self::Nvidia => write!(f, "nvidia"),
+        }
+    }
+}
+impl FromStr for Device {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dri" => Ok(Self::Dri),
+            "nvidia" => Ok(Self::Nvidia),
+            _ => Err(Error::new(
+                eyre!("Unknown device {}: expected dri or nvidia", s),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+impl Device {
+    /// Whether this server has the device at all
+    async fn present(&self) -> bool {
+        let path = match self {
+            Self::Dri => DRI_PATH,
+            Self::Nvidia => NVIDIA_PATH,
+        };
+        tokio::fs::metadata(path).await.is_ok()
+    }
+}
+
+/// The devices the manifest asks for, in any of its containers
+pub fn requested(manifest: &Manifest) -> BTreeSet<Device> {
+    let procedures = manifest.package_procedures().filter_map(|p| match p {
+        PackageProcedure::Docker(d) => Some(d.devices()),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    });
+    manifest
+        .containers
+        .iter()
+        .map(|c| c.main.devices())
+        .chain(procedures)
+        .flatten()
+        .collect()
+}
+
+/// What a package installed before devices had to be approved was given: `gpu-acceleration` gave
+/// it `/dev/dri`, so that is approved rather than taken away
+pub fn grandfathered(manifest: &Manifest) -> BTreeSet<Device> {
+    requested(manifest)
+        .into_iter()
+        .filter(|d| d == &Device::Dri)
+        .collect()
+}
+
+fn get_devices<'a>(path: &'a Path, res: &'a mut Vec<PathBuf>) -> BoxFuture<'a, Result<(), Error>> {
+    async move {
+        let mut read_dir = tokio::fs::read_dir(path).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let fty = entry.metadata().await?.file_type();
+            if fty.is_block_device() || fty.is_char_device() {
+                res.push(entry.path());
+            } else if fty.is_dir() {
+                get_devices(&*entry.path(), res).await?;
+            }
+        }
+        Ok(())
+    }
+    .boxed()
+}
+
+/// For `docker run`, to give a container of `package` those of the `requested` devices that
+/// were approved. The others are left out: the package has to do without them.
+pub async fn docker_args(
+    ctx: &RpcContext,
+    package: &PackageId,
+    requested: BTreeSet<Device>,
+) -> Result<Vec<OsString>, Error> {
+    if requested.is_empty() {
+        return Ok(Vec::new());
+    }
+    let approved = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.approved_devices())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .flatten()
+        .unwrap_or_default();
+    let mut res = Vec::new();
+    if runtime().rootless() {
+        if requested.intersection(&approved).next().is_some() {
+            tracing::warn!(
+                "{} is rootless, so the devices approved for {} cannot be given to it",
+                runtime().name(),
+                package
+            );
+        }
+        return Ok(res);
+    }
+    for device in requested.intersection(&approved) {
+        if !device.present().await {
+            tracing::warn!(
+                "{} was approved for {}, but is not present",
+                device,
+                package
+            );
+            continue;
+        }
+        match device {
+            Device::Dri => {
+                let mut devices = Vec::new();
+                get_devices(Path::new(DRI_PATH), &mut devices).await?;
+                for device in devices {
+                    res.push("--device".into());
+                    res.push(device.into());
+                }
+            }
+            Device::Nvidia => res.extend(runtime().gpu_args()),
+        }
+    }
+    Ok(res)
+}
+
+/// Tells the operator a package that was just installed asks for devices that are not approved
+pub async fn notify_pending(ctx: &RpcContext, manifest: &Manifest) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let (requested, approved) = installed(&mut db, &manifest.id).await?;
+    let pending = requested
+        .difference(&approved)
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    ctx.notification_manager
+        .notify(
+            &mut db,
+            Some(manifest.id.clone()),
+            NotificationLevel::Info,
+            "Hardware Access Requested".to_owned(),
+            format!(
+                "{} asks for access to {}, which it is not given until it is approved.",
+                manifest.title,
+                pending.join(", ")
+            ),
+            (),
+            None,
+        )
+        .await
+}
+
+async fn installed<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+) -> Result<(BTreeSet<Device>, BTreeSet<Device>), Error> {
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|p| p.installed())
+        .get(db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    Ok((
+        requested(&installed.manifest),
+        installed.approved_devices.unwrap_or_default(),
+    ))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageDevices {
+    pub requested: BTreeSet<Device>,
+    pub approved: BTreeSet<Device>,
+}
+
+#[command(subcommands(list, approve, revoke))]
+pub fn devices() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_devices(arg: PackageDevices, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "DEVICE", "REQUESTED", "APPROVED"]);
+    for device in arg.requested.union(&arg.approved) {
+        table.add_row(row![
+            device,
+            arg.requested.contains(device),
+            arg.approved.contains(device)
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The devices `id` asks for, and those it was approved
+#[command(display(display_devices), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<PackageDevices, Error> {
+    let (requested, approved) = installed(&mut ctx.db.handle(), &id).await?;
+    Ok(PackageDevices {
+        requested,
+        approved,
+    })
+}
+
+/// Gives the containers of `id` the `device` its manifest asks for. Devices are shared with
+/// the host and the other packages given them, which can reach their memory, so only approve
+/// those of packages you trust. The package is restarted if it is running.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn approve(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] device: Device,
+) -> Result<(), Error> {
+    let (requested, _) = installed(&mut ctx.db.handle(), &id).await?;
+    if !requested.contains(&device) {
+        return Err(Error::new(
+            eyre!("{} does not ask for {}", id, device),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    if !device.present().await {
+        return Err(Error::new(
+            eyre!("This server has no {} device", device),
+            ErrorKind::NotFound,
+        ));
+    }
+    update(&ctx, id, device, true).await
+}
+
+/// Takes `device` away from the containers of `id`. The package is restarted if it is running.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn revoke(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] device: Device,
+) -> Result<(), Error> {
+    update(&ctx, id, device, false).await
+}
+
+async fn update(
+    ctx: &RpcContext,
+    id: PackageId,
+    device: Device,
+    approved: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|m| m.installed())
+        .check(&mut tx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    let mut devices = installed
+        .clone()
+        .approved_devices()
+        .get_mut(&mut tx)
+        .await?;
+    let set = devices.get_or_insert_with(BTreeSet::new);
+    let changed = if approved {
+        set.insert(device)
+    } else {
+        set.remove(&device)
+    };
+    devices.save(&mut tx).await?;
+    let status = installed.status().main().get(&mut tx).await?.into_owned();
+    tx.commit().await?;
+    if changed && matches!(status, MainStatus::Running { .. }) {
+        crate::control::restart(ctx.clone(), id).await?;
+    }
+    Ok(())
+}
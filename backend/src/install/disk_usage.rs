@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Duration;
+
+use bollard::image::ListImagesOptions;
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::VolumeId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use super::rollback::rollback_dir;
+use super::PKG_ARCHIVE_DIR;
+use crate::context::RpcContext;
+use crate::db::model::PackageDataEntry;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::s9pk::reader::ImageTag;
+use crate::shutdown::Shutdown;
+use crate::util::io::dir_size;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::volume::{data_dir, Volume, BACKUP_DIR};
+use crate::{Error, ErrorKind};
+
+const DISK_USAGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Bytes used by an installed package, as of `measured-at`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageDiskUsage {
+    pub volumes: BTreeMap<VolumeId, u64>,
+    pub images: u64,
+    /// The s9pks kept for the installed and previous versions
+    pub archives: u64,
+    /// The data kept to roll back the last update
+    pub rollback: u64,
+    /// As of the last time a backup drive was attached
+    pub backup: Option<u64>,
+    pub measured_at: DateTime<Utc>,
+}
+
+async fn size_of(path: &Path) -> Result<u64, Error> {
+    if tokio::fs::metadata(path).await.is_err() {
+        return Ok(0);
+    }
+    Ok(dir_size(path, None).await?)
+}
+
+/// The size of the docker images of every package. Images tagged for several packages count
+/// towards the first.
+async fn image_sizes(ctx: &RpcContext) -> Result<BTreeMap<PackageId, u64>, Error> {
+    let mut sizes = BTreeMap::new();
+    for image in ctx
+        .docker
+        .list_images(Some(ListImagesOptions::<String> {
+            all: false,
+            filters: HashMap::new(),
+            digests: false,
+        }))
+        .await?
+    {
+        if let Some(tag) = image
+            .repo_tags
+            .iter()
+            .find_map(|t| t.parse::<ImageTag>().ok())
+        {
+            *sizes.entry(tag.package_id).or_default() += image.size.max(0) as u64;
+        }
+    }
+    Ok(sizes)
+}
+
+async fn measure(
+    ctx: &RpcContext,
+    manifest: &Manifest,
+    images: u64,
+    prev: Option<&PackageDiskUsage>,
+) -> Result<PackageDiskUsage, Error> {
+    let mut volumes = BTreeMap::new();
+    for (volume_id, volume) in manifest.volumes.iter() {
+        if let Volume::Data { .. } = volume {
+            volumes.insert(
+                volume_id.clone(),
+                size_of(&data_dir(&ctx.datadir, &manifest.id, volume_id)).await?,
+            );
+        }
+    }
+    // only mounted while a backup drive is attached
+    let backup_path = Path::new(BACKUP_DIR).join(&manifest.id);
+    let backup = if tokio::fs::metadata(&backup_path).await.is_ok() {
+        Some(size_of(&backup_path).await?)
+    } else {
+        prev.and_then(|p| p.backup)
+    };
+    Ok(PackageDiskUsage {
+        volumes,
+        images,
+        archives: size_of(&ctx.datadir.join(PKG_ARCHIVE_DIR).join(&manifest.id)).await?,
+        rollback: size_of(&rollback_dir(&ctx.datadir, &manifest.id)).await?,
+        backup,
+        measured_at: Utc::now(),
+    })
+}
+
+/// Measures every installed package and stores the result with it
+#[instrument(skip_all)]
+pub async fn refresh(ctx: &RpcContext) -> Result<(), Error> {
+    let images = image_sizes(ctx).await?;
+    let packages = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .0;
+    for (id, pde) in packages {
+        let installed = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        let usage = measure(
+            ctx,
+            &installed.manifest,
+            images.get(&id).copied().unwrap_or_default(),
+            installed.disk_usage.as_ref(),
+        )
+        .await?;
+        let mut db = ctx.db.handle();
+        let mut tx = db.begin().await?;
+        // the package may have been removed while it was measured
+        if let Some(installed_model) = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&id)
+            .and_then(|p| p.installed())
+            .check(&mut tx)
+            .await?
+        {
+            installed_model
+                .disk_usage()
+                .put(&mut tx, &Some(usage))
+                .await?;
+        }
+        tx.commit().await?;
+    }
+    Ok(())
+}
+
+fn display_disk_usage(arg: BTreeMap<PackageId, Option<PackageDiskUsage>>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(
+        row![bc => "PACKAGE", "VOLUMES", "IMAGES", "ARCHIVES", "ROLLBACK", "BACKUP", "MEASURED AT"],
+    );
+    for (id, usage) in &arg {
+        if let Some(usage) = usage {
+            table.add_row(row![
+                &**id,
+                usage.volumes.values().sum::<u64>(),
+                usage.images,
+                usage.archives,
+                usage.rollback,
+                usage
+                    .backup
+                    .map_or_else(|| "N/A".to_owned(), |b| b.to_string()),
+                usage.measured_at.to_rfc3339(),
+            ]);
+        } else {
+            table.add_row(row![&**id, "N/A", "N/A", "N/A", "N/A", "N/A", "never"]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The bytes used by each installed package, or just by `id`, as of their last measurement
+#[command(
+    rename = "disk-usage",
+    display(display_disk_usage),
+    metadata(read_only = true)
+)]
+#[instrument(skip_all)]
+pub async fn disk_usage(
+    #[context] ctx: RpcContext,
+    #[arg] id: Option<PackageId>,
+    #[arg(long = "refresh", help = "Measure again before reporting")] refresh: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<PackageId, Option<PackageDiskUsage>>, Error> {
+    if refresh {
+        self::refresh(&ctx).await?;
+    }
+    let usage: BTreeMap<_, _> = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .0
+        .into_iter()
+        .filter_map(|(id, pde)| match pde {
+            PackageDataEntry::Installed { installed, .. } => Some((id, installed.disk_usage)),
+            _ => None,
+        })
+        .filter(|(pkg, _)| id.as_ref().map_or(true, |id| id == pkg))
+        .collect();
+    if let Some(id) = id {
+        if usage.is_empty() {
+            return Err(Error::new(
+                eyre!("{} is not installed", id),
+                ErrorKind::NotFound,
+            ));
+        }
+    }
+    Ok(usage)
+}
+
+/// Measures package disk usage every hour until the server shuts down
+pub async fn launch_disk_usage_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(DISK_USAGE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = refresh(ctx).await {
+                    tracing::error!("Error Measuring Package Disk Usage: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
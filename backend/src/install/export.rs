@@ -0,0 +1,108 @@
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use futures::FutureExt;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use rpc_toolkit::command;
+use rpc_toolkit::hyper::{Body, Response};
+use rpc_toolkit::yajrc::RpcError;
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use super::PKG_ARCHIVE_DIR;
+use crate::context::{CliContext, RpcContext};
+use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Sends the s9pk of the installed version of `id`, which still carries its developer's signature
+/// so another server will accept it. Fetch the returned guid from `/rest/rpc/<guid>` within 30
+/// seconds to receive it.
+#[command(
+    custom_cli(cli_export(async, context(CliContext))),
+    display(display_none),
+    metadata(read_only = true)
+)]
+#[instrument(skip_all)]
+pub async fn export(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[allow(unused_variables)]
+    #[arg(
+        long = "path",
+        help = "CLI Only: Where to write the s9pk. Defaults to <id>.s9pk in the current directory"
+    )]
+    path: Option<PathBuf>,
+) -> Result<RequestGuid, Error> {
+    let version = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|p| p.installed())
+        .map(|m| m.manifest().version())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    let archive = ctx
+        .datadir
+        .join(PKG_ARCHIVE_DIR)
+        .join(&id)
+        .join(version.as_str())
+        .join(AsRef::<Path>::as_ref(&id).with_extension("s9pk"));
+    if tokio::fs::metadata(&archive).await.is_err() {
+        return Err(Error::new(
+            eyre!("The s9pk of {}@{} is missing", id, version),
+            ErrorKind::NotFound,
+        ));
+    }
+    let guid = RequestGuid::new();
+    let handler = Box::new(move |_| {
+        async move {
+            let file = tokio::fs::File::open(&archive)
+                .await
+                .with_ctx(|_| (ErrorKind::Filesystem, archive.display().to_string()))?;
+            let len = file.metadata().await?.len();
+            Response::builder()
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(CONTENT_LENGTH, len)
+                .body(Body::wrap_stream(tokio_util::io::ReaderStream::new(file)))
+                .with_kind(ErrorKind::Network)
+        }
+        .boxed()
+    });
+    ctx.add_continuation(
+        guid.clone(),
+        RpcContinuation::rest(handler, Duration::from_secs(30)),
+    )
+    .await;
+    Ok(guid)
+}
+
+#[instrument(skip_all)]
+async fn cli_export(ctx: CliContext, id: PackageId, path: Option<PathBuf>) -> Result<(), RpcError> {
+    let path = path.unwrap_or_else(|| AsRef::<Path>::as_ref(&id).with_extension("s9pk"));
+    let guid = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.export",
+        serde_json::json!({ "id": id }),
+        PhantomData::<RequestGuid>,
+    )
+    .await?
+    .result?;
+    let mut res = ctx
+        .client
+        .get(format!("{}rest/rpc/{}", ctx.base_url, guid))
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut file = tokio::fs::File::create(&path).await?;
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+    tracing::info!("Package Exported to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bollard::image::{ListImagesOptions, PruneImagesOptions, RemoveImageOptions};
+use clap::ArgMatches;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use super::PKG_ARCHIVE_DIR;
+use crate::context::RpcContext;
+use crate::db::model::PackageDataEntry;
+use crate::s9pk::manifest::PackageId;
+use crate::s9pk::reader::ImageTag;
+use crate::shutdown::Shutdown;
+use crate::util::io::dir_size;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::Error;
+
+const GC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GarbageKind {
+    Archive,
+    Image,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Garbage {
+    pub kind: GarbageKind,
+    pub package_id: PackageId,
+    pub version: Version,
+    /// The archive directory, or the image tags
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GcReport {
+    /// Whether the garbage was removed, or only listed
+    pub removed: bool,
+    pub garbage: Vec<Garbage>,
+    pub reclaimable: u64,
+}
+
+/// The result of `server prune-images`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PruneReport {
+    /// Whether the images were removed, or only listed
+    pub removed: bool,
+    /// The images of package versions that are no longer installed
+    pub unreferenced: Vec<Garbage>,
+    /// The ids of the untagged images no container uses, whose tags went to the images of an
+    /// update
+    pub dangling: Vec<String>,
+    /// What the image layers take on disk less, or about what they would. Layers shared with
+    /// images that are kept are not counted.
+    pub reclaimed: u64,
+}
+
+/// The installed version of every package. Packages that are in the middle of an install, update,
+/// restore or removal map to `None`, and nothing of theirs is collected.
+async fn versions_in_use(ctx: &RpcContext) -> Result<BTreeMap<PackageId, Option<Version>>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .0
+        .iter()
+        .map(|(id, pde)| match pde {
+            PackageDataEntry::Installed { installed, .. } => {
+                (id.clone(), Some(installed.manifest.version.clone()))
+            }
+            _ => (id.clone(), None),
+        })
+        .collect())
+}
+
+fn is_garbage(
+    in_use: &BTreeMap<PackageId, Option<Version>>,
+    id: &PackageId,
+    version: &Version,
+) -> bool {
+    match in_use.get(id) {
+        Some(Some(installed)) => installed != version,
+        Some(None) => false,
+        None => true,
+    }
+}
+
+async fn find_archives(
+    ctx: &RpcContext,
+    in_use: &BTreeMap<PackageId, Option<Version>>,
+) -> Result<Vec<(Garbage, PathBuf)>, Error> {
+    let mut garbage = Vec::new();
+    let root = ctx.datadir.join(PKG_ARCHIVE_DIR);
+    if tokio::fs::metadata(&root).await.is_err() {
+        return Ok(garbage);
+    }
+    let mut packages = tokio::fs::read_dir(&root).await?;
+    while let Some(package) = packages.next_entry().await? {
+        let id: PackageId = match package.file_name().to_str().map(|s| s.parse()) {
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        let mut versions = tokio::fs::read_dir(package.path()).await?;
+        while let Some(version) = versions.next_entry().await? {
+            let v: Version = match version.file_name().to_str().map(|s| s.parse()) {
+                Some(Ok(v)) => v,
+                _ => continue,
+            };
+            if is_garbage(in_use, &id, &v) {
+                let path = version.path();
+                garbage.push((
+                    Garbage {
+                        kind: GarbageKind::Archive,
+                        package_id: id.clone(),
+                        version: v,
+                        name: path.display().to_string(),
+                        size: dir_size(&path, None).await?,
+                    },
+                    path,
+                ));
+            }
+        }
+    }
+    Ok(garbage)
+}
+
+async fn find_images(
+    ctx: &RpcContext,
+    in_use: &BTreeMap<PackageId, Option<Version>>,
+) -> Result<Vec<(Garbage, Vec<String>)>, Error> {
+    let mut garbage = Vec::new();
+    for image in ctx
+        .docker
+        .list_images(Some(ListImagesOptions::<String> {
+            all: false,
+            filters: HashMap::new(),
+            digests: false,
+        }))
+        .await?
+    {
+        let tags = image
+            .repo_tags
+            .iter()
+            .filter_map(|t| t.parse::<ImageTag>().ok().map(|tag| (t, tag)))
+            .collect::<Vec<_>>();
+        // images shared with anything still in use are kept
+        if tags.is_empty()
+            || tags.len() != image.repo_tags.len()
+            || !tags
+                .iter()
+                .all(|(_, tag)| is_garbage(in_use, &tag.package_id, &tag.version))
+        {
+            continue;
+        }
+        let names = tags.iter().map(|(t, _)| (*t).clone()).collect::<Vec<_>>();
+        let (_, first) = &tags[0];
+        garbage.push((
+            Garbage {
+                kind: GarbageKind::Image,
+                package_id: first.package_id.clone(),
+                version: first.version.clone(),
+                name: names.join(", "),
+                size: image.size.max(0) as u64,
+            },
+            names,
+        ));
+    }
+    Ok(garbage)
+}
+
+/// The ids of the untagged images no container uses, and what they take on disk
+async fn find_dangling(ctx: &RpcContext) -> Result<Vec<(String, u64)>, Error> {
+    let mut filters = HashMap::new();
+    filters.insert("dangling".to_owned(), vec!["true".to_owned()]);
+    Ok(ctx
+        .docker
+        .list_images(Some(ListImagesOptions {
+            all: false,
+            filters,
+            digests: false,
+        }))
+        .await?
+        .into_iter()
+        .map(|image| {
+            let size = if image.shared_size >= 0 {
+                image.size - image.shared_size
+            } else {
+                image.size
+            };
+            (image.id, size.max(0) as u64)
+        })
+        .collect())
+}
+
+/// Untags `images`, which removes them along with the layers only they use, unless `dry_run` is
+/// set. Returns those that were removed.
+async fn remove_images(
+    ctx: &RpcContext,
+    images: Vec<(Garbage, Vec<String>)>,
+    dry_run: bool,
+) -> Vec<Garbage> {
+    let mut garbage = Vec::with_capacity(images.len());
+    for (item, tags) in images {
+        if !dry_run {
+            let mut removed = true;
+            for tag in &tags {
+                if let Err(e) = ctx
+                    .docker
+                    .remove_image(
+                        tag,
+                        Some(RemoveImageOptions {
+                            force: false,
+                            noprune: false,
+                        }),
+                        None,
+                    )
+                    .await
+                {
+                    tracing::warn!("Failed to remove image {}: {}", tag, e);
+                    removed = false;
+                }
+            }
+            if !removed {
+                continue;
+            }
+        }
+        garbage.push(item);
+    }
+    garbage
+}
+
+async fn layers_size(ctx: &RpcContext) -> Result<u64, Error> {
+    Ok(ctx
+        .docker
+        .df()
+        .await?
+        .layers_size
+        .unwrap_or_default()
+        .max(0) as u64)
+}
+
+/// Finds the docker images of package versions that are no longer installed, and those left
+/// dangling by updates, and removes them unless `dry_run` is set
+#[instrument(skip_all)]
+pub async fn prune(ctx: &RpcContext, dry_run: bool) -> Result<PruneReport, Error> {
+    let in_use = versions_in_use(ctx).await?;
+    let images = find_images(ctx, &in_use).await?;
+    let dangling = find_dangling(ctx).await?;
+    if dry_run {
+        return Ok(PruneReport {
+            removed: false,
+            reclaimed: images
+                .iter()
+                .map(|(item, _)| item.size)
+                .chain(dangling.iter().map(|(_, size)| *size))
+                .sum(),
+            unreferenced: images.into_iter().map(|(item, _)| item).collect(),
+            dangling: dangling.into_iter().map(|(id, _)| id).collect(),
+        });
+    }
+    let before = layers_size(ctx).await?;
+    let unreferenced = remove_images(ctx, images, false).await;
+    let mut filters = HashMap::new();
+    filters.insert("dangling", vec!["true"]);
+    let deleted = ctx
+        .docker
+        .prune_images(Some(PruneImagesOptions { filters }))
+        .await?
+        .images_deleted
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|i| i.deleted)
+        .collect::<BTreeSet<_>>();
+    Ok(PruneReport {
+        removed: true,
+        unreferenced,
+        dangling: dangling
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| deleted.contains(id))
+            .collect(),
+        reclaimed: before.saturating_sub(layers_size(ctx).await?),
+    })
+}
+
+/// Finds the archives and docker images of package versions that are no longer installed, and
+/// removes them unless `dry_run` is set
+#[instrument(skip_all)]
+pub async fn collect(ctx: &RpcContext, dry_run: bool) -> Result<GcReport, Error> {
+    let in_use = versions_in_use(ctx).await?;
+    let archives = find_archives(ctx, &in_use).await?;
+    let images = find_images(ctx, &in_use).await?;
+    let mut garbage = Vec::with_capacity(archives.len() + images.len());
+    for (item, path) in archives {
+        if !dry_run {
+            if let Err(e) = tokio::fs::remove_dir_all(&path).await {
+                tracing::warn!("Failed to remove {}: {}", path.display(), e);
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                // only succeeds once no versions are left
+                tokio::fs::remove_dir(parent).await.unwrap_or_default();
+            }
+        }
+        garbage.push(item);
+    }
+    garbage.extend(remove_images(ctx, images, dry_run).await);
+    Ok(GcReport {
+        removed: !dry_run,
+        reclaimable: garbage.iter().map(|g| g.size).sum(),
+        garbage,
+    })
+}
+
+fn display_gc(arg: GcReport, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "KIND", "PACKAGE", "VERSION", "SIZE (BYTES)", "NAME"]);
+    for item in &arg.garbage {
+        table.add_row(row![
+            match item.kind {
+                GarbageKind::Archive => "archive",
+                GarbageKind::Image => "image",
+            },
+            &*item.package_id,
+            item.version.as_str(),
+            item.size,
+            &item.name,
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    println!(
+        "{} {} bytes",
+        if arg.removed {
+            "Reclaimed"
+        } else {
+            "Reclaimable:"
+        },
+        arg.reclaimable
+    );
+}
+
+/// Removes package archives and docker images left behind by updates and failed installs
+#[command(display(display_gc), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn gc(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "dry-run", long = "dry-run")] dry_run: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<GcReport, Error> {
+    collect(&ctx, dry_run).await
+}
+
+fn display_prune(arg: PruneReport, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "KIND", "PACKAGE", "VERSION", "NAME"]);
+    for item in &arg.unreferenced {
+        table.add_row(row![
+            "unreferenced",
+            &*item.package_id,
+            item.version.as_str(),
+            &item.name,
+        ]);
+    }
+    for id in &arg.dangling {
+        table.add_row(row!["dangling", "-", "-", id]);
+    }
+    table.print_tty(false).unwrap();
+    println!(
+        "{} {} bytes",
+        if arg.removed {
+            "Reclaimed"
+        } else {
+            "Reclaimable:"
+        },
+        arg.reclaimed
+    );
+}
+
+/// Removes the docker images of package versions that are no longer installed, and those left
+/// untagged by updates, along with the layers no other image shares
+#[command(
+    rename = "prune-images",
+    display(display_prune),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn prune_images(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "dry-run", long = "dry-run")] dry_run: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<PruneReport, Error> {
+    prune(&ctx, dry_run).await
+}
+
+/// Collects package garbage and prunes images once a day until the server shuts down
+pub async fn launch_gc_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(GC_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = collect(ctx, false).await {
+                    tracing::error!("Error Collecting Package Garbage: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+                match prune(ctx, false).await {
+                    Ok(report) if report.reclaimed > 0 => {
+                        tracing::info!("Pruned Images, Reclaiming {} Bytes", report.reclaimed)
+                    }
+                    Ok(_) => (),
+                    Err(e) => {
+                        tracing::error!("Error Pruning Images: {}", e);
+                        tracing::debug!("{:?}", e);
+                    }
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[test]
+fn garbage() {
+    let in_use: BTreeMap<PackageId, Option<Version>> = [
+        ("bitcoind".parse().unwrap(), Some("25.0.0".parse().unwrap())),
+        ("lnd".parse().unwrap(), None),
+    ]
+    .into_iter()
+    .collect();
+    let check = |id: &str, v: &str| is_garbage(&in_use, &id.parse().unwrap(), &v.parse().unwrap());
+    assert!(!check("bitcoind", "25.0.0"));
+    assert!(check("bitcoind", "24.0.1"));
+    // mid-update, so everything is kept
+    assert!(!check("lnd", "0.16.0"));
+    assert!(check("electrs", "0.9.14"));
+}
@@ -0,0 +1,59 @@
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::db::model::InstalledPackageDataEntry;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::display_none;
+use crate::{Error, ErrorKind};
+
+/// Fails if `installed` is held at a version other than that of `manifest`
+pub fn ensure_not_held(
+    installed: &InstalledPackageDataEntry,
+    manifest: &Manifest,
+) -> Result<(), Error> {
+    if installed.held && installed.manifest.version != manifest.version {
+        return Err(Error::new(
+            eyre!(
+                "{} is held at {}. Unhold it to update.",
+                manifest.id,
+                installed.manifest.version
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(())
+}
+
+async fn set_held(ctx: &RpcContext, id: &PackageId, held: bool) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|m| m.installed())
+        .check(&mut tx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?
+        .held()
+        .put(&mut tx, &held)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Keeps `id` at its installed version: updates are neither offered nor installed until it is
+/// unheld
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn hold(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    set_held(&ctx, &id, true).await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn unhold(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    set_held(&ctx, &id, false).await
+}
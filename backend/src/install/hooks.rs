@@ -0,0 +1,254 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke, Version};
+use crate::{Error, ErrorKind, ResultExt};
+
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEvent {
+    PreInstall,
+    PostInstall,
+    PreUpdate,
+    PostUpdate,
+    PreUninstall,
+    PostUninstall,
+}
+impl HookEvent {
+    /// A failing pre hook aborts the operation, a failing post hook is only logged
+    pub fn is_pre(&self) -> bool {
+        matches!(
+            self,
+            HookEvent::PreInstall | HookEvent::PreUpdate | HookEvent::PreUninstall
+        )
+    }
+}
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HookEvent::PreInstall => write!(f, "pre-install"),
+            HookEvent::PostInstall => write!(f, "post-install"),
+            HookEvent::PreUpdate => write!(f, "pre-update"),
+            HookEvent::PostUpdate => write!(f, "post-update"),
+            HookEvent::PreUninstall => write!(f, "pre-uninstall"),
+            HookEvent::PostUninstall => write!(f, "post-uninstall"),
+        }
+    }
+}
+impl FromStr for HookEvent {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pre-install" => Ok(HookEvent::PreInstall),
+            "post-install" => Ok(HookEvent::PostInstall),
+            "pre-update" => Ok(HookEvent::PreUpdate),
+            "post-update" => Ok(HookEvent::PostUpdate),
+            "pre-uninstall" => Ok(HookEvent::PreUninstall),
+            "post-uninstall" => Ok(HookEvent::PostUninstall),
+            _ => Err(Error::new(
+                eyre!("Must be one of \"pre-install\", \"post-install\", \"pre-update\", \"post-update\", \"pre-uninstall\", \"post-uninstall\"."),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+
+/// What a hook is told about the operation. Scripts receive it as `HOOK_EVENT`, `PACKAGE_ID`,
+/// `PACKAGE_VERSION` and `PREVIOUS_VERSION` environment variables, webhooks as a JSON body.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct HookPayload {
+    pub event: HookEvent,
+    pub package_id: PackageId,
+    pub version: Version,
+    pub previous_version: Option<Version>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hook {
+    pub id: i32,
+    pub event: HookEvent,
+    /// An http(s) url to POST to, or the absolute path of an executable
+    pub target: String,
+    pub created_at: DateTime<Utc>,
+}
+
+fn is_webhook(target: &str) -> bool {
+    target.starts_with("https://") || target.starts_with("http://")
+}
+
+fn check_target(target: &str) -> Result<(), Error> {
+    if is_webhook(target) {
+        target
+            .parse::<reqwest::Url>()
+            .with_kind(ErrorKind::ParseUrl)?;
+    } else if !Path::new(target).is_absolute() {
+        return Err(Error::new(
+            eyre!("Hook must be an http(s) url or an absolute path to an executable"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(())
+}
+
+#[command(subcommands(add, list, remove))]
+pub fn hooks() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Runs `target` whenever `event` happens to any package, and returns the id of the hook
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] event: HookEvent,
+    #[arg] target: String,
+) -> Result<i32, Error> {
+    check_target(&target)?;
+    let event = event.to_string();
+    Ok(sqlx::query!(
+        "INSERT INTO package_hooks (event, target) VALUES ($1, $2) RETURNING id",
+        event,
+        target,
+    )
+    .fetch_one(&ctx.secret_store)
+    .await?
+    .id)
+}
+
+fn display_hooks(arg: Vec<Hook>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ID", "EVENT", "TARGET", "ADDED AT"]);
+    for hook in arg {
+        table.add_row(row![
+            hook.id,
+            hook.event.to_string(),
+            &hook.target,
+            hook.created_at.to_rfc3339(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_hooks), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<Hook>, Error> {
+    sqlx::query!("SELECT id, event, target, created_at FROM package_hooks ORDER BY id")
+        .fetch_all(&ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok(Hook {
+                id: r.id,
+                event: r.event.parse()?,
+                target: r.target,
+                created_at: DateTime::from_utc(r.created_at, Utc),
+            })
+        })
+        .collect()
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] id: i32) -> Result<(), Error> {
+    let removed = sqlx::query!("DELETE FROM package_hooks WHERE id = $1", id)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected();
+    if removed == 0 {
+        return Err(Error::new(
+            eyre!("Hook {} does not exist", id),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+async fn run_hook(ctx: &RpcContext, target: &str, payload: &HookPayload) -> Result<(), Error> {
+    if is_webhook(target) {
+        ctx.client
+            .post(target)
+            .timeout(HOOK_TIMEOUT)
+            .json(payload)
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .error_for_status()
+            .with_kind(ErrorKind::Network)?;
+    } else {
+        let mut cmd = Command::new(target);
+        cmd.env("HOOK_EVENT", payload.event.to_string())
+            .env("PACKAGE_ID", payload.package_id.as_str())
+            .env("PACKAGE_VERSION", payload.version.as_str())
+            .env(
+                "PREVIOUS_VERSION",
+                payload.previous_version.as_ref().map_or("", |v| v.as_str()),
+            )
+            .kill_on_drop(true);
+        tokio::time::timeout(HOOK_TIMEOUT, cmd.invoke(ErrorKind::Action))
+            .await
+            .map_err(|_| Error::new(eyre!("Timed out"), ErrorKind::Action))??;
+    }
+    Ok(())
+}
+
+/// Runs every hook registered for the event of `payload`, in the order they were added. Errors are
+/// only returned for pre hooks.
+#[instrument(skip_all)]
+pub async fn run(ctx: &RpcContext, payload: HookPayload) -> Result<(), Error> {
+    let event = payload.event.to_string();
+    let targets = sqlx::query!(
+        "SELECT target FROM package_hooks WHERE event = $1 ORDER BY id",
+        event
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?;
+    for r in targets {
+        if let Err(e) = run_hook(ctx, &r.target, &payload).await {
+            let e = Error::new(
+                eyre!("{} hook {} failed: {}", event, r.target, e.source),
+                e.kind,
+            );
+            if payload.event.is_pre() {
+                return Err(e);
+            }
+            tracing::warn!("{}", e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn targets() {
+    assert!(check_target("https://example.com/hook").is_ok());
+    assert!(check_target("/usr/local/bin/update-dns").is_ok());
+    assert!(check_target("update-dns").is_err());
+}
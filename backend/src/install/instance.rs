@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use bollard::image::{ListImagesOptions, RemoveImageOptions, TagImageOptions};
+use color_eyre::eyre::eyre;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::Version;
+use crate::{Error, ErrorKind};
+
+/// The id a package is installed under as the instance `name`, e.g. `nextcloud-work`
+pub fn instance_id(id: &PackageId, name: &str) -> Result<PackageId, Error> {
+    if name
+        .split('-')
+        .any(|part| part.is_empty() || !part.bytes().all(|b| b.is_ascii_lowercase()))
+    {
+        return Err(Error::new(
+            eyre!(
+                "Instance names may only contain lowercase letters and single dashes between them"
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(format!("{}-{}", id, name).parse()?)
+}
+
+/// Works out which package `id` refers to on the marketplace, and which id it is installed
+/// under. An instance that is already installed is updated from the package it was made from.
+pub async fn resolve(
+    ctx: &RpcContext,
+    id: &str,
+    instance: Option<&str>,
+) -> Result<(PackageId, PackageId), Error> {
+    let id: PackageId = id.parse()?;
+    if let Some(name) = instance {
+        let install_id = instance_id(&id, name)?;
+        let mut db = ctx.db.handle();
+        let package = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&install_id);
+        let exists = package
+            .clone()
+            .map(|p| p.manifest().version())
+            .get(&mut db)
+            .await?
+            .into_owned()
+            .is_some();
+        let instance_of = package
+            .and_then(|p| p.installed())
+            .map(|i| i.instance_of())
+            .get(&mut db)
+            .await?
+            .into_owned()
+            .flatten();
+        return match instance_of {
+            _ if !exists => Ok((id, install_id)),
+            Some(base) if base == id => Ok((id, install_id)),
+            _ => Err(Error::new(
+                eyre!(
+                    "{} is already installed and is not an instance of {}",
+                    install_id,
+                    id
+                ),
+                ErrorKind::InvalidRequest,
+            )),
+        };
+    }
+    let instance_of = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|p| p.installed())
+        .map(|i| i.instance_of())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .flatten();
+    Ok(match instance_of {
+        Some(base) => (base, id),
+        None => (id.clone(), id),
+    })
+}
+
+/// Makes `manifest` describe the installed package `pkg_id` when that is an instance of it, and
+/// returns the id the package was published under
+pub fn adopt(manifest: &mut Manifest, pkg_id: &PackageId) -> Option<PackageId> {
+    if &manifest.id == pkg_id
+        || !pkg_id
+            .as_str()
+            .strip_prefix(manifest.id.as_str())
+            .map_or(false, |name| name.starts_with('-'))
+    {
+        return None;
+    }
+    Some(std::mem::replace(&mut manifest.id, pkg_id.clone()))
+}
+
+/// Tags the images just loaded for `base` with the id of the instance, so that its containers
+/// find them. The tags of `base` are removed again unless `base` itself uses them.
+#[instrument(skip_all)]
+pub async fn tag_images(
+    ctx: &RpcContext,
+    base: &PackageId,
+    id: &PackageId,
+    version: &Version,
+) -> Result<(), Error> {
+    let base_prefix = format!("start9/{}/", base);
+    let base_in_use = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(base)
+        .map(|p| p.manifest().version())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .map_or(false, |v| &v == version);
+    let images = ctx
+        .docker
+        .list_images(Some(ListImagesOptions {
+            all: false,
+            filters: HashMap::from([(
+                "reference".to_owned(),
+                vec![format!("{}*:{}", base_prefix, version)],
+            )]),
+            digests: false,
+        }))
+        .await?;
+    for tag in images.into_iter().flat_map(|image| image.repo_tags) {
+        let image = match tag
+            .strip_prefix(&base_prefix)
+            .and_then(|t| t.strip_suffix(&format!(":{}", version)))
+        {
+            Some(image) => image,
+            None => continue,
+        };
+        ctx.docker
+            .tag_image(
+                &tag,
+                Some(TagImageOptions {
+                    repo: format!("start9/{}/{}", id, image),
+                    tag: version.to_string(),
+                }),
+            )
+            .await?;
+        if !base_in_use {
+            ctx.docker
+                .remove_image(
+                    &tag,
+                    Some(RemoveImageOptions {
+                        force: false,
+                        noprune: true,
+                    }),
+                    None,
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn instances() {
+    let base: PackageId = "nextcloud".parse().unwrap();
+    assert_eq!(
+        instance_id(&base, "work").unwrap().as_str(),
+        "nextcloud-work"
+    );
+    assert_eq!(
+        instance_id(&base, "home-lab").unwrap().as_str(),
+        "nextcloud-home-lab"
+    );
+    assert!(instance_id(&base, "work2").is_err());
+    assert!(instance_id(&base, "Work 2").is_err());
+    assert!(instance_id(&base, "work-").is_err());
+}
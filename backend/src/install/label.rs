@@ -0,0 +1,56 @@
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::{Error, ErrorKind};
+
+const MAX_LABEL_LEN: usize = 64;
+
+fn check_label(label: &str) -> Result<(), Error> {
+    if label.trim().is_empty() || label.chars().count() > MAX_LABEL_LEN {
+        return Err(Error::new(
+            eyre!("Labels must be between 1 and {} characters", MAX_LABEL_LEN),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(())
+}
+
+/// Shows `id` as `label` instead of its title, or as its title again when no label is given
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn label(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] label: Option<String>,
+) -> Result<(), Error> {
+    let label = label.map(|l| l.trim().to_owned());
+    if let Some(label) = &label {
+        check_label(label)?;
+    }
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|m| m.installed())
+        .check(&mut tx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?
+        .label()
+        .put(&mut tx, &label)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[test]
+fn labels() {
+    assert!(check_label("Nextcloud (Family)").is_ok());
+    assert!(check_label("  ").is_err());
+    assert!(check_label(&"a".repeat(MAX_LABEL_LEN + 1)).is_err());
+}
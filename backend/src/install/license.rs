@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::install::PKG_PUBLIC_DIR;
+use crate::marketplace::auth::registry_get;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::{Error, ErrorKind, ResultExt};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LicenseAcceptance {
+    pub package_id: PackageId,
+    pub version: Version,
+    /// Of the LICENSE.md that was accepted
+    pub sha256: String,
+    pub accepted_at: DateTime<Utc>,
+}
+
+pub fn license_sha256(license: &str) -> String {
+    hex::encode(openssl::sha::sha256(license.as_bytes()))
+}
+
+/// The LICENSE.md of `id` at exactly `version`
+#[instrument(skip_all)]
+pub async fn fetch(
+    ctx: &RpcContext,
+    marketplace_url: &Url,
+    id: &PackageId,
+    version: &Version,
+) -> Result<String, Error> {
+    registry_get(
+        ctx,
+        format!(
+            "{}/package/v0/license/{}?spec=={}",
+            marketplace_url, id, version,
+        )
+        .parse()?,
+    )
+    .send()
+    .await
+    .with_kind(ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(ErrorKind::Registry)?
+    .text()
+    .await
+    .with_kind(ErrorKind::Registry)
+}
+
+async fn is_recorded<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    sha256: &str,
+) -> Result<bool, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .license_acceptances()
+        .get(db)
+        .await?
+        .iter()
+        .any(|a| &a.package_id == id && a.sha256 == sha256))
+}
+
+/// Whether `id` is installed with the license of `sha256`. Packages installed before acceptances
+/// were recorded have none on record, so the license they were installed with stands in for one.
+async fn is_installed_with<Db: DbHandle>(
+    ctx: &RpcContext,
+    db: &mut Db,
+    id: &PackageId,
+    sha256: &str,
+) -> Result<bool, Error> {
+    let version = if let Some(version) = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest().version())
+        .get(db)
+        .await?
+        .into_owned()
+    {
+        version
+    } else {
+        return Ok(false);
+    };
+    let path = ctx
+        .datadir
+        .join(PKG_PUBLIC_DIR)
+        .join(id)
+        .join(version.as_str())
+        .join("LICENSE.md");
+    match tokio::fs::read_to_string(&path).await {
+        Ok(installed) => Ok(license_sha256(&installed) == sha256),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_ctx(|_| (ErrorKind::Filesystem, path.display().to_string())),
+    }
+}
+
+/// Whether `license` was accepted for `id` before, for this or an earlier version, including the
+/// version that is installed
+pub async fn is_accepted<Db: DbHandle>(
+    ctx: &RpcContext,
+    db: &mut Db,
+    id: &PackageId,
+    license: &str,
+) -> Result<bool, Error> {
+    let sha256 = license_sha256(license);
+    Ok(is_recorded(db, id, &sha256).await? || is_installed_with(ctx, db, id, &sha256).await?)
+}
+
+/// Records that `license` was accepted for `id` at `version` when `accept` is set, and otherwise
+/// fails unless it was accepted before
+#[instrument(skip_all)]
+pub async fn ensure_accepted(
+    ctx: &RpcContext,
+    id: &PackageId,
+    version: &Version,
+    license: &str,
+    accept: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let sha256 = license_sha256(license);
+    if is_recorded(&mut tx, id, &sha256).await? {
+        return Ok(());
+    }
+    // a license the package was already installed with is recorded as accepted on update
+    if !accept && !is_installed_with(ctx, &mut tx, id, &sha256).await? {
+        return Err(Error::new(
+            eyre!(
+                "The license of {} {} has not been accepted. Review it and install again with --accept-license.",
+                id,
+                version
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut acceptances = crate::db::DatabaseModel::new()
+        .server_info()
+        .license_acceptances()
+        .get_mut(&mut tx)
+        .await?;
+    acceptances.push(LicenseAcceptance {
+        package_id: id.clone(),
+        version: version.clone(),
+        sha256,
+        accepted_at: Utc::now(),
+    });
+    acceptances.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[command(subcommands(history))]
+pub fn license() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_history(arg: Vec<LicenseAcceptance>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "VERSION", "SHA256", "ACCEPTED AT"]);
+    for acceptance in arg {
+        table.add_row(row![
+            &*acceptance.package_id,
+            acceptance.version.as_str(),
+            &acceptance.sha256,
+            acceptance.accepted_at.to_rfc3339(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// Every license that was accepted, or only those of `id`, oldest first
+#[command(display(display_history), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn history(
+    #[context] ctx: RpcContext,
+    #[arg] id: Option<PackageId>,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<LicenseAcceptance>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .license_acceptances()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .into_iter()
+        .filter(|a| id.as_ref().map_or(true, |id| &a.package_id == id))
+        .collect())
+}
@@ -1,7 +1,7 @@
 use std::collections::BTreeMap;
 use std::io::SeekFrom;
 use std::marker::PhantomData;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::Stdio;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -20,12 +20,15 @@ use rpc_toolkit::command;
 use rpc_toolkit::yajrc::RpcError;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt};
-use tokio::process::Command;
 use tokio::sync::oneshot;
 use tokio_stream::wrappers::ReadDirStream;
 use tracing::instrument;
 
 use self::cleanup::{cleanup_failed, remove_from_current_dependents_lists};
+use self::hooks::{HookEvent, HookPayload};
+use self::plan::InstallPlan;
+use crate::auth::PasswordType;
+use crate::backup::target::BackupTargetId;
 use crate::config::ConfigReceipts;
 use crate::context::{CliContext, RpcContext};
 use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
@@ -39,9 +42,12 @@ use crate::dependencies::{
     DependencyError, DependencyErrors,
 };
 use crate::install::cleanup::{cleanup, update_dependency_errors_of_dependents};
-use crate::install::progress::{InstallProgress, InstallProgressTracker};
-use crate::marketplace::with_query_params;
+use crate::install::progress::{InstallPhase, InstallProgress, InstallProgressTracker};
+use crate::marketplace::auth::registry_get;
+use crate::marketplace::channel::channel_for;
+use crate::net::interface::RawProtocol;
 use crate::notifications::NotificationLevel;
+use crate::procedure::docker::runtime;
 use crate::s9pk::manifest::{Manifest, PackageId};
 use crate::s9pk::reader::S9pkReader;
 use crate::status::{MainStatus, Status};
@@ -52,15 +58,35 @@ use crate::version::{Current, VersionT};
 use crate::volume::{asset_dir, script_dir};
 use crate::{Error, ErrorKind, ResultExt};
 
+pub mod auto_update;
 pub mod cleanup;
+pub mod constraint;
+pub mod devices;
+pub mod disk_usage;
+pub mod export;
+pub mod gc;
+pub mod hold;
+pub mod hooks;
+pub mod instance;
+pub mod label;
+pub mod license;
+pub mod plan;
 pub mod progress;
+pub mod queue;
+pub mod remote;
+pub mod resources;
+pub mod rollback;
 pub mod update;
+pub mod update_all;
+pub mod updates;
+pub mod upload;
+pub mod verify;
 
 pub const PKG_ARCHIVE_DIR: &str = "package-data/archive";
 pub const PKG_PUBLIC_DIR: &str = "package-data/public";
 pub const PKG_WASM_DIR: &str = "package-data/wasm";
 
-#[command(display(display_serializable))]
+#[command(display(display_serializable), metadata(read_only = true))]
 pub async fn list(#[context] ctx: RpcContext) -> Result<Vec<(PackageId, Version)>, Error> {
     let mut hdl = ctx.db.handle();
     let package_data = crate::db::DatabaseModel::new()
@@ -128,55 +154,78 @@ pub async fn install(
         String,
     >,
     #[arg(long = "version-priority", rename = "version-priority")] version_priority: Option<MinMax>,
-) -> Result<(), Error> {
+    #[arg(
+        long = "dry-run",
+        rename = "dry-run",
+        help = "Show what would be installed without installing anything"
+    )]
+    dry_run: bool,
+    #[arg(
+        long = "instance",
+        help = "Install another copy of the package, as <id>-<instance>"
+    )]
+    instance: Option<String>,
+    #[arg(
+        long = "accept-license",
+        rename = "accept-license",
+        help = "Accept the license of the package without reviewing it"
+    )]
+    accept_license: bool,
+) -> Result<Option<InstallPlan>, Error> {
+    let (id, install_id) = instance::resolve(&ctx, &id, instance.as_deref()).await?;
     let version_str = match &version_spec {
         None => "*",
         Some(v) => &*v,
     };
-    let version: VersionRange = version_str.parse()?;
+    let constraint = constraint::constraint_for(&mut ctx.db.handle(), &install_id, &id).await?;
+    let version = constraint::constrain(version_str.parse()?, constraint.as_ref());
     let marketplace_url =
         marketplace_url.unwrap_or_else(|| crate::DEFAULT_MARKETPLACE.parse().unwrap());
     let version_priority = version_priority.unwrap_or_default();
-    let man: Manifest = ctx
-        .client
-        .get(with_query_params(
-            &ctx,
-            format!(
-                "{}/package/v0/manifest/{}?spec={}&version-priority={}",
-                marketplace_url, id, version, version_priority,
-            )
-            .parse()?,
-        ))
-        .send()
-        .await
-        .with_kind(crate::ErrorKind::Registry)?
-        .error_for_status()
-        .with_kind(crate::ErrorKind::Registry)?
-        .json()
-        .await
-        .with_kind(crate::ErrorKind::Registry)?;
-    let s9pk = ctx
-        .client
-        .get(with_query_params(
-            &ctx,
-            format!(
-                "{}/package/v0/{}.s9pk?spec=={}&version-priority={}",
-                marketplace_url, id, man.version, version_priority,
-            )
-            .parse()?,
-        ))
-        .send()
-        .await
-        .with_kind(crate::ErrorKind::Registry)?
-        .error_for_status()
-        .with_kind(crate::ErrorKind::Registry)?;
+    let channel = channel_for(&mut ctx.db.handle(), &marketplace_url, &install_id, &id).await?;
+    let man: Manifest = registry_get(
+        &ctx,
+        format!(
+            "{}/package/v0/manifest/{}?spec={}&version-priority={}&channel={}",
+            marketplace_url, id, version, version_priority, channel,
+        )
+        .parse()?,
+    )
+    .send()
+    .await
+    .with_kind(crate::ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(crate::ErrorKind::Registry)?
+    .json()
+    .await
+    .with_kind(crate::ErrorKind::Registry)?;
+    let s9pk_url: Url = format!(
+        "{}/package/v0/{}.s9pk?spec=={}&version-priority={}&channel={}",
+        marketplace_url, id, man.version, version_priority, channel,
+    )
+    .parse()?;
 
-    if man.id.as_str() != id || !man.version.satisfies(&version) {
+    constraint::ensure_allowed(&install_id, &man.version, constraint.as_ref())?;
+    if man.id != id || !man.version.satisfies(&version) {
         return Err(Error::new(
             eyre!("Fetched package does not match requested id and version"),
             ErrorKind::Registry,
         ));
     }
+    let mut man = man;
+    man.id = install_id;
+
+    let license = license::fetch(&ctx, &marketplace_url, &id, &man.version).await?;
+    if dry_run {
+        let s9pk = open_s9pk(&ctx, s9pk_url).await?;
+        let mut plan =
+            plan::plan(&ctx, man.clone(), &marketplace_url, s9pk.content_length()).await?;
+        if !license::is_accepted(&ctx, &mut ctx.db.handle(), &man.id, &license).await? {
+            plan.license = Some(license);
+        }
+        return Ok(Some(plan));
+    }
+    license::ensure_accepted(&ctx, &man.id, &man.version, &license, accept_license).await?;
 
     let public_dir_path = ctx
         .datadir
@@ -188,41 +237,23 @@ pub async fn install(
     let icon_type = man.assets.icon_type();
     let (license_res, instructions_res, icon_res) = tokio::join!(
         async {
-            tokio::io::copy(
-                &mut response_to_reader(
-                    ctx.client
-                        .get(with_query_params(
-                            &ctx,
-                            format!(
-                                "{}/package/v0/license/{}?spec=={}",
-                                marketplace_url, id, man.version,
-                            )
-                            .parse()?,
-                        ))
-                        .send()
-                        .await?
-                        .error_for_status()?,
-                ),
-                &mut File::create(public_dir_path.join("LICENSE.md")).await?,
-            )
-            .await?;
+            tokio::fs::write(public_dir_path.join("LICENSE.md"), &license).await?;
             Ok::<_, color_eyre::eyre::Report>(())
         },
         async {
             tokio::io::copy(
                 &mut response_to_reader(
-                    ctx.client
-                        .get(with_query_params(
-                            &ctx,
-                            format!(
-                                "{}/package/v0/instructions/{}?spec=={}",
-                                marketplace_url, id, man.version,
-                            )
-                            .parse()?,
-                        ))
-                        .send()
-                        .await?
-                        .error_for_status()?,
+                    registry_get(
+                        &ctx,
+                        format!(
+                            "{}/package/v0/instructions/{}?spec=={}",
+                            marketplace_url, id, man.version,
+                        )
+                        .parse()?,
+                    )
+                    .send()
+                    .await?
+                    .error_for_status()?,
                 ),
                 &mut File::create(public_dir_path.join("INSTRUCTIONS.md")).await?,
             )
@@ -232,18 +263,17 @@ pub async fn install(
         async {
             tokio::io::copy(
                 &mut response_to_reader(
-                    ctx.client
-                        .get(with_query_params(
-                            &ctx,
-                            format!(
-                                "{}/package/v0/icon/{}?spec=={}",
-                                marketplace_url, id, man.version,
-                            )
-                            .parse()?,
-                        ))
-                        .send()
-                        .await?
-                        .error_for_status()?,
+                    registry_get(
+                        &ctx,
+                        format!(
+                            "{}/package/v0/icon/{}?spec=={}",
+                            marketplace_url, id, man.version,
+                        )
+                        .parse()?,
+                    )
+                    .send()
+                    .await?
+                    .error_for_status()?,
                 ),
                 &mut File::create(public_dir_path.join(format!("icon.{}", icon_type))).await?,
             )
@@ -261,7 +291,8 @@ pub async fn install(
         tracing::warn!("Failed to pre-download icon: {}", e);
     }
 
-    let progress = InstallProgress::new(s9pk.content_length());
+    // the size is only known once the download is opened, which waits for the install's turn
+    let progress = InstallProgress::new(None);
     let static_files = StaticFiles::local(&man.id, &man.version, icon_type);
     let mut db_handle = ctx.db.handle();
     let mut tx = db_handle.begin().await?;
@@ -276,6 +307,7 @@ pub async fn install(
             static_files,
             ..
         }) => {
+            hold::ensure_not_held(&installed, &man)?;
             *pde = Some(PackageDataEntry::Updating {
                 install_progress: progress.clone(),
                 static_files,
@@ -301,18 +333,37 @@ pub async fn install(
     tx.commit().await?;
     drop(db_handle);
 
+    let ticket =
+        ctx.install_queue
+            .enqueue(&man.id, &man.version, man.dependencies.0.keys().cloned());
     tokio::spawn(async move {
+        let _turn = ctx.install_queue.wait_turn(&man.id, ticket).await;
         let mut db_handle = ctx.db.handle();
-        if let Err(e) = download_install_s9pk(
-            &ctx,
-            &man,
-            Some(marketplace_url),
-            InstallProgress::new(s9pk.content_length()),
-            response_to_reader(s9pk),
-            None,
-        )
-        .await
-        {
+        let res = async {
+            // opened only once it is this install's turn, so queued installs hold no connections
+            let s9pk = match open_s9pk(&ctx, s9pk_url).await {
+                Ok(a) => a,
+                Err(e) => {
+                    let mut handle = ctx.db.handle();
+                    let mut tx = handle.begin().await?;
+                    let receipts = cleanup::CleanupFailedReceipts::new(&mut tx).await?;
+                    cleanup_failed(&ctx, &mut tx, &man.id, &receipts).await?;
+                    tx.commit().await?;
+                    return Err(e);
+                }
+            };
+            download_install_s9pk(
+                &ctx,
+                &man,
+                Some(marketplace_url),
+                InstallProgress::new(s9pk.content_length()),
+                response_to_reader(s9pk),
+                None,
+            )
+            .await
+        }
+        .await;
+        if let Err(e) = res {
             let err_str = format!("Install of {}@{} Failed: {}", man.id, man.version, e);
             tracing::error!("{}", err_str);
             tracing::debug!("{:?}", e);
@@ -335,16 +386,83 @@ pub async fn install(
         }
     });
 
+    Ok(None)
+}
+
+/// Marks `manifest` as installing, or as updating when another version of it is installed
+async fn begin_install(
+    ctx: &RpcContext,
+    manifest: &Manifest,
+    progress: Arc<InstallProgress>,
+) -> Result<(), Error> {
+    let mut hdl = ctx.db.handle();
+    let mut tx = hdl.begin().await?;
+
+    let mut pde = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&manifest.id)
+        .get_mut(&mut tx)
+        .await?;
+    match pde.take() {
+        Some(PackageDataEntry::Installed {
+            installed,
+            static_files,
+            ..
+        }) => {
+            hold::ensure_not_held(&installed, manifest)?;
+            *pde = Some(PackageDataEntry::Updating {
+                install_progress: progress.clone(),
+                installed,
+                manifest: manifest.clone(),
+                static_files,
+            })
+        }
+        None => {
+            *pde = Some(PackageDataEntry::Installing {
+                install_progress: progress.clone(),
+                static_files: StaticFiles::local(
+                    &manifest.id,
+                    &manifest.version,
+                    &manifest.assets.icon_type(),
+                ),
+                manifest: manifest.clone(),
+            })
+        }
+        _ => {
+            return Err(Error::new(
+                eyre!("Cannot install over a package in a transient state"),
+                crate::ErrorKind::InvalidRequest,
+            ))
+        }
+    }
+    pde.save(&mut tx).await?;
+    tx.commit().await?;
     Ok(())
 }
 
+/// Accepts an upload of the s9pk for `manifest` at the returned guid. Given a `url` instead, the
+/// s9pk is fetched from there in the background and nothing is returned.
 #[command(rpc_only, display(display_none))]
 #[instrument(skip_all)]
 pub async fn sideload(
     #[context] ctx: RpcContext,
-    #[arg] manifest: Manifest,
+    #[arg] manifest: Option<Manifest>,
     #[arg] icon: Option<String>,
-) -> Result<RequestGuid, Error> {
+    #[arg] url: Option<Url>,
+) -> Result<Option<RequestGuid>, Error> {
+    let manifest = match (manifest, url) {
+        (Some(manifest), None) => manifest,
+        (None, Some(url)) => {
+            remote::sideload_url(ctx, url)?;
+            return Ok(None);
+        }
+        _ => {
+            return Err(Error::new(
+                eyre!("Sideload needs either a manifest or a url"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
     let new_ctx = ctx.clone();
     let guid = RequestGuid::new();
     if let Some(icon) = icon {
@@ -397,46 +515,7 @@ pub async fn sideload(
             let progress = InstallProgress::new(content_length);
 
             let mut hdl = new_ctx.db.handle();
-            let mut tx = hdl.begin().await?;
-
-            let mut pde = crate::db::DatabaseModel::new()
-                .package_data()
-                .idx_model(&manifest.id)
-                .get_mut(&mut tx)
-                .await?;
-            match pde.take() {
-                Some(PackageDataEntry::Installed {
-                    installed,
-                    static_files,
-                    ..
-                }) => {
-                    *pde = Some(PackageDataEntry::Updating {
-                        install_progress: progress.clone(),
-                        installed,
-                        manifest: manifest.clone(),
-                        static_files,
-                    })
-                }
-                None => {
-                    *pde = Some(PackageDataEntry::Installing {
-                        install_progress: progress.clone(),
-                        static_files: StaticFiles::local(
-                            &manifest.id,
-                            &manifest.version,
-                            &manifest.assets.icon_type(),
-                        ),
-                        manifest: manifest.clone(),
-                    })
-                }
-                _ => {
-                    return Err(Error::new(
-                        eyre!("Cannot install over a package in a transient state"),
-                        crate::ErrorKind::InvalidRequest,
-                    ))
-                }
-            }
-            pde.save(&mut tx).await?;
-            tx.commit().await?;
+            begin_install(&new_ctx, &manifest, progress.clone()).await?;
 
             let (send, recv) = oneshot::channel();
 
@@ -504,7 +583,7 @@ pub async fn sideload(
         RpcContinuation::rest(handler, Duration::from_secs(30)),
     )
     .await;
-    Ok(guid)
+    Ok(Some(guid))
 }
 
 #[instrument(skip_all)]
@@ -514,50 +593,40 @@ async fn cli_install(
     marketplace_url: Option<Url>,
     version_spec: Option<String>,
     version_priority: Option<MinMax>,
+    dry_run: bool,
+    instance: Option<String>,
+    accept_license: bool,
 ) -> Result<(), RpcError> {
-    if target.ends_with(".s9pk") {
-        let path = PathBuf::from(target);
-
-        // inspect manifest no verify
-        let mut reader = S9pkReader::open(&path, false).await?;
-        let manifest = reader.manifest().await?;
-        let icon = reader.icon().await?.to_vec().await?;
-        let icon_str = format!(
-            "data:image/{};base64,{}",
-            manifest.assets.icon_type(),
-            base64::encode(&icon)
-        );
-
-        // rpc call remote sideload
+    if dry_run && (target.ends_with(".s9pk") || target.contains("://")) {
+        return Err(crate::Error::new(
+            eyre!("--dry-run is only supported when installing from a marketplace"),
+            ErrorKind::InvalidRequest,
+        )
+        .into());
+    }
+    if instance.is_some() && (target.ends_with(".s9pk") || target.contains("://")) {
+        return Err(crate::Error::new(
+            eyre!("--instance is only supported when installing from a marketplace"),
+            ErrorKind::InvalidRequest,
+        )
+        .into());
+    }
+    if target.starts_with("https://") || target.starts_with("http://") {
         tracing::debug!("calling package.sideload");
-        let guid = rpc_toolkit::command_helpers::call_remote(
-            ctx.clone(),
+        rpc_toolkit::command_helpers::call_remote(
+            ctx,
             "package.sideload",
-            serde_json::json!({ "manifest": manifest, "icon": icon_str }),
-            PhantomData::<RequestGuid>,
+            serde_json::json!({ "url": target }),
+            PhantomData::<Option<RequestGuid>>,
         )
         .await?
         .result?;
-        tracing::debug!("package.sideload succeeded {:?}", guid);
-
-        // hit continuation api with guid that comes back
-        let file = tokio::fs::File::open(path).await?;
-        let content_length = file.metadata().await?.len();
-        let body = Body::wrap_stream(tokio_util::io::ReaderStream::new(file));
-        let res = ctx
-            .client
-            .post(format!("{}rest/rpc/{}", ctx.base_url, guid,))
-            .header(CONTENT_LENGTH, content_length)
-            .body(body)
-            .send()
-            .await?;
-        if res.status().as_u16() == 200 {
-            tracing::info!("Package Uploaded")
-        } else {
-            tracing::info!("Package Upload failed: {}", res.text().await?)
-        }
+        tracing::info!("Package Download Started");
+    } else if target.ends_with(".s9pk") {
+        upload::cli_upload(&ctx, Path::new(&target)).await?;
+        tracing::info!("Package Uploaded");
     } else {
-        let params = match (target.split_once("@"), version_spec) {
+        let mut params = match (target.split_once("@"), version_spec) {
             (Some((pkg, v)), None) => {
                 serde_json::json!({ "id": pkg, "marketplace-url": marketplace_url, "version-spec": v, "version-priority": version_priority })
             }
@@ -575,34 +644,115 @@ async fn cli_install(
                 serde_json::json!({ "id": target, "marketplace-url": marketplace_url, "version-priority": version_priority })
             }
         };
+        params["dry-run"] = serde_json::Value::Bool(dry_run);
+        if let Some(instance) = instance {
+            params["instance"] = serde_json::Value::String(instance);
+        }
+        let mut accept_license = accept_license;
+        if !dry_run && !accept_license {
+            let mut preview = params.clone();
+            preview["dry-run"] = serde_json::Value::Bool(true);
+            let license = rpc_toolkit::command_helpers::call_remote(
+                ctx.clone(),
+                "package.install",
+                preview,
+                PhantomData::<Option<InstallPlan>>,
+            )
+            .await?
+            .result?
+            .and_then(|plan| plan.license);
+            if let Some(license) = license {
+                use std::io::Write;
+
+                println!("{}", license);
+                print!("Accept this license? [y/N]: ");
+                std::io::stdout().flush()?;
+                let mut answer = String::new();
+                std::io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    return Err(crate::Error::new(
+                        eyre!("The license was not accepted"),
+                        ErrorKind::InvalidRequest,
+                    )
+                    .into());
+                }
+                accept_license = true;
+            }
+        }
+        params["accept-license"] = serde_json::Value::Bool(accept_license);
         tracing::debug!("calling package.install");
-        rpc_toolkit::command_helpers::call_remote(
+        let plan = rpc_toolkit::command_helpers::call_remote(
             ctx,
             "package.install",
             params,
-            PhantomData::<()>,
+            PhantomData::<Option<InstallPlan>>,
         )
         .await?
         .result?;
         tracing::debug!("package.install succeeded");
+        if let Some(plan) = plan {
+            plan::display_plan(&plan);
+        }
     }
     Ok(())
 }
 
+/// What happens to the volumes of a package when it is uninstalled
+#[derive(Clone)]
+pub enum DataDisposition {
+    Wipe,
+    /// The volumes stay where they are, and are used again if the package is reinstalled
+    Keep,
+    /// The package is backed up to the target before its volumes are wiped
+    Archive {
+        target_id: BackupTargetId,
+        password: PasswordType,
+    },
+}
+
 #[command(
     subcommands(self(uninstall_impl(async)), uninstall_dry),
     display(display_none),
     metadata(sync_db = true)
 )]
-pub async fn uninstall(#[arg] id: PackageId) -> Result<PackageId, Error> {
-    Ok(id)
+pub async fn uninstall(
+    #[arg] id: PackageId,
+    #[arg(rename = "keep-data", long = "keep-data")] keep_data: bool,
+    #[arg(rename = "archive-data", long = "archive-data")] archive_data: Option<BackupTargetId>,
+    #[arg(long = "password", help = "Required with --archive-data")] password: Option<PasswordType>,
+) -> Result<(PackageId, DataDisposition), Error> {
+    let disposition = match (keep_data, archive_data, password) {
+        (true, Some(_), _) => {
+            return Err(Error::new(
+                eyre!("--keep-data and --archive-data cannot be used together"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+        (true, None, _) => DataDisposition::Keep,
+        (false, Some(target_id), Some(password)) => DataDisposition::Archive {
+            target_id,
+            password,
+        },
+        (false, Some(_), None) => {
+            return Err(Error::new(
+                eyre!("--archive-data requires the master password"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+        (false, None, _) => DataDisposition::Wipe,
+    };
+    Ok((id, disposition))
 }
 
-#[command(rename = "dry", display(display_serializable))]
+#[command(
+    rename = "dry",
+    display(display_serializable),
+    metadata(read_only = true)
+)]
 #[instrument(skip_all)]
 pub async fn uninstall_dry(
     #[context] ctx: RpcContext,
-    #[parent_data] id: PackageId,
+    #[parent_data] (id, _): (PackageId, DataDisposition),
 ) -> Result<BreakageRes, Error> {
     let mut db = ctx.db.handle();
     let mut tx = db.begin().await?;
@@ -623,7 +773,39 @@ pub async fn uninstall_dry(
 }
 
 #[instrument(skip_all)]
-pub async fn uninstall_impl(ctx: RpcContext, id: PackageId) -> Result<(), Error> {
+pub async fn uninstall_impl(
+    ctx: RpcContext,
+    (id, disposition): (PackageId, DataDisposition),
+) -> Result<(), Error> {
+    let version = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|x| x.installed())
+        .map(|x| x.manifest().version())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    if let Some(version) = &version {
+        hooks::run(
+            &ctx,
+            HookPayload {
+                event: HookEvent::PreUninstall,
+                package_id: id.clone(),
+                version: version.clone(),
+                previous_version: None,
+            },
+        )
+        .await?;
+    }
+    if let DataDisposition::Archive {
+        target_id,
+        password,
+    } = disposition.clone()
+    {
+        let password = password.decrypt(&ctx)?;
+        crate::backup::backup_bulk::backup_package(&ctx, target_id, &password, &id).await?;
+    }
+
     let mut handle = ctx.db.handle();
     let mut tx = handle.begin().await?;
     crate::db::DatabaseModel::new()
@@ -666,8 +848,26 @@ pub async fn uninstall_impl(ctx: RpcContext, id: PackageId) -> Result<(), Error>
                 &mut ctx.db.handle(),
                 &mut ctx.secret_store.acquire().await?,
                 &id,
+                matches!(disposition, DataDisposition::Keep),
             )
-            .await
+            .await?;
+            if let Some(version) = version {
+                hooks::run(
+                    &ctx,
+                    HookPayload {
+                        event: HookEvent::PostUninstall,
+                        package_id: id.clone(),
+                        version,
+                        previous_version: None,
+                    },
+                )
+                .await
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to run hooks for {}: {}", id, e);
+                    tracing::debug!("{:?}", e);
+                });
+            }
+            Ok::<_, Error>(())
         }
         .await
         {
@@ -725,6 +925,15 @@ impl DownloadInstallReceipts {
     }
 }
 
+async fn open_s9pk(ctx: &RpcContext, url: Url) -> Result<reqwest::Response, Error> {
+    registry_get(ctx, url)
+        .send()
+        .await
+        .with_kind(crate::ErrorKind::Registry)?
+        .error_for_status()
+        .with_kind(crate::ErrorKind::Registry)
+}
+
 #[instrument(skip_all)]
 pub async fn download_install_s9pk(
     ctx: &RpcContext,
@@ -737,19 +946,48 @@ pub async fn download_install_s9pk(
     let pkg_id = &temp_manifest.id;
     let version = &temp_manifest.version;
     let mut previous_state: Option<MainStatus> = None;
+    let mut previous_version: Option<Version> = None;
 
     if let Err(e) = async {
-        if crate::db::DatabaseModel::new()
+        let installed_version = crate::db::DatabaseModel::new()
             .package_data()
             .idx_model(&pkg_id)
             .and_then(|x| x.installed())
-            .exists(&mut ctx.db.handle())
+            .map(|x| x.manifest().version())
+            .get(&mut ctx.db.handle())
             .await
-            .unwrap_or(false)
-        {
+            .ok()
+            .and_then(|v| v.into_owned());
+        previous_version = installed_version.clone();
+        hooks::run(
+            ctx,
+            HookPayload {
+                event: if installed_version.is_some() {
+                    HookEvent::PreUpdate
+                } else {
+                    HookEvent::PreInstall
+                },
+                package_id: pkg_id.clone(),
+                version: version.clone(),
+                previous_version: installed_version.clone(),
+            },
+        )
+        .await?;
+        if let Some(installed_version) = installed_version {
             previous_state = crate::control::stop_impl(ctx.clone(), pkg_id.clone())
                 .await
                 .ok();
+            if &installed_version != version {
+                if let Err(e) = rollback::snapshot(ctx, pkg_id, &installed_version).await {
+                    tracing::warn!(
+                        "Failed to keep {}@{} for rollback: {}",
+                        pkg_id,
+                        installed_version,
+                        e
+                    );
+                    tracing::debug!("{:?}", e);
+                }
+            }
         }
         let mut db_handle = ctx.db.handle();
         let mut tx = db_handle.begin().await?;
@@ -793,6 +1031,54 @@ pub async fn download_install_s9pk(
                 }
             }
         }
+        // raw ports are bound on every address, so they must not be taken by another package
+        let raw_map = raw_port_status(&manifests, pkg_id);
+        let own_lan = temp_manifest
+            .interfaces
+            .0
+            .values()
+            .flat_map(|iface| iface.lan_config.iter().flatten().map(|(p, _)| p.0))
+            .collect::<Vec<_>>();
+        for (_id, iface) in &temp_manifest.interfaces.0 {
+            for (p, raw) in iface.raw_config.iter().flatten() {
+                for protocol in &raw.protocols {
+                    if let Some(pkg) = raw_map.get(&(*protocol, p.0)) {
+                        return Err(Error::new(
+                            eyre!(
+                                "{} port {} is already forwarded for package: {}",
+                                protocol,
+                                p.0,
+                                pkg
+                            ),
+                            ErrorKind::LanPortConflict,
+                        ));
+                    }
+                    if *protocol != RawProtocol::Tcp {
+                        continue;
+                    }
+                    if own_lan.contains(&p.0) {
+                        return Err(Error::new(
+                            eyre!("Port {} is both a LAN and a raw port", p.0),
+                            ErrorKind::LanPortConflict,
+                        ));
+                    }
+                    if let Some((_, pkg)) = port_map.get(p).filter(|(_, pkg)| pkg != pkg_id) {
+                        return Err(Error::new(
+                            eyre!("Port {} is a LAN port of package: {}", p.0, pkg),
+                            ErrorKind::LanPortConflict,
+                        ));
+                    }
+                }
+            }
+            for (p, _) in iface.lan_config.iter().flatten() {
+                if let Some(pkg) = raw_map.get(&(RawProtocol::Tcp, p.0)) {
+                    return Err(Error::new(
+                        eyre!("Port {} is a raw port of package: {}", p.0, pkg),
+                        ErrorKind::LanPortConflict,
+                    ));
+                }
+            }
+        }
         drop(receipts);
         tx.save().await?;
         drop(db_handle);
@@ -820,6 +1106,7 @@ pub async fn download_install_s9pk(
             .open(&pkg_archive)
             .await?;
 
+        progress.start_phase(InstallPhase::Download, None);
         progress
             .track_download_during(progress_model.clone(), &ctx.db, || async {
                 let mut progress_writer = InstallProgressTracker::new(&mut dst, progress.clone());
@@ -835,6 +1122,7 @@ pub async fn download_install_s9pk(
         dst.seek(SeekFrom::Start(0)).await?;
 
         let progress_reader = InstallProgressTracker::new(dst, progress.clone());
+        progress.start_phase(InstallPhase::Verify, None);
         let mut s9pk_reader = progress
             .track_read_during(progress_model.clone(), &ctx.db, || {
                 S9pkReader::from_reader(progress_reader, true)
@@ -874,6 +1162,24 @@ pub async fn download_install_s9pk(
         if previous_state.map(|x| x.running()).unwrap_or(false) {
             crate::control::start(ctx.clone(), pkg_id.clone()).await?;
         }
+        if let Err(e) = hooks::run(
+            ctx,
+            HookPayload {
+                event: if previous_version.is_some() {
+                    HookEvent::PostUpdate
+                } else {
+                    HookEvent::PostInstall
+                },
+                package_id: pkg_id.clone(),
+                version: version.clone(),
+                previous_version,
+            },
+        )
+        .await
+        {
+            tracing::warn!("Failed to run hooks for {}@{}: {}", pkg_id, version, e);
+            tracing::debug!("{:?}", e);
+        }
         Ok(())
     }
 }
@@ -902,6 +1208,37 @@ impl InstallS9Receipts {
     }
 }
 
+/// Pipes a `docker save` tarball into `docker load`
+#[instrument(skip_all)]
+pub async fn docker_load<R: AsyncRead + Unpin + Send>(rdr: &mut R) -> Result<(), Error> {
+    let mut load = runtime()
+        .command()
+        .arg("load")
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let load_in = load.stdin.take().ok_or_else(|| {
+        Error::new(
+            eyre!("Could not write to stdin of docker load"),
+            crate::ErrorKind::Docker,
+        )
+    })?;
+    copy_and_shutdown(rdr, load_in).await?;
+    let res = load.wait_with_output().await?;
+    if !res.status.success() {
+        Err(Error::new(
+            eyre!(
+                "{}",
+                String::from_utf8(res.stderr)
+                    .unwrap_or_else(|e| format!("Could not parse stderr: {}", e))
+            ),
+            crate::ErrorKind::Docker,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 #[instrument(skip_all)]
 pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     ctx: &RpcContext,
@@ -913,17 +1250,53 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
 ) -> Result<(), Error> {
     rdr.validate().await?;
     rdr.validated();
+    if let Some(multiarch) = rdr.architectures().await? {
+        if !multiarch.available.contains(&**crate::ARCH) {
+            return Err(Error::new(
+                eyre!(
+                    "Package has no images for {}, only for {}",
+                    &**crate::ARCH,
+                    multiarch
+                        .available
+                        .iter()
+                        .map(|a| a.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                crate::ErrorKind::ValidateS9pk,
+            ));
+        }
+    }
     let developer_key = rdr.developer_key().clone();
     rdr.reset().await?;
     let model = crate::db::DatabaseModel::new()
         .package_data()
         .idx_model(pkg_id);
+    if let Some(marketplace_url) = &marketplace_url {
+        crate::marketplace::pin::ensure_pinned(&ctx.secret_store, marketplace_url, &developer_key)
+            .await?;
+    } else {
+        // registry packages are vouched for by the registry, and updates signed by the same
+        // developer or restores from a backup need no new trust
+        let vouched = match model.clone().get(&mut ctx.db.handle()).await?.into_owned() {
+            Some(PackageDataEntry::Updating { installed, .. }) => {
+                installed.developer_key == developer_key
+            }
+            Some(PackageDataEntry::Restoring { .. }) => true,
+            _ => false,
+        };
+        if !vouched {
+            crate::s9pk::keys::ensure_trusted(&ctx.secret_store, &developer_key).await?;
+        }
+    }
     let progress_model = model.clone().and_then(|m| m.install_progress());
 
     tracing::info!("Install {}@{}: Unpacking Manifest", pkg_id, version);
-    let manifest = progress
+    progress.start_phase(InstallPhase::Unpack, None);
+    let mut manifest = progress
         .track_read_during(progress_model.clone(), &ctx.db, || rdr.manifest())
         .await?;
+    let instance_of = instance::adopt(&mut manifest, pkg_id);
     tracing::info!("Install {}@{}: Unpacked Manifest", pkg_id, version);
 
     tracing::info!("Install {}@{}: Fetching Dependency Info", pkg_id, version);
@@ -939,20 +1312,18 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
         {
             Some(local_man)
         } else if let Some(marketplace_url) = &marketplace_url {
-            match ctx
-                .client
-                .get(with_query_params(
-                    ctx,
-                    format!(
-                        "{}/package/v0/manifest/{}?spec={}",
-                        marketplace_url, dep, info.version,
-                    )
-                    .parse()?,
-                ))
-                .send()
-                .await
-                .with_kind(crate::ErrorKind::Registry)?
-                .error_for_status()
+            match registry_get(
+                ctx,
+                format!(
+                    "{}/package/v0/manifest/{}?spec={}",
+                    marketplace_url, dep, info.version,
+                )
+                .parse()?,
+            )
+            .send()
+            .await
+            .with_kind(crate::ErrorKind::Registry)?
+            .error_for_status()
             {
                 Ok(a) => Ok(Some(
                     a.json()
@@ -977,19 +1348,17 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
                 let icon_path = dir.join(format!("icon.{}", manifest.assets.icon_type()));
                 if tokio::fs::metadata(&icon_path).await.is_err() {
                     tokio::fs::create_dir_all(&dir).await?;
-                    let icon = ctx
-                        .client
-                        .get(with_query_params(
-                            ctx,
-                            format!(
-                                "{}/package/v0/icon/{}?spec={}",
-                                marketplace_url, dep, info.version,
-                            )
-                            .parse()?,
-                        ))
-                        .send()
-                        .await
-                        .with_kind(crate::ErrorKind::Registry)?;
+                    let icon = registry_get(
+                        ctx,
+                        format!(
+                            "{}/package/v0/icon/{}?spec={}",
+                            marketplace_url, dep, info.version,
+                        )
+                        .parse()?,
+                    )
+                    .send()
+                    .await
+                    .with_kind(crate::ErrorKind::Registry)?;
                     let mut dst = File::create(&icon_path).await?;
                     tokio::io::copy(&mut response_to_reader(icon), &mut dst).await?;
                     dst.sync_all().await?;
@@ -1071,39 +1440,20 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     );
 
     tracing::info!("Install {}@{}: Unpacking Docker Images", pkg_id, version);
+    progress.start_phase(InstallPhase::LoadImages, Some(1));
     progress
         .track_read_during(progress_model.clone(), &ctx.db, || async {
-            let mut load = Command::new("docker")
-                .arg("load")
-                .stdin(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?;
-            let load_in = load.stdin.take().ok_or_else(|| {
-                Error::new(
-                    eyre!("Could not write to stdin of docker load"),
-                    crate::ErrorKind::Docker,
-                )
-            })?;
-            let mut docker_rdr = rdr.docker_images().await?;
-            copy_and_shutdown(&mut docker_rdr, load_in).await?;
-            let res = load.wait_with_output().await?;
-            if !res.status.success() {
-                Err(Error::new(
-                    eyre!(
-                        "{}",
-                        String::from_utf8(res.stderr)
-                            .unwrap_or_else(|e| format!("Could not parse stderr: {}", e))
-                    ),
-                    crate::ErrorKind::Docker,
-                ))
-            } else {
-                Ok(())
-            }
+            docker_load(&mut rdr.docker_images().await?).await
         })
         .await?;
+    if let Some(base) = &instance_of {
+        instance::tag_images(ctx, base, pkg_id, version).await?;
+    }
     tracing::info!("Install {}@{}: Unpacked Docker Images", pkg_id, version,);
+    progress.complete_phase(InstallPhase::LoadImages);
 
     tracing::info!("Install {}@{}: Unpacking Assets", pkg_id, version);
+    progress.start_phase(InstallPhase::Unpack, None);
     progress
         .track_read_during(progress_model.clone(), &ctx.db, || async {
             let asset_dir = asset_dir(&ctx.datadir, pkg_id, version);
@@ -1131,6 +1481,8 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     tracing::info!("Install {}@{}: Unpacked Assets", pkg_id, version);
 
     progress.unpack_complete.store(true, Ordering::SeqCst);
+    progress.start_phase(InstallPhase::Configure, None);
+    progress.sync_phases();
 
     progress_model.put(&mut ctx.db.handle(), &progress).await?;
 
@@ -1147,7 +1499,8 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
     tracing::info!("Install {}@{}: Created volumes", pkg_id, version);
 
     tracing::info!("Install {}@{}: Installing interfaces", pkg_id, version);
-    let interface_addresses = manifest.interfaces.install(&mut sql_tx, pkg_id).await?;
+    let mut interface_addresses = manifest.interfaces.install(&mut sql_tx, pkg_id).await?;
+    crate::net::domain::restore_addresses(&mut tx, pkg_id, &mut interface_addresses).await?;
     tracing::info!("Install {}@{}: Installed interfaces", pkg_id, version);
 
     tracing::info!("Install {}@{}: Creating manager", pkg_id, version);
@@ -1161,7 +1514,7 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
             .0
             .iter()
             .filter_map(|(id, info)| {
-                if info.requirement.required() {
+                if info.requirement.tracked() {
                     Some((id.clone(), CurrentDependencyInfo::default()))
                 } else {
                     None
@@ -1229,6 +1582,27 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
             dependency_errors: DependencyErrors::default(),
         },
         marketplace_url,
+        held: matches!(
+            &*pde,
+            PackageDataEntry::Updating {
+                installed: InstalledPackageDataEntry { held: true, .. },
+                ..
+            }
+        ),
+        disk_usage: None,
+        instance_of,
+        label: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.label.clone(),
+            _ => None,
+        },
+        resource_limits: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.resource_limits,
+            _ => Default::default(),
+        },
+        approved_devices: match &*pde {
+            PackageDataEntry::Updating { installed, .. } => installed.approved_devices.clone(),
+            _ => Some(Default::default()),
+        },
         developer_key,
         manifest: manifest.clone(),
         last_backup: match &*pde {
@@ -1435,6 +1809,11 @@ pub async fn install_s9pk<R: AsyncRead + AsyncSeek + Unpin + Send + Sync>(
 
     tracing::info!("Install {}@{}: Complete", pkg_id, version);
 
+    if let Err(e) = devices::notify_pending(ctx, &manifest).await {
+        tracing::error!("Error Notifying of Requested Devices: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+
     Ok(())
 }
 
@@ -1455,7 +1834,8 @@ pub fn load_images<'a, P: AsRef<Path> + 'a + Send + Sync>(
                         let path = entry.path();
                         let ext = path.extension().and_then(|ext| ext.to_str());
                         if ext == Some("tar") || ext == Some("s9pk") {
-                            let mut load = Command::new("docker")
+                            let mut load = runtime()
+                                .command()
                                 .arg("load")
                                 .stdin(Stdio::piped())
                                 .stderr(Stdio::piped())
@@ -1524,6 +1904,23 @@ pub fn load_images<'a, P: AsRef<Path> + 'a + Send + Sync>(
     .boxed()
 }
 
+fn raw_port_status(
+    manifests: &Vec<Manifest>,
+    except: &PackageId,
+) -> BTreeMap<(RawProtocol, u16), PackageId> {
+    let mut ret = BTreeMap::new();
+    for m in manifests.iter().filter(|m| &m.id != except) {
+        for (_id, iface) in &m.interfaces.0 {
+            for (p, raw) in iface.raw_config.iter().flatten() {
+                for protocol in &raw.protocols {
+                    ret.insert((*protocol, p.0), m.id.clone());
+                }
+            }
+        }
+    }
+    ret
+}
+
 fn ssl_port_status(manifests: &Vec<Manifest>) -> BTreeMap<Port, (bool, PackageId)> {
     let mut ret = BTreeMap::new();
     for m in manifests {
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use emver::VersionRange;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::constraint::{constrain, constraint_for};
+use super::MinMax;
+use crate::context::RpcContext;
+use crate::db::model::{InstalledPackageDataEntry, PackageDataEntry};
+use crate::dependencies::{DependencyRequirement, TaggedDependencyError};
+use crate::disk::util::get_available;
+use crate::marketplace::auth::registry_get;
+use crate::marketplace::channel::channel_for;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::Version;
+use crate::{Error, ErrorKind, ResultExt};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedChange {
+    pub package_id: PackageId,
+    /// The installed version, if any
+    pub from: Option<Version>,
+    pub to: Version,
+    pub download_size: Option<u64>,
+    /// Whether a migration script carries the package data across versions
+    pub migration: bool,
+    /// Whether the package must be configured before it can start again
+    pub needs_config: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct InstallPlan {
+    /// The requested package, followed by the dependencies that must be installed or updated
+    pub changes: Vec<PlannedChange>,
+    /// Required dependencies that the marketplace cannot satisfy, or that are held
+    pub unresolved: BTreeMap<PackageId, VersionRange>,
+    /// Optional and suggested dependencies that are not installed, and will not be
+    pub recommended: BTreeMap<PackageId, VersionRange>,
+    /// Dependents that would be broken by the new versions
+    pub breakages: BTreeMap<PackageId, TaggedDependencyError>,
+    /// Size of the s9pks to download, each of which is kept in the package archive
+    pub required_space: u64,
+    pub available_space: u64,
+    /// The license of the requested package, unless it was accepted for it before
+    pub license: Option<String>,
+}
+
+/// Mirrors how `install_s9pk` picks a migration between two versions
+fn has_migration(prev: &Manifest, next: &Manifest) -> bool {
+    next.migrations
+        .from
+        .keys()
+        .any(|range| prev.version.satisfies(range))
+        || prev
+            .migrations
+            .to
+            .keys()
+            .any(|range| next.version.satisfies(range))
+}
+
+async fn fetch_dependency(
+    ctx: &RpcContext,
+    marketplace_url: &Url,
+    id: &PackageId,
+    spec: &VersionRange,
+) -> Result<(Manifest, Option<u64>), Error> {
+    let channel = channel_for(&mut ctx.db.handle(), marketplace_url, id, id).await?;
+    let constraint = constraint_for(&mut ctx.db.handle(), id, id).await?;
+    let spec = constrain(spec.clone(), constraint.as_ref());
+    let manifest: Manifest = registry_get(
+        ctx,
+        format!(
+            "{}/package/v0/manifest/{}?spec={}&version-priority={}&channel={}",
+            marketplace_url,
+            id,
+            spec,
+            MinMax::Max,
+            channel,
+        )
+        .parse()?,
+    )
+    .send()
+    .await
+    .with_kind(ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(ErrorKind::Registry)?
+    .json()
+    .await
+    .with_kind(ErrorKind::Registry)?;
+    let size = registry_get(
+        ctx,
+        format!(
+            "{}/package/v0/{}.s9pk?spec=={}&version-priority={}&channel={}",
+            marketplace_url,
+            id,
+            manifest.version,
+            MinMax::Max,
+            channel,
+        )
+        .parse()?,
+    )
+    .send()
+    .await
+    .with_kind(ErrorKind::Registry)?
+    .error_for_status()
+    .with_kind(ErrorKind::Registry)?
+    .content_length();
+    Ok((manifest, size))
+}
+
+/// Works out what installing `manifest` from `marketplace_url` would change, without changing
+/// anything
+#[instrument(skip_all)]
+pub async fn plan(
+    ctx: &RpcContext,
+    manifest: Manifest,
+    marketplace_url: &Url,
+    download_size: Option<u64>,
+) -> Result<InstallPlan, Error> {
+    let installed: BTreeMap<PackageId, InstalledPackageDataEntry> = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .0
+        .into_iter()
+        .filter_map(|(id, pde)| match pde {
+            PackageDataEntry::Installed { installed, .. } => Some((id, installed)),
+            _ => None,
+        })
+        .collect();
+    let mut plan = InstallPlan {
+        changes: Vec::new(),
+        unresolved: BTreeMap::new(),
+        recommended: BTreeMap::new(),
+        breakages: BTreeMap::new(),
+        required_space: 0,
+        available_space: get_available(&ctx.datadir).await?,
+        license: None,
+    };
+    let mut seen = BTreeSet::from([manifest.id.clone()]);
+    let mut queue = VecDeque::from([(manifest, download_size)]);
+    while let Some((manifest, size)) = queue.pop_front() {
+        for (dep_id, dep_info) in &manifest.dependencies.0 {
+            if seen.contains(dep_id) {
+                continue;
+            }
+            if !dep_info.requirement.required() {
+                if matches!(
+                    dep_info.requirement,
+                    DependencyRequirement::Optional | DependencyRequirement::Suggests
+                ) && !installed.contains_key(dep_id)
+                {
+                    plan.recommended
+                        .insert(dep_id.clone(), dep_info.version.clone());
+                }
+                continue;
+            }
+            match installed.get(dep_id) {
+                Some(dep) if dep.manifest.version.satisfies(&dep_info.version) => continue,
+                Some(dep) if dep.held => {
+                    plan.unresolved
+                        .insert(dep_id.clone(), dep_info.version.clone());
+                    continue;
+                }
+                _ => (),
+            }
+            match fetch_dependency(ctx, marketplace_url, dep_id, &dep_info.version).await {
+                Ok(dep) => {
+                    seen.insert(dep_id.clone());
+                    queue.push_back(dep);
+                }
+                Err(e) => {
+                    tracing::debug!("Cannot resolve {}: {:?}", dep_id, e);
+                    plan.unresolved
+                        .insert(dep_id.clone(), dep_info.version.clone());
+                }
+            }
+        }
+        let prev = installed.get(&manifest.id);
+        if let Some(prev) = prev {
+            super::hold::ensure_not_held(prev, &manifest)?;
+            if prev.manifest.version != manifest.version {
+                plan.breakages.extend(
+                    super::update::dry(ctx.clone(), manifest.id.clone(), manifest.version.clone())
+                        .await?
+                        .0,
+                );
+            }
+        }
+        let migration = prev.map_or(false, |prev| has_migration(&prev.manifest, &manifest));
+        plan.required_space += size.unwrap_or_default();
+        plan.changes.push(PlannedChange {
+            package_id: manifest.id.clone(),
+            from: prev.map(|prev| prev.manifest.version.clone()),
+            to: manifest.version.clone(),
+            download_size: size,
+            migration,
+            needs_config: manifest.config.is_some()
+                && !prev.map_or(false, |prev| migration && prev.status.configured),
+        });
+    }
+    Ok(plan)
+}
+
+/// Prints `plan` for `package install --dry-run`
+pub fn display_plan(plan: &InstallPlan) {
+    use prettytable::*;
+
+    let mut table = Table::new();
+    table.add_row(
+        row![bc => "PACKAGE", "FROM", "TO", "DOWNLOAD (BYTES)", "MIGRATION", "NEEDS CONFIG"],
+    );
+    for change in &plan.changes {
+        table.add_row(row![
+            &*change.package_id,
+            change.from.as_ref().map_or("N/A", |v| v.as_str()),
+            change.to.as_str(),
+            change
+                .download_size
+                .map_or_else(|| "unknown".to_owned(), |s| s.to_string()),
+            change.migration,
+            change.needs_config,
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    for (id, spec) in &plan.unresolved {
+        println!("Unresolved dependency: {} {}", id, spec);
+    }
+    for (id, spec) in &plan.recommended {
+        println!("Recommended, not installed: {} {}", id, spec);
+    }
+    for (id, breakage) in &plan.breakages {
+        println!(
+            "Would break {}: {}",
+            id,
+            breakage.error.to_string().trim_end()
+        );
+    }
+    println!(
+        "Requires {} of {} available bytes",
+        plan.required_space, plan.available_space
+    );
+    if plan.license.is_some() {
+        println!("Its license has to be accepted");
+    }
+}
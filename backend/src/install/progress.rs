@@ -1,8 +1,9 @@
+use std::collections::BTreeMap;
 use std::future::Future;
 use std::io::SeekFrom;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::time::Duration;
 
@@ -12,6 +13,25 @@ use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
 use crate::Error;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstallPhase {
+    #[default]
+    Download,
+    Verify,
+    Unpack,
+    LoadImages,
+    Configure,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PhaseProgress {
+    pub done: u64,
+    pub total: Option<u64>,
+    pub complete: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, HasModel, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct InstallProgress {
@@ -22,6 +42,13 @@ pub struct InstallProgress {
     pub validation_complete: AtomicBool,
     pub unpacked: AtomicU64,
     pub unpack_complete: AtomicBool,
+    /// The phase the install is in now
+    #[serde(default)]
+    pub phase: Mutex<InstallPhase>,
+    /// Progress through each phase that has started: bytes for `download`, `verify` and
+    /// `unpack`, steps for the rest
+    #[serde(default)]
+    pub phases: Mutex<BTreeMap<InstallPhase, PhaseProgress>>,
 }
 impl InstallProgress {
     pub fn new(size: Option<u64>) -> Arc<Self> {
@@ -33,8 +60,55 @@ impl InstallProgress {
             validation_complete: AtomicBool::new(false),
             unpacked: AtomicU64::new(0),
             unpack_complete: AtomicBool::new(false),
+            phase: Mutex::new(InstallPhase::Download),
+            phases: Mutex::new(BTreeMap::new()),
         })
     }
+    /// Moves on to `phase`. The byte counted phases take their total from `size`.
+    pub fn start_phase(&self, phase: InstallPhase, steps: Option<u64>) {
+        *self.phase.lock().unwrap() = phase;
+        let total = match phase {
+            InstallPhase::Download | InstallPhase::Verify | InstallPhase::Unpack => self.size,
+            InstallPhase::LoadImages | InstallPhase::Configure => steps,
+        };
+        self.phases
+            .lock()
+            .unwrap()
+            .entry(phase)
+            .or_insert_with(|| PhaseProgress {
+                done: 0,
+                total,
+                complete: false,
+            });
+    }
+    pub fn complete_phase(&self, phase: InstallPhase) {
+        if let Some(progress) = self.phases.lock().unwrap().get_mut(&phase) {
+            progress.done = progress.total.unwrap_or(progress.done);
+            progress.complete = true;
+        }
+    }
+    /// Copies the byte counters into the phases they belong to, ahead of writing to the db
+    pub fn sync_phases(&self) {
+        let mut phases = self.phases.lock().unwrap();
+        for (phase, done, complete) in [
+            (
+                InstallPhase::Download,
+                &self.downloaded,
+                &self.download_complete,
+            ),
+            (
+                InstallPhase::Verify,
+                &self.validated,
+                &self.validation_complete,
+            ),
+            (InstallPhase::Unpack, &self.unpacked, &self.unpack_complete),
+        ] {
+            if let Some(progress) = phases.get_mut(&phase) {
+                progress.done = done.load(Ordering::SeqCst);
+                progress.complete = complete.load(Ordering::SeqCst);
+            }
+        }
+    }
     pub fn download_complete(&self) {
         self.download_complete.store(true, Ordering::SeqCst)
     }
@@ -44,11 +118,13 @@ impl InstallProgress {
         mut db: Db,
     ) -> Result<(), Error> {
         while !self.download_complete.load(Ordering::SeqCst) {
+            self.sync_phases();
             let mut tx = db.begin().await?;
             model.put(&mut tx, &self).await?;
             tx.save().await?;
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
+        self.sync_phases();
         let mut tx = db.begin().await?;
         model.put(&mut tx, &self).await?;
         tx.save().await?;
@@ -78,11 +154,13 @@ impl InstallProgress {
         complete: Arc<AtomicBool>,
     ) -> Result<(), Error> {
         while !complete.load(Ordering::SeqCst) {
+            self.sync_phases();
             let mut tx = db.begin().await?;
             model.put(&mut tx, &self).await?;
             tx.save().await?;
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
+        self.sync_phases();
         let mut tx = db.begin().await?;
         model.put(&mut tx, &self).await?;
         tx.save().await?;
@@ -0,0 +1,242 @@
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::Error;
+
+/// How many installs may download and unpack at the same time
+pub const INSTALL_PARALLELISM: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueueState {
+    Waiting,
+    Installing,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct QueuedInstall {
+    pub id: PackageId,
+    pub version: Version,
+    pub state: QueueState,
+    pub queued_at: DateTime<Utc>,
+    /// Queued dependencies that have to finish first
+    pub waiting_for: BTreeSet<PackageId>,
+    #[serde(skip)]
+    dependencies: BTreeSet<PackageId>,
+    /// Tells apart entries for the same package, e.g. a reinstall queued behind an install
+    #[serde(skip)]
+    ticket: u64,
+}
+
+/// Accepts installs immediately and runs them with at most `INSTALL_PARALLELISM` at once. An
+/// install waits for any of its dependencies that are also queued, so they go in first.
+pub struct InstallQueue {
+    entries: std::sync::Mutex<Vec<QueuedInstall>>,
+    next_ticket: AtomicU64,
+    changed: Notify,
+    slots: Semaphore,
+}
+impl InstallQueue {
+    pub fn new() -> Self {
+        InstallQueue {
+            entries: std::sync::Mutex::new(Vec::new()),
+            next_ticket: AtomicU64::new(0),
+            changed: Notify::new(),
+            slots: Semaphore::new(INSTALL_PARALLELISM),
+        }
+    }
+    /// Returns the ticket to wait for the turn of this entry with
+    pub fn enqueue(
+        &self,
+        id: &PackageId,
+        version: &Version,
+        dependencies: impl IntoIterator<Item = PackageId>,
+    ) -> u64 {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        self.entries.lock().unwrap().push(QueuedInstall {
+            id: id.clone(),
+            version: version.clone(),
+            state: QueueState::Waiting,
+            queued_at: Utc::now(),
+            waiting_for: BTreeSet::new(),
+            dependencies: dependencies.into_iter().collect(),
+            ticket,
+        });
+        ticket
+    }
+    /// Waits until `id` may install. Dropping the returned turn takes its entry off the queue.
+    #[instrument(skip_all)]
+    pub async fn wait_turn(&self, id: &PackageId, ticket: u64) -> InstallTurn<'_> {
+        loop {
+            // registered before checking so a change in between still wakes us
+            let changed = self.changed.notified();
+            if self.blocked_on(id).is_empty() {
+                if let Ok(permit) = self.slots.try_acquire() {
+                    if let Some(entry) = self
+                        .entries
+                        .lock()
+                        .unwrap()
+                        .iter_mut()
+                        .find(|e| e.ticket == ticket)
+                    {
+                        entry.state = QueueState::Installing;
+                    }
+                    return InstallTurn {
+                        queue: self,
+                        ticket,
+                        permit: Some(permit),
+                    };
+                }
+            }
+            changed.await;
+        }
+    }
+    fn blocked_on(&self, id: &PackageId) -> BTreeSet<PackageId> {
+        blocked_on(&self.entries.lock().unwrap(), id)
+    }
+    pub fn list(&self) -> Vec<QueuedInstall> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .iter()
+            .map(|e| QueuedInstall {
+                waiting_for: blocked_on(&entries, &e.id),
+                ..e.clone()
+            })
+            .collect()
+    }
+}
+
+/// The queued dependencies of `id`. When two queued packages depend on each other, the one queued
+/// first goes first.
+fn blocked_on(entries: &[QueuedInstall], id: &PackageId) -> BTreeSet<PackageId> {
+    let pos = if let Some(pos) = entries.iter().position(|e| &e.id == id) {
+        pos
+    } else {
+        return BTreeSet::new();
+    };
+    let entry = &entries[pos];
+    entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| {
+            entry.dependencies.contains(&e.id) && (*i < pos || !e.dependencies.contains(id))
+        })
+        .map(|(_, e)| e.id.clone())
+        .collect()
+}
+
+pub struct InstallTurn<'a> {
+    queue: &'a InstallQueue,
+    ticket: u64,
+    permit: Option<SemaphorePermit<'a>>,
+}
+impl<'a> Drop for InstallTurn<'a> {
+    fn drop(&mut self) {
+        self.queue
+            .entries
+            .lock()
+            .unwrap()
+            .retain(|e| e.ticket != self.ticket);
+        // free the slot before waking anyone, or they could find it still taken
+        drop(self.permit.take());
+        self.queue.changed.notify_waiters();
+    }
+}
+
+#[command(subcommands(list))]
+pub fn queue() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_queue(arg: Vec<QueuedInstall>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ID", "VERSION", "STATE", "QUEUED AT", "WAITING FOR"]);
+    for entry in arg {
+        table.add_row(row![
+            &*entry.id,
+            entry.version.as_str(),
+            match entry.state {
+                QueueState::Waiting => "waiting",
+                QueueState::Installing => "installing",
+            },
+            entry.queued_at.to_rfc3339(),
+            entry
+                .waiting_for
+                .iter()
+                .map(|id| id.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_queue), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<QueuedInstall>, Error> {
+    Ok(ctx.install_queue.list())
+}
+
+#[test]
+fn dependency_order() {
+    let queued = |id: &str, deps: &[&str]| QueuedInstall {
+        id: id.parse().unwrap(),
+        version: "0.1.0".parse().unwrap(),
+        state: QueueState::Waiting,
+        queued_at: Utc::now(),
+        waiting_for: BTreeSet::new(),
+        dependencies: deps.iter().map(|d| d.parse().unwrap()).collect(),
+        ticket: 0,
+    };
+    let entries = vec![
+        queued("bitcoind", &[]),
+        queued("lnd", &["bitcoind"]),
+        queued("btc-rpc-proxy", &["bitcoind", "electrs"]),
+        queued("electrs", &["bitcoind"]),
+    ];
+    assert!(blocked_on(&entries, &"bitcoind".parse().unwrap()).is_empty());
+    assert_eq!(
+        blocked_on(&entries, &"lnd".parse().unwrap()),
+        ["bitcoind".parse().unwrap()].into_iter().collect()
+    );
+    // electrs was queued later but still goes first
+    assert_eq!(
+        blocked_on(&entries, &"btc-rpc-proxy".parse().unwrap()),
+        ["bitcoind".parse().unwrap(), "electrs".parse().unwrap()]
+            .into_iter()
+            .collect()
+    );
+}
+
+#[tokio::test]
+async fn turn_removes_only_its_entry() {
+    let queue = InstallQueue::new();
+    let id: PackageId = "bitcoind".parse().unwrap();
+    let version: Version = "0.1.0".parse().unwrap();
+    let first = queue.enqueue(&id, &version, []);
+    queue.enqueue(&id, &version, []);
+    drop(queue.wait_turn(&id, first).await);
+    assert_eq!(queue.list().len(), 1);
+}
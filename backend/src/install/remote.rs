@@ -0,0 +1,175 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use futures::StreamExt;
+use reqwest::header::RANGE;
+use reqwest::{Client, StatusCode, Url};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use super::progress::InstallProgress;
+use super::{begin_install, download_install_s9pk, PKG_PUBLIC_DIR};
+use crate::context::RpcContext;
+use crate::notifications::NotificationLevel;
+use crate::s9pk::reader::S9pkReader;
+use crate::util::AsyncFileExt;
+use crate::{Error, ErrorKind, ResultExt};
+
+const DOWNLOAD_DIR: &str = "package-data/tmp/sideload";
+const DOWNLOAD_ATTEMPTS: u64 = 5;
+
+/// Only fetch over TLS, or over tor which authenticates the host itself
+fn check_url(url: &Url) -> Result<(), Error> {
+    match url.scheme() {
+        "https" => Ok(()),
+        "http" if url.host_str().map_or(false, |h| h.ends_with(".onion")) => Ok(()),
+        _ => Err(Error::new(
+            eyre!("Package URL must be https or a .onion address"),
+            ErrorKind::InvalidRequest,
+        )),
+    }
+}
+
+/// Partial downloads of the same url pick up where they left off
+fn download_path<P: AsRef<Path>>(datadir: P, url: &Url) -> PathBuf {
+    datadir
+        .as_ref()
+        .join(DOWNLOAD_DIR)
+        .join(hex::encode(openssl::sha::sha256(url.as_str().as_bytes())))
+        .with_extension("s9pk")
+}
+
+/// Appends the rest of `url` to whatever is already at `dst`
+async fn download_from(client: &Client, url: &Url, dst: &Path) -> Result<(), Error> {
+    let have = tokio::fs::metadata(dst).await.map(|m| m.len()).unwrap_or(0);
+    let mut req = client.get(url.clone());
+    if have > 0 {
+        req = req.header(RANGE, format!("bytes={}-", have));
+    }
+    let res = req.send().await.with_kind(ErrorKind::Network)?;
+    if have > 0 && res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(()); // already have all of it
+    }
+    let res = res.error_for_status().with_kind(ErrorKind::Registry)?;
+    let mut file = if res.status() == StatusCode::PARTIAL_CONTENT {
+        OpenOptions::new().append(true).open(dst).await?
+    } else {
+        // the server sent the whole thing
+        File::create(dst).await?
+    };
+    let mut body = res.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        file.write_all(&chunk.with_kind(ErrorKind::Network)?)
+            .await?;
+    }
+    file.sync_all().await?;
+    Ok(())
+}
+
+/// Downloads `url` to `dst`, resuming after dropped connections
+#[instrument(skip_all)]
+pub async fn download_resumable(client: &Client, url: &Url, dst: &Path) -> Result<(), Error> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match download_from(client, url, dst).await {
+            Err(e) if e.kind == ErrorKind::Network && attempt < DOWNLOAD_ATTEMPTS => {
+                tracing::warn!("Download of {} interrupted, resuming: {}", url, e);
+                tracing::debug!("{:?}", e);
+                tokio::time::sleep(Duration::from_secs(attempt * 5)).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Verifies and installs the s9pk at `path`, which is deleted afterwards whether or not that
+/// succeeded
+#[instrument(skip_all)]
+pub(super) async fn install_file(ctx: &RpcContext, path: &Path) -> Result<(), Error> {
+    let res = install_file_impl(ctx, path).await;
+    File::delete(path).await?;
+    res
+}
+
+async fn install_file_impl(ctx: &RpcContext, path: &Path) -> Result<(), Error> {
+    let mut rdr = S9pkReader::open(path, true).await?;
+    let manifest = rdr.manifest().await?;
+    let public_dir_path = ctx
+        .datadir
+        .join(PKG_PUBLIC_DIR)
+        .join(&manifest.id)
+        .join(manifest.version.as_str());
+    tokio::fs::create_dir_all(&public_dir_path).await?;
+    let mut icon =
+        File::create(public_dir_path.join(format!("icon.{}", manifest.assets.icon_type()))).await?;
+    tokio::io::copy(&mut rdr.icon().await?, &mut icon).await?;
+    icon.sync_all().await?;
+    drop(rdr);
+
+    let size = tokio::fs::metadata(path).await?.len();
+    let progress = InstallProgress::new(Some(size));
+    begin_install(ctx, &manifest, progress.clone()).await?;
+    download_install_s9pk(
+        ctx,
+        &manifest,
+        None,
+        progress,
+        File::open(path).await?,
+        None,
+    )
+    .await
+}
+
+#[instrument(skip_all)]
+async fn sideload_url_impl(ctx: &RpcContext, url: &Url) -> Result<(), Error> {
+    let path = download_path(&ctx.datadir, url);
+    tokio::fs::create_dir_all(path.parent().unwrap_or(&path)).await?;
+    download_resumable(&ctx.client, url, &path).await?;
+    // a bad download would only be resumed, so it is started over next time
+    install_file(ctx, &path).await
+}
+
+/// Fetches, verifies and installs the s9pk at `url` in the background
+pub fn sideload_url(ctx: RpcContext, url: Url) -> Result<(), Error> {
+    check_url(&url)?;
+    tokio::spawn(async move {
+        if let Err(e) = sideload_url_impl(&ctx, &url).await {
+            let err_str = format!("Sideload of {} Failed: {}", url, e);
+            tracing::error!("{}", err_str);
+            tracing::debug!("{:?}", e);
+            if let Err(e) = ctx
+                .notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    None,
+                    NotificationLevel::Error,
+                    String::from("Sideload Failed"),
+                    err_str,
+                    (),
+                    None,
+                )
+                .await
+            {
+                tracing::error!("Failed to issue Notification: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[test]
+fn url_schemes() {
+    assert!(check_url(&"https://example.com/foo.s9pk".parse().unwrap()).is_ok());
+    assert!(check_url(
+        &"http://2gz4nh5yjfpzdlwco6bpqhs5yyjohvrdhcgzp3dbmsm4ecljxyshbyid.onion/foo.s9pk"
+            .parse()
+            .unwrap()
+    )
+    .is_ok());
+    assert!(check_url(&"http://example.com/foo.s9pk".parse().unwrap()).is_err());
+    assert!(check_url(&"file:///tmp/foo.s9pk".parse().unwrap()).is_err());
+}
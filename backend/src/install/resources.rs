@@ -0,0 +1,273 @@
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::procedure::docker::{runtime, DockerProcedure};
+use crate::procedure::PackageProcedure;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::status::MainStatus;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind};
+
+/// The weight docker gives containers without `--cpu-shares`
+const DEFAULT_CPU_SHARES: u32 = 1024;
+/// The least docker accepts
+const MIN_CPU_SHARES: u32 = 2;
+const MIN_MEMORY_LIMIT_MB: usize = 6;
+
+/// The cgroup limits of a container, declared in the manifest next to `shm-size-mb`, or set by
+/// the user with `package resources set`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Relative to the 1024 of the containers without, only when the CPU is contended
+    pub cpu_shares: Option<u32>,
+    /// Memory the container can use, swap included, before it is killed
+    pub memory_limit_mb: Option<usize>,
+}
+impl ResourceLimits {
+    pub fn validate(&self) -> Result<(), color_eyre::eyre::Report> {
+        if self.cpu_shares.map_or(false, |s| s < MIN_CPU_SHARES) {
+            color_eyre::eyre::bail!("cpu-shares must be at least {}", MIN_CPU_SHARES);
+        }
+        if self
+            .memory_limit_mb
+            .map_or(false, |m| m < MIN_MEMORY_LIMIT_MB)
+        {
+            color_eyre::eyre::bail!("memory-limit-mb must be at least {}", MIN_MEMORY_LIMIT_MB);
+        }
+        Ok(())
+    }
+    /// Each limit of `self`, or of `declared` where `self` has none
+    pub fn or(self, declared: Self) -> Self {
+        Self {
+            cpu_shares: self.cpu_shares.or(declared.cpu_shares),
+            memory_limit_mb: self.memory_limit_mb.or(declared.memory_limit_mb),
+        }
+    }
+    /// For `run`, and `update` but for the memory, which it cannot lift
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if let Some(cpu_shares) = self.cpu_shares {
+            res.push(format!("--cpu-shares={}", cpu_shares));
+        }
+        if let Some(memory_limit_mb) = self.memory_limit_mb {
+            res.push(format!("--memory={}m", memory_limit_mb));
+            res.push(format!("--memory-swap={}m", memory_limit_mb));
+        }
+        res
+    }
+}
+
+/// The limits the manifest declares for the main container
+pub fn declared(manifest: &Manifest) -> ResourceLimits {
+    match (&manifest.containers, &manifest.main) {
+        (Some(containers), _) => containers.main.resources(),
+        (None, PackageProcedure::Docker(main)) => main.resources(),
+        #[allow(unreachable_patterns)]
+        _ => ResourceLimits::default(),
+    }
+}
+
+/// The limits the containers of `package` are created with: those set by the user, and those
+/// `declared` by the manifest for the container otherwise
+pub async fn effective(
+    ctx: &RpcContext,
+    package: &PackageId,
+    declared: ResourceLimits,
+) -> Result<ResourceLimits, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.resource_limits())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .unwrap_or_default()
+        .or(declared))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageResources {
+    pub declared: ResourceLimits,
+    /// See `package resources set`
+    pub overridden: ResourceLimits,
+    pub effective: ResourceLimits,
+}
+
+#[command(subcommands(get, set, reset))]
+pub fn resources() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_resources(arg: PackageResources, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let show = |v: Option<String>| v.unwrap_or_else(|| "-".to_owned());
+    let mut table = Table::new();
+    table.add_row(row![bc => "", "CPU SHARES", "MEMORY LIMIT"]);
+    for (name, limits) in [
+        ("declared", &arg.declared),
+        ("overridden", &arg.overridden),
+        ("effective", &arg.effective),
+    ] {
+        table.add_row(row![
+            name,
+            show(limits.cpu_shares.map(|s| s.to_string())),
+            show(limits.memory_limit_mb.map(|m| format!("{} MiB", m)))
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+async fn installed<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+) -> Result<(Manifest, ResourceLimits, MainStatus), Error> {
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|p| p.installed())
+        .get(db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    Ok((
+        installed.manifest,
+        installed.resource_limits,
+        installed.status.main,
+    ))
+}
+
+/// The CPU shares and memory limit of the containers of `id`
+#[command(display(display_resources), metadata(read_only = true))]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<PackageResources, Error> {
+    let (manifest, overridden, _) = installed(&mut ctx.db.handle(), &id).await?;
+    let declared = declared(&manifest);
+    Ok(PackageResources {
+        declared,
+        overridden,
+        effective: overridden.or(declared),
+    })
+}
+
+/// Holds the containers of `id` to the limits given instead of those of its manifest. The
+/// running main container is updated in place, or restarted to lift its memory limit.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg(long = "cpu-shares")] cpu_shares: Option<u32>,
+    #[arg(long = "memory-limit-mb")] memory_limit_mb: Option<usize>,
+) -> Result<(), Error> {
+    let limits = ResourceLimits {
+        cpu_shares,
+        memory_limit_mb,
+    };
+    limits
+        .validate()
+        .map_err(|e| Error::new(e, ErrorKind::InvalidRequest))?;
+    update(&ctx, &id, |overridden| *overridden = limits.or(*overridden)).await
+}
+
+/// Holds the containers of `id` to the limits of its manifest again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn reset(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    update(&ctx, &id, |overridden| {
+        *overridden = ResourceLimits::default()
+    })
+    .await
+}
+
+async fn update(
+    ctx: &RpcContext,
+    id: &PackageId,
+    f: impl FnOnce(&mut ResourceLimits),
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let (manifest, before, status) = installed(&mut db, id).await?;
+    let mut overridden = before;
+    f(&mut overridden);
+    let mut tx = db.begin().await?;
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(id)
+        .and_then(|m| m.installed())
+        .check(&mut tx)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?
+        .resource_limits()
+        .put(&mut tx, &overridden)
+        .await?;
+    tx.commit().await?;
+
+    if !matches!(status, MainStatus::Running { .. }) {
+        return Ok(());
+    }
+    let declared = declared(&manifest);
+    let (before, after) = (before.or(declared), overridden.or(declared));
+    if before.memory_limit_mb.is_some() && after.memory_limit_mb.is_none() {
+        return crate::control::restart(ctx.clone(), id.clone()).await;
+    }
+    let after = ResourceLimits {
+        cpu_shares: after.cpu_shares.or(Some(DEFAULT_CPU_SHARES)),
+        ..after
+    };
+    runtime()
+        .command()
+        .arg("update")
+        .args(after.docker_args())
+        .arg(DockerProcedure::container_name(id, None))
+        .invoke(ErrorKind::Docker)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn resource_limits() {
+    let declared = ResourceLimits {
+        cpu_shares: Some(512),
+        memory_limit_mb: Some(1024),
+    };
+    let overridden = ResourceLimits {
+        cpu_shares: None,
+        memory_limit_mb: Some(2048),
+    };
+    assert_eq!(
+        overridden.or(declared),
+        ResourceLimits {
+            cpu_shares: Some(512),
+            memory_limit_mb: Some(2048),
+        }
+    );
+    assert_eq!(
+        overridden.or(declared).docker_args(),
+        ["--cpu-shares=512", "--memory=2048m", "--memory-swap=2048m"]
+    );
+    assert!(ResourceLimits {
+        cpu_shares: Some(1),
+        memory_limit_mb: None,
+    }
+    .validate()
+    .is_err());
+}
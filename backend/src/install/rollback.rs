@@ -0,0 +1,266 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use helpers::{Rsync, RsyncOptions};
+use patch_db::{DbHandle, LockType};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tracing::instrument;
+
+use super::progress::InstallProgress;
+use super::{download_install_s9pk, PKG_ARCHIVE_DIR};
+use crate::context::RpcContext;
+use crate::db::model::{PackageDataEntry, StaticFiles};
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::PackageId;
+use crate::s9pk::reader::S9pkReader;
+use crate::util::{display_none, Version};
+use crate::volume::PKG_VOLUME_DIR;
+use crate::{Error, ErrorKind, ResultExt};
+
+pub const PKG_ROLLBACK_DIR: &str = "package-data/rollback";
+
+/// What was kept from before the last update
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RollbackInfo {
+    pub version: Version,
+    pub created_at: DateTime<Utc>,
+}
+
+pub fn rollback_dir<P: AsRef<Path>>(datadir: P, id: &PackageId) -> PathBuf {
+    datadir.as_ref().join(PKG_ROLLBACK_DIR).join(id)
+}
+
+fn s9pk_path(dir: &Path, id: &PackageId) -> PathBuf {
+    dir.join(AsRef::<Path>::as_ref(id).with_extension("s9pk"))
+}
+
+async fn sync(src: &Path, dst: &Path) -> Result<(), Error> {
+    tokio::fs::create_dir_all(dst).await?;
+    Rsync::new(
+        format!("{}/", src.display()),
+        dst,
+        RsyncOptions {
+            delete: true,
+            force: true,
+            ignore_existing: false,
+            exclude: Vec::new(),
+            no_permissions: false,
+            no_owner: false,
+        },
+    )
+    .await?
+    .wait()
+    .await?;
+    Ok(())
+}
+
+/// Keeps the s9pk and the data volumes of the installed `version`, replacing whatever was kept
+/// before, so the update about to happen can be undone. The package must be stopped.
+#[instrument(skip_all)]
+pub async fn snapshot(ctx: &RpcContext, id: &PackageId, version: &Version) -> Result<(), Error> {
+    let dir = rollback_dir(&ctx.datadir, id);
+    discard(&ctx.datadir, id).await?;
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let archive = s9pk_path(
+        &ctx.datadir
+            .join(PKG_ARCHIVE_DIR)
+            .join(id)
+            .join(version.as_str()),
+        id,
+    );
+    // the archive is removed once the update is done, so a hard link costs no extra space
+    if tokio::fs::hard_link(&archive, s9pk_path(&dir, id))
+        .await
+        .is_err()
+    {
+        tokio::fs::copy(&archive, s9pk_path(&dir, id))
+            .await
+            .with_ctx(|_| (ErrorKind::Filesystem, archive.display().to_string()))?;
+    }
+
+    let volumes = ctx.datadir.join(PKG_VOLUME_DIR).join(id).join("data");
+    if tokio::fs::metadata(&volumes).await.is_ok() {
+        sync(&volumes, &dir.join("data")).await?;
+    }
+
+    let info = serde_json::to_vec(&RollbackInfo {
+        version: version.clone(),
+        created_at: Utc::now(),
+    })
+    .with_kind(ErrorKind::Serialization)?;
+    tokio::fs::write(dir.join("rollback.json"), info).await?;
+    Ok(())
+}
+
+/// Removes whatever was kept for rolling `id` back
+#[instrument(skip_all)]
+pub async fn discard<P: AsRef<Path>>(datadir: P, id: &PackageId) -> Result<(), Error> {
+    let dir = rollback_dir(datadir, id);
+    if tokio::fs::metadata(&dir).await.is_ok() {
+        tokio::fs::remove_dir_all(&dir)
+            .await
+            .with_ctx(|_| (ErrorKind::Filesystem, dir.display().to_string()))?;
+    }
+    Ok(())
+}
+
+async fn load_info(dir: &Path) -> Result<Option<RollbackInfo>, Error> {
+    match tokio::fs::read(dir.join("rollback.json")).await {
+        Ok(info) => Ok(Some(
+            serde_json::from_slice(&info).with_kind(ErrorKind::Deserialization)?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reinstalls the version that was installed before the last update and puts its data back the
+/// way it was. The undone update is kept in turn, so running this again goes forward again.
+#[command(display(display_none), metadata(sync_db = true))]
+#[instrument(skip_all)]
+pub async fn rollback(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<(), Error> {
+    let dir = rollback_dir(&ctx.datadir, &id);
+    let info = load_info(&dir).await?.ok_or_else(|| {
+        Error::new(
+            eyre!("There is no previous version of {} to roll back to", id),
+            ErrorKind::NotFound,
+        )
+    })?;
+    let mut s9pk = S9pkReader::from_reader(File::open(s9pk_path(&dir, &id)).await?, true).await?;
+    let man = s9pk.manifest().await?;
+    if man.id != id || man.version != info.version {
+        return Err(Error::new(
+            eyre!("Kept package does not match {}@{}", id, info.version),
+            ErrorKind::ParseS9pk,
+        ));
+    }
+    let size = tokio::fs::metadata(s9pk_path(&dir, &id)).await?.len();
+    drop(s9pk);
+
+    let previous_state = crate::control::stop_impl(ctx.clone(), id.clone()).await?;
+
+    let progress = InstallProgress::new(Some(size));
+    let static_files = StaticFiles::local(&man.id, &man.version, man.assets.icon_type());
+    let mut db_handle = ctx.db.handle();
+    let mut tx = db_handle.begin().await?;
+    crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .lock(&mut tx, LockType::Write)
+        .await?;
+    let mut pde = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .get_mut(&mut tx)
+        .await?;
+    let marketplace_url = match pde.take() {
+        Some(PackageDataEntry::Installed { installed, .. })
+            if installed.manifest.version != man.version =>
+        {
+            let marketplace_url = installed.marketplace_url.clone();
+            *pde = Some(PackageDataEntry::Updating {
+                install_progress: progress.clone(),
+                static_files,
+                installed,
+                manifest: man.clone(),
+            });
+            marketplace_url
+        }
+        Some(PackageDataEntry::Installed { .. }) => {
+            return Err(Error::new(
+                eyre!("{}@{} is already installed", id, man.version),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+        None => {
+            return Err(Error::new(
+                eyre!("{} is not installed", id),
+                ErrorKind::NotFound,
+            ))
+        }
+        _ => {
+            return Err(Error::new(
+                eyre!("Cannot roll back a package in a transient state"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    pde.save(&mut tx).await?;
+    tx.commit().await?;
+    drop(db_handle);
+
+    // installing takes a snapshot of the version being replaced, so set this one aside first
+    let restoring = dir.with_extension("restoring");
+    if tokio::fs::metadata(&restoring).await.is_ok() {
+        tokio::fs::remove_dir_all(&restoring).await?;
+    }
+    tokio::fs::rename(&dir, &restoring).await?;
+
+    tokio::spawn(async move {
+        let res = async {
+            download_install_s9pk(
+                &ctx,
+                &man,
+                marketplace_url,
+                progress,
+                File::open(s9pk_path(&restoring, &id)).await?,
+                None,
+            )
+            .await?;
+            if tokio::fs::metadata(restoring.join("data")).await.is_ok() {
+                sync(
+                    &restoring.join("data"),
+                    &ctx.datadir.join(PKG_VOLUME_DIR).join(&id).join("data"),
+                )
+                .await?;
+            }
+            tokio::fs::remove_dir_all(&restoring).await?;
+            if previous_state.running() {
+                crate::control::start(ctx.clone(), id.clone()).await?;
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+        if let Err(e) = res {
+            // keep what we were rolling back to, so it can be tried again
+            if tokio::fs::metadata(&restoring).await.is_ok() {
+                if let Err(e) = async {
+                    discard(&ctx.datadir, &id).await?;
+                    tokio::fs::rename(&restoring, rollback_dir(&ctx.datadir, &id)).await?;
+                    Ok::<_, Error>(())
+                }
+                .await
+                {
+                    tracing::error!("Failed to restore rollback snapshot of {}: {}", id, e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            let err_str = format!("Rollback of {} to {} Failed: {}", id, man.version, e);
+            tracing::error!("{}", err_str);
+            tracing::debug!("{:?}", e);
+            if let Err(e) = ctx
+                .notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    Some(id),
+                    NotificationLevel::Error,
+                    String::from("Rollback Failed"),
+                    err_str,
+                    (),
+                    None,
+                )
+                .await
+            {
+                tracing::error!("Failed to issue Notification: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -61,7 +61,7 @@ pub async fn update() -> Result<(), Error> {
 }
 
 #[instrument(skip_all)]
-#[command(display(display_serializable))]
+#[command(display(display_serializable), metadata(read_only = true))]
 pub async fn dry(
     #[context] ctx: RpcContext,
     #[arg] id: PackageId,
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use emver::VersionRange;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::auto_update::latest_manifest;
+use super::constraint::constraint_for;
+use crate::context::RpcContext;
+use crate::db::model::PackageDataEntry;
+use crate::marketplace::channel::channel_for;
+use crate::notifications::{NotificationLevel, NotificationType};
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::{Error, ErrorKind};
+
+const INSTALL_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The parts of a manifest that decide whether versions of packages fit together
+#[derive(Debug, Clone)]
+struct Node {
+    version: Version,
+    /// Every dependency, and whether it is required
+    dependencies: BTreeMap<PackageId, (VersionRange, bool)>,
+}
+impl From<&Manifest> for Node {
+    fn from(manifest: &Manifest) -> Self {
+        Node {
+            version: manifest.version.clone(),
+            dependencies: manifest
+                .dependencies
+                .0
+                .iter()
+                .map(|(id, info)| {
+                    (
+                        id.clone(),
+                        (info.version.clone(), info.requirement.required()),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Drops every candidate that would leave a required dependency unsatisfied, or that a dependent
+/// would not accept, until the remaining candidates fit together with what is installed. Returns
+/// the reasons the dropped candidates were skipped.
+fn resolve(
+    installed: &BTreeMap<PackageId, Node>,
+    candidates: &mut BTreeMap<PackageId, Node>,
+) -> BTreeMap<PackageId, String> {
+    let mut skipped = BTreeMap::new();
+    loop {
+        let after = |id: &PackageId| candidates.get(id).or_else(|| installed.get(id));
+        let conflict = candidates.iter().find_map(|(id, node)| {
+            for (dep_id, (range, required)) in &node.dependencies {
+                if !required {
+                    continue;
+                }
+                match after(dep_id) {
+                    None => {
+                        return Some((
+                            id.clone(),
+                            format!("requires {}, which is not installed", dep_id),
+                        ))
+                    }
+                    Some(dep) if !dep.version.satisfies(range) => {
+                        return Some((id.clone(), format!("requires {} {}", dep_id, range)))
+                    }
+                    _ => (),
+                }
+            }
+            installed
+                .keys()
+                .filter(|other| *other != id)
+                .find_map(|other| {
+                    let (range, _) = after(other)?.dependencies.get(id)?;
+                    if node.version.satisfies(range) {
+                        None
+                    } else {
+                        Some((id.clone(), format!("{} requires {} {}", other, id, range)))
+                    }
+                })
+        });
+        match conflict {
+            Some((id, reason)) => {
+                candidates.remove(&id);
+                skipped.insert(id, reason);
+            }
+            None => return skipped,
+        }
+    }
+}
+
+/// Orders `candidates` so that required dependencies are updated before their dependents
+fn order(candidates: &BTreeMap<PackageId, Node>) -> Vec<PackageId> {
+    let mut ordered: Vec<PackageId> = Vec::new();
+    let mut remaining: Vec<&PackageId> = candidates.keys().collect();
+    while !remaining.is_empty() {
+        let ready = remaining.iter().position(|id| {
+            candidates[*id]
+                .dependencies
+                .iter()
+                .all(|(dep_id, (_, required))| {
+                    !required || !candidates.contains_key(dep_id) || ordered.contains(dep_id)
+                })
+        });
+        // a dependency cycle is updated in id order
+        ordered.push(remaining.remove(ready.unwrap_or(0)).clone());
+    }
+    ordered
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PlannedUpdate {
+    pub package_id: PackageId,
+    pub from: Version,
+    pub to: Version,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateAllPlan {
+    /// In the order they are applied
+    pub updates: Vec<PlannedUpdate>,
+    /// Packages that have a newer version which cannot be installed along with the rest
+    pub skipped: BTreeMap<PackageId, String>,
+}
+
+/// The outcome of `package update-all`, sent as a notification once every update has been tried
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateReport {
+    pub updated: BTreeMap<PackageId, Version>,
+    pub failed: BTreeMap<PackageId, String>,
+    pub skipped: BTreeMap<PackageId, String>,
+}
+impl NotificationType for UpdateReport {
+    const CODE: i32 = 3;
+    const NAME: &'static str = "update-report";
+    fn summary(&self) -> Option<String> {
+        Some(format!(
+            "{} updated, {} failed, {} skipped",
+            self.updated.len(),
+            self.failed.len(),
+            self.skipped.len()
+        ))
+    }
+}
+
+fn display_update_all(arg: UpdateAllPlan, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    if arg.updates.is_empty() {
+        println!("No updates to install");
+    } else {
+        let mut table = Table::new();
+        table.add_row(row![bc => "PACKAGE", "FROM", "TO"]);
+        for update in &arg.updates {
+            table.add_row(row![
+                &*update.package_id,
+                update.from.as_str(),
+                update.to.as_str(),
+            ]);
+        }
+        table.print_tty(false).unwrap();
+    }
+    for (id, reason) in &arg.skipped {
+        println!("Skipping {}: {}", id, reason);
+    }
+}
+
+/// Waits for the install of `id` that was just started, and returns whether it ended at `version`
+async fn wait_for_install(
+    ctx: &RpcContext,
+    id: &PackageId,
+    version: &Version,
+) -> Result<(), Error> {
+    loop {
+        tokio::time::sleep(INSTALL_POLL_INTERVAL).await;
+        match crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(id)
+            .get(&mut ctx.db.handle())
+            .await?
+            .into_owned()
+        {
+            Some(PackageDataEntry::Installing { .. }) | Some(PackageDataEntry::Updating { .. }) => {
+                continue
+            }
+            Some(PackageDataEntry::Installed { manifest, .. }) if &manifest.version == version => {
+                return Ok(())
+            }
+            _ => {
+                return Err(Error::new(
+                    eyre!("Update did not complete, see the Install Failed notification"),
+                    ErrorKind::Unknown,
+                ))
+            }
+        }
+    }
+}
+
+async fn apply(
+    ctx: RpcContext,
+    mut installed: BTreeMap<PackageId, Node>,
+    mut candidates: BTreeMap<PackageId, Node>,
+    marketplaces: BTreeMap<PackageId, Url>,
+    mut report: UpdateReport,
+) -> UpdateReport {
+    for id in order(&candidates) {
+        let node = match candidates.remove(&id) {
+            Some(node) => node,
+            None => continue,
+        };
+        // an earlier update that this one needs may have failed
+        if let Some((dep_id, range)) =
+            node.dependencies
+                .iter()
+                .find_map(|(dep_id, (range, required))| {
+                    let dep = installed.get(dep_id)?;
+                    if *required && !dep.version.satisfies(range) {
+                        Some((dep_id, range))
+                    } else {
+                        None
+                    }
+                })
+        {
+            report.skipped.insert(
+                id,
+                format!("requires {} {}, which was not updated", dep_id, range),
+            );
+            continue;
+        }
+        let res = async {
+            super::install(
+                ctx.clone(),
+                id.to_string(),
+                marketplaces.get(&id).cloned(),
+                Some(format!("={}", node.version)),
+                None,
+                false,
+                None,
+                false,
+            )
+            .await?;
+            wait_for_install(&ctx, &id, &node.version).await
+        }
+        .await;
+        match res {
+            Ok(()) => {
+                report.updated.insert(id.clone(), node.version.clone());
+                installed.insert(id, node);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to update {}: {}", id, e);
+                tracing::debug!("{:?}", e);
+                report.failed.insert(id, e.source.to_string());
+            }
+        }
+    }
+    report
+}
+
+/// Finds the newest version of every installed marketplace package, works out which of them can
+/// be installed together, and installs them one at a time, dependencies first. Returns straight
+/// away with the plan; a notification reports the outcome.
+#[command(
+    rename = "update-all",
+    display(display_update_all),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn update_all(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<UpdateAllPlan, Error> {
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let mut installed = BTreeMap::new();
+    let mut candidates = BTreeMap::new();
+    let mut marketplaces = BTreeMap::new();
+    let mut skipped = BTreeMap::new();
+    for (id, pde) in package_data.0 {
+        let installed_pkg = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        let current = Node::from(&installed_pkg.manifest);
+        let marketplace_url = installed_pkg.marketplace_url.clone();
+        installed.insert(id.clone(), current.clone());
+        let marketplace_url = if let Some(url) = marketplace_url {
+            url
+        } else {
+            continue; // sideloaded
+        };
+        let published_id = installed_pkg.instance_of.as_ref().unwrap_or(&id);
+        let channel =
+            channel_for(&mut ctx.db.handle(), &marketplace_url, &id, published_id).await?;
+        let constraint = constraint_for(&mut ctx.db.handle(), &id, published_id).await?;
+        let latest = match latest_manifest(
+            &ctx,
+            &marketplace_url,
+            published_id,
+            channel,
+            constraint.as_ref(),
+        )
+        .await
+        {
+            Ok(latest) => latest,
+            Err(e) => {
+                tracing::warn!("Failed to check for updates to {}: {}", id, e);
+                tracing::debug!("{:?}", e);
+                skipped.insert(id, format!("could not reach marketplace: {}", e.source));
+                continue;
+            }
+        };
+        if latest.version <= current.version {
+            continue;
+        }
+        if let Some(constraint) = constraint.filter(|c| !latest.version.satisfies(c)) {
+            skipped.insert(id, format!("constrained to {}", constraint));
+            continue;
+        }
+        if installed_pkg.held {
+            skipped.insert(id, format!("held at {}", current.version));
+            continue;
+        }
+        marketplaces.insert(id.clone(), marketplace_url);
+        candidates.insert(id, Node::from(&latest));
+    }
+    skipped.extend(resolve(&installed, &mut candidates));
+    let plan = UpdateAllPlan {
+        updates: order(&candidates)
+            .into_iter()
+            .map(|id| PlannedUpdate {
+                from: installed[&id].version.clone(),
+                to: candidates[&id].version.clone(),
+                package_id: id,
+            })
+            .collect(),
+        skipped: skipped.clone(),
+    };
+    if plan.updates.is_empty() {
+        return Ok(plan);
+    }
+
+    let report = UpdateReport {
+        skipped,
+        ..Default::default()
+    };
+    tokio::spawn(async move {
+        let report = apply(ctx.clone(), installed, candidates, marketplaces, report).await;
+        let (level, message) = if report.failed.is_empty() {
+            (
+                NotificationLevel::Success,
+                format!("{} packages were updated", report.updated.len()),
+            )
+        } else {
+            (
+                NotificationLevel::Warning,
+                "Updates have completed, but some package(s) failed to update".to_owned(),
+            )
+        };
+        if let Err(e) = ctx
+            .notification_manager
+            .notify(
+                &mut ctx.db.handle(),
+                None,
+                level,
+                "Updates Complete".to_owned(),
+                message,
+                report,
+                None,
+            )
+            .await
+        {
+            tracing::error!("Failed to issue Notification: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    });
+
+    Ok(plan)
+}
+
+#[test]
+fn upgrade_set() {
+    let node = |version: &str, deps: &[(&str, &str)]| Node {
+        version: version.parse().unwrap(),
+        dependencies: deps
+            .iter()
+            .map(|(id, range)| (id.parse().unwrap(), (range.parse().unwrap(), true)))
+            .collect(),
+    };
+    let id = |s: &str| s.parse::<PackageId>().unwrap();
+    let installed = BTreeMap::from([
+        (id("bitcoind"), node("24.0.0", &[])),
+        (id("lnd"), node("0.15.0", &[("bitcoind", "<25.0.0")])),
+        (
+            id("btc-rpc-proxy"),
+            node("0.3.0", &[("bitcoind", ">=24.0.0")]),
+        ),
+    ]);
+    // lnd has no release accepting bitcoind 25 yet
+    let mut candidates = BTreeMap::from([
+        (id("bitcoind"), node("25.0.0", &[])),
+        (
+            id("btc-rpc-proxy"),
+            node("0.4.0", &[("bitcoind", ">=25.0.0")]),
+        ),
+    ]);
+    let skipped = resolve(&installed, &mut candidates);
+    assert!(skipped.contains_key(&id("bitcoind")));
+    assert!(skipped.contains_key(&id("btc-rpc-proxy")));
+    assert!(candidates.is_empty());
+
+    let mut candidates = BTreeMap::from([
+        (
+            id("btc-rpc-proxy"),
+            node("0.4.0", &[("bitcoind", ">=25.0.0")]),
+        ),
+        (id("bitcoind"), node("25.0.0", &[])),
+        (id("lnd"), node("0.16.0", &[("bitcoind", ">=25.0.0")])),
+    ]);
+    assert!(resolve(&installed, &mut candidates).is_empty());
+    assert_eq!(order(&candidates)[0], id("bitcoind"));
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::auto_update::latest_manifest;
+use super::constraint::constraint_for;
+use crate::context::RpcContext;
+use crate::db::model::PackageDataEntry;
+use crate::marketplace::channel::{channel_for, Channel};
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::Error;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AvailableUpdate {
+    pub title: String,
+    pub current: Version,
+    pub latest: Version,
+    pub marketplace_url: Url,
+    pub channel: Channel,
+    /// Held packages are never updated until unheld
+    pub held: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AvailableUpdates {
+    pub updates: BTreeMap<PackageId, AvailableUpdate>,
+    /// Packages whose registry could not be asked, and why
+    pub errors: BTreeMap<PackageId, String>,
+}
+
+#[command(subcommands(available))]
+pub fn updates() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_available(arg: AvailableUpdates, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "CURRENT", "LATEST", "CHANNEL", "HELD"]);
+    for (id, update) in &arg.updates {
+        table.add_row(row![
+            &**id,
+            update.current.as_str(),
+            update.latest.as_str(),
+            update.channel.to_string(),
+            update.held,
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    for (id, error) in &arg.errors {
+        eprintln!("Could not check {}: {}", id, error);
+    }
+}
+
+/// Asks the registry of every installed marketplace package for its newest version on the
+/// channel it follows, and lists those that are newer than what is installed
+#[command(display(display_available), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn available(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<AvailableUpdates, Error> {
+    let package_data = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let mut res = AvailableUpdates::default();
+    for (id, pde) in package_data.0 {
+        let installed = match pde {
+            PackageDataEntry::Installed { installed, .. } => installed,
+            _ => continue,
+        };
+        let marketplace_url = if let Some(url) = installed.marketplace_url.clone() {
+            url
+        } else {
+            continue; // sideloaded
+        };
+        let published_id = installed.instance_of.as_ref().unwrap_or(&id);
+        let channel =
+            channel_for(&mut ctx.db.handle(), &marketplace_url, &id, published_id).await?;
+        let constraint = constraint_for(&mut ctx.db.handle(), &id, published_id).await?;
+        match latest_manifest(
+            &ctx,
+            &marketplace_url,
+            published_id,
+            channel,
+            constraint.as_ref(),
+        )
+        .await
+        {
+            Ok(latest)
+                if latest.version > installed.manifest.version
+                    && constraint
+                        .as_ref()
+                        .map_or(true, |c| latest.version.satisfies(c)) =>
+            {
+                res.updates.insert(
+                    id,
+                    AvailableUpdate {
+                        title: installed.display_name().to_owned(),
+                        current: installed.manifest.version.clone(),
+                        latest: latest.version,
+                        marketplace_url,
+                        channel,
+                        held: installed.held,
+                    },
+                );
+            }
+            Ok(_) => (),
+            Err(e) => {
+                res.errors.insert(id, e.source.to_string());
+            }
+        }
+    }
+    Ok(res)
+}
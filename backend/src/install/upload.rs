@@ -0,0 +1,532 @@
+use std::collections::BTreeMap;
+use std::io::SeekFrom;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, StreamExt};
+use http::header::CONTENT_LENGTH;
+use http::{Request, Response, StatusCode};
+use hyper::Body;
+use openssl::sha::Sha256;
+use rpc_toolkit::command;
+use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::instrument;
+
+use super::remote::install_file;
+use crate::context::{CliContext, RpcContext};
+use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
+use crate::notifications::NotificationLevel;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, AsyncFileExt};
+use crate::{Error, ErrorKind, ResultExt};
+
+const UPLOAD_DIR: &str = "package-data/tmp/upload";
+/// The most a single append may carry
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+/// What the cli sends per append
+const CLI_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+const CLI_ATTEMPTS: u64 = 5;
+/// Uploads nothing was appended to for this long are discarded
+const UPLOAD_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+lazy_static::lazy_static! {
+    static ref UPLOAD_LOCKS: std::sync::Mutex<BTreeMap<String, Arc<Mutex<()>>>> =
+        std::sync::Mutex::new(BTreeMap::new());
+}
+
+/// Held while the files of upload `id` are written, checked or removed, so that appends cannot
+/// interleave and an upload is never expired in the middle of one
+async fn lock_upload(id: &str) -> OwnedMutexGuard<()> {
+    let lock = UPLOAD_LOCKS
+        .lock()
+        .unwrap()
+        .entry(id.to_owned())
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+/// Like [`lock_upload`], but `None` if the upload is busy
+fn try_lock_upload(id: &str) -> Option<OwnedMutexGuard<()>> {
+    let lock = UPLOAD_LOCKS
+        .lock()
+        .unwrap()
+        .entry(id.to_owned())
+        .or_default()
+        .clone();
+    lock.try_lock_owned().ok()
+}
+
+/// Forgets the lock of an upload whose files were removed
+fn forget_upload(id: &str) {
+    UPLOAD_LOCKS.lock().unwrap().remove(id);
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct UploadInfo {
+    size: u64,
+    /// Hex encoded, of the whole s9pk
+    sha256: Option<String>,
+    /// When the upload was started or last appended to
+    last_active: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UploadStatus {
+    pub size: u64,
+    /// Where the next append has to start
+    pub received: u64,
+}
+
+struct UploadPaths {
+    info: PathBuf,
+    data: PathBuf,
+}
+
+fn upload_paths(datadir: &Path, id: &str) -> Result<UploadPaths, Error> {
+    // the id ends up in a path, so it has to be one we could have handed out
+    let id = RequestGuid::from(id)
+        .ok_or_else(|| Error::new(eyre!("Invalid upload id"), ErrorKind::InvalidRequest))?;
+    let dir = datadir.join(UPLOAD_DIR);
+    Ok(UploadPaths {
+        info: dir.join(format!("{}.json", id)),
+        data: dir.join(format!("{}.s9pk", id)),
+    })
+}
+
+fn check_sha256(sha256: &str) -> Result<String, Error> {
+    let sha256 = sha256.to_lowercase();
+    match hex::decode(&sha256) {
+        Ok(bytes) if bytes.len() == 32 => Ok(sha256),
+        _ => Err(Error::new(
+            eyre!("sha256 must be 64 hex characters"),
+            ErrorKind::InvalidRequest,
+        )),
+    }
+}
+
+async fn len_of(path: &Path) -> Result<u64, Error> {
+    match tokio::fs::metadata(path).await {
+        Ok(m) => Ok(m.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn load(datadir: &Path, id: &str) -> Result<(UploadPaths, UploadInfo), Error> {
+    let paths = upload_paths(datadir, id)?;
+    let info = match tokio::fs::read(&paths.info).await {
+        Ok(info) => serde_json::from_slice(&info).with_kind(ErrorKind::Deserialization)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::new(
+                eyre!("Upload {} does not exist", id),
+                ErrorKind::NotFound,
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok((paths, info))
+}
+
+async fn save(paths: &UploadPaths, info: &UploadInfo) -> Result<(), Error> {
+    tokio::fs::write(&paths.info, IoFormat::Json.to_vec(info)?).await?;
+    Ok(())
+}
+
+async fn sha256_of<R: AsyncRead + Unpin>(mut rdr: R) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let n = rdr.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finish()))
+}
+
+/// Removes the files of uploads that were abandoned. Uploads that are being appended to are
+/// left alone.
+async fn expire(datadir: &Path) -> Result<(), Error> {
+    let dir = datadir.join(UPLOAD_DIR);
+    let mut entries = match tokio::fs::read_dir(&dir).await {
+        Ok(a) => a,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let expiry =
+        chrono::Duration::from_std(UPLOAD_EXPIRY).unwrap_or_else(|_| chrono::Duration::max_value());
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let id = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(id) => id.to_owned(),
+            None => continue,
+        };
+        if path.extension().map_or(true, |ext| ext != "json") {
+            // the data of an upload is removed together with its info, except for committed
+            // uploads that are left behind by their install
+            let orphaned = !upload_paths(datadir, &id).map_or(false, |p| p.info.exists());
+            let modified = entry.metadata().await?.modified()?;
+            if orphaned
+                && SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    > UPLOAD_EXPIRY
+            {
+                File::delete(&path).await?;
+            }
+            continue;
+        }
+        let _guard = if let Some(guard) = try_lock_upload(&id) {
+            guard
+        } else {
+            continue;
+        };
+        let (paths, info) = match load(datadir, &id).await {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::warn!("Removing unreadable upload {}: {}", id, e);
+                File::delete(&path).await?;
+                continue;
+            }
+        };
+        if Utc::now() - info.last_active > expiry {
+            File::delete(&paths.data).await?;
+            File::delete(&paths.info).await?;
+            forget_upload(&id);
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(init, append, status, commit, cancel))]
+pub fn upload() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Starts an upload of an s9pk of `size` bytes, and returns its id. Browsers outside of a secure
+/// context cannot hash, so `sha256` is optional here and for every chunk.
+#[command(rpc_only, metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn init(
+    #[context] ctx: RpcContext,
+    #[arg] size: u64,
+    #[arg] sha256: Option<String>,
+) -> Result<String, Error> {
+    let sha256 = sha256.as_deref().map(check_sha256).transpose()?;
+    expire(&ctx.datadir).await?;
+    let id = RequestGuid::new().to_string();
+    let paths = upload_paths(&ctx.datadir, &id)?;
+    tokio::fs::create_dir_all(ctx.datadir.join(UPLOAD_DIR)).await?;
+    File::create(&paths.data).await?.sync_all().await?;
+    save(
+        &paths,
+        &UploadInfo {
+            size,
+            sha256,
+            last_active: Utc::now(),
+        },
+    )
+    .await?;
+    Ok(id)
+}
+
+fn respond(status: StatusCode, body: impl Into<Body>) -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(status)
+        .body(body.into())
+        .with_kind(ErrorKind::Network)
+}
+
+/// Returns a continuation to POST the bytes of the upload from `offset` on to. `sha256` is that
+/// of the posted chunk, which is discarded if it does not match.
+#[command(rpc_only, metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn append(
+    #[context] ctx: RpcContext,
+    #[arg] id: String,
+    #[arg] offset: u64,
+    #[arg] sha256: Option<String>,
+) -> Result<RequestGuid, Error> {
+    let sha256 = sha256.as_deref().map(check_sha256).transpose()?;
+    {
+        // a client that is about to post counts as active
+        let _guard = lock_upload(&id).await;
+        let (paths, mut info) = load(&ctx.datadir, &id).await?;
+        info.last_active = Utc::now();
+        save(&paths, &info).await?;
+    }
+    let guid = RequestGuid::new();
+    let datadir = ctx.datadir.clone();
+    let handler = Box::new(move |req: Request<Body>| {
+        async move {
+            let mut body = req.into_body();
+            let mut chunk = Vec::new();
+            while let Some(part) = body.next().await {
+                let part = part.with_kind(ErrorKind::Network)?;
+                if chunk.len() + part.len() > MAX_CHUNK_SIZE {
+                    return respond(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("Chunks may be at most {} bytes", MAX_CHUNK_SIZE),
+                    );
+                }
+                chunk.extend_from_slice(&part);
+            }
+            if sha256.map_or(false, |s| hex::encode(openssl::sha::sha256(&chunk)) != s) {
+                return respond(StatusCode::BAD_REQUEST, "Chunk does not match its sha256");
+            }
+            let _guard = lock_upload(&id).await;
+            let (paths, mut info) = match load(&datadir, &id).await {
+                Ok(a) => a,
+                Err(e) if e.kind == ErrorKind::NotFound => {
+                    return respond(StatusCode::NOT_FOUND, format!("{}", e.source))
+                }
+                Err(e) => return Err(e),
+            };
+            let received = len_of(&paths.data).await?;
+            if received != offset {
+                return respond(
+                    StatusCode::CONFLICT,
+                    format!("Upload continues at {}", received),
+                );
+            }
+            if received + chunk.len() as u64 > info.size {
+                return respond(
+                    StatusCode::BAD_REQUEST,
+                    "Chunk exceeds the size of the upload",
+                );
+            }
+            let mut file = OpenOptions::new().append(true).open(&paths.data).await?;
+            file.write_all(&chunk).await?;
+            file.sync_all().await?;
+            info.last_active = Utc::now();
+            save(&paths, &info).await?;
+            respond(StatusCode::OK, (received + chunk.len() as u64).to_string())
+        }
+        .boxed()
+    });
+    ctx.add_continuation(
+        guid.clone(),
+        RpcContinuation::rest(handler, Duration::from_secs(30)),
+    )
+    .await;
+    Ok(guid)
+}
+
+fn display_status(arg: UploadStatus, matches: &ArgMatches) {
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+    println!("{} of {} bytes received", arg.received, arg.size);
+}
+
+/// How much of an upload has arrived, to resume it after a dropped connection
+#[command(display(display_status), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn status(
+    #[context] ctx: RpcContext,
+    #[arg] id: String,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<UploadStatus, Error> {
+    let (paths, info) = load(&ctx.datadir, &id).await?;
+    Ok(UploadStatus {
+        size: info.size,
+        received: len_of(&paths.data).await?,
+    })
+}
+
+/// Checks a complete upload against its sha256 and installs it in the background
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn commit(#[context] ctx: RpcContext, #[arg] id: String) -> Result<(), Error> {
+    let _guard = lock_upload(&id).await;
+    let (paths, info) = load(&ctx.datadir, &id).await?;
+    let received = len_of(&paths.data).await?;
+    if received != info.size {
+        return Err(Error::new(
+            eyre!(
+                "Only {} of {} bytes have been uploaded",
+                received,
+                info.size
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let matches = match &info.sha256 {
+        Some(sha256) => &sha256_of(File::open(&paths.data).await?).await? == sha256,
+        None => true,
+    };
+    if !matches {
+        File::delete(&paths.data).await?;
+        File::delete(&paths.info).await?;
+        forget_upload(&id);
+        return Err(Error::new(
+            eyre!("Upload does not match its sha256, start it over"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    File::delete(&paths.info).await?;
+    forget_upload(&id);
+    tokio::spawn(async move {
+        if let Err(e) = install_file(&ctx, &paths.data).await {
+            let err_str = format!("Sideload Failed: {}", e);
+            tracing::error!("{}", err_str);
+            tracing::debug!("{:?}", e);
+            if let Err(e) = ctx
+                .notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    None,
+                    NotificationLevel::Error,
+                    String::from("Sideload Failed"),
+                    err_str,
+                    (),
+                    None,
+                )
+                .await
+            {
+                tracing::error!("Failed to issue Notification: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn cancel(#[context] ctx: RpcContext, #[arg] id: String) -> Result<(), Error> {
+    let _guard = lock_upload(&id).await;
+    let (paths, _) = load(&ctx.datadir, &id).await?;
+    File::delete(&paths.data).await?;
+    File::delete(&paths.info).await?;
+    forget_upload(&id);
+    Ok(())
+}
+
+async fn cli_status(ctx: &CliContext, id: &str) -> Result<UploadStatus, RpcError> {
+    Ok(rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.upload.status",
+        serde_json::json!({ "id": id }),
+        PhantomData::<UploadStatus>,
+    )
+    .await?
+    .result?)
+}
+
+/// Sends the chunk of `chunk` at `offset`, and returns where the upload continues
+async fn cli_append(
+    ctx: &CliContext,
+    id: &str,
+    offset: u64,
+    chunk: Vec<u8>,
+) -> Result<u64, RpcError> {
+    let guid = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.upload.append",
+        serde_json::json!({
+            "id": id,
+            "offset": offset,
+            "sha256": hex::encode(openssl::sha::sha256(&chunk)),
+        }),
+        PhantomData::<RequestGuid>,
+    )
+    .await?
+    .result?;
+    let res = ctx
+        .client
+        .post(format!("{}rest/rpc/{}", ctx.base_url, guid))
+        .header(CONTENT_LENGTH, chunk.len())
+        .body(chunk)
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?;
+    let status = res.status();
+    let text = res.text().await.with_kind(ErrorKind::Network)?;
+    if status == StatusCode::CONFLICT {
+        // an earlier attempt arrived after all
+        return Ok(cli_status(ctx, id).await?.received);
+    }
+    if !status.is_success() {
+        return Err(Error::new(eyre!("{}", text), ErrorKind::Network).into());
+    }
+    text.trim()
+        .parse::<u64>()
+        .with_kind(ErrorKind::Network)
+        .map_err(From::from)
+}
+
+/// Uploads the s9pk at `path` in chunks, resuming after dropped connections, and installs it
+#[instrument(skip_all)]
+pub async fn cli_upload(ctx: &CliContext, path: &Path) -> Result<(), RpcError> {
+    let mut file = File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let sha256 = sha256_of(&mut file).await?;
+    let id = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.upload.init",
+        serde_json::json!({ "size": size, "sha256": sha256 }),
+        PhantomData::<String>,
+    )
+    .await?
+    .result?;
+    let mut offset = 0;
+    let mut attempt = 0;
+    while offset < size {
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut chunk = Vec::with_capacity(CLI_CHUNK_SIZE);
+        (&mut file)
+            .take(CLI_CHUNK_SIZE as u64)
+            .read_to_end(&mut chunk)
+            .await?;
+        match cli_append(ctx, &id, offset, chunk).await {
+            Ok(received) => {
+                attempt = 0;
+                offset = received;
+                tracing::info!("Uploaded {} of {} bytes", offset, size);
+            }
+            Err(e) if attempt < CLI_ATTEMPTS => {
+                attempt += 1;
+                tracing::warn!("Upload interrupted, resuming: {}", e);
+                tokio::time::sleep(Duration::from_secs(attempt * 5)).await;
+                if let Ok(status) = cli_status(ctx, &id).await {
+                    offset = status.received;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "package.upload.commit",
+        serde_json::json!({ "id": id }),
+        PhantomData::<()>,
+    )
+    .await?
+    .result?;
+    Ok(())
+}
+
+#[test]
+fn upload_ids() {
+    let id = RequestGuid::new().to_string();
+    assert!(upload_paths(Path::new("/embassy-data"), &id).is_ok());
+    assert!(upload_paths(Path::new("/embassy-data"), "../../etc/passwd").is_err());
+    assert!(check_sha256(&"AB".repeat(32)).is_ok());
+    assert!(check_sha256("abcd").is_err());
+}
@@ -0,0 +1,279 @@
+use std::path::Path;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use futures::TryStreamExt;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::instrument;
+
+use super::{docker_load, PKG_ARCHIVE_DIR, PKG_PUBLIC_DIR};
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::s9pk::reader::S9pkReader;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Version;
+use crate::volume::{asset_dir, script_dir};
+use crate::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    StaticFile,
+    Script,
+    Asset,
+    Image,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Problem {
+    Missing,
+    Modified,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// The file path, or the image tag
+    pub name: String,
+    pub problem: Problem,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct VerifyReport {
+    pub package_id: PackageId,
+    pub version: Version,
+    pub issues: Vec<Issue>,
+    pub repaired: bool,
+}
+
+async fn sha256<R: AsyncRead + Unpin>(mut rdr: R) -> Result<[u8; 32], Error> {
+    let mut hasher = openssl::sha::Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = rdr.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finish())
+}
+
+async fn check_file(
+    issues: &mut Vec<Issue>,
+    kind: IssueKind,
+    path: &Path,
+    expected: [u8; 32],
+) -> Result<(), Error> {
+    let problem = match File::open(path).await {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Some(Problem::Missing),
+        Err(e) => return Err(e.into()),
+        Ok(f) if sha256(f).await? != expected => Some(Problem::Modified),
+        Ok(_) => None,
+    };
+    if let Some(problem) = problem {
+        issues.push(Issue {
+            kind,
+            name: path.display().to_string(),
+            problem,
+        });
+    }
+    Ok(())
+}
+
+async fn restore_file<R: AsyncRead + Unpin>(mut src: R, dst: &Path) -> Result<(), Error> {
+    if let Some(parent) = dst.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = File::create(dst).await?;
+    tokio::io::copy(&mut src, &mut file).await?;
+    file.sync_all().await?;
+    Ok(())
+}
+
+fn display_verify(arg: VerifyReport, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    if arg.issues.is_empty() {
+        println!("{}@{} is intact", arg.package_id, arg.version);
+        return;
+    }
+    let mut table = Table::new();
+    table.add_row(row![bc => "KIND", "PROBLEM", "NAME"]);
+    for issue in &arg.issues {
+        table.add_row(row![
+            match issue.kind {
+                IssueKind::StaticFile => "static file",
+                IssueKind::Script => "script",
+                IssueKind::Asset => "asset",
+                IssueKind::Image => "image",
+            },
+            match issue.problem {
+                Problem::Missing => "missing",
+                Problem::Modified => "modified",
+            },
+            &issue.name,
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    if arg.repaired {
+        println!("Repaired from the archived s9pk");
+    }
+}
+
+/// Checks the installed static files, scripts, assets and docker images of `id` against its
+/// archived s9pk, which is itself checked against the developer's signature. With `repair`, the
+/// affected parts are unpacked again; running containers keep their image until restarted.
+#[command(display(display_verify), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn verify(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg(long = "repair")] repair: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<VerifyReport, Error> {
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|p| p.installed())
+        .map(|m| m.manifest())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", id), ErrorKind::NotFound))?;
+    let instance_of = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&id)
+        .and_then(|p| p.installed())
+        .map(|i| i.instance_of())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .flatten();
+    let version = &manifest.version;
+    let archive = ctx
+        .datadir
+        .join(PKG_ARCHIVE_DIR)
+        .join(&id)
+        .join(version.as_str())
+        .join(AsRef::<Path>::as_ref(&id).with_extension("s9pk"));
+    let mut rdr = S9pkReader::open(&archive, true).await.map_err(|e| {
+        Error::new(
+            eyre!(
+                "The archived s9pk of {}@{} cannot be used to verify it, reinstall the package instead: {}",
+                id,
+                version,
+                e.source
+            ),
+            ErrorKind::ParseS9pk,
+        )
+    })?;
+
+    let public_dir = ctx
+        .datadir
+        .join(PKG_PUBLIC_DIR)
+        .join(&id)
+        .join(version.as_str());
+    let license_path = public_dir.join("LICENSE.md");
+    let instructions_path = public_dir.join("INSTRUCTIONS.md");
+    let icon_path = public_dir.join(format!("icon.{}", manifest.assets.icon_type()));
+    let scripts_path = script_dir(&ctx.datadir, &id, version).join("embassy.js");
+    let asset_dir = asset_dir(&ctx.datadir, &id, version);
+
+    let mut issues = Vec::new();
+    let expected = sha256(rdr.license().await?).await?;
+    check_file(&mut issues, IssueKind::StaticFile, &license_path, expected).await?;
+    let expected = sha256(rdr.instructions().await?).await?;
+    check_file(
+        &mut issues,
+        IssueKind::StaticFile,
+        &instructions_path,
+        expected,
+    )
+    .await?;
+    let expected = sha256(rdr.icon().await?).await?;
+    check_file(&mut issues, IssueKind::StaticFile, &icon_path, expected).await?;
+    if let Some(scripts) = rdr.scripts().await? {
+        let expected = sha256(scripts).await?;
+        check_file(&mut issues, IssueKind::Script, &scripts_path, expected).await?;
+    }
+    {
+        let mut tar = tokio_tar::Archive::new(rdr.assets().await?);
+        let mut entries = tar.entries()?;
+        while let Some(entry) = entries.try_next().await? {
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = asset_dir.join(&*entry.path()?);
+            let expected = sha256(entry).await?;
+            check_file(&mut issues, IssueKind::Asset, &path, expected).await?;
+        }
+    }
+    for (tag, expected) in rdr.image_ids().await? {
+        // the archive is tagged for the package an instance was made from
+        let tag = match &instance_of {
+            Some(base) => tag.replacen(&format!("start9/{}/", base), &format!("start9/{}/", id), 1),
+            None => tag,
+        };
+        let problem = match ctx.docker.inspect_image(&tag).await {
+            Ok(image) if image.id.as_deref() == Some(&*expected) => continue,
+            Ok(_) => Problem::Modified,
+            Err(bollard::errors::Error::DockerResponseServerError {
+                status_code: 404, // NOT FOUND
+                ..
+            }) => Problem::Missing,
+            Err(e) => return Err(e.into()),
+        };
+        issues.push(Issue {
+            kind: IssueKind::Image,
+            name: tag,
+            problem,
+        });
+    }
+
+    let repaired = repair && !issues.is_empty();
+    if repaired {
+        let affected = |kind| issues.iter().any(|i| i.kind == kind);
+        if affected(IssueKind::StaticFile) {
+            restore_file(rdr.license().await?, &license_path).await?;
+            restore_file(rdr.instructions().await?, &instructions_path).await?;
+            restore_file(rdr.icon().await?, &icon_path).await?;
+        }
+        if affected(IssueKind::Script) {
+            if let Some(scripts) = rdr.scripts().await? {
+                restore_file(scripts, &scripts_path).await?;
+            }
+        }
+        if affected(IssueKind::Asset) {
+            tokio::fs::create_dir_all(&asset_dir).await?;
+            let mut tar = tokio_tar::Archive::new(rdr.assets().await?);
+            tar.unpack(&asset_dir).await?;
+        }
+        if affected(IssueKind::Image) {
+            docker_load(&mut rdr.docker_images().await?).await?;
+            if let Some(base) = &instance_of {
+                super::instance::tag_images(&ctx, base, &id, version).await?;
+            }
+        }
+        tracing::info!("Repaired {} problems in {}@{}", issues.len(), id, version);
+    }
+
+    Ok(VerifyReport {
+        package_id: id,
+        version: version.clone(),
+        issues,
+        repaired,
+    })
+}
@@ -28,6 +28,7 @@ pub mod developer;
 pub mod diagnostic;
 pub mod disk;
 pub mod error;
+pub mod exec;
 pub mod hostname;
 pub mod init;
 pub mod inspect;
@@ -90,6 +91,7 @@ pub fn main_api() -> Result<(), RpcError> {
     system::logs,
     system::kernel_logs,
     system::metrics,
+    install::gc::prune_images,
     shutdown::shutdown,
     shutdown::restart,
     shutdown::rebuild,
@@ -103,16 +105,37 @@ pub fn server() -> Result<(), RpcError> {
     action::action,
     install::install,
     install::sideload,
+    install::upload::upload,
     install::uninstall,
     install::list,
+    install::export::export,
+    install::gc::gc,
+    install::disk_usage::disk_usage,
+    install::hold::hold,
+    install::hold::unhold,
+    install::label::label,
+    install::resources::resources,
+    install::devices::devices,
+    install::license::license,
+    install::hooks::hooks,
+    install::verify::verify,
+    install::auto_update::auto_update,
+    install::queue::queue,
+    install::rollback::rollback,
     install::update::update,
+    install::update_all::update_all,
+    install::constraint::constraint,
+    install::updates::updates,
+    s9pk::keys::keys,
     config::config,
     control::start,
     control::stop,
     control::restart,
     logs::logs,
+    exec::exec,
     properties::properties,
     dependencies::dependency,
+    dependencies::dependency_graph,
     backup::package_backup,
 ))]
 pub fn package() -> Result<(), RpcError> {
@@ -122,6 +145,7 @@ pub fn package() -> Result<(), RpcError> {
 #[command(subcommands(
     version::git_info,
     s9pk::pack,
+    s9pk::extract::extract,
     developer::verify,
     developer::init,
     inspect::inspect
@@ -26,6 +26,7 @@ use tracing::instrument;
 use crate::context::{CliContext, RpcContext};
 use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
 use crate::error::ResultExt;
+use crate::net::interface::InterfaceId;
 use crate::procedure::docker::DockerProcedure;
 use crate::s9pk::manifest::PackageId;
 use crate::util::display_none;
@@ -212,12 +213,15 @@ pub enum LogSource {
     Kernel,
     Service(&'static str),
     Container(PackageId),
+    /// The requests to an interface of a package, see `net access-log`
+    Access(PackageId, InterfaceId),
 }
 
 #[command(
     custom_cli(cli_logs(async, context(CliContext))),
     subcommands(self(logs_nofollow(async)), logs_follow),
-    display(display_none)
+    display(display_none),
+    metadata(read_only = true)
 )]
 pub async fn logs(
     #[arg] id: PackageId,
@@ -256,7 +260,12 @@ pub async fn logs_nofollow(
 ) -> Result<LogResponse, Error> {
     fetch_logs(LogSource::Container(id), limit, cursor, before).await
 }
-#[command(rpc_only, rename = "follow", display(display_none))]
+#[command(
+    rpc_only,
+    rename = "follow",
+    display(display_none),
+    metadata(read_only = true)
+)]
 pub async fn logs_follow(
     #[context] ctx: RpcContext,
     #[parent_data] (id, limit, _, _, _): (PackageId, Option<usize>, Option<String>, bool, bool),
@@ -272,8 +281,8 @@ pub async fn cli_logs_generic_nofollow(
     cursor: Option<String>,
     before: bool,
 ) -> Result<(), RpcError> {
-    let res = rpc_toolkit::command_helpers::call_remote(
-        ctx.clone(),
+    cli_logs_remote_nofollow(
+        ctx,
         method,
         serde_json::json!({
             "id": id,
@@ -281,6 +290,20 @@ pub async fn cli_logs_generic_nofollow(
             "cursor": cursor,
             "before": before,
         }),
+    )
+    .await
+}
+
+/// Prints the logs returned by `method` for `params`, for logs identified by more than an id
+pub async fn cli_logs_remote_nofollow(
+    ctx: CliContext,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<(), RpcError> {
+    let res = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        method,
+        params,
         PhantomData::<LogResponse>,
     )
     .await?
@@ -299,13 +322,27 @@ pub async fn cli_logs_generic_follow(
     id: Option<PackageId>,
     limit: Option<usize>,
 ) -> Result<(), RpcError> {
-    let res = rpc_toolkit::command_helpers::call_remote(
-        ctx.clone(),
+    cli_logs_remote_follow(
+        ctx,
         method,
         serde_json::json!({
             "id": id,
             "limit": limit,
         }),
+    )
+    .await
+}
+
+/// Prints the logs streamed by `method` for `params`, for logs identified by more than an id
+pub async fn cli_logs_remote_follow(
+    ctx: CliContext,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<(), RpcError> {
+    let res = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        method,
+        params,
         PhantomData::<LogFollowResponse>,
     )
     .await?
@@ -371,6 +408,11 @@ pub async fn journalctl(
                 DockerProcedure::container_name(&id, None)
             ));
         }
+        LogSource::Access(package, interface) => {
+            cmd.arg(format!("--namespace={}", crate::net::access_log::NAMESPACE));
+            cmd.arg(format!("STARTOS_PACKAGE={}", package));
+            cmd.arg(format!("STARTOS_INTERFACE={}", interface));
+        }
     };
 
     let cursor_formatted = format!("--after-cursor={}", cursor.clone().unwrap_or(""));
@@ -20,6 +20,7 @@ use tracing::instrument;
 
 use crate::context::RpcContext;
 use crate::manager::sync::synchronizer;
+use crate::net::isolation::{self, network_name};
 use crate::net::net_controller::NetService;
 use crate::net::vhost::AlpnInfo;
 use crate::procedure::docker::{DockerContainer, DockerProcedure, LongRunning};
@@ -564,6 +565,11 @@ async fn add_network_for_main(
     seed: &ManagerSeed,
     ip: std::net::Ipv4Addr,
 ) -> Result<NetService, Error> {
+    // what it may reach depends on the dependencies it has now
+    if let Err(e) = isolation::refresh(&seed.ctx.net_controller, &mut seed.ctx.db.handle()).await {
+        tracing::error!("Error Refreshing Isolation Policy: {}", e);
+        tracing::debug!("{:?}", e);
+    }
     let mut svc = seed
         .ctx
         .net_controller
@@ -579,7 +585,11 @@ async fn add_network_for_main(
                 id.clone(),
                 external.0,
                 internal.internal,
-                Err(AlpnInfo::Specified(vec![])),
+                Err(if internal.passthrough {
+                    AlpnInfo::Passthrough
+                } else {
+                    AlpnInfo::Specified(vec![])
+                }),
             )
             .await?;
         }
@@ -587,6 +597,24 @@ async fn add_network_for_main(
             svc.add_tor(&mut tx, id.clone(), external.0, internal.0)
                 .await?;
         }
+        // an eepsite has a single port: the one served over tor at 80, the first one otherwise
+        if let Some(tor) = &interface.tor_config {
+            if let Some(internal) = tor
+                .port_mapping
+                .iter()
+                .find(|(external, _)| external.0 == 80)
+                .or_else(|| tor.port_mapping.iter().next())
+                .map(|(_, internal)| internal.0)
+            {
+                svc.add_i2p(&mut tx, id.clone(), internal).await?;
+            }
+        }
+        for (external, raw) in interface.raw_config.iter().flatten() {
+            for protocol in &raw.protocols {
+                svc.add_raw(id.clone(), *protocol, external.0, raw.internal)
+                    .await?;
+            }
+        }
     }
     for volume in seed.manifest.volumes.values() {
         if let Volume::Certificate { interface_id } = volume {
@@ -615,7 +643,7 @@ async fn get_running_ip(
                 match res
                     .network_settings
                     .and_then(|ns| ns.networks)
-                    .and_then(|mut n| n.remove("start9"))
+                    .and_then(|mut n| n.remove(&network_name(&state.seed.manifest.id)))
                     .and_then(|es| es.ip_address)
                     .filter(|ip| !ip.is_empty())
                     .map(|ip| ip.parse())
@@ -667,7 +695,7 @@ async fn get_long_running_ip(seed: &ManagerSeed, runtime: &mut LongRunning) -> G
                 match res
                     .network_settings
                     .and_then(|ns| ns.networks)
-                    .and_then(|mut n| n.remove("start9"))
+                    .and_then(|mut n| n.remove(&network_name(&seed.manifest.id)))
                     .and_then(|es| es.ip_address)
                     .filter(|ip| !ip.is_empty())
                     .map(|ip| ip.parse())
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use reqwest::{RequestBuilder, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::{base, belongs_to, client_for, with_query_params};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// What is attached to requests for a registry that does not serve packages to everyone
+#[derive(Clone)]
+pub enum RegistryAuth {
+    Token(String),
+    Basic { username: String, password: String },
+}
+impl RegistryAuth {
+    fn new(
+        token: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<Self, Error> {
+        match (token, username, password) {
+            (Some(token), None, None) => Ok(RegistryAuth::Token(token)),
+            (None, Some(username), Some(password)) => {
+                Ok(RegistryAuth::Basic { username, password })
+            }
+            _ => Err(Error::new(
+                eyre!("Either a token, or a username and password are required"),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+    pub fn authorize(&self, req: RequestBuilder) -> RequestBuilder {
+        match self {
+            RegistryAuth::Token(token) => req.bearer_auth(token),
+            RegistryAuth::Basic { username, password } => req.basic_auth(username, Some(password)),
+        }
+    }
+}
+
+/// The credentials of every registry, by url without a trailing slash
+#[instrument(skip_all)]
+pub async fn load_credentials(secrets: &PgPool) -> Result<BTreeMap<String, RegistryAuth>, Error> {
+    let mut res = BTreeMap::new();
+    for row in sqlx::query!("SELECT url, token, username, password FROM registry_credentials")
+        .fetch_all(secrets)
+        .await?
+    {
+        match RegistryAuth::new(row.token, row.username, row.password) {
+            Ok(auth) => {
+                res.insert(row.url, auth);
+            }
+            Err(e) => tracing::warn!("Ignoring credentials for {}: {}", row.url, e),
+        }
+    }
+    Ok(res)
+}
+
+/// The credentials for the registry `url` belongs to, if any
+pub fn credentials(ctx: &RpcContext, url: &Url) -> Option<RegistryAuth> {
+    let credentials = ctx.registry_credentials.read().unwrap();
+    credentials
+        .iter()
+        .find(|(registry, _)| belongs_to(url, registry))
+        .map(|(_, auth)| auth.clone())
+}
+
+/// A request to the registry `url` belongs to, with the query params every registry expects and
+/// the credentials of wherever it is routed to, if that needs any. A mirror is never sent the
+/// credentials of the registry it mirrors, only those set for the mirror itself.
+pub fn registry_get(ctx: &RpcContext, url: Url) -> RequestBuilder {
+    let client = client_for(ctx, &url);
+    let url = with_query_params(ctx, url);
+    let auth = credentials(ctx, &url);
+    let req = client.get(url);
+    match auth {
+        Some(auth) => auth.authorize(req),
+        None => req,
+    }
+}
+
+#[command(subcommands(set, remove, list))]
+pub fn auth() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Attaches `token` as a bearer token, or `username` and `password` as basic auth, to every
+/// request for the registry at `url`. Requests routed to a mirror of it only go with credentials
+/// set for the mirror.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] url: Url,
+    #[arg(long = "token")] token: Option<String>,
+    #[arg(long = "username")] username: Option<String>,
+    #[arg(long = "password")] password: Option<String>,
+) -> Result<(), Error> {
+    let auth = RegistryAuth::new(token, username, password)?;
+    let url = base(&url).to_owned();
+    let (token, username, password) = match &auth {
+        RegistryAuth::Token(token) => (Some(token), None, None),
+        RegistryAuth::Basic { username, password } => (None, Some(username), Some(password)),
+    };
+    sqlx::query!(
+        "INSERT INTO registry_credentials (url, token, username, password) VALUES ($1, $2, $3, $4) ON CONFLICT (url) DO UPDATE SET token = EXCLUDED.token, username = EXCLUDED.username, password = EXCLUDED.password, created_at = CURRENT_TIMESTAMP",
+        url,
+        token,
+        username,
+        password
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    ctx.registry_credentials.write().unwrap().insert(url, auth);
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] url: Url) -> Result<(), Error> {
+    let url = base(&url).to_owned();
+    let removed = sqlx::query!("DELETE FROM registry_credentials WHERE url = $1", url)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected();
+    if removed == 0 {
+        return Err(Error::new(
+            eyre!("No credentials are stored for {}", url),
+            ErrorKind::NotFound,
+        ));
+    }
+    ctx.registry_credentials.write().unwrap().remove(&url);
+    Ok(())
+}
+
+/// A registry that credentials are stored for. The secrets themselves are never returned.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CredentialsInfo {
+    pub url: String,
+    /// Set for basic auth, and unset for a bearer token
+    pub username: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+fn display_credentials(arg: Vec<CredentialsInfo>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "REGISTRY", "AUTH", "USERNAME", "CREATED AT"]);
+    for info in arg {
+        table.add_row(row![
+            &info.url,
+            if info.username.is_some() {
+                "basic"
+            } else {
+                "token"
+            },
+            info.username.as_deref().unwrap_or("N/A"),
+            info.created_at.to_rfc3339(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_credentials), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<CredentialsInfo>, Error> {
+    Ok(
+        sqlx::query!("SELECT url, username, created_at FROM registry_credentials ORDER BY url")
+            .fetch_all(&ctx.secret_store)
+            .await?
+            .into_iter()
+            .map(|row| CredentialsInfo {
+                url: row.url,
+                username: row.username,
+                created_at: DateTime::from_utc(row.created_at, Utc),
+            })
+            .collect(),
+    )
+}
+
+#[test]
+fn credentials_required() {
+    assert!(RegistryAuth::new(Some("t".to_owned()), None, None).is_ok());
+    assert!(RegistryAuth::new(None, Some("u".to_owned()), Some("p".to_owned())).is_ok());
+    assert!(RegistryAuth::new(None, Some("u".to_owned()), None).is_err());
+    assert!(RegistryAuth::new(Some("t".to_owned()), Some("u".to_owned()), None).is_err());
+    assert!(RegistryAuth::new(None, None, None).is_err());
+}
@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::util::serde::IoFormat;
+use crate::Error;
+
+const CACHE_DIR: &str = "package-data/tmp/marketplace-cache";
+/// How long a response is served without asking the registry whether it changed
+const FRESH_FOR: Duration = Duration::from_secs(60);
+
+/// A response of a registry, kept so that the marketplace can be browsed while it is unreachable
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CacheEntry {
+    pub url: Url,
+    pub etag: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+    pub value: Value,
+}
+impl CacheEntry {
+    pub fn is_fresh(&self) -> bool {
+        Utc::now()
+            .signed_duration_since(self.fetched_at)
+            .to_std()
+            .map_or(true, |age| age < FRESH_FOR)
+    }
+}
+
+fn path(ctx: &RpcContext, url: &Url) -> PathBuf {
+    ctx.datadir.join(CACHE_DIR).join(format!(
+        "{}.json",
+        hex::encode(openssl::sha::sha256(url.as_str().as_bytes()))
+    ))
+}
+
+/// The cached response for `url`, unless there is none or it cannot be read
+pub async fn load(ctx: &RpcContext, url: &Url) -> Option<CacheEntry> {
+    let contents = tokio::fs::read(path(ctx, url)).await.ok()?;
+    match IoFormat::Json.from_slice::<CacheEntry>(&contents) {
+        Ok(entry) if &entry.url == url => Some(entry),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::warn!("Discarding unreadable marketplace cache for {}: {}", url, e);
+            None
+        }
+    }
+}
+
+#[instrument(skip_all)]
+pub async fn save(ctx: &RpcContext, entry: &CacheEntry) -> Result<(), Error> {
+    let path = path(ctx, &entry.url);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp, IoFormat::Json.to_vec(entry)?).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+/// The registry `url` is a request to: everything up to its api path
+pub fn registry_of(url: &Url) -> Url {
+    let mut registry = url.clone();
+    registry.set_query(None);
+    registry.set_fragment(None);
+    let path = url.path();
+    let root = ["/package/v0", "/eos/v0"]
+        .iter()
+        .find_map(|api| path.find(api))
+        .map_or("/", |idx| &path[..=idx]);
+    registry.set_path(root);
+    registry
+}
+
+/// Records whether the marketplace is showing what `registry` served before, rather than what
+/// it serves now, so that the UI can say so
+#[instrument(skip_all)]
+pub async fn set_offline(
+    ctx: &RpcContext,
+    registry: Url,
+    since: Option<DateTime<Utc>>,
+) -> Result<(), Error> {
+    let model = crate::db::DatabaseModel::new()
+        .server_info()
+        .offline_registries();
+    let mut db = ctx.db.handle();
+    if model.get(&mut db).await?.contains_key(&registry) == since.is_some() {
+        return Ok(());
+    }
+    let mut tx = db.begin().await?;
+    let mut offline = model.get_mut(&mut tx).await?;
+    match since {
+        Some(since) => {
+            offline.insert(registry, since);
+        }
+        None => {
+            offline.remove(&registry);
+        }
+    }
+    offline.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[test]
+fn registries() {
+    let url: Url = "https://registry.start9.com/package/v0/index?page=1"
+        .parse()
+        .unwrap();
+    assert_eq!(registry_of(&url).as_str(), "https://registry.start9.com/");
+    let url: Url = "https://example.com/start9/package/v0/icon/bitcoind"
+        .parse()
+        .unwrap();
+    assert_eq!(registry_of(&url).as_str(), "https://example.com/start9/");
+}
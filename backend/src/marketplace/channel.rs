@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use patch_db::{DbHandle, HasModel};
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::base;
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// Which releases of a package a registry offers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Channel {
+    #[default]
+    Stable,
+    Beta,
+}
+impl std::str::FromStr for Channel {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stable" => Ok(Channel::Stable),
+            "beta" => Ok(Channel::Beta),
+            _ => Err(Error::new(
+                eyre!("Must be one of \"stable\", \"beta\"."),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+impl std::fmt::Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Channel::Stable => write!(f, "stable"),
+            Channel::Beta => write!(f, "beta"),
+        }
+    }
+}
+
+/// The channel followed for each registry, and for packages that follow another one than their
+/// registry
+#[derive(Debug, Clone, Default, Deserialize, Serialize, HasModel)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReleaseChannels {
+    pub registries: BTreeMap<Url, Channel>,
+    pub packages: BTreeMap<PackageId, Channel>,
+}
+impl ReleaseChannels {
+    /// The channel `ids` follow on `registry`, checked in order for a channel of their own
+    pub fn get<'a>(&self, registry: &Url, ids: impl IntoIterator<Item = &'a PackageId>) -> Channel {
+        ids.into_iter()
+            .find_map(|id| self.packages.get(id))
+            .or_else(|| {
+                self.registries
+                    .iter()
+                    .find(|(url, _)| base(url) == base(registry))
+                    .map(|(_, channel)| channel)
+            })
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// The channel to query `registry` with for the package installed as `id`, that was published as
+/// `published_id`
+pub async fn channel_for<Db: DbHandle>(
+    db: &mut Db,
+    registry: &Url,
+    id: &PackageId,
+    published_id: &PackageId,
+) -> Result<Channel, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .release_channels()
+        .get(db)
+        .await?
+        .into_owned()
+        .get(registry, [id, published_id]))
+}
+
+#[command(subcommands(set_registry, set_package, list))]
+pub fn channel() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Follows `channel` for every package of `registry` that does not follow one of its own
+#[command(
+    rename = "set-registry",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn set_registry(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] channel: Channel,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut channels = crate::db::DatabaseModel::new()
+        .server_info()
+        .release_channels()
+        .get_mut(&mut db)
+        .await?;
+    channels
+        .registries
+        .retain(|url, _| base(url) != base(&registry));
+    if channel != Channel::Stable {
+        channels.registries.insert(registry, channel);
+    }
+    channels.save(&mut db).await?;
+    Ok(())
+}
+
+/// Follows `channel` for `id` on whichever registry it is installed from, or the channel of that
+/// registry when unset
+#[command(
+    rename = "set-package",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn set_package(
+    #[context] ctx: RpcContext,
+    #[arg] id: PackageId,
+    #[arg] channel: Option<Channel>,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut channels = crate::db::DatabaseModel::new()
+        .server_info()
+        .release_channels()
+        .get_mut(&mut db)
+        .await?;
+    match channel {
+        Some(channel) => channels.packages.insert(id, channel),
+        None => channels.packages.remove(&id),
+    };
+    channels.save(&mut db).await?;
+    Ok(())
+}
+
+fn display_channels(arg: ReleaseChannels, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "REGISTRY OR PACKAGE", "CHANNEL"]);
+    for (url, channel) in &arg.registries {
+        table.add_row(row![url.as_str(), channel.to_string()]);
+    }
+    for (id, channel) in &arg.packages {
+        table.add_row(row![&**id, channel.to_string()]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// Only what differs from following the stable channel everywhere
+#[command(display(display_channels), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<ReleaseChannels, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .release_channels()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn channel_precedence() {
+    let registry: Url = "https://registry.start9.com/".parse().unwrap();
+    let bitcoind: PackageId = "bitcoind".parse().unwrap();
+    let lnd: PackageId = "lnd".parse().unwrap();
+    let mut channels = ReleaseChannels::default();
+    assert_eq!(channels.get(&registry, [&bitcoind]), Channel::Stable);
+    channels.registries.insert(registry.clone(), Channel::Beta);
+    channels.packages.insert(bitcoind.clone(), Channel::Stable);
+    assert_eq!(channels.get(&registry, [&bitcoind]), Channel::Stable);
+    assert_eq!(channels.get(&registry, [&lnd]), Channel::Beta);
+    let other: Url = "https://community-registry.start9.com/".parse().unwrap();
+    assert_eq!(channels.get(&other, [&lnd]), Channel::Stable);
+}
@@ -0,0 +1,571 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use patch_db::DbHandle;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{StatusCode, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use self::cache::CacheEntry;
+use crate::context::RpcContext;
+use crate::shutdown::Shutdown;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::version::VersionT;
+use crate::{Error, ErrorKind, ResultExt};
+
+pub mod auth;
+pub mod cache;
+pub mod channel;
+pub mod pin;
+pub mod search;
+pub mod serve;
+
+const MIRROR_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Generous, since mirrors are often only reachable over tor
+const MIRROR_CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[command(subcommands(
+    get,
+    search::search,
+    search::categories,
+    channel::channel,
+    mirror,
+    pin::pin,
+    auth::auth,
+    tor_only,
+    serve::serve
+))]
+pub fn marketplace() -> Result<(), Error> {
+    Ok(())
+}
+
+/// One of the urls a registry can be reached at, and whether it could be when last checked
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MirrorStatus {
+    pub url: Url,
+    /// Unknown until checked
+    pub reachable: Option<bool>,
+    pub checked_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+impl MirrorStatus {
+    fn new(url: Url) -> Self {
+        MirrorStatus {
+            url,
+            reachable: None,
+            checked_at: None,
+            error: None,
+        }
+    }
+}
+
+pub fn base(url: &Url) -> &str {
+    url.as_str().trim_end_matches('/')
+}
+
+/// Whether `url` is a request to the registry at `registry`, given without a trailing slash
+fn belongs_to(url: &Url, registry: &str) -> bool {
+    url.as_str().strip_prefix(registry).map_or(false, |rest| {
+        rest.is_empty() || rest.starts_with('/') || rest.starts_with('?')
+    })
+}
+
+/// The client requests for the registry `url` belongs to are sent with
+pub fn client_for<'a>(ctx: &'a RpcContext, url: &Url) -> &'a reqwest::Client {
+    let tor_only = ctx.tor_only_registries.read().unwrap();
+    if tor_only.iter().any(|registry| belongs_to(url, registry)) {
+        &ctx.tor_client
+    } else {
+        &ctx.client
+    }
+}
+
+/// The url requests for a registry go to: the first that is not known to be unreachable, and
+/// the registry itself when none are
+fn active(urls: &[MirrorStatus]) -> Option<&Url> {
+    urls.iter()
+        .find(|m| m.reachable != Some(false))
+        .or_else(|| urls.first())
+        .map(|m| &m.url)
+}
+
+fn routes(registries: &BTreeMap<Url, Vec<MirrorStatus>>) -> BTreeMap<String, String> {
+    registries
+        .iter()
+        .filter_map(|(registry, urls)| {
+            active(urls).map(|url| (base(registry).to_owned(), base(url).to_owned()))
+        })
+        .collect()
+}
+
+/// Sends requests for a registry to the mirror it currently fails over to
+fn route(ctx: &RpcContext, url: Url) -> Url {
+    let routes = ctx.registry_routes.read().unwrap();
+    for (registry, active) in routes.iter() {
+        if let Some(rest) = url.as_str().strip_prefix(registry.as_str()) {
+            if !(rest.is_empty() || rest.starts_with('/') || rest.starts_with('?')) {
+                continue;
+            }
+            if registry != active {
+                if let Ok(routed) = format!("{}{}", active, rest).parse() {
+                    return routed;
+                }
+            }
+            break;
+        }
+    }
+    url
+}
+
+pub fn with_query_params(ctx: &RpcContext, url: Url) -> Url {
+    let mut url = route(ctx, url);
+    url.query_pairs_mut()
+        .append_pair(
+            "os.version",
+            &crate::version::Current::new().semver().to_string(),
+        )
+        .append_pair(
+            "os.compat",
+            &crate::version::Current::new().compat().to_string(),
+        )
+        .append_pair("os.arch", crate::OS_ARCH)
+        .append_pair("hardware.arch", &*crate::ARCH)
+        .append_pair("hardware.ram", &ctx.hardware.ram.to_string());
+
+    for hw in &ctx.hardware.devices {
+        url.query_pairs_mut()
+            .append_pair(&format!("hardware.device.{}", hw.class()), hw.product());
+    }
+
+    url
+}
+
+/// Serves `url` from the marketplace cache while it is fresh, and otherwise revalidates it. The
+/// cached response is served instead of an error while its registry cannot be reached.
+#[command(metadata(read_only = true))]
+pub async fn get(#[context] ctx: RpcContext, #[arg] url: Url) -> Result<Value, Error> {
+    let cached = cache::load(&ctx, &url).await;
+    if let Some(entry) = cached.as_ref().filter(|entry| entry.is_fresh()) {
+        return Ok(entry.value.clone());
+    }
+    let registry = cache::registry_of(&url);
+    let etag = cached.as_ref().and_then(|entry| entry.etag.clone());
+    let entry = match (fetch(&ctx, &url, etag).await, cached) {
+        (Ok(Fetched::NotModified), Some(entry)) => CacheEntry {
+            fetched_at: Utc::now(),
+            ..entry
+        },
+        (Ok(Fetched::NotModified), None) => {
+            return Err(Error::new(
+                eyre!("Registry responded Not Modified to an unconditional request"),
+                crate::ErrorKind::Registry,
+            ))
+        }
+        (Ok(Fetched::Modified { value, etag }), _) => CacheEntry {
+            url,
+            etag,
+            fetched_at: Utc::now(),
+            value,
+        },
+        (Err(e), Some(entry))
+            if matches!(
+                e.kind,
+                crate::ErrorKind::Network | crate::ErrorKind::Registry
+            ) =>
+        {
+            tracing::warn!("Serving cached {}: {}", entry.url, e);
+            cache::set_offline(&ctx, registry, Some(entry.fetched_at)).await?;
+            return Ok(entry.value);
+        }
+        (Err(e), _) => return Err(e),
+    };
+    if let Err(e) = cache::save(&ctx, &entry).await {
+        tracing::warn!("Failed to cache {}: {}", entry.url, e);
+    }
+    cache::set_offline(&ctx, registry, None).await?;
+    Ok(entry.value)
+}
+
+enum Fetched {
+    NotModified,
+    Modified { value: Value, etag: Option<String> },
+}
+
+async fn fetch(ctx: &RpcContext, url: &Url, etag: Option<String>) -> Result<Fetched, Error> {
+    let mut response = loop {
+        let mut req = auth::registry_get(ctx, url.clone());
+        if let Some(etag) = &etag {
+            req = req.header(IF_NONE_MATCH, etag);
+        }
+        let req = req.build().with_kind(crate::ErrorKind::Network)?;
+        let routed = req.url().clone();
+        let res = client_for(ctx, url).execute(req).await;
+        if let Err(e) = &res {
+            if (e.is_connect() || e.is_timeout()) && fail_over(ctx, &routed, e).await? {
+                continue;
+            }
+        }
+        break res.with_kind(crate::ErrorKind::Network)?;
+    };
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+    if status.is_success() {
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_owned());
+        let value = match response
+            .headers_mut()
+            .remove("Content-Type")
+            .as_ref()
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(";").next())
+            .map(|h| h.trim())
+        {
+            Some("application/json") => response
+                .json()
+                .await
+                .with_kind(crate::ErrorKind::Deserialization),
+            Some("text/plain") => Ok(Value::String(
+                response
+                    .text()
+                    .await
+                    .with_kind(crate::ErrorKind::Registry)?,
+            )),
+            Some(ctype) => Ok(Value::String(format!(
+                "data:{};base64,{}",
+                ctype,
+                base64::encode_config(
+                    &response
+                        .bytes()
+                        .await
+                        .with_kind(crate::ErrorKind::Registry)?,
+                    base64::URL_SAFE
+                )
+            ))),
+            _ => Err(Error::new(
+                eyre!("missing Content-Type"),
+                crate::ErrorKind::Registry,
+            )),
+        }?;
+        Ok(Fetched::Modified { value, etag })
+    } else {
+        let message = response.text().await.with_kind(crate::ErrorKind::Network)?;
+        Err(Error::new(
+            eyre!("{}", message),
+            match status {
+                StatusCode::BAD_REQUEST => crate::ErrorKind::InvalidRequest,
+                StatusCode::NOT_FOUND => crate::ErrorKind::NotFound,
+                _ => crate::ErrorKind::Registry,
+            },
+        ))
+    }
+}
+
+/// Marks the mirror `url` went to as unreachable, and returns whether requests for its registry
+/// now go elsewhere
+async fn fail_over(ctx: &RpcContext, url: &Url, error: &reqwest::Error) -> Result<bool, Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let mut registries = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_mirrors()
+        .get_mut(&mut tx)
+        .await?;
+    let mut failed_over = false;
+    for urls in registries.values_mut() {
+        let before = active(urls).cloned();
+        if let Some(mirror) = urls
+            .iter_mut()
+            .find(|m| url.as_str().starts_with(base(&m.url)))
+        {
+            tracing::warn!("Registry mirror {} is unreachable: {}", mirror.url, error);
+            mirror.reachable = Some(false);
+            mirror.checked_at = Some(Utc::now());
+            mirror.error = Some(error.to_string());
+            failed_over = active(urls) != before.as_ref();
+            break;
+        }
+    }
+    *ctx.registry_routes.write().unwrap() = routes(&registries);
+    registries.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(failed_over)
+}
+
+async fn check_mirror(ctx: &RpcContext, registry: &Url, url: &Url) -> Result<(), Error> {
+    let req = client_for(ctx, registry)
+        .get(format!("{}/package/v0/info", base(url)))
+        .timeout(MIRROR_CHECK_TIMEOUT);
+    // only the credentials set for the mirror itself, never those of the registry it mirrors
+    match auth::credentials(ctx, url) {
+        Some(auth) => auth.authorize(req),
+        None => req,
+    }
+    .send()
+    .await
+    .with_kind(ErrorKind::Network)?
+    .error_for_status()
+    .with_kind(ErrorKind::Registry)?;
+    Ok(())
+}
+
+/// Checks every url of every registry that has mirrors, and routes requests accordingly
+#[instrument(skip_all)]
+pub async fn check_mirrors(ctx: &RpcContext) -> Result<(), Error> {
+    let mut registries = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_mirrors()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    for (registry, urls) in registries.iter_mut() {
+        for mirror in urls.iter_mut() {
+            let res = check_mirror(ctx, registry, &mirror.url).await;
+            mirror.reachable = Some(res.is_ok());
+            mirror.checked_at = Some(Utc::now());
+            mirror.error = res.err().map(|e| e.source.to_string());
+        }
+    }
+    // mirrors may have been added or removed while checking
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let mut current = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_mirrors()
+        .get_mut(&mut tx)
+        .await?;
+    for (registry, urls) in current.iter_mut() {
+        for mirror in urls.iter_mut() {
+            if let Some(checked) = registries
+                .get(registry)
+                .and_then(|c| c.iter().find(|c| c.url == mirror.url))
+            {
+                *mirror = checked.clone();
+            }
+        }
+    }
+    *ctx.registry_routes.write().unwrap() = routes(&current);
+    current.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Checks registry mirrors every five minutes until the server shuts down
+pub async fn launch_mirror_check_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(MIRROR_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = check_mirrors(ctx).await {
+                    tracing::error!("Error Checking Registry Mirrors: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+/// Fetches indexes and packages of `registry`, and checks its mirrors, only over tor when `enable` is
+/// set, even when it is not an onion service
+#[command(
+    rename = "tor-only",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn tor_only(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] enable: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let mut registries = crate::db::DatabaseModel::new()
+        .server_info()
+        .tor_only_registries()
+        .get_mut(&mut tx)
+        .await?;
+    registries.retain(|url| base(url) != base(&registry));
+    if enable {
+        registries.insert(registry);
+    }
+    *ctx.tor_only_registries.write().unwrap() =
+        registries.iter().map(|url| base(url).to_owned()).collect();
+    registries.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[command(subcommands(add, remove, list, check))]
+pub fn mirror() -> Result<(), Error> {
+    Ok(())
+}
+
+async fn update_mirrors<F: FnOnce(&mut BTreeMap<Url, Vec<MirrorStatus>>) -> Result<(), Error>>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let mut registries = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_mirrors()
+        .get_mut(&mut tx)
+        .await?;
+    f(&mut registries)?;
+    *ctx.registry_routes.write().unwrap() = routes(&registries);
+    registries.save(&mut tx).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Lets requests for `registry` fail over to `url`, tried in the order they were added
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] url: Url,
+) -> Result<(), Error> {
+    if base(&registry) == base(&url) {
+        return Err(Error::new(
+            eyre!("A registry cannot be its own mirror"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    update_mirrors(&ctx, |registries| {
+        let urls = registries
+            .entry(registry.clone())
+            .or_insert_with(|| vec![MirrorStatus::new(registry.clone())]);
+        if urls.iter().any(|m| base(&m.url) == base(&url)) {
+            return Err(Error::new(
+                eyre!("{} is already a mirror of {}", url, registry),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        urls.push(MirrorStatus::new(url));
+        Ok(())
+    })
+    .await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] url: Url,
+) -> Result<(), Error> {
+    update_mirrors(&ctx, |registries| {
+        let urls = registries
+            .get_mut(&registry)
+            .filter(|urls| urls.iter().skip(1).any(|m| base(&m.url) == base(&url)))
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("{} is not a mirror of {}", url, registry),
+                    ErrorKind::NotFound,
+                )
+            })?;
+        urls.retain(|m| base(&m.url) != base(&url));
+        if urls.len() == 1 {
+            registries.remove(&registry);
+        }
+        Ok(())
+    })
+    .await
+}
+
+fn display_mirrors(arg: BTreeMap<Url, Vec<MirrorStatus>>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "REGISTRY", "URL", "ACTIVE", "REACHABLE", "CHECKED AT", "ERROR"]);
+    for (registry, urls) in &arg {
+        let active = active(urls);
+        for mirror in urls {
+            table.add_row(row![
+                registry.as_str(),
+                mirror.url.as_str(),
+                active == Some(&mirror.url),
+                mirror
+                    .reachable
+                    .map_or_else(|| "unknown".to_owned(), |r| r.to_string()),
+                mirror
+                    .checked_at
+                    .map_or_else(|| "never".to_owned(), |t| t.to_rfc3339()),
+                mirror.error.as_deref().unwrap_or(""),
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// Every registry with mirrors, the registry itself first
+#[command(display(display_mirrors), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<Url, Vec<MirrorStatus>>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_mirrors()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+/// Checks every mirror now instead of waiting for the next scheduled check
+#[command(display(display_mirrors), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn check(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<Url, Vec<MirrorStatus>>, Error> {
+    check_mirrors(&ctx).await?;
+    list(ctx, None).await
+}
+
+#[test]
+fn mirror_selection() {
+    let registry: Url = "https://registry.start9.com/".parse().unwrap();
+    let mirror: Url = "http://registry.example.onion".parse().unwrap();
+    let mut urls = vec![
+        MirrorStatus::new(registry.clone()),
+        MirrorStatus::new(mirror.clone()),
+    ];
+    assert_eq!(active(&urls), Some(&registry));
+    urls[0].reachable = Some(false);
+    assert_eq!(active(&urls), Some(&mirror));
+    urls[1].reachable = Some(false);
+    assert_eq!(active(&urls), Some(&registry));
+    let routes = routes(&BTreeMap::from([(registry, urls)]));
+    assert_eq!(
+        routes
+            .get("https://registry.start9.com")
+            .map(|s| s.as_str()),
+        Some("https://registry.start9.com")
+    );
+}
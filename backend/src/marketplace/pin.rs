@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ed25519_dalek::PublicKey;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::base;
+use crate::context::RpcContext;
+use crate::s9pk::keys::{encode_key, parse_key};
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PinnedKey {
+    pub registry: String,
+    pub key: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[command(subcommands(add, list, remove))]
+pub fn pin() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Only installs packages from `registry` that are signed with `key`, or another key pinned for it
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] key: String,
+) -> Result<(), Error> {
+    let registry = base(&registry);
+    let key = encode_key(&parse_key(&key)?);
+    let added = sqlx::query!(
+        "INSERT INTO registry_pinned_keys (registry, pubkey) VALUES ($1, $2) ON CONFLICT (registry, pubkey) DO NOTHING",
+        registry,
+        key
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected();
+    if added == 0 {
+        return Err(Error::new(
+            eyre!("Key is already pinned for {}", registry),
+            ErrorKind::Duplicate,
+        ));
+    }
+    Ok(())
+}
+
+fn display_pins(arg: Vec<PinnedKey>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "REGISTRY", "KEY", "PINNED AT"]);
+    for pin in arg {
+        table.add_row(row![&pin.registry, &pin.key, pin.created_at.to_rfc3339()]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_pins), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<PinnedKey>, Error> {
+    Ok(sqlx::query!(
+        "SELECT registry, pubkey, created_at FROM registry_pinned_keys ORDER BY registry, created_at"
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| PinnedKey {
+        registry: r.registry,
+        key: r.pubkey,
+        created_at: DateTime::from_utc(r.created_at, Utc),
+    })
+    .collect())
+}
+
+/// Unpins `key` for `registry`. Once no key is pinned for it, packages signed with any key are
+/// installed from it again.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(
+    #[context] ctx: RpcContext,
+    #[arg] registry: Url,
+    #[arg] key: String,
+) -> Result<(), Error> {
+    let registry = base(&registry);
+    let key = encode_key(&parse_key(&key)?);
+    let removed = sqlx::query!(
+        "DELETE FROM registry_pinned_keys WHERE registry = $1 AND pubkey = $2",
+        registry,
+        key
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected();
+    if removed == 0 {
+        return Err(Error::new(
+            eyre!("Key is not pinned for {}", registry),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+/// Fails if keys are pinned for `registry` and `key` is not one of them
+#[instrument(skip_all)]
+pub async fn ensure_pinned(secrets: &PgPool, registry: &Url, key: &PublicKey) -> Result<(), Error> {
+    let registry = base(registry);
+    let pinned = sqlx::query!(
+        "SELECT pubkey FROM registry_pinned_keys WHERE registry = $1",
+        registry
+    )
+    .fetch_all(secrets)
+    .await?;
+    let encoded = encode_key(key);
+    if pinned.is_empty() || pinned.iter().any(|r| r.pubkey == encoded) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            eyre!(
+                "Package from {} is signed by {}, which is not a key pinned for that registry",
+                registry,
+                encoded
+            ),
+            ErrorKind::InvalidSignature,
+        ))
+    }
+}
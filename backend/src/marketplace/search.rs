@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use clap::ArgMatches;
+use futures::future::join_all;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use super::{base, get};
+use crate::context::RpcContext;
+use crate::util::serde::display_serializable;
+use crate::{Error, ErrorKind, ResultExt};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+
+/// A package listed by one of the registries that were searched
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchResult {
+    pub registry: Url,
+    /// As listed in the index of `registry`
+    pub package: Value,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SearchResults {
+    pub packages: Vec<SearchResult>,
+    /// Registries that could not be searched, and why
+    pub errors: BTreeMap<Url, String>,
+}
+
+fn parse_comma_separated(arg: &str, _: &ArgMatches) -> Result<Vec<Url>, Error> {
+    arg.split(',')
+        .map(|s| s.trim().parse().map_err(Error::from))
+        .collect()
+}
+
+/// The registries in `registries`, or else every registry added in the UI
+async fn known_registries(ctx: &RpcContext, registries: Vec<Url>) -> Result<Vec<Url>, Error> {
+    if !registries.is_empty() {
+        return Ok(registries);
+    }
+    let ui = crate::db::DatabaseModel::new()
+        .ui()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    Ok(ui["marketplace"]["known-hosts"]
+        .as_object()
+        .into_iter()
+        .flat_map(|hosts| hosts.keys())
+        .filter_map(|url| url.parse().ok())
+        .collect())
+}
+
+/// Queries every registry for the same page, listing the results of each in the order given
+#[command(display(display_serializable), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn search(
+    #[context] ctx: RpcContext,
+    #[arg] query: Option<String>,
+    #[arg(long = "category")] category: Option<String>,
+    #[arg(long = "page")] page: Option<u32>,
+    #[arg(long = "per-page")] per_page: Option<u32>,
+    #[arg(long = "registries", parse(parse_comma_separated))] registries: Option<Vec<Url>>,
+) -> Result<SearchResults, Error> {
+    let registries = known_registries(&ctx, registries.unwrap_or_default()).await?;
+    let responses = join_all(registries.into_iter().map(|registry| {
+        let ctx = ctx.clone();
+        let mut url: Result<Url, _> = format!("{}/package/v0/index", base(&registry)).parse();
+        if let Ok(url) = &mut url {
+            let mut params = url.query_pairs_mut();
+            if let Some(query) = &query {
+                params.append_pair("query", query);
+            }
+            if let Some(category) = &category {
+                params.append_pair("category", category);
+            }
+            params
+                .append_pair("page", &page.unwrap_or(1).to_string())
+                .append_pair(
+                    "per-page",
+                    &per_page.unwrap_or(DEFAULT_PER_PAGE).to_string(),
+                );
+        }
+        async move {
+            let res = match url {
+                Ok(url) => get(ctx, url).await,
+                Err(e) => Err(e.into()),
+            };
+            (registry, res)
+        }
+    }))
+    .await;
+    let mut results = SearchResults::default();
+    for (registry, res) in responses {
+        match res {
+            Ok(Value::Array(packages)) => {
+                results
+                    .packages
+                    .extend(packages.into_iter().map(|package| SearchResult {
+                        registry: registry.clone(),
+                        package,
+                    }))
+            }
+            Ok(_) => {
+                results
+                    .errors
+                    .insert(registry, "Registry index is not a list".to_owned());
+            }
+            Err(e) => {
+                results.errors.insert(registry, e.source.to_string());
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Categories {
+    /// Every category of any registry, with the registries that have it
+    pub categories: BTreeMap<String, BTreeSet<Url>>,
+    /// Registries whose categories could not be fetched, and why
+    pub errors: BTreeMap<Url, String>,
+}
+
+#[derive(Deserialize)]
+struct RegistryInfo {
+    #[serde(default)]
+    categories: Vec<String>,
+}
+
+#[command(display(display_serializable), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn categories(
+    #[context] ctx: RpcContext,
+    #[arg(long = "registries", parse(parse_comma_separated))] registries: Option<Vec<Url>>,
+) -> Result<Categories, Error> {
+    let registries = known_registries(&ctx, registries.unwrap_or_default()).await?;
+    let responses = join_all(registries.into_iter().map(|registry| {
+        let ctx = ctx.clone();
+        async move {
+            let res = match format!("{}/package/v0/info", base(&registry)).parse() {
+                Ok(url) => get(ctx, url).await.and_then(|info| {
+                    serde_json::from_value::<RegistryInfo>(info)
+                        .with_kind(ErrorKind::Deserialization)
+                }),
+                Err(e) => Err(Error::from(e)),
+            };
+            (registry, res)
+        }
+    }))
+    .await;
+    let mut res = Categories::default();
+    for (registry, info) in responses {
+        match info {
+            Ok(info) => {
+                for category in info.categories {
+                    res.categories
+                        .entry(category)
+                        .or_default()
+                        .insert(registry.clone());
+                }
+            }
+            Err(e) => {
+                res.errors.insert(registry, e.source.to_string());
+            }
+        }
+    }
+    Ok(res)
+}
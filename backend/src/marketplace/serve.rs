@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use emver::VersionRange;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::request::Parts as RequestParts;
+use hyper::{Body, Response, StatusCode};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::install::PKG_ARCHIVE_DIR;
+use crate::net::utils::ViaTor;
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::s9pk::reader::S9pkReader;
+use crate::util::{display_none, Version};
+use crate::{Error, ErrorKind, ResultExt};
+
+const DEFAULT_PER_PAGE: usize = 20;
+
+/// See `marketplace serve`
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryServer {
+    pub enabled: bool,
+    /// Only answers requests made to the onion address of this server
+    pub tor_only: bool,
+}
+
+/// Serves the s9pks archived on this server at `/registry/` as a registry, so other servers can
+/// install them from it without reaching the internet. Add it to their marketplace as
+/// `http://<this server>/registry/`.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn serve(
+    #[context] ctx: RpcContext,
+    #[arg] enable: bool,
+    #[arg(long = "tor-only")] tor_only: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut server = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_server()
+        .get_mut(&mut db)
+        .await?;
+    *server = RegistryServer {
+        enabled: enable,
+        tor_only: enable && tor_only,
+    };
+    server.save(&mut db).await?;
+    Ok(())
+}
+
+struct Archived {
+    path: PathBuf,
+    manifest: Manifest,
+}
+
+/// Every archived s9pk, by the id and version it was published as
+async fn archived(
+    ctx: &RpcContext,
+) -> Result<BTreeMap<PackageId, BTreeMap<Version, Archived>>, Error> {
+    let archive_dir = ctx.datadir.join(PKG_ARCHIVE_DIR);
+    let mut res: BTreeMap<PackageId, BTreeMap<Version, Archived>> = BTreeMap::new();
+    let mut ids = match tokio::fs::read_dir(&archive_dir).await {
+        Ok(ids) => ids,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(res),
+        Err(e) => {
+            return Err(e).with_ctx(|_| (ErrorKind::Filesystem, archive_dir.display().to_string()))
+        }
+    };
+    while let Some(id_dir) = ids.next_entry().await? {
+        let id = id_dir.file_name();
+        let mut versions = tokio::fs::read_dir(id_dir.path()).await?;
+        while let Some(version_dir) = versions.next_entry().await? {
+            let path = version_dir
+                .path()
+                .join(Path::new(&id).with_extension("s9pk"));
+            if tokio::fs::metadata(&path).await.is_err() {
+                continue;
+            }
+            let manifest = match S9pkReader::open(&path, false).await {
+                Ok(mut rdr) => rdr.manifest().await,
+                Err(e) => Err(e),
+            };
+            match manifest {
+                Ok(manifest) => {
+                    res.entry(manifest.id.clone())
+                        .or_default()
+                        .insert(manifest.version.clone(), Archived { path, manifest });
+                }
+                Err(e) => {
+                    tracing::warn!("Not serving {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// The archived version of `id` that satisfies `spec`, preferring the newest unless
+/// `version-priority` is `min`
+fn select<'a>(
+    archived: &'a BTreeMap<PackageId, BTreeMap<Version, Archived>>,
+    id: &PackageId,
+    query: &BTreeMap<String, String>,
+) -> Result<&'a Archived, Error> {
+    let spec: VersionRange = match query.get("spec") {
+        Some(spec) => spec.parse().with_kind(ErrorKind::InvalidRequest)?,
+        None => VersionRange::Any,
+    };
+    let mut matching = archived
+        .get(id)
+        .into_iter()
+        .flat_map(|versions| versions.values())
+        .filter(|a| a.manifest.version.satisfies(&spec));
+    match query.get("version-priority").map(|p| p.as_str()) {
+        Some("min") => matching.next(),
+        _ => matching.last(),
+    }
+    .ok_or_else(|| {
+        Error::new(
+            eyre!("No archived version of {} satisfies {}", id, spec),
+            ErrorKind::NotFound,
+        )
+    })
+}
+
+fn respond(content_type: &str, body: impl Into<Body>) -> Result<Response<Body>, Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .body(body.into())
+        .with_kind(ErrorKind::Network)
+}
+
+fn respond_json(value: &Value) -> Result<Response<Body>, Error> {
+    respond(
+        "application/json",
+        serde_json::to_vec(value).with_kind(ErrorKind::Serialization)?,
+    )
+}
+
+async fn icon_url(archived: &Archived) -> Result<String, Error> {
+    let icon = S9pkReader::open(&archived.path, false)
+        .await?
+        .icon()
+        .await?
+        .to_vec()
+        .await?;
+    Ok(format!(
+        "data:image/{};base64,{}",
+        archived.manifest.assets.icon_type(),
+        base64::encode(icon)
+    ))
+}
+
+async fn index(
+    archived: &BTreeMap<PackageId, BTreeMap<Version, Archived>>,
+    query: &BTreeMap<String, String>,
+) -> Result<Value, Error> {
+    if query.contains_key("category") {
+        // archived packages do not carry the categories of the registry they came from
+        return Ok(json!([]));
+    }
+    let search = query.get("query").map(|q| q.to_lowercase());
+    let page: usize = query.get("page").and_then(|p| p.parse().ok()).unwrap_or(1);
+    let per_page: usize = query
+        .get("per-page")
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(DEFAULT_PER_PAGE);
+    let mut packages = Vec::new();
+    for (id, versions, latest) in archived
+        .iter()
+        .filter_map(|(id, versions)| Some((id, versions, versions.values().last()?)))
+        .filter(|(_, _, latest)| {
+            search.as_ref().map_or(true, |search| {
+                latest.manifest.id.to_lowercase().contains(search)
+                    || latest.manifest.title.to_lowercase().contains(search)
+                    || latest
+                        .manifest
+                        .description
+                        .short
+                        .to_lowercase()
+                        .contains(search)
+            })
+        })
+        .skip(page.saturating_sub(1) * per_page)
+        .take(per_page)
+    {
+        let version = &latest.manifest.version;
+        packages.push(json!({
+            "icon": icon_url(latest).await?,
+            "license": format!("/registry/package/v0/license/{}?spec=={}", id, version),
+            "instructions": format!("/registry/package/v0/instructions/{}?spec=={}", id, version),
+            "manifest": latest.manifest,
+            "categories": [],
+            "versions": versions.keys().collect::<Vec<_>>(),
+            "dependency-metadata": {},
+            "published-at": chrono::DateTime::<chrono::Utc>::from(
+                tokio::fs::metadata(&latest.path).await?.modified()?
+            ),
+        }));
+    }
+    Ok(Value::Array(packages))
+}
+
+async fn route(
+    ctx: &RpcContext,
+    path: &str,
+    query: &BTreeMap<String, String>,
+) -> Result<Response<Body>, Error> {
+    let not_found = || Error::new(eyre!("Not Found"), ErrorKind::NotFound);
+    let path = path.strip_prefix("package/v0/").ok_or_else(not_found)?;
+    match path.split_once('/') {
+        None if path == "info" => {
+            let hostname = crate::db::DatabaseModel::new()
+                .server_info()
+                .hostname()
+                .get(&mut ctx.db.handle())
+                .await?
+                .into_owned();
+            let name = match hostname {
+                Some(hostname) => format!("{} Archive", hostname),
+                None => "StartOS Archive".to_owned(),
+            };
+            respond_json(&json!({ "name": name, "categories": [] }))
+        }
+        None if path == "index" => respond_json(&index(&archived(ctx).await?, query).await?),
+        None => {
+            let id: PackageId = path
+                .strip_suffix(".s9pk")
+                .ok_or_else(not_found)?
+                .parse()
+                .with_kind(ErrorKind::NotFound)?;
+            let archived = archived(ctx).await?;
+            let path = &select(&archived, &id, query)?.path;
+            let file = tokio::fs::File::open(path)
+                .await
+                .with_ctx(|_| (ErrorKind::Filesystem, path.display().to_string()))?;
+            let len = file.metadata().await?.len();
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(CONTENT_LENGTH, len)
+                .body(Body::wrap_stream(tokio_util::io::ReaderStream::new(file)))
+                .with_kind(ErrorKind::Network)
+        }
+        Some((section, id)) => {
+            let id: PackageId = id.parse().with_kind(ErrorKind::NotFound)?;
+            let archived = archived(ctx).await?;
+            let selected = select(&archived, &id, query)?;
+            match section {
+                "manifest" => respond_json(
+                    &serde_json::to_value(&selected.manifest)
+                        .with_kind(ErrorKind::Serialization)?,
+                ),
+                "icon" => {
+                    let mut rdr = S9pkReader::open(&selected.path, false).await?;
+                    respond(
+                        &format!("image/{}", selected.manifest.assets.icon_type()),
+                        rdr.icon().await?.to_vec().await?,
+                    )
+                }
+                "license" => {
+                    let mut rdr = S9pkReader::open(&selected.path, false).await?;
+                    respond("text/markdown", rdr.license().await?.to_vec().await?)
+                }
+                "instructions" => {
+                    let mut rdr = S9pkReader::open(&selected.path, false).await?;
+                    respond("text/markdown", rdr.instructions().await?.to_vec().await?)
+                }
+                _ => Err(not_found()),
+            }
+        }
+    }
+}
+
+/// Answers a request for `/registry/<path>` while `marketplace serve` is enabled
+#[instrument(skip_all)]
+pub async fn handle(
+    ctx: &RpcContext,
+    req: &RequestParts,
+    path: &str,
+) -> Result<Response<Body>, Error> {
+    let server = crate::db::DatabaseModel::new()
+        .server_info()
+        .registry_server()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    // decided by the address Tor forwarded the connection to, since the client controls the host
+    let over_tor = req.extensions.get::<ViaTor>().is_some();
+    let res = if !server.enabled || (server.tor_only && !over_tor) {
+        Err(Error::new(eyre!("Not Found"), ErrorKind::NotFound))
+    } else {
+        let query = url::form_urlencoded::parse(req.uri.query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+        route(ctx, path, &query).await
+    };
+    match res {
+        Ok(res) => Ok(res),
+        Err(e) => {
+            let status = match e.kind {
+                ErrorKind::NotFound => StatusCode::NOT_FOUND,
+                ErrorKind::InvalidRequest => StatusCode::BAD_REQUEST,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Response::builder()
+                .status(status)
+                .body(e.source.to_string().into())
+                .with_kind(ErrorKind::Network)
+        }
+    }
+}
@@ -1,5 +1,4 @@
 use std::borrow::Borrow;
-use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use basic_cookies::Cookie;
@@ -19,12 +18,16 @@ use rpc_toolkit::yajrc::RpcMethod;
 use rpc_toolkit::Metadata;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
-use tokio::sync::Mutex;
 
+use crate::auth::user::{get_role, Role};
+use crate::auth::{access, audit};
 use crate::context::RpcContext;
 use crate::{Error, ResultExt};
 
 pub const LOCAL_AUTH_COOKIE_PATH: &str = "/run/embassy/rpc.authcookie";
+/// Sessions expire once they have gone unused this long.
+/// Trusted devices renew theirs through `auth.refresh` instead of prompting for a password.
+pub const SESSION_IDLE_TIMEOUT: &str = "3 days";
 
 pub trait AsLogoutSessionId {
     fn as_logout_session_id(self) -> String;
@@ -55,13 +58,92 @@ impl HasLoggedOutSessions {
         }
         Ok(HasLoggedOutSessions(()))
     }
+
+    /// Logs out every active session, optionally sparing one, in a single transaction
+    pub async fn all(except: Option<&str>, ctx: &RpcContext) -> Result<Self, Error> {
+        let mut open_authed_websockets = ctx.open_authed_websockets.lock().await;
+        let mut tx = ctx.secret_store.begin().await?;
+        let sessions = sqlx::query!(
+            "UPDATE session SET logged_out = CURRENT_TIMESTAMP WHERE (logged_out IS NULL OR logged_out > CURRENT_TIMESTAMP) AND ($1::text IS NULL OR id <> $1) RETURNING id",
+            except
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+        for session in sessions {
+            for socket in open_authed_websockets
+                .remove(&session.id)
+                .unwrap_or_default()
+            {
+                let _ = socket.send(());
+            }
+        }
+        Ok(HasLoggedOutSessions(()))
+    }
+}
+
+/// Failed login attempts are counted until a full window passes without any attempt.
+/// Once the limit is reached, further attempts are refused until the window has passed.
+pub const LOGIN_ATTEMPT_LIMIT: usize = 3;
+pub const LOGIN_ATTEMPT_WINDOW: Duration = Duration::from_secs(20);
+/// The methods whose attempts are counted and refused once the limit is reached
+const LOGIN_ATTEMPT_METHODS: &[&str] = &["auth.login", "auth.recover", "auth.ssh-challenge"];
+
+pub struct LoginAttempts {
+    pub failures: usize,
+    pub last: Instant,
+}
+impl Default for LoginAttempts {
+    fn default() -> Self {
+        Self {
+            failures: 0,
+            last: Instant::now(),
+        }
+    }
+}
+impl LoginAttempts {
+    pub fn in_window(&self) -> bool {
+        self.last.elapsed() < LOGIN_ATTEMPT_WINDOW
+    }
+
+    pub fn locked(&self) -> bool {
+        self.in_window() && self.failures >= LOGIN_ATTEMPT_LIMIT
+    }
+
+    /// How long until attempts are accepted again, if they are currently refused
+    pub fn remaining(&self) -> Option<Duration> {
+        if self.locked() {
+            Some(LOGIN_ATTEMPT_WINDOW.saturating_sub(self.last.elapsed()))
+        } else {
+            None
+        }
+    }
+
+    pub fn record(&mut self, failed: bool) {
+        if self.in_window() {
+            if failed {
+                self.failures += 1;
+            }
+        } else {
+            self.failures = 0;
+        }
+        self.last = Instant::now();
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
 }
 
 /// Used when we need to know that we have logged in with a valid user
 #[derive(Clone, Copy)]
-pub struct HasValidSession(());
+pub struct HasValidSession(Role);
 
 impl HasValidSession {
+    pub fn role(&self) -> Role {
+        self.0
+    }
+
     pub async fn from_request_parts(
         request_parts: &RequestParts,
         ctx: &RpcContext,
@@ -79,7 +161,9 @@ impl HasValidSession {
                 }
             }
             if let Some(cookie) = cookies.iter().find(|c| c.get_name() == "session") {
-                if let Ok(s) = Self::from_session(&HashSessionToken::from_cookie(cookie), ctx).await
+                if let Ok(s) =
+                    Self::from_session(&HashSessionToken::from_cookie(cookie), request_parts, ctx)
+                        .await
                 {
                     return Ok(s);
                 }
@@ -91,24 +175,34 @@ impl HasValidSession {
         ))
     }
 
-    pub async fn from_session(session: &HashSessionToken, ctx: &RpcContext) -> Result<Self, Error> {
+    pub async fn from_session(
+        session: &HashSessionToken,
+        request_parts: &RequestParts,
+        ctx: &RpcContext,
+    ) -> Result<Self, Error> {
         let session_hash = session.hashed();
-        let session = sqlx::query!("UPDATE session SET last_active = CURRENT_TIMESTAMP WHERE id = $1 AND logged_out IS NULL OR logged_out > CURRENT_TIMESTAMP", session_hash)
-            .execute(&mut ctx.secret_store.acquire().await?)
-            .await?;
-        if session.rows_affected() == 0 {
-            return Err(Error::new(
-                eyre!("UNAUTHORIZED"),
-                crate::ErrorKind::Authorization,
-            ));
+        let last_ip = access::client_ip(request_parts).map(|ip| ip.to_string());
+        let last_host = access::request_host(request_parts);
+        let mut secrets = ctx.secret_store.acquire().await?;
+        let session = sqlx::query!("UPDATE session SET last_active = CURRENT_TIMESTAMP, last_ip = $3, last_host = $4 WHERE id = $1 AND (logged_out IS NULL OR logged_out > CURRENT_TIMESTAMP) AND last_active > CURRENT_TIMESTAMP - $2::text::interval RETURNING username, read_only", session_hash, SESSION_IDLE_TIMEOUT, last_ip, last_host)
+            .fetch_optional(&mut secrets)
+            .await?
+            .ok_or_else(|| Error::new(eyre!("UNAUTHORIZED"), crate::ErrorKind::Authorization))?;
+        let role = if let Some(username) = session.username {
+            get_role(&mut secrets, &username).await?
+        } else {
+            Role::Admin
+        };
+        if session.read_only {
+            return Ok(Self(role.min(Role::Viewer)));
         }
-        Ok(Self(()))
+        Ok(Self(role))
     }
 
     pub async fn from_local(local: &Cookie<'_>) -> Result<Self, Error> {
         let token = tokio::fs::read_to_string(LOCAL_AUTH_COOKIE_PATH).await?;
         if local.get_value() == &*token {
-            Ok(Self(()))
+            Ok(Self(Role::Admin))
         } else {
             Err(Error::new(
                 eyre!("UNAUTHORIZED"),
@@ -142,6 +236,11 @@ impl HashSessionToken {
     }
 
     pub fn from_request_parts(request_parts: &RequestParts) -> Result<Self, Error> {
+        Self::from_request_cookie(request_parts, "session")
+    }
+
+    /// Reads a token of this form stored under a cookie other than `session`
+    pub fn from_request_cookie(request_parts: &RequestParts, name: &str) -> Result<Self, Error> {
         if let Some(cookie_header) = request_parts.headers.get(COOKIE) {
             let cookies = Cookie::parse(
                 cookie_header
@@ -149,7 +248,7 @@ impl HashSessionToken {
                     .with_kind(crate::ErrorKind::Authorization)?,
             )
             .with_kind(crate::ErrorKind::Authorization)?;
-            if let Some(session) = cookies.iter().find(|c| c.get_name() == "session") {
+            if let Some(session) = cookies.iter().find(|c| c.get_name() == name) {
                 return Ok(Self::from_cookie(session));
             }
         }
@@ -171,6 +270,10 @@ impl HashSessionToken {
         self.hashed.as_str()
     }
 
+    pub fn token(&self) -> &str {
+        self.token.as_str()
+    }
+
     pub fn as_hash(self) -> String {
         self.hashed
     }
@@ -212,42 +315,70 @@ impl Borrow<String> for HashSessionToken {
 }
 
 pub fn auth<M: Metadata>(ctx: RpcContext) -> DynMiddleware<M> {
-    let rate_limiter = Arc::new(Mutex::new((0_usize, Instant::now())));
     Box::new(
         move |req: &mut Request<Body>,
               metadata: M|
               -> BoxFuture<Result<Result<DynMiddlewareStage2, Response<Body>>, HttpError>> {
             let ctx = ctx.clone();
-            let rate_limiter = rate_limiter.clone();
             async move {
                 let mut header_stub = Request::new(Body::empty());
                 *header_stub.headers_mut() = req.headers().clone();
                 let m2: DynMiddlewareStage2 = Box::new(move |req, rpc_req| {
                     async move {
-                        if let Err(e) = HasValidSession::from_request_parts(req, &ctx).await {
-                            if metadata
-                                .get(rpc_req.method.as_str(), "authenticated")
-                                .unwrap_or(true)
-                            {
-                                let (res_parts, _) = Response::new(()).into_parts();
-                                return Ok(Err(to_response(
-                                    &req.headers,
-                                    res_parts,
-                                    Err(e.into()),
-                                    |_| StatusCode::OK,
-                                )?));
-                            } else if rpc_req.method.as_str() == "auth.login" {
-                                let guard = rate_limiter.lock().await;
-                                if guard.1.elapsed() < Duration::from_secs(20) {
-                                    if guard.0 >= 3 {
+                        if let Err(e) =
+                            access::check(&ctx.secret_store, req, rpc_req.method.as_str()).await
+                        {
+                            let (res_parts, _) = Response::new(()).into_parts();
+                            return Ok(Err(to_response(
+                                &req.headers,
+                                res_parts,
+                                Err(e.into()),
+                                |_| StatusCode::OK,
+                            )?));
+                        }
+                        let authenticated = metadata
+                            .get(rpc_req.method.as_str(), "authenticated")
+                            .unwrap_or(true);
+                        let limited = LOGIN_ATTEMPT_METHODS.contains(&rpc_req.method.as_str());
+                        match HasValidSession::from_request_parts(req, &ctx).await {
+                            Ok(session) if authenticated => {
+                                let required = Role::required(&metadata, rpc_req.method.as_str());
+                                if session.role() < required {
+                                    let (res_parts, _) = Response::new(()).into_parts();
+                                    return Ok(Err(to_response(
+                                        &req.headers,
+                                        res_parts,
+                                        Err(Error::new(
+                                            eyre!("This action requires the {} role", required),
+                                            crate::ErrorKind::Authorization,
+                                        )
+                                        .into()),
+                                        |_| StatusCode::OK,
+                                    )?));
+                                }
+                            }
+                            Ok(_) => (),
+                            Err(e) => {
+                                if authenticated {
+                                    let (res_parts, _) = Response::new(()).into_parts();
+                                    return Ok(Err(to_response(
+                                        &req.headers,
+                                        res_parts,
+                                        Err(e.into()),
+                                        |_| StatusCode::OK,
+                                    )?));
+                                } else if limited {
+                                    if ctx.login_attempts.lock().await.locked() {
                                         let (res_parts, _) = Response::new(()).into_parts();
                                         return Ok(Err(to_response(
                                             &req.headers,
                                             res_parts,
                                             Err(Error::new(
                                                 eyre!(
-                                                "Please limit login attempts to 3 per 20 seconds."
-                                            ),
+                                                    "Please limit login attempts to {} per {} seconds.",
+                                                    LOGIN_ATTEMPT_LIMIT,
+                                                    LOGIN_ATTEMPT_WINDOW.as_secs()
+                                                ),
                                                 crate::ErrorKind::RateLimited,
                                             )
                                             .into()),
@@ -257,17 +388,40 @@ pub fn auth<M: Metadata>(ctx: RpcContext) -> DynMiddleware<M> {
                                 }
                             }
                         }
+                        let audit = if authenticated
+                            && !metadata
+                                .get(rpc_req.method.as_str(), "read_only")
+                                .unwrap_or(false)
+                        {
+                            Some((
+                                HashSessionToken::from_request_parts(req)
+                                    .ok()
+                                    .map(|t| t.as_hash()),
+                                rpc_req.method.as_str().to_owned(),
+                                serde_json::to_value(&rpc_req.params).unwrap_or_default(),
+                            ))
+                        } else {
+                            None
+                        };
                         let m3: DynMiddlewareStage3 = Box::new(move |_, res| {
                             async move {
-                                let mut guard = rate_limiter.lock().await;
-                                if guard.1.elapsed() < Duration::from_secs(20) {
-                                    if res.is_err() {
-                                        guard.0 += 1;
+                                if let Some((session, method, params)) = audit {
+                                    if let Err(e) = audit::record(
+                                        &ctx.secret_store,
+                                        session.as_deref(),
+                                        &method,
+                                        &params,
+                                        res.is_ok(),
+                                    )
+                                    .await
+                                    {
+                                        tracing::error!("Error Recording Audit Log: {}", e);
+                                        tracing::debug!("{:?}", e);
                                     }
-                                } else {
-                                    guard.0 = 0;
                                 }
-                                guard.1 = Instant::now();
+                                if limited {
+                                    ctx.login_attempts.lock().await.record(res.is_err());
+                                }
                                 Ok(Ok(noop4()))
                             }
                             .boxed()
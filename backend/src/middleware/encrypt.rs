@@ -1,11 +1,22 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use aes::cipher::{CipherKey, NewCipher, Nonce, StreamCipher};
 use aes::Aes256Ctr;
+use color_eyre::eyre::eyre;
 use hmac::Hmac;
+use josekit::jwk::alg::ec::EcCurve;
 use josekit::jwk::Jwk;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use tracing::instrument;
 
+use crate::Error;
+
+/// How long the keypair replaced by a rotation is still accepted, so a client that fetched the
+/// old public key can still submit passwords encrypted to it
+pub const ROTATED_KEY_GRACE: Duration = Duration::from_secs(15 * 60);
+
 pub fn pbkdf2(password: impl AsRef<[u8]>, salt: impl AsRef<[u8]>) -> CipherKey<Aes256Ctr> {
     let mut aeskey = CipherKey::<Aes256Ctr>::default();
     pbkdf2::pbkdf2::<Hmac<Sha256>>(
@@ -89,6 +100,50 @@ impl EncryptedWire {
     }
 }
 
+/// The keypair clients encrypt passwords to, along with the one it replaced while that is still
+/// within its grace period
+pub struct WireKeys {
+    current: Arc<Jwk>,
+    previous: Option<(Arc<Jwk>, Instant)>,
+}
+impl WireKeys {
+    pub fn new(current: Jwk) -> Self {
+        Self {
+            current: Arc::new(current),
+            previous: None,
+        }
+    }
+
+    pub fn current(&self) -> Arc<Jwk> {
+        self.current.clone()
+    }
+
+    /// Replaces the current keypair with a freshly generated one
+    pub fn rotate(&mut self) -> Result<Arc<Jwk>, Error> {
+        let new = Jwk::generate_ec_key(EcCurve::P256).map_err(|e| {
+            tracing::debug!("{:?}", e);
+            Error::new(eyre!("Couldn't generate ec key"), crate::ErrorKind::Unknown)
+        })?;
+        let old = std::mem::replace(&mut self.current, Arc::new(new));
+        self.previous = Some((old, Instant::now()));
+        Ok(self.current())
+    }
+
+    /// Decrypts with the current keypair, falling back to the previous one during its grace
+    /// period
+    pub fn decrypt(&self, wire: EncryptedWire) -> Option<String> {
+        if let Some(res) = wire.clone().decrypt(&self.current) {
+            return Some(res);
+        }
+        match &self.previous {
+            Some((previous, rotated)) if rotated.elapsed() < ROTATED_KEY_GRACE => {
+                wire.decrypt(previous)
+            }
+            _ => None,
+        }
+    }
+}
+
 /// We created this test by first making the private key, then restoring from this private key for recreatability.
 /// After this the frontend then encoded an password, then we are testing that the output that we got (hand coded)
 /// will be the shape we want.
@@ -112,3 +167,25 @@ fn test_gen_awk() {
         &encrypted.decrypt(std::sync::Arc::new(private_key)).unwrap()
     );
 }
+
+#[test]
+fn test_rotated_key_grace() {
+    use josekit::jwe::alg::ecdh_es::EcdhEsJweAlgorithm;
+    use josekit::jwe::JweHeader;
+
+    let mut keys = WireKeys::new(Jwk::generate_ec_key(EcCurve::P256).unwrap());
+    let encrypter = EcdhEsJweAlgorithm::EcdhEs
+        .encrypter_from_jwk(&keys.current().to_public_key().unwrap())
+        .unwrap();
+    let mut header = JweHeader::new();
+    header.set_content_encryption("A128CBC-HS256");
+    let encrypted: EncryptedWire = EncryptedWire {
+        encrypted: serde_json::from_str(
+            &josekit::jwe::serialize_flattened_json(b"testing12345", &header, &encrypter).unwrap(),
+        )
+        .unwrap(),
+    };
+    keys.rotate().unwrap();
+    assert!(encrypted.clone().decrypt(keys.current()).is_none());
+    assert_eq!(keys.decrypt(encrypted).as_deref(), Some("testing12345"));
+}
@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use rpc_toolkit::yajrc::RpcError;
+use tracing::instrument;
+
+use crate::context::{CliContext, RpcContext};
+use crate::logs::{
+    cli_logs_remote_follow, cli_logs_remote_nofollow, fetch_logs, follow_logs, LogFollowResponse,
+    LogResponse, LogSource,
+};
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// The journal namespace of the access logs, rotated on its own by the journald instance set up
+/// in `postinst`, so they cannot push the logs of the OS out
+pub const NAMESPACE: &str = "startos-access";
+const SOCKET: &str = "/run/systemd/journal.startos-access/socket";
+
+/// The interfaces whose requests are logged, by package
+pub type AccessLogMap = BTreeMap<PackageId, BTreeSet<InterfaceId>>;
+
+/// Writes entries to the journal namespace of the access logs, dropping them when journald
+/// cannot keep up rather than slowing down the connections
+pub(super) struct AccessLogger {
+    socket: Option<UnixDatagram>,
+}
+impl AccessLogger {
+    pub(super) fn new() -> Self {
+        let socket = UnixDatagram::unbound().and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            Ok(socket)
+        });
+        Self {
+            socket: socket
+                .map_err(|e| {
+                    tracing::error!("Error Opening Access Log Socket: {}", e);
+                    tracing::debug!("{:?}", e);
+                })
+                .ok(),
+        }
+    }
+    fn send(&self, interface: &(PackageId, InterfaceId), message: &str) {
+        if let Some(socket) = &self.socket {
+            // the native protocol of journald, whose values must not have newlines
+            let message = message.replace(|c: char| c.is_control(), "?");
+            let entry = format!(
+                "MESSAGE={}\nPRIORITY=6\nSYSLOG_IDENTIFIER={}\nSTARTOS_PACKAGE={}\nSTARTOS_INTERFACE={}\n",
+                message, NAMESPACE, interface.0, interface.1
+            );
+            if let Err(e) = socket.send_to(entry.as_bytes(), SOCKET) {
+                tracing::debug!("Error Writing Access Log: {}", e);
+            }
+        }
+    }
+}
+
+/// Where the requests a client makes over a connection to an interface are logged
+pub(super) struct AccessLog {
+    logger: Arc<AccessLogger>,
+    interface: (PackageId, InterfaceId),
+    peer: IpAddr,
+}
+impl AccessLog {
+    pub(super) fn new(
+        logger: Arc<AccessLogger>,
+        interface: (PackageId, InterfaceId),
+        peer: IpAddr,
+    ) -> Self {
+        let peer = match peer {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(peer, IpAddr::V4),
+            ip => ip,
+        };
+        Self {
+            logger,
+            interface,
+            peer,
+        }
+    }
+    /// e.g. `192.168.1.10 files.local "GET / HTTP/1.1" "curl/8.0.1" 200 12ms`, with the time
+    /// the target took to answer, if it did
+    pub(super) fn request(&self, description: &str, status: u16, elapsed: Option<Duration>) {
+        let elapsed = elapsed.map_or_else(|| "-".to_owned(), |e| format!("{}ms", e.as_millis()));
+        self.logger.send(
+            &self.interface,
+            &format!("{} {} {} {}", self.peer, description, status, elapsed),
+        )
+    }
+    /// For the connections passed through, whose requests cannot be read
+    pub(super) fn connection(&self, server_name: Option<&str>) {
+        self.logger.send(
+            &self.interface,
+            &format!("{} {} CONNECT", self.peer, server_name.unwrap_or("-")),
+        )
+    }
+}
+
+/// Logs the interfaces enabled with `net access-log enable` again after a restart
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let access_logs = crate::db::DatabaseModel::new()
+        .server_info()
+        .access_logs()
+        .get(db)
+        .await?
+        .into_owned();
+    for (package, interfaces) in access_logs {
+        for interface in interfaces {
+            net.vhost
+                .set_access_log((package.clone(), interface), true)
+                .await;
+        }
+    }
+    Ok(())
+}
+
+#[command(rename = "access-log", subcommands(enable, disable, list))]
+pub fn access_log() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Logs the requests to the LAN interface `interface` of `package` made from now on, at its
+/// LAN address and its domains, for `net logs`. Over Tor, requests do not go through the
+/// reverse proxy, and are not logged.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .map_or(true, |i| i.lan_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no LAN interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    update(&ctx, &mut db, package, interface, true).await
+}
+
+/// Stops logging the requests to `interface` of `package`. What was logged is kept until it is
+/// rotated out.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    update(&ctx, &mut ctx.db.handle(), package, interface, false).await
+}
+
+async fn update<Db: DbHandle>(
+    ctx: &RpcContext,
+    db: &mut Db,
+    package: PackageId,
+    interface: InterfaceId,
+    enabled: bool,
+) -> Result<(), Error> {
+    let mut all = crate::db::DatabaseModel::new()
+        .server_info()
+        .access_logs()
+        .get_mut(db)
+        .await?;
+    if enabled {
+        all.entry(package.clone())
+            .or_default()
+            .insert(interface.clone());
+    } else if let Some(interfaces) = all.get_mut(&package) {
+        interfaces.remove(&interface);
+        if interfaces.is_empty() {
+            all.remove(&package);
+        }
+    }
+    ctx.net_controller
+        .vhost
+        .set_access_log((package, interface), enabled)
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    all.save(db).await?;
+    Ok(())
+}
+
+fn display_access_logs(arg: AccessLogMap, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE"]);
+    for (package, interfaces) in &arg {
+        for interface in interfaces {
+            table.add_row(row![&**package, &**interface]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The interfaces whose requests are logged
+#[command(display(display_access_logs), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<AccessLogMap, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .access_logs()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+type LogsParams = (
+    PackageId,
+    InterfaceId,
+    Option<usize>,
+    Option<String>,
+    bool,
+    bool,
+);
+
+/// The requests logged for `interface` of `package`, see `net access-log`
+#[command(
+    custom_cli(cli_logs(async, context(CliContext))),
+    subcommands(self(logs_nofollow(async)), logs_follow),
+    display(display_none),
+    metadata(read_only = true)
+)]
+pub async fn logs(
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(short = 'l', long = "limit")] limit: Option<usize>,
+    #[arg(short = 'c', long = "cursor")] cursor: Option<String>,
+    #[arg(short = 'B', long = "before", default)] before: bool,
+    #[arg(short = 'f', long = "follow", default)] follow: bool,
+) -> Result<LogsParams, Error> {
+    Ok((package, interface, limit, cursor, before, follow))
+}
+pub async fn cli_logs(
+    ctx: CliContext,
+    (package, interface, limit, cursor, before, follow): LogsParams,
+) -> Result<(), RpcError> {
+    if follow {
+        if cursor.is_some() {
+            return Err(RpcError::from(Error::new(
+                eyre!("The argument '--cursor <cursor>' cannot be used with '--follow'"),
+                crate::ErrorKind::InvalidRequest,
+            )));
+        }
+        if before {
+            return Err(RpcError::from(Error::new(
+                eyre!("The argument '--before' cannot be used with '--follow'"),
+                crate::ErrorKind::InvalidRequest,
+            )));
+        }
+        cli_logs_remote_follow(
+            ctx,
+            "net.logs.follow",
+            serde_json::json!({
+                "package": package,
+                "interface": interface,
+                "limit": limit,
+            }),
+        )
+        .await
+    } else {
+        cli_logs_remote_nofollow(
+            ctx,
+            "net.logs",
+            serde_json::json!({
+                "package": package,
+                "interface": interface,
+                "limit": limit,
+                "cursor": cursor,
+                "before": before,
+            }),
+        )
+        .await
+    }
+}
+pub async fn logs_nofollow(
+    _ctx: (),
+    (package, interface, limit, cursor, before, _): LogsParams,
+) -> Result<LogResponse, Error> {
+    fetch_logs(LogSource::Access(package, interface), limit, cursor, before).await
+}
+
+#[command(
+    rpc_only,
+    rename = "follow",
+    display(display_none),
+    metadata(read_only = true)
+)]
+pub async fn logs_follow(
+    #[context] ctx: RpcContext,
+    #[parent_data] (package, interface, limit, _, _, _): LogsParams,
+) -> Result<LogFollowResponse, Error> {
+    follow_logs(ctx, LogSource::Access(package, interface), limit).await
+}
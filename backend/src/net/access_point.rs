@@ -0,0 +1,438 @@
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::shutdown::Shutdown;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind};
+
+/// The NetworkManager connection of the access point set up with `wifi access-point enable`
+pub const AP_CONNECTION: &str = "startos-ap";
+/// The NetworkManager connection of the access point of the setup, which is not saved
+pub const SETUP_AP_CONNECTION: &str = "startos-setup-ap";
+const SETUP_SSID: &str = "StartOS Setup";
+/// Where clients of the access point reach this server, every name resolving to it, see
+/// `dnsmasq-shared.d/startos-captive.conf` in `postinst`
+pub const AP_ADDRESS: Ipv4Addr = Ipv4Addr::new(10, 42, 0, 1);
+/// How long this server is offline before the access point is brought up
+const FALLBACK_DELAY: Duration = Duration::from_secs(60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the access point is up without clients before it is taken down, for the Wi-Fi
+/// interface to try the known networks again
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// See `wifi access-point`. Its password is only kept by NetworkManager, with the connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct AccessPointSettings {
+    /// Whether the access point is brought up when this server is offline
+    pub enabled: bool,
+    pub ssid: Option<String>,
+}
+
+async fn connections(active: bool) -> Result<Vec<String>, Error> {
+    let mut cmd = Command::new("nmcli");
+    cmd.arg("-t").arg("-g").arg("NAME").arg("con").arg("show");
+    if active {
+        cmd.arg("--active");
+    }
+    Ok(String::from_utf8(cmd.invoke(ErrorKind::Wifi).await?)?
+        .lines()
+        .map(|l| l.to_owned())
+        .collect())
+}
+
+async fn exists(connection: &str) -> Result<bool, Error> {
+    Ok(connections(false).await?.iter().any(|c| c == connection))
+}
+
+async fn is_active(connection: &str) -> Result<bool, Error> {
+    Ok(connections(true).await?.iter().any(|c| c == connection))
+}
+
+/// The password of the access point `connection`, if it has one
+async fn psk(connection: &str) -> Result<Option<String>, Error> {
+    let psk = String::from_utf8(
+        Command::new("nmcli")
+            .arg("-s")
+            .arg("-t")
+            .arg("-g")
+            .arg("802-11-wireless-security.psk")
+            .arg("con")
+            .arg("show")
+            .arg(connection)
+            .invoke(ErrorKind::Wifi)
+            .await?,
+    )?;
+    let psk = psk.trim();
+    Ok(if psk.is_empty() {
+        None
+    } else {
+        Some(psk.to_owned())
+    })
+}
+
+/// Replaces the access point `connection` on `interface`, which is open without a `psk`,
+/// serving DHCP and DNS to its clients from `AP_ADDRESS`
+async fn configure(
+    connection: &str,
+    interface: &str,
+    ssid: &str,
+    psk: Option<&str>,
+    save: bool,
+) -> Result<(), Error> {
+    if exists(connection).await? {
+        Command::new("nmcli")
+            .arg("con")
+            .arg("delete")
+            .arg(connection)
+            .invoke(ErrorKind::Wifi)
+            .await?;
+    }
+    let mut cmd = Command::new("nmcli");
+    cmd.arg("con")
+        .arg("add")
+        .arg("save")
+        .arg(if save { "yes" } else { "no" })
+        .arg("type")
+        .arg("wifi")
+        .arg("ifname")
+        .arg(interface)
+        .arg("con-name")
+        .arg(connection)
+        .arg("autoconnect")
+        .arg("no")
+        .arg("ssid")
+        .arg(ssid)
+        .arg("802-11-wireless.mode")
+        .arg("ap")
+        .arg("802-11-wireless.band")
+        .arg("bg")
+        .arg("ipv4.method")
+        .arg("shared")
+        .arg("ipv4.addresses")
+        .arg(format!("{}/24", AP_ADDRESS))
+        .arg("ipv6.method")
+        .arg("ignore");
+    if let Some(psk) = psk {
+        cmd.arg("wifi-sec.key-mgmt")
+            .arg("wpa-psk")
+            .arg("wifi-sec.psk")
+            .arg(psk);
+    }
+    cmd.invoke(ErrorKind::Wifi).await?;
+    Ok(())
+}
+
+async fn up(connection: &str) -> Result<(), Error> {
+    Command::new("nmcli")
+        .arg("con")
+        .arg("up")
+        .arg(connection)
+        .invoke(ErrorKind::Wifi)
+        .await?;
+    Ok(())
+}
+
+async fn down(connection: &str) -> Result<(), Error> {
+    Command::new("nmcli")
+        .arg("con")
+        .arg("down")
+        .arg(connection)
+        .invoke(ErrorKind::Wifi)
+        .await?;
+    Ok(())
+}
+
+/// Whether this server has a default route. The access point adds none, so it does not count.
+async fn online() -> Result<bool, Error> {
+    Ok(!Command::new("ip")
+        .arg("route")
+        .arg("show")
+        .arg("default")
+        .invoke(ErrorKind::Network)
+        .await?
+        .is_empty())
+}
+
+/// The clients connected to the access point on `interface`
+async fn stations(interface: &str) -> Result<usize, Error> {
+    Ok(String::from_utf8(
+        Command::new("iw")
+            .arg("dev")
+            .arg(interface)
+            .arg("station")
+            .arg("dump")
+            .invoke(ErrorKind::Wifi)
+            .await?,
+    )?
+    .lines()
+    .filter(|l| l.starts_with("Station "))
+    .count())
+}
+
+/// Brings the access point up once this server has been offline for `FALLBACK_DELAY`, and takes
+/// it down once it is online, or once it has gone unused for `RETRY_INTERVAL`
+#[derive(Default)]
+struct Fallback {
+    offline_since: Option<Instant>,
+    up_since: Option<Instant>,
+}
+impl Fallback {
+    /// Whether the access point is up after the check
+    async fn check(&mut self, connection: &str, interface: &str) -> Result<bool, Error> {
+        if is_active(connection).await? {
+            let up_since = *self.up_since.get_or_insert_with(Instant::now);
+            if online().await?
+                || (up_since.elapsed() > RETRY_INTERVAL && stations(interface).await? == 0)
+            {
+                tracing::info!("Taking Down Wi-Fi Access Point");
+                self.up_since = None;
+                self.offline_since = None;
+                down(connection).await?;
+                return Ok(false);
+            }
+            Ok(true)
+        } else {
+            self.up_since = None;
+            if online().await? {
+                self.offline_since = None;
+            } else if self
+                .offline_since
+                .get_or_insert_with(Instant::now)
+                .elapsed()
+                > FALLBACK_DELAY
+            {
+                tracing::info!("Offline, Bringing Up Wi-Fi Access Point");
+                self.offline_since = None;
+                up(connection).await?;
+                return Ok(true);
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Lets the clients of the access point on `interface` through the firewall while it is up
+async fn sync_firewall(ctx: &RpcContext, interface: Option<&str>) -> Result<(), Error> {
+    if ctx
+        .net_controller
+        .firewall
+        .set_access_point(interface.map(|i| i.to_owned()))
+        .await
+    {
+        ctx.net_controller.sync_firewall().await?;
+    }
+    Ok(())
+}
+
+async fn stop(connection: &str) {
+    if let Ok(true) = is_active(connection).await {
+        if let Err(e) = down(connection).await {
+            tracing::error!("Error Taking Down Wi-Fi Access Point: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+}
+
+/// Lets a server without a network be set up from a phone, over an open access point serving
+/// the setup page, until the setup is done
+pub async fn launch_setup_task(interface: String, mut shutdown: Receiver<()>) {
+    if let Err(e) = configure(SETUP_AP_CONNECTION, &interface, SETUP_SSID, None, false).await {
+        tracing::error!("Error Configuring Wi-Fi Access Point: {}", e);
+        tracing::debug!("{:?}", e);
+        return;
+    }
+    let mut fallback = Fallback::default();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = fallback.check(SETUP_AP_CONNECTION, &interface).await {
+                    tracing::error!("Error Checking Wi-Fi Access Point: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+    stop(SETUP_AP_CONNECTION).await;
+}
+
+/// Brings up the access point of `wifi access-point enable` while this server is offline, for
+/// its network to be configured again from the UI, until it shuts down
+pub async fn launch_access_point_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let interface = match &ctx.wifi_interface {
+        Some(interface) => interface.clone(),
+        None => return,
+    };
+    let mut fallback = Fallback::default();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let res = async {
+                    let settings = crate::db::DatabaseModel::new()
+                        .server_info()
+                        .access_point()
+                        .get(&mut ctx.db.handle())
+                        .await?
+                        .into_owned();
+                    let up = if settings.enabled {
+                        fallback.check(AP_CONNECTION, &interface).await?
+                    } else {
+                        fallback = Fallback::default();
+                        false
+                    };
+                    sync_firewall(ctx, Some(interface.as_str()).filter(|_| up)).await
+                }
+                .await;
+                if let Err(e) = res {
+                    tracing::error!("Error Checking Wi-Fi Access Point: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+    stop(AP_CONNECTION).await;
+}
+
+#[command(rename = "access-point", subcommands(enable, disable, get))]
+pub fn access_point() -> Result<(), Error> {
+    Ok(())
+}
+
+fn interface(ctx: &RpcContext) -> Result<&str, Error> {
+    ctx.wifi_interface
+        .as_deref()
+        .ok_or_else(|| Error::new(eyre!("No WiFi interface available"), ErrorKind::Wifi))
+}
+
+/// Brings up an access point named `ssid` on the Wi-Fi interface whenever this server has been
+/// offline for a minute, at which the UI is served to its clients at `10.42.0.1`. It is taken
+/// down once this server is back online, and every 10 minutes without clients for the known
+/// networks to be tried again. The password is kept if not given again.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(
+    #[context] ctx: RpcContext,
+    #[arg(long = "ssid")] ssid: Option<String>,
+    #[arg(long = "password")] password: Option<String>,
+) -> Result<(), Error> {
+    let interface = interface(&ctx)?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .access_point()
+        .get_mut(&mut db)
+        .await?;
+    let ssid = match ssid.or_else(|| settings.ssid.clone()) {
+        Some(ssid) => ssid,
+        None => format!(
+            "StartOS {}",
+            ctx.account.read().await.hostname.no_dot_host_name()
+        ),
+    };
+    if ssid.is_empty() || ssid.len() > 32 {
+        return Err(Error::new(
+            eyre!("The SSID must have 1 to 32 bytes"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let password = match password {
+        Some(password) => password,
+        None if exists(AP_CONNECTION).await? => psk(AP_CONNECTION).await?.ok_or_else(|| {
+            Error::new(eyre!("A password must be given"), ErrorKind::InvalidRequest)
+        })?,
+        None => {
+            return Err(Error::new(
+                eyre!("A password must be given"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    if !password.is_ascii() || password.len() < 8 || password.len() > 63 {
+        return Err(Error::new(
+            eyre!("The password must have 8 to 63 characters, without special characters"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    configure(AP_CONNECTION, interface, &ssid, Some(&password), true).await?;
+    settings.enabled = true;
+    settings.ssid = Some(ssid);
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Stops bringing up the access point, taking it down if it is up
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .access_point()
+        .get_mut(&mut db)
+        .await?;
+    settings.enabled = false;
+    settings.save(&mut db).await?;
+    stop(AP_CONNECTION).await;
+    sync_firewall(&ctx, None).await
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AccessPointInfo {
+    pub enabled: bool,
+    pub ssid: Option<String>,
+    /// Whether the access point is up
+    pub active: bool,
+}
+
+fn display_access_point(arg: AccessPointInfo, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ENABLED", "SSID", "ACTIVE"]);
+    table.add_row(row![
+        arg.enabled,
+        arg.ssid.as_deref().unwrap_or("N/A"),
+        arg.active
+    ]);
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_access_point), metadata(read_only = true))]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<AccessPointInfo, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .access_point()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    Ok(AccessPointInfo {
+        enabled: settings.enabled,
+        ssid: settings.ssid,
+        active: is_active(AP_CONNECTION).await?,
+    })
+}
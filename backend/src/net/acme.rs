@@ -0,0 +1,1085 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Builder, X509Extension, X509NameBuilder, X509ReqBuilder, X509};
+use patch_db::DbHandle;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Client, Response, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::RwLock;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::dns01::{provider_for, zone_for, DnsProvider};
+use crate::net::domain::check_domain;
+use crate::net::net_controller::NetController;
+use crate::net::ssl::{generate_key, rand_serial, CERTIFICATE_VERSION};
+use crate::s9pk::manifest::PackageId;
+use crate::shutdown::Shutdown;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// The ALPN protocol a TLS-ALPN-01 validation connects with (RFC 8737)
+pub const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+/// id-pe-acmeIdentifier
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+const RENEW_BEFORE_DAYS: i64 = 30;
+const RENEWAL_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// How the provider is shown that this server answers for a domain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Challenge {
+    /// Serves a token at `http://<domain>/.well-known/acme-challenge/`, needs port 80
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    /// Presents a challenge certificate on port 443
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+    /// Publishes a TXT record through the DNS provider of the zone, needs no open ports and is
+    /// the only way to get a wildcard certificate
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+impl FromStr for Challenge {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http-01" => Ok(Challenge::Http01),
+            "tls-alpn-01" => Ok(Challenge::TlsAlpn01),
+            "dns-01" => Ok(Challenge::Dns01),
+            _ => Err(Error::new(
+                eyre!("Must be one of \"http-01\", \"tls-alpn-01\", \"dns-01\"."),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+impl std::fmt::Display for Challenge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Challenge::Http01 => write!(f, "http-01"),
+            Challenge::TlsAlpn01 => write!(f, "tls-alpn-01"),
+            Challenge::Dns01 => write!(f, "dns-01"),
+        }
+    }
+}
+
+/// A package interface served at a domain instead of the StartOS UI
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DomainTarget {
+    pub package: PackageId,
+    pub interface: InterfaceId,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AcmeDomain {
+    pub challenge: Challenge,
+    /// The StartOS UI when unset
+    pub target: Option<DomainTarget>,
+    /// Of the certificate currently served
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Why the last request for a certificate failed
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AcmeSettings {
+    /// The directory of the provider registered with `net acme init`
+    pub provider: Option<Url>,
+    pub email: Option<String>,
+    pub domains: BTreeMap<String, AcmeDomain>,
+    /// The provider answering DNS-01 challenges for each zone, see `net acme dns`
+    #[serde(default)]
+    pub dns_providers: BTreeMap<String, String>,
+}
+impl AcmeSettings {
+    /// The configured wildcard whose certificate `domain` is served with, never one for a
+    /// wildcard itself, which has a certificate of its own
+    pub fn wildcard_for(&self, domain: &str) -> Option<&str> {
+        if domain.starts_with("*.") {
+            return None;
+        }
+        let wildcard = format!("*.{}", domain.split_once('.')?.1);
+        self.domains
+            .get_key_value(&wildcard)
+            .map(|(wildcard, _)| wildcard.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct AcmeCert {
+    pub key: PKey<Private>,
+    /// Leaf first
+    pub chain: Vec<X509>,
+}
+impl AcmeCert {
+    pub fn rustls_chain(&self) -> Result<Vec<tokio_rustls::rustls::Certificate>, Error> {
+        self.chain
+            .iter()
+            .map(|c| Ok(tokio_rustls::rustls::Certificate(c.to_der()?)))
+            .collect()
+    }
+    pub fn rustls_key(&self) -> Result<tokio_rustls::rustls::PrivateKey, Error> {
+        Ok(tokio_rustls::rustls::PrivateKey(
+            self.key.private_key_to_der()?,
+        ))
+    }
+}
+
+/// Issued certificates and pending challenges, shared with the reverse proxy and the web server
+#[derive(Debug, Default)]
+pub struct AcmeState {
+    certs: RwLock<BTreeMap<String, Arc<AcmeCert>>>,
+    /// key authorizations by token
+    http01: RwLock<BTreeMap<String, String>>,
+    /// challenge certificates by domain
+    tls_alpn01: RwLock<BTreeMap<String, Arc<AcmeCert>>>,
+}
+impl AcmeState {
+    /// The certificate issued for `domain`, or else for the wildcard covering it
+    pub async fn cert(&self, domain: &str) -> Option<Arc<AcmeCert>> {
+        let certs = self.certs.read().await;
+        certs
+            .get(domain)
+            .or_else(|| certs.get(&format!("*.{}", domain.split_once('.')?.1)))
+            .cloned()
+    }
+    pub async fn challenge_cert(&self, domain: &str) -> Option<Arc<AcmeCert>> {
+        self.tls_alpn01.read().await.get(domain).cloned()
+    }
+    pub async fn key_authorization(&self, token: &str) -> Option<String> {
+        self.http01.read().await.get(token).cloned()
+    }
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn jwk(key: &PKey<Private>) -> Result<Value, Error> {
+    let ec = key.ec_key()?;
+    let mut ctx = BigNumContext::new()?;
+    let mut x = BigNum::new()?;
+    let mut y = BigNum::new()?;
+    ec.public_key()
+        .affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+    Ok(json!({
+        "crv": "P-256",
+        "kty": "EC",
+        "x": b64(&x.to_vec_padded(32)?),
+        "y": b64(&y.to_vec_padded(32)?),
+    }))
+}
+
+/// RFC 7638, which needs the members in lexicographic order and without whitespace
+fn thumbprint(key: &PKey<Private>) -> Result<String, Error> {
+    let jwk = jwk(key)?;
+    let canonical = format!(
+        r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+        jwk["x"].as_str().unwrap_or_default(),
+        jwk["y"].as_str().unwrap_or_default()
+    );
+    Ok(b64(&sha256(canonical.as_bytes())))
+}
+
+fn expires_at(cert: &X509) -> Result<DateTime<Utc>, Error> {
+    let diff = Asn1Time::from_unix(0)?.diff(cert.not_after())?;
+    NaiveDateTime::from_timestamp_opt(diff.days as i64 * 86400 + diff.secs as i64, 0)
+        .map(|t| DateTime::from_utc(t, Utc))
+        .ok_or_else(|| Error::new(eyre!("Certificate expiry out of range"), ErrorKind::Acme))
+}
+
+fn make_csr(key: &PKey<Private>, domain: &str) -> Result<Vec<u8>, Error> {
+    let mut builder = X509ReqBuilder::new()?;
+    let mut subject_name_builder = X509NameBuilder::new()?;
+    subject_name_builder.append_entry_by_text("CN", domain)?;
+    builder.set_subject_name(&subject_name_builder.build())?;
+    builder.set_pubkey(key)?;
+    let mut extensions = Stack::new()?;
+    extensions.push(
+        SubjectAlternativeName::new()
+            .dns(domain)
+            .build(&builder.x509v3_context(None))?,
+    )?;
+    builder.add_extensions(&extensions)?;
+    builder.sign(key, MessageDigest::sha256())?;
+    Ok(builder.build().to_der()?)
+}
+
+/// The self-signed certificate presented for `domain` to a TLS-ALPN-01 validation
+fn tls_alpn01_cert(domain: &str, key_authorization: &str) -> Result<AcmeCert, Error> {
+    let key = generate_key()?;
+    let mut builder = X509Builder::new()?;
+    builder.set_version(CERTIFICATE_VERSION)?;
+    let embargo = Asn1Time::days_from_now(0)?;
+    builder.set_not_before(&embargo)?;
+    let expiration = Asn1Time::days_from_now(7)?;
+    builder.set_not_after(&expiration)?;
+    builder.set_serial_number(&*rand_serial()?)?;
+
+    let mut subject_name_builder = X509NameBuilder::new()?;
+    subject_name_builder.append_entry_by_text("CN", domain)?;
+    let subject_name = subject_name_builder.build();
+    builder.set_subject_name(&subject_name)?;
+    builder.set_issuer_name(&subject_name)?;
+    builder.set_pubkey(&key)?;
+
+    let subject_alt_name = SubjectAlternativeName::new()
+        .dns(domain)
+        .build(&builder.x509v3_context(None, None))?;
+    // the DER encoded OCTET STRING of the digest of the key authorization
+    let mut identifier = vec![0x04, 0x20];
+    identifier.extend_from_slice(&sha256(key_authorization.as_bytes()));
+    let acme_identifier = X509Extension::new_from_der(
+        &Asn1Object::from_str(ACME_IDENTIFIER_OID)?,
+        true,
+        &Asn1OctetString::new_from_bytes(&identifier)?,
+    )?;
+    builder.append_extension(subject_alt_name)?;
+    builder.append_extension(acme_identifier)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+    Ok(AcmeCert {
+        key,
+        chain: vec![builder.build()],
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Problem {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    detail: String,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<ChallengeObject>,
+}
+
+#[derive(Clone, Deserialize)]
+struct ChallengeObject {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    #[serde(default)]
+    token: String,
+    error: Option<Problem>,
+}
+
+fn location(res: &Response) -> Result<String, Error> {
+    res.headers()
+        .get(LOCATION)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_owned())
+        .ok_or_else(|| Error::new(eyre!("ACME provider sent no Location"), ErrorKind::Acme))
+}
+
+/// An RFC 8555 client for a single account
+struct AcmeClient<'a> {
+    http: &'a Client,
+    key: PKey<Private>,
+    /// The account url, once registered
+    kid: Option<String>,
+    directory: Directory,
+    nonce: Option<String>,
+}
+impl<'a> AcmeClient<'a> {
+    async fn new(
+        http: &'a Client,
+        provider: &Url,
+        key: PKey<Private>,
+        kid: Option<String>,
+    ) -> Result<AcmeClient<'a>, Error> {
+        let directory = http
+            .get(provider.clone())
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .error_for_status()
+            .with_kind(ErrorKind::Acme)?
+            .json()
+            .await
+            .with_kind(ErrorKind::Acme)?;
+        Ok(Self {
+            http,
+            key,
+            kid,
+            directory,
+            nonce: None,
+        })
+    }
+
+    async fn nonce(&mut self) -> Result<String, Error> {
+        if let Some(nonce) = self.nonce.take() {
+            return Ok(nonce);
+        }
+        let res = self
+            .http
+            .head(&self.directory.new_nonce)
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?;
+        replay_nonce(&res)
+            .ok_or_else(|| Error::new(eyre!("ACME provider sent no nonce"), ErrorKind::Acme))
+    }
+
+    fn sign(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Vec<u8>, Error> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = jwk(&self.key)?,
+        }
+        let protected = b64(&serde_json::to_vec(&protected).with_kind(ErrorKind::Serialization)?);
+        let payload = match payload {
+            Some(payload) => b64(&serde_json::to_vec(payload).with_kind(ErrorKind::Serialization)?),
+            // POST-as-GET
+            None => String::new(),
+        };
+        let der = Signer::new(MessageDigest::sha256(), &self.key)?
+            .sign_oneshot_to_vec(format!("{}.{}", protected, payload).as_bytes())?;
+        // JWS wants the raw r || s rather than the DER openssl produces
+        let sig = EcdsaSig::from_der(&der)?;
+        let mut signature = sig.r().to_vec_padded(32)?;
+        signature.extend(sig.s().to_vec_padded(32)?);
+        serde_json::to_vec(&json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(&signature),
+        }))
+        .with_kind(ErrorKind::Serialization)
+    }
+
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response, Error> {
+        let mut retried = false;
+        loop {
+            let nonce = self.nonce().await?;
+            let res = self
+                .http
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(self.sign(url, &nonce, payload)?)
+                .send()
+                .await
+                .with_kind(ErrorKind::Network)?;
+            self.nonce = replay_nonce(&res);
+            if res.status().is_success() {
+                return Ok(res);
+            }
+            let status = res.status();
+            let problem: Problem = res.json().await.unwrap_or_default();
+            // nonces expire, and the provider already sent a fresh one
+            if problem.kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            return Err(Error::new(
+                eyre!("ACME provider responded {}: {}", status, problem.detail),
+                ErrorKind::Acme,
+            ));
+        }
+    }
+
+    async fn register(&mut self, email: &str) -> Result<String, Error> {
+        let url = self.directory.new_account.clone();
+        let res = self
+            .post(
+                &url,
+                Some(&json!({
+                    "termsOfServiceAgreed": true,
+                    "contact": [format!("mailto:{}", email)],
+                })),
+            )
+            .await?;
+        let kid = location(&res)?;
+        self.kid = Some(kid.clone());
+        Ok(kid)
+    }
+
+    async fn order(
+        &mut self,
+        acme: &AcmeState,
+        domain: &str,
+        challenge: Challenge,
+        dns: Option<&(String, DnsProvider)>,
+    ) -> Result<AcmeCert, Error> {
+        let url = self.directory.new_order.clone();
+        let res = self
+            .post(
+                &url,
+                Some(&json!({ "identifiers": [{ "type": "dns", "value": domain }] })),
+            )
+            .await?;
+        let order_url = location(&res)?;
+        let mut order: Order = res.json().await.with_kind(ErrorKind::Acme)?;
+        for authorization in &order.authorizations {
+            self.authorize(acme, domain, authorization, challenge, dns)
+                .await?;
+        }
+
+        let key = generate_key()?;
+        let csr = make_csr(&key, domain)?;
+        let finalize = order.finalize.clone();
+        order = self
+            .post(&finalize, Some(&json!({ "csr": b64(&csr) })))
+            .await?
+            .json()
+            .await
+            .with_kind(ErrorKind::Acme)?;
+        for _ in 0..POLL_ATTEMPTS {
+            match order.status.as_str() {
+                "valid" => break,
+                "invalid" => {
+                    return Err(Error::new(
+                        eyre!(
+                            "ACME provider refused to issue a certificate for {}",
+                            domain
+                        ),
+                        ErrorKind::Acme,
+                    ))
+                }
+                _ => (),
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            order = self
+                .post(&order_url, None)
+                .await?
+                .json()
+                .await
+                .with_kind(ErrorKind::Acme)?;
+        }
+        let certificate = order.certificate.ok_or_else(|| {
+            Error::new(
+                eyre!(
+                    "ACME provider did not issue a certificate for {} in time",
+                    domain
+                ),
+                ErrorKind::Acme,
+            )
+        })?;
+        let pem = self
+            .post(&certificate, None)
+            .await?
+            .text()
+            .await
+            .with_kind(ErrorKind::Acme)?;
+        Ok(AcmeCert {
+            key,
+            chain: X509::stack_from_pem(pem.as_bytes())?,
+        })
+    }
+
+    async fn authorize(
+        &mut self,
+        acme: &AcmeState,
+        domain: &str,
+        url: &str,
+        challenge: Challenge,
+        dns: Option<&(String, DnsProvider)>,
+    ) -> Result<(), Error> {
+        let authorization: Authorization = self
+            .post(url, None)
+            .await?
+            .json()
+            .await
+            .with_kind(ErrorKind::Acme)?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let offered = authorization
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == challenge.to_string())
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("ACME provider does not offer {} for {}", challenge, domain),
+                    ErrorKind::Acme,
+                )
+            })?;
+        let key_authorization = format!("{}.{}", offered.token, thumbprint(&self.key)?);
+        // a wildcard is validated at the domain it is for
+        let record = format!("_acme-challenge.{}", domain.trim_start_matches("*."));
+        let digest = b64(&sha256(key_authorization.as_bytes()));
+        let mut published = None;
+        match (challenge, dns) {
+            (Challenge::Http01, _) => {
+                acme.http01
+                    .write()
+                    .await
+                    .insert(offered.token.clone(), key_authorization);
+            }
+            (Challenge::TlsAlpn01, _) => {
+                acme.tls_alpn01.write().await.insert(
+                    domain.to_owned(),
+                    Arc::new(tls_alpn01_cert(domain, &key_authorization)?),
+                );
+            }
+            (Challenge::Dns01, Some((zone, provider))) => {
+                published = Some(provider.publish(self.http, zone, &record, &digest).await?);
+                tokio::time::sleep(provider.propagation()).await;
+            }
+            (Challenge::Dns01, None) => {
+                return Err(Error::new(
+                    eyre!(
+                        "No DNS provider to publish the challenge for {} with",
+                        domain
+                    ),
+                    ErrorKind::Acme,
+                ))
+            }
+        }
+        let res = self.validate(url, &offered.url).await;
+        match (challenge, dns, published) {
+            (Challenge::Http01, _, _) => {
+                acme.http01.write().await.remove(&offered.token);
+            }
+            (Challenge::TlsAlpn01, _, _) => {
+                acme.tls_alpn01.write().await.remove(domain);
+            }
+            (Challenge::Dns01, Some((zone, provider)), Some(published)) => {
+                if let Err(e) = provider
+                    .unpublish(self.http, zone, &record, &digest, &published)
+                    .await
+                {
+                    tracing::warn!(
+                        "Could not remove the challenge record for {}: {}",
+                        domain,
+                        e
+                    );
+                }
+            }
+            (Challenge::Dns01, _, _) => (),
+        }
+        res
+    }
+
+    async fn validate(&mut self, url: &str, challenge_url: &str) -> Result<(), Error> {
+        self.post(challenge_url, Some(&json!({}))).await?;
+        for _ in 0..POLL_ATTEMPTS {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let authorization: Authorization = self
+                .post(url, None)
+                .await?
+                .json()
+                .await
+                .with_kind(ErrorKind::Acme)?;
+            match authorization.status.as_str() {
+                "valid" => return Ok(()),
+                "pending" | "processing" => (),
+                status => {
+                    let detail = authorization
+                        .challenges
+                        .into_iter()
+                        .find_map(|c| c.error)
+                        .map_or_else(|| format!("authorization is {}", status), |e| e.detail);
+                    return Err(Error::new(eyre!("{}", detail), ErrorKind::Acme));
+                }
+            }
+        }
+        Err(Error::new(
+            eyre!("ACME provider did not validate the challenge in time"),
+            ErrorKind::Acme,
+        ))
+    }
+}
+
+fn replay_nonce(res: &Response) -> Option<String> {
+    res.headers()
+        .get("replay-nonce")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_owned())
+}
+
+/// Serves the certificates issued before the last restart, and binds every configured domain
+#[instrument(skip_all)]
+pub async fn load<Db: DbHandle>(
+    net: &NetController,
+    secrets: &PgPool,
+    db: &mut Db,
+) -> Result<(), Error> {
+    let mut certs = net.ssl.acme.certs.write().await;
+    for r in sqlx::query!("SELECT domain, key, fullchain FROM acme_certs")
+        .fetch_all(secrets)
+        .await?
+    {
+        certs.insert(
+            r.domain,
+            Arc::new(AcmeCert {
+                key: PKey::private_key_from_pem(r.key.as_bytes())?,
+                chain: X509::stack_from_pem(r.fullchain.as_bytes())?,
+            }),
+        );
+    }
+    drop(certs);
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(db)
+        .await?
+        .into_owned();
+    for (domain, cfg) in settings.domains {
+        if !domain.starts_with("*.") {
+            net.add_domain(domain, cfg.target).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn issue(ctx: &RpcContext, domain: &str) -> Result<DateTime<Utc>, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let provider = settings.provider.ok_or_else(|| {
+        Error::new(
+            eyre!("No ACME account is registered, run `net acme init` first"),
+            ErrorKind::Acme,
+        )
+    })?;
+    let challenge = settings
+        .domains
+        .get(domain)
+        .ok_or_else(|| Error::new(eyre!("{} is not configured", domain), ErrorKind::NotFound))?
+        .challenge;
+    let account = sqlx::query!(
+        "SELECT key, url FROM acme_accounts WHERE provider = $1",
+        provider.as_str()
+    )
+    .fetch_optional(&ctx.secret_store)
+    .await?
+    .ok_or_else(|| {
+        Error::new(
+            eyre!("No ACME account is registered with {}", provider),
+            ErrorKind::Acme,
+        )
+    })?;
+    let mut client = AcmeClient::new(
+        &ctx.client,
+        &provider,
+        PKey::private_key_from_pem(account.key.as_bytes())?,
+        Some(account.url),
+    )
+    .await?;
+    let dns = match challenge {
+        Challenge::Dns01 => {
+            Some(provider_for(&ctx.secret_store, &settings.dns_providers, domain).await?)
+        }
+        _ => None,
+    };
+    let cert = client
+        .order(
+            &ctx.net_controller.ssl.acme,
+            domain,
+            challenge,
+            dns.as_ref(),
+        )
+        .await?;
+    let leaf = cert.chain.first().ok_or_else(|| {
+        Error::new(
+            eyre!("ACME provider sent an empty certificate chain"),
+            ErrorKind::Acme,
+        )
+    })?;
+    let expires_at = expires_at(leaf)?;
+    let fullchain = cert
+        .chain
+        .iter()
+        .map(|c| c.to_pem())
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+    sqlx::query!(
+        "INSERT INTO acme_certs (domain, key, fullchain, expires_at) VALUES ($1, $2, $3, $4) ON CONFLICT (domain) DO UPDATE SET key = EXCLUDED.key, fullchain = EXCLUDED.fullchain, expires_at = EXCLUDED.expires_at",
+        domain,
+        String::from_utf8(cert.key.private_key_to_pem_pkcs8()?).with_kind(ErrorKind::Utf8)?,
+        String::from_utf8(fullchain).with_kind(ErrorKind::Utf8)?,
+        expires_at.naive_utc()
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    ctx.net_controller
+        .ssl
+        .acme
+        .certs
+        .write()
+        .await
+        .insert(domain.to_owned(), Arc::new(cert));
+    Ok(expires_at)
+}
+
+/// Requests a new certificate for `domain`, and records how that went
+#[instrument(skip_all)]
+pub(super) async fn renew(ctx: &RpcContext, domain: &str) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let res = match settings.wildcard_for(domain) {
+        // served with the certificate of the wildcard, which is renewed on its own
+        Some(wildcard) => settings.domains[wildcard].expires_at.ok_or_else(|| {
+            Error::new(
+                eyre!("{} has no certificate yet", wildcard),
+                ErrorKind::Acme,
+            )
+        }),
+        None => issue(ctx, domain).await,
+    };
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    if let Some(cfg) = settings.domains.get_mut(domain) {
+        match &res {
+            Ok(expires_at) => {
+                cfg.expires_at = Some(*expires_at);
+                cfg.error = None;
+            }
+            Err(e) => cfg.error = Some(e.source.to_string()),
+        }
+    }
+    settings.save(&mut db).await?;
+    res.map(|_| ())
+}
+
+async fn renew_due(ctx: &RpcContext) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let due = Utc::now() + chrono::Duration::days(RENEW_BEFORE_DAYS);
+    // wildcards sort first, so the domains they cover see their new certificate
+    for (domain, cfg) in settings.domains {
+        if cfg.expires_at.map_or(true, |expires_at| expires_at < due) {
+            if let Err(e) = renew(ctx, &domain).await {
+                tracing::error!("Error Renewing Certificate for {}: {}", domain, e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Renews certificates within 30 days of expiring, twice a day until the server shuts down
+pub async fn launch_renewal_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(RENEWAL_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = renew_due(ctx).await {
+                    tracing::error!("Error Renewing ACME Certificates: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[command(subcommands(init, add, remove, renew_cmd, list, super::dns01::dns))]
+pub fn acme() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Registers an account with `--provider`, Let's Encrypt unless given, accepting its terms of
+/// service. `email` is told about certificates that are about to expire.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn init(
+    #[context] ctx: RpcContext,
+    #[arg] email: String,
+    #[arg(long = "provider")] provider: Option<Url>,
+) -> Result<(), Error> {
+    let provider = match provider {
+        Some(provider) => provider,
+        None => LETS_ENCRYPT.parse()?,
+    };
+    let mut client = AcmeClient::new(&ctx.client, &provider, generate_key()?, None).await?;
+    let kid = client.register(&email).await?;
+    sqlx::query!(
+        "INSERT INTO acme_accounts (provider, key, url) VALUES ($1, $2, $3) ON CONFLICT (provider) DO UPDATE SET key = EXCLUDED.key, url = EXCLUDED.url",
+        provider.as_str(),
+        String::from_utf8(client.key.private_key_to_pem_pkcs8()?).with_kind(ErrorKind::Utf8)?,
+        kid
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    settings.provider = Some(provider);
+    settings.email = Some(email);
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Serves `domain` with a certificate from the registered provider, routing it to the StartOS UI,
+/// or to `target`. Request the certificate with `renew` afterwards.
+pub(super) async fn configure(
+    ctx: &RpcContext,
+    domain: &str,
+    challenge: Challenge,
+    target: Option<DomainTarget>,
+) -> Result<(), Error> {
+    let wildcard = domain.starts_with("*.");
+    check_domain(domain.trim_start_matches("*."))?;
+    if wildcard && (challenge != Challenge::Dns01 || target.is_some()) {
+        return Err(Error::new(
+            eyre!("Wildcard certificates need --challenge dns-01, and serve the domains they cover that are added with `net domain add`"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    if settings.domains.contains_key(domain) {
+        return Err(Error::new(
+            eyre!("{} is already configured", domain),
+            ErrorKind::Duplicate,
+        ));
+    }
+    if challenge == Challenge::Dns01 && zone_for(settings.dns_providers.keys(), domain).is_none() {
+        return Err(Error::new(
+            eyre!(
+                "No DNS provider is configured for a zone containing {}, see `net acme dns`",
+                domain
+            ),
+            ErrorKind::NotFound,
+        ));
+    }
+    settings.domains.insert(
+        domain.to_owned(),
+        AcmeDomain {
+            challenge,
+            target: target.clone(),
+            expires_at: None,
+            error: None,
+        },
+    );
+    settings.save(&mut db).await?;
+    if wildcard {
+        // only ever served for the domains it covers
+        return Ok(());
+    }
+    ctx.net_controller
+        .add_domain(domain.to_owned(), target)
+        .await
+}
+
+/// Stops serving `domain` and forgets its certificate, returning what it was routed to
+pub(super) async fn unconfigure(
+    ctx: &RpcContext,
+    domain: &str,
+) -> Result<Option<DomainTarget>, Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    let removed = settings
+        .domains
+        .remove(domain)
+        .ok_or_else(|| Error::new(eyre!("{} is not configured", domain), ErrorKind::NotFound))?;
+    sqlx::query!("DELETE FROM acme_certs WHERE domain = $1", domain)
+        .execute(&ctx.secret_store)
+        .await?;
+    ctx.net_controller
+        .ssl
+        .acme
+        .certs
+        .write()
+        .await
+        .remove(domain);
+    ctx.net_controller.remove_domain(domain).await?;
+    settings.save(&mut db).await?;
+    Ok(removed.target)
+}
+
+/// Requests a certificate for `domain` and serves the StartOS UI at it. `domain` must resolve to
+/// this server, with port 80 forwarded to it for http-01 or port 443 for tls-alpn-01, or be in a
+/// zone given to `net acme dns` for dns-01. The certificate is renewed 30 days before it expires.
+/// See `net domain` to serve a package at a domain instead. A wildcard like `*.example.com` is
+/// only issued, with dns-01, and then serves the domains it covers without a certificate of
+/// their own.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] domain: String,
+    #[arg(long = "challenge")] challenge: Option<Challenge>,
+) -> Result<(), Error> {
+    let domain = domain.to_lowercase();
+    configure(&ctx, &domain, challenge.unwrap_or_default(), None).await?;
+    renew(&ctx, &domain).await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] domain: String) -> Result<(), Error> {
+    let domain = domain.to_lowercase();
+    if let Some(target) = unconfigure(&ctx, &domain).await? {
+        super::domain::forget_address(&ctx, &domain, &target).await?;
+    }
+    Ok(())
+}
+
+/// Requests new certificates now for `domain`, or every configured domain
+#[command(
+    rename = "renew",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn renew_cmd(
+    #[context] ctx: RpcContext,
+    #[arg] domain: Option<String>,
+) -> Result<(), Error> {
+    let domains = match domain {
+        Some(domain) => vec![domain.to_lowercase()],
+        None => crate::db::DatabaseModel::new()
+            .server_info()
+            .acme()
+            .get(&mut ctx.db.handle())
+            .await?
+            .into_owned()
+            .domains
+            .into_keys()
+            .collect(),
+    };
+    for domain in domains {
+        renew(&ctx, &domain).await?;
+    }
+    Ok(())
+}
+
+fn display_domains(arg: AcmeSettings, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "DOMAIN", "SERVES", "CHALLENGE", "EXPIRES", "ERROR"]);
+    for (domain, cfg) in &arg.domains {
+        table.add_row(row![
+            domain,
+            cfg.target.as_ref().map_or_else(
+                || "StartOS".to_owned(),
+                |t| format!("{}/{}", t.package, t.interface)
+            ),
+            cfg.challenge.to_string(),
+            cfg.expires_at
+                .map_or_else(|| "N/A".to_owned(), |e| e.to_rfc3339()),
+            cfg.error.as_deref().unwrap_or_default(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_domains), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<AcmeSettings, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn challenge_cert() {
+    let cert = tls_alpn01_cert("example.com", "token.thumbprint").unwrap();
+    let leaf = &cert.chain[0];
+    assert!(leaf
+        .subject_alt_names()
+        .into_iter()
+        .flatten()
+        .any(|n| n.dnsname() == Some("example.com")));
+    assert!(leaf.public_key().unwrap().public_eq(&cert.key));
+    assert_eq!(thumbprint(&cert.key).unwrap().len(), 43);
+}
+
+#[test]
+fn wildcards() {
+    let mut settings = AcmeSettings::default();
+    settings.domains.insert(
+        "*.example.com".to_owned(),
+        AcmeDomain {
+            challenge: Challenge::Dns01,
+            target: None,
+            expires_at: None,
+            error: None,
+        },
+    );
+    assert_eq!(
+        settings.wildcard_for("cloud.example.com"),
+        Some("*.example.com")
+    );
+    assert_eq!(settings.wildcard_for("a.cloud.example.com"), None);
+    assert_eq!(settings.wildcard_for("example.com"), None);
+    assert_eq!(settings.wildcard_for("*.example.com"), None);
+}
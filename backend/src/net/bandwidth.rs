@@ -0,0 +1,525 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use chrono::{DurationRound, NaiveDateTime, Utc};
+use clap::ArgMatches;
+use models::{Id, InterfaceId};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::process::Command;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::shutdown::Shutdown;
+use crate::system::MebiBytes;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Invoke;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Counts what containers send and receive, including through the vhost and raw forwards
+const TABLE: &str = "startos-bandwidth";
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+/// How long hourly usage is kept
+const RETENTION_DAYS: i64 = 365;
+/// The period of the usage in the server metrics, like the billing period of most plans
+const METRICS_DAYS: i64 = 30;
+
+/// Bytes through an interface, from the side of the package
+#[derive(Default)]
+pub struct Traffic {
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+impl Traffic {
+    pub(super) fn receive(&self, bytes: usize) {
+        self.received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub(super) fn send(&self, bytes: usize) {
+        self.sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    fn take(&self) -> Usage {
+        Usage {
+            received: self.received.swap(0, Ordering::Relaxed),
+            sent: self.sent.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+/// Counts what a client sends to an interface and receives from it
+#[pin_project::pin_project]
+pub struct CountingStream<S> {
+    #[pin]
+    stream: S,
+    traffic: Option<Arc<Traffic>>,
+}
+impl<S> CountingStream<S> {
+    pub fn new(stream: S, traffic: Option<Arc<Traffic>>) -> Self {
+        Self { stream, traffic }
+    }
+}
+impl<S: AsyncRead> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let start = buf.filled().len();
+        let res = this.stream.poll_read(cx, buf);
+        if let Some(traffic) = this.traffic {
+            traffic.receive(buf.filled().len() - start);
+        }
+        res
+    }
+}
+impl<S: AsyncWrite> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.project();
+        let res = this.stream.poll_write(cx, buf);
+        if let (Some(traffic), Poll::Ready(Ok(len))) = (this.traffic, &res) {
+            traffic.send(*len);
+        }
+        res
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().stream.poll_flush(cx)
+    }
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().stream.poll_shutdown(cx)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Usage {
+    pub received: u64,
+    pub sent: u64,
+}
+impl Usage {
+    fn is_empty(&self) -> bool {
+        self.received == 0 && self.sent == 0
+    }
+}
+
+#[derive(Default)]
+struct Containers {
+    ips: BTreeMap<PackageId, Ipv4Addr>,
+    /// The counters as of the last sample, since nftables counts from when the table was made
+    last: BTreeMap<String, u64>,
+    /// What the counters of a replaced table had since the last sample
+    pending: BTreeMap<PackageId, Usage>,
+    /// Whether the table was made, which only happens once a container is counted
+    built: bool,
+}
+
+/// Keeps the counters of the containers and the interfaces of packages
+#[derive(Default)]
+pub struct BandwidthMonitor {
+    interfaces: std::sync::Mutex<BTreeMap<(PackageId, InterfaceId), Arc<Traffic>>>,
+    containers: Mutex<Containers>,
+}
+impl BandwidthMonitor {
+    pub(super) fn traffic(&self, interface: (PackageId, InterfaceId)) -> Arc<Traffic> {
+        self.interfaces
+            .lock()
+            .unwrap()
+            .entry(interface)
+            .or_default()
+            .clone()
+    }
+    /// Counts what the container of `package` sends and receives at `ip`
+    pub(super) async fn add_container(
+        &self,
+        package: PackageId,
+        ip: Ipv4Addr,
+    ) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.get(&package) == Some(&ip) {
+            return Ok(());
+        }
+        containers.ips.insert(package, ip);
+        self.rebuild(&mut containers).await
+    }
+    pub(super) async fn remove_container(&self, package: &PackageId) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.remove(package).is_none() {
+            return Ok(());
+        }
+        self.rebuild(&mut containers).await
+    }
+    /// Replaces the table, keeping what it counted since the last sample for the next one
+    async fn rebuild(&self, containers: &mut Containers) -> Result<(), Error> {
+        if containers.built {
+            if let Ok(usage) = read_counters(&mut containers.last).await {
+                for (package, usage) in usage {
+                    let pending = containers.pending.entry(package).or_default();
+                    pending.received += usage.received;
+                    pending.sent += usage.sent;
+                }
+            }
+        }
+        containers.last.clear();
+        nft(&ruleset(&containers.ips)).await?;
+        containers.built = true;
+        Ok(())
+    }
+    /// What each container and interface used since the last sample. The container as a whole
+    /// is under the interface with an empty id.
+    async fn sample(&self) -> Result<BTreeMap<(PackageId, String), Usage>, Error> {
+        let mut res = BTreeMap::new();
+        for ((package, interface), traffic) in self.interfaces.lock().unwrap().iter() {
+            let usage = traffic.take();
+            if !usage.is_empty() {
+                res.insert((package.clone(), interface.as_str().to_owned()), usage);
+            }
+        }
+        let mut containers = self.containers.lock().await;
+        let mut usage = std::mem::take(&mut containers.pending);
+        // nothing was counted before the table is made
+        if containers.built {
+            match read_counters(&mut containers.last).await {
+                Ok(counted) => {
+                    for (package, counted) in counted {
+                        let total = usage.entry(package).or_default();
+                        total.received += counted.received;
+                        total.sent += counted.sent;
+                    }
+                }
+                // counted with the next sample instead
+                Err(e) => {
+                    tracing::error!("Error Reading Container Bandwidth: {e}");
+                    tracing::debug!("{e:?}");
+                }
+            }
+        }
+        for (package, usage) in usage {
+            let total = res.entry((package, String::new())).or_default();
+            total.received += usage.received;
+            total.sent += usage.sent;
+        }
+        Ok(res)
+    }
+}
+
+/// Counts by the address of each container: what arrives from it is sent, and what leaves to it
+/// is received
+fn ruleset(ips: &BTreeMap<PackageId, Ipv4Addr>) -> String {
+    let mut counters = String::new();
+    let mut prerouting = String::new();
+    let mut postrouting = String::new();
+    for (package, ip) in ips {
+        counters += &format!(
+            "\tcounter \"sent-{0}\" {{}}\n\tcounter \"received-{0}\" {{}}\n",
+            package
+        );
+        prerouting += &format!("\t\tip saddr {} counter name \"sent-{}\"\n", ip, package);
+        postrouting += &format!(
+            "\t\tip daddr {} counter name \"received-{}\"\n",
+            ip, package
+        );
+    }
+    format!(
+        "table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n{counters}\tchain prerouting {{\n\t\ttype filter hook prerouting priority raw; policy accept;\n{prerouting}\t}}\n\tchain postrouting {{\n\t\ttype filter hook postrouting priority filter; policy accept;\n{postrouting}\t}}\n}}\n",
+        table = TABLE,
+        counters = counters,
+        prerouting = prerouting,
+        postrouting = postrouting,
+    )
+}
+
+async fn nft(script: &str) -> Result<(), Error> {
+    let mut cmd = Command::new("nft")
+        .arg("-f")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = cmd.stdin.take() {
+        stdin.write_all(script.as_bytes()).await?;
+    }
+    let res = cmd.wait_with_output().await?;
+    crate::ensure_code!(
+        res.status.success(),
+        ErrorKind::Network,
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    Ok(())
+}
+
+/// The counters of `nft --json list counters`, by name
+fn parse_counters(output: &str) -> Result<BTreeMap<String, u64>, Error> {
+    #[derive(Deserialize)]
+    struct Output {
+        nftables: Vec<Entry>,
+    }
+    #[derive(Deserialize)]
+    struct Entry {
+        counter: Option<Counter>,
+    }
+    #[derive(Deserialize)]
+    struct Counter {
+        name: String,
+        table: String,
+        bytes: u64,
+    }
+    Ok(serde_json::from_str::<Output>(output)
+        .with_kind(ErrorKind::Deserialization)?
+        .nftables
+        .into_iter()
+        .filter_map(|e| e.counter)
+        .filter(|c| c.table == TABLE)
+        .map(|c| (c.name, c.bytes))
+        .collect())
+}
+
+/// What the containers used since `last`, which becomes the current counters
+async fn read_counters(
+    last: &mut BTreeMap<String, u64>,
+) -> Result<BTreeMap<PackageId, Usage>, Error> {
+    let counters = parse_counters(&String::from_utf8(
+        Command::new("nft")
+            .arg("--json")
+            .arg("list")
+            .arg("counters")
+            .arg("table")
+            .arg("inet")
+            .arg(TABLE)
+            .invoke(ErrorKind::Network)
+            .await?,
+    )?)?;
+    let mut res: BTreeMap<PackageId, Usage> = BTreeMap::new();
+    for (name, bytes) in &counters {
+        let delta = bytes.saturating_sub(last.get(name).copied().unwrap_or(0));
+        if let Some(package) = name.strip_prefix("sent-") {
+            res.entry(package.parse()?).or_default().sent += delta;
+        } else if let Some(package) = name.strip_prefix("received-") {
+            res.entry(package.parse()?).or_default().received += delta;
+        }
+    }
+    *last = counters;
+    Ok(res)
+}
+
+fn this_hour() -> NaiveDateTime {
+    let now = Utc::now();
+    now.duration_trunc(chrono::Duration::hours(1))
+        .unwrap_or(now)
+        .naive_utc()
+}
+
+/// Adds what was used since the last sample to the usage of this hour
+async fn record(ctx: &RpcContext) -> Result<(), Error> {
+    let hour = this_hour();
+    for ((package, interface), usage) in ctx.net_controller.bandwidth.sample().await? {
+        sqlx::query!(
+            "INSERT INTO bandwidth_usage (package, interface, hour, received, sent) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (package, interface, hour) DO UPDATE SET received = bandwidth_usage.received + EXCLUDED.received, sent = bandwidth_usage.sent + EXCLUDED.sent",
+            *package,
+            interface,
+            hour,
+            usage.received as i64,
+            usage.sent as i64,
+        )
+        .execute(&ctx.secret_store)
+        .await?;
+    }
+    let expired = hour - chrono::Duration::days(RETENTION_DAYS);
+    sqlx::query!("DELETE FROM bandwidth_usage WHERE hour < $1", expired)
+        .execute(&ctx.secret_store)
+        .await?;
+    let since = hour - chrono::Duration::days(METRICS_DAYS);
+    let mut metrics = BTreeMap::new();
+    for (package, usage) in totals(&ctx.secret_store, since).await? {
+        metrics.insert(
+            format!("{} Received", package),
+            MebiBytes(usage.total.received as f64 / 1024.0 / 1024.0),
+        );
+        metrics.insert(
+            format!("{} Sent", package),
+            MebiBytes(usage.total.sent as f64 / 1024.0 / 1024.0),
+        );
+    }
+    if let Some(cache) = ctx.metrics_cache.write().await.as_mut() {
+        cache.bandwidth = metrics;
+    }
+    Ok(())
+}
+
+pub async fn launch_bandwidth_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = record(ctx).await {
+                    tracing::error!("Error Recording Bandwidth Usage: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+/// Forgets the usage of `id` when it is uninstalled
+pub async fn remove_usage<Ex>(secrets: &mut Ex, id: &PackageId) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let id_str = id.as_str();
+    sqlx::query!("DELETE FROM bandwidth_usage WHERE package = $1", id_str)
+        .execute(secrets)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PackageUsage {
+    /// Everything the container sent and received
+    pub total: Usage,
+    /// What clients of each interface sent and received through this server
+    pub interfaces: BTreeMap<InterfaceId, Usage>,
+    /// The total of each hour, when asked for one package
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub hourly: Vec<(NaiveDateTime, Usage)>,
+}
+
+async fn totals(
+    secrets: &PgPool,
+    since: NaiveDateTime,
+) -> Result<BTreeMap<PackageId, PackageUsage>, Error> {
+    let mut res: BTreeMap<PackageId, PackageUsage> = BTreeMap::new();
+    for row in sqlx::query!(
+        r#"SELECT package, interface, SUM(received)::BIGINT AS "received!", SUM(sent)::BIGINT AS "sent!" FROM bandwidth_usage WHERE hour >= $1 GROUP BY package, interface"#,
+        since
+    )
+    .fetch_all(secrets)
+    .await?
+    {
+        let usage = Usage {
+            received: row.received as u64,
+            sent: row.sent as u64,
+        };
+        let package = res.entry(row.package.parse()?).or_default();
+        if row.interface.is_empty() {
+            package.total = usage;
+        } else {
+            package
+                .interfaces
+                .insert(InterfaceId::from(Id::try_from(row.interface)?), usage);
+        }
+    }
+    Ok(res)
+}
+
+fn display_usage(arg: BTreeMap<PackageId, PackageUsage>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    fn mib(bytes: u64) -> String {
+        format!("{:.2} MiB", bytes as f64 / 1024.0 / 1024.0)
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE", "RECEIVED", "SENT"]);
+    for (package, usage) in &arg {
+        table.add_row(row![
+            &**package,
+            "*",
+            mib(usage.total.received),
+            mib(usage.total.sent)
+        ]);
+        for (interface, usage) in &usage.interfaces {
+            table.add_row(row![
+                &**package,
+                &**interface,
+                mib(usage.received),
+                mib(usage.sent)
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+    for usage in arg.values().filter(|u| !u.hourly.is_empty()) {
+        println!();
+        let mut table = Table::new();
+        table.add_row(row![bc => "HOUR (UTC)", "RECEIVED", "SENT"]);
+        for (hour, usage) in &usage.hourly {
+            table.add_row(row![hour, mib(usage.received), mib(usage.sent)]);
+        }
+        table.print_tty(false).unwrap();
+    }
+}
+
+/// What each package sent and received over the last `--days`, 30 unless given, by hour for
+/// `--package`. Everything the container does counts, including traffic within this server
+/// and over Tor.
+#[command(display(display_usage), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn bandwidth(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+    #[arg(long = "package")] package: Option<PackageId>,
+    #[arg(long = "days")] days: Option<u32>,
+) -> Result<BTreeMap<PackageId, PackageUsage>, Error> {
+    record(&ctx).await?;
+    let since = this_hour() - chrono::Duration::days(days.unwrap_or(METRICS_DAYS as u32) as i64);
+    let mut res = totals(&ctx.secret_store, since).await?;
+    if let Some(package) = package {
+        let mut usage = res.remove(&package).unwrap_or_default();
+        usage.hourly = sqlx::query!(
+            "SELECT hour, received, sent FROM bandwidth_usage WHERE package = $1 AND interface = '' AND hour >= $2 ORDER BY hour",
+            *package,
+            since
+        )
+        .fetch_all(&ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|row| {
+            (
+                row.hour,
+                Usage {
+                    received: row.received as u64,
+                    sent: row.sent as u64,
+                },
+            )
+        })
+        .collect();
+        res = [(package, usage)].into();
+    }
+    Ok(res)
+}
+
+#[test]
+fn counters() {
+    let ips = [("bitcoind".parse().unwrap(), Ipv4Addr::new(172, 18, 0, 2))].into();
+    let script = ruleset(&ips);
+    assert!(script.contains("ip saddr 172.18.0.2 counter name \"sent-bitcoind\""));
+    assert!(script.contains("ip daddr 172.18.0.2 counter name \"received-bitcoind\""));
+    let counters = parse_counters(
+        r#"{"nftables": [{"metainfo": {"version": "1.0.6", "json_schema_version": 1}}, {"counter": {"family": "inet", "name": "sent-bitcoind", "table": "startos-bandwidth", "handle": 1, "packets": 3, "bytes": 1500}}, {"counter": {"family": "inet", "name": "other", "table": "filter", "handle": 2, "packets": 1, "bytes": 60}}]}"#,
+    )
+    .unwrap();
+    assert_eq!(counters.len(), 1);
+    assert_eq!(counters["sent-bitcoind"], 1500);
+}
@@ -0,0 +1,506 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use itertools::Itertools;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509VerifyResult, X509};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::process::Command;
+use tracing::instrument;
+
+use crate::account::AccountInfo;
+use crate::context::RpcContext;
+use crate::hostname::Hostname;
+use crate::net::keys::Key;
+use crate::net::net_controller::NetController;
+use crate::net::ssl::{generate_key, make_leaf_cert, make_root_cert, SANInfo};
+use crate::status::MainStatus;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind};
+
+const TRUSTED_ROOT_PATH: &str = "/usr/local/share/ca-certificates/startos-root-ca.crt";
+
+/// How `net ca export` encodes the root CA
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaEncoding {
+    #[default]
+    Pem,
+    /// In base64 over RPC, written as is by the CLI
+    Der,
+    /// A configuration profile installing the root CA on Apple devices
+    Mobileconfig,
+}
+impl fmt::Display for CaEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Pem => write!(f, "pem"),
+            Self::Der => write!(f, "der"),
+            Self::Mobileconfig => write!(f, "mobileconfig"),
+        }
+    }
+}
+impl FromStr for CaEncoding {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pem" | "crt" => Ok(Self::Pem),
+            "der" | "cer" => Ok(Self::Der),
+            "mobileconfig" => Ok(Self::Mobileconfig),
+            _ => Err(Error::new(
+                eyre!("Unknown encoding {}: expected pem, der or mobileconfig", s),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+
+/// The SHA-256 fingerprint of `cert`, as shown in the UI
+pub fn fingerprint(cert: &X509) -> Result<String, Error> {
+    Ok(cert
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|x| format!("{x:X}"))
+        .join(":"))
+}
+
+fn common_name(cert: &X509) -> Option<String> {
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .next()
+        .and_then(|e| e.data().as_utf8().ok())
+        .map(|n| n.to_string())
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A configuration profile with a single root certificate payload. Its identifiers are derived
+/// from the certificate, so that installing it again replaces the profile rather than adding one.
+pub fn mobileconfig(cert: &X509) -> Result<String, Error> {
+    let der = cert.to_der()?;
+    let digest = cert.digest(MessageDigest::sha256())?;
+    let uuid = |bytes: &[u8]| {
+        uuid::Builder::from_random_bytes(bytes.try_into().unwrap())
+            .into_uuid()
+            .to_string()
+            .to_uppercase()
+    };
+    let profile_uuid = uuid(&digest[..16]);
+    let payload_uuid = uuid(&digest[16..]);
+    let name = xml_escape(&common_name(cert).unwrap_or_else(|| "StartOS Root CA".to_owned()));
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>PayloadContent</key>
+	<array>
+		<dict>
+			<key>PayloadCertificateFileName</key>
+			<string>startos-root-ca.crt</string>
+			<key>PayloadContent</key>
+			<data>{data}</data>
+			<key>PayloadDisplayName</key>
+			<string>{name}</string>
+			<key>PayloadIdentifier</key>
+			<string>com.start9.startos.root-ca.{payload_uuid}</string>
+			<key>PayloadType</key>
+			<string>com.apple.security.root</string>
+			<key>PayloadUUID</key>
+			<string>{payload_uuid}</string>
+			<key>PayloadVersion</key>
+			<integer>1</integer>
+		</dict>
+	</array>
+	<key>PayloadDisplayName</key>
+	<string>{name}</string>
+	<key>PayloadIdentifier</key>
+	<string>com.start9.startos.{profile_uuid}</string>
+	<key>PayloadType</key>
+	<string>Configuration</string>
+	<key>PayloadUUID</key>
+	<string>{profile_uuid}</string>
+	<key>PayloadVersion</key>
+	<integer>1</integer>
+</dict>
+</plist>
+"#,
+        data = base64::encode(der),
+    ))
+}
+
+/// Checks that `key` can sign the certificates of this server as `chain[0]`: the key must match
+/// it, and `chain` must go up to a self-signed root CA, each certificate signed by the next. A
+/// certificate is signed with it and verified, so that an expired CA, or one that is not allowed
+/// to sign certificates for this server, is refused as well.
+fn validate(key: &PKey<Private>, chain: &[X509], hostname: &Hostname) -> Result<(), Error> {
+    let (cert, root) = match (chain.first(), chain.last()) {
+        (Some(cert), Some(root)) => (cert, root),
+        _ => {
+            return Err(Error::new(
+                eyre!("No certificate was given"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    if !cert.public_key()?.public_eq(key) {
+        return Err(Error::new(
+            eyre!("The key does not belong to the first certificate"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    for (cert, issuer) in chain.iter().tuple_windows() {
+        if issuer.issued(cert) != X509VerifyResult::OK || !cert.verify(&*issuer.public_key()?)? {
+            return Err(Error::new(
+                eyre!(
+                    "{} is not signed by {}",
+                    common_name(cert).as_deref().unwrap_or("A certificate"),
+                    common_name(issuer).as_deref().unwrap_or("the next one")
+                ),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+    }
+    if root.issued(root) != X509VerifyResult::OK {
+        return Err(Error::new(
+            eyre!("The last certificate must be the self-signed root CA"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut store = X509StoreBuilder::new()?;
+    store.add_cert(root.clone())?;
+    let store = store.build();
+    let mut intermediates = Stack::new()?;
+    for cert in chain {
+        intermediates.push(cert.clone())?;
+    }
+    let leaf_key = generate_key()?;
+    let leaf = make_leaf_cert(
+        (key, cert),
+        (
+            &leaf_key,
+            &SANInfo::new(&Key::new(None), hostname, BTreeSet::new()),
+        ),
+    )?;
+    let mut context = X509StoreContext::new()?;
+    if let Some(e) = context.init(&store, &leaf, &intermediates, |c| {
+        Ok((!c.verify_cert()?).then(|| c.error()))
+    })? {
+        return Err(Error::new(
+            eyre!("The CA cannot sign certificates for this server: {}", e),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(())
+}
+
+/// The CA imported with `net ca import`, and the certificates above it
+async fn imported(secrets: &PgPool) -> Result<Option<(PKey<Private>, Vec<X509>)>, Error> {
+    sqlx::query!("SELECT key_pem, chain_pem FROM imported_ca WHERE id = 0")
+        .fetch_optional(secrets)
+        .await?
+        .map(|r| {
+            Ok((
+                PKey::private_key_from_pem(r.key_pem.as_bytes())?,
+                X509::stack_from_pem(r.chain_pem.as_bytes())?,
+            ))
+        })
+        .transpose()
+}
+
+async fn set_imported(
+    net: &NetController,
+    key: PKey<Private>,
+    chain: Vec<X509>,
+) -> Result<(), Error> {
+    let mut chain = chain.into_iter();
+    let cert = chain.next().ok_or_else(|| {
+        Error::new(
+            eyre!("The imported CA has no certificate"),
+            ErrorKind::OpenSsl,
+        )
+    })?;
+    net.ssl.set_signer(key, cert, chain.collect()).await;
+    Ok(())
+}
+
+/// Signs the certificates with the CA imported with `net ca import` again after a restart
+pub async fn load(net: &NetController, secrets: &PgPool) -> Result<(), Error> {
+    if let Some((key, chain)) = imported(secrets).await? {
+        set_imported(net, key, chain).await?;
+    }
+    Ok(())
+}
+
+/// The root CA the certificates of this server chain up to
+pub async fn root_ca(secrets: &PgPool, account: &AccountInfo) -> Result<X509, Error> {
+    Ok(imported(secrets)
+        .await?
+        .and_then(|(_, mut chain)| chain.pop())
+        .unwrap_or_else(|| account.root_ca_cert.clone()))
+}
+
+/// Adds `root` to the CA store of the OS, in place of the one there
+pub async fn trust(root: &X509) -> Result<(), Error> {
+    tokio::fs::write(TRUSTED_ROOT_PATH, root.to_pem()?).await?;
+    Command::new("update-ca-certificates")
+        .invoke(ErrorKind::OpenSsl)
+        .await?;
+    Ok(())
+}
+
+/// Trusts the root CA in use, shows its fingerprint, and restarts the packages that are running
+/// for them to be given certificates signed by the new CA: the ones they were given before are
+/// not trusted by the reverse proxy anymore.
+async fn apply(ctx: &RpcContext) -> Result<(), Error> {
+    let root = ctx.net_controller.ssl.root_ca().await;
+    trust(&root).await?;
+    let mut db = ctx.db.handle();
+    crate::db::DatabaseModel::new()
+        .server_info()
+        .ca_fingerprint()
+        .put(&mut db, &fingerprint(&root)?)
+        .await?;
+    for package in crate::db::DatabaseModel::new()
+        .package_data()
+        .keys(&mut db)
+        .await?
+    {
+        let status = crate::db::DatabaseModel::new()
+            .package_data()
+            .idx_model(&package)
+            .and_then(|p| p.installed())
+            .map(|i| i.status().main())
+            .get(&mut db)
+            .await?
+            .into_owned();
+        if matches!(status, Some(MainStatus::Running { .. })) {
+            crate::control::restart(ctx.clone(), package).await?;
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(export, info, regenerate, import, remove))]
+pub fn ca() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_export(arg: String, matches: &ArgMatches) {
+    if matches.value_of("encoding") == Some("der") {
+        std::io::stdout()
+            .write_all(&base64::decode(arg).unwrap())
+            .unwrap();
+    } else {
+        print!("{}", arg);
+    }
+}
+
+/// The root CA clients must trust to reach this server over HTTPS at its `.local` and onion
+/// addresses, in PEM by default
+#[command(display(display_export), metadata(read_only = true))]
+pub async fn export(
+    #[context] ctx: RpcContext,
+    #[arg(long = "encoding")] encoding: Option<CaEncoding>,
+) -> Result<String, Error> {
+    let root = ctx.net_controller.ssl.root_ca().await;
+    Ok(match encoding.unwrap_or_default() {
+        CaEncoding::Pem => String::from_utf8(root.to_pem()?)?,
+        CaEncoding::Der => base64::encode(root.to_der()?),
+        CaEncoding::Mobileconfig => mobileconfig(&root)?,
+    })
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CertInfo {
+    pub subject: String,
+    pub fingerprint: String,
+    pub not_after: String,
+}
+impl CertInfo {
+    fn new(cert: &X509) -> Result<Self, Error> {
+        Ok(Self {
+            subject: cert
+                .subject_name()
+                .entries()
+                .filter_map(|e| {
+                    Some(format!(
+                        "{}={}",
+                        e.object().nid().short_name().ok()?,
+                        e.data().as_utf8().ok()?
+                    ))
+                })
+                .join(", "),
+            fingerprint: fingerprint(cert)?,
+            not_after: cert.not_after().to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CaInfo {
+    /// Whether the certificates are signed by the CA of `net ca import`, rather than by an
+    /// intermediate CA of the root CA of this server
+    pub imported: bool,
+    /// The CA that signs the certificates, followed by the ones above it up to the root CA
+    pub chain: Vec<CertInfo>,
+}
+
+fn display_info(arg: CaInfo, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "SUBJECT", "FINGERPRINT", "EXPIRES"]);
+    for cert in &arg.chain {
+        table.add_row(row![&cert.subject, &cert.fingerprint, &cert.not_after]);
+    }
+    table.print_tty(false).unwrap();
+    if arg.imported {
+        println!("Signed by an imported CA");
+    }
+}
+
+#[command(display(display_info), metadata(read_only = true))]
+pub async fn info(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<CaInfo, Error> {
+    Ok(CaInfo {
+        imported: imported(&ctx.secret_store).await?.is_some(),
+        chain: ctx
+            .net_controller
+            .ssl
+            .chain()
+            .await
+            .iter()
+            .map(CertInfo::new)
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+/// Replaces the root CA of this server with a new one, which clients must trust instead. While a
+/// CA imported with `net ca import` is in use, the new root CA is only used once it is removed.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn regenerate(#[context] ctx: RpcContext) -> Result<(), Error> {
+    let is_imported = imported(&ctx.secret_store).await?.is_some();
+    let mut account = ctx.account.write().await;
+    account.root_ca_key = generate_key()?;
+    account.root_ca_cert = make_root_cert(&account.root_ca_key, &account.hostname)?;
+    account.save(&ctx.secret_store).await?;
+    if is_imported {
+        return Ok(());
+    }
+    ctx.net_controller
+        .ssl
+        .set_root((&account.root_ca_key, &account.root_ca_cert))
+        .await?;
+    drop(account);
+    apply(&ctx).await
+}
+
+fn parse_pem(stdin: &mut std::io::Stdin, _: &ArgMatches) -> Result<String, Error> {
+    let mut pem = String::new();
+    stdin.read_to_string(&mut pem)?;
+    Ok(pem)
+}
+
+/// Signs the certificates with a CA of an existing PKI, rather than an intermediate CA of the
+/// root CA of this server. Takes on stdin the certificate of the CA, followed by the ones above
+/// it up to the root CA, and its unencrypted private key, in PEM.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn import(
+    #[context] ctx: RpcContext,
+    #[arg(stdin, parse(parse_pem))] pem: String,
+) -> Result<(), Error> {
+    let key = PKey::private_key_from_pem(pem.as_bytes()).map_err(|_| {
+        Error::new(
+            eyre!("No unencrypted private key was given"),
+            ErrorKind::InvalidRequest,
+        )
+    })?;
+    let chain = X509::stack_from_pem(pem.as_bytes())?;
+    validate(&key, &chain, &ctx.account.read().await.hostname)?;
+    let key_pem = String::from_utf8(key.private_key_to_pem_pkcs8()?)?;
+    let chain_pem = String::from_utf8(
+        chain
+            .iter()
+            .map(|c| c.to_pem())
+            .flatten_ok()
+            .collect::<Result<Vec<u8>, _>>()?,
+    )?;
+    sqlx::query!(
+        "INSERT INTO imported_ca (id, key_pem, chain_pem) VALUES (0, $1, $2) ON CONFLICT (id) DO UPDATE SET key_pem = EXCLUDED.key_pem, chain_pem = EXCLUDED.chain_pem",
+        key_pem,
+        chain_pem,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    set_imported(&ctx.net_controller, key, chain).await?;
+    apply(&ctx).await
+}
+
+/// Signs the certificates with an intermediate CA of the root CA of this server again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    if imported(&ctx.secret_store).await?.is_none() {
+        return Err(Error::new(eyre!("No CA was imported"), ErrorKind::NotFound));
+    }
+    sqlx::query!("DELETE FROM imported_ca WHERE id = 0")
+        .execute(&ctx.secret_store)
+        .await?;
+    let account = ctx.account.read().await;
+    ctx.net_controller
+        .ssl
+        .set_root((&account.root_ca_key, &account.root_ca_cert))
+        .await?;
+    drop(account);
+    apply(&ctx).await
+}
+
+#[test]
+fn ca_chains() {
+    use crate::net::ssl::make_int_cert;
+
+    let hostname = Hostname("adjective-noun".to_owned());
+    let root_key = generate_key().unwrap();
+    let root = make_root_cert(&root_key, &hostname).unwrap();
+    let int_key = generate_key().unwrap();
+    let int = make_int_cert((&root_key, &root), &int_key).unwrap();
+    validate(&int_key, &[int.clone(), root.clone()], &hostname).unwrap();
+    validate(&root_key, &[root.clone()], &hostname).unwrap();
+    assert!(validate(&root_key, &[int.clone(), root.clone()], &hostname).is_err());
+    assert!(validate(&int_key, &[int.clone()], &hostname).is_err());
+    assert!(validate(&int_key, &[root.clone(), int], &hostname).is_err());
+
+    let profile = mobileconfig(&root).unwrap();
+    assert!(profile.contains("<string>com.apple.security.root</string>"));
+    assert!(profile.contains(&base64::encode(root.to_der().unwrap())));
+    assert_eq!(profile, mobileconfig(&root).unwrap());
+}
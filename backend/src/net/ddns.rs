@@ -0,0 +1,558 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use reqwest::{Client, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::Receiver;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::dns01::{cloudflare, CLOUDFLARE_API};
+use crate::net::domain::check_domain;
+use crate::notifications::NotificationLevel;
+use crate::shutdown::Shutdown;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const DUCKDNS_API: &str = "https://www.duckdns.org/update";
+const DUCKDNS_SUFFIX: &str = ".duckdns.org";
+/// Answer with the address the request came from, as plain text
+pub(super) const IPV4_LOOKUP: &str = "https://api.ipify.org";
+const IPV6_LOOKUP: &str = "https://api6.ipify.org";
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(15);
+const DDNS_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RECORD_TTL: u32 = 60;
+
+/// How a hostname is pointed at this server, with its credentials. Kept in the secret store.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum DdnsProvider {
+    #[serde(rename = "duckdns")]
+    DuckDns {
+        token: String,
+    },
+    Cloudflare {
+        api_token: String,
+    },
+    /// Requested with `GET`, after replacing `{hostname}`, `{ip}` and `{ipv6}` in the url
+    Http {
+        url: String,
+    },
+}
+impl DdnsProvider {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DdnsProvider::DuckDns { .. } => "duckdns",
+            DdnsProvider::Cloudflare { .. } => "cloudflare",
+            DdnsProvider::Http { .. } => "http",
+        }
+    }
+
+    /// Points `hostname` at `ipv4`, and at `ipv6` when publishing AAAA records
+    #[instrument(skip_all)]
+    pub async fn update(
+        &self,
+        http: &Client,
+        hostname: &str,
+        ipv4: Option<Ipv4Addr>,
+        ipv6: Option<Ipv6Addr>,
+    ) -> Result<(), Error> {
+        match self {
+            DdnsProvider::DuckDns { token } => {
+                let domain = hostname.strip_suffix(DUCKDNS_SUFFIX).unwrap_or(hostname);
+                let mut query = vec![("domains", domain.to_owned()), ("token", token.clone())];
+                if let Some(ipv4) = ipv4 {
+                    query.push(("ip", ipv4.to_string()));
+                }
+                if let Some(ipv6) = ipv6 {
+                    query.push(("ipv6", ipv6.to_string()));
+                }
+                let res = http
+                    .get(DUCKDNS_API)
+                    .query(&query)
+                    .send()
+                    .await
+                    .with_kind(ErrorKind::Network)?
+                    .text()
+                    .await
+                    .with_kind(ErrorKind::Network)?;
+                if res.trim() != "OK" {
+                    return Err(Error::new(
+                        eyre!("DuckDNS refused the update for {}", hostname),
+                        ErrorKind::Ddns,
+                    ));
+                }
+                Ok(())
+            }
+            DdnsProvider::Cloudflare { api_token } => {
+                let zone_id = cloudflare_zone(http, api_token, hostname).await?;
+                let records = ipv4
+                    .map(|ip| ("A", ip.to_string()))
+                    .into_iter()
+                    .chain(ipv6.map(|ip| ("AAAA", ip.to_string())));
+                for (kind, content) in records {
+                    let record = json!({
+                        "type": kind,
+                        "name": hostname,
+                        "content": content,
+                        "ttl": RECORD_TTL,
+                    });
+                    let url = format!("{}/zones/{}/dns_records", CLOUDFLARE_API, zone_id);
+                    let existing = cloudflare(
+                        http.get(&url)
+                            .query(&[("type", kind), ("name", hostname)])
+                            .bearer_auth(api_token),
+                        ErrorKind::Ddns,
+                    )
+                    .await?;
+                    match existing["result"][0]["id"].as_str() {
+                        Some(id) => {
+                            cloudflare(
+                                http.put(format!("{}/{}", url, id))
+                                    .bearer_auth(api_token)
+                                    .json(&record),
+                                ErrorKind::Ddns,
+                            )
+                            .await?
+                        }
+                        None => {
+                            cloudflare(
+                                http.post(&url).bearer_auth(api_token).json(&record),
+                                ErrorKind::Ddns,
+                            )
+                            .await?
+                        }
+                    };
+                }
+                Ok(())
+            }
+            DdnsProvider::Http { url } => {
+                let url = expand(url, hostname, ipv4, ipv6)?;
+                let res = http.get(url).send().await.with_kind(ErrorKind::Network)?;
+                if !res.status().is_success() {
+                    return Err(Error::new(
+                        eyre!("The update for {} failed with {}", hostname, res.status()),
+                        ErrorKind::Ddns,
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The id of the Cloudflare zone `hostname` is in: the longest of its parents the token manages
+async fn cloudflare_zone(http: &Client, api_token: &str, hostname: &str) -> Result<String, Error> {
+    let mut zone = hostname;
+    while let Some((_, parent)) = zone.split_once('.') {
+        let zones = cloudflare(
+            http.get(format!("{}/zones", CLOUDFLARE_API))
+                .query(&[("name", zone)])
+                .bearer_auth(api_token),
+            ErrorKind::Ddns,
+        )
+        .await?;
+        if let Some(id) = zones["result"][0]["id"].as_str() {
+            return Ok(id.to_owned());
+        }
+        zone = parent;
+    }
+    Err(Error::new(
+        eyre!(
+            "Cloudflare does not manage a zone containing {} for this token",
+            hostname
+        ),
+        ErrorKind::Ddns,
+    ))
+}
+
+/// Fills the placeholders of a generic update url
+fn expand(
+    url: &str,
+    hostname: &str,
+    ipv4: Option<Ipv4Addr>,
+    ipv6: Option<Ipv6Addr>,
+) -> Result<Url, Error> {
+    url.replace("{hostname}", hostname)
+        .replace("{ip}", &ipv4.map(|ip| ip.to_string()).unwrap_or_default())
+        .replace("{ipv6}", &ipv6.map(|ip| ip.to_string()).unwrap_or_default())
+        .parse()
+        .with_kind(ErrorKind::ParseUrl)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DdnsHost {
+    pub provider: String,
+    /// Also point the hostname at the public IPv6 address of this server
+    pub ipv6: bool,
+    /// The addresses last published
+    pub ipv4_address: Option<Ipv4Addr>,
+    pub ipv6_address: Option<Ipv6Addr>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Why the last update failed
+    pub error: Option<String>,
+}
+
+/// See `net ddns`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct DdnsSettings {
+    pub hostnames: BTreeMap<String, DdnsHost>,
+}
+
+pub(super) async fn lookup<T: std::str::FromStr>(http: &Client, url: &str) -> Result<T, Error> {
+    http.get(url)
+        .timeout(LOOKUP_TIMEOUT)
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .text()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .trim()
+        .parse()
+        .map_err(|_| {
+            Error::new(
+                eyre!("{} did not answer with an IP address", url),
+                ErrorKind::Ddns,
+            )
+        })
+}
+
+/// Updates the hostnames whose public address changed, or whose last update failed, notifying
+/// when one starts failing. With `force`, every hostname is updated.
+///
+/// The providers are called without holding the settings, which are merged per hostname after,
+/// so that hostnames set or removed in the meantime are left as they were.
+async fn update_all(ctx: &RpcContext, force: bool) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .ddns()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    if settings.hostnames.is_empty() {
+        return Ok(());
+    }
+    let ipv4 = lookup::<Ipv4Addr>(&ctx.client, IPV4_LOOKUP).await;
+    // most networks do not have one
+    let ipv6 = if settings.hostnames.values().any(|h| h.ipv6) {
+        lookup::<Ipv6Addr>(&ctx.client, IPV6_LOOKUP).await.ok()
+    } else {
+        None
+    };
+    let ipv4 = match (ipv4, ipv6) {
+        (Ok(ipv4), _) => Some(ipv4),
+        // an IPv6-only network
+        (Err(_), Some(_)) => None,
+        (Err(e), None) => return Err(e),
+    };
+    let mut results = Vec::new();
+    for (hostname, host) in &settings.hostnames {
+        let ipv6 = ipv6.filter(|_| host.ipv6);
+        if !force && host.error.is_none() && host.ipv4_address == ipv4 && host.ipv6_address == ipv6
+        {
+            continue;
+        }
+        let provider = sqlx::query!(
+            "SELECT provider FROM ddns_providers WHERE hostname = $1",
+            hostname
+        )
+        .fetch_optional(&ctx.secret_store)
+        .await?
+        .map(|r| r.provider);
+        let provider = match provider {
+            Some(a) => a,
+            // removed since
+            None => continue,
+        };
+        let res = async {
+            serde_json::from_str::<DdnsProvider>(&provider)
+                .with_kind(ErrorKind::Deserialization)?
+                .update(&ctx.client, hostname, ipv4, ipv6)
+                .await
+        }
+        .await;
+        results.push((hostname.clone(), provider, host.ipv6, ipv6, res));
+    }
+    if results.is_empty() {
+        return Ok(());
+    }
+
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .ddns()
+        .get_mut(&mut db)
+        .await?;
+    let mut failed = Vec::new();
+    for (hostname, provider, wants_ipv6, ipv6, res) in results {
+        let host = match settings.hostnames.get_mut(&hostname) {
+            Some(host) if host.ipv6 == wants_ipv6 => host,
+            // removed since, or updated by whoever changed it
+            _ => continue,
+        };
+        if sqlx::query!(
+            "SELECT provider FROM ddns_providers WHERE hostname = $1",
+            hostname
+        )
+        .fetch_optional(&ctx.secret_store)
+        .await?
+        .map_or(true, |r| r.provider != provider)
+        {
+            continue;
+        }
+        match res {
+            Ok(()) => {
+                host.ipv4_address = ipv4;
+                host.ipv6_address = ipv6;
+                host.updated_at = Some(Utc::now());
+                host.error = None;
+            }
+            Err(e) => {
+                tracing::error!("Error Updating Dynamic DNS for {}: {}", hostname, e);
+                tracing::debug!("{:?}", e);
+                if host.error.is_none() {
+                    failed.push(format!("{}: {}", hostname, e.source));
+                }
+                host.error = Some(e.source.to_string());
+            }
+        }
+    }
+    settings.save(&mut db).await?;
+    if !failed.is_empty() {
+        ctx.notification_manager
+            .notify(
+                &mut db,
+                None,
+                NotificationLevel::Warning,
+                "Dynamic DNS Update Failed".to_owned(),
+                format!(
+                    "These hostnames may no longer point to this server: {}",
+                    failed.join(", ")
+                ),
+                (),
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Keeps every hostname pointed at the public address of this server until it shuts down
+pub async fn launch_ddns_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(DDNS_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = update_all(ctx, false).await {
+                    tracing::error!("Error Updating Dynamic DNS: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[command(subcommands(duckdns_cmd, cloudflare_cmd, http, remove, update, list))]
+pub fn ddns() -> Result<(), Error> {
+    Ok(())
+}
+
+async fn set(
+    ctx: &RpcContext,
+    hostname: String,
+    provider: DdnsProvider,
+    ipv6: bool,
+) -> Result<(), Error> {
+    let hostname = hostname.to_lowercase();
+    check_domain(&hostname)?;
+    sqlx::query!(
+        "INSERT INTO ddns_providers (hostname, provider) VALUES ($1, $2) ON CONFLICT (hostname) DO UPDATE SET provider = EXCLUDED.provider",
+        hostname,
+        serde_json::to_string(&provider).with_kind(ErrorKind::Serialization)?
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .ddns()
+        .get_mut(&mut db)
+        .await?;
+    settings.hostnames.insert(
+        hostname,
+        DdnsHost {
+            provider: provider.kind().to_owned(),
+            ipv6,
+            ipv4_address: None,
+            ipv6_address: None,
+            updated_at: None,
+            error: None,
+        },
+    );
+    settings.save(&mut db).await?;
+    drop(db);
+    update_all(ctx, false).await
+}
+
+/// Keeps `hostname`, a subdomain of duckdns.org, pointed at this server
+#[command(
+    rename = "duckdns",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn duckdns_cmd(
+    #[context] ctx: RpcContext,
+    #[arg] hostname: String,
+    #[arg] token: String,
+    #[arg(long = "ipv6")] ipv6: bool,
+) -> Result<(), Error> {
+    let hostname = if hostname.contains('.') {
+        hostname
+    } else {
+        format!("{}{}", hostname, DUCKDNS_SUFFIX)
+    };
+    if !hostname.to_lowercase().ends_with(DUCKDNS_SUFFIX) {
+        return Err(Error::new(
+            eyre!("{} is not a DuckDNS hostname", hostname),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    set(&ctx, hostname, DdnsProvider::DuckDns { token }, ipv6).await
+}
+
+/// Keeps `hostname` pointed at this server through Cloudflare, with an API token that may edit
+/// the DNS records of its zone
+#[command(
+    rename = "cloudflare",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn cloudflare_cmd(
+    #[context] ctx: RpcContext,
+    #[arg] hostname: String,
+    #[arg(rename = "api-token")] api_token: String,
+    #[arg(long = "ipv6")] ipv6: bool,
+) -> Result<(), Error> {
+    set(&ctx, hostname, DdnsProvider::Cloudflare { api_token }, ipv6).await
+}
+
+/// Keeps `hostname` pointed at this server by requesting `url`, with `{hostname}`, `{ip}` and
+/// `{ipv6}` replaced, whenever its public address changes
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn http(
+    #[context] ctx: RpcContext,
+    #[arg] hostname: String,
+    #[arg] url: String,
+    #[arg(long = "ipv6")] ipv6: bool,
+) -> Result<(), Error> {
+    expand(&url, &hostname, None, None)?;
+    set(&ctx, hostname, DdnsProvider::Http { url }, ipv6).await
+}
+
+/// Stops updating `hostname`, leaving its records as they are
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] hostname: String) -> Result<(), Error> {
+    let hostname = hostname.to_lowercase();
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .ddns()
+        .get_mut(&mut db)
+        .await?;
+    if settings.hostnames.remove(&hostname).is_none() {
+        return Err(Error::new(
+            eyre!("{} is not kept up to date", hostname),
+            ErrorKind::NotFound,
+        ));
+    }
+    sqlx::query!("DELETE FROM ddns_providers WHERE hostname = $1", hostname)
+        .execute(&ctx.secret_store)
+        .await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Updates every hostname now, even if the public address of this server has not changed
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn update(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update_all(&ctx, true).await
+}
+
+fn display_hostnames(arg: BTreeMap<String, DdnsHost>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "HOSTNAME", "PROVIDER", "ADDRESSES", "UPDATED", "ERROR"]);
+    for (hostname, host) in &arg {
+        let addresses = host
+            .ipv4_address
+            .map(|ip| ip.to_string())
+            .into_iter()
+            .chain(host.ipv6_address.map(|ip| ip.to_string()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        table.add_row(row![
+            hostname,
+            &host.provider,
+            addresses,
+            host.updated_at
+                .map_or_else(|| "N/A".to_owned(), |u| u.to_rfc3339()),
+            host.error.as_deref().unwrap_or_default(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_hostnames), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<String, DdnsHost>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .ddns()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .hostnames)
+}
+
+#[test]
+fn expand_url() {
+    assert_eq!(
+        expand(
+            "https://dyn.example.com/nic/update?hostname={hostname}&myip={ip}",
+            "home.example.com",
+            Some(Ipv4Addr::new(203, 0, 113, 7)),
+            None,
+        )
+        .unwrap()
+        .as_str(),
+        "https://dyn.example.com/nic/update?hostname=home.example.com&myip=203.0.113.7"
+    );
+    assert!(expand("not a url {ip}", "home.example.com", None, None).is_err());
+}
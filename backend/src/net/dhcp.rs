@@ -23,6 +23,7 @@ async fn _ips() -> Result<BTreeSet<IpAddr>, Error> {
             std::iter::empty()
                 .chain(i.ipv4.map(IpAddr::from))
                 .chain(i.ipv6.map(IpAddr::from))
+                .chain(i.ipv6_ula.map(IpAddr::from))
         })
         .collect())
 }
@@ -71,9 +72,13 @@ pub async fn update(#[context] ctx: RpcContext, #[arg] interface: String) -> Res
             cached.extend(
                 std::iter::empty()
                     .chain(ip_info.ipv4.map(IpAddr::from))
-                    .chain(ip_info.ipv6.map(IpAddr::from)),
+                    .chain(ip_info.ipv6.map(IpAddr::from))
+                    .chain(ip_info.ipv6_ula.map(IpAddr::from)),
             );
         }
+        drop(cached);
+        // the prefix of the network decides who is on the LAN
+        ctx.net_controller.sync_firewall().await?;
     }
     Ok(())
 }
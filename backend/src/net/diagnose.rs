@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use futures::future::join_all;
+use itertools::Itertools;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::ddns::{lookup, IPV4_LOOKUP};
+use crate::net::dhcp::ips;
+use crate::net::interface::RawProtocol;
+use crate::net::ip_config::parse_default_gateway;
+use crate::net::mdns::resolve_mdns;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::Invoke;
+use crate::{Error, ErrorKind};
+
+/// Resolved to check DNS
+const DNS_CHECK_HOST: &str = "start9.com";
+const CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+/// Connections through tor take longer to fail
+const PORT_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CheckStatus {
+    Passed,
+    Failed,
+    /// The check could not be made, e.g. as a port refused by the tor exits
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Check {
+    pub status: CheckStatus,
+    /// What was found, or what went wrong
+    pub message: String,
+}
+impl Check {
+    fn passed(message: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Passed,
+            message: message.into(),
+        }
+    }
+    fn failed(message: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Failed,
+            message: message.into(),
+        }
+    }
+    fn unknown(message: impl Into<String>) -> Self {
+        Self {
+            status: CheckStatus::Unknown,
+            message: message.into(),
+        }
+    }
+}
+impl From<Result<String, Error>> for Check {
+    fn from(res: Result<String, Error>) -> Self {
+        match res {
+            Ok(message) => Self::passed(message),
+            Err(e) => Self::failed(e.source.to_string()),
+        }
+    }
+}
+
+/// The result of `net diagnose`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NetworkReport {
+    /// Whether public names resolve
+    pub dns: Check,
+    /// Whether the default gateway answers pings
+    pub gateway: Check,
+    /// Whether tor finished bootstrapping
+    pub tor: Check,
+    /// Whether the `.local` hostname of this server resolves to it over mDNS
+    pub mdns: Check,
+    /// The public IPv4 address of this server, as seen by `IPV4_LOOKUP`
+    pub public_ip: Option<Ipv4Addr>,
+    /// Whether the TCP ports open to the clearnet can be reached at `public_ip` from outside,
+    /// through the tor network
+    pub ports: BTreeMap<u16, Check>,
+}
+
+async fn timeout<T>(fut: impl std::future::Future<Output = Result<T, Error>>) -> Result<T, Error> {
+    tokio::time::timeout(CHECK_TIMEOUT, fut)
+        .await
+        .map_err(|_| Error::new(eyre!("Timed out"), ErrorKind::Network))?
+}
+
+async fn check_dns() -> Result<String, Error> {
+    let addrs = timeout(async {
+        Ok(tokio::net::lookup_host((DNS_CHECK_HOST, 443))
+            .await?
+            .map(|a| a.ip())
+            .unique()
+            .collect::<Vec<_>>())
+    })
+    .await?;
+    if addrs.is_empty() {
+        return Err(Error::new(
+            eyre!("{} has no address", DNS_CHECK_HOST),
+            ErrorKind::Network,
+        ));
+    }
+    Ok(format!(
+        "{} resolves to {}",
+        DNS_CHECK_HOST,
+        addrs.iter().join(", ")
+    ))
+}
+
+async fn check_gateway() -> Result<String, Error> {
+    let routes = String::from_utf8(
+        Command::new("ip")
+            .arg("route")
+            .arg("show")
+            .arg("default")
+            .invoke(ErrorKind::Network)
+            .await?,
+    )?;
+    let gateway = parse_default_gateway(&routes)
+        .ok_or_else(|| Error::new(eyre!("There is no default gateway"), ErrorKind::Network))?;
+    Command::new("ping")
+        .arg("-c")
+        .arg("3")
+        .arg("-W")
+        .arg("2")
+        .arg(gateway.to_string())
+        .invoke(ErrorKind::Network)
+        .await
+        .map_err(|_| {
+            Error::new(
+                eyre!("The default gateway {} does not answer", gateway),
+                ErrorKind::Network,
+            )
+        })?;
+    Ok(format!("The default gateway {} answers", gateway))
+}
+
+/// The progress and summary of a `status/bootstrap-phase` of tor
+pub(super) fn parse_bootstrap_phase(phase: &str) -> (Option<u8>, Option<&str>) {
+    let progress = phase
+        .split_ascii_whitespace()
+        .find_map(|w| w.strip_prefix("PROGRESS="))
+        .and_then(|p| p.parse().ok());
+    let summary = phase
+        .split_once("SUMMARY=\"")
+        .and_then(|(_, s)| s.split_once('"'))
+        .map(|(s, _)| s);
+    (progress, summary)
+}
+
+async fn check_tor(ctx: &RpcContext) -> Check {
+    match timeout(ctx.net_controller.tor.bootstrap_phase()).await {
+        Ok(phase) => match parse_bootstrap_phase(&phase) {
+            (Some(100), _) => Check::passed("Tor is bootstrapped"),
+            (progress, summary) => Check::failed(format!(
+                "Tor is bootstrapping at {}%: {}",
+                progress.unwrap_or_default(),
+                summary.unwrap_or("unknown")
+            )),
+        },
+        Err(e) => Check::failed(e.source.to_string()),
+    }
+}
+
+async fn check_mdns(ctx: &RpcContext) -> Result<String, Error> {
+    let hostname = ctx.account.read().await.hostname.local_domain_name();
+    let ip = timeout(resolve_mdns(&hostname)).await.map_err(|e| {
+        Error::new(
+            eyre!("{} does not resolve: {}", hostname, e.source),
+            ErrorKind::Network,
+        )
+    })?;
+    if !ips().await?.contains(&ip) {
+        return Err(Error::new(
+            eyre!("{} resolves to {}, which is not this server", hostname, ip),
+            ErrorKind::Network,
+        ));
+    }
+    Ok(format!("{} resolves to {}", hostname, ip))
+}
+
+/// Connects to `port` of `ip` through tor, whose exits are outside the networks of this server.
+/// A connection refused by the exit policy says nothing about the port.
+async fn check_port(tor_socks: SocketAddr, ip: Ipv4Addr, port: u16) -> Check {
+    match tokio::time::timeout(
+        PORT_CHECK_TIMEOUT,
+        tokio_socks::tcp::Socks5Stream::connect(tor_socks, (ip.to_string(), port)),
+    )
+    .await
+    {
+        Ok(Ok(_)) => Check::passed(format!("{}:{} is reachable", ip, port)),
+        Ok(Err(tokio_socks::Error::ConnectionNotAllowedByRuleset)) => {
+            Check::unknown(format!("The tor exits do not connect to port {}", port))
+        }
+        Ok(Err(tokio_socks::Error::ConnectionRefused)) => {
+            Check::failed(format!("{}:{} refuses connections", ip, port))
+        }
+        Ok(Err(tokio_socks::Error::TtlExpired)) | Err(_) => {
+            Check::failed(format!("{}:{} does not answer", ip, port))
+        }
+        Ok(Err(e)) => Check::unknown(format!("Could not connect through tor: {}", e)),
+    }
+}
+
+fn display_report(arg: NetworkReport, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "CHECK", "STATUS", "MESSAGE"]);
+    let ports = arg
+        .ports
+        .iter()
+        .map(|(port, check)| (format!("port {}", port), check));
+    let checks = [
+        ("dns".to_owned(), &arg.dns),
+        ("gateway".to_owned(), &arg.gateway),
+        ("tor".to_owned(), &arg.tor),
+        ("mdns".to_owned(), &arg.mdns),
+    ]
+    .into_iter()
+    .chain(ports);
+    for (name, check) in checks {
+        let status = match check.status {
+            CheckStatus::Passed => "passed",
+            CheckStatus::Failed => "failed",
+            CheckStatus::Unknown => "unknown",
+        };
+        table.add_row(row![name, status, &check.message]);
+    }
+    table.print_tty(false).unwrap();
+    if let Some(ip) = arg.public_ip {
+        println!("Public IP: {}", ip);
+    }
+}
+
+/// Checks what the network of this server needs to work, for troubleshooting. Takes up to a
+/// minute, as the checks of the ports go through tor.
+#[command(display(display_report), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn diagnose(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<NetworkReport, Error> {
+    let (dns, gateway, tor, mdns, public_ip) = tokio::join!(
+        check_dns(),
+        check_gateway(),
+        check_tor(&ctx),
+        check_mdns(&ctx),
+        lookup::<Ipv4Addr>(&ctx.client, IPV4_LOOKUP),
+    );
+    let public = ctx
+        .net_controller
+        .open_ports()
+        .await
+        .public
+        .into_iter()
+        .filter(|(protocol, _)| *protocol == RawProtocol::Tcp)
+        .map(|(_, port)| port);
+    let tor_socks = ctx.tor_socks;
+    let ports = match &public_ip {
+        Ok(ip) => {
+            let ip = *ip;
+            join_all(
+                public.map(|port| async move { (port, check_port(tor_socks, ip, port).await) }),
+            )
+            .await
+        }
+        Err(e) => public
+            .map(|port| {
+                (
+                    port,
+                    Check::unknown(format!("The public IP is unknown: {}", e.source)),
+                )
+            })
+            .collect(),
+    };
+    Ok(NetworkReport {
+        dns: dns.into(),
+        gateway: gateway.into(),
+        tor,
+        mdns: mdns.into(),
+        public_ip: public_ip.ok(),
+        ports: ports.into_iter().collect(),
+    })
+}
+
+#[test]
+fn bootstrap_phases() {
+    assert_eq!(
+        parse_bootstrap_phase("NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY=\"Done\""),
+        (Some(100), Some("Done"))
+    );
+    assert_eq!(
+        parse_bootstrap_phase(
+            "NOTICE BOOTSTRAP PROGRESS=14 TAG=handshake SUMMARY=\"Handshaking with a relay\""
+        ),
+        (Some(14), Some("Handshaking with a relay"))
+    );
+    assert_eq!(parse_bootstrap_phase("garbage"), (None, None));
+}
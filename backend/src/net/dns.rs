@@ -1,6 +1,6 @@
 use std::borrow::Borrow;
 use std::collections::BTreeMap;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Weak};
 use std::time::Duration;
 
@@ -14,13 +14,30 @@ use tokio::sync::RwLock;
 use tracing::instrument;
 use trust_dns_server::authority::MessageResponseBuilder;
 use trust_dns_server::client::op::{Header, ResponseCode};
-use trust_dns_server::client::rr::{Name, Record, RecordType};
+use trust_dns_server::client::rr::{Name, RData, Record, RecordType};
 use trust_dns_server::server::{Request, RequestHandler, ResponseHandler, ResponseInfo};
 use trust_dns_server::ServerFuture;
 
 use crate::util::Invoke;
 use crate::{Error, ErrorKind, ResultExt};
 
+/// Sends the `.embassy` names of the host to the server. Set globally rather than on
+/// `br-start9`, which has no containers on it, and so no carrier, for resolved to use.
+const RESOLVED_CONF: &str = "/etc/systemd/resolved.conf.d/40-startos-embassy.conf";
+
+/// Where the `.embassy` names are served for the host, of those of `bind`
+pub(super) fn local_addr(bind: &[SocketAddr]) -> SocketAddr {
+    bind.first()
+        .map(|addr| {
+            if addr.ip().is_unspecified() {
+                SocketAddr::from(([127, 0, 0, 1], addr.port()))
+            } else {
+                *addr
+            }
+        })
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 53)))
+}
+
 pub struct DnsController {
     services: Weak<RwLock<BTreeMap<Option<PackageId>, BTreeMap<Ipv4Addr, Weak<()>>>>>,
     #[allow(dead_code)]
@@ -29,11 +46,25 @@ pub struct DnsController {
 
 struct Resolver {
     services: Arc<RwLock<BTreeMap<Option<PackageId>, BTreeMap<Ipv4Addr, Weak<()>>>>>,
+    /// Answers `.local` names with this address and forwards everything else to the host
+    /// resolver, for devices that cannot use mDNS
+    lan: Option<Ipv4Addr>,
 }
 impl Resolver {
-    async fn resolve(&self, name: &Name) -> Option<Vec<Ipv4Addr>> {
-        match name.iter().next_back() {
-            Some(b"embassy") => {
+    async fn resolve(&self, name: &Name) -> Option<Vec<IpAddr>> {
+        match (name.iter().next_back(), self.lan) {
+            (Some(b"local"), Some(ip)) => Some(vec![ip.into()]),
+            (_, Some(_)) => {
+                let name = name.to_string();
+                Some(
+                    tokio::net::lookup_host((name.trim_end_matches('.'), 0))
+                        .await
+                        .ok()?
+                        .map(|addr| addr.ip())
+                        .collect(),
+                )
+            }
+            (Some(b"embassy"), None) => {
                 if let Some(pkg) = name.iter().rev().skip(1).next() {
                     if let Some(ip) = self.services.read().await.get(&Some(
                         std::str::from_utf8(pkg)
@@ -44,7 +75,7 @@ impl Resolver {
                         Some(
                             ip.iter()
                                 .filter(|(_, rc)| rc.strong_count() > 0)
-                                .map(|(ip, _)| *ip)
+                                .map(|(ip, _)| IpAddr::V4(*ip))
                                 .collect(),
                         )
                     } else {
@@ -55,7 +86,7 @@ impl Resolver {
                         Some(
                             ip.iter()
                                 .filter(|(_, rc)| rc.strong_count() > 0)
-                                .map(|(ip, _)| *ip)
+                                .map(|(ip, _)| IpAddr::V4(*ip))
                                 .collect(),
                         )
                     } else {
@@ -77,51 +108,38 @@ impl RequestHandler for Resolver {
     ) -> ResponseInfo {
         let query = request.request_info().query;
         if let Some(ip) = self.resolve(query.name().borrow()).await {
-            match query.query_type() {
-                RecordType::A => {
-                    response_handle
-                        .send_response(
-                            MessageResponseBuilder::from_message_request(&*request).build(
-                                Header::response_from_request(request.header()),
-                                &ip.into_iter()
-                                    .map(|ip| {
-                                        Record::from_rdata(
-                                            request.request_info().query.name().to_owned().into(),
-                                            0,
-                                            trust_dns_server::client::rr::RData::A(ip),
-                                        )
-                                    })
-                                    .collect::<Vec<_>>(),
-                                [],
-                                [],
-                                [],
-                            ),
-                        )
-                        .await
-                }
-                a => {
-                    if a != RecordType::AAAA {
-                        tracing::warn!(
-                            "Non A-Record requested for {}: {:?}",
-                            query.name(),
-                            query.query_type()
-                        );
-                    }
-                    let mut res = Header::response_from_request(request.header());
-                    res.set_response_code(ResponseCode::NXDomain);
-                    response_handle
-                        .send_response(
-                            MessageResponseBuilder::from_message_request(&*request).build(
-                                res.into(),
-                                [],
-                                [],
-                                [],
-                                [],
-                            ),
-                        )
-                        .await
-                }
+            let a = query.query_type();
+            if a != RecordType::A && a != RecordType::AAAA && self.lan.is_none() {
+                tracing::warn!("Non address record requested for {}: {:?}", query.name(), a);
             }
+            // the name exists, so other record types get an empty answer rather than NXDOMAIN,
+            // which resolvers would apply to its addresses too
+            let answers = ip
+                .into_iter()
+                .filter_map(|ip| match (a, ip) {
+                    (RecordType::A, IpAddr::V4(ip)) => Some(RData::A(ip)),
+                    (RecordType::AAAA, IpAddr::V6(ip)) => Some(RData::AAAA(ip)),
+                    _ => None,
+                })
+                .map(|rdata| {
+                    Record::from_rdata(
+                        request.request_info().query.name().to_owned().into(),
+                        0,
+                        rdata,
+                    )
+                })
+                .collect::<Vec<_>>();
+            response_handle
+                .send_response(
+                    MessageResponseBuilder::from_message_request(&*request).build(
+                        Header::response_from_request(request.header()),
+                        &answers,
+                        [],
+                        [],
+                        [],
+                    ),
+                )
+                .await
         } else {
             let mut res = Header::response_from_request(request.header());
             res.set_response_code(ResponseCode::NXDomain);
@@ -154,6 +172,7 @@ impl DnsController {
 
         let mut server = ServerFuture::new(Resolver {
             services: services.clone(),
+            lan: None,
         });
         server.register_listener(
             TcpListener::bind(bind)
@@ -163,16 +182,17 @@ impl DnsController {
         );
         server.register_socket(UdpSocket::bind(bind).await.with_kind(ErrorKind::Network)?);
 
-        Command::new("resolvectl")
-            .arg("dns")
-            .arg("br-start9")
-            .arg("127.0.0.1")
-            .invoke(ErrorKind::Network)
-            .await?;
-        Command::new("resolvectl")
-            .arg("domain")
-            .arg("br-start9")
-            .arg("embassy")
+        if let Some(parent) = std::path::Path::new(RESOLVED_CONF).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(
+            RESOLVED_CONF,
+            format!("[Resolve]\nDNS={}\nDomains=~embassy\n", local_addr(bind)),
+        )
+        .await?;
+        Command::new("systemctl")
+            .arg("reload-or-restart")
+            .arg("systemd-resolved")
             .invoke(ErrorKind::Network)
             .await?;
 
@@ -189,6 +209,38 @@ impl DnsController {
         })
     }
 
+    /// Serves `.local` names as `ip` on `bind` until the handle is dropped, see [`Resolver::lan`]
+    #[instrument(skip_all)]
+    pub async fn serve_lan(
+        &self,
+        bind: SocketAddr,
+        ip: Ipv4Addr,
+    ) -> Result<NonDetachingJoinHandle<Result<(), Error>>, Error> {
+        let services = Weak::upgrade(&self.services).ok_or_else(|| {
+            Error::new(
+                eyre!("DNS Server Thread has exited"),
+                crate::ErrorKind::Network,
+            )
+        })?;
+        let mut server = ServerFuture::new(Resolver {
+            services,
+            lan: Some(ip),
+        });
+        server.register_listener(
+            TcpListener::bind(bind)
+                .await
+                .with_kind(ErrorKind::Network)?,
+            Duration::from_secs(30),
+        );
+        server.register_socket(UdpSocket::bind(bind).await.with_kind(ErrorKind::Network)?);
+        Ok(tokio::spawn(
+            server
+                .block_until_done()
+                .map_err(|e| Error::new(e, ErrorKind::Network)),
+        )
+        .into())
+    }
+
     pub async fn add(&self, pkg_id: Option<PackageId>, ip: Ipv4Addr) -> Result<Arc<()>, Error> {
         if let Some(services) = Weak::upgrade(&self.services) {
             let mut writable = services.write().await;
@@ -0,0 +1,656 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use reqwest::Client;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::net::UdpSocket;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::domain::check_domain;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+pub(super) const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+const DESEC_API: &str = "https://desec.io/api/v1";
+/// The lowest TTL deSEC accepts
+const DESEC_TTL: u32 = 3600;
+const RECORD_TTL: u32 = 60;
+const RFC2136_TIMEOUT: Duration = Duration::from_secs(10);
+/// How far the clocks of this server and the name server may differ (RFC 8945)
+const TSIG_FUDGE: u16 = 300;
+
+/// The HMAC an RFC 2136 server authenticates updates with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TsigAlgorithm {
+    #[default]
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+    #[serde(rename = "hmac-sha512")]
+    HmacSha512,
+}
+impl TsigAlgorithm {
+    fn digest(&self) -> MessageDigest {
+        match self {
+            TsigAlgorithm::HmacSha256 => MessageDigest::sha256(),
+            TsigAlgorithm::HmacSha512 => MessageDigest::sha512(),
+        }
+    }
+}
+impl FromStr for TsigAlgorithm {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hmac-sha256" => Ok(TsigAlgorithm::HmacSha256),
+            "hmac-sha512" => Ok(TsigAlgorithm::HmacSha512),
+            _ => Err(Error::new(
+                eyre!("Must be one of \"hmac-sha256\", \"hmac-sha512\"."),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+impl std::fmt::Display for TsigAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TsigAlgorithm::HmacSha256 => write!(f, "hmac-sha256"),
+            TsigAlgorithm::HmacSha512 => write!(f, "hmac-sha512"),
+        }
+    }
+}
+
+/// Publishes the TXT records DNS-01 challenges are answered with. Only ever kept in the secret
+/// store, since it holds credentials for the zone.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum DnsProvider {
+    #[serde(rename_all = "kebab-case")]
+    Cloudflare { api_token: String },
+    #[serde(rename_all = "kebab-case")]
+    Desec { token: String },
+    /// A name server accepting dynamic updates, authenticated with TSIG
+    #[serde(rename_all = "kebab-case")]
+    Rfc2136 {
+        server: SocketAddr,
+        key_name: String,
+        algorithm: TsigAlgorithm,
+        /// base64
+        secret: String,
+    },
+}
+impl DnsProvider {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DnsProvider::Cloudflare { .. } => "cloudflare",
+            DnsProvider::Desec { .. } => "desec",
+            DnsProvider::Rfc2136 { .. } => "rfc2136",
+        }
+    }
+
+    /// How long a published record takes to be seen by the ACME provider
+    pub fn propagation(&self) -> Duration {
+        match self {
+            DnsProvider::Cloudflare { .. } => Duration::from_secs(10),
+            DnsProvider::Desec { .. } => Duration::from_secs(60),
+            DnsProvider::Rfc2136 { .. } => Duration::from_secs(5),
+        }
+    }
+
+    /// Publishes `value` as a TXT record at `name` in `zone`, returning what `unpublish` needs to
+    /// remove it again
+    #[instrument(skip_all)]
+    pub async fn publish(
+        &self,
+        http: &Client,
+        zone: &str,
+        name: &str,
+        value: &str,
+    ) -> Result<String, Error> {
+        match self {
+            DnsProvider::Cloudflare { api_token } => {
+                let zones = cloudflare(
+                    http.get(format!("{}/zones", CLOUDFLARE_API))
+                        .query(&[("name", zone)])
+                        .bearer_auth(api_token),
+                    ErrorKind::Acme,
+                )
+                .await?;
+                let zone_id = zones["result"][0]["id"].as_str().ok_or_else(|| {
+                    Error::new(
+                        eyre!("Cloudflare does not manage {} for this token", zone),
+                        ErrorKind::Acme,
+                    )
+                })?;
+                let record = cloudflare(
+                    http.post(format!("{}/zones/{}/dns_records", CLOUDFLARE_API, zone_id))
+                        .bearer_auth(api_token)
+                        .json(&json!({
+                            "type": "TXT",
+                            "name": name,
+                            "content": value,
+                            "ttl": RECORD_TTL,
+                        })),
+                    ErrorKind::Acme,
+                )
+                .await?;
+                let record_id = record["result"]["id"].as_str().unwrap_or_default();
+                Ok(format!("{}/dns_records/{}", zone_id, record_id))
+            }
+            DnsProvider::Desec { token } => {
+                desec(http, token, zone, name, vec![format!("\"{}\"", value)]).await?;
+                Ok(String::new())
+            }
+            DnsProvider::Rfc2136 {
+                server,
+                key_name,
+                algorithm,
+                secret,
+            } => {
+                update(
+                    *server,
+                    key_name,
+                    *algorithm,
+                    secret,
+                    zone,
+                    &[Update::Add(name, value)],
+                )
+                .await?;
+                Ok(String::new())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn unpublish(
+        &self,
+        http: &Client,
+        zone: &str,
+        name: &str,
+        value: &str,
+        published: &str,
+    ) -> Result<(), Error> {
+        match self {
+            DnsProvider::Cloudflare { api_token } => {
+                cloudflare(
+                    http.delete(format!("{}/zones/{}", CLOUDFLARE_API, published))
+                        .bearer_auth(api_token),
+                    ErrorKind::Acme,
+                )
+                .await?;
+                Ok(())
+            }
+            DnsProvider::Desec { token } => desec(http, token, zone, name, Vec::new()).await,
+            DnsProvider::Rfc2136 {
+                server,
+                key_name,
+                algorithm,
+                secret,
+            } => {
+                update(
+                    *server,
+                    key_name,
+                    *algorithm,
+                    secret,
+                    zone,
+                    &[Update::Delete(name, value)],
+                )
+                .await
+            }
+        }
+    }
+}
+
+/// Sends `updates` to `zone` on `server` as an RFC 2136 dynamic update
+async fn update(
+    server: SocketAddr,
+    key_name: &str,
+    algorithm: TsigAlgorithm,
+    secret: &str,
+    zone: &str,
+    updates: &[Update<'_>],
+) -> Result<(), Error> {
+    let secret = base64::decode(secret).with_kind(ErrorKind::Deserialization)?;
+    let id: u16 = rand::random();
+    let time_signed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let msg = sign_update(
+        &update_message(id, zone, updates),
+        key_name,
+        algorithm,
+        &secret,
+        time_signed,
+    )?;
+
+    let socket = UdpSocket::bind(if server.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    })
+    .await?;
+    socket.connect(server).await?;
+    socket.send(&msg).await?;
+    let mut res = [0; 512];
+    let len = tokio::time::timeout(RFC2136_TIMEOUT, socket.recv(&mut res))
+        .await
+        .map_err(|_| {
+            Error::new(
+                eyre!("{} did not answer the update in time", server),
+                ErrorKind::Acme,
+            )
+        })??;
+    if len < 4 || res[..2] != id.to_be_bytes() {
+        return Err(Error::new(
+            eyre!("{} sent an invalid response", server),
+            ErrorKind::Acme,
+        ));
+    }
+    match res[3] & 0x0f {
+        0 => Ok(()),
+        rcode => Err(Error::new(
+            eyre!("{} refused the update with RCODE {}", server, rcode),
+            ErrorKind::Acme,
+        )),
+    }
+}
+
+/// Sends `req` to the Cloudflare API, failing with `kind` unless it succeeds
+pub(super) async fn cloudflare(
+    req: reqwest::RequestBuilder,
+    kind: ErrorKind,
+) -> Result<Value, Error> {
+    let res: Value = req
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .json()
+        .await
+        .with_kind(kind)?;
+    if res["success"].as_bool() != Some(true) {
+        return Err(Error::new(
+            eyre!("Cloudflare responded: {}", res["errors"]),
+            kind,
+        ));
+    }
+    Ok(res)
+}
+
+/// Replaces the TXT records at `name`, deleting them if `records` is empty
+async fn desec(
+    http: &Client,
+    token: &str,
+    zone: &str,
+    name: &str,
+    records: Vec<String>,
+) -> Result<(), Error> {
+    let res = http
+        .patch(format!("{}/domains/{}/rrsets/", DESEC_API, zone))
+        .header("Authorization", format!("Token {}", token))
+        .json(&json!([{
+            "subname": subname(zone, name),
+            "type": "TXT",
+            "ttl": DESEC_TTL,
+            "records": records,
+        }]))
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?;
+    if !res.status().is_success() {
+        return Err(Error::new(
+            eyre!(
+                "deSEC responded {}: {}",
+                res.status(),
+                res.text().await.unwrap_or_default()
+            ),
+            ErrorKind::Acme,
+        ));
+    }
+    Ok(())
+}
+
+/// `name` relative to `zone`
+fn subname<'a>(zone: &str, name: &'a str) -> &'a str {
+    name.strip_suffix(zone)
+        .map(|s| s.strip_suffix('.').unwrap_or(s))
+        .unwrap_or(name)
+}
+
+/// The longest of `zones` that `domain`, or the domain a wildcard is for, is in
+pub fn zone_for<'a>(zones: impl IntoIterator<Item = &'a String>, domain: &str) -> Option<&'a str> {
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    zones
+        .into_iter()
+        .filter(|zone| {
+            domain == zone.as_str()
+                || domain
+                    .strip_suffix(zone.as_str())
+                    .map_or(false, |s| s.ends_with('.'))
+        })
+        .max_by_key(|zone| zone.len())
+        .map(|zone| zone.as_str())
+}
+
+/// The provider and zone DNS-01 challenges for `domain` are answered in
+pub async fn provider_for(
+    secrets: &PgPool,
+    zones: &BTreeMap<String, String>,
+    domain: &str,
+) -> Result<(String, DnsProvider), Error> {
+    let zone = zone_for(zones.keys(), domain).ok_or_else(|| {
+        Error::new(
+            eyre!(
+                "No DNS provider is configured for a zone containing {}, see `net acme dns`",
+                domain
+            ),
+            ErrorKind::NotFound,
+        )
+    })?;
+    let provider = sqlx::query!(
+        "SELECT provider FROM acme_dns_providers WHERE zone = $1",
+        zone
+    )
+    .fetch_one(secrets)
+    .await?
+    .provider;
+    Ok((
+        zone.to_owned(),
+        serde_json::from_str(&provider).with_kind(ErrorKind::Deserialization)?,
+    ))
+}
+
+enum Update<'a> {
+    /// Adds a TXT record at the name with the value
+    Add(&'a str, &'a str),
+    /// Removes the TXT record at the name with the value
+    Delete(&'a str, &'a str),
+}
+
+fn wire_name(name: &str) -> Vec<u8> {
+    let mut res = Vec::with_capacity(name.len() + 2);
+    for label in name
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|l| !l.is_empty())
+    {
+        res.push(label.len() as u8);
+        res.extend(label.to_ascii_lowercase().bytes());
+    }
+    res.push(0);
+    res
+}
+
+/// An unsigned RFC 2136 UPDATE message, with a TSIG record still to be counted and appended
+fn update_message(id: u16, zone: &str, updates: &[Update<'_>]) -> Vec<u8> {
+    const TXT: u16 = 16;
+    const SOA: u16 = 6;
+    const IN: u16 = 1;
+    const NONE: u16 = 254;
+
+    let mut msg = Vec::new();
+    msg.extend(id.to_be_bytes());
+    // opcode UPDATE
+    msg.extend(0x2800_u16.to_be_bytes());
+    msg.extend(1_u16.to_be_bytes());
+    msg.extend(0_u16.to_be_bytes());
+    msg.extend((updates.len() as u16).to_be_bytes());
+    msg.extend(0_u16.to_be_bytes());
+
+    msg.extend(wire_name(zone));
+    msg.extend(SOA.to_be_bytes());
+    msg.extend(IN.to_be_bytes());
+
+    for update in updates {
+        let (name, value, class, ttl) = match update {
+            Update::Add(name, value) => (name, value, IN, RECORD_TTL),
+            Update::Delete(name, value) => (name, value, NONE, 0),
+        };
+        msg.extend(wire_name(name));
+        msg.extend(TXT.to_be_bytes());
+        msg.extend(class.to_be_bytes());
+        msg.extend(ttl.to_be_bytes());
+        let mut rdata = Vec::new();
+        for chunk in value.as_bytes().chunks(255) {
+            rdata.push(chunk.len() as u8);
+            rdata.extend(chunk);
+        }
+        msg.extend((rdata.len() as u16).to_be_bytes());
+        msg.extend(rdata);
+    }
+    msg
+}
+
+/// Appends the TSIG record authenticating `msg` (RFC 8945)
+fn sign_update(
+    msg: &[u8],
+    key_name: &str,
+    algorithm: TsigAlgorithm,
+    secret: &[u8],
+    time_signed: u64,
+) -> Result<Vec<u8>, Error> {
+    const TSIG: u16 = 250;
+    const ANY: u16 = 255;
+
+    let key_name = wire_name(key_name);
+    let algorithm_name = wire_name(&algorithm.to_string());
+    let time_signed = &time_signed.to_be_bytes()[2..];
+
+    let mut signed = msg.to_vec();
+    signed.extend(&key_name);
+    signed.extend(ANY.to_be_bytes());
+    signed.extend(0_u32.to_be_bytes());
+    signed.extend(&algorithm_name);
+    signed.extend(time_signed);
+    signed.extend(TSIG_FUDGE.to_be_bytes());
+    // error and other len
+    signed.extend(0_u16.to_be_bytes());
+    signed.extend(0_u16.to_be_bytes());
+    let key = PKey::hmac(secret)?;
+    let mac = Signer::new(algorithm.digest(), &key)?.sign_oneshot_to_vec(&signed)?;
+
+    let mut rdata = algorithm_name;
+    rdata.extend(time_signed);
+    rdata.extend(TSIG_FUDGE.to_be_bytes());
+    rdata.extend((mac.len() as u16).to_be_bytes());
+    rdata.extend(&mac);
+    rdata.extend(&msg[..2]);
+    rdata.extend(0_u16.to_be_bytes());
+    rdata.extend(0_u16.to_be_bytes());
+
+    let mut res = msg.to_vec();
+    // ARCOUNT
+    res[10..12].copy_from_slice(&1_u16.to_be_bytes());
+    res.extend(key_name);
+    res.extend(TSIG.to_be_bytes());
+    res.extend(ANY.to_be_bytes());
+    res.extend(0_u32.to_be_bytes());
+    res.extend((rdata.len() as u16).to_be_bytes());
+    res.extend(rdata);
+    Ok(res)
+}
+
+#[command(subcommands(cloudflare_cmd, desec_cmd, rfc2136, remove, list))]
+pub fn dns() -> Result<(), Error> {
+    Ok(())
+}
+
+async fn set(ctx: &RpcContext, zone: String, provider: DnsProvider) -> Result<(), Error> {
+    let zone = zone.to_lowercase();
+    check_domain(&zone)?;
+    sqlx::query!(
+        "INSERT INTO acme_dns_providers (zone, provider) VALUES ($1, $2) ON CONFLICT (zone) DO UPDATE SET provider = EXCLUDED.provider",
+        zone,
+        serde_json::to_string(&provider).with_kind(ErrorKind::Serialization)?
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    settings
+        .dns_providers
+        .insert(zone, provider.kind().to_owned());
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Answers DNS-01 challenges for domains in `zone` through Cloudflare, with an API token that may
+/// edit its DNS records
+#[command(
+    rename = "cloudflare",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn cloudflare_cmd(
+    #[context] ctx: RpcContext,
+    #[arg] zone: String,
+    #[arg(rename = "api-token")] api_token: String,
+) -> Result<(), Error> {
+    set(&ctx, zone, DnsProvider::Cloudflare { api_token }).await
+}
+
+/// Answers DNS-01 challenges for domains in `zone` through deSEC
+#[command(
+    rename = "desec",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn desec_cmd(
+    #[context] ctx: RpcContext,
+    #[arg] zone: String,
+    #[arg] token: String,
+) -> Result<(), Error> {
+    set(&ctx, zone, DnsProvider::Desec { token }).await
+}
+
+/// Answers DNS-01 challenges for domains in `zone` by sending dynamic updates to `server`, signed
+/// with the TSIG key `key-name` and its base64 `secret`
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn rfc2136(
+    #[context] ctx: RpcContext,
+    #[arg] zone: String,
+    #[arg] server: SocketAddr,
+    #[arg(rename = "key-name")] key_name: String,
+    #[arg] secret: String,
+    #[arg(long = "algorithm")] algorithm: Option<TsigAlgorithm>,
+) -> Result<(), Error> {
+    base64::decode(&secret).with_kind(ErrorKind::InvalidRequest)?;
+    set(
+        &ctx,
+        zone,
+        DnsProvider::Rfc2136 {
+            server,
+            key_name,
+            algorithm: algorithm.unwrap_or_default(),
+            secret,
+        },
+    )
+    .await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] zone: String) -> Result<(), Error> {
+    let zone = zone.to_lowercase();
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get_mut(&mut db)
+        .await?;
+    if settings.dns_providers.remove(&zone).is_none() {
+        return Err(Error::new(
+            eyre!("No DNS provider is configured for {}", zone),
+            ErrorKind::NotFound,
+        ));
+    }
+    sqlx::query!("DELETE FROM acme_dns_providers WHERE zone = $1", zone)
+        .execute(&ctx.secret_store)
+        .await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+fn display_providers(arg: BTreeMap<String, String>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ZONE", "PROVIDER"]);
+    for (zone, provider) in &arg {
+        table.add_row(row![zone, provider]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_providers), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<String, String>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .dns_providers)
+}
+
+#[test]
+fn zones() {
+    let zones: Vec<String> = vec!["example.com".into(), "lab.example.com".into()];
+    assert_eq!(zone_for(&zones, "example.com"), Some("example.com"));
+    assert_eq!(zone_for(&zones, "*.example.com"), Some("example.com"));
+    assert_eq!(
+        zone_for(&zones, "cloud.lab.example.com"),
+        Some("lab.example.com")
+    );
+    assert_eq!(zone_for(&zones, "notexample.com"), None);
+    assert_eq!(
+        subname("example.com", "_acme-challenge.example.com"),
+        "_acme-challenge"
+    );
+    assert_eq!(subname("example.com", "example.com"), "");
+    assert_eq!(
+        wire_name("Example.com."),
+        b"\x07example\x03com\x00".to_vec()
+    );
+}
+
+#[test]
+fn tsig() {
+    let msg = update_message(
+        0x1234,
+        "example.com",
+        &[Update::Add("_acme-challenge.example.com", "value")],
+    );
+    let signed = sign_update(&msg, "acme", TsigAlgorithm::HmacSha256, b"secret", 0).unwrap();
+    assert_eq!(&signed[..msg.len()][..10], &msg[..10]);
+    assert_eq!(&signed[10..12], &[0, 1]);
+    // TSIG RR header, algorithm name, time, fudge, 32 byte MAC, id, error, other len
+    let rdata_len = 13 + 6 + 2 + 2 + 32 + 2 + 2 + 2;
+    assert_eq!(signed.len(), msg.len() + 6 + 10 + rdata_len);
+}
@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use tracing::instrument;
+
+use super::acme::{configure, renew, unconfigure, AcmeDomain, Challenge, DomainTarget};
+use crate::context::RpcContext;
+use crate::db::model::InterfaceAddressMap;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// Fails unless `domain` is a fully qualified domain name a public CA can issue a certificate for
+pub fn check_domain(domain: &str) -> Result<(), Error> {
+    let valid = domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && !domain.ends_with(".local")
+        && !domain.ends_with(".onion");
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(
+            eyre!("{} is not a fully qualified domain name", domain),
+            ErrorKind::InvalidRequest,
+        ))
+    }
+}
+
+async fn update_addresses<F: FnOnce(&mut Vec<String>)>(
+    ctx: &RpcContext,
+    target: &DomainTarget,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    if let Some(addresses) = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&target.package)
+        .and_then(|m| m.installed())
+        .and_then(|i| i.interface_addresses().idx_model(&target.interface))
+        .check(&mut tx)
+        .await?
+    {
+        let mut clearnet = addresses.clearnet_addresses().get_mut(&mut tx).await?;
+        f(&mut clearnet);
+        clearnet.save(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Lists `domain` among the addresses of the interface it serves
+async fn remember_address(
+    ctx: &RpcContext,
+    domain: &str,
+    target: &DomainTarget,
+) -> Result<(), Error> {
+    update_addresses(ctx, target, |addresses| {
+        if !addresses.iter().any(|a| a == domain) {
+            addresses.push(domain.to_owned());
+        }
+    })
+    .await
+}
+
+pub(super) async fn forget_address(
+    ctx: &RpcContext,
+    domain: &str,
+    target: &DomainTarget,
+) -> Result<(), Error> {
+    update_addresses(ctx, target, |addresses| addresses.retain(|a| a != domain)).await
+}
+
+/// Lists the domains of `id` among the addresses of its interfaces again, after it is reinstalled
+pub async fn restore_addresses<Db: DbHandle>(
+    db: &mut Db,
+    id: &PackageId,
+    addresses: &mut InterfaceAddressMap,
+) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(db)
+        .await?
+        .into_owned();
+    for (domain, cfg) in settings.domains {
+        if let Some(target) = cfg.target.filter(|t| &t.package == id) {
+            if let Some(addresses) = addresses.0.get_mut(&target.interface) {
+                addresses.clearnet_addresses.push(domain);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(add, remove, list))]
+pub fn domain() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Serves `interface` of `package` at `domain`, on the ports of its LAN address, with a
+/// certificate from the provider registered with `net acme init`. `domain` must resolve to this
+/// server, with port 80 forwarded to it for http-01 or port 443 for tls-alpn-01.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] domain: String,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(long = "challenge")] challenge: Option<Challenge>,
+) -> Result<(), Error> {
+    let domain = domain.to_lowercase();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    let lan_config = manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("{} has no interface {}", package, interface),
+                ErrorKind::NotFound,
+            )
+        })?
+        .lan_config
+        .as_ref();
+    if lan_config.is_none() {
+        return Err(Error::new(
+            eyre!(
+                "{} of {} is not served on the LAN, so it cannot be served at a domain",
+                interface,
+                package
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let target = DomainTarget { package, interface };
+    configure(
+        &ctx,
+        &domain,
+        challenge.unwrap_or_default(),
+        Some(target.clone()),
+    )
+    .await?;
+    remember_address(&ctx, &domain, &target).await?;
+    renew(&ctx, &domain).await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] domain: String) -> Result<(), Error> {
+    let domain = domain.to_lowercase();
+    if let Some(target) = unconfigure(&ctx, &domain).await? {
+        forget_address(&ctx, &domain, &target).await?;
+    }
+    Ok(())
+}
+
+fn display_domains(arg: BTreeMap<String, AcmeDomain>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "DOMAIN", "PACKAGE", "INTERFACE", "EXPIRES", "ERROR"]);
+    for (domain, cfg) in &arg {
+        if let Some(target) = &cfg.target {
+            table.add_row(row![
+                domain,
+                &*target.package,
+                &*target.interface,
+                cfg.expires_at
+                    .map_or_else(|| "N/A".to_owned(), |e| e.to_rfc3339()),
+                cfg.error.as_deref().unwrap_or_default(),
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The domains packages are served at, see `net acme list` for the StartOS UI
+#[command(display(display_domains), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<String, AcmeDomain>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .acme()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .domains
+        .into_iter()
+        .filter(|(_, cfg)| cfg.target.is_some())
+        .collect())
+}
+
+#[test]
+fn domains() {
+    assert!(check_domain("cloud.example.com").is_ok());
+    assert!(check_domain("example.co.uk").is_ok());
+    assert!(check_domain("localhost").is_err());
+    assert!(check_domain("adjective-noun.local").is_err());
+    assert!(check_domain("-bad.example.com").is_err());
+    assert!(check_domain("a..example.com").is_err());
+    assert!(check_domain("*.example.com").is_err());
+}
@@ -0,0 +1,950 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::Read;
+use std::net::Ipv4Addr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ipnet::Ipv4Net;
+use patch_db::{DbHandle, HasModel};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::PackageId;
+use crate::shutdown::Shutdown;
+use crate::status::MainStatus;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, HOST_IP};
+
+/// Sends the traffic of packages through Tor or the tunnel, and drops what would leave
+/// otherwise
+const TABLE: &str = "startos-egress";
+pub const EGRESS_INTERFACE: &str = "wg-egress";
+const EGRESS_CONFIG_PATH: &str = "/run/embassy/wg-egress.conf";
+/// Marks the packets of the packages using the tunnel, which are routed by `ROUTE_TABLE`
+const MARK: u32 = 0x5210;
+const ROUTE_TABLE: u32 = 51821;
+/// The subnet of the containers, which they keep reaching directly
+const CONTAINER_SUBNET: &str = "172.18.0.0/16";
+/// Where the torrc has tor accept the connections and queries of the packages using it
+const TOR_TRANS_PORT: u16 = 9040;
+const TOR_DNS_PORT: u16 = 9053;
+const KEEPALIVE_SECS: u16 = 25;
+/// WireGuard rejects the keys of a session this long after its handshake, and makes a new one
+/// well before while the peer answers
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(180);
+const KILL_SWITCH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// For tor to bootstrap and the tunnel to make its first handshake after a restart
+const KILL_SWITCH_GRACE: Duration = Duration::from_secs(180);
+
+/// How the traffic a package starts leaves this server
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Egress {
+    #[default]
+    Direct,
+    /// TCP and DNS through tor, anything else is dropped
+    Tor,
+    /// Everything through the tunnel of `net egress tunnel`, dropped while it is down
+    Wireguard,
+}
+impl fmt::Display for Egress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Direct => write!(f, "direct"),
+            Self::Tor => write!(f, "tor"),
+            Self::Wireguard => write!(f, "wireguard"),
+        }
+    }
+}
+impl FromStr for Egress {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(Self::Direct),
+            "tor" => Ok(Self::Tor),
+            "wireguard" | "wg" | "vpn" => Ok(Self::Wireguard),
+            _ => Err(Error::new(
+                eyre!("Unknown egress {}: expected direct, tor or wireguard", s),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+
+/// The public half of the tunnel, the keys of this end are in the secret store
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EgressTunnel {
+    /// Of this end, given by the VPN provider
+    pub address: Ipv4Net,
+    /// The queries of the packages are sent there, and to their own resolver without it
+    pub dns: Option<Ipv4Addr>,
+    pub public_key: String,
+    pub endpoint: String,
+}
+
+/// See `net egress`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize, HasModel)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct EgressSettings {
+    pub tunnel: Option<EgressTunnel>,
+    /// The packages that do not go direct
+    pub packages: BTreeMap<PackageId, Egress>,
+    /// The egresses found down, whose packages are cut off until they are back
+    pub down: BTreeSet<Egress>,
+}
+
+/// A `wg-quick` config, as VPN providers hand them out
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TunnelConfig {
+    pub tunnel: EgressTunnel,
+    pub private_key: String,
+    pub preshared_key: Option<String>,
+}
+impl TunnelConfig {
+    /// Reads the first IPv4 address and resolver of `[Interface]`, and the first `[Peer]`
+    pub fn parse(config: &str) -> Result<Self, Error> {
+        let mut section = "";
+        let mut values: BTreeMap<(&str, String), &str> = BTreeMap::new();
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.starts_with('[') && line.ends_with(']') {
+                section = &line[1..line.len() - 1];
+            } else if let Some((key, value)) = line.split_once('=') {
+                values
+                    .entry((section, key.trim().to_ascii_lowercase()))
+                    .or_insert_with(|| value.trim());
+            }
+        }
+        let get = |section: &'static str, key: &str| {
+            values
+                .get(&(section, key.to_owned()))
+                .copied()
+                .ok_or_else(|| {
+                    Error::new(
+                        eyre!("The tunnel config has no {} in [{}]", key, section),
+                        ErrorKind::ParseNetAddress,
+                    )
+                })
+        };
+        let first_v4 = |list: &str| {
+            list.split(',')
+                .map(|s| s.trim())
+                .find(|s| !s.contains(':'))
+                .map(|s| s.to_owned())
+        };
+        let address = first_v4(get("Interface", "address")?)
+            .and_then(|a| {
+                a.parse::<Ipv4Net>()
+                    .ok()
+                    .or_else(|| Ipv4Net::new(a.parse().ok()?, 32).ok())
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("The tunnel config has no IPv4 address"),
+                    ErrorKind::ParseNetAddress,
+                )
+            })?;
+        let dns = match get("Interface", "dns") {
+            Ok(dns) => first_v4(dns).and_then(|d| d.parse().ok()),
+            Err(_) => None,
+        };
+        let res = Self {
+            tunnel: EgressTunnel {
+                address,
+                dns,
+                public_key: get("Peer", "publickey")?.to_owned(),
+                endpoint: get("Peer", "endpoint")?.to_owned(),
+            },
+            private_key: get("Interface", "privatekey")?.to_owned(),
+            preshared_key: get("Peer", "presharedkey").ok().map(|k| k.to_owned()),
+        };
+        for key in [&res.private_key, &res.tunnel.public_key]
+            .into_iter()
+            .chain(&res.preshared_key)
+        {
+            if base64::decode(key).map_or(true, |k| k.len() != 32) {
+                return Err(Error::new(
+                    eyre!("{:?} is not a WireGuard key", key),
+                    ErrorKind::ParseNetAddress,
+                ));
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn parse_tunnel_config(stdin: &mut std::io::Stdin, _: &ArgMatches) -> Result<TunnelConfig, Error> {
+    let mut config = String::new();
+    stdin.read_to_string(&mut config)?;
+    TunnelConfig::parse(&config)
+}
+
+#[derive(Default)]
+struct Containers {
+    ips: BTreeMap<PackageId, Ipv4Addr>,
+    egress: BTreeMap<PackageId, Egress>,
+    dns: Option<Ipv4Addr>,
+    down: BTreeSet<Egress>,
+}
+
+/// Owns the `inet startos-egress` nftables table, which routes the traffic of the containers by
+/// the egress of their package
+#[derive(Default)]
+pub struct EgressController {
+    containers: Mutex<Containers>,
+}
+impl EgressController {
+    pub(super) async fn add_container(
+        &self,
+        package: PackageId,
+        ip: Ipv4Addr,
+    ) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.get(&package) == Some(&ip) {
+            return Ok(());
+        }
+        containers.ips.insert(package, ip);
+        sync(&containers).await
+    }
+    pub(super) async fn remove_container(&self, package: &PackageId) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.remove(package).is_none() {
+            return Ok(());
+        }
+        sync(&containers).await
+    }
+    async fn set_settings(&self, settings: &EgressSettings) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        containers.egress = settings.packages.clone();
+        containers.dns = settings.tunnel.as_ref().and_then(|t| t.dns);
+        sync(&containers).await
+    }
+    /// Cuts the packages using `egress` off while it is down, so nothing they send leaves some
+    /// other way, and lets them through again once it is back
+    async fn set_down(&self, egress: Egress, down: bool) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        let changed = if down {
+            containers.down.insert(egress)
+        } else {
+            containers.down.remove(&egress)
+        };
+        if changed {
+            sync(&containers).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the table, or removes it while every container goes direct
+async fn sync(containers: &Containers) -> Result<(), Error> {
+    let routed = |egress: Egress| {
+        containers
+            .ips
+            .iter()
+            .filter(|(package, _)| containers.egress.get(*package) == Some(&egress))
+            .map(|(_, ip)| *ip)
+            .collect::<Vec<_>>()
+    };
+    let (tor, wireguard) = (routed(Egress::Tor), routed(Egress::Wireguard));
+    let down = containers
+        .down
+        .iter()
+        .flat_map(|egress| routed(*egress))
+        .collect::<Vec<_>>();
+    if tor.is_empty() && wireguard.is_empty() {
+        if table_exists().await {
+            Command::new("nft")
+                .arg("delete")
+                .arg("table")
+                .arg("inet")
+                .arg(TABLE)
+                .invoke(ErrorKind::Network)
+                .await?;
+        }
+        return Ok(());
+    }
+    nft(&ruleset(&tor, &wireguard, &down, containers.dns)).await
+}
+
+async fn table_exists() -> bool {
+    Command::new("nft")
+        .arg("list")
+        .arg("table")
+        .arg("inet")
+        .arg(TABLE)
+        .invoke(ErrorKind::Network)
+        .await
+        .is_ok()
+}
+
+async fn nft(script: &str) -> Result<(), Error> {
+    let mut cmd = Command::new("nft")
+        .arg("-f")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = cmd.stdin.take() {
+        stdin.write_all(script.as_bytes()).await?;
+    }
+    let res = cmd.wait_with_output().await?;
+    crate::ensure_code!(
+        res.status.success(),
+        ErrorKind::Network,
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    Ok(())
+}
+
+/// The connections of the `tor` containers are redirected to its transparent proxy and their
+/// queries to its resolver, as the queries of the `wireguard` containers are to the resolver of
+/// the tunnel, unless they ask this server for the names only it knows. The packets of the
+/// `wireguard` containers are marked to be routed through the tunnel, and those that would leave
+/// through another interface are dropped. The
+/// `down` containers only reach the other containers, without even the resolver of this server.
+fn ruleset(
+    tor: &[Ipv4Addr],
+    wireguard: &[Ipv4Addr],
+    down: &[Ipv4Addr],
+    dns: Option<Ipv4Addr>,
+) -> String {
+    let set = |ips: &[Ipv4Addr]| {
+        format!(
+            "{{ {} }}",
+            ips.iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    };
+    let host = Ipv4Addr::from(HOST_IP);
+    let mut kill = Vec::new();
+    let mut mark = Vec::new();
+    let mut dnat = Vec::new();
+    let mut forward = Vec::new();
+    if !tor.is_empty() {
+        let tor = set(tor);
+        dnat.push(format!(
+            "ip saddr {} ip daddr != {} udp dport 53 dnat ip to {}:{}",
+            tor, host, host, TOR_DNS_PORT
+        ));
+        dnat.push(format!(
+            "ip saddr {} ip daddr != {} tcp dport 53 dnat ip to {}:{}",
+            tor, host, host, TOR_TRANS_PORT
+        ));
+        dnat.push(format!(
+            "ip saddr {} ip daddr != {} meta l4proto tcp dnat ip to {}:{}",
+            tor, CONTAINER_SUBNET, host, TOR_TRANS_PORT
+        ));
+        forward.push(format!(
+            "ip saddr {} ip daddr != {} drop",
+            tor, CONTAINER_SUBNET
+        ));
+    }
+    if !wireguard.is_empty() {
+        let wireguard = set(wireguard);
+        mark.push(format!("ip saddr {} meta mark set {:#x}", wireguard, MARK));
+        if let Some(dns) = dns {
+            dnat.push(format!(
+                "ip saddr {} ip daddr != {} meta l4proto {{ tcp, udp }} th dport 53 dnat ip to {}",
+                wireguard, host, dns
+            ));
+        }
+        forward.push(format!(
+            "ip saddr {} ip daddr != {} oifname != {:?} drop",
+            wireguard, CONTAINER_SUBNET, EGRESS_INTERFACE
+        ));
+    }
+    if !down.is_empty() {
+        let down = set(down);
+        kill.push(format!(
+            "ip saddr {} meta l4proto {{ tcp, udp }} th dport 53 drop",
+            down
+        ));
+        kill.push(format!(
+            "ip saddr {} ip daddr != {} drop",
+            down, CONTAINER_SUBNET
+        ));
+    }
+    let chain = |name: &str, hook: &str, rules: &[String]| {
+        format!(
+            "\tchain {} {{\n\t\t{}; policy accept;\n{}\t}}\n",
+            name,
+            hook,
+            rules
+                .iter()
+                .map(|r| format!("\t\t{}\n", r))
+                .collect::<String>()
+        )
+    };
+    format!(
+        "table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n{kill}{mark}{dnat}{forward}{masquerade}}}\n",
+        table = TABLE,
+        kill = chain("kill", "type filter hook prerouting priority raw", &kill),
+        mark = chain("mark", "type filter hook prerouting priority mangle", &mark),
+        dnat = chain("dnat", "type nat hook prerouting priority dstnat", &dnat),
+        forward = chain("forward", "type filter hook forward priority filter", &forward),
+        masquerade = chain(
+            "masquerade",
+            "type nat hook postrouting priority srcnat",
+            &[format!("oifname {:?} masquerade", EGRESS_INTERFACE)]
+        ),
+    )
+}
+
+async fn tunnel_keys(secrets: &PgPool) -> Result<Option<(String, Option<String>)>, Error> {
+    Ok(
+        sqlx::query!("SELECT private_key, preshared_key FROM egress_tunnel WHERE id = 0")
+            .fetch_optional(secrets)
+            .await?
+            .map(|r| (r.private_key, r.preshared_key)),
+    )
+}
+
+async fn interface_exists() -> bool {
+    Command::new("ip")
+        .arg("link")
+        .arg("show")
+        .arg(EGRESS_INTERFACE)
+        .invoke(ErrorKind::Wireguard)
+        .await
+        .is_ok()
+}
+
+/// Removes the rule routing the marked packets, if there is one
+async fn del_rule() {
+    Command::new("ip")
+        .arg("rule")
+        .arg("del")
+        .arg("fwmark")
+        .arg(format!("{:#x}", MARK))
+        .arg("table")
+        .arg(ROUTE_TABLE.to_string())
+        .invoke(ErrorKind::Wireguard)
+        .await
+        .map(|_| ())
+        .unwrap_or_default()
+}
+
+/// Brings the tunnel up or down, and routes the packages to match `settings`
+#[instrument(skip_all)]
+async fn apply(
+    net: &NetController,
+    secrets: &PgPool,
+    settings: &EgressSettings,
+) -> Result<(), Error> {
+    let (tunnel, (private_key, preshared_key)) =
+        match (&settings.tunnel, tunnel_keys(secrets).await?) {
+            (Some(tunnel), Some(keys)) => (tunnel, keys),
+            _ => {
+                del_rule().await;
+                if interface_exists().await {
+                    Command::new("ip")
+                        .arg("link")
+                        .arg("del")
+                        .arg(EGRESS_INTERFACE)
+                        .invoke(ErrorKind::Wireguard)
+                        .await?;
+                }
+                return net.egress.set_settings(settings).await;
+            }
+        };
+
+    if !interface_exists().await {
+        Command::new("ip")
+            .arg("link")
+            .arg("add")
+            .arg("dev")
+            .arg(EGRESS_INTERFACE)
+            .arg("type")
+            .arg("wireguard")
+            .invoke(ErrorKind::Wireguard)
+            .await?;
+    }
+    Command::new("ip")
+        .arg("address")
+        .arg("flush")
+        .arg("dev")
+        .arg(EGRESS_INTERFACE)
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    Command::new("ip")
+        .arg("address")
+        .arg("add")
+        .arg(tunnel.address.to_string())
+        .arg("dev")
+        .arg(EGRESS_INTERFACE)
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    let mut conf = format!(
+        "[Interface]\nPrivateKey = {}\n\n[Peer]\nPublicKey = {}\nEndpoint = {}\nAllowedIPs = 0.0.0.0/0\nPersistentKeepalive = {}\n",
+        private_key, tunnel.public_key, tunnel.endpoint, KEEPALIVE_SECS,
+    );
+    if let Some(preshared_key) = preshared_key {
+        conf += &format!("PresharedKey = {}\n", preshared_key);
+    }
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(EGRESS_CONFIG_PATH)
+        .await?;
+    file.write_all(conf.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    let res = Command::new("wg")
+        .arg("syncconf")
+        .arg(EGRESS_INTERFACE)
+        .arg(EGRESS_CONFIG_PATH)
+        .invoke(ErrorKind::Wireguard)
+        .await;
+    tokio::fs::remove_file(EGRESS_CONFIG_PATH).await?;
+    res?;
+    Command::new("ip")
+        .arg("link")
+        .arg("set")
+        .arg(EGRESS_INTERFACE)
+        .arg("up")
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    // the replies come back through the tunnel, from addresses routed elsewhere
+    tokio::fs::write(
+        format!("/proc/sys/net/ipv4/conf/{}/rp_filter", EGRESS_INTERFACE),
+        "2",
+    )
+    .await?;
+    for route in [
+        vec![
+            "default".to_owned(),
+            "dev".to_owned(),
+            EGRESS_INTERFACE.to_owned(),
+        ],
+        // the containers still reach each other and this server directly
+        vec!["throw".to_owned(), CONTAINER_SUBNET.to_owned()],
+    ] {
+        Command::new("ip")
+            .arg("route")
+            .arg("replace")
+            .args(route)
+            .arg("table")
+            .arg(ROUTE_TABLE.to_string())
+            .invoke(ErrorKind::Wireguard)
+            .await?;
+    }
+    del_rule().await;
+    Command::new("ip")
+        .arg("rule")
+        .arg("add")
+        .arg("fwmark")
+        .arg(format!("{:#x}", MARK))
+        .arg("table")
+        .arg(ROUTE_TABLE.to_string())
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    net.egress.set_settings(settings).await
+}
+
+/// Brings the tunnel up and routes the packages after a restart, before they start. The
+/// packages are routed even if the tunnel fails, so theirs is dropped rather than sent direct,
+/// and those cut off before stay so until their egress is found back up.
+pub async fn load<Db: DbHandle>(
+    net: &NetController,
+    secrets: &PgPool,
+    db: &mut Db,
+) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .get(db)
+        .await?
+        .into_owned();
+    for egress in &settings.down {
+        net.egress.set_down(*egress, true).await?;
+    }
+    let res = apply(net, secrets, &settings).await;
+    if res.is_err() {
+        net.egress.set_settings(&settings).await?;
+    }
+    res
+}
+
+/// The latest handshake of a peer in the output of `wg show <interface> latest-handshakes`, as
+/// time since the epoch
+fn latest_handshake(out: &str) -> Option<Duration> {
+    out.lines()
+        .filter_map(|l| l.split_whitespace().nth(1)?.parse::<u64>().ok())
+        // never
+        .filter(|t| *t != 0)
+        .max()
+        .map(Duration::from_secs)
+}
+
+/// Whether the tunnel made a handshake recently enough for its session to still be in use
+async fn wireguard_up() -> bool {
+    let Ok(out) = Command::new("wg")
+        .arg("show")
+        .arg(EGRESS_INTERFACE)
+        .arg("latest-handshakes")
+        .invoke(ErrorKind::Wireguard)
+        .await
+    else {
+        return false;
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    latest_handshake(&String::from_utf8_lossy(&out))
+        .map_or(false, |t| now.saturating_sub(t) < HANDSHAKE_TIMEOUT)
+}
+
+/// Whether tor has a circuit and sees the network, which it stops seeing when its guards
+/// cannot be reached, long after it finished bootstrapping
+async fn tor_up(net: &NetController) -> bool {
+    matches!(
+        net.tor
+            .get_info("status/circuit-established")
+            .await
+            .as_deref(),
+        Ok("1")
+    ) && matches!(
+        net.tor.get_info("network-liveness").await.as_deref(),
+        Ok("up")
+    )
+}
+
+/// Cuts off the packages of the egresses that are down, and lets those of the egresses that
+/// are back through
+async fn check_kill_switch(ctx: &RpcContext) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let packages = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .packages()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    let mut down = BTreeMap::new();
+    for egress in [Egress::Tor, Egress::Wireguard] {
+        let using = packages
+            .iter()
+            .filter(|(_, e)| **e == egress)
+            .map(|(package, _)| package.to_string())
+            .collect::<Vec<_>>();
+        let up = using.is_empty()
+            || match egress {
+                Egress::Direct => true,
+                Egress::Tor => tor_up(&ctx.net_controller).await,
+                Egress::Wireguard => wireguard_up().await,
+            };
+        ctx.net_controller.egress.set_down(egress, !up).await?;
+        down.insert(egress, (!up).then_some(using));
+    }
+    let mut found_down = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .down()
+        .get_mut(&mut db)
+        .await?;
+    let mut changed = false;
+    let mut engaged = Vec::new();
+    for (egress, using) in down {
+        let name = match egress {
+            Egress::Direct => "The default route",
+            Egress::Tor => "Tor",
+            Egress::Wireguard => "The WireGuard tunnel",
+        };
+        match using {
+            Some(using) if found_down.insert(egress) => {
+                changed = true;
+                tracing::warn!("{} is down, cutting off {}", name, using.join(", "));
+                engaged.push(format!(
+                    "{} is down, so {} cannot connect to anything outside this server until it is back.",
+                    name,
+                    using.join(", ")
+                ));
+            }
+            None if found_down.remove(&egress) => {
+                changed = true;
+                tracing::info!("{} is back up", name);
+            }
+            _ => (),
+        }
+    }
+    if changed {
+        found_down.save(&mut db).await?;
+    }
+    if !engaged.is_empty() {
+        ctx.notification_manager
+            .notify(
+                &mut db,
+                None,
+                NotificationLevel::Warning,
+                "Egress Kill-Switch Engaged".to_owned(),
+                engaged.join(" "),
+                (),
+                None,
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Watches tor and the tunnel until the server shuts down, cutting off the packages using
+/// them while they are down rather than letting anything they send leave over the default
+/// route
+pub async fn launch_kill_switch_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + KILL_SWITCH_GRACE,
+        KILL_SWITCH_CHECK_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = check_kill_switch(ctx).await {
+                    tracing::error!("Error Checking Egress: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[command(subcommands(set, list, tunnel))]
+pub fn egress() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Sends what `package` connects to `direct`ly, through `tor`, or through the `wireguard`
+/// tunnel. The package is restarted if it is running, so none of its connections are left on
+/// the way they went before.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] egress: Egress,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .get_mut(&mut db)
+        .await?;
+    if egress == Egress::Wireguard && settings.tunnel.is_none() {
+        return Err(Error::new(
+            eyre!("Set up the tunnel with `net egress tunnel set` first"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    if egress == Egress::Direct {
+        settings.packages.remove(&package);
+    } else {
+        settings.packages.insert(package.clone(), egress);
+    }
+    ctx.net_controller.egress.set_settings(&settings).await?;
+    settings.save(&mut db).await?;
+    if matches!(status, MainStatus::Running { .. }) {
+        crate::control::restart(ctx.clone(), package).await?;
+    }
+    Ok(())
+}
+
+fn display_egress(arg: EgressSettings, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    match &arg.tunnel {
+        Some(tunnel) => println!("Tunnel to {} as {}", tunnel.endpoint, tunnel.address),
+        None => println!("No tunnel"),
+    }
+    for egress in &arg.down {
+        println!("Kill-switch engaged: {} is down", egress);
+    }
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "EGRESS"]);
+    for (package, egress) in &arg.packages {
+        table.add_row(row![&**package, egress]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The packages that do not go direct, and the tunnel
+#[command(display(display_egress), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<EgressSettings, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[command(subcommands(tunnel_set, tunnel_remove))]
+pub fn tunnel() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Connects to the VPN of the `wg-quick` config read from stdin, replacing the tunnel there
+/// was. Only its first peer, and IPv4, are used.
+#[command(
+    rename = "set",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn tunnel_set(
+    #[context] ctx: RpcContext,
+    #[arg(stdin, parse(parse_tunnel_config))] config: TunnelConfig,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .get_mut(&mut db)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO egress_tunnel (id, private_key, preshared_key) VALUES (0, $1, $2) ON CONFLICT (id) DO UPDATE SET private_key = EXCLUDED.private_key, preshared_key = EXCLUDED.preshared_key",
+        config.private_key,
+        config.preshared_key,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    settings.tunnel = Some(config.tunnel);
+    apply(&ctx.net_controller, &ctx.secret_store, &settings).await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Disconnects from the VPN, once no package uses it
+#[command(
+    rename = "remove",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn tunnel_remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .egress()
+        .get_mut(&mut db)
+        .await?;
+    let using = settings
+        .packages
+        .iter()
+        .filter(|(_, egress)| **egress == Egress::Wireguard)
+        .map(|(package, _)| package.to_string())
+        .collect::<Vec<_>>();
+    if !using.is_empty() {
+        return Err(Error::new(
+            eyre!("The tunnel is used by {}", using.join(", ")),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    sqlx::query!("DELETE FROM egress_tunnel WHERE id = 0")
+        .execute(&ctx.secret_store)
+        .await?;
+    settings.tunnel = None;
+    apply(&ctx.net_controller, &ctx.secret_store, &settings).await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[test]
+fn tunnel_configs() {
+    let config = TunnelConfig::parse(
+        "[Interface]
+# Device: Quick Fox
+PrivateKey = YNqHbfBQKaGvzefSSuufuNKTB+nn4NHzZ9ObQ4bsC1E=
+Address = 10.64.1.2/32,fc00:bbbb:bbbb:bb01::1:102/128
+DNS = 10.64.0.1
+
+[Peer]
+PublicKey = HYy5TR7ZpVybinPLZMU55nAbOnhjjnA4dyn8ZYM4jTY=
+AllowedIPs = 0.0.0.0/0,::0/0
+Endpoint = 193.32.127.66:51820
+",
+    )
+    .unwrap();
+    assert_eq!(
+        config.tunnel.address,
+        "10.64.1.2/32".parse::<Ipv4Net>().unwrap()
+    );
+    assert_eq!(config.tunnel.dns, Some(Ipv4Addr::new(10, 64, 0, 1)));
+    assert_eq!(config.tunnel.endpoint, "193.32.127.66:51820");
+    assert_eq!(config.preshared_key, None);
+    assert!(TunnelConfig::parse("[Interface]\nAddress = 10.0.0.2\n").is_err());
+
+    let script = ruleset(
+        &[Ipv4Addr::new(172, 18, 0, 2)],
+        &[Ipv4Addr::new(172, 18, 0, 3)],
+        &[Ipv4Addr::new(172, 18, 0, 3)],
+        Some(Ipv4Addr::new(10, 64, 0, 1)),
+    );
+    let rules = script.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    assert!(rules.contains(
+        &"ip saddr { 172.18.0.2 } ip daddr != 172.18.0.1 udp dport 53 dnat ip to 172.18.0.1:9053"
+    ));
+    assert!(rules.contains(
+        &"ip saddr { 172.18.0.3 } ip daddr != 172.18.0.1 meta l4proto { tcp, udp } th dport 53 dnat ip to 10.64.0.1"
+    ));
+    assert!(rules.contains(
+        &"ip saddr { 172.18.0.2 } ip daddr != 172.18.0.0/16 meta l4proto tcp dnat ip to 172.18.0.1:9040"
+    ));
+    assert!(rules.contains(&"ip saddr { 172.18.0.3 } meta mark set 0x5210"));
+    assert!(rules.contains(
+        &"ip saddr { 172.18.0.3 } ip daddr != 172.18.0.0/16 oifname != \"wg-egress\" drop"
+    ));
+    assert!(rules.contains(&"oifname \"wg-egress\" masquerade"));
+    assert!(rules.contains(&"ip saddr { 172.18.0.3 } ip daddr != 172.18.0.0/16 drop"));
+    assert!(rules.contains(&"ip saddr { 172.18.0.3 } meta l4proto { tcp, udp } th dport 53 drop"));
+
+    assert_eq!(
+        latest_handshake(
+            "HYy5TR7ZpVybinPLZMU55nAbOnhjjnA4dyn8ZYM4jTY=\t1697040000\nYNqHbfBQKaGvzefSSuufuNKTB+nn4NHzZ9ObQ4bsC1E=\t0\n"
+        ),
+        Some(Duration::from_secs(1697040000))
+    );
+    assert_eq!(
+        latest_handshake("HYy5TR7ZpVybinPLZMU55nAbOnhjjnA4dyn8ZYM4jTY=\t0\n"),
+        None
+    );
+}
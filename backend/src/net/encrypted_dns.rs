@@ -0,0 +1,724 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use helpers::NonDetachingJoinHandle;
+use openssl::x509::X509;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerName};
+use tokio_rustls::TlsConnector;
+use tracing::instrument;
+use trust_dns_server::client::op::Message;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, ResultExt, HOST_IP};
+
+/// Points systemd-resolved, and so the host, at the resolver while it is enabled
+const RESOLVED_CONF: &str = "/etc/systemd/resolved.conf.d/50-startos-encrypted-dns.conf";
+/// Keeps the servers a network hands out from answering for names outside their own domains
+/// when NetworkManager brings a connection up again, which resets that setting of its link
+const DISPATCHER: &str = "/etc/NetworkManager/dispatcher.d/50-startos-encrypted-dns";
+const DISPATCHER_SCRIPT: &str = "#!/bin/sh\ncase \"$2\" in\n\tup|dhcp4-change|dhcp6-change|reapply) resolvectl default-route \"$1\" no ;;\nesac\n";
+const CA_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// How many connections to each provider over TLS are kept open for the next queries
+const TLS_IDLE: usize = 4;
+/// The providers that can be named instead of given as `address#hostname`
+const PROVIDERS: &[(&str, [u8; 4], &str)] = &[
+    ("cloudflare", [1, 1, 1, 1], "cloudflare-dns.com"),
+    ("quad9", [9, 9, 9, 9], "dns.quad9.net"),
+    ("google", [8, 8, 8, 8], "dns.google"),
+    ("mullvad", [194, 242, 2, 2], "dns.mullvad.net"),
+];
+
+/// See `net encrypted-dns`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct EncryptedDnsSettings {
+    pub enabled: bool,
+    pub protocol: DnsProtocol,
+    /// Asked in order, starting with the last one that answered
+    pub providers: Vec<DnsProvider>,
+    /// Asks the providers without encryption when none of them can be reached over `protocol`,
+    /// rather than failing the lookup
+    pub fallback: bool,
+}
+impl Default for EncryptedDnsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: DnsProtocol::Tls,
+            providers: ["cloudflare", "quad9"]
+                .into_iter()
+                .filter_map(|p| p.parse().ok())
+                .collect(),
+            fallback: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DnsProtocol {
+    /// DNS over TLS, on port 853
+    Tls,
+    /// DNS over HTTPS, at `/dns-query`
+    Https,
+}
+impl fmt::Display for DnsProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tls => write!(f, "tls"),
+            Self::Https => write!(f, "https"),
+        }
+    }
+}
+impl FromStr for DnsProtocol {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" | "dot" => Ok(Self::Tls),
+            "https" | "doh" => Ok(Self::Https),
+            _ => Err(Error::new(
+                eyre!("Unknown DNS protocol {}: expected tls or https", s),
+                ErrorKind::ParseNetAddress,
+            )),
+        }
+    }
+}
+
+/// An upstream resolver, reached at `address` and checked against a certificate for
+/// `hostname`, which is also where it serves DNS over HTTPS
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DnsProvider {
+    pub address: IpAddr,
+    pub hostname: String,
+}
+impl fmt::Display for DnsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.address, self.hostname)
+    }
+}
+impl FromStr for DnsProvider {
+    type Err = Error;
+    /// The name of one of [`PROVIDERS`], or `address#hostname` as in `resolved.conf`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, address, hostname)) = PROVIDERS.iter().find(|(name, _, _)| *name == s) {
+            return Ok(Self {
+                address: Ipv4Addr::from(*address).into(),
+                hostname: (*hostname).to_owned(),
+            });
+        }
+        let (address, hostname) = s.split_once('#').ok_or_else(|| {
+            Error::new(
+                eyre!(
+                    "Unknown DNS provider {}: expected one of {} or address#hostname",
+                    s,
+                    PROVIDERS
+                        .iter()
+                        .map(|(name, _, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                ErrorKind::ParseNetAddress,
+            )
+        })?;
+        ServerName::try_from(hostname).with_kind(ErrorKind::ParseNetAddress)?;
+        Ok(Self {
+            address: address.parse().with_kind(ErrorKind::ParseNetAddress)?,
+            hostname: hostname.to_owned(),
+        })
+    }
+}
+
+/// Forwards DNS messages in wire format to the providers
+struct Forwarder {
+    settings: EncryptedDnsSettings,
+    /// Where `.embassy` names go, as they only exist on this server
+    local: SocketAddr,
+    tls: TlsConnector,
+    /// The open connections to each provider over TLS, which are not answering a query
+    tls_idle: Vec<std::sync::Mutex<Vec<TlsStream<TcpStream>>>>,
+    /// One per provider, connecting to its address rather than resolving its hostname, which
+    /// would ask this resolver again
+    https: Vec<reqwest::Client>,
+    /// The index of the provider that last answered
+    preferred: AtomicUsize,
+}
+impl Forwarder {
+    async fn new(settings: EncryptedDnsSettings, local: SocketAddr) -> Result<Self, Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(
+            &X509::stack_from_pem(&tokio::fs::read(CA_BUNDLE).await?)?
+                .into_iter()
+                .map(|c| c.to_der())
+                .collect::<Result<Vec<_>, _>>()?,
+        );
+        let tls = TlsConnector::from(Arc::new(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        ));
+        let https = settings
+            .providers
+            .iter()
+            .map(|p| {
+                reqwest::Client::builder()
+                    .resolve(&p.hostname, SocketAddr::new(p.address, 443))
+                    .timeout(TIMEOUT)
+                    .build()
+                    .with_kind(ErrorKind::Network)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            tls_idle: settings
+                .providers
+                .iter()
+                .map(|_| Default::default())
+                .collect(),
+            settings,
+            local,
+            tls,
+            https,
+            preferred: AtomicUsize::new(0),
+        })
+    }
+
+    fn active(&self) -> Option<&DnsProvider> {
+        self.settings
+            .providers
+            .get(self.preferred.load(Ordering::Relaxed))
+    }
+
+    /// The answer to `query`, or a server failure if no provider gave one
+    async fn resolve(&self, query: &[u8]) -> Vec<u8> {
+        if is_local(query) {
+            return match timeout(plain(self.local, query)).await {
+                Ok(res) => res,
+                Err(e) => {
+                    tracing::error!("Local DNS server did not answer: {}", e);
+                    tracing::debug!("{:?}", e);
+                    servfail(query)
+                }
+            };
+        }
+        let count = self.settings.providers.len();
+        let start = self.preferred.load(Ordering::Relaxed);
+        for idx in (0..count).map(|i| (start + i) % count) {
+            let provider = &self.settings.providers[idx];
+            let res = match self.settings.protocol {
+                DnsProtocol::Tls => timeout(self.tls(idx, provider, query)).await,
+                DnsProtocol::Https => self.https(idx, provider, query).await,
+            };
+            match res {
+                Ok(res) => {
+                    self.preferred.store(idx, Ordering::Relaxed);
+                    return res;
+                }
+                Err(e) => {
+                    tracing::warn!("DNS provider {} did not answer: {}", provider, e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+        }
+        if self.settings.fallback {
+            for provider in &self.settings.providers {
+                match timeout(plain(SocketAddr::new(provider.address, 53), query)).await {
+                    Ok(res) => return res,
+                    Err(e) => {
+                        tracing::warn!("DNS provider {} did not answer: {}", provider, e);
+                        tracing::debug!("{:?}", e);
+                    }
+                }
+            }
+        }
+        servfail(query)
+    }
+
+    /// Asks over a connection left open by an earlier query if there is one, as providers close
+    /// them when they please, and over a new one otherwise
+    async fn tls(
+        &self,
+        idx: usize,
+        provider: &DnsProvider,
+        query: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        let idle = self.tls_idle[idx].lock().unwrap().pop();
+        if let Some(mut stream) = idle {
+            if let Ok(res) = exchange(&mut stream, query).await {
+                self.keep_tls(idx, stream);
+                return Ok(res);
+            }
+        }
+        let tcp = TcpStream::connect((provider.address, 853)).await?;
+        let mut stream = self
+            .tls
+            .connect(
+                ServerName::try_from(provider.hostname.as_str())
+                    .with_kind(ErrorKind::ParseNetAddress)?,
+                tcp,
+            )
+            .await
+            .with_kind(ErrorKind::Network)?;
+        let res = exchange(&mut stream, query).await?;
+        self.keep_tls(idx, stream);
+        Ok(res)
+    }
+
+    fn keep_tls(&self, idx: usize, stream: TlsStream<TcpStream>) {
+        let mut idle = self.tls_idle[idx].lock().unwrap();
+        if idle.len() < TLS_IDLE {
+            idle.push(stream);
+        }
+    }
+
+    async fn https(
+        &self,
+        idx: usize,
+        provider: &DnsProvider,
+        query: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        Ok(self.https[idx]
+            .post(format!("https://{}/dns-query", provider.hostname))
+            .header(reqwest::header::CONTENT_TYPE, "application/dns-message")
+            .header(reqwest::header::ACCEPT, "application/dns-message")
+            .body(query.to_vec())
+            .send()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .error_for_status()
+            .with_kind(ErrorKind::Network)?
+            .bytes()
+            .await
+            .with_kind(ErrorKind::Network)?
+            .to_vec())
+    }
+}
+
+async fn timeout<F: std::future::Future<Output = Result<Vec<u8>, Error>>>(
+    fut: F,
+) -> Result<Vec<u8>, Error> {
+    tokio::time::timeout(TIMEOUT, fut)
+        .await
+        .map_err(|_| Error::new(eyre!("Timed out"), ErrorKind::Network))?
+}
+
+/// Asks `addr` over TCP, so answers are never truncated
+async fn plain(addr: SocketAddr, query: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut stream = TcpStream::connect(addr).await?;
+    Ok(exchange(&mut stream, query).await?)
+}
+
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    query: &[u8],
+) -> std::io::Result<Vec<u8>> {
+    write_message(stream, query).await?;
+    read_message(stream).await
+}
+
+/// Reads a message prefixed with its length, as DNS over TCP and TLS send them
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<Vec<u8>> {
+    let len = stream.read_u16().await?;
+    let mut message = vec![0; len as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+async fn write_message<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    message: &[u8],
+) -> std::io::Result<()> {
+    let mut buf = Vec::with_capacity(message.len() + 2);
+    buf.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    buf.extend_from_slice(message);
+    stream.write_all(&buf).await?;
+    stream.flush().await
+}
+
+fn is_local(query: &[u8]) -> bool {
+    Message::from_vec(query)
+        .ok()
+        .and_then(|m| m.queries().first().map(|q| q.name().clone()))
+        .map_or(false, |name| name.iter().next_back() == Some(b"embassy"))
+}
+
+/// The query, turned into an answer with the SERVFAIL code
+fn servfail(query: &[u8]) -> Vec<u8> {
+    let mut res = query.to_vec();
+    if res.len() >= 4 {
+        res[2] |= 0x80;
+        res[3] = (res[3] & 0xf0) | 2;
+    }
+    res
+}
+
+/// The forwarder the servers answer with, replaced when the settings change
+type CurrentForwarder = Arc<RwLock<Arc<Forwarder>>>;
+
+async fn serve_udp(socket: UdpSocket, current: CurrentForwarder) {
+    let socket = Arc::new(socket);
+    let mut buf = vec![0; 4096];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::error!("Error receiving DNS query: {}", e);
+                tracing::debug!("{:?}", e);
+                continue;
+            }
+        };
+        let query = buf[..len].to_vec();
+        let socket = socket.clone();
+        let forwarder = current.read().unwrap().clone();
+        tokio::spawn(async move {
+            let res = forwarder.resolve(&query).await;
+            if let Err(e) = socket.send_to(&res, peer).await {
+                tracing::error!("Error answering DNS query: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        });
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, current: CurrentForwarder) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::error!("Error accepting DNS connection: {}", e);
+                tracing::debug!("{:?}", e);
+                continue;
+            }
+        };
+        let current = current.clone();
+        tokio::spawn(async move {
+            while let Ok(Ok(query)) =
+                tokio::time::timeout(Duration::from_secs(30), read_message(&mut stream)).await
+            {
+                let forwarder = current.read().unwrap().clone();
+                let res = forwarder.resolve(&query).await;
+                if write_message(&mut stream, &res).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// Owns the resolver of `net encrypted-dns`, served at `HOST_IP` so containers can reach it
+/// as well as the host
+pub struct EncryptedDnsController {
+    local: SocketAddr,
+    server: Mutex<Option<(CurrentForwarder, NonDetachingJoinHandle<()>)>>,
+}
+impl EncryptedDnsController {
+    /// `dns_bind` is where the `.embassy` names are served
+    pub(super) fn new(dns_bind: &[SocketAddr]) -> Self {
+        Self {
+            local: crate::net::dns::local_addr(dns_bind),
+            server: Mutex::new(None),
+        }
+    }
+
+    /// The resolver containers should use, while it is enabled
+    pub(super) async fn container_dns(&self) -> Option<Ipv4Addr> {
+        self.server
+            .lock()
+            .await
+            .as_ref()
+            .map(|_| Ipv4Addr::from(HOST_IP))
+    }
+
+    async fn active(&self) -> Option<DnsProvider> {
+        self.server
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|(f, _)| f.read().unwrap().active().cloned())
+    }
+
+    /// Starts, reconfigures or stops the resolver, and points the host at it while it runs. A
+    /// running resolver keeps its sockets, and keeps answering with its old settings if the new
+    /// ones cannot be applied.
+    #[instrument(skip_all)]
+    async fn apply(&self, settings: &EncryptedDnsSettings) -> Result<(), Error> {
+        let mut server = self.server.lock().await;
+        if !settings.enabled {
+            server.take();
+            if tokio::fs::metadata(DISPATCHER).await.is_ok() {
+                tokio::fs::remove_file(DISPATCHER).await?;
+            }
+            if tokio::fs::metadata(RESOLVED_CONF).await.is_ok() {
+                tokio::fs::remove_file(RESOLVED_CONF).await?;
+                reload_resolved(true).await?;
+            }
+            return Ok(());
+        }
+        let forwarder = Arc::new(Forwarder::new(settings.clone(), self.local).await?);
+        if let Some((current, _)) = &*server {
+            *current.write().unwrap() = forwarder;
+        } else {
+            let bind = SocketAddr::from((HOST_IP, 53));
+            let socket = UdpSocket::bind(bind).await.with_kind(ErrorKind::Network)?;
+            let listener = TcpListener::bind(bind)
+                .await
+                .with_kind(ErrorKind::Network)?;
+            let current = Arc::new(RwLock::new(forwarder));
+            *server = Some((
+                current.clone(),
+                tokio::spawn(async move {
+                    futures::join!(
+                        serve_udp(socket, current.clone()),
+                        serve_tcp(listener, current)
+                    );
+                })
+                .into(),
+            ));
+        }
+        if let Some(parent) = Path::new(DISPATCHER).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(DISPATCHER, DISPATCHER_SCRIPT).await?;
+        tokio::fs::set_permissions(DISPATCHER, std::fs::Permissions::from_mode(0o755)).await?;
+        if let Some(parent) = Path::new(RESOLVED_CONF).parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // replaces the `.embassy` settings of `DnsController`, as the resolver forwards them
+        tokio::fs::write(
+            RESOLVED_CONF,
+            format!(
+                "[Resolve]\nDNS=\nDNS={}\nDomains=\nDomains=~.\n",
+                Ipv4Addr::from(HOST_IP)
+            ),
+        )
+        .await?;
+        reload_resolved(false).await
+    }
+}
+
+/// Rereads [`RESOLVED_CONF`], and lets the servers the network hands out be used for names
+/// outside their own domains only while it is absent
+async fn reload_resolved(default_route: bool) -> Result<(), Error> {
+    Command::new("systemctl")
+        .arg("reload-or-restart")
+        .arg("systemd-resolved")
+        .invoke(ErrorKind::Network)
+        .await?;
+    for iface in crate::net::dhcp::init_ips().await?.into_keys() {
+        Command::new("resolvectl")
+            .arg("default-route")
+            .arg(&iface)
+            .arg(if default_route { "yes" } else { "no" })
+            .invoke(ErrorKind::Network)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Starts the resolver if `net encrypted-dns` is enabled
+#[instrument(skip_all)]
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .encrypted_dns()
+        .get(db)
+        .await?
+        .into_owned();
+    net.encrypted_dns.apply(&settings).await
+}
+
+async fn update<F: FnOnce(&mut EncryptedDnsSettings) -> Result<(), Error>>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .encrypted_dns()
+        .get_mut(&mut db)
+        .await?;
+    f(&mut settings)?;
+    ctx.net_controller.encrypted_dns.apply(&settings).await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(
+    rename = "encrypted-dns",
+    subcommands(enable, disable, providers, status)
+)]
+pub fn encrypted_dns() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Resolves names for the host and the containers through the providers over `protocol`,
+/// instead of in plaintext through the servers the network hands out. Containers started
+/// before this use the previous servers until they restart.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(
+    #[context] ctx: RpcContext,
+    #[arg(long = "protocol")] protocol: Option<DnsProtocol>,
+    #[arg(long = "fallback")] fallback: bool,
+) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        if settings.providers.is_empty() {
+            return Err(Error::new(
+                eyre!("No DNS providers are set, see `net encrypted-dns providers`"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        settings.enabled = true;
+        if let Some(protocol) = protocol {
+            settings.protocol = protocol;
+        }
+        settings.fallback = fallback;
+        Ok(())
+    })
+    .await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        settings.enabled = false;
+        Ok(())
+    })
+    .await
+}
+
+fn parse_comma_separated(arg: &str, _: &ArgMatches) -> Result<Vec<DnsProvider>, Error> {
+    arg.split(',').map(|p| p.trim().parse()).collect()
+}
+
+/// Replaces the providers, asked in the order given. Each is one of cloudflare, quad9, google
+/// and mullvad, or `address#hostname`.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn providers(
+    #[context] ctx: RpcContext,
+    #[arg(parse(parse_comma_separated))] providers: Vec<DnsProvider>,
+) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        if providers.is_empty() {
+            return Err(Error::new(
+                eyre!("At least one DNS provider is required"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        settings.providers = providers;
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EncryptedDnsInfo {
+    #[serde(flatten)]
+    pub settings: EncryptedDnsSettings,
+    /// The provider answering, while enabled
+    pub active: Option<DnsProvider>,
+}
+
+fn display_status(arg: EncryptedDnsInfo, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ENABLED", "PROTOCOL", "FALLBACK"]);
+    table.add_row(row![
+        arg.settings.enabled,
+        arg.settings.protocol,
+        arg.settings.fallback
+    ]);
+    table.print_tty(false).unwrap();
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PROVIDER", "ACTIVE"]);
+    for provider in &arg.settings.providers {
+        table.add_row(row![
+            provider,
+            if arg.active.as_ref() == Some(provider) {
+                "*"
+            } else {
+                ""
+            }
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_status), metadata(read_only = true))]
+pub async fn status(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<EncryptedDnsInfo, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .encrypted_dns()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    Ok(EncryptedDnsInfo {
+        settings,
+        active: ctx.net_controller.encrypted_dns.active().await,
+    })
+}
+
+#[test]
+fn providers_and_queries() {
+    assert_eq!(
+        "quad9".parse::<DnsProvider>().unwrap().to_string(),
+        "9.9.9.9#dns.quad9.net"
+    );
+    assert_eq!(
+        "2606:4700:4700::1111#one.one.one.one"
+            .parse::<DnsProvider>()
+            .unwrap(),
+        DnsProvider {
+            address: "2606:4700:4700::1111".parse().unwrap(),
+            hostname: "one.one.one.one".to_owned(),
+        }
+    );
+    assert!("1.1.1.1".parse::<DnsProvider>().is_err());
+    assert!("opendns".parse::<DnsProvider>().is_err());
+
+    // a query for start9.embassy, A record
+    let query = [
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, b's', b't',
+        b'a', b'r', b't', b'9', 0x07, b'e', b'm', b'b', b'a', b's', b's', b'y', 0x00, 0x00, 0x01,
+        0x00, 0x01,
+    ];
+    assert!(is_local(&query));
+    let res = servfail(&query);
+    assert_eq!(&res[..4], &[0x12, 0x34, 0x81, 0x02]);
+    assert_eq!(&res[4..], &query[4..]);
+}
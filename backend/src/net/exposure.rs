@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// The exposure of each interface, by package. Interfaces that are not listed are reachable
+/// from everywhere their manifest serves them.
+pub type ExposureMap = BTreeMap<PackageId, BTreeMap<InterfaceId, Exposure>>;
+
+/// Where an interface can be reached from, see `net exposure set`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct Exposure {
+    /// At its `.local` address and aliases, advertised over mDNS
+    pub lan: bool,
+    /// At its onion address
+    pub tor: bool,
+    /// At its domains, and by clients outside the private networks of this server. Without
+    /// it, the reverse proxy and the firewall turn away public addresses.
+    pub clearnet: bool,
+}
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            lan: true,
+            tor: true,
+            clearnet: true,
+        }
+    }
+}
+impl Exposure {
+    pub fn serves_domain(&self, domain: &str) -> bool {
+        if domain.ends_with(".local") {
+            self.lan
+        } else {
+            self.clearnet
+        }
+    }
+}
+
+/// Applies the exposures set with `net exposure set` again after a restart, before the
+/// packages bind their interfaces
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let exposure = crate::db::DatabaseModel::new()
+        .server_info()
+        .exposure()
+        .get(db)
+        .await?
+        .into_owned();
+    for (package, interfaces) in exposure {
+        for (interface, exposure) in interfaces {
+            net.vhost
+                .set_exposure((package.clone(), interface), Some(exposure))
+                .await;
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(set, list))]
+pub fn exposure() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Changes where `interface` of `package` can be reached from, keeping what is not given.
+/// The package is restarted if it is running, to bind its interfaces again. Its raw ports are
+/// forwarded unless it is only reachable over Tor, and closed to public addresses without
+/// `--clearnet`.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(long = "lan")] lan: Option<bool>,
+    #[arg(long = "tor")] tor: Option<bool>,
+    #[arg(long = "clearnet")] clearnet: Option<bool>,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if !manifest.interfaces.0.contains_key(&interface) {
+        return Err(Error::new(
+            eyre!("{} has no interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    let mut all = crate::db::DatabaseModel::new()
+        .server_info()
+        .exposure()
+        .get_mut(&mut db)
+        .await?;
+    let mut exposure = all
+        .get(&package)
+        .and_then(|interfaces| interfaces.get(&interface))
+        .cloned()
+        .unwrap_or_default();
+    exposure.lan = lan.unwrap_or(exposure.lan);
+    exposure.tor = tor.unwrap_or(exposure.tor);
+    exposure.clearnet = clearnet.unwrap_or(exposure.clearnet);
+    let exposure = Some(exposure).filter(|e| e != &Exposure::default());
+    match &exposure {
+        Some(exposure) => {
+            all.entry(package.clone())
+                .or_default()
+                .insert(interface.clone(), exposure.clone());
+        }
+        None => {
+            if let Some(interfaces) = all.get_mut(&package) {
+                interfaces.remove(&interface);
+                if interfaces.is_empty() {
+                    all.remove(&package);
+                }
+            }
+        }
+    }
+    ctx.net_controller
+        .vhost
+        .set_exposure((package.clone(), interface), exposure)
+        .await;
+    ctx.net_controller.sync_firewall().await?;
+    all.save(&mut db).await?;
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(&mut db)
+        .await?
+        .into_owned();
+    if matches!(status, Some(MainStatus::Running { .. })) {
+        crate::control::restart(ctx.clone(), package).await?;
+    }
+    Ok(())
+}
+
+fn display_exposure(arg: ExposureMap, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE", "LAN", "TOR", "CLEARNET"]);
+    for (package, interfaces) in &arg {
+        for (interface, exposure) in interfaces {
+            table.add_row(row![
+                &**package,
+                &**interface,
+                exposure.lan,
+                exposure.tor,
+                exposure.clearnet
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The interfaces that are not reachable from everywhere
+#[command(display(display_exposure), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<ExposureMap, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .exposure()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn domains() {
+    let lan_only = Exposure {
+        clearnet: false,
+        ..Default::default()
+    };
+    assert!(lan_only.serves_domain("files.local"));
+    assert!(!lan_only.serves_domain("files.example.com"));
+    let public = Exposure {
+        lan: false,
+        ..Default::default()
+    };
+    assert!(!public.serves_domain("files.local"));
+    assert!(public.serves_domain("files.example.com"));
+}
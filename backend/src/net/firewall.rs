@@ -0,0 +1,605 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::process::Stdio;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ipnet::{IpNet, Ipv6Net};
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::interface::RawProtocol;
+use crate::net::net_controller::NetController;
+use crate::net::utils::{is_ula, set_on_link_v6};
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind};
+
+const TABLE: &str = "startos";
+/// Open whatever else is served: ssh, and http(s) for the UI and the redirect to it
+const BASE_PORTS: &[u16] = &[22, 80, 443];
+/// The sources `utils::is_private` accepts, for ports only serving interfaces closed to the
+/// clearnet, along with the global IPv6 prefixes of the networks this server is on
+const PRIVATE_V4: &str = "10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16, 100.64.0.0/10";
+const PRIVATE_V6: &str = "fc00::/7, fe80::/10";
+
+/// See `net firewall`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct FirewallSettings {
+    pub enabled: bool,
+    /// Checked in order, before the ports StartOS opens for what it serves
+    pub rules: Vec<FirewallRule>,
+}
+impl Default for FirewallSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FirewallAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FirewallRule {
+    pub action: FirewallAction,
+    pub protocol: RawProtocol,
+    /// Every port if unset
+    pub port: Option<u16>,
+    /// Every address if unset
+    pub source: Option<IpNet>,
+}
+impl FirewallRule {
+    fn nft(&self) -> String {
+        let mut rule = String::new();
+        match self.source {
+            Some(IpNet::V4(net)) => rule += &format!("ip saddr {} ", net),
+            Some(IpNet::V6(net)) => rule += &format!("ip6 saddr {} ", net),
+            None => (),
+        }
+        match self.port {
+            Some(port) => rule += &format!("{} dport {} ", self.protocol, port),
+            None => rule += &format!("meta l4proto {} ", self.protocol),
+        }
+        rule += match self.action {
+            FirewallAction::Allow => "accept",
+            FirewallAction::Deny => "drop",
+        };
+        rule
+    }
+}
+
+/// The ports open for what StartOS serves besides the vhost and raw forwards, such as a VPN, by
+/// what serves them
+pub type Services = BTreeMap<&'static str, (RawProtocol, u16)>;
+
+/// What StartOS serves, by who can reach it
+#[derive(Debug, Default)]
+pub struct OpenPorts {
+    pub public: BTreeSet<(RawProtocol, u16)>,
+    /// Only open to private addresses, see `net exposure`
+    pub private: BTreeSet<(RawProtocol, u16)>,
+}
+
+/// Owns the `inet startos` nftables table, which only lets connections to physical interfaces
+/// through to what this server serves and what `net firewall` allows
+#[derive(Default)]
+pub struct FirewallController {
+    /// Unset until loaded, so nothing is filtered before the rules are known
+    settings: Mutex<Option<FirewallSettings>>,
+    services: Mutex<Services>,
+    /// The interface of `wifi access-point` while it is up, whose clients get DHCP and DNS from
+    /// this server
+    access_point: Mutex<Option<String>>,
+}
+impl FirewallController {
+    pub(super) async fn set_settings(&self, settings: FirewallSettings) {
+        *self.settings.lock().await = Some(settings);
+    }
+    /// Opens `port` for `service`, or closes what was open for it
+    pub(super) async fn set_service(
+        &self,
+        service: &'static str,
+        port: Option<(RawProtocol, u16)>,
+    ) {
+        let mut services = self.services.lock().await;
+        match port {
+            Some(port) => services.insert(service, port),
+            None => services.remove(service),
+        };
+    }
+    /// Serves DHCP and DNS to the clients on `interface`, or stops, returning whether that
+    /// changed
+    pub(super) async fn set_access_point(&self, interface: Option<String>) -> bool {
+        let mut access_point = self.access_point.lock().await;
+        if *access_point == interface {
+            return false;
+        }
+        *access_point = interface;
+        true
+    }
+    /// Replaces the table with one opening `open`, or removes it while disabled. Either way,
+    /// the prefixes of the networks this server is on are updated for `utils::is_private`.
+    #[instrument(skip_all)]
+    pub(super) async fn sync(&self, mut open: OpenPorts) -> Result<(), Error> {
+        let ips = crate::net::dhcp::init_ips().await?;
+        let on_link = ips
+            .values()
+            .filter(|info| info.ipv6.map_or(false, |ip| !is_ula(&ip)))
+            .filter_map(|info| info.ipv6_range.map(|net| net.trunc()))
+            .collect::<Vec<_>>();
+        set_on_link_v6(on_link.clone());
+        let settings = self.settings.lock().await;
+        let settings = match &*settings {
+            Some(settings) => settings,
+            None => return Ok(()),
+        };
+        if !settings.enabled {
+            if table_exists().await {
+                Command::new("nft")
+                    .arg("delete")
+                    .arg("table")
+                    .arg("inet")
+                    .arg(TABLE)
+                    .invoke(ErrorKind::Firewall)
+                    .await?;
+            }
+            return Ok(());
+        }
+        open.public
+            .extend(self.services.lock().await.values().copied());
+        let physical = ips.into_keys().collect::<Vec<_>>();
+        if physical.is_empty() {
+            return Ok(());
+        }
+        let access_point = self.access_point.lock().await.clone();
+        nft(&ruleset(
+            &physical,
+            &on_link,
+            access_point.as_deref(),
+            settings,
+            &open,
+        ))
+        .await
+    }
+}
+
+async fn table_exists() -> bool {
+    Command::new("nft")
+        .arg("list")
+        .arg("table")
+        .arg("inet")
+        .arg(TABLE)
+        .invoke(ErrorKind::Firewall)
+        .await
+        .is_ok()
+}
+
+/// Applies `script` atomically: either all of it or none of it
+async fn nft(script: &str) -> Result<(), Error> {
+    let mut cmd = Command::new("nft")
+        .arg("-f")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = cmd.stdin.take() {
+        stdin.write_all(script.as_bytes()).await?;
+    }
+    let res = cmd.wait_with_output().await?;
+    crate::ensure_code!(
+        res.status.success(),
+        ErrorKind::Firewall,
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    Ok(())
+}
+
+/// The table filtering what reaches `physical` interfaces, whose networks have the global IPv6
+/// prefixes `on_link`, and `access_point` serves clients. Declaring it before deleting it
+/// replaces it whether or not it exists.
+fn ruleset(
+    physical: &[String],
+    on_link: &[Ipv6Net],
+    access_point: Option<&str>,
+    settings: &FirewallSettings,
+    open: &OpenPorts,
+) -> String {
+    let mut rules = vec![
+        format!(
+            "iifname != {{ {} }} accept",
+            physical
+                .iter()
+                .map(|i| format!("{:?}", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        "ct state established,related accept".to_owned(),
+        "ct state invalid drop".to_owned(),
+        "meta l4proto { icmp, ipv6-icmp } accept".to_owned(),
+        // DHCP and DHCPv6 replies, and mDNS
+        "udp sport 67 udp dport 68 accept".to_owned(),
+        "udp sport 547 udp dport 546 accept".to_owned(),
+        "udp dport 5353 accept".to_owned(),
+    ];
+    if let Some(access_point) = access_point {
+        // DHCP and DNS requests of its clients
+        rules.push(format!(
+            "iifname {:?} udp dport {{ 53, 67 }} accept",
+            access_point
+        ));
+        rules.push(format!("iifname {:?} tcp dport 53 accept", access_point));
+    }
+    rules.extend(settings.rules.iter().map(|r| r.nft()));
+    for protocol in [RawProtocol::Tcp, RawProtocol::Udp] {
+        let base: &[u16] = match protocol {
+            RawProtocol::Tcp => BASE_PORTS,
+            RawProtocol::Udp => &[],
+        };
+        let ports = open
+            .public
+            .iter()
+            .filter(|(p, _)| *p == protocol)
+            .map(|(_, port)| *port)
+            .chain(base.iter().copied())
+            .collect::<BTreeSet<_>>();
+        if !ports.is_empty() {
+            rules.push(format!("{} dport {} accept", protocol, port_set(&ports)));
+        }
+        let private = open
+            .private
+            .iter()
+            .filter(|(p, port)| *p == protocol && !ports.contains(port))
+            .map(|(_, port)| *port)
+            .collect::<BTreeSet<_>>();
+        if !private.is_empty() {
+            let private_v6 = std::iter::once(PRIVATE_V6.to_owned())
+                .chain(on_link.iter().map(|net| net.to_string()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            for (family, sources) in [("ip", PRIVATE_V4), ("ip6", private_v6.as_str())] {
+                rules.push(format!(
+                    "{} saddr {{ {} }} {} dport {} accept",
+                    family,
+                    sources,
+                    protocol,
+                    port_set(&private)
+                ));
+            }
+        }
+    }
+    rules.push("drop".to_owned());
+    format!(
+        "table inet {table}\ndelete table inet {table}\ntable inet {table} {{\n\tchain input {{\n\t\ttype filter hook input priority filter; policy accept;\n{rules}\n\t}}\n}}\n",
+        table = TABLE,
+        rules = rules
+            .iter()
+            .map(|r| format!("\t\t{}", r))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn port_set(ports: &BTreeSet<u16>) -> String {
+    format!(
+        "{{ {} }}",
+        ports
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+/// Starts filtering with the settings of `net firewall`
+#[instrument(skip_all)]
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .firewall()
+        .get(db)
+        .await?
+        .into_owned();
+    net.firewall.set_settings(settings).await;
+    net.sync_firewall().await
+}
+
+async fn update<F: FnOnce(&mut FirewallSettings) -> Result<(), Error>>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .firewall()
+        .get_mut(&mut db)
+        .await?;
+    f(&mut settings)?;
+    ctx.net_controller
+        .firewall
+        .set_settings(settings.clone())
+        .await;
+    ctx.net_controller.sync_firewall().await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(subcommands(enable, disable, allow, deny, remove, list))]
+pub fn firewall() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Drops connections to the physical interfaces of this server, unless to what it serves or
+/// allowed by a rule. Connections from containers, the VPN and the tailnet are not filtered.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        settings.enabled = true;
+        Ok(())
+    })
+    .await
+}
+
+/// Lets every connection through
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        settings.enabled = false;
+        Ok(())
+    })
+    .await
+}
+
+async fn add_rule(
+    ctx: &RpcContext,
+    action: FirewallAction,
+    protocol: RawProtocol,
+    port: Option<u16>,
+    source: Option<IpNet>,
+) -> Result<(), Error> {
+    let rule = FirewallRule {
+        action,
+        protocol,
+        port,
+        source: source.map(|s| s.trunc()),
+    };
+    update(ctx, |settings| {
+        if settings.rules.contains(&rule) {
+            return Err(Error::new(
+                eyre!("The rule already exists"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        settings.rules.push(rule);
+        Ok(())
+    })
+    .await
+}
+
+/// Accepts connections over `protocol` to `--port`, or every port, from `--source`, or every
+/// address
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn allow(
+    #[context] ctx: RpcContext,
+    #[arg] protocol: RawProtocol,
+    #[arg(long = "port")] port: Option<u16>,
+    #[arg(long = "source")] source: Option<IpNet>,
+) -> Result<(), Error> {
+    add_rule(&ctx, FirewallAction::Allow, protocol, port, source).await
+}
+
+/// Drops connections over `protocol` to `--port`, or every port, from `--source`, or every
+/// address, even to what this server serves
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn deny(
+    #[context] ctx: RpcContext,
+    #[arg] protocol: RawProtocol,
+    #[arg(long = "port")] port: Option<u16>,
+    #[arg(long = "source")] source: Option<IpNet>,
+) -> Result<(), Error> {
+    add_rule(&ctx, FirewallAction::Deny, protocol, port, source).await
+}
+
+/// Removes the rule at `index` in `net firewall list`
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] index: usize) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        if index >= settings.rules.len() {
+            return Err(Error::new(
+                eyre!("There is no rule {}", index),
+                ErrorKind::NotFound,
+            ));
+        }
+        settings.rules.remove(index);
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct FirewallInfo {
+    #[serde(flatten)]
+    pub settings: FirewallSettings,
+    /// The ports open for what this server serves
+    pub open: BTreeMap<RawProtocol, BTreeSet<u16>>,
+    /// The ports only open to private addresses, see `net exposure`
+    pub private: BTreeMap<RawProtocol, BTreeSet<u16>>,
+}
+
+fn display_firewall(arg: FirewallInfo, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    println!(
+        "Firewall {}",
+        if arg.settings.enabled {
+            "enabled"
+        } else {
+            "disabled"
+        }
+    );
+    let mut table = Table::new();
+    table.add_row(row![bc => "#", "ACTION", "PROTOCOL", "PORT", "SOURCE"]);
+    for (index, rule) in arg.settings.rules.iter().enumerate() {
+        table.add_row(row![
+            index,
+            match rule.action {
+                FirewallAction::Allow => "allow",
+                FirewallAction::Deny => "deny",
+            },
+            rule.protocol,
+            rule.port.map_or_else(|| "*".to_owned(), |p| p.to_string()),
+            rule.source
+                .map_or_else(|| "*".to_owned(), |s| s.to_string())
+        ]);
+    }
+    for (protocol, ports) in &arg.open {
+        for port in ports {
+            table.add_row(row!["", "allow", protocol, port, "served"]);
+        }
+    }
+    for (protocol, ports) in &arg.private {
+        for port in ports {
+            table.add_row(row![
+                "",
+                "allow",
+                protocol,
+                port,
+                "served, private networks"
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The rules, and the ports open for what this server serves
+#[command(display(display_firewall), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<FirewallInfo, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .firewall()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let ports = ctx.net_controller.open_ports().await;
+    let mut open: BTreeMap<RawProtocol, BTreeSet<u16>> = BTreeMap::new();
+    for (protocol, port) in ports.public {
+        open.entry(protocol).or_default().insert(port);
+    }
+    let mut private: BTreeMap<RawProtocol, BTreeSet<u16>> = BTreeMap::new();
+    for (protocol, port) in ports.private {
+        private.entry(protocol).or_default().insert(port);
+    }
+    open.entry(RawProtocol::Tcp)
+        .or_default()
+        .extend(BASE_PORTS.iter().copied());
+    Ok(FirewallInfo {
+        settings,
+        open,
+        private,
+    })
+}
+
+#[test]
+fn rulesets() {
+    let settings = FirewallSettings {
+        enabled: true,
+        rules: vec![
+            FirewallRule {
+                action: FirewallAction::Deny,
+                protocol: RawProtocol::Tcp,
+                port: Some(22),
+                source: Some("192.168.2.0/24".parse().unwrap()),
+            },
+            FirewallRule {
+                action: FirewallAction::Allow,
+                protocol: RawProtocol::Udp,
+                port: None,
+                source: Some("fd00::/8".parse().unwrap()),
+            },
+        ],
+    };
+    let script = ruleset(
+        &["eth0".to_owned()],
+        &["2001:db8:1::/64".parse().unwrap()],
+        None,
+        &settings,
+        &OpenPorts {
+            public: [(RawProtocol::Tcp, 8443), (RawProtocol::Udp, 51820)]
+                .into_iter()
+                .collect(),
+            private: [(RawProtocol::Tcp, 8080)].into_iter().collect(),
+        },
+    );
+    let rules = script.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    assert_eq!(rules[0], "table inet startos");
+    assert_eq!(rules[1], "delete table inet startos");
+    assert!(rules.contains(&"iifname != { \"eth0\" } accept"));
+    assert!(rules.contains(&"ip saddr 192.168.2.0/24 tcp dport 22 drop"));
+    assert!(rules.contains(&"ip6 saddr fd00::/8 meta l4proto udp accept"));
+    assert!(rules.contains(&"tcp dport { 22, 80, 443, 8443 } accept"));
+    assert!(rules.contains(&"udp dport { 51820 } accept"));
+    assert!(rules
+        .contains(&"ip6 saddr { fc00::/7, fe80::/10, 2001:db8:1::/64 } tcp dport { 8080 } accept"));
+    let deny = rules
+        .iter()
+        .position(|r| r.ends_with("tcp dport 22 drop"))
+        .unwrap();
+    let open = rules
+        .iter()
+        .position(|r| r.starts_with("tcp dport {"))
+        .unwrap();
+    assert!(deny < open);
+    assert_eq!(rules[rules.len() - 3], "drop");
+    assert!(!script.contains("dport { 53, 67 }"));
+
+    let script = ruleset(
+        &["wlan0".to_owned()],
+        &[],
+        Some("wlan0"),
+        &settings,
+        &OpenPorts::default(),
+    );
+    let rules = script.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    assert!(rules.contains(&"iifname \"wlan0\" udp dport { 53, 67 } accept"));
+    assert!(rules.contains(&"iifname \"wlan0\" tcp dport 53 accept"));
+    let dhcp = rules
+        .iter()
+        .position(|r| r.ends_with("udp dport { 53, 67 } accept"))
+        .unwrap();
+    let deny = rules
+        .iter()
+        .position(|r| r.ends_with("tcp dport 22 drop"))
+        .unwrap();
+    assert!(dhcp < deny);
+}
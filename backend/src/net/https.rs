@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// The longest an HSTS policy can be cached by browsers for, 2 years
+const MAX_HSTS_AGE: u64 = 2 * 365 * 24 * 60 * 60;
+
+/// How HTTPS is enforced by the reverse proxy, see `net https`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct HttpsSettings {
+    /// For the OS, and the interfaces without a policy of their own
+    pub global: HttpsPolicy,
+    pub interfaces: BTreeMap<PackageId, BTreeMap<InterfaceId, HttpsPolicy>>,
+}
+impl HttpsSettings {
+    pub fn policy(&self, interface: Option<&(PackageId, InterfaceId)>) -> &HttpsPolicy {
+        interface
+            .and_then(|(package, interface)| self.interfaces.get(package)?.get(interface))
+            .unwrap_or(&self.global)
+    }
+}
+
+/// Only applied at the domains with a publicly trusted certificate: the `.local` and onion
+/// addresses, and IP addresses, are always served over plain HTTP as well, since their
+/// certificates are signed by the root CA of this server, which a client may not trust.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct HttpsPolicy {
+    /// Plain HTTP requests are redirected permanently, rather than temporarily, to HTTPS
+    pub redirect: bool,
+    /// Sent on every HTTPS response, replacing the header the target sent
+    pub hsts: Option<Hsts>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hsts {
+    /// In seconds
+    pub max_age: u64,
+    pub include_subdomains: bool,
+}
+impl Hsts {
+    /// The value of the `Strict-Transport-Security` header
+    pub fn header(&self) -> String {
+        if self.include_subdomains {
+            format!("max-age={}; includeSubDomains", self.max_age)
+        } else {
+            format!("max-age={}", self.max_age)
+        }
+    }
+}
+
+/// Whether a policy can be applied to requests for `host`, as given in the SNI or the `Host`
+/// header: browsers that cached it could not reach a `.local` or onion address, or an IP
+/// address, over plain HTTP again.
+pub fn enforceable(host: &str) -> bool {
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') && port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    host.contains('.')
+        && !host.starts_with('[')
+        && host.parse::<IpAddr>().is_err()
+        && ![".local", ".onion", ".embassy", ".localhost"]
+            .iter()
+            .any(|suffix| host.ends_with(suffix))
+}
+
+/// Applies the policies set with `net https set` again after a restart
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .https()
+        .get(db)
+        .await?
+        .into_owned();
+    net.vhost.set_https(settings).await;
+    Ok(())
+}
+
+#[command(subcommands(set, clear, list))]
+pub fn https() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Changes the policy of the LAN interface `interface` of `package`, or the global one without
+/// them, keeping what is not given. An HSTS max age of 0 stops sending the header. Connections
+/// made from now on are affected.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg(long = "package")] package: Option<PackageId>,
+    #[arg(long = "interface")] interface: Option<InterfaceId>,
+    #[arg(long = "redirect")] redirect: Option<bool>,
+    #[arg(long = "hsts-max-age")] hsts_max_age: Option<u64>,
+    #[arg(long = "include-subdomains")] include_subdomains: Option<bool>,
+) -> Result<(), Error> {
+    if hsts_max_age.map_or(false, |age| age > MAX_HSTS_AGE) {
+        return Err(Error::new(
+            eyre!("The HSTS max age must be at most {} seconds", MAX_HSTS_AGE),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut db = ctx.db.handle();
+    let interface = match (package, interface) {
+        (Some(package), Some(interface)) => {
+            check_interface(&mut db, &package, &interface).await?;
+            Some((package, interface))
+        }
+        (None, None) => None,
+        _ => {
+            return Err(Error::new(
+                eyre!("A package and an interface must be given together"),
+                ErrorKind::InvalidRequest,
+            ))
+        }
+    };
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .https()
+        .get_mut(&mut db)
+        .await?;
+    let mut policy = settings.policy(interface.as_ref()).clone();
+    policy.redirect = redirect.unwrap_or(policy.redirect);
+    match hsts_max_age {
+        Some(0) => policy.hsts = None,
+        Some(max_age) => {
+            policy.hsts = Some(Hsts {
+                max_age,
+                include_subdomains: include_subdomains.unwrap_or_else(|| {
+                    policy.hsts.as_ref().map_or(false, |h| h.include_subdomains)
+                }),
+            })
+        }
+        None => {
+            if let (Some(hsts), Some(include_subdomains)) = (&mut policy.hsts, include_subdomains) {
+                hsts.include_subdomains = include_subdomains;
+            }
+        }
+    }
+    match interface {
+        Some((package, interface)) => {
+            settings
+                .interfaces
+                .entry(package)
+                .or_default()
+                .insert(interface, policy);
+        }
+        None => settings.global = policy,
+    }
+    ctx.net_controller
+        .vhost
+        .set_https((*settings).clone())
+        .await;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Applies the global policy to `interface` of `package` again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn clear(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .https()
+        .get_mut(&mut db)
+        .await?;
+    if let Some(interfaces) = settings.interfaces.get_mut(&package) {
+        interfaces.remove(&interface);
+        if interfaces.is_empty() {
+            settings.interfaces.remove(&package);
+        }
+    }
+    ctx.net_controller
+        .vhost
+        .set_https((*settings).clone())
+        .await;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+async fn check_interface<Db: DbHandle>(
+    db: &mut Db,
+    package: &PackageId,
+    interface: &InterfaceId,
+) -> Result<(), Error> {
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(interface)
+        .map_or(true, |i| i.lan_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no LAN interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+fn display_https(arg: HttpsSettings, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE", "REDIRECT", "HSTS"]);
+    let policies = std::iter::once(("*", "*", &arg.global)).chain(arg.interfaces.iter().flat_map(
+        |(package, interfaces)| {
+            interfaces
+                .iter()
+                .map(move |(interface, policy)| (&**package, &**interface, policy))
+        },
+    ));
+    for (package, interface, policy) in policies {
+        table.add_row(row![
+            package,
+            interface,
+            policy.redirect,
+            policy
+                .hsts
+                .as_ref()
+                .map_or_else(|| "N/A".to_owned(), |h| h.header()),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_https), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<HttpsSettings, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .https()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn enforced_hosts() {
+    assert!(enforceable("files.example.com"));
+    assert!(enforceable("files.example.com:8443"));
+    assert!(!enforceable("adjective-noun.local"));
+    assert!(!enforceable("adjective-noun.local:443"));
+    assert!(!enforceable("abcdefghijklmnop.onion"));
+    assert!(!enforceable("192.168.1.10"));
+    assert!(!enforceable("[fe80::1]:443"));
+    assert!(!enforceable("localhost"));
+    assert_eq!(
+        Hsts {
+            max_age: 31536000,
+            include_subdomains: true
+        }
+        .header(),
+        "max-age=31536000; includeSubDomains"
+    );
+}
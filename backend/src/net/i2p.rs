@@ -0,0 +1,434 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use helpers::NonDetachingJoinHandle;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+/// The SAM bridge of i2pd
+const SAM: ([u8; 4], u16) = ([127, 0, 0, 1], 7656);
+/// Ed25519
+const SIGNATURE_TYPE: u8 = 7;
+/// How long to wait before publishing a destination again after its session ended
+const RETRY: Duration = Duration::from_secs(30);
+
+/// The destination of an eepsite, as given by the SAM bridge: in the base64 of I2P, its public
+/// half `destination` and its private keys `key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct I2pKey {
+    pub destination: String,
+    pub key: String,
+}
+impl I2pKey {
+    /// The `.b32.i2p` address of the destination
+    pub fn address(&self) -> Result<String, Error> {
+        let destination = base64::decode(self.destination.replace('-', "+").replace('~', "/"))
+            .with_kind(ErrorKind::Deserialization)?;
+        Ok(format!(
+            "{}.b32.i2p",
+            base32::encode(
+                base32::Alphabet::RFC4648 { padding: false },
+                &openssl::sha::sha256(&destination)
+            )
+            .to_ascii_lowercase()
+        ))
+    }
+
+    pub async fn for_interface<Ex>(
+        secrets: &mut Ex,
+        package: &PackageId,
+        interface: &InterfaceId,
+    ) -> Result<Option<Self>, Error>
+    where
+        for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+    {
+        Ok(sqlx::query!(
+            "SELECT destination, key FROM i2p_keys WHERE package = $1 AND interface = $2",
+            **package,
+            **interface
+        )
+        .fetch_optional(secrets)
+        .await?
+        .map(|r| Self {
+            destination: r.destination,
+            key: r.key,
+        }))
+    }
+}
+
+/// The values of a reply of the SAM bridge, e.g. `SESSION STATUS RESULT=OK`
+fn parse_reply(line: &str) -> BTreeMap<String, String> {
+    let mut res = BTreeMap::new();
+    let mut rest = line.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.rsplit(' ').next().unwrap_or_default().to_owned();
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(' ').unwrap_or((value, "")),
+        };
+        res.insert(key, value.to_owned());
+        rest = next;
+    }
+    res
+}
+
+async fn sam_command(
+    sam: &mut BufReader<TcpStream>,
+    command: &str,
+) -> Result<BTreeMap<String, String>, Error> {
+    sam.get_mut()
+        .write_all(format!("{}\n", command).as_bytes())
+        .await?;
+    let mut line = String::new();
+    sam.read_line(&mut line).await?;
+    let reply = parse_reply(&line);
+    match reply.get("RESULT").map(|r| r.as_str()) {
+        None | Some("OK") => Ok(reply),
+        Some(result) => Err(Error::new(
+            eyre!(
+                "{}: {}",
+                result,
+                reply.get("MESSAGE").map_or("", |m| m.as_str())
+            ),
+            ErrorKind::Network,
+        )),
+    }
+}
+
+async fn sam_connect() -> Result<BufReader<TcpStream>, Error> {
+    let mut sam = BufReader::new(
+        TcpStream::connect(SocketAddr::from(SAM))
+            .await
+            .with_ctx(|_| (ErrorKind::Network, "Connecting to the SAM bridge of i2pd"))?,
+    );
+    sam_command(&mut sam, "HELLO VERSION MIN=3.1 MAX=3.3").await?;
+    Ok(sam)
+}
+
+async fn generate_key() -> Result<I2pKey, Error> {
+    let mut sam = sam_connect().await?;
+    let mut reply = sam_command(
+        &mut sam,
+        &format!("DEST GENERATE SIGNATURE_TYPE={}", SIGNATURE_TYPE),
+    )
+    .await?;
+    match (reply.remove("PUB"), reply.remove("PRIV")) {
+        (Some(destination), Some(key)) => Ok(I2pKey { destination, key }),
+        _ => Err(Error::new(
+            eyre!("The SAM bridge did not generate a destination"),
+            ErrorKind::Network,
+        )),
+    }
+}
+
+/// Publishes `key` until the session is closed, forwarding its streams to `target`
+async fn serve(id: &str, key: &I2pKey, target: SocketAddr) -> Result<(), Error> {
+    let mut session = sam_connect().await?;
+    sam_command(
+        &mut session,
+        &format!(
+            "SESSION CREATE STYLE=STREAM ID={} DESTINATION={} SIGNATURE_TYPE={}",
+            id, key.key, SIGNATURE_TYPE
+        ),
+    )
+    .await?;
+    let mut forward = sam_connect().await?;
+    sam_command(
+        &mut forward,
+        &format!(
+            "STREAM FORWARD ID={} PORT={} HOST={} SILENT=true",
+            id,
+            target.port(),
+            target.ip()
+        ),
+    )
+    .await?;
+    // the session lasts as long as the sockets that made it
+    let mut line = String::new();
+    loop {
+        line.clear();
+        tokio::select! {
+            res = session.read_line(&mut line) => {
+                if res? == 0 {
+                    break;
+                }
+                if let Some(ping) = line.strip_prefix("PING") {
+                    session
+                        .get_mut()
+                        .write_all(format!("PONG{}", ping).as_bytes())
+                        .await?;
+                }
+            }
+            res = tokio::io::copy(&mut forward, &mut tokio::io::sink()) => {
+                res?;
+                break;
+            }
+        }
+    }
+    Err(Error::new(
+        eyre!("The SAM bridge closed the session"),
+        ErrorKind::Network,
+    ))
+}
+
+/// Keeps the eepsites of the interfaces published through the SAM bridge of i2pd, like
+/// [`TorController`](crate::net::tor::TorController) does their onion services
+#[derive(Default)]
+pub struct I2pController {
+    services: Mutex<BTreeMap<String, (Weak<()>, NonDetachingJoinHandle<()>)>>,
+}
+impl I2pController {
+    pub(super) async fn add(&self, key: &I2pKey, target: SocketAddr) -> Result<Arc<()>, Error> {
+        let address = key.address()?;
+        let mut services = self.services.lock().await;
+        if let Some(rc) = services.get(&address).and_then(|(rc, _)| rc.upgrade()) {
+            return Ok(rc);
+        }
+        let rc = Arc::new(());
+        let id = address.split('.').next().unwrap_or_default()[..16].to_owned();
+        let key = key.clone();
+        let thread = tokio::spawn(async move {
+            loop {
+                if let Err(e) = serve(&id, &key, target).await {
+                    tracing::error!("Error Publishing I2P Destination {}: {}", id, e);
+                    tracing::debug!("{:?}", e);
+                }
+                tokio::time::sleep(RETRY).await;
+            }
+        });
+        services.insert(address, (Arc::downgrade(&rc), thread.into()));
+        Ok(rc)
+    }
+    /// Stops publishing the destinations nothing holds anymore
+    pub(super) async fn gc(&self) {
+        self.services
+            .lock()
+            .await
+            .retain(|_, (rc, _)| rc.strong_count() > 0);
+    }
+}
+
+/// Forgets the destinations of `id` when it is uninstalled
+pub async fn remove_keys<Ex>(secrets: &mut Ex, id: &PackageId) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let id_str = id.as_str();
+    sqlx::query!("DELETE FROM i2p_keys WHERE package = $1", id_str)
+        .execute(secrets)
+        .await?;
+    Ok(())
+}
+
+#[command(subcommands(publish, unpublish, list))]
+pub fn i2p() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Sets the eepsite address of `interface` of `package`, and has the package restarted if it
+/// is running, to be bound at it
+async fn update_address(
+    ctx: &RpcContext,
+    package: &PackageId,
+    interface: &InterfaceId,
+    address: Option<String>,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    if let Some(addresses) = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|m| m.installed())
+        .and_then(|i| i.interface_addresses().idx_model(interface))
+        .check(&mut tx)
+        .await?
+    {
+        let mut i2p_address = addresses.i2p_address().get_mut(&mut tx).await?;
+        *i2p_address = address;
+        i2p_address.save(&mut tx).await?;
+    }
+    tx.commit().await?;
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(&mut db)
+        .await?
+        .into_owned();
+    if matches!(status, Some(MainStatus::Running { .. })) {
+        crate::control::restart(ctx.clone(), package.clone()).await?;
+    }
+    Ok(())
+}
+
+/// Publishes `interface` of `package` as an eepsite, at the port it serves at over Tor: 80
+/// if it has it, its first otherwise. Its address is kept until it is unpublished.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn publish(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<String, Error> {
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .map_or(true, |i| i.tor_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no Tor interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    let mut secrets = ctx.secret_store.acquire().await?;
+    let key = match I2pKey::for_interface(&mut secrets, &package, &interface).await? {
+        Some(key) => key,
+        None => {
+            let key = generate_key().await?;
+            sqlx::query!(
+                "INSERT INTO i2p_keys (package, interface, destination, key) VALUES ($1, $2, $3, $4)",
+                *package,
+                *interface,
+                key.destination,
+                key.key,
+            )
+            .execute(&mut secrets)
+            .await?;
+            key
+        }
+    };
+    let address = key.address()?;
+    update_address(&ctx, &package, &interface, Some(address.clone())).await?;
+    Ok(address)
+}
+
+/// Stops publishing `interface` of `package`, forgetting its address: it gets a new one if it
+/// is published again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn unpublish(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    if sqlx::query!(
+        "DELETE FROM i2p_keys WHERE package = $1 AND interface = $2",
+        *package,
+        *interface
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("{} of {} is not published", interface, package),
+            ErrorKind::NotFound,
+        ));
+    }
+    update_address(&ctx, &package, &interface, None).await
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Eepsite {
+    pub package: PackageId,
+    pub interface: InterfaceId,
+    pub address: String,
+}
+
+fn display_eepsites(arg: Vec<Eepsite>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE", "ADDRESS"]);
+    for eepsite in &arg {
+        table.add_row(row![
+            &*eepsite.package,
+            &*eepsite.interface,
+            &eepsite.address
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The published interfaces
+#[command(display(display_eepsites), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<Eepsite>, Error> {
+    sqlx::query!(
+        "SELECT package, interface, destination, key FROM i2p_keys ORDER BY package, interface"
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(Eepsite {
+            address: I2pKey {
+                destination: r.destination,
+                key: r.key,
+            }
+            .address()?,
+            package: r.package.parse()?,
+            interface: r.interface.parse()?,
+        })
+    })
+    .collect()
+}
+
+#[test]
+fn sam_replies() {
+    let reply = parse_reply("SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"Session already exists\"\n");
+    assert_eq!(reply["RESULT"], "I2P_ERROR");
+    assert_eq!(reply["MESSAGE"], "Session already exists");
+    let reply = parse_reply("DEST REPLY PUB=AAAA~-Aa PRIV=BBBB\n");
+    assert_eq!(reply["PUB"], "AAAA~-Aa");
+    assert_eq!(reply["PRIV"], "BBBB");
+    assert_eq!(
+        I2pKey {
+            destination: "AAAA".to_owned(),
+            key: String::new(),
+        }
+        .address()
+        .unwrap()
+        .len(),
+        52 + ".b32.i2p".len()
+    );
+}
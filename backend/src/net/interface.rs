@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use indexmap::IndexSet;
 pub use models::InterfaceId;
@@ -8,6 +8,7 @@ use torut::onion::TorSecretKeyV3;
 use tracing::instrument;
 
 use crate::db::model::{InterfaceAddressMap, InterfaceAddresses};
+use crate::net::i2p::I2pKey;
 use crate::s9pk::manifest::PackageId;
 use crate::util::serde::Port;
 use crate::{Error, ResultExt};
@@ -42,6 +43,8 @@ impl Interfaces {
             let mut addrs = InterfaceAddresses {
                 tor_address: None,
                 lan_address: None,
+                clearnet_addresses: Vec::new(),
+                i2p_address: None,
             };
             if iface.tor_config.is_some() || iface.lan_config.is_some() {
                 let key = TorSecretKeyV3::generate();
@@ -73,6 +76,11 @@ impl Interfaces {
                         Some(format!("{}.local", onion.get_address_without_dot_onion()));
                 }
             }
+            if iface.tor_config.is_some() {
+                if let Some(key) = I2pKey::for_interface(&mut *secrets, package_id, id).await? {
+                    addrs.i2p_address = Some(key.address()?);
+                }
+            }
             interface_addresses.0.insert(id.clone(), addrs);
         }
         Ok(interface_addresses)
@@ -86,21 +94,61 @@ pub struct Interface {
     pub description: String,
     pub tor_config: Option<TorConfig>,
     pub lan_config: Option<BTreeMap<Port, LanPortConfig>>,
+    /// Non-HTTP ports forwarded as-is on every address of this server, e.g. for electrum or DNS
+    #[serde(default)]
+    pub raw_config: Option<BTreeMap<Port, RawPortConfig>>,
     pub ui: bool,
     pub protocols: IndexSet<String>,
 }
 impl Interface {
+    /// Whether the proxy passes some of its LAN connections through without reading their
+    /// requests, so it cannot ask for credentials or apply directives on them
+    pub fn passes_through(&self) -> bool {
+        self.lan_config
+            .as_ref()
+            .map_or(false, |lan| lan.values().any(|l| l.passthrough))
+    }
     #[instrument(skip_all)]
     pub fn validate(&self) -> Result<(), color_eyre::eyre::Report> {
         if self.tor_config.is_some() && !self.protocols.contains("tcp") {
             color_eyre::eyre::bail!("must support tcp to set up a tor hidden service");
         }
-        if self.lan_config.is_some() && !self.protocols.contains("http") {
-            color_eyre::eyre::bail!("must support http to set up a lan service");
+        if let Some(lan) = &self.lan_config {
+            if lan.values().any(|l| !l.passthrough) && !self.protocols.contains("http") {
+                color_eyre::eyre::bail!("must support http to set up a lan service");
+            }
+            if lan.values().any(|l| l.passthrough) && !self.protocols.contains("tcp") {
+                color_eyre::eyre::bail!("must support tcp to pass lan connections through");
+            }
+            for (port, lan) in lan {
+                if lan.passthrough && !lan.ssl {
+                    color_eyre::eyre::bail!(
+                        "lan port {} must use ssl to be passed through by SNI",
+                        port.0
+                    );
+                }
+            }
         }
         if self.ui && !(self.protocols.contains("http") || self.protocols.contains("https")) {
             color_eyre::eyre::bail!("must support http or https to serve a ui");
         }
+        for (port, raw) in self.raw_config.iter().flatten() {
+            if RESERVED_PORTS.contains(&port.0) {
+                color_eyre::eyre::bail!("port {} is reserved by StartOS", port.0);
+            }
+            if raw.protocols.is_empty() {
+                color_eyre::eyre::bail!("raw port {} must forward tcp or udp", port.0);
+            }
+            for protocol in &raw.protocols {
+                if !self.protocols.contains(protocol.as_str()) {
+                    color_eyre::eyre::bail!(
+                        "must support {} to forward raw port {}",
+                        protocol.as_str(),
+                        port.0
+                    );
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -111,11 +159,57 @@ pub struct TorConfig {
     pub port_mapping: BTreeMap<Port, Port>,
 }
 
+/// Ports StartOS listens on itself: ssh, dns, http(s), mdns, llmnr, postgres, x11 and tor
+pub const RESERVED_PORTS: &[u16] = &[22, 53, 80, 443, 5353, 5355, 5432, 6010, 9050, 9051];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RawProtocol {
+    Tcp,
+    Udp,
+}
+impl RawProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RawProtocol::Tcp => "tcp",
+            RawProtocol::Udp => "udp",
+        }
+    }
+}
+impl std::fmt::Display for RawProtocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl std::str::FromStr for RawProtocol {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tcp" => Ok(RawProtocol::Tcp),
+            "udp" => Ok(RawProtocol::Udp),
+            _ => Err(Error::new(
+                color_eyre::eyre::eyre!("{} is not a protocol, only tcp and udp", s),
+                crate::ErrorKind::ParseNetAddress,
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RawPortConfig {
+    pub internal: u16,
+    pub protocols: BTreeSet<RawProtocol>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct LanPortConfig {
     pub ssl: bool,
     pub internal: u16,
+    /// Routes the TLS connections to the package by SNI without terminating them, for packages
+    /// that hold their own certificates, e.g. mail servers
+    pub passthrough: bool,
 }
 impl<'de> Deserialize<'de> for LanPortConfig {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -128,6 +222,8 @@ impl<'de> Deserialize<'de> for LanPortConfig {
             ssl: bool,
             internal: Option<u16>,
             mapping: Option<u16>,
+            #[serde(default)]
+            passthrough: bool,
         }
 
         let config = PermissiveLanPortConfig::deserialize(deserializer)?;
@@ -137,6 +233,70 @@ impl<'de> Deserialize<'de> for LanPortConfig {
                 .internal
                 .or(config.mapping)
                 .ok_or_else(|| serde::de::Error::missing_field("internal"))?,
+            passthrough: config.passthrough,
         })
     }
 }
+
+#[test]
+fn raw_ports() {
+    let interface = |raw: &str, protocols: &[&str]| Interface {
+        name: "Electrum".into(),
+        description: String::new(),
+        tor_config: None,
+        lan_config: None,
+        raw_config: Some(serde_json::from_str(raw).unwrap()),
+        ui: false,
+        protocols: protocols.iter().map(|p| p.to_string()).collect(),
+    };
+    assert!(interface(
+        r#"{"50001":{"internal":50001,"protocols":["tcp"]}}"#,
+        &["tcp"]
+    )
+    .validate()
+    .is_ok());
+    assert!(interface(
+        r#"{"50001":{"internal":50001,"protocols":["udp"]}}"#,
+        &["tcp"]
+    )
+    .validate()
+    .is_err());
+    assert!(
+        interface(r#"{"53":{"internal":53,"protocols":["udp"]}}"#, &["udp"])
+            .validate()
+            .is_err()
+    );
+    assert!(
+        interface(r#"{"50001":{"internal":50001,"protocols":[]}}"#, &["tcp"])
+            .validate()
+            .is_err()
+    );
+}
+
+#[test]
+fn passthrough_ports() {
+    let interface = |lan: &str, protocols: &[&str]| Interface {
+        name: "IMAP".into(),
+        description: String::new(),
+        tor_config: None,
+        lan_config: Some(serde_json::from_str(lan).unwrap()),
+        raw_config: None,
+        ui: false,
+        protocols: protocols.iter().map(|p| p.to_string()).collect(),
+    };
+    assert!(interface(
+        r#"{"993":{"ssl":true,"internal":993,"passthrough":true}}"#,
+        &["tcp", "imaps"]
+    )
+    .validate()
+    .is_ok());
+    assert!(interface(
+        r#"{"993":{"ssl":false,"internal":143,"passthrough":true}}"#,
+        &["tcp", "imap"]
+    )
+    .validate()
+    .is_err());
+    assert!(interface(r#"{"443":{"ssl":true,"internal":80}}"#, &["tcp"])
+        .validate()
+        .is_err());
+}
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ipnet::{Ipv4Net, Ipv6Net};
+use lazy_static::lazy_static;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::utils::iface_is_physical;
+use crate::notifications::NotificationLevel;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind};
+
+/// How long the network has after a change to reach the gateway before it is reverted
+const CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    /// Held while a change is applied and checked, so changes do not revert each other
+    static ref APPLYING: Mutex<()> = Mutex::new(());
+}
+
+/// The addresses of a physical interface, see `net config`. What is not set comes from the
+/// network: DHCP for IPv4, and router advertisements or DHCPv6 for IPv6.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct IpConfig {
+    /// e.g. `192.168.1.10/24`
+    pub ipv4: Option<Ipv4Net>,
+    pub ipv4_gateway: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Net>,
+    pub ipv6_gateway: Option<Ipv6Addr>,
+    /// Replaces the DNS servers from the network
+    pub dns: Vec<IpAddr>,
+}
+impl IpConfig {
+    fn validate(&self) -> Result<(), Error> {
+        fn invalid(msg: String) -> Result<(), Error> {
+            Err(Error::new(eyre!("{}", msg), ErrorKind::InvalidRequest))
+        }
+        match (&self.ipv4, &self.ipv4_gateway) {
+            (None, Some(_)) => {
+                return invalid("An IPv4 gateway needs a static IPv4 address".into())
+            }
+            (Some(net), Some(gateway)) if !net.contains(gateway) || net.addr() == *gateway => {
+                return invalid(format!("{} is not a gateway within {}", gateway, net))
+            }
+            _ => (),
+        }
+        match (&self.ipv6, &self.ipv6_gateway) {
+            (None, Some(_)) => {
+                return invalid("An IPv6 gateway needs a static IPv6 address".into())
+            }
+            // the gateway of IPv6 is usually the link-local address of the router
+            (Some(net), Some(gateway))
+                if net.addr() == *gateway
+                    || !(net.contains(gateway) || gateway.segments()[0] & 0xffc0 == 0xfe80) =>
+            {
+                return invalid(format!("{} is not a gateway for {}", gateway, net))
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// The `nmcli connection modify` settings for this configuration. Addresses are set before
+    /// `manual` and cleared before `auto`, since `manual` requires an address.
+    fn nmcli_settings(&self) -> Vec<(&'static str, String)> {
+        fn join<T: ToString>(items: impl IntoIterator<Item = T>) -> String {
+            items
+                .into_iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        let dns4 = join(self.dns.iter().filter(|ip| ip.is_ipv4()));
+        let dns6 = join(self.dns.iter().filter(|ip| ip.is_ipv6()));
+        let ignore_auto_dns = if self.dns.is_empty() { "no" } else { "yes" };
+        vec![
+            ("ipv4.addresses", join(self.ipv4)),
+            ("ipv4.gateway", join(self.ipv4_gateway)),
+            (
+                "ipv4.method",
+                if self.ipv4.is_some() {
+                    "manual"
+                } else {
+                    "auto"
+                }
+                .to_owned(),
+            ),
+            ("ipv6.addresses", join(self.ipv6)),
+            ("ipv6.gateway", join(self.ipv6_gateway)),
+            (
+                "ipv6.method",
+                if self.ipv6.is_some() {
+                    "manual"
+                } else {
+                    "auto"
+                }
+                .to_owned(),
+            ),
+            ("ipv4.dns", dns4),
+            ("ipv4.ignore-auto-dns", ignore_auto_dns.to_owned()),
+            ("ipv6.dns", dns6),
+            ("ipv6.ignore-auto-dns", ignore_auto_dns.to_owned()),
+        ]
+    }
+}
+
+/// The NetworkManager connection active on `interface`
+async fn connection(interface: &str) -> Result<String, Error> {
+    let name = String::from_utf8(
+        Command::new("nmcli")
+            .arg("-g")
+            .arg("GENERAL.CONNECTION")
+            .arg("device")
+            .arg("show")
+            .arg(interface)
+            .invoke(ErrorKind::Network)
+            .await?,
+    )?
+    .trim()
+    .to_owned();
+    if name.is_empty() {
+        return Err(Error::new(
+            eyre!("{} is not connected to a network", interface),
+            ErrorKind::Network,
+        ));
+    }
+    Ok(name)
+}
+
+async fn apply(connection: &str, config: &IpConfig) -> Result<(), Error> {
+    let mut cmd = Command::new("nmcli");
+    cmd.arg("connection").arg("modify").arg(connection);
+    for (key, value) in config.nmcli_settings() {
+        cmd.arg(key).arg(value);
+    }
+    cmd.invoke(ErrorKind::Network).await?;
+    Command::new("nmcli")
+        .arg("-w")
+        .arg(CHECK_TIMEOUT.as_secs().to_string())
+        .arg("connection")
+        .arg("up")
+        .arg(connection)
+        .invoke(ErrorKind::Network)
+        .await?;
+    Ok(())
+}
+
+/// The gateways of `interface`, preferring the configured ones to the default routes
+async fn gateways(interface: &str, config: &IpConfig) -> Result<Vec<IpAddr>, Error> {
+    let mut res = Vec::new();
+    for (proto, configured) in [
+        ("-4", config.ipv4_gateway.map(IpAddr::from)),
+        ("-6", config.ipv6_gateway.map(IpAddr::from)),
+    ] {
+        if let Some(gateway) = configured {
+            res.push(gateway);
+            continue;
+        }
+        let routes = String::from_utf8(
+            Command::new("ip")
+                .arg(proto)
+                .arg("route")
+                .arg("show")
+                .arg("default")
+                .arg("dev")
+                .arg(interface)
+                .invoke(ErrorKind::Network)
+                .await?,
+        )?;
+        res.extend(parse_default_gateway(&routes));
+    }
+    Ok(res)
+}
+
+/// The `via` of the first route in `ip route show default` output
+pub(super) fn parse_default_gateway(routes: &str) -> Option<IpAddr> {
+    routes.lines().find_map(|line| {
+        let mut words = line.split_ascii_whitespace();
+        words.find(|w| *w == "via")?;
+        words.next()?.parse().ok()
+    })
+}
+
+/// Whether `interface` has the configured addresses and reaches a gateway within
+/// `CHECK_TIMEOUT`. A network without a gateway only needs the addresses.
+async fn check(interface: &str, config: &IpConfig) -> bool {
+    let deadline = tokio::time::Instant::now() + CHECK_TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if let Ok(true) = async {
+            let info = crate::db::model::IpInfo::for_interface(interface).await?;
+            if config
+                .ipv4
+                .map_or(false, |net| info.ipv4 != Some(net.addr()))
+                || config.ipv6.map_or(false, |net| {
+                    info.ipv6 != Some(net.addr()) && info.ipv6_ula != Some(net.addr())
+                })
+            {
+                return Ok(false);
+            }
+            let gateways = gateways(interface, config).await?;
+            if gateways.is_empty() {
+                return Ok(info.ipv4.is_some() || info.ipv6.is_some());
+            }
+            for gateway in gateways {
+                if Command::new("ping")
+                    .arg("-c")
+                    .arg("1")
+                    .arg("-W")
+                    .arg("1")
+                    .arg("-I")
+                    .arg(interface)
+                    .arg(gateway.to_string())
+                    .invoke(ErrorKind::Network)
+                    .await
+                    .is_ok()
+                {
+                    return Ok(true);
+                }
+            }
+            Ok::<_, Error>(false)
+        }
+        .await
+        {
+            return true;
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+    false
+}
+
+/// Applies `config` and checks the connection, going back to `previous` if it fails. Returns
+/// whether `config` was kept.
+#[instrument(skip_all)]
+async fn apply_checked(
+    ctx: &RpcContext,
+    interface: &str,
+    config: &IpConfig,
+    previous: &IpConfig,
+) -> Result<bool, Error> {
+    let _guard = APPLYING.lock().await;
+    let connection = connection(interface).await?;
+    let kept = match apply(&connection, config).await {
+        Ok(()) => check(interface, config).await,
+        Err(e) => {
+            tracing::error!("Error Configuring {}: {}", interface, e);
+            tracing::debug!("{:?}", e);
+            false
+        }
+    };
+    let current = if kept {
+        config
+    } else {
+        tracing::warn!(
+            "{} lost connectivity, reverting its configuration",
+            interface
+        );
+        apply(&connection, previous).await?;
+        previous
+    };
+    let mut db = ctx.db.handle();
+    let mut ip_config = crate::db::DatabaseModel::new()
+        .server_info()
+        .ip_config()
+        .get_mut(&mut db)
+        .await?;
+    if current == &IpConfig::default() {
+        ip_config.remove(interface);
+    } else {
+        ip_config.insert(interface.to_owned(), current.clone());
+    }
+    ip_config.save(&mut db).await?;
+    crate::net::dhcp::update(ctx.clone(), interface.to_owned()).await?;
+    Ok(kept)
+}
+
+/// Changes the configuration of `interface` in the background, since the connection of the
+/// client may not survive it, and notifies if it had to be reverted
+async fn change(ctx: RpcContext, interface: String, config: IpConfig) -> Result<(), Error> {
+    config.validate()?;
+    if !iface_is_physical(&interface).await {
+        return Err(Error::new(
+            eyre!("{} is not a physical network interface", interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    let previous = crate::db::DatabaseModel::new()
+        .server_info()
+        .ip_config()
+        .get(&mut ctx.db.handle())
+        .await?
+        .get(&interface)
+        .cloned()
+        .unwrap_or_default();
+    if previous == config {
+        return Ok(());
+    }
+    // fail before the change if there is nothing to apply it to
+    connection(&interface).await?;
+    tokio::spawn(async move {
+        let message = match apply_checked(&ctx, &interface, &config, &previous).await {
+            Ok(true) => return,
+            Ok(false) => format!(
+                "{} could not reach the network with its new configuration, which was reverted",
+                interface
+            ),
+            Err(e) => {
+                tracing::error!("Error Reverting Configuration of {}: {}", interface, e);
+                tracing::debug!("{:?}", e);
+                format!("Configuring {} failed: {}", interface, e)
+            }
+        };
+        if let Err(e) = ctx
+            .notification_manager
+            .notify(
+                &mut ctx.db.handle(),
+                None,
+                NotificationLevel::Error,
+                "Network Configuration Reverted".to_owned(),
+                message,
+                (),
+                None,
+            )
+            .await
+        {
+            tracing::error!("Failed to notify: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    });
+    Ok(())
+}
+
+fn parse_comma_separated(arg: &str, _: &ArgMatches) -> Result<Vec<IpAddr>, Error> {
+    arg.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.trim().parse().map_err(Error::from))
+        .collect()
+}
+
+#[command(rename = "config", subcommands(set, reset, list))]
+pub fn ip_config() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Gives `interface` static addresses and DNS servers instead of the ones from the network.
+/// If the gateway is unreachable afterwards, the previous configuration is restored.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] interface: String,
+    #[arg(long = "ipv4")] ipv4: Option<Ipv4Net>,
+    #[arg(long = "ipv4-gateway")] ipv4_gateway: Option<Ipv4Addr>,
+    #[arg(long = "ipv6")] ipv6: Option<Ipv6Net>,
+    #[arg(long = "ipv6-gateway")] ipv6_gateway: Option<Ipv6Addr>,
+    #[arg(long = "dns", parse(parse_comma_separated))] dns: Option<Vec<IpAddr>>,
+) -> Result<(), Error> {
+    change(
+        ctx,
+        interface,
+        IpConfig {
+            ipv4,
+            ipv4_gateway,
+            ipv6,
+            ipv6_gateway,
+            dns: dns.unwrap_or_default(),
+        },
+    )
+    .await
+}
+
+/// Configures `interface` from the network again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn reset(#[context] ctx: RpcContext, #[arg] interface: String) -> Result<(), Error> {
+    change(ctx, interface, IpConfig::default()).await
+}
+
+fn display_config(arg: BTreeMap<String, IpConfig>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    fn or_auto<T: ToString>(value: Option<T>) -> String {
+        value.map_or_else(|| "auto".to_owned(), |v| v.to_string())
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "INTERFACE", "IPV4", "GATEWAY", "IPV6", "GATEWAY", "DNS"]);
+    for (interface, config) in &arg {
+        table.add_row(row![
+            interface,
+            or_auto(config.ipv4),
+            or_auto(config.ipv4_gateway),
+            or_auto(config.ipv6),
+            or_auto(config.ipv6_gateway),
+            if config.dns.is_empty() {
+                "auto".to_owned()
+            } else {
+                config
+                    .dns
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The interfaces that are not configured from the network
+#[command(display(display_config), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<String, IpConfig>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .ip_config()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn configs() {
+    let config = IpConfig {
+        ipv4: Some("192.168.1.10/24".parse().unwrap()),
+        ipv4_gateway: Some("192.168.1.1".parse().unwrap()),
+        dns: vec![
+            "1.1.1.1".parse().unwrap(),
+            "2606:4700::1111".parse().unwrap(),
+        ],
+        ..Default::default()
+    };
+    assert!(config.validate().is_ok());
+    let settings = config
+        .nmcli_settings()
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(settings["ipv4.method"], "manual");
+    assert_eq!(settings["ipv4.addresses"], "192.168.1.10/24");
+    assert_eq!(settings["ipv6.method"], "auto");
+    assert_eq!(settings["ipv6.addresses"], "");
+    assert_eq!(settings["ipv4.dns"], "1.1.1.1");
+    assert_eq!(settings["ipv6.ignore-auto-dns"], "yes");
+    assert!(IpConfig {
+        ipv4_gateway: Some("10.0.0.1".parse().unwrap()),
+        ..config.clone()
+    }
+    .validate()
+    .is_err());
+    assert!(IpConfig {
+        ipv4: None,
+        ..config
+    }
+    .validate()
+    .is_err());
+    assert_eq!(
+        parse_default_gateway("default via 192.168.1.1 proto dhcp metric 100 "),
+        Some("192.168.1.1".parse().unwrap())
+    );
+    assert_eq!(parse_default_gateway(""), None);
+}
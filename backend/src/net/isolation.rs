@@ -0,0 +1,905 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use bollard::network::{CreateNetworkOptions, InspectNetworkOptions, ListNetworksOptions};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ipnet::Ipv4Net;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::firewall::FirewallAction;
+use crate::net::interface::InterfaceId;
+use crate::net::net_controller::NetController;
+use crate::procedure::docker::{runtime, DockerProcedure};
+use crate::s9pk::manifest::{Manifest, PackageId};
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Only lets the containers attached to the networks of other packages reach what they may
+const TABLE: &str = "startos-isolation";
+/// The networks of the packages are carved out of it, after the `/24` of `start9`, which only
+/// holds `HOST_IP` now
+const CONTAINER_SUBNET: [u8; 2] = [172, 18];
+
+lazy_static::lazy_static! {
+    /// Held while a network is created, so two containers of a package starting together do
+    /// not each create one
+    static ref NETWORKS: Mutex<()> = Mutex::new(());
+}
+
+/// See `net isolation`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct IsolationSettings {
+    /// Checked in order. Without one that matches, a package reaches every interface of its
+    /// dependencies, and nothing of the other packages.
+    pub rules: Vec<IsolationRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IsolationRule {
+    pub action: FirewallAction,
+    pub package: PackageId,
+    pub target: PackageId,
+    /// Every interface of `target` if unset
+    pub interface: Option<InterfaceId>,
+}
+impl IsolationRule {
+    fn matches(&self, package: &PackageId, target: &PackageId, interface: &InterfaceId) -> bool {
+        &self.package == package
+            && &self.target == target
+            && self.interface.as_ref().map_or(true, |i| i == interface)
+    }
+}
+
+/// The docker network of `package`, which its containers are started on
+pub fn network_name(package: &PackageId) -> String {
+    format!("start9-{}", package)
+}
+
+/// The first `/24` no network overlaps
+fn free_subnet(used: &BTreeSet<Ipv4Net>) -> Option<Ipv4Net> {
+    (1..=255)
+        .map(|n| {
+            Ipv4Net::new(
+                Ipv4Addr::new(CONTAINER_SUBNET[0], CONTAINER_SUBNET[1], n, 0),
+                24,
+            )
+            .unwrap()
+        })
+        .find(|subnet| {
+            !used
+                .iter()
+                .any(|u| u.contains(subnet) || subnet.contains(u))
+        })
+}
+
+/// Creates the network of `package` if it does not exist yet, and returns its name
+#[instrument(skip_all)]
+pub async fn ensure_network(
+    docker: &bollard::Docker,
+    package: &PackageId,
+) -> Result<String, Error> {
+    let _guard = NETWORKS.lock().await;
+    let name = network_name(package);
+    let networks = docker
+        .list_networks(None::<ListNetworksOptions<String>>)
+        .await?;
+    if networks.iter().any(|n| n.name.as_deref() == Some(&*name)) {
+        return Ok(name);
+    }
+    let used = networks
+        .iter()
+        .flat_map(|n| n.ipam.iter().flat_map(|i| i.config.iter().flatten()))
+        .filter_map(|c| c.subnet.as_deref()?.parse::<Ipv4Net>().ok())
+        .map(|s| s.trunc())
+        .collect();
+    let subnet = free_subnet(&used).ok_or_else(|| {
+        Error::new(
+            eyre!("There is no subnet left for the network of {}", package),
+            ErrorKind::Network,
+        )
+    })?;
+    docker
+        .create_network(CreateNetworkOptions {
+            name: name.as_str(),
+            driver: "bridge",
+            ipam: bollard::models::Ipam {
+                config: Some(vec![bollard::models::IpamConfig {
+                    subnet: Some(subnet.to_string()),
+                    gateway: subnet.hosts().next().map(|g| g.to_string()),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            },
+            options: runtime().network_options(),
+            ..Default::default()
+        })
+        .await?;
+    tracing::info!("Created network {} at {}", name, subnet);
+    Ok(name)
+}
+
+/// Removes the network of an uninstalled package
+pub async fn remove_network(docker: &bollard::Docker, package: &PackageId) -> Result<(), Error> {
+    match docker.remove_network(&network_name(package)).await {
+        Ok(())
+        | Err(bollard::errors::Error::DockerResponseServerError {
+            status_code: 404, // NOT FOUND
+            ..
+        }) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The internal ports of each interface of `manifest`
+fn interface_ports(manifest: &Manifest) -> BTreeMap<InterfaceId, BTreeSet<u16>> {
+    manifest
+        .interfaces
+        .0
+        .iter()
+        .map(|(id, interface)| {
+            let lan = interface
+                .lan_config
+                .iter()
+                .flatten()
+                .map(|(_, l)| l.internal);
+            let tor = interface
+                .tor_config
+                .iter()
+                .flat_map(|t| t.port_mapping.values())
+                .map(|p| p.0);
+            let raw = interface
+                .raw_config
+                .iter()
+                .flatten()
+                .map(|(_, r)| r.internal);
+            (id.clone(), lan.chain(tor).chain(raw).collect())
+        })
+        .collect()
+}
+
+/// The interfaces of `target` that `package` may reach: those the first rule that matches
+/// allows, or every one without one if `target` is a dependency of `package`
+fn allowed(
+    rules: &[IsolationRule],
+    package: &PackageId,
+    target: &PackageId,
+    dependency: bool,
+    interfaces: impl IntoIterator<Item = InterfaceId>,
+) -> BTreeSet<InterfaceId> {
+    interfaces
+        .into_iter()
+        .filter(|interface| {
+            match rules
+                .iter()
+                .find(|r| r.matches(package, target, interface))
+                .map(|r| r.action)
+            {
+                Some(FirewallAction::Allow) => true,
+                Some(FirewallAction::Deny) => false,
+                None => dependency,
+            }
+        })
+        .collect()
+}
+
+/// The interfaces of the other packages each package may reach, by package and target,
+/// along with their internal ports
+type Reachable = BTreeMap<PackageId, BTreeMap<PackageId, BTreeMap<InterfaceId, BTreeSet<u16>>>>;
+
+async fn reachable<Db: DbHandle>(db: &mut Db) -> Result<Reachable, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .isolation()
+        .get(db)
+        .await?
+        .into_owned();
+    let packages = crate::db::DatabaseModel::new()
+        .package_data()
+        .get(db)
+        .await?
+        .into_owned();
+    let installed = packages
+        .0
+        .iter()
+        .filter_map(|(id, pde)| Some((id, pde.installed()?)))
+        .collect::<BTreeMap<_, _>>();
+    let mut res = Reachable::new();
+    for (package, package_installed) in &installed {
+        for (target, target_installed) in &installed {
+            if package == target {
+                continue;
+            }
+            let ports = interface_ports(&target_installed.manifest);
+            let dependency = package_installed
+                .current_dependencies
+                .0
+                .contains_key(*target);
+            let interfaces = allowed(
+                &settings.rules,
+                package,
+                target,
+                dependency,
+                ports.keys().cloned(),
+            );
+            if interfaces.is_empty() {
+                continue;
+            }
+            res.entry((*package).clone()).or_default().insert(
+                (*target).clone(),
+                interfaces
+                    .into_iter()
+                    .map(|i| {
+                        let p = ports.get(&i).cloned().unwrap_or_default();
+                        (i, p)
+                    })
+                    .collect(),
+            );
+        }
+    }
+    Ok(res)
+}
+
+#[derive(Default)]
+struct Containers {
+    ips: BTreeMap<PackageId, Ipv4Addr>,
+    /// The ports of the other packages each package may reach, by package and target
+    policy: BTreeMap<PackageId, BTreeMap<PackageId, BTreeSet<u16>>>,
+    /// The address of the main container of each package on the networks of the running
+    /// packages it may reach, by package and target
+    attached: BTreeMap<PackageId, BTreeMap<PackageId, Ipv4Addr>>,
+    /// The package of each running procedure container, by name, and its addresses on the
+    /// networks of the running packages it may reach, by target
+    procedures: BTreeMap<String, (PackageId, BTreeMap<PackageId, Ipv4Addr>)>,
+}
+
+/// Attaches the main containers of the packages to the networks of those they may reach, and
+/// owns the `bridge startos-isolation` nftables table, which only lets them through there to
+/// the ports of the interfaces they may reach
+#[derive(Default)]
+pub struct IsolationController {
+    containers: Mutex<Containers>,
+}
+impl IsolationController {
+    pub(super) async fn add_container(
+        &self,
+        package: PackageId,
+        ip: Ipv4Addr,
+    ) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.get(&package) == Some(&ip) {
+            return Ok(());
+        }
+        containers.ips.insert(package.clone(), ip);
+        // a new container, attached to nothing yet
+        containers.attached.remove(&package);
+        sync(&mut containers).await
+    }
+    pub(super) async fn remove_container(&self, package: &PackageId) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.ips.remove(package).is_none() {
+            return Ok(());
+        }
+        containers.attached.remove(package);
+        sync(&mut containers).await
+    }
+    async fn set_policy(&self, reachable: &Reachable) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        let policy = reachable
+            .iter()
+            .map(|(package, targets)| {
+                (
+                    package.clone(),
+                    targets
+                        .iter()
+                        .map(|(target, interfaces)| {
+                            (
+                                target.clone(),
+                                interfaces.values().flatten().copied().collect(),
+                            )
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+        if containers.policy == policy {
+            return Ok(());
+        }
+        containers.policy = policy;
+        sync(&mut containers).await
+    }
+    /// See [`ProcedureNetworks::attach`]. The addresses of the container are picked rather
+    /// than handed out as it starts, so it is filtered from its start, as the main container.
+    async fn attach_procedure(
+        &self,
+        docker: &bollard::Docker,
+        package: &PackageId,
+        container: &str,
+    ) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        let wanted = containers
+            .policy
+            .get(package)
+            .into_iter()
+            .flat_map(|targets| targets.keys())
+            .filter(|target| containers.ips.contains_key(*target))
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut attached = BTreeMap::new();
+        for target in wanted {
+            let network = network_name(&target);
+            let res = async {
+                let ip = procedure_ip(docker, &network).await?;
+                runtime()
+                    .command()
+                    .arg("network")
+                    .arg("connect")
+                    .arg(format!("--ip={}", ip))
+                    .arg(&network)
+                    .arg(container)
+                    .invoke(ErrorKind::Docker)
+                    .await?;
+                Ok::<_, Error>(ip)
+            }
+            .await;
+            match res {
+                Ok(ip) => {
+                    attached.insert(target, ip);
+                }
+                Err(e) => tracing::error!("Failed to attach {} to {}: {}", container, target, e),
+            }
+        }
+        if attached.is_empty() {
+            return Ok(());
+        }
+        containers
+            .procedures
+            .insert(container.to_owned(), (package.clone(), attached));
+        apply(&containers).await
+    }
+    /// Forgets the addresses of a procedure container that exited, which the runtime frees
+    async fn detach_procedure(&self, container: &str) -> Result<(), Error> {
+        let mut containers = self.containers.lock().await;
+        if containers.procedures.remove(container).is_none() {
+            return Ok(());
+        }
+        apply(&containers).await
+    }
+}
+
+/// The networks a procedure container was attached to, see [`ProcedureNetworks::attach`].
+/// Dropping it forgets its addresses there.
+pub struct ProcedureNetworks {
+    net: Arc<NetController>,
+    container: String,
+}
+impl ProcedureNetworks {
+    /// Attaches `container`, created for a procedure of `package` but not started yet, to the
+    /// networks of the running packages `package` may reach, until it exits
+    #[instrument(skip_all)]
+    pub async fn attach(
+        net: &Arc<NetController>,
+        docker: &bollard::Docker,
+        package: &PackageId,
+        container: &str,
+    ) -> Result<Self, Error> {
+        net.isolation
+            .attach_procedure(docker, package, container)
+            .await?;
+        Ok(Self {
+            net: net.clone(),
+            container: container.to_owned(),
+        })
+    }
+}
+impl Drop for ProcedureNetworks {
+    fn drop(&mut self) {
+        let net = self.net.clone();
+        let container = std::mem::take(&mut self.container);
+        tokio::spawn(async move {
+            if let Err(e) = net.isolation.detach_procedure(&container).await {
+                tracing::error!("Failed to detach {}: {}", container, e);
+                tracing::debug!("{:?}", e);
+            }
+        });
+    }
+}
+
+/// The last free address of `network`, for a procedure container, as the runtime hands out
+/// the first ones to the containers it attaches itself
+async fn procedure_ip(docker: &bollard::Docker, network: &str) -> Result<Ipv4Addr, Error> {
+    let network = docker
+        .inspect_network(network, None::<InspectNetworkOptions<String>>)
+        .await?;
+    let subnet = network
+        .ipam
+        .iter()
+        .flat_map(|i| i.config.iter().flatten())
+        .find_map(|c| c.subnet.as_deref()?.parse::<Ipv4Net>().ok())
+        .ok_or_else(|| Error::new(eyre!("Network has no IPv4 subnet"), ErrorKind::Docker))?;
+    let used = network
+        .containers
+        .iter()
+        .flatten()
+        .filter_map(|(_, c)| c.ipv4_address.as_deref()?.parse::<Ipv4Net>().ok())
+        .map(|ip| ip.addr())
+        .collect();
+    free_address(subnet, &used).ok_or_else(|| {
+        Error::new(
+            eyre!("There is no address left on the network"),
+            ErrorKind::Network,
+        )
+    })
+}
+
+/// The last address of `subnet` that is not `used`
+fn free_address(subnet: Ipv4Net, used: &BTreeSet<Ipv4Addr>) -> Option<Ipv4Addr> {
+    subnet.hosts().rev().find(|ip| !used.contains(ip))
+}
+
+/// The address of `container` on `network`
+async fn attached_ip(container: &str, network: &str) -> Result<Ipv4Addr, Error> {
+    #[derive(Deserialize)]
+    struct Endpoint {
+        #[serde(rename = "IPAddress")]
+        ip_address: String,
+    }
+    let out = runtime()
+        .command()
+        .arg("inspect")
+        .arg("--format")
+        .arg("{{json .NetworkSettings.Networks}}")
+        .arg(container)
+        .invoke(ErrorKind::Docker)
+        .await?;
+    let networks: BTreeMap<String, Endpoint> =
+        serde_json::from_slice(&out).with_kind(ErrorKind::Deserialization)?;
+    Ok(networks
+        .get(network)
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("{} is not attached to {}", container, network),
+                ErrorKind::Docker,
+            )
+        })?
+        .ip_address
+        .parse()
+        .with_kind(ErrorKind::ParseNetAddress)?)
+}
+
+/// Attaches and detaches the main containers to match the policy, then replaces the table
+async fn sync(containers: &mut Containers) -> Result<(), Error> {
+    for package in containers.ips.keys().cloned().collect::<Vec<_>>() {
+        let container = DockerProcedure::container_name(&package, None);
+        let wanted = containers
+            .policy
+            .get(&package)
+            .into_iter()
+            .flat_map(|targets| targets.keys())
+            .filter(|target| containers.ips.contains_key(*target))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let attached = containers.attached.entry(package.clone()).or_default();
+        for target in attached
+            .keys()
+            .filter(|t| !wanted.contains(*t))
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            if let Err(e) = runtime()
+                .command()
+                .arg("network")
+                .arg("disconnect")
+                .arg(network_name(&target))
+                .arg(&container)
+                .invoke(ErrorKind::Docker)
+                .await
+            {
+                tracing::warn!("Failed to detach {} from {}: {}", package, target, e);
+            }
+            attached.remove(&target);
+        }
+        for target in wanted {
+            if attached.contains_key(&target) {
+                continue;
+            }
+            let network = network_name(&target);
+            let res = async {
+                runtime()
+                    .command()
+                    .arg("network")
+                    .arg("connect")
+                    .arg(&network)
+                    .arg(&container)
+                    .invoke(ErrorKind::Docker)
+                    .await?;
+                attached_ip(&container, &network).await
+            }
+            .await;
+            match res {
+                Ok(ip) => {
+                    attached.insert(target, ip);
+                }
+                // it just cannot reach the target
+                Err(e) => tracing::error!("Failed to attach {} to {}: {}", package, target, e),
+            }
+        }
+    }
+    containers.attached.retain(|_, targets| !targets.is_empty());
+    apply(containers).await
+}
+
+/// Replaces the table, to filter the attached main and procedure containers
+async fn apply(containers: &Containers) -> Result<(), Error> {
+    let attached = containers
+        .attached
+        .iter()
+        .chain(containers.procedures.values().map(|(p, t)| (p, t)))
+        .collect::<Vec<_>>();
+    let links = attached
+        .iter()
+        .flat_map(|(package, targets)| {
+            let policy = containers.policy.get(*package);
+            let ips = &containers.ips;
+            targets.iter().filter_map(move |(target, from)| {
+                Some(Link {
+                    from: *from,
+                    to: *ips.get(target)?,
+                    ports: policy?.get(target)?.clone(),
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    if attached.is_empty() {
+        if table_exists().await {
+            Command::new("nft")
+                .arg("delete")
+                .arg("table")
+                .arg("bridge")
+                .arg(TABLE)
+                .invoke(ErrorKind::Network)
+                .await?;
+        }
+        return Ok(());
+    }
+    let attachments = attached
+        .iter()
+        .flat_map(|(_, targets)| targets.values().copied())
+        .collect::<Vec<_>>();
+    nft(&ruleset(&links, &attachments)).await
+}
+
+async fn table_exists() -> bool {
+    Command::new("nft")
+        .arg("list")
+        .arg("table")
+        .arg("bridge")
+        .arg(TABLE)
+        .invoke(ErrorKind::Network)
+        .await
+        .is_ok()
+}
+
+async fn nft(script: &str) -> Result<(), Error> {
+    let mut cmd = Command::new("nft")
+        .arg("-f")
+        .arg("-")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = cmd.stdin.take() {
+        use tokio::io::AsyncWriteExt;
+        stdin.write_all(script.as_bytes()).await?;
+    }
+    let res = cmd.wait_with_output().await?;
+    crate::ensure_code!(
+        res.status.success(),
+        ErrorKind::Network,
+        "{}",
+        String::from_utf8_lossy(&res.stderr)
+    );
+    Ok(())
+}
+
+/// A package reaching another, from its address on the network of the other
+struct Link {
+    from: Ipv4Addr,
+    to: Ipv4Addr,
+    ports: BTreeSet<u16>,
+}
+
+/// The `attachments` only start connections to the `ports` of their `link`, and nothing else
+/// connects to them. Connections between the containers of a package are not filtered, and
+/// those between the networks of packages are dropped by the runtime, see
+/// `ContainerRuntime::network_options`.
+fn ruleset(links: &[Link], attachments: &[Ipv4Addr]) -> String {
+    let set = |items: Vec<String>| format!("{{ {} }}", items.join(", "));
+    let mut rules = vec!["ct state established,related accept".to_owned()];
+    for link in links {
+        if link.ports.is_empty() {
+            continue;
+        }
+        rules.push(format!(
+            "ip saddr {} ip daddr {} meta l4proto {{ tcp, udp }} th dport {} accept",
+            link.from,
+            link.to,
+            set(link.ports.iter().map(|p| p.to_string()).collect())
+        ));
+    }
+    let attachments = set(attachments.iter().map(|ip| ip.to_string()).collect());
+    rules.push(format!("ip saddr {} drop", attachments));
+    rules.push(format!("ip daddr {} drop", attachments));
+    format!(
+        "table bridge {table}\ndelete table bridge {table}\ntable bridge {table} {{\n\tchain forward {{\n\t\ttype filter hook forward priority filter; policy accept;\n{rules}\n\t}}\n}}\n",
+        table = TABLE,
+        rules = rules
+            .iter()
+            .map(|r| format!("\t\t{}", r))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+/// Recomputes what each package may reach, from the rules and the dependencies of the
+/// installed packages. Done as a package starts, so the dependencies it was installed or
+/// updated with are picked up.
+#[instrument(skip_all)]
+pub async fn refresh<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    net.isolation.set_policy(&reachable(db).await?).await
+}
+
+async fn update<F: FnOnce(&mut IsolationSettings) -> Result<(), Error>>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .isolation()
+        .get_mut(&mut db)
+        .await?;
+    f(&mut settings)?;
+    settings.save(&mut db).await?;
+    refresh(&ctx.net_controller, &mut db).await
+}
+
+#[command(subcommands(allow, deny, remove, list))]
+pub fn isolation() -> Result<(), Error> {
+    Ok(())
+}
+
+async fn add_rule(
+    ctx: &RpcContext,
+    action: FirewallAction,
+    package: PackageId,
+    target: PackageId,
+    interface: Option<InterfaceId>,
+) -> Result<(), Error> {
+    if package == target {
+        return Err(Error::new(
+            eyre!("The containers of a package always reach each other"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let rule = IsolationRule {
+        action,
+        package,
+        target,
+        interface,
+    };
+    update(ctx, |settings| {
+        if settings.rules.contains(&rule) {
+            return Err(Error::new(
+                eyre!("The rule already exists"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        settings.rules.push(rule);
+        Ok(())
+    })
+    .await
+}
+
+/// Lets `package` reach `--interface`, or every interface, of `target`, whether or not it is a
+/// dependency. Takes effect on running packages.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn allow(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] target: PackageId,
+    #[arg(long = "interface")] interface: Option<InterfaceId>,
+) -> Result<(), Error> {
+    add_rule(&ctx, FirewallAction::Allow, package, target, interface).await
+}
+
+/// Keeps `package` from reaching `--interface`, or every interface, of `target`, even if it is
+/// a dependency. Takes effect on running packages.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn deny(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] target: PackageId,
+    #[arg(long = "interface")] interface: Option<InterfaceId>,
+) -> Result<(), Error> {
+    add_rule(&ctx, FirewallAction::Deny, package, target, interface).await
+}
+
+/// Removes the rule at `index` in `net isolation list`
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] index: usize) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        if index >= settings.rules.len() {
+            return Err(Error::new(
+                eyre!("There is no rule {}", index),
+                ErrorKind::NotFound,
+            ));
+        }
+        settings.rules.remove(index);
+        Ok(())
+    })
+    .await
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IsolationInfo {
+    #[serde(flatten)]
+    pub settings: IsolationSettings,
+    /// The interfaces of the other packages each package may reach, by package and target
+    pub reachable: BTreeMap<PackageId, BTreeMap<PackageId, BTreeSet<InterfaceId>>>,
+}
+
+fn display_isolation(arg: IsolationInfo, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "#", "ACTION", "PACKAGE", "TARGET", "INTERFACE"]);
+    for (index, rule) in arg.settings.rules.iter().enumerate() {
+        table.add_row(row![
+            index,
+            match rule.action {
+                FirewallAction::Allow => "allow",
+                FirewallAction::Deny => "deny",
+            },
+            &*rule.package,
+            &*rule.target,
+            rule.interface
+                .as_ref()
+                .map_or_else(|| "*".to_owned(), |i| i.to_string())
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "REACHES", "INTERFACES"]);
+    for (package, targets) in &arg.reachable {
+        for (target, interfaces) in targets {
+            table.add_row(row![
+                &**package,
+                &**target,
+                interfaces
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The rules, and the interfaces of the other packages each package reaches by them
+#[command(display(display_isolation), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<IsolationInfo, Error> {
+    let mut db = ctx.db.handle();
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .isolation()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    let reachable = reachable(&mut db)
+        .await?
+        .into_iter()
+        .map(|(package, targets)| {
+            (
+                package,
+                targets
+                    .into_iter()
+                    .map(|(target, interfaces)| (target, interfaces.into_keys().collect()))
+                    .collect(),
+            )
+        })
+        .collect();
+    Ok(IsolationInfo {
+        settings,
+        reachable,
+    })
+}
+
+#[test]
+fn policies() {
+    let id = |s: &str| s.parse::<PackageId>().unwrap();
+    let interface = |s: &str| InterfaceId::from(models::Id::try_from(s).unwrap());
+    let rules = vec![
+        IsolationRule {
+            action: FirewallAction::Deny,
+            package: id("lnd"),
+            target: id("bitcoind"),
+            interface: Some(interface("peer")),
+        },
+        IsolationRule {
+            action: FirewallAction::Allow,
+            package: id("mempool"),
+            target: id("electrs"),
+            interface: None,
+        },
+    ];
+    let interfaces = || vec![interface("rpc"), interface("peer")];
+    assert_eq!(
+        allowed(&rules, &id("lnd"), &id("bitcoind"), true, interfaces()),
+        [interface("rpc")].into_iter().collect()
+    );
+    assert!(allowed(&rules, &id("lnd"), &id("electrs"), false, interfaces()).is_empty());
+    assert_eq!(
+        allowed(&rules, &id("mempool"), &id("electrs"), false, interfaces()).len(),
+        2
+    );
+
+    let used = ["172.18.0.0/24", "172.18.1.0/24"]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+    assert_eq!(free_subnet(&used), Some("172.18.2.0/24".parse().unwrap()));
+    assert_eq!(
+        free_subnet(&["172.18.0.0/16".parse().unwrap()].into_iter().collect()),
+        None
+    );
+    let subnet = "172.18.1.0/24".parse().unwrap();
+    assert_eq!(
+        free_address(
+            subnet,
+            &[Ipv4Addr::new(172, 18, 1, 254)].into_iter().collect()
+        ),
+        Some(Ipv4Addr::new(172, 18, 1, 253))
+    );
+
+    let script = ruleset(
+        &[Link {
+            from: Ipv4Addr::new(172, 18, 1, 3),
+            to: Ipv4Addr::new(172, 18, 1, 2),
+            ports: [8332, 28332].into_iter().collect(),
+        }],
+        &[Ipv4Addr::new(172, 18, 1, 3), Ipv4Addr::new(172, 18, 2, 4)],
+    );
+    let rules = script.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    assert_eq!(rules[0], "table bridge startos-isolation");
+    assert!(rules.contains(
+        &"ip saddr 172.18.1.3 ip daddr 172.18.1.2 meta l4proto { tcp, udp } th dport { 8332, 28332 } accept"
+    ));
+    assert!(rules.contains(&"ip saddr { 172.18.1.3, 172.18.2.4 } drop"));
+    assert!(rules.contains(&"ip daddr { 172.18.1.3, 172.18.2.4 } drop"));
+}
@@ -1,17 +1,28 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
 use color_eyre::eyre::eyre;
 use ed25519_dalek::{ExpandedSecretKey, SecretKey};
+use itertools::Itertools;
 use models::{Id, InterfaceId, PackageId};
 use openssl::pkey::{PKey, Private};
 use openssl::sha::Sha256;
 use openssl::x509::X509;
 use p256::elliptic_curve::pkcs8::EncodePrivateKey;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
 use sqlx::PgExecutor;
 use ssh_key::private::Ed25519PrivateKey;
+use ssh_key::public::Ed25519PublicKey;
 use torut::onion::{OnionAddressV3, TorSecretKeyV3};
+use tracing::instrument;
 use zeroize::Zeroize;
 
+use crate::context::RpcContext;
 use crate::net::ssl::CertPair;
-use crate::Error;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
 
 // TODO: delete once we may change tor addresses
 async fn compat(
@@ -120,12 +131,12 @@ impl Key {
     pub fn new(interface: Option<(PackageId, InterfaceId)>) -> Self {
         Self::from_bytes(interface, rand::random())
     }
-    pub(super) fn with_certs(self, certs: CertPair, int: X509, root: X509) -> KeyInfo {
+    pub(super) fn with_certs(self, certs: CertPair, int: X509, chain: Vec<X509>) -> KeyInfo {
         KeyInfo {
             key: self,
             certs,
             int,
-            root,
+            chain,
         }
     }
     pub async fn for_package(
@@ -241,7 +252,8 @@ pub struct KeyInfo {
     key: Key,
     certs: CertPair,
     int: X509,
-    root: X509,
+    /// The certificates above `int`, up to the root CA
+    chain: Vec<X509>,
 }
 impl KeyInfo {
     pub fn key(&self) -> &Key {
@@ -254,16 +266,287 @@ impl KeyInfo {
         &self.int
     }
     pub fn root_ca(&self) -> &X509 {
-        &self.root
+        self.chain.last().unwrap_or(&self.int)
     }
     pub fn fullchain_ed25519(&self) -> Vec<&X509> {
-        vec![&self.certs.ed25519, &self.int, &self.root]
+        [&self.certs.ed25519, &self.int]
+            .into_iter()
+            .chain(&self.chain)
+            .collect()
     }
     pub fn fullchain_nistp256(&self) -> Vec<&X509> {
-        vec![&self.certs.nistp256, &self.int, &self.root]
+        [&self.certs.nistp256, &self.int]
+            .into_iter()
+            .chain(&self.chain)
+            .collect()
     }
 }
 
+/// Keeps `key` in `retired_keys`, for `net keys retired`
+async fn retire(secrets: impl PgExecutor<'_>, key: &Key) -> Result<(), Error> {
+    let (package, interface) = key
+        .interface
+        .as_ref()
+        .map(|(package, interface)| (package.to_string(), interface.to_string()))
+        .unzip();
+    let base = key.base.as_slice();
+    let tor_key = key.tor_key.as_slice();
+    sqlx::query!(
+        "INSERT INTO retired_keys (package, interface, key, tor_key) VALUES ($1, $2, $3, $4)",
+        package,
+        interface,
+        base,
+        tor_key,
+    )
+    .execute(secrets)
+    .await?;
+    Ok(())
+}
+
+/// Replaces the keys of `interface` of `package`, and the addresses shown for it
+async fn rotate_interface(
+    ctx: &RpcContext,
+    package: &PackageId,
+    interface: &InterfaceId,
+) -> Result<(), Error> {
+    let mut tx = ctx.secret_store.begin().await?;
+    let old = Key::for_interface(&mut tx, Some((package.clone(), interface.clone()))).await?;
+    retire(&mut tx, &old).await?;
+    let new = Key::new(old.interface());
+    let base = new.base.as_slice();
+    let tor_key = new.tor_key.as_slice();
+    sqlx::query!(
+        "UPDATE network_keys SET key = $3 WHERE package = $1 AND interface = $2",
+        **package,
+        **interface,
+        base,
+    )
+    .execute(&mut tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO tor (package, interface, key) VALUES ($1, $2, $3) ON CONFLICT (package, interface) DO UPDATE SET key = EXCLUDED.key",
+        **package,
+        **interface,
+        tor_key,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    let mut db = ctx.db.handle();
+    let mut addresses = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.interface_addresses())
+        .get_mut(&mut db)
+        .await?;
+    if let Some(addrs) = addresses
+        .as_mut()
+        .and_then(|addresses| addresses.0.get_mut(interface))
+    {
+        if addrs.tor_address.is_some() {
+            addrs.tor_address = Some(new.tor_address().to_string());
+        }
+        if addrs.lan_address.is_some() {
+            addrs.lan_address = Some(new.local_address());
+        }
+    }
+    addresses.save(&mut db).await?;
+    Ok(())
+}
+
+/// Replaces the keys of the server itself, and the addresses and SSH host key shown for it
+async fn rotate_server(ctx: &RpcContext) -> Result<(), Error> {
+    let mut account = ctx.account.write().await;
+    let mut tx = ctx.secret_store.begin().await?;
+    retire(&mut tx, &account.key).await?;
+    let new = Key::new(None);
+    let base = new.base.as_slice();
+    sqlx::query!(
+        "UPDATE account SET network_key = $1, tor_key = NULL WHERE id = 0",
+        base
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    let mut db = ctx.db.handle();
+    let mut server_info = crate::db::DatabaseModel::new()
+        .server_info()
+        .get_mut(&mut db)
+        .await?;
+    server_info.tor_address = format!("https://{}", new.tor_address()).parse()?;
+    server_info.pubkey =
+        ssh_key::PublicKey::from(Ed25519PublicKey::from(&new.ssh_key())).to_openssh()?;
+    server_info.save(&mut db).await?;
+    account.key = new;
+    Ok(())
+}
+
+#[command(subcommands(rotate, retired))]
+pub fn keys() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Replaces the Tor and TLS keys of `interface` of `package`, of every interface of `package`
+/// without `interface`, or of the server itself without either, e.g. after they may have been
+/// leaked. Their addresses change, and the old keys are kept, see `net keys retired`. Running
+/// packages, or the server, are restarted to use the new keys.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn rotate(
+    #[context] ctx: RpcContext,
+    #[arg(long = "package")] package: Option<PackageId>,
+    #[arg(long = "interface")] interface: Option<InterfaceId>,
+    #[arg(long = "confirm", default)] confirm: bool,
+) -> Result<(), Error> {
+    let Some(package) = package else {
+        if interface.is_some() {
+            return Err(Error::new(
+                eyre!("--interface requires --package"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        if !confirm {
+            return Err(Error::new(
+                eyre!("Rotating the keys of the server changes its Tor and LAN addresses and its SSH host key, and restarts it. Pass --confirm to proceed."),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        rotate_server(&ctx).await?;
+        return crate::shutdown::restart(ctx).await;
+    };
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    let interfaces = manifest
+        .interfaces
+        .0
+        .iter()
+        .filter(|(id, i)| {
+            interface
+                .as_ref()
+                .map_or(true, |interface| interface == *id)
+                && (i.tor_config.is_some() || i.lan_config.is_some())
+        })
+        .map(|(id, _)| id.clone())
+        .collect::<Vec<_>>();
+    if interfaces.is_empty() {
+        return Err(Error::new(
+            match &interface {
+                Some(interface) => eyre!("{} has no Tor or LAN interface {}", package, interface),
+                None => eyre!("{} has no Tor or LAN interfaces", package),
+            },
+            ErrorKind::NotFound,
+        ));
+    }
+    if !confirm {
+        return Err(Error::new(
+            eyre!(
+                "Rotating the keys of {} changes the Tor and LAN addresses of {}, and restarts it if it is running. Pass --confirm to proceed.",
+                package,
+                interfaces.iter().join(", ")
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    for interface in &interfaces {
+        rotate_interface(&ctx, &package, interface).await?;
+    }
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(&mut db)
+        .await?
+        .into_owned();
+    if matches!(status, Some(MainStatus::Running { .. })) {
+        crate::control::restart(ctx.clone(), package).await?;
+    }
+    Ok(())
+}
+
+/// Keys replaced by `net keys rotate`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RetiredKey {
+    pub id: i32,
+    /// `None` for the keys of the server itself
+    pub package: Option<PackageId>,
+    pub interface: Option<InterfaceId>,
+    /// The onion address the key was used for
+    pub tor_address: String,
+    pub retired_at: DateTime<Utc>,
+}
+
+fn display_retired(arg: Vec<RetiredKey>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ID", "PACKAGE", "INTERFACE", "TOR ADDRESS", "RETIRED AT"]);
+    for key in &arg {
+        table.add_row(row![
+            key.id,
+            key.package.as_deref().map_or("-", |p| p.as_str()),
+            key.interface.as_deref().map_or("-", |i| i.as_str()),
+            &key.tor_address,
+            key.retired_at.to_rfc3339()
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The keys replaced by `net keys rotate`, oldest first
+#[command(display(display_retired), metadata(read_only = true, admin = true))]
+pub async fn retired(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<RetiredKey>, Error> {
+    sqlx::query!("SELECT id, package, interface, tor_key, retired_at FROM retired_keys ORDER BY id")
+        .fetch_all(&ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok(RetiredKey {
+                id: r.id,
+                package: r.package.map(|p| p.parse()).transpose()?,
+                interface: r
+                    .interface
+                    .map(Id::try_from)
+                    .transpose()?
+                    .map(InterfaceId::from),
+                tor_address: TorSecretKeyV3::from(<[u8; 64]>::try_from(r.tor_key).map_err(
+                    |e: Vec<u8>| {
+                        Error::new(
+                            eyre!("Invalid length for tor key {} expected 64", e.len()),
+                            ErrorKind::Database,
+                        )
+                    },
+                )?)
+                .public()
+                .get_onion_address()
+                .to_string(),
+                retired_at: DateTime::from_utc(r.retired_at, Utc),
+            })
+        })
+        .collect()
+}
+
 #[test]
 pub fn test_keygen() {
     let key = Key::new(None);
@@ -1,20 +1,39 @@
 use std::collections::BTreeMap;
-use std::net::Ipv4Addr;
+use std::net::IpAddr;
 use std::sync::{Arc, Weak};
 
+use clap::ArgMatches;
 use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
 use tokio::process::{Child, Command};
 use tokio::sync::Mutex;
 use tracing::instrument;
 
-use crate::util::Invoke;
-use crate::{Error, ResultExt};
+use crate::context::RpcContext;
+use crate::net::acme::DomainTarget;
+use crate::net::interface::Interface;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, ResultExt};
 
-pub async fn resolve_mdns(hostname: &str) -> Result<Ipv4Addr, Error> {
+/// Prefers the IPv4 address of `hostname`, falling back to IPv6 on IPv6-only LANs
+pub async fn resolve_mdns(hostname: &str) -> Result<IpAddr, Error> {
+    match resolve_mdns_proto(hostname, "-4").await {
+        Ok(ip) => Ok(ip),
+        Err(e) => resolve_mdns_proto(hostname, "-6").await.map_err(|_| e),
+    }
+}
+
+async fn resolve_mdns_proto(hostname: &str, proto: &str) -> Result<IpAddr, Error> {
     Ok(String::from_utf8(
         Command::new("avahi-resolve-host-name")
             .kill_on_drop(true)
-            .arg("-4")
+            .arg(proto)
             .arg(hostname)
             .invoke(crate::ErrorKind::Network)
             .await?,
@@ -44,11 +63,39 @@ impl MdnsController {
     pub async fn gc(&self, alias: String) -> Result<(), Error> {
         self.0.lock().await.gc(alias).await
     }
+    /// Advertises the DNS-SD service `name` of `service_type`, at `port` of `host`, replacing
+    /// any with the same name
+    pub async fn publish(
+        &self,
+        name: String,
+        service_type: &str,
+        host: &str,
+        port: u16,
+    ) -> Result<(), Error> {
+        let cmd = Command::new("avahi-publish-service")
+            .kill_on_drop(true)
+            .arg("-H")
+            .arg(host)
+            .arg(&name)
+            .arg(service_type)
+            .arg(port.to_string())
+            .spawn()?;
+        self.0.lock().await.published.insert(name, cmd);
+        Ok(())
+    }
+    pub async fn unpublish(&self, name: &str) -> Result<(), Error> {
+        if let Some(mut cmd) = self.0.lock().await.published.remove(name) {
+            cmd.kill().await.with_kind(crate::ErrorKind::Network)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct MdnsControllerInner {
     alias_cmd: Option<Child>,
     services: BTreeMap<String, Weak<()>>,
+    /// The DNS-SD services of `net mdns service`, by name
+    published: BTreeMap<String, Child>,
 }
 
 impl MdnsControllerInner {
@@ -57,6 +104,7 @@ impl MdnsControllerInner {
         let mut res = MdnsControllerInner {
             alias_cmd: None,
             services: BTreeMap::new(),
+            published: BTreeMap::new(),
         };
         res.sync().await?;
         Ok(res)
@@ -98,3 +146,507 @@ impl MdnsControllerInner {
         Ok(())
     }
 }
+
+/// See `net mdns`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct MdnsSettings {
+    /// Served at `{alias}.local`, besides their LAN address
+    pub aliases: BTreeMap<String, DomainTarget>,
+    /// The DNS-SD services advertised, by name
+    pub services: BTreeMap<String, MdnsService>,
+}
+impl MdnsSettings {
+    /// Services are advertised at the first alias of their interface, which is easier to read
+    /// than its LAN address
+    fn alias_of(&self, target: &DomainTarget) -> Option<String> {
+        self.aliases
+            .iter()
+            .find(|(_, t)| *t == target)
+            .map(|(alias, _)| format!("{}.local", alias))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MdnsService {
+    #[serde(flatten)]
+    pub target: DomainTarget,
+    /// e.g. `_http._tcp`
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub port: u16,
+}
+
+fn check_alias(alias: &str) -> Result<String, Error> {
+    let alias = alias.to_lowercase();
+    let alias = alias.strip_suffix(".local").unwrap_or(&alias);
+    if alias.is_empty()
+        || alias.len() > 63
+        || alias.starts_with('-')
+        || alias.ends_with('-')
+        || !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(Error::new(
+            eyre!("{} is not a valid hostname", alias),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(alias.to_owned())
+}
+
+fn check_service_type(service_type: &str) -> Result<(), Error> {
+    let valid = service_type
+        .strip_suffix("._tcp")
+        .or_else(|| service_type.strip_suffix("._udp"))
+        .and_then(|s| s.strip_prefix('_'))
+        .map_or(false, |s| {
+            !s.is_empty()
+                && s.len() <= 15
+                && s.chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(
+            eyre!(
+                "{} is not a DNS-SD service type, e.g. _http._tcp",
+                service_type
+            ),
+            ErrorKind::InvalidRequest,
+        ))
+    }
+}
+
+async fn lan_interface<Db: DbHandle>(
+    db: &mut Db,
+    target: &DomainTarget,
+) -> Result<(Interface, Option<String>), Error> {
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&target.package)
+        .and_then(|p| p.installed())
+        .get(db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("{} is not installed", target.package),
+                ErrorKind::NotFound,
+            )
+        })?;
+    let interface = installed
+        .manifest
+        .interfaces
+        .0
+        .get(&target.interface)
+        .filter(|i| i.lan_config.is_some())
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                eyre!(
+                    "{} has no LAN interface {}",
+                    target.package,
+                    target.interface
+                ),
+                ErrorKind::NotFound,
+            )
+        })?;
+    let lan_address = installed
+        .interface_addresses
+        .0
+        .get(&target.interface)
+        .and_then(|a| a.lan_address.clone());
+    Ok((interface, lan_address))
+}
+
+async fn publish<Db: DbHandle>(
+    net: &NetController,
+    db: &mut Db,
+    settings: &MdnsSettings,
+    name: &str,
+    service: &MdnsService,
+) -> Result<(), Error> {
+    let host = match settings.alias_of(&service.target) {
+        Some(alias) => alias,
+        None => lan_interface(db, &service.target).await?.1.ok_or_else(|| {
+            Error::new(
+                eyre!("{} has no LAN address", service.target.interface),
+                ErrorKind::NotFound,
+            )
+        })?,
+    };
+    net.mdns
+        .publish(name.to_owned(), &service.service_type, &host, service.port)
+        .await
+}
+
+/// Publishes the aliases and services of `net mdns` again after a restart
+#[instrument(skip_all)]
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get(db)
+        .await?
+        .into_owned();
+    for (alias, target) in &settings.aliases {
+        net.add_alias(alias.clone(), target.clone()).await?;
+    }
+    for (name, service) in &settings.services {
+        if let Err(e) = publish(net, db, &settings, name, service).await {
+            tracing::error!("Error Advertising {}: {}", name, e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(alias, service, list, browse))]
+pub fn mdns() -> Result<(), Error> {
+    Ok(())
+}
+
+#[command(subcommands(alias_add, alias_remove))]
+pub fn alias() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Serves the LAN interface `interface` of `package` at `{alias}.local` as well, on every port
+/// of its LAN address
+#[command(
+    rename = "add",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn alias_add(
+    #[context] ctx: RpcContext,
+    #[arg] alias: String,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    let alias = check_alias(&alias)?;
+    let mut db = ctx.db.handle();
+    let target = DomainTarget { package, interface };
+    lan_interface(&mut db, &target).await?;
+    let hostname = crate::db::DatabaseModel::new()
+        .server_info()
+        .hostname()
+        .get(&mut db)
+        .await?
+        .into_owned();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get_mut(&mut db)
+        .await?;
+    if settings.aliases.contains_key(&alias) || hostname.as_deref() == Some(alias.as_str()) {
+        return Err(Error::new(
+            eyre!("{}.local is already in use", alias),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    ctx.net_controller
+        .add_alias(alias.clone(), target.clone())
+        .await?;
+    settings.aliases.insert(alias, target);
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(
+    rename = "remove",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn alias_remove(#[context] ctx: RpcContext, #[arg] alias: String) -> Result<(), Error> {
+    let alias = check_alias(&alias)?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get_mut(&mut db)
+        .await?;
+    let target = settings.aliases.remove(&alias).ok_or_else(|| {
+        Error::new(
+            eyre!("{}.local is not an alias", alias),
+            ErrorKind::NotFound,
+        )
+    })?;
+    ctx.net_controller.remove_alias(&alias, &target).await?;
+    // the services of the interface move to its next alias, or its LAN address
+    for (name, service) in &settings.services {
+        if service.target == target {
+            publish(&ctx.net_controller, &mut db, &settings, name, service).await?;
+        }
+    }
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(subcommands(service_add, service_remove))]
+pub fn service() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Advertises `interface` of `package` to the LAN as the DNS-SD service `name` of `type`, so
+/// clients can discover it, at `--port` or the first port of its LAN address
+#[command(
+    rename = "add",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn service_add(
+    #[context] ctx: RpcContext,
+    #[arg] name: String,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(rename = "type")] service_type: String,
+    #[arg(long = "port")] port: Option<u16>,
+) -> Result<(), Error> {
+    if name.is_empty() || name.len() > 63 || name.chars().any(|c| c.is_control()) {
+        return Err(Error::new(
+            eyre!("The name of a service must be 1 to 63 bytes long"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    check_service_type(&service_type)?;
+    let mut db = ctx.db.handle();
+    let target = DomainTarget { package, interface };
+    let (lan, _) = lan_interface(&mut db, &target).await?;
+    let port = match port {
+        Some(port) => port,
+        None => lan
+            .lan_config
+            .iter()
+            .flat_map(|c| c.keys())
+            .map(|p| p.0)
+            .next()
+            .unwrap_or(443),
+    };
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get_mut(&mut db)
+        .await?;
+    let service = MdnsService {
+        target,
+        service_type,
+        port,
+    };
+    publish(&ctx.net_controller, &mut db, &settings, &name, &service).await?;
+    settings.services.insert(name, service);
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[command(
+    rename = "remove",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn service_remove(#[context] ctx: RpcContext, #[arg] name: String) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get_mut(&mut db)
+        .await?;
+    if settings.services.remove(&name).is_none() {
+        return Err(Error::new(
+            eyre!("{} is not advertised", name),
+            ErrorKind::NotFound,
+        ));
+    }
+    ctx.net_controller.mdns.unpublish(&name).await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+fn display_settings(arg: MdnsSettings, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ALIAS", "PACKAGE", "INTERFACE"]);
+    for (alias, target) in &arg.aliases {
+        table.add_row(row![
+            format!("{}.local", alias),
+            &*target.package,
+            &*target.interface
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    println!();
+    let mut table = Table::new();
+    table.add_row(row![bc => "SERVICE", "TYPE", "PORT", "PACKAGE", "INTERFACE"]);
+    for (name, service) in &arg.services {
+        table.add_row(row![
+            name,
+            service.service_type,
+            service.port,
+            &*service.target.package,
+            &*service.target.interface
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_settings), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<MdnsSettings, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .mdns()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+/// A service found on the LAN with `net mdns browse`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BrowsedService {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub service_type: String,
+    pub interface: String,
+    pub hostname: String,
+    pub address: IpAddr,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+/// Undoes the `\DDD` escapes of `avahi-browse --parsable`
+fn unescape(s: &str) -> String {
+    let mut res = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match std::str::from_utf8(bytes.get(i + 1..i + 4).unwrap_or_default())
+            .ok()
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|d| d.parse::<u8>().ok())
+        {
+            Some(b) => {
+                res.push(b);
+                i += 4;
+            }
+            None => {
+                res.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Parses the resolved services of `avahi-browse --parsable --resolve`
+fn parse_browse(output: &str) -> Vec<BrowsedService> {
+    let mut res = Vec::new();
+    for line in output.lines() {
+        let fields = line.split(';').collect::<Vec<_>>();
+        if fields.len() < 10 || fields[0] != "=" {
+            continue;
+        }
+        let (address, port) = match (fields[7].parse(), fields[8].parse()) {
+            (Ok(address), Ok(port)) => (address, port),
+            _ => continue,
+        };
+        let service = BrowsedService {
+            name: unescape(fields[3]),
+            service_type: fields[4].to_owned(),
+            interface: fields[1].to_owned(),
+            hostname: fields[6].to_owned(),
+            address,
+            port,
+            txt: fields[9..]
+                .join(";")
+                .split("\" \"")
+                .map(|t| unescape(t.trim_matches('"')))
+                .filter(|t| !t.is_empty())
+                .collect(),
+        };
+        if !res.contains(&service) {
+            res.push(service);
+        }
+    }
+    res
+}
+
+fn display_browsed(arg: Vec<BrowsedService>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "NAME", "TYPE", "HOSTNAME", "ADDRESS", "PORT"]);
+    for service in &arg {
+        table.add_row(row![
+            service.name,
+            service.service_type,
+            service.hostname,
+            service.address,
+            service.port
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The DNS-SD services advertised on the LAN, of `--type` or every type
+#[command(display(display_browsed), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn browse(
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+    #[arg(long = "type")] service_type: Option<String>,
+) -> Result<Vec<BrowsedService>, Error> {
+    let mut cmd = Command::new("avahi-browse");
+    cmd.kill_on_drop(true)
+        .arg("--parsable")
+        .arg("--resolve")
+        .arg("--terminate");
+    match &service_type {
+        Some(service_type) => {
+            check_service_type(service_type)?;
+            cmd.arg(service_type)
+        }
+        None => cmd.arg("--all"),
+    };
+    Ok(parse_browse(&String::from_utf8(
+        cmd.invoke(ErrorKind::Network).await?,
+    )?))
+}
+
+#[test]
+fn browse_output() {
+    let services = parse_browse(concat!(
+        "+;eth0;IPv4;Immich\\032Photos;_https._tcp;local\n",
+        "=;eth0;IPv4;Immich\\032Photos;_https._tcp;local;photos.local;192.168.1.10;443;\"path=/\" \"v=1\"\n",
+        "=;eth0;IPv6;Printer;_ipp._tcp;local;printer.local;fe80::1;631;\n",
+    ));
+    assert_eq!(services.len(), 2);
+    assert_eq!(services[0].name, "Immich Photos");
+    assert_eq!(services[0].txt, ["path=/", "v=1"]);
+    assert_eq!(services[1].port, 631);
+    assert!(services[1].txt.is_empty());
+    assert!(check_service_type("_http._tcp").is_ok());
+    assert!(check_service_type("http._tcp").is_err());
+    assert_eq!(check_alias("Photos.local").unwrap(), "photos");
+}
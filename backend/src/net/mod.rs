@@ -6,23 +6,73 @@ use rpc_toolkit::command;
 
 use crate::Error;
 
+pub mod access_log;
+pub mod access_point;
+pub mod acme;
+pub mod bandwidth;
+pub mod ca;
+pub mod ddns;
 pub mod dhcp;
+pub mod diagnose;
 pub mod dns;
+pub mod dns01;
+pub mod domain;
+pub mod egress;
+pub mod encrypted_dns;
+pub mod exposure;
+pub mod firewall;
+pub mod https;
+pub mod i2p;
 pub mod interface;
+pub mod ip_config;
+pub mod isolation;
 pub mod keys;
 pub mod mdns;
 pub mod net_controller;
+pub mod proxy_auth;
+pub mod proxy_directives;
+pub mod rate_limit;
+pub mod raw;
 pub mod ssl;
 pub mod static_server;
+pub mod tailscale;
 pub mod tor;
 pub mod utils;
+pub mod vanity;
 pub mod vhost;
 pub mod web_server;
 pub mod wifi;
+pub mod wireguard;
 
 pub const PACKAGE_CERT_PATH: &str = "/var/lib/embassy/ssl";
 
-#[command(subcommands(tor::tor, dhcp::dhcp))]
+#[command(subcommands(
+    tor::tor,
+    dhcp::dhcp,
+    acme::acme,
+    domain::domain,
+    wireguard::wireguard,
+    tailscale::tailscale,
+    ddns::ddns,
+    proxy_auth::proxy_auth,
+    proxy_directives::proxy,
+    mdns::mdns,
+    ip_config::ip_config,
+    firewall::firewall,
+    bandwidth::bandwidth,
+    encrypted_dns::encrypted_dns,
+    exposure::exposure,
+    https::https,
+    egress::egress,
+    i2p::i2p,
+    isolation::isolation,
+    rate_limit::rate_limit,
+    access_log::access_log,
+    access_log::logs,
+    ca::ca,
+    diagnose::diagnose,
+    keys::keys
+))]
 pub fn net() -> Result<(), Error> {
     Ok(())
 }
@@ -1,20 +1,33 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, Weak};
 
 use color_eyre::eyre::eyre;
 use models::InterfaceId;
 use sqlx::PgExecutor;
+use tokio::sync::Mutex;
 use tracing::instrument;
 
 use crate::error::ErrorCollection;
 use crate::hostname::Hostname;
+use crate::net::acme::DomainTarget;
+use crate::net::bandwidth::BandwidthMonitor;
 use crate::net::dns::DnsController;
+use crate::net::egress::EgressController;
+use crate::net::encrypted_dns::EncryptedDnsController;
+use crate::net::firewall::{FirewallController, OpenPorts};
+use crate::net::i2p::{I2pController, I2pKey};
+use crate::net::interface::RawProtocol;
+use crate::net::isolation::IsolationController;
 use crate::net::keys::Key;
 use crate::net::mdns::MdnsController;
+use crate::net::raw::RawController;
 use crate::net::ssl::{export_cert, export_key, SslManager};
-use crate::net::tor::TorController;
+use crate::net::tailscale::Exposed;
+use crate::net::tor::{TorBridges, TorController};
+use crate::net::utils::TOR_LOOPBACK;
 use crate::net::vhost::{AlpnInfo, VHostController};
+use crate::net::wireguard::WireguardController;
 use crate::s9pk::manifest::PackageId;
 use crate::volume::cert_dir;
 use crate::{Error, HOST_IP};
@@ -23,9 +36,49 @@ pub struct NetController {
     pub(super) tor: TorController,
     pub(super) mdns: MdnsController,
     pub(super) vhost: VHostController,
+    pub(super) raw: RawController,
     pub(super) dns: DnsController,
     pub(super) ssl: Arc<SslManager>,
     pub(super) os_bindings: Vec<Arc<()>>,
+    pub(super) wireguard: WireguardController,
+    pub(super) firewall: FirewallController,
+    pub(super) bandwidth: Arc<BandwidthMonitor>,
+    pub(super) encrypted_dns: EncryptedDnsController,
+    pub(super) egress: EgressController,
+    pub(super) i2p: I2pController,
+    pub(super) isolation: IsolationController,
+    os_key: Key,
+    domains: Mutex<Domains>,
+    /// The mDNS records of `net mdns alias`, by alias
+    aliases: Mutex<BTreeMap<String, Arc<()>>>,
+    /// Only while this server is in a tailnet, see `net tailscale`
+    tailnet: Mutex<Option<Exposed>>,
+    /// The interfaces of the raw forwards, for `net exposure`
+    raw_interfaces: Mutex<BTreeMap<(RawProtocol, u16), (PackageId, InterfaceId)>>,
+}
+
+type LanTarget = (Key, SocketAddr, Result<(), AlpnInfo>);
+
+/// See `net domain`
+#[derive(Default)]
+struct Domains {
+    targets: BTreeMap<String, Option<DomainTarget>>,
+    /// The LAN interfaces packages are serving, by external port
+    lan: BTreeMap<(PackageId, InterfaceId), BTreeMap<u16, LanTarget>>,
+    bindings: BTreeMap<(String, u16), Arc<()>>,
+}
+impl Domains {
+    fn bound_to(&self, interface: &(PackageId, InterfaceId)) -> Vec<String> {
+        self.targets
+            .iter()
+            .filter(|(_, target)| {
+                target.as_ref().map_or(false, |t| {
+                    t.package == interface.0 && t.interface == interface.1
+                })
+            })
+            .map(|(domain, _)| domain.clone())
+            .collect()
+    }
 }
 
 impl NetController {
@@ -33,19 +86,34 @@ impl NetController {
     pub async fn init(
         tor_control: SocketAddr,
         tor_socks: SocketAddr,
+        tor_bridges: TorBridges,
         dns_bind: &[SocketAddr],
         ssl: SslManager,
         hostname: &Hostname,
         os_key: &Key,
     ) -> Result<Self, Error> {
         let ssl = Arc::new(ssl);
+        let bandwidth = Arc::new(BandwidthMonitor::default());
         let mut res = Self {
-            tor: TorController::new(tor_control, tor_socks),
+            tor: TorController::new(tor_control, tor_socks, tor_bridges),
             mdns: MdnsController::init().await?,
-            vhost: VHostController::new(ssl.clone()),
+            vhost: VHostController::new(ssl.clone(), bandwidth.clone()),
+            raw: RawController::new(),
             dns: DnsController::init(dns_bind).await?,
             ssl,
             os_bindings: Vec::new(),
+            wireguard: WireguardController::default(),
+            firewall: FirewallController::default(),
+            bandwidth,
+            encrypted_dns: EncryptedDnsController::new(dns_bind),
+            egress: EgressController::default(),
+            i2p: I2pController::default(),
+            isolation: IsolationController::default(),
+            os_key: os_key.clone(),
+            domains: Mutex::new(Domains::default()),
+            aliases: Mutex::new(BTreeMap::new()),
+            tailnet: Mutex::new(None),
+            raw_interfaces: Mutex::new(BTreeMap::new()),
         };
         res.add_os_bindings(hostname, os_key).await?;
         Ok(res)
@@ -120,7 +188,7 @@ impl NetController {
         // Tor (http)
         self.os_bindings.push(
             self.tor
-                .add(key.tor_key(), 80, ([127, 0, 0, 1], 80).into())
+                .add(key.tor_key(), 80, (TOR_LOOPBACK, 80).into(), None)
                 .await?,
         );
 
@@ -138,13 +206,188 @@ impl NetController {
         );
         self.os_bindings.push(
             self.tor
-                .add(key.tor_key(), 443, ([127, 0, 0, 1], 443).into())
+                .add(key.tor_key(), 443, (TOR_LOOPBACK, 443).into(), None)
                 .await?,
         );
 
         Ok(())
     }
 
+    /// Serves the StartOS UI at `domain`, or the LAN interface of `target` for as long as its
+    /// package serves it
+    pub async fn add_domain(
+        &self,
+        domain: String,
+        target: Option<DomainTarget>,
+    ) -> Result<(), Error> {
+        let mut domains = self.domains.lock().await;
+        let lan = match &target {
+            Some(target) => {
+                let interface = (target.package.clone(), target.interface.clone());
+                if self.vhost.exposure(&interface).await.serves_domain(&domain) {
+                    domains.lan.get(&interface).cloned().unwrap_or_default()
+                } else {
+                    BTreeMap::new()
+                }
+            }
+            None => [(
+                443,
+                (
+                    self.os_key.clone(),
+                    ([127, 0, 0, 1], 80).into(),
+                    Err(AlpnInfo::Specified(vec!["http/1.1".into(), "h2".into()])),
+                ),
+            )]
+            .into(),
+        };
+        for (external, (key, addr, connect_ssl)) in lan {
+            let rc = self
+                .vhost
+                .add(key, Some(domain.clone()), external, addr, connect_ssl)
+                .await?;
+            domains.bindings.insert((domain.clone(), external), rc);
+        }
+        domains.targets.insert(domain, target);
+        Ok(())
+    }
+
+    pub async fn remove_domain(&self, domain: &str) -> Result<(), Error> {
+        let mut domains = self.domains.lock().await;
+        domains.targets.remove(domain);
+        let externals: Vec<u16> = domains
+            .bindings
+            .keys()
+            .filter(|(d, _)| d == domain)
+            .map(|(_, external)| *external)
+            .collect();
+        for external in externals {
+            domains.bindings.remove(&(domain.to_owned(), external));
+            self.vhost.gc(Some(domain.to_owned()), external).await?;
+        }
+        Ok(())
+    }
+
+    /// Serves the LAN interface of `target` at `{alias}.local` as well, with the name in its
+    /// certificate
+    pub async fn add_alias(&self, alias: String, target: DomainTarget) -> Result<(), Error> {
+        let name = format!("{}.local", alias);
+        self.ssl
+            .add_alias(
+                (target.package.clone(), target.interface.clone()),
+                name.clone(),
+            )
+            .await;
+        self.add_domain(name, Some(target)).await?;
+        let rc = self.mdns.add(alias.clone()).await?;
+        self.aliases.lock().await.insert(alias, rc);
+        Ok(())
+    }
+
+    pub async fn remove_alias(&self, alias: &str, target: &DomainTarget) -> Result<(), Error> {
+        let name = format!("{}.local", alias);
+        self.remove_domain(&name).await?;
+        self.ssl
+            .remove_alias(&(target.package.clone(), target.interface.clone()), &name)
+            .await;
+        if let Some(rc) = self.aliases.lock().await.remove(alias) {
+            drop(rc);
+            self.mdns.gc(alias.to_owned()).await?;
+        }
+        Ok(())
+    }
+
+    /// Routes the `exposed` interfaces that are running to the tailnet, or withdraws every route
+    /// when this server leaves it
+    pub async fn set_tailnet(&self, exposed: Option<Exposed>) -> Result<(), Error> {
+        let domains = self.domains.lock().await;
+        let mut tailnet = self.tailnet.lock().await;
+        let leaving = exposed.is_none() && tailnet.is_some();
+        *tailnet = exposed;
+        drop(tailnet);
+        if leaving {
+            super::tailscale::route(None).await
+        } else {
+            self.sync_tailnet(&domains).await
+        }
+    }
+
+    /// Routes again after the proxy settings of an interface changed, since tailnet devices reach
+    /// the container directly and only interfaces the proxy does not guard are routed
+    pub async fn refresh_tailnet(&self) -> Result<(), Error> {
+        let domains = self.domains.lock().await;
+        self.sync_tailnet(&domains).await
+    }
+
+    async fn sync_tailnet(&self, domains: &Domains) -> Result<(), Error> {
+        if let Some(exposed) = &*self.tailnet.lock().await {
+            let mut targets = BTreeSet::new();
+            for (interface, lan) in &domains.lan {
+                if !exposed
+                    .get(&interface.0)
+                    .map_or(false, |interfaces| interfaces.contains(&interface.1))
+                {
+                    continue;
+                }
+                if self.vhost.guarded(interface).await {
+                    tracing::warn!(
+                        "Not routing {}/{} to the tailnet: it has proxy auth, directives, an access log or rate limits, which tailnet devices would bypass",
+                        interface.0,
+                        interface.1
+                    );
+                    continue;
+                }
+                targets.extend(lan.values().map(|(_, target, _)| *target));
+            }
+            super::tailscale::route(Some(targets)).await?;
+        }
+        Ok(())
+    }
+
+    /// The ports of the vhost and raw forwards, split by whether they only serve interfaces
+    /// closed to the clearnet
+    pub(super) async fn open_ports(&self) -> OpenPorts {
+        let mut res = OpenPorts::default();
+        let raw_interfaces = self.raw_interfaces.lock().await;
+        for port in self.raw.ports().await {
+            let private = match raw_interfaces.get(&port) {
+                Some(interface) => !self.vhost.exposure(interface).await.clearnet,
+                None => false,
+            };
+            if private {
+                res.private.insert(port);
+            } else {
+                res.public.insert(port);
+            }
+        }
+        drop(raw_interfaces);
+        let private = self.vhost.private_ports().await;
+        for port in self.vhost.ports().await {
+            if private.contains(&port) {
+                res.private.insert((RawProtocol::Tcp, port));
+            } else {
+                res.public.insert((RawProtocol::Tcp, port));
+            }
+        }
+        res
+    }
+
+    pub(super) async fn sync_firewall(&self) -> Result<(), Error> {
+        self.firewall.sync(self.open_ports().await).await
+    }
+
+    /// Opens and closes ports as packages come and go, without failing what changed them
+    async fn sync_firewall_logged(&self) {
+        if let Err(e) = self.sync_firewall().await {
+            tracing::error!("Error Updating Firewall: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+
+    /// The resolver containers should be started with, see `net encrypted-dns`
+    pub async fn container_dns(&self) -> Option<Ipv4Addr> {
+        self.encrypted_dns.container_dns().await
+    }
+
     #[instrument(skip_all)]
     pub async fn create_service(
         self: &Arc<Self>,
@@ -152,6 +395,13 @@ impl NetController {
         ip: Ipv4Addr,
     ) -> Result<NetService, Error> {
         let dns = self.dns.add(Some(package.clone()), ip).await?;
+        if let Err(e) = self.bandwidth.add_container(package.clone(), ip).await {
+            tracing::error!("Error Counting Bandwidth of {}: {}", package, e);
+            tracing::debug!("{:?}", e);
+        }
+        // the package must not start if its traffic would go direct
+        self.egress.add_container(package.clone(), ip).await?;
+        self.isolation.add_container(package.clone(), ip).await?;
 
         Ok(NetService {
             id: package,
@@ -159,7 +409,9 @@ impl NetController {
             dns,
             controller: Arc::downgrade(self),
             tor: BTreeMap::new(),
+            i2p: BTreeMap::new(),
             lan: BTreeMap::new(),
+            raw: BTreeMap::new(),
         })
     }
 
@@ -169,13 +421,32 @@ impl NetController {
         external: u16,
         target: SocketAddr,
     ) -> Result<Vec<Arc<()>>, Error> {
-        let mut rcs = Vec::with_capacity(1);
-        rcs.push(self.tor.add(key.tor_key(), external, target).await?);
+        let mut rcs = Vec::with_capacity(2);
+        let mut max_streams = None;
+        let mut target = target;
+        if let Some(interface) = key.interface() {
+            if !self.vhost.exposure(&interface).await.tor {
+                return Ok(rcs);
+            }
+            let limit = self.vhost.rate_limit(&interface).await;
+            max_streams = limit.tor_max_streams();
+            if limit.gates_tor() {
+                let (gate, rc) = self.vhost.add_tor_gate(interface, target).await?;
+                target = gate;
+                rcs.push(rc);
+            }
+        }
+        rcs.push(
+            self.tor
+                .add(key.tor_key(), external, target, max_streams)
+                .await?,
+        );
         Ok(rcs)
     }
 
     async fn remove_tor(&self, key: &Key, external: u16, rcs: Vec<Arc<()>>) -> Result<(), Error> {
         drop(rcs);
+        self.vhost.gc_tor_gates().await;
         self.tor.gc(Some(key.tor_key()), Some(external)).await
     }
 
@@ -187,25 +458,120 @@ impl NetController {
         connect_ssl: Result<(), AlpnInfo>,
     ) -> Result<Vec<Arc<()>>, Error> {
         let mut rcs = Vec::with_capacity(2);
-        rcs.push(
-            self.vhost
-                .add(
-                    key.clone(),
-                    Some(key.local_address()),
-                    external,
-                    target.into(),
-                    connect_ssl,
-                )
-                .await?,
-        );
-        rcs.push(self.mdns.add(key.base_address()).await?);
+        let exposure = match key.interface() {
+            Some(interface) => self.vhost.exposure(&interface).await,
+            None => Default::default(),
+        };
+        if exposure.lan {
+            rcs.push(
+                self.vhost
+                    .add(
+                        key.clone(),
+                        Some(key.local_address()),
+                        external,
+                        target.into(),
+                        connect_ssl.clone(),
+                    )
+                    .await?,
+            );
+            rcs.push(self.mdns.add(key.base_address()).await?);
+        }
+        if let Some(interface) = key.interface() {
+            let mut domains = self.domains.lock().await;
+            for domain in domains.bound_to(&interface) {
+                if !exposure.serves_domain(&domain) {
+                    continue;
+                }
+                let rc = self
+                    .vhost
+                    .add(
+                        key.clone(),
+                        Some(domain.clone()),
+                        external,
+                        target,
+                        connect_ssl.clone(),
+                    )
+                    .await?;
+                domains.bindings.insert((domain, external), rc);
+            }
+            domains
+                .lan
+                .entry(interface)
+                .or_default()
+                .insert(external, (key, target, connect_ssl));
+            if let Err(e) = self.sync_tailnet(&domains).await {
+                tracing::error!("Error Routing {} to the Tailnet: {}", target, e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+        self.sync_firewall_logged().await;
         Ok(rcs)
     }
 
     async fn remove_lan(&self, key: &Key, external: u16, rcs: Vec<Arc<()>>) -> Result<(), Error> {
         drop(rcs);
         self.mdns.gc(key.base_address()).await?;
-        self.vhost.gc(Some(key.local_address()), external).await
+        if let Some(interface) = key.interface() {
+            let mut domains = self.domains.lock().await;
+            if let Some(lan) = domains.lan.get_mut(&interface) {
+                lan.remove(&external);
+                if lan.is_empty() {
+                    domains.lan.remove(&interface);
+                }
+            }
+            for domain in domains.bound_to(&interface) {
+                domains.bindings.remove(&(domain.clone(), external));
+                self.vhost.gc(Some(domain), external).await?;
+            }
+            if let Err(e) = self.sync_tailnet(&domains).await {
+                tracing::error!("Error Withdrawing Tailnet Routes: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        }
+        self.vhost.gc(Some(key.local_address()), external).await?;
+        self.sync_firewall_logged().await;
+        Ok(())
+    }
+
+    async fn add_raw(
+        &self,
+        interface: (PackageId, InterfaceId),
+        protocol: RawProtocol,
+        external: u16,
+        target: SocketAddr,
+    ) -> Result<Arc<()>, Error> {
+        // a raw port cannot tell its clients apart by name, so it is forwarded on every address
+        // unless the interface is only served over Tor
+        let exposure = self.vhost.exposure(&interface).await;
+        if !exposure.lan && !exposure.clearnet {
+            return Ok(Arc::new(()));
+        }
+        let traffic = self.bandwidth.traffic(interface.clone());
+        let rc = self.raw.add(protocol, external, target, traffic).await?;
+        self.raw_interfaces
+            .lock()
+            .await
+            .insert((protocol, external), interface);
+        self.sync_firewall_logged().await;
+        Ok(rc)
+    }
+
+    async fn remove_raw(
+        &self,
+        protocol: RawProtocol,
+        external: u16,
+        rc: Arc<()>,
+    ) -> Result<(), Error> {
+        drop(rc);
+        self.raw.gc(protocol, external).await?;
+        if !self.raw.ports().await.contains(&(protocol, external)) {
+            self.raw_interfaces
+                .lock()
+                .await
+                .remove(&(protocol, external));
+        }
+        self.sync_firewall_logged().await;
+        Ok(())
     }
 }
 
@@ -215,7 +581,10 @@ pub struct NetService {
     dns: Arc<()>,
     controller: Weak<NetController>,
     tor: BTreeMap<(InterfaceId, u16), (Key, Vec<Arc<()>>)>,
+    /// The published eepsites, see `net i2p`
+    i2p: BTreeMap<InterfaceId, Arc<()>>,
     lan: BTreeMap<(InterfaceId, u16), (Key, Vec<Arc<()>>)>,
+    raw: BTreeMap<(InterfaceId, RawProtocol, u16), Arc<()>>,
 }
 impl NetService {
     fn net_controller(&self) -> Result<Arc<NetController>, Error> {
@@ -258,6 +627,28 @@ impl NetService {
         }
         Ok(())
     }
+    /// Publishes `id` as an eepsite, if it was with `net i2p publish`
+    pub async fn add_i2p<Ex>(
+        &mut self,
+        secrets: &mut Ex,
+        id: InterfaceId,
+        internal: u16,
+    ) -> Result<(), Error>
+    where
+        for<'a> &'a mut Ex: PgExecutor<'a>,
+    {
+        let key = match I2pKey::for_interface(secrets, &self.id, &id).await? {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let ctrl = self.net_controller()?;
+        let rc = ctrl
+            .i2p
+            .add(&key, SocketAddr::new(self.ip.into(), internal))
+            .await?;
+        self.i2p.insert(id, rc);
+        Ok(())
+    }
     pub async fn add_lan<Ex>(
         &mut self,
         secrets: &mut Ex,
@@ -296,6 +687,37 @@ impl NetService {
         }
         Ok(())
     }
+    pub async fn add_raw(
+        &mut self,
+        id: InterfaceId,
+        protocol: RawProtocol,
+        external: u16,
+        internal: u16,
+    ) -> Result<(), Error> {
+        let ctrl = self.net_controller()?;
+        let rc = ctrl
+            .add_raw(
+                (self.id.clone(), id.clone()),
+                protocol,
+                external,
+                SocketAddr::new(self.ip.into(), internal),
+            )
+            .await?;
+        self.raw.insert((id, protocol, external), rc);
+        Ok(())
+    }
+    pub async fn remove_raw(
+        &mut self,
+        id: InterfaceId,
+        protocol: RawProtocol,
+        external: u16,
+    ) -> Result<(), Error> {
+        let ctrl = self.net_controller()?;
+        if let Some(rc) = self.raw.remove(&(id, protocol, external)) {
+            ctrl.remove_raw(protocol, external, rc).await?;
+        }
+        Ok(())
+    }
     pub async fn export_cert<Ex>(
         &self,
         secrets: &mut Ex,
@@ -331,8 +753,16 @@ impl NetService {
             for ((_, external), (key, rcs)) in std::mem::take(&mut self.tor) {
                 errors.handle(ctrl.remove_tor(&key, external, rcs).await);
             }
+            std::mem::take(&mut self.i2p);
+            ctrl.i2p.gc().await;
+            for ((_, protocol, external), rc) in std::mem::take(&mut self.raw) {
+                errors.handle(ctrl.remove_raw(protocol, external, rc).await);
+            }
             std::mem::take(&mut self.dns);
             errors.handle(ctrl.dns.gc(Some(self.id.clone()), self.ip).await);
+            errors.handle(ctrl.bandwidth.remove_container(&self.id).await);
+            errors.handle(ctrl.egress.remove_container(&self.id).await);
+            errors.handle(ctrl.isolation.remove_container(&self.id).await);
             self.ip = Ipv4Addr::new(0, 0, 0, 0);
             errors.into_result()
         } else {
@@ -356,7 +786,9 @@ impl Drop for NetService {
                     dns: Default::default(),
                     controller: Default::default(),
                     tor: Default::default(),
+                    i2p: Default::default(),
                     lan: Default::default(),
+                    raw: Default::default(),
                 },
             );
             tokio::spawn(async move { svc.remove_all().await.unwrap() });
@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::{Id, InterfaceId};
+use rpc_toolkit::command;
+use sqlx::{Executor, PgPool, Postgres};
+use tracing::instrument;
+
+use crate::account::hash_password;
+use crate::context::RpcContext;
+use crate::middleware::auth::LoginAttempts;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+pub(super) const UNAUTHORIZED: &[u8] = b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"StartOS\", charset=\"UTF-8\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// The credentials the reverse proxy asks for before letting a connection through to an
+/// interface, see `net proxy-auth`
+#[derive(Clone)]
+pub struct ProxyAuth {
+    username: String,
+    /// argon2
+    password: String,
+    /// The `Authorization` headers that have matched, so every request is not hashed again
+    verified: Arc<Mutex<BTreeSet<String>>>,
+    /// The wrong credentials sent from each address, which are refused for a while once they
+    /// reach the limit logins have
+    failures: Arc<Mutex<BTreeMap<IpAddr, LoginAttempts>>>,
+}
+impl ProxyAuth {
+    fn new(username: String, password: String) -> Self {
+        Self {
+            username,
+            password,
+            verified: Default::default(),
+            failures: Default::default(),
+        }
+    }
+
+    /// Whether `peer` sent wrong credentials too many times to be checked again yet
+    pub(super) fn locked(&self, peer: IpAddr) -> bool {
+        self.failures
+            .lock()
+            .unwrap()
+            .get(&peer)
+            .map_or(false, |a| a.locked())
+    }
+
+    fn record(&self, peer: IpAddr, failed: bool) {
+        let mut failures = self.failures.lock().unwrap();
+        if failed {
+            failures.retain(|_, a| a.in_window());
+            failures.entry(peer).or_default().record(true);
+        } else {
+            failures.remove(&peer);
+        }
+    }
+
+    /// Whether the `Authorization` header of a request from `peer` carries the credentials
+    pub(super) async fn check(&self, peer: IpAddr, authorization: Option<&str>) -> bool {
+        let header = match authorization {
+            Some(a) => a.trim().to_owned(),
+            None => return false,
+        };
+        if self.verified.lock().unwrap().contains(&header) {
+            return true;
+        }
+        let credentials = match header
+            .strip_prefix("Basic ")
+            .and_then(|b| base64::decode(b.trim()).ok())
+            .and_then(|c| String::from_utf8(c).ok())
+        {
+            Some(c) => c,
+            None => return false,
+        };
+        let (username, password) = match credentials.split_once(':') {
+            Some(c) => c,
+            None => return false,
+        };
+        if username != self.username {
+            self.record(peer, true);
+            return false;
+        }
+        let hash = self.password.clone();
+        let password = password.to_owned();
+        let valid = tokio::task::spawn_blocking(move || {
+            argon2::verify_encoded(&hash, password.as_bytes()).unwrap_or(false)
+        })
+        .await
+        .unwrap_or(false);
+        if valid {
+            self.verified.lock().unwrap().insert(header);
+        }
+        self.record(peer, !valid);
+        valid
+    }
+}
+
+/// Requires the credentials set with `net proxy-auth set` again after a restart
+#[instrument(skip_all)]
+pub async fn load(net: &NetController, secrets: &PgPool) -> Result<(), Error> {
+    for row in sqlx::query!("SELECT package, interface, username, password FROM proxy_auth")
+        .fetch_all(secrets)
+        .await?
+    {
+        net.vhost
+            .set_auth(
+                (
+                    row.package.parse()?,
+                    InterfaceId::from(Id::try_from(row.interface)?),
+                ),
+                Some(ProxyAuth::new(row.username, row.password)),
+            )
+            .await;
+    }
+    Ok(())
+}
+
+/// Forgets the credentials of the interfaces of `id` when it is uninstalled
+pub async fn remove_credentials<Ex>(
+    net: &NetController,
+    secrets: &mut Ex,
+    id: &PackageId,
+) -> Result<(), Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let id_str = id.as_str();
+    sqlx::query!("DELETE FROM proxy_auth WHERE package = $1", id_str)
+        .execute(secrets)
+        .await?;
+    net.vhost.remove_package_auth(id).await;
+    Ok(())
+}
+
+#[command(rename = "proxy-auth", subcommands(set, remove, list))]
+pub fn proxy_auth() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Asks for `username` and `password` before serving the LAN interface `interface` of
+/// `package`, at its LAN address and its domains. Connections over Tor reach the package
+/// directly, and are not affected.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg] username: String,
+    #[arg] password: String,
+) -> Result<(), Error> {
+    if username.is_empty() || username.contains(':') {
+        return Err(Error::new(
+            eyre!("The username must not be empty or contain ':'"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .map_or(true, |i| i.lan_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no LAN interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    if manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .map_or(false, |i| i.passes_through())
+    {
+        return Err(Error::new(
+            eyre!(
+                "{} of {} passes TLS through to the package, so the proxy cannot ask for credentials",
+                interface,
+                package
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let hash = hash_password(&password)?;
+    sqlx::query!(
+        "INSERT INTO proxy_auth (package, interface, username, password) VALUES ($1, $2, $3, $4) ON CONFLICT (package, interface) DO UPDATE SET username = EXCLUDED.username, password = EXCLUDED.password",
+        *package,
+        *interface,
+        username,
+        hash,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    ctx.net_controller
+        .vhost
+        .set_auth((package, interface), Some(ProxyAuth::new(username, hash)))
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "DELETE FROM proxy_auth WHERE package = $1 AND interface = $2",
+        *package,
+        *interface,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    ctx.net_controller
+        .vhost
+        .set_auth((package, interface), None)
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    Ok(())
+}
+
+fn display_credentials(
+    arg: BTreeMap<PackageId, BTreeMap<InterfaceId, String>>,
+    matches: &ArgMatches,
+) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE", "USERNAME"]);
+    for (package, interfaces) in &arg {
+        for (interface, username) in interfaces {
+            table.add_row(row![&**package, &**interface, username]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The usernames the interfaces ask for, by package
+#[command(display(display_credentials), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<PackageId, BTreeMap<InterfaceId, String>>, Error> {
+    let mut res: BTreeMap<PackageId, BTreeMap<InterfaceId, String>> = BTreeMap::new();
+    for row in sqlx::query!("SELECT package, interface, username, password FROM proxy_auth")
+        .fetch_all(&ctx.secret_store)
+        .await?
+    {
+        res.entry(row.package.parse()?).or_default().insert(
+            InterfaceId::from(Id::try_from(row.interface)?),
+            row.username,
+        );
+    }
+    Ok(res)
+}
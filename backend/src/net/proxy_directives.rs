@@ -0,0 +1,929 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::access_log::AccessLog;
+use crate::net::net_controller::NetController;
+use crate::net::proxy_auth::{ProxyAuth, UNAUTHORIZED};
+use crate::net::rate_limit::ConnectionSlot;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, parse_stdin_deserializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// Longest request head, or chunk header, read before a connection is refused
+const MAX_HEAD: usize = 16 * 1024;
+const MAX_IDLE_TIMEOUT: u64 = 24 * 60 * 60;
+/// Headers the proxy frames or routes requests by, which cannot be overridden
+const RESERVED_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "transfer-encoding",
+    "connection",
+    "upgrade",
+    "te",
+    "trailer",
+];
+const BAD_REQUEST: &[u8] =
+    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const PAYLOAD_TOO_LARGE: &[u8] =
+    b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const TOO_MANY_REQUESTS: &[u8] =
+    b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const HEADERS_TOO_LARGE: &[u8] = b"HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// The directives of each interface, by package
+pub type ProxyDirectiveMap = BTreeMap<PackageId, BTreeMap<InterfaceId, ProxyDirectives>>;
+
+/// How the reverse proxy handles the requests to a LAN interface, for packages that need more
+/// than passing them through, see `net proxy set`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct ProxyDirectives {
+    /// Set on every request, replacing any the client sent with the same name
+    pub headers: BTreeMap<String, String>,
+    /// In bytes. Requests with larger bodies are refused.
+    pub max_body_size: Option<u64>,
+    /// In seconds, how long a connection, websockets included, can go without traffic.
+    /// 5 minutes otherwise.
+    pub idle_timeout: Option<u64>,
+    /// Path prefixes replaced before a request is passed on, e.g. `/api/` with `/`
+    pub path_rewrites: BTreeMap<String, String>,
+}
+impl ProxyDirectives {
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, value) in &self.headers {
+            if !is_token(name) {
+                return Err(Error::new(
+                    eyre!("{:?} is not a valid header name", name),
+                    ErrorKind::InvalidRequest,
+                ));
+            }
+            if RESERVED_HEADERS
+                .iter()
+                .any(|r| r.eq_ignore_ascii_case(name))
+            {
+                return Err(Error::new(
+                    eyre!("The {} header is set by the proxy", name),
+                    ErrorKind::InvalidRequest,
+                ));
+            }
+            if !value.chars().all(|c| c == '\t' || (' '..='~').contains(&c)) {
+                return Err(Error::new(
+                    eyre!("The value of the {} header must be printable ASCII", name),
+                    ErrorKind::InvalidRequest,
+                ));
+            }
+        }
+        if let Some(timeout) = self.idle_timeout {
+            if timeout == 0 || timeout > MAX_IDLE_TIMEOUT {
+                return Err(Error::new(
+                    eyre!("The idle timeout must be 1 to {} seconds", MAX_IDLE_TIMEOUT),
+                    ErrorKind::InvalidRequest,
+                ));
+            }
+        }
+        for path in self.path_rewrites.iter().flat_map(|(from, to)| [from, to]) {
+            if !path.starts_with('/') || !path.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(Error::new(
+                    eyre!("{:?} is not an absolute path", path),
+                    ErrorKind::InvalidRequest,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout.map(Duration::from_secs)
+    }
+
+    /// Replaces the longest prefix of `path` that is rewritten
+    fn rewrite_path(&self, path: &str) -> String {
+        self.path_rewrites
+            .iter()
+            .filter(|(from, _)| path.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+            .map_or_else(
+                || path.to_owned(),
+                |(from, to)| format!("{}{}", to, &path[from.len()..]),
+            )
+    }
+
+    fn apply(&self, head: &mut RequestHead) {
+        head.path = self.rewrite_path(&head.path);
+        head.headers
+            .retain(|(name, _)| !self.headers.keys().any(|h| h.eq_ignore_ascii_case(name)));
+        head.headers.extend(
+            self.headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct RequestHead {
+    method: String,
+    path: String,
+    version: String,
+    headers: Vec<(String, String)>,
+}
+impl RequestHead {
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.split("\r\n").filter(|l| !l.is_empty());
+        let mut request = lines.next()?.split(' ');
+        let (method, path, version) = (request.next()?, request.next()?, request.next()?);
+        if request.next().is_some() || !is_token(method) || !version.starts_with("HTTP/1.") {
+            return None;
+        }
+        Some(Self {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            version: version.to_owned(),
+            headers: parse_headers(lines)?,
+        })
+    }
+
+    fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        find_headers(&self.headers, name)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers(name).next()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        to_bytes(
+            format!("{} {} {}", self.method, self.path, self.version),
+            &self.headers,
+        )
+    }
+
+    /// How the request is logged, e.g. `files.local "GET / HTTP/1.1" "curl/8.0.1"`
+    fn description(&self) -> String {
+        format!(
+            "{} \"{} {} {}\" \"{}\"",
+            self.header("host").unwrap_or("-"),
+            self.method,
+            self.path,
+            self.version,
+            self.header("user-agent").unwrap_or("-")
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ResponseHead {
+    status_line: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+impl ResponseHead {
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.split("\r\n").filter(|l| !l.is_empty());
+        let status_line = lines.next()?;
+        let (version, status) = status_line.split_once(' ')?;
+        let status = status.split(' ').next()?;
+        if !version.starts_with("HTTP/1.") || status.len() != 3 {
+            return None;
+        }
+        Some(Self {
+            status_line: status_line.to_owned(),
+            status: status.parse().ok()?,
+            headers: parse_headers(lines)?,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        find_headers(&self.headers, name).next()
+    }
+
+    /// Replaces the headers the target sent with `name`
+    fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_owned(), value.to_owned()));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        to_bytes(self.status_line.clone(), &self.headers)
+    }
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Option<Vec<(String, String)>> {
+    lines
+        .map(|line| {
+            let (name, value) = line.split_once(':')?;
+            is_token(name).then(|| (name.to_owned(), value.trim().to_owned()))
+        })
+        .collect()
+}
+
+fn find_headers<'a>(
+    headers: &'a [(String, String)],
+    name: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    headers
+        .iter()
+        .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn to_bytes(start_line: String, headers: &[(String, String)]) -> Vec<u8> {
+    let mut res = start_line;
+    res.push_str("\r\n");
+    for (name, value) in headers {
+        res.push_str(name);
+        res.push_str(": ");
+        res.push_str(value);
+        res.push_str("\r\n");
+    }
+    res.push_str("\r\n");
+    res.into_bytes()
+}
+
+enum Head {
+    Request(RequestHead),
+    Closed,
+    Rejected(&'static [u8]),
+}
+
+async fn read_head<R: AsyncRead + Unpin>(client: &mut BufReader<R>) -> std::io::Result<Head> {
+    let mut head = Vec::new();
+    loop {
+        let limit = (MAX_HEAD + 1 - head.len()) as u64;
+        let n = (&mut *client)
+            .take(limit)
+            .read_until(b'\n', &mut head)
+            .await?;
+        if n == 0 {
+            return Ok(if head.is_empty() {
+                Head::Closed
+            } else {
+                Head::Rejected(BAD_REQUEST)
+            });
+        }
+        if head.len() > MAX_HEAD {
+            return Ok(Head::Rejected(HEADERS_TOO_LARGE));
+        }
+        if head == b"\r\n" {
+            // empty lines before a request are ignored
+            head.clear();
+        } else if head.ends_with(b"\r\n\r\n") {
+            break;
+        }
+    }
+    Ok(String::from_utf8(head)
+        .ok()
+        .and_then(|text| RequestHead::parse(&text))
+        .map_or(Head::Rejected(BAD_REQUEST), Head::Request))
+}
+
+async fn read_response_head<R: AsyncRead + Unpin>(
+    target: &mut BufReader<R>,
+) -> std::io::Result<Option<ResponseHead>> {
+    let mut head = Vec::new();
+    loop {
+        let limit = (MAX_HEAD + 1 - head.len()) as u64;
+        let n = (&mut *target)
+            .take(limit)
+            .read_until(b'\n', &mut head)
+            .await?;
+        if n == 0 {
+            if head.is_empty() {
+                return Ok(None);
+            }
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        if head.len() > MAX_HEAD {
+            return Err(invalid("response head too large"));
+        }
+        if head.ends_with(b"\r\n\r\n") {
+            break;
+        }
+    }
+    String::from_utf8(head)
+        .ok()
+        .and_then(|text| ResponseHead::parse(&text))
+        .map(Some)
+        .ok_or_else(|| invalid("invalid response head"))
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_owned())
+}
+
+async fn relay_exact<R, W>(
+    client: &mut BufReader<R>,
+    target: &mut W,
+    len: u64,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if tokio::io::copy(&mut (&mut *client).take(len), target).await? < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+async fn read_line<R: AsyncRead + Unpin>(client: &mut BufReader<R>) -> std::io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    (&mut *client)
+        .take(MAX_HEAD as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    if !line.ends_with(b"\r\n") {
+        return Err(invalid("unterminated chunk header"));
+    }
+    Ok(line)
+}
+
+/// Relays a chunked body, unless it is larger than `max`
+async fn relay_chunked<R, W>(
+    client: &mut BufReader<R>,
+    target: &mut W,
+    max: Option<u64>,
+) -> std::io::Result<bool>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut total = 0_u64;
+    loop {
+        let line = read_line(client).await?;
+        let size = std::str::from_utf8(&line)
+            .ok()
+            .and_then(|l| u64::from_str_radix(l.split(';').next()?.trim(), 16).ok())
+            .ok_or_else(|| invalid("invalid chunk size"))?;
+        total = total.saturating_add(size);
+        if max.map_or(false, |max| total > max) {
+            return Ok(false);
+        }
+        target.write_all(&line).await?;
+        if size == 0 {
+            // trailers, up to the empty line
+            loop {
+                let line = read_line(client).await?;
+                target.write_all(&line).await?;
+                if line == b"\r\n" {
+                    return Ok(true);
+                }
+            }
+        }
+        relay_exact(client, target, size + 2).await?;
+    }
+}
+
+/// A request passed on to the target, whose response is read by `relay_responses`
+struct PendingRequest {
+    head: bool,
+    /// As the client sent it, for `net access-log`
+    description: String,
+    started: Instant,
+    /// Told whether the target switched protocols, for a request asking to upgrade
+    upgrade: Option<oneshot::Sender<bool>>,
+}
+
+/// The status of a response the proxy refuses a request with
+fn status_of(res: &[u8]) -> u16 {
+    res.get(9..12)
+        .and_then(|status| std::str::from_utf8(status).ok())
+        .and_then(|status| status.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Relays the requests of `client` to `target` one at a time with `directives` applied, or
+/// returns the response a request is refused with. Every request is counted by `slot`, and the
+/// refused ones are logged to `log`. The requests passed on are sent to `pending`, for their
+/// responses to be read, and counted in `in_flight` until they are.
+async fn relay_requests<R, W>(
+    client: R,
+    target: &mut W,
+    peer: IpAddr,
+    auth: Option<&ProxyAuth>,
+    directives: &ProxyDirectives,
+    slot: Option<&ConnectionSlot>,
+    log: Option<&AccessLog>,
+    pending: &UnboundedSender<PendingRequest>,
+    in_flight: &AtomicUsize,
+) -> std::io::Result<Option<&'static [u8]>>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut client = BufReader::new(client);
+    loop {
+        let mut head = match read_head(&mut client).await? {
+            Head::Request(head) => head,
+            Head::Closed => {
+                target.shutdown().await?;
+                return Ok(None);
+            }
+            Head::Rejected(res) => {
+                if let Some(log) = log {
+                    log.request("-", status_of(res), None);
+                }
+                return Ok(Some(res));
+            }
+        };
+        let description = head.description();
+        let refuse = |res: &'static [u8]| -> std::io::Result<Option<&'static [u8]>> {
+            if let Some(log) = log {
+                log.request(&description, status_of(res), None);
+            }
+            Ok(Some(res))
+        };
+        if slot.map_or(false, |slot| !slot.request()) {
+            return refuse(TOO_MANY_REQUESTS);
+        }
+        if let Some(auth) = auth {
+            if auth.locked(peer) {
+                return refuse(TOO_MANY_REQUESTS);
+            }
+            if !auth.check(peer, head.header("authorization")).await {
+                return refuse(UNAUTHORIZED);
+            }
+        }
+        let chunked = match head.header("transfer-encoding") {
+            // the length of a body must not be ambiguous, or requests could be smuggled past
+            // the directives
+            Some(_) if head.header("content-length").is_some() => return refuse(BAD_REQUEST),
+            Some(te) if te.to_ascii_lowercase().trim_end().ends_with("chunked") => true,
+            Some(_) => return refuse(BAD_REQUEST),
+            None => false,
+        };
+        let length = match head.headers("content-length").collect::<Vec<_>>()[..] {
+            [] => None,
+            [length] => match length.parse::<u64>() {
+                Ok(length) => Some(length),
+                Err(_) => return refuse(BAD_REQUEST),
+            },
+            _ => return refuse(BAD_REQUEST),
+        };
+        if let (Some(max), Some(length)) = (directives.max_body_size, length) {
+            if length > max {
+                return refuse(PAYLOAD_TOO_LARGE);
+            }
+        }
+        let (upgrade, upgraded) = if head.header("upgrade").is_some() {
+            let (send, recv) = oneshot::channel();
+            (Some(send), Some(recv))
+        } else {
+            (None, None)
+        };
+        in_flight.fetch_add(1, Ordering::SeqCst);
+        pending
+            .send(PendingRequest {
+                head: head.method.eq_ignore_ascii_case("HEAD"),
+                description,
+                started: Instant::now(),
+                upgrade,
+            })
+            .unwrap_or_default();
+        directives.apply(&mut head);
+        target.write_all(&head.to_bytes()).await?;
+        if chunked {
+            if !relay_chunked(&mut client, target, directives.max_body_size).await? {
+                return Ok(Some(PAYLOAD_TOO_LARGE));
+            }
+        } else if let Some(length) = length {
+            relay_exact(&mut client, target, length).await?;
+        }
+        if let Some(upgraded) = upgraded {
+            // nothing the client sends after is a request until the target has answered
+            if upgraded.await.unwrap_or(false) {
+                // e.g. a websocket, which is passed through as is from now on
+                tokio::io::copy_buf(&mut client, target).await?;
+                target.shutdown().await?;
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Relays the responses of `target` to `client` one at a time, with the
+/// `Strict-Transport-Security` header set to `hsts`, logging them to `log`. Each request is
+/// taken off `in_flight` once its response has been relayed whole.
+async fn relay_responses<R, W>(
+    target: R,
+    client: &mut W,
+    hsts: Option<&str>,
+    log: Option<&AccessLog>,
+    pending: &mut UnboundedReceiver<PendingRequest>,
+    in_flight: &AtomicUsize,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut target = BufReader::new(target);
+    loop {
+        let mut head = match read_response_head(&mut target).await? {
+            Some(head) => head,
+            None => return Ok(()),
+        };
+        if head.status == 101 {
+            let upgrade = match pending.recv().await {
+                Some(request) => {
+                    if let Some(log) = log {
+                        log.request(&request.description, 101, Some(request.started.elapsed()));
+                    }
+                    request.upgrade
+                }
+                None => None,
+            };
+            // a target switching protocols unasked is not followed
+            let upgrade = match upgrade {
+                Some(upgrade) => upgrade,
+                None => return Ok(()),
+            };
+            client.write_all(&head.to_bytes()).await?;
+            upgrade.send(true).unwrap_or_default();
+            // e.g. a websocket, which is passed through as is from now on
+            tokio::io::copy_buf(&mut target, client).await?;
+            return Ok(());
+        }
+        if head.status < 200 {
+            // informational, before the response to the same request
+            client.write_all(&head.to_bytes()).await?;
+            continue;
+        }
+        let mut request = pending.recv().await;
+        if let Some(upgrade) = request.as_mut().and_then(|r| r.upgrade.take()) {
+            upgrade.send(false).unwrap_or_default();
+        }
+        if let (Some(log), Some(request)) = (log, &request) {
+            log.request(
+                &request.description,
+                head.status,
+                Some(request.started.elapsed()),
+            );
+        }
+        if let Some(hsts) = hsts {
+            head.set_header("Strict-Transport-Security", hsts);
+        }
+        client.write_all(&head.to_bytes()).await?;
+        if request.map_or(false, |r| r.head) || head.status == 204 || head.status == 304 {
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let chunked = head
+            .header("transfer-encoding")
+            .map(|te| te.to_ascii_lowercase().trim_end().ends_with("chunked"));
+        let length = head
+            .header("content-length")
+            .and_then(|length| length.parse::<u64>().ok());
+        match (chunked, length) {
+            (Some(true), _) => {
+                relay_chunked(&mut target, client, None).await?;
+            }
+            (None, Some(length)) => relay_exact(&mut target, client, length).await?,
+            _ => {
+                // the body ends with the connection
+                tokio::io::copy_buf(&mut target, client).await?;
+                return Ok(());
+            }
+        }
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Proxies an HTTP/1.1 connection from `peer`, checking `auth` and applying `directives` to every request,
+/// holding the client to the rate limit of `slot` and logging its requests to `log`. Responses
+/// are passed back as they are, unless `hsts` is given. A refused request is answered only when
+/// no response is being relayed, and the connection is closed either way.
+pub(super) async fn relay<C, T>(
+    client: &mut C,
+    target: &mut T,
+    peer: IpAddr,
+    auth: Option<&ProxyAuth>,
+    directives: &ProxyDirectives,
+    hsts: Option<&str>,
+    slot: Option<&ConnectionSlot>,
+    log: Option<&AccessLog>,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let (client_read, mut client_write) = tokio::io::split(client);
+    let (mut target_read, mut target_write) = tokio::io::split(target);
+    // the responses are read to know when the target switches protocols, and when a refusal
+    // would not land in the middle of one
+    let (pending, mut pending_responses) = unbounded_channel();
+    let in_flight = AtomicUsize::new(0);
+    let refused = {
+        let requests = relay_requests(
+            client_read,
+            &mut target_write,
+            peer,
+            auth,
+            directives,
+            slot,
+            log,
+            &pending,
+            &in_flight,
+        );
+        let responses = relay_responses(
+            &mut target_read,
+            &mut client_write,
+            hsts,
+            log,
+            &mut pending_responses,
+            &in_flight,
+        );
+        tokio::pin!(requests, responses);
+        tokio::select! {
+            res = &mut requests => match res? {
+                Some(res) => Some(res),
+                None => {
+                    responses.await?;
+                    None
+                }
+            },
+            res = &mut responses => {
+                res?;
+                None
+            }
+        }
+    };
+    if let Some(res) = refused {
+        if in_flight.load(Ordering::SeqCst) == 0 {
+            client_write.write_all(res).await?;
+        }
+        client_write.shutdown().await?;
+    }
+    Ok(())
+}
+
+/// Applies the directives set with `net proxy set` again after a restart
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let directives = crate::db::DatabaseModel::new()
+        .server_info()
+        .proxy_directives()
+        .get(db)
+        .await?
+        .into_owned();
+    for (package, interfaces) in directives {
+        for (interface, directives) in interfaces {
+            net.vhost
+                .set_directives((package.clone(), interface), Some(directives))
+                .await;
+        }
+    }
+    Ok(())
+}
+
+#[command(subcommands(set, clear, list))]
+pub fn proxy() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Applies the directives read from stdin to the requests to the LAN interface `interface` of
+/// `package`, at its LAN address and its domains, e.g.
+/// `{"headers":{"X-Forwarded-Proto":"https"},"max-body-size":1073741824,"idle-timeout":3600,"path-rewrites":{"/api/":"/"}}`.
+/// Connections over Tor reach the package directly, and are not affected.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(stdin, parse(parse_stdin_deserializable))] directives: ProxyDirectives,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<(), Error> {
+    directives.validate()?;
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(&interface)
+        .map_or(true, |i| i.lan_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no LAN interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    if !directives.is_empty()
+        && manifest
+            .interfaces
+            .0
+            .get(&interface)
+            .map_or(false, |i| i.passes_through())
+    {
+        return Err(Error::new(
+            eyre!(
+                "{} of {} passes TLS through to the package, so the proxy cannot apply directives",
+                interface,
+                package
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    update(
+        &ctx,
+        package,
+        interface,
+        Some(directives).filter(|d| !d.is_empty()),
+    )
+    .await
+}
+
+/// Passes the requests to `interface` of `package` through as they are again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn clear(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    update(&ctx, package, interface, None).await
+}
+
+async fn update(
+    ctx: &RpcContext,
+    package: PackageId,
+    interface: InterfaceId,
+    directives: Option<ProxyDirectives>,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut all = crate::db::DatabaseModel::new()
+        .server_info()
+        .proxy_directives()
+        .get_mut(&mut db)
+        .await?;
+    match &directives {
+        Some(directives) => {
+            all.entry(package.clone())
+                .or_default()
+                .insert(interface.clone(), directives.clone());
+        }
+        None => {
+            if let Some(interfaces) = all.get_mut(&package) {
+                interfaces.remove(&interface);
+                if interfaces.is_empty() {
+                    all.remove(&package);
+                }
+            }
+        }
+    }
+    ctx.net_controller
+        .vhost
+        .set_directives((package, interface), directives)
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    all.save(&mut db).await?;
+    Ok(())
+}
+
+fn display_directives(arg: ProxyDirectiveMap, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(
+        row![bc => "PACKAGE", "INTERFACE", "HEADERS", "MAX BODY", "IDLE TIMEOUT", "REWRITES"],
+    );
+    for (package, interfaces) in &arg {
+        for (interface, directives) in interfaces {
+            table.add_row(row![
+                &**package,
+                &**interface,
+                directives
+                    .headers
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                directives
+                    .max_body_size
+                    .map_or_else(|| "N/A".to_owned(), |s| s.to_string()),
+                directives
+                    .idle_timeout
+                    .map_or_else(|| "N/A".to_owned(), |t| format!("{}s", t)),
+                directives
+                    .path_rewrites
+                    .iter()
+                    .map(|(from, to)| format!("{} -> {}", from, to))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_directives), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<ProxyDirectiveMap, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .proxy_directives()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn rewrite_requests() {
+    let directives = ProxyDirectives {
+        headers: [("X-Forwarded-Proto".to_owned(), "https".to_owned())].into(),
+        path_rewrites: [
+            ("/api/".to_owned(), "/".to_owned()),
+            ("/api/v2/".to_owned(), "/v2/".to_owned()),
+        ]
+        .into(),
+        ..Default::default()
+    };
+    assert!(directives.validate().is_ok());
+    let mut head = RequestHead::parse(
+        "GET /api/v2/items?page=2 HTTP/1.1\r\nHost: files.local\r\nx-forwarded-proto: http\r\n\r\n",
+    )
+    .unwrap();
+    directives.apply(&mut head);
+    assert_eq!(
+        head.to_bytes(),
+        b"GET /v2/items?page=2 HTTP/1.1\r\nHost: files.local\r\nX-Forwarded-Proto: https\r\n\r\n"
+    );
+    assert_eq!(directives.rewrite_path("/apis"), "/apis");
+    assert!(RequestHead::parse("GET / HTTP/1.1\r\nBad Header: x\r\n\r\n").is_none());
+    let reserved = ProxyDirectives {
+        headers: [("Content-Length".to_owned(), "0".to_owned())].into(),
+        ..Default::default()
+    };
+    assert!(reserved.validate().is_err());
+}
+
+#[test]
+fn rewrite_responses() {
+    let mut head = ResponseHead::parse(
+        "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nstrict-transport-security: max-age=60\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(head.status, 200);
+    head.set_header("Strict-Transport-Security", "max-age=31536000");
+    assert_eq!(
+        head.to_bytes(),
+        b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nStrict-Transport-Security: max-age=31536000\r\n\r\n"
+    );
+    assert!(ResponseHead::parse("HTTP/1.1 20 OK\r\n\r\n").is_none());
+}
+
+#[test]
+fn describe_requests() {
+    let head = RequestHead::parse(
+        "GET /items HTTP/1.1\r\nHost: files.local\r\nUser-Agent: curl/8.0.1\r\n\r\n",
+    )
+    .unwrap();
+    assert_eq!(
+        head.description(),
+        "files.local \"GET /items HTTP/1.1\" \"curl/8.0.1\""
+    );
+    assert_eq!(status_of(TOO_MANY_REQUESTS), 429);
+    assert_eq!(status_of(UNAUTHORIZED), 401);
+}
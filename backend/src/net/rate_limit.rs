@@ -0,0 +1,468 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+/// Clients an interface keeps track of before forgetting the ones that are idle
+const MAX_CLIENTS: usize = 4096;
+
+/// The rate limits of each interface, by package
+pub type RateLimitMap = BTreeMap<PackageId, BTreeMap<InterfaceId, RateLimit>>;
+
+/// What the clients of an interface are held to by the reverse proxy, see `net rate-limit set`.
+/// Clients are told apart by address, IPv6 ones by /64.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct RateLimit {
+    /// Requests each client can make per minute, on average. The ones over it are answered
+    /// with a 429.
+    pub requests_per_minute: Option<u32>,
+    /// Requests each client can make at once before being held to the rate, the rate itself
+    /// otherwise
+    pub burst: Option<u32>,
+    /// Connections open at once to the interface, from every client together, over Tor as well
+    pub max_connections: Option<u32>,
+    /// Connections open at once from each client. Over Tor, where clients cannot be told apart,
+    /// streams per circuit: the onion service closes the circuits over it.
+    pub max_client_connections: Option<u32>,
+}
+impl RateLimit {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+    fn burst(&self) -> f64 {
+        self.burst.or(self.requests_per_minute).unwrap_or_default() as f64
+    }
+    /// The `MaxStreams` of the onion service of the interface
+    pub(super) fn tor_max_streams(&self) -> Option<u16> {
+        self.max_client_connections
+            .map(|max| max.min(u16::MAX as u32) as u16)
+    }
+    /// Whether the onion services of the interface forward through the proxy, to be held to
+    /// `max-connections` too
+    pub(super) fn gates_tor(&self) -> bool {
+        self.max_connections.is_some()
+    }
+    /// Whether the onion services of the interface are bound the same way with `other`, so
+    /// changing from one to the other does not need the package to bind them again
+    fn binds_tor_like(&self, other: &Self) -> bool {
+        self.tor_max_streams() == other.tor_max_streams() && self.gates_tor() == other.gates_tor()
+    }
+}
+
+fn client(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => {
+                let [a, b, c, d, ..] = ip.segments();
+                IpAddr::V6(Ipv6Addr::new(a, b, c, d, 0, 0, 0, 0))
+            }
+        },
+        ip => ip,
+    }
+}
+
+struct Client {
+    connections: u32,
+    /// Requests it can make right away
+    tokens: f64,
+    updated: Instant,
+}
+impl Client {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        if let Some(rate) = limit.requests_per_minute {
+            let elapsed = now.duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64 / 60.0).min(limit.burst());
+        }
+        self.updated = now;
+    }
+}
+
+#[derive(Default)]
+struct Clients {
+    connections: u32,
+    clients: BTreeMap<IpAddr, Client>,
+}
+
+/// Holds the clients of an interface to its [`RateLimit`], for the connections made while it
+/// is set
+pub(super) struct RateLimiter {
+    limit: RateLimit,
+    clients: Mutex<Clients>,
+}
+impl RateLimiter {
+    pub(super) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+    pub(super) fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+    /// Counts a connection from `peer` until the slot is dropped, or `None` if it is over a cap
+    pub(super) fn connect(self: &Arc<Self>, peer: IpAddr) -> Option<ConnectionSlot> {
+        let peer = client(peer);
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if self
+            .limit
+            .max_connections
+            .map_or(false, |max| clients.connections >= max)
+        {
+            return None;
+        }
+        if clients.clients.len() >= MAX_CLIENTS && !clients.clients.contains_key(&peer) {
+            let limit = &self.limit;
+            clients.clients.retain(|_, c| {
+                c.refill(limit, now);
+                c.connections > 0 || c.tokens < limit.burst()
+            });
+            // many clients coming and going: the ones not connected are forgotten for a fresh
+            // burst, and new ones are refused while every remembered one is connected
+            if clients.clients.len() >= MAX_CLIENTS {
+                clients.clients.retain(|_, c| c.connections > 0);
+            }
+            if clients.clients.len() >= MAX_CLIENTS {
+                return None;
+            }
+        }
+        let burst = self.limit.burst();
+        let client = clients.clients.entry(peer).or_insert_with(|| Client {
+            connections: 0,
+            tokens: burst,
+            updated: now,
+        });
+        if self
+            .limit
+            .max_client_connections
+            .map_or(false, |max| client.connections >= max)
+        {
+            return None;
+        }
+        client.connections += 1;
+        clients.connections += 1;
+        Some(ConnectionSlot {
+            limiter: self.clone(),
+            peer: Some(peer),
+        })
+    }
+    /// Counts a connection towards `max-connections` only, for clients that cannot be told
+    /// apart
+    pub(super) fn connect_anonymous(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        let mut clients = self.clients.lock().unwrap();
+        if self
+            .limit
+            .max_connections
+            .map_or(false, |max| clients.connections >= max)
+        {
+            return None;
+        }
+        clients.connections += 1;
+        Some(ConnectionSlot {
+            limiter: self.clone(),
+            peer: None,
+        })
+    }
+}
+
+/// A connection counted by a [`RateLimiter`]
+pub(super) struct ConnectionSlot {
+    limiter: Arc<RateLimiter>,
+    peer: Option<IpAddr>,
+}
+impl ConnectionSlot {
+    /// Whether the client can make another request now, spending it if so
+    pub(super) fn request(&self) -> bool {
+        let limit = &self.limiter.limit;
+        if limit.requests_per_minute.is_none() {
+            return true;
+        }
+        let mut clients = self.limiter.clients.lock().unwrap();
+        match self.peer.and_then(|peer| clients.clients.get_mut(&peer)) {
+            Some(client) => {
+                client.refill(limit, Instant::now());
+                if client.tokens >= 1.0 {
+                    client.tokens -= 1.0;
+                    true
+                } else {
+                    false
+                }
+            }
+            None => true,
+        }
+    }
+}
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        let mut clients = self.limiter.clients.lock().unwrap();
+        clients.connections = clients.connections.saturating_sub(1);
+        if let Some(client) = self.peer.and_then(|peer| clients.clients.get_mut(&peer)) {
+            client.connections = client.connections.saturating_sub(1);
+        }
+    }
+}
+
+/// Applies the rate limits set with `net rate-limit set` again after a restart, before the
+/// packages bind their interfaces
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let rate_limits = crate::db::DatabaseModel::new()
+        .server_info()
+        .rate_limits()
+        .get(db)
+        .await?
+        .into_owned();
+    for (package, interfaces) in rate_limits {
+        for (interface, limit) in interfaces {
+            net.vhost
+                .set_rate_limit((package.clone(), interface), Some(limit))
+                .await;
+        }
+    }
+    Ok(())
+}
+
+#[command(rename = "rate-limit", subcommands(set, clear, list))]
+pub fn rate_limit() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Changes the rate limits of `interface` of `package`, keeping what is not given. A value of
+/// 0 removes that limit. Connections made to the reverse proxy from now on are affected. The
+/// package is restarted if it is running and the caps on connections changed, for its onion
+/// services to be held to them.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(long = "requests-per-minute")] requests_per_minute: Option<u32>,
+    #[arg(long = "burst")] burst: Option<u32>,
+    #[arg(long = "max-connections")] max_connections: Option<u32>,
+    #[arg(long = "max-client-connections")] max_client_connections: Option<u32>,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if !manifest.interfaces.0.contains_key(&interface) {
+        return Err(Error::new(
+            eyre!("{} has no interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    let mut all = crate::db::DatabaseModel::new()
+        .server_info()
+        .rate_limits()
+        .get_mut(&mut db)
+        .await?;
+    let old = all
+        .get(&package)
+        .and_then(|interfaces| interfaces.get(&interface))
+        .cloned()
+        .unwrap_or_default();
+    let mut limit = old.clone();
+    for (value, new) in [
+        (&mut limit.requests_per_minute, requests_per_minute),
+        (&mut limit.burst, burst),
+        (&mut limit.max_connections, max_connections),
+        (&mut limit.max_client_connections, max_client_connections),
+    ] {
+        if let Some(new) = new {
+            *value = Some(new).filter(|n| *n > 0);
+        }
+    }
+    if limit.burst.is_some() && limit.requests_per_minute.is_none() {
+        return Err(Error::new(
+            eyre!("A burst can only be given with a rate"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let rebind = !limit.binds_tor_like(&old);
+    let limit = Some(limit).filter(|l| !l.is_empty());
+    match &limit {
+        Some(limit) => {
+            all.entry(package.clone())
+                .or_default()
+                .insert(interface.clone(), limit.clone());
+        }
+        None => {
+            if let Some(interfaces) = all.get_mut(&package) {
+                interfaces.remove(&interface);
+                if interfaces.is_empty() {
+                    all.remove(&package);
+                }
+            }
+        }
+    }
+    ctx.net_controller
+        .vhost
+        .set_rate_limit((package.clone(), interface), limit)
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    all.save(&mut db).await?;
+    if rebind {
+        restart_if_running(&ctx, &mut db, package).await?;
+    }
+    Ok(())
+}
+
+/// Removes every rate limit of `interface` of `package`
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn clear(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut all = crate::db::DatabaseModel::new()
+        .server_info()
+        .rate_limits()
+        .get_mut(&mut db)
+        .await?;
+    let old = match all.get_mut(&package) {
+        Some(interfaces) => {
+            let old = interfaces.remove(&interface);
+            if interfaces.is_empty() {
+                all.remove(&package);
+            }
+            old.unwrap_or_default()
+        }
+        None => RateLimit::default(),
+    };
+    ctx.net_controller
+        .vhost
+        .set_rate_limit((package.clone(), interface), None)
+        .await;
+    ctx.net_controller.refresh_tailnet().await?;
+    all.save(&mut db).await?;
+    if !old.binds_tor_like(&RateLimit::default()) {
+        restart_if_running(&ctx, &mut db, package).await?;
+    }
+    Ok(())
+}
+
+async fn restart_if_running<Db: DbHandle>(
+    ctx: &RpcContext,
+    db: &mut Db,
+    package: PackageId,
+) -> Result<(), Error> {
+    let status = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.status().main())
+        .get(db)
+        .await?
+        .into_owned();
+    if matches!(status, Some(MainStatus::Running { .. })) {
+        crate::control::restart(ctx.clone(), package).await?;
+    }
+    Ok(())
+}
+
+fn display_rate_limits(arg: RateLimitMap, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    fn cell(value: Option<u32>) -> String {
+        value.map_or_else(|| "N/A".to_owned(), |v| v.to_string())
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc =>
+        "PACKAGE",
+        "INTERFACE",
+        "REQUESTS/MIN",
+        "BURST",
+        "CONNECTIONS",
+        "CLIENT CONNECTIONS"
+    ]);
+    for (package, interfaces) in &arg {
+        for (interface, limit) in interfaces {
+            table.add_row(row![
+                &**package,
+                &**interface,
+                cell(limit.requests_per_minute),
+                cell(limit.burst),
+                cell(limit.max_connections),
+                cell(limit.max_client_connections)
+            ]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// The interfaces with rate limits
+#[command(display(display_rate_limits), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<RateLimitMap, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .rate_limits()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
+#[test]
+fn limits() {
+    let limiter = Arc::new(RateLimiter::new(RateLimit {
+        requests_per_minute: Some(60),
+        burst: Some(2),
+        max_connections: Some(3),
+        max_client_connections: Some(2),
+    }));
+    let a = limiter.connect("192.168.1.10".parse().unwrap()).unwrap();
+    let b = limiter
+        .connect("::ffff:192.168.1.10".parse().unwrap())
+        .unwrap();
+    assert!(limiter.connect("192.168.1.10".parse().unwrap()).is_none());
+    let c = limiter.connect("2001:db8::1".parse().unwrap()).unwrap();
+    assert!(limiter
+        .connect("2001:db8:0:1::1".parse().unwrap())
+        .is_none());
+    drop(c);
+    assert!(limiter.connect("2001:db8::2".parse().unwrap()).is_some());
+    assert!(a.request());
+    assert!(b.request());
+    assert!(!a.request());
+    let tor = limiter.connect_anonymous().unwrap();
+    assert!(tor.request());
+    assert!(limiter.connect_anonymous().is_none());
+    drop(tor);
+    assert!(limiter.connect_anonymous().is_some());
+}
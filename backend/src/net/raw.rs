@@ -0,0 +1,260 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use helpers::NonDetachingJoinHandle;
+use models::ResultExt;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::Mutex;
+
+use crate::net::bandwidth::{CountingStream, Traffic};
+use crate::net::interface::RawProtocol;
+use crate::{Error, ErrorKind};
+
+/// How long a UDP session can go without a reply from the target before it is dropped
+const UDP_IDLE: Duration = Duration::from_secs(60);
+const UDP_BUFFER: usize = 65536;
+/// How many UDP sessions a single client address can hold open on a port at once
+const UDP_SESSIONS_PER_SOURCE: usize = 64;
+
+/// Forwards TCP and UDP ports on every address of this server to package containers, for
+/// protocols the vhost proxy cannot route by hostname
+#[derive(Default)]
+pub struct RawController {
+    servers: Mutex<BTreeMap<(RawProtocol, u16), RawServer>>,
+}
+impl RawController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub async fn add(
+        &self,
+        protocol: RawProtocol,
+        external: u16,
+        target: SocketAddr,
+        traffic: Arc<Traffic>,
+    ) -> Result<Arc<()>, Error> {
+        let mut writable = self.servers.lock().await;
+        if let Some(server) = writable.get(&(protocol, external)) {
+            if let Some(rc) = Weak::upgrade(&server.rc) {
+                if server.target == target {
+                    return Ok(rc);
+                }
+                return Err(Error::new(
+                    eyre!(
+                        "{} port {} is already forwarded to {}",
+                        protocol,
+                        external,
+                        server.target
+                    ),
+                    ErrorKind::LanPortConflict,
+                ));
+            }
+        }
+        let rc = Arc::new(());
+        let server =
+            RawServer::new(protocol, external, target, traffic, Arc::downgrade(&rc)).await?;
+        writable.insert((protocol, external), server);
+        Ok(rc)
+    }
+    pub async fn gc(&self, protocol: RawProtocol, external: u16) -> Result<(), Error> {
+        let mut writable = self.servers.lock().await;
+        if let Some(server) = writable.remove(&(protocol, external)) {
+            if server.rc.strong_count() > 0 {
+                writable.insert((protocol, external), server);
+            }
+        }
+        Ok(())
+    }
+    /// The ports being forwarded, which `net firewall` opens
+    pub(super) async fn ports(&self) -> BTreeSet<(RawProtocol, u16)> {
+        self.servers
+            .lock()
+            .await
+            .iter()
+            .filter(|(_, server)| server.rc.strong_count() > 0)
+            .map(|(port, _)| *port)
+            .collect()
+    }
+}
+
+struct RawServer {
+    target: SocketAddr,
+    rc: Weak<()>,
+    _thread: NonDetachingJoinHandle<()>,
+}
+impl RawServer {
+    async fn new(
+        protocol: RawProtocol,
+        port: u16,
+        target: SocketAddr,
+        traffic: Arc<Traffic>,
+        rc: Weak<()>,
+    ) -> Result<Self, Error> {
+        let bind = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port);
+        let thread = match protocol {
+            RawProtocol::Tcp => {
+                let listener = TcpListener::bind(bind)
+                    .await
+                    .with_kind(ErrorKind::LanPortConflict)?;
+                tokio::spawn(serve_tcp(listener, target, traffic))
+            }
+            RawProtocol::Udp => {
+                let socket = UdpSocket::bind(bind)
+                    .await
+                    .with_kind(ErrorKind::LanPortConflict)?;
+                tokio::spawn(serve_udp(Arc::new(socket), target, traffic))
+            }
+        };
+        Ok(Self {
+            target,
+            rc,
+            _thread: thread.into(),
+        })
+    }
+}
+
+async fn serve_tcp(listener: TcpListener, target: SocketAddr, traffic: Arc<Traffic>) {
+    let port = listener.local_addr().map_or(0, |a| a.port());
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let mut stream = CountingStream::new(stream, Some(traffic.clone()));
+                tokio::spawn(async move {
+                    if let Err(e) = async {
+                        let mut target_stream = TcpStream::connect(target)
+                            .await
+                            .with_kind(ErrorKind::Network)?;
+                        tokio::io::copy_bidirectional(&mut stream, &mut target_stream)
+                            .await
+                            .map_or_else(
+                                |e| match e.kind() {
+                                    std::io::ErrorKind::UnexpectedEof => Ok(()),
+                                    _ => Err(e),
+                                },
+                                |_| Ok(()),
+                            )?;
+                        Ok::<_, Error>(())
+                    }
+                    .await
+                    {
+                        tracing::error!("Error in RawController on tcp port {port}: {e}");
+                        tracing::debug!("{e:?}")
+                    }
+                });
+            }
+            Err(e) => {
+                tracing::error!("Error in RawController on tcp port {port}: {e}");
+                tracing::debug!("{e:?}");
+            }
+        }
+    }
+}
+
+struct UdpSession {
+    socket: Arc<UdpSocket>,
+    _replies: NonDetachingJoinHandle<()>,
+}
+
+type UdpSessions = Mutex<BTreeMap<SocketAddr, UdpSession>>;
+
+/// Relays datagrams through a socket per client, so replies from the target reach the client
+/// they answer. The sessions end with the forward, as their tasks are dropped with this one.
+async fn serve_udp(socket: Arc<UdpSocket>, target: SocketAddr, traffic: Arc<Traffic>) {
+    let port = socket.local_addr().map_or(0, |a| a.port());
+    let sessions: Arc<UdpSessions> = Default::default();
+    let mut buf = vec![0_u8; UDP_BUFFER];
+    loop {
+        let (len, peer) = match socket.recv_from(&mut buf).await {
+            Ok(a) => a,
+            Err(e) => {
+                tracing::error!("Error in RawController on udp port {port}: {e}");
+                tracing::debug!("{e:?}");
+                continue;
+            }
+        };
+        let mut writable = sessions.lock().await;
+        let session = writable.get(&peer).map(|s| s.socket.clone());
+        let session = match session {
+            Some(session) => session,
+            None if writable.keys().filter(|a| a.ip() == peer.ip()).count()
+                >= UDP_SESSIONS_PER_SOURCE =>
+            {
+                tracing::debug!(
+                    "Dropping datagram from {peer}: too many udp sessions on port {port}"
+                );
+                continue;
+            }
+            None => {
+                match udp_session(&socket, &sessions, &mut writable, peer, target, &traffic).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        tracing::error!("Error in RawController on udp port {port}: {e}");
+                        tracing::debug!("{e:?}");
+                        continue;
+                    }
+                }
+            }
+        };
+        drop(writable);
+        traffic.receive(len);
+        if let Err(e) = session.send(&buf[..len]).await {
+            tracing::error!("Error in RawController on udp port {port}: {e}");
+            tracing::debug!("{e:?}");
+        }
+    }
+}
+
+async fn udp_session(
+    socket: &Arc<UdpSocket>,
+    sessions: &Arc<UdpSessions>,
+    writable: &mut BTreeMap<SocketAddr, UdpSession>,
+    peer: SocketAddr,
+    target: SocketAddr,
+    traffic: &Arc<Traffic>,
+) -> Result<Arc<UdpSocket>, Error> {
+    let session = Arc::new(
+        UdpSocket::bind(SocketAddr::new(
+            match target.ip() {
+                IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+            },
+            0,
+        ))
+        .await
+        .with_kind(ErrorKind::Network)?,
+    );
+    session
+        .connect(target)
+        .await
+        .with_kind(ErrorKind::Network)?;
+    let (socket, sessions, reply, traffic) = (
+        socket.clone(),
+        Arc::downgrade(sessions),
+        session.clone(),
+        traffic.clone(),
+    );
+    let replies = tokio::spawn(async move {
+        let mut buf = vec![0_u8; UDP_BUFFER];
+        while let Ok(Ok(len)) = tokio::time::timeout(UDP_IDLE, reply.recv(&mut buf)).await {
+            if let Err(e) = socket.send_to(&buf[..len], peer).await {
+                tracing::debug!("Error Replying to {peer}: {e:?}");
+                break;
+            }
+            traffic.send(len);
+        }
+        if let Some(sessions) = sessions.upgrade() {
+            sessions.lock().await.remove(&peer);
+        }
+    });
+    writable.insert(
+        peer,
+        UdpSession {
+            socket: session.clone(),
+            _replies: replies.into(),
+        },
+    );
+    Ok(session)
+}
@@ -5,6 +5,7 @@ use std::path::Path;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures::FutureExt;
+use models::InterfaceId;
 use openssl::asn1::{Asn1Integer, Asn1Time};
 use openssl::bn::{BigNum, MsbOption};
 use openssl::ec::{EcGroup, EcKey};
@@ -18,11 +19,13 @@ use tracing::instrument;
 
 use crate::account::AccountInfo;
 use crate::hostname::Hostname;
+use crate::net::acme::AcmeState;
 use crate::net::dhcp::ips;
 use crate::net::keys::{Key, KeyInfo};
+use crate::s9pk::manifest::PackageId;
 use crate::{Error, ErrorKind, ResultExt};
 
-static CERTIFICATE_VERSION: i32 = 2; // X509 version 3 is actually encoded as '2' in the cert because fuck you.
+pub(super) static CERTIFICATE_VERSION: i32 = 2; // X509 version 3 is actually encoded as '2' in the cert because fuck you.
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct CertPair {
@@ -36,11 +39,18 @@ impl CertPair {
         signer: (&PKey<Private>, &X509),
         applicant: &Key,
         ip: BTreeSet<IpAddr>,
+        aliases: &BTreeSet<String>,
     ) -> Result<(Self, bool), Error> {
         let mut updated = false;
         let mut updated_cert = |cert: Option<&X509>, osk: PKey<Private>| -> Result<X509, Error> {
             let mut ips = BTreeSet::new();
             if let Some(cert) = cert {
+                let names = cert
+                    .subject_alt_names()
+                    .iter()
+                    .flatten()
+                    .filter_map(|a| a.dnsname().map(|n| n.to_owned()))
+                    .collect::<BTreeSet<_>>();
                 ips.extend(
                     cert.subject_alt_names()
                         .iter()
@@ -57,13 +67,20 @@ impl CertPair {
                     .compare(Asn1Time::days_from_now(30)?.as_ref())?
                     == Ordering::Greater
                     && ips.is_superset(&ip)
+                    && names.is_superset(aliases)
                 {
                     return Ok(cert.clone());
                 }
             }
             ips.extend(ip.iter().copied());
             updated = true;
-            make_leaf_cert(signer, (&osk, &SANInfo::new(&applicant, hostname, ips)))
+            let mut san = SANInfo::new(&applicant, hostname, ips);
+            san.dns.extend(
+                aliases
+                    .iter()
+                    .map(|a| MaybeWildcard::WithoutWildcard(a.clone())),
+            );
+            make_leaf_cert(signer, (&osk, &san))
         };
         Ok((
             Self {
@@ -78,13 +95,23 @@ impl CertPair {
     }
 }
 
+/// What the certificates of the interfaces are signed with
+#[derive(Debug)]
+struct Signer {
+    key: PKey<Private>,
+    cert: X509,
+    /// The certificates above `cert`, up to the root CA
+    chain: Vec<X509>,
+}
+
 #[derive(Debug)]
 pub struct SslManager {
     hostname: Hostname,
-    root_cert: X509,
-    int_key: PKey<Private>,
-    int_cert: X509,
+    signer: RwLock<Signer>,
     cert_cache: RwLock<BTreeMap<Key, CertPair>>,
+    /// The names of interfaces besides their LAN address, see `net mdns alias`
+    aliases: RwLock<BTreeMap<(PackageId, InterfaceId), BTreeSet<String>>>,
+    pub(super) acme: AcmeState,
 }
 impl SslManager {
     pub fn new(account: &AccountInfo) -> Result<Self, Error> {
@@ -92,21 +119,37 @@ impl SslManager {
         let int_cert = make_int_cert((&account.root_ca_key, &account.root_ca_cert), &int_key)?;
         Ok(Self {
             hostname: account.hostname.clone(),
-            root_cert: account.root_ca_cert.clone(),
-            int_key,
-            int_cert,
+            signer: RwLock::new(Signer {
+                key: int_key,
+                cert: int_cert,
+                chain: vec![account.root_ca_cert.clone()],
+            }),
             cert_cache: RwLock::new(BTreeMap::new()),
+            aliases: RwLock::new(BTreeMap::new()),
+            acme: AcmeState::default(),
         })
     }
     pub async fn with_certs(&self, key: Key, ip: IpAddr) -> Result<KeyInfo, Error> {
         let mut ips = ips().await?;
         ips.insert(ip);
+        let aliases = match key.interface() {
+            Some(interface) => self
+                .aliases
+                .read()
+                .await
+                .get(&interface)
+                .cloned()
+                .unwrap_or_default(),
+            None => BTreeSet::new(),
+        };
+        let signer = self.signer.read().await;
         let (pair, updated) = CertPair::updated(
             self.cert_cache.read().await.get(&key),
             &self.hostname,
-            (&self.int_key, &self.int_cert),
+            (&signer.key, &signer.cert),
             &key,
             ips,
+            &aliases,
         )?;
         if updated {
             self.cert_cache
@@ -115,7 +158,53 @@ impl SslManager {
                 .insert(key.clone(), pair.clone());
         }
 
-        Ok(key.with_certs(pair, self.int_cert.clone(), self.root_cert.clone()))
+        Ok(key.with_certs(pair, signer.cert.clone(), signer.chain.clone()))
+    }
+    /// The root CA the certificates chain up to, which clients must trust
+    pub async fn root_ca(&self) -> X509 {
+        let signer = self.signer.read().await;
+        signer.chain.last().unwrap_or(&signer.cert).clone()
+    }
+    /// The CA that signs the certificates, followed by the ones above it
+    pub async fn chain(&self) -> Vec<X509> {
+        let signer = self.signer.read().await;
+        std::iter::once(signer.cert.clone())
+            .chain(signer.chain.iter().cloned())
+            .collect()
+    }
+    /// Signs the certificates with a new intermediate CA of `root` from now on
+    pub async fn set_root(&self, root: (&PKey<Private>, &X509)) -> Result<(), Error> {
+        let int_key = generate_key()?;
+        let int_cert = make_int_cert(root, &int_key)?;
+        self.set_signer(int_key, int_cert, vec![root.1.clone()])
+            .await;
+        Ok(())
+    }
+    /// Signs the certificates with `key` from now on, whose certificate is `cert`, followed by
+    /// the ones above it in `chain`. The certificates signed before are issued again when next
+    /// requested.
+    pub async fn set_signer(&self, key: PKey<Private>, cert: X509, chain: Vec<X509>) {
+        let mut signer = self.signer.write().await;
+        *signer = Signer { key, cert, chain };
+        self.cert_cache.write().await.clear();
+    }
+    /// Includes `name` in the certificates of `interface` from now on
+    pub async fn add_alias(&self, interface: (PackageId, InterfaceId), name: String) {
+        self.aliases
+            .write()
+            .await
+            .entry(interface)
+            .or_default()
+            .insert(name);
+    }
+    pub async fn remove_alias(&self, interface: &(PackageId, InterfaceId), name: &str) {
+        let mut writable = self.aliases.write().await;
+        if let Some(names) = writable.get_mut(interface) {
+            names.remove(name);
+            if names.is_empty() {
+                writable.remove(interface);
+            }
+        }
     }
 }
 
@@ -144,7 +233,7 @@ pub async fn export_cert(chain: &[&X509], target: &Path) -> Result<(), Error> {
 }
 
 #[instrument(skip_all)]
-fn rand_serial() -> Result<Asn1Integer, Error> {
+pub(super) fn rand_serial() -> Result<Asn1Integer, Error> {
     let mut bn = BigNum::new()?;
     bn.rand(64, MsbOption::MAYBE_ZERO, false)?;
     let asn1 = Asn1Integer::from_bn(&bn)?;
@@ -29,6 +29,8 @@ use crate::middleware::auth::{auth as auth_middleware, HasValidSession};
 use crate::middleware::cors::cors;
 use crate::middleware::db::db as db_middleware;
 use crate::middleware::diagnostic::diagnostic as diagnostic_middleware;
+use crate::net::https::enforceable;
+use crate::net::utils::Tls;
 use crate::net::HttpHandler;
 use crate::{diagnostic_api, install_api, main_api, setup_api, Error, ErrorKind, ResultExt};
 
@@ -38,6 +40,9 @@ static NOT_AUTHORIZED: &[u8] = b"Not Authorized";
 
 static EMBEDDED_UIS: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/../frontend/dist/static");
 
+/// Where HTTP-01 validations fetch key authorizations from, see `net acme`
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
 const PROXY_STRIP_HEADERS: &[&str] = &["cookie", "host", "origin", "referer", "user-agent"];
 
 fn status_fn(_: i32) -> StatusCode {
@@ -172,6 +177,23 @@ pub async fn main_ui_server_router(ctx: RpcContext) -> Result<HttpHandler, Error
         let ctx = ctx.clone();
 
         async move {
+            if let Some(res) = https_redirect(&ctx, &req).await {
+                return Ok(res);
+            }
+            // the access policy covers these as it does the rpc, see `auth_middleware`
+            let req = if req.uri().path().starts_with("/ws/")
+                || req.uri().path().starts_with("/rest/")
+            {
+                let (parts, body) = req.into_parts();
+                if let Err(e) =
+                    crate::auth::access::check(&ctx.secret_store, &parts, parts.uri.path()).await
+                {
+                    return Ok(forbidden(e));
+                }
+                Request::from_parts(parts, body)
+            } else {
+                req
+            };
             let res = match req.uri().path() {
                 path if path.starts_with("/rpc/") => {
                     let auth_middleware = auth_middleware(ctx.clone());
@@ -192,6 +214,7 @@ pub async fn main_ui_server_router(ctx: RpcContext) -> Result<HttpHandler, Error
                         .map_err(|err| Error::new(eyre!("{}", err), crate::ErrorKind::Network))
                 }
                 "/ws/db" => subscribe(ctx, req).await,
+                "/ws/notifications" => crate::notifications::stream::subscribe(ctx, req).await,
                 path if path.starts_with("/ws/rpc/") => {
                     match RequestGuid::from(path.strip_prefix("/ws/rpc/").unwrap()) {
                         None => {
@@ -222,6 +245,22 @@ pub async fn main_ui_server_router(ctx: RpcContext) -> Result<HttpHandler, Error
                         },
                     }
                 }
+                path if path.starts_with(ACME_CHALLENGE_PATH) => {
+                    match ctx
+                        .net_controller
+                        .ssl
+                        .acme
+                        .key_authorization(path.strip_prefix(ACME_CHALLENGE_PATH).unwrap())
+                        .await
+                    {
+                        Some(key_authorization) => Response::builder()
+                            .status(StatusCode::OK)
+                            .header(http::header::CONTENT_TYPE, "application/octet-stream")
+                            .body(key_authorization.into())
+                            .with_kind(ErrorKind::Network),
+                        None => Ok(not_found()),
+                    }
+                }
                 _ => main_embassy_ui(req, ctx).await,
             };
 
@@ -236,6 +275,33 @@ pub async fn main_ui_server_router(ctx: RpcContext) -> Result<HttpHandler, Error
     Ok(handler)
 }
 
+/// Redirects plain HTTP requests permanently to HTTPS, if the global policy set with
+/// `net https set` says so and the host has a publicly trusted certificate
+async fn https_redirect(ctx: &RpcContext, req: &Request<Body>) -> Option<Response<Body>> {
+    if req.extensions().get::<Tls>().is_some() || req.uri().path().starts_with(ACME_CHALLENGE_PATH)
+    {
+        return None;
+    }
+    let host = req.headers().get(http::header::HOST)?.to_str().ok()?;
+    if !enforceable(host) || !ctx.net_controller.vhost.https_policy(None).await.redirect {
+        return None;
+    }
+    let name = host.split(':').next().unwrap_or(host);
+    ctx.net_controller.ssl.acme.cert(name).await?;
+    Response::builder()
+        .status(StatusCode::PERMANENT_REDIRECT)
+        .header(
+            http::header::LOCATION,
+            format!(
+                "https://{}{}",
+                name,
+                req.uri().path_and_query().map_or("/", |p| p.as_str())
+            ),
+        )
+        .body(Body::empty())
+        .ok()
+}
+
 async fn alt_ui(req: Request<Body>, ui_mode: UiMode) -> Result<Response<Body>, Error> {
     let (request_parts, _body) = req.into_parts();
     match &request_parts.method {
@@ -289,7 +355,7 @@ async fn main_embassy_ui(req: Request<Body>, ctx: RpcContext) -> Result<Response
                         .await
                     } else if let Ok(rest) = sub_path.strip_prefix("eos") {
                         match rest.to_str() {
-                            Some("local.crt") => cert_send(&ctx.account.read().await.root_ca_cert),
+                            Some("local.crt") => cert_send(&ctx.net_controller.ssl.root_ca().await),
                             None => Ok(bad_request()),
                             _ => Ok(not_found()),
                         }
@@ -334,9 +400,12 @@ async fn main_embassy_ui(req: Request<Body>, ctx: RpcContext) -> Result<Response
                 Err(e) => un_authorized(e, &format!("proxy/{target}")),
             }
         }
+        (&Method::GET, Some(("registry", path))) => {
+            crate::marketplace::serve::handle(&ctx, &request_parts, path).await
+        }
         (&Method::GET, Some(("eos", "local.crt"))) => {
             match HasValidSession::from_request_parts(&request_parts, &ctx).await {
-                Ok(_) => cert_send(&ctx.account.read().await.root_ca_cert),
+                Ok(_) => cert_send(&ctx.net_controller.ssl.root_ca().await),
                 Err(e) => un_authorized(e, "eos/local.crt"),
             }
         }
@@ -390,6 +459,14 @@ fn method_not_allowed() -> Response<Body> {
         .unwrap()
 }
 
+/// HTTP status code 403
+fn forbidden(err: Error) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(err.to_string().into())
+        .unwrap()
+}
+
 fn server_error(err: Error) -> Response<Body> {
     Response::builder()
         .status(StatusCode::INTERNAL_SERVER_ERROR)
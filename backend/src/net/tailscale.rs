@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::{IpAddr, SocketAddr};
+
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use models::InterfaceId;
+use patch_db::DbHandle;
+use reqwest::Url;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::net_controller::NetController;
+use crate::s9pk::manifest::PackageId;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, ResultExt};
+
+pub const TAILSCALE_INTERFACE: &str = "tailscale0";
+/// Filters what tailnet devices reach through the routes this server advertises
+const TAILNET_CHAIN: &str = "STARTOS-TAILNET";
+const AUTH_KEY_PATH: &str = "/run/embassy/tailscale.authkey";
+
+/// The interfaces of each package that are available over the tailnet
+pub type Exposed = BTreeMap<PackageId, BTreeSet<InterfaceId>>;
+
+/// See `net tailscale`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct TailscaleSettings {
+    pub enabled: bool,
+    /// The Headscale server to join instead of Tailscale
+    pub login_server: Option<Url>,
+    pub hostname: Option<String>,
+    pub exposed: Exposed,
+}
+
+async fn iptables(args: &[&str]) -> Result<Vec<u8>, Error> {
+    Command::new("iptables")
+        .args(args)
+        .invoke(ErrorKind::Tailscale)
+        .await
+}
+
+/// Advertises the containers `targets` are in as routes to the tailnet, and lets its devices
+/// reach only `targets` through them. Withdraws every route when `targets` is `None`.
+#[instrument(skip_all)]
+pub(super) async fn route(targets: Option<BTreeSet<SocketAddr>>) -> Result<(), Error> {
+    if iptables(&["-L", TAILNET_CHAIN, "-n"]).await.is_err() {
+        iptables(&["-N", TAILNET_CHAIN]).await?;
+    }
+    iptables(&["-F", TAILNET_CHAIN]).await?;
+    let jump = ["FORWARD", "-i", TAILSCALE_INTERFACE, "-j", TAILNET_CHAIN];
+    let jumping = iptables(&[&["-C"][..], &jump[..]].concat()).await.is_ok();
+    let targets = match targets {
+        Some(targets) => targets,
+        None => {
+            if jumping {
+                iptables(&[&["-D"][..], &jump[..]].concat()).await?;
+            }
+            iptables(&["-X", TAILNET_CHAIN]).await?;
+            return Ok(());
+        }
+    };
+    for target in &targets {
+        iptables(&[
+            "-A",
+            TAILNET_CHAIN,
+            "-d",
+            &format!("{}/32", target.ip()),
+            "-p",
+            "tcp",
+            "--dport",
+            &target.port().to_string(),
+            "-j",
+            "ACCEPT",
+        ])
+        .await?;
+    }
+    iptables(&["-A", TAILNET_CHAIN, "-j", "DROP"]).await?;
+    if !jumping {
+        iptables(&[&["-I"][..], &jump[..]].concat()).await?;
+    }
+    let routes = targets
+        .iter()
+        .map(|t| t.ip())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|ip| format!("{}/32", ip))
+        .collect::<Vec<_>>()
+        .join(",");
+    Command::new("tailscale")
+        .arg("set")
+        .arg(format!("--advertise-routes={}", routes))
+        .invoke(ErrorKind::Tailscale)
+        .await?;
+    Ok(())
+}
+
+/// Routes the exposed interfaces after a restart, if this server is in a tailnet
+pub async fn load<Db: DbHandle>(net: &NetController, db: &mut Db) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .tailscale()
+        .get(db)
+        .await?
+        .into_owned();
+    if settings.enabled {
+        net.set_tailnet(Some(settings.exposed)).await?;
+    }
+    Ok(())
+}
+
+#[command(subcommands(up, down, expose, status))]
+pub fn tailscale() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Joins the tailnet `auth-key` is for, on Tailscale or at `--login-server` for Headscale, and
+/// advertises the interfaces exposed with `net tailscale expose` to it
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn up(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "auth-key")] auth_key: String,
+    #[arg(long = "login-server")] login_server: Option<Url>,
+    #[arg(long = "hostname")] hostname: Option<String>,
+) -> Result<(), Error> {
+    // kept off the command line, where other processes could read it
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(AUTH_KEY_PATH)
+        .await?;
+    file.write_all(auth_key.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    let mut cmd = Command::new("tailscale");
+    cmd.arg("up")
+        .arg("--reset")
+        .arg(format!("--authkey=file:{}", AUTH_KEY_PATH))
+        // the names of this server are resolved by its own DNS
+        .arg("--accept-dns=false");
+    if let Some(login_server) = &login_server {
+        cmd.arg(format!("--login-server={}", login_server));
+    }
+    if let Some(hostname) = &hostname {
+        cmd.arg(format!("--hostname={}", hostname));
+    }
+    let res = cmd.invoke(ErrorKind::Tailscale).await;
+    tokio::fs::remove_file(AUTH_KEY_PATH).await?;
+    res?;
+
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .tailscale()
+        .get_mut(&mut db)
+        .await?;
+    settings.enabled = true;
+    settings.login_server = login_server;
+    settings.hostname = hostname;
+    ctx.net_controller
+        .set_tailnet(Some(settings.exposed.clone()))
+        .await?;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Withdraws every route and disconnects from the tailnet, keeping the interfaces exposed for
+/// when it is joined again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn down(#[context] ctx: RpcContext) -> Result<(), Error> {
+    ctx.net_controller.set_tailnet(None).await?;
+    Command::new("tailscale")
+        .arg("down")
+        .invoke(ErrorKind::Tailscale)
+        .await?;
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .tailscale()
+        .get_mut(&mut db)
+        .await?;
+    settings.enabled = false;
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+/// Makes the LAN interface `interface` of `package` available over the tailnet, at the address
+/// of its container, while the package is running. Tailnet devices reach the container directly,
+/// so interfaces with proxy auth, directives, an access log or rate limits are not routed.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn expose(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg] enable: bool,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(&package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut db)
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if enable
+        && manifest
+            .interfaces
+            .0
+            .get(&interface)
+            .map_or(true, |i| i.lan_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no LAN interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    if enable
+        && ctx
+            .net_controller
+            .vhost
+            .guarded(&(package.clone(), interface.clone()))
+            .await
+    {
+        return Err(Error::new(
+            eyre!(
+                "{} of {} has proxy auth, directives, an access log or rate limits, which tailnet devices would bypass",
+                interface,
+                package
+            ),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .tailscale()
+        .get_mut(&mut db)
+        .await?;
+    if enable {
+        settings
+            .exposed
+            .entry(package)
+            .or_default()
+            .insert(interface);
+    } else if let Some(interfaces) = settings.exposed.get_mut(&package) {
+        interfaces.remove(&interface);
+        if interfaces.is_empty() {
+            settings.exposed.remove(&package);
+        }
+    }
+    if settings.enabled {
+        ctx.net_controller
+            .set_tailnet(Some(settings.exposed.clone()))
+            .await?;
+    }
+    settings.save(&mut db).await?;
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TailscaleStatus {
+    /// As reported by tailscaled, e.g. `Running` or `NeedsLogin`
+    pub state: String,
+    pub dns_name: Option<String>,
+    pub addresses: Vec<IpAddr>,
+    #[serde(flatten)]
+    pub settings: TailscaleSettings,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawStatus {
+    backend_state: String,
+    #[serde(rename = "Self")]
+    node: Option<RawNode>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct RawNode {
+    #[serde(rename = "DNSName", default)]
+    dns_name: String,
+    #[serde(rename = "TailscaleIPs", default)]
+    addresses: Vec<IpAddr>,
+}
+
+fn display_status(arg: TailscaleStatus, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    println!(
+        "{} {}",
+        arg.state,
+        arg.dns_name.as_deref().unwrap_or_default()
+    );
+    for address in &arg.addresses {
+        println!("{}", address);
+    }
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "INTERFACE"]);
+    for (package, interfaces) in &arg.settings.exposed {
+        for interface in interfaces {
+            table.add_row(row![&**package, &**interface]);
+        }
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_status), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn status(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<TailscaleStatus, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .tailscale()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let raw: RawStatus = serde_json::from_slice(
+        &Command::new("tailscale")
+            .arg("status")
+            .arg("--json")
+            .invoke(ErrorKind::Tailscale)
+            .await?,
+    )
+    .with_kind(ErrorKind::Deserialization)?;
+    let node = raw.node.filter(|n| !n.addresses.is_empty());
+    Ok(TailscaleStatus {
+        state: raw.backend_state,
+        dns_name: node
+            .as_ref()
+            .map(|n| n.dns_name.trim_end_matches('.').to_owned()),
+        addresses: node.map(|n| n.addresses).unwrap_or_default(),
+        settings,
+    })
+}
+
+#[test]
+fn raw_status() {
+    let raw: RawStatus = serde_json::from_str(
+        r#"{"BackendState":"Running","Self":{"DNSName":"server.tailnet.ts.net.","TailscaleIPs":["100.64.0.1","fd7a:115c:a1e0::1"]}}"#,
+    )
+    .unwrap();
+    assert_eq!(raw.backend_state, "Running");
+    let node = raw.node.unwrap();
+    assert_eq!(node.dns_name, "server.tailnet.ts.net.");
+    assert_eq!(node.addresses.len(), 2);
+}
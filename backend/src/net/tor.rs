@@ -11,12 +11,14 @@ use futures::{FutureExt, TryStreamExt};
 use helpers::NonDetachingJoinHandle;
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use models::InterfaceId;
 use regex::Regex;
 use rpc_toolkit::command;
 use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpStream;
 use tokio::process::Command;
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, RwLock};
 use tokio::time::Instant;
 use torut::control::{AsyncEvent, AuthenticatedConn, ConnError};
 use torut::onion::{OnionAddressV3, TorSecretKeyV3};
@@ -27,12 +29,104 @@ use crate::logs::{
     cli_logs_generic_follow, cli_logs_generic_nofollow, fetch_logs, follow_logs, journalctl,
     LogFollowResponse, LogResponse, LogSource,
 };
+use crate::s9pk::manifest::PackageId;
+use crate::status::MainStatus;
 use crate::util::serde::{display_serializable, IoFormat};
 use crate::util::{display_none, Invoke};
 use crate::{Error, ErrorKind, ResultExt as _};
 
 pub const SYSTEMD_UNIT: &str = "tor@default";
 const STARTING_HEALTH_TIMEOUT: u64 = 120; // 2min
+/// Begins a `hs_ed25519_secret_key` file
+const HS_SECRET_KEY_HEADER: &[u8] = b"== ed25519v1-secret: type0 ==\0\0\0";
+const TORRC_PATH: &str = "/etc/tor/torrc";
+const TORRC_BASE: &str = "SocksPort 0.0.0.0:9050
+SocksPolicy accept 127.0.0.1
+SocksPolicy accept 172.18.0.0/16
+SocksPolicy reject *
+ControlPort 9051
+CookieAuthentication 1
+TransPort 172.18.0.1:9040
+DNSPort 172.18.0.1:9053
+AutomapHostsOnResolve 1
+";
+/// The pluggable transports bridges may use, with the client that implements each
+const TRANSPORTS: &[(&str, &str)] = &[
+    ("obfs4", "/usr/bin/obfs4proxy"),
+    ("snowflake", "/usr/bin/snowflake-client"),
+];
+
+/// See `net tor bridges`
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct TorBridges {
+    /// Connect to the Tor network only through `bridges`
+    pub enabled: bool,
+    /// Bridge lines, e.g. from bridges.torproject.org, without the leading `Bridge`
+    pub bridges: Vec<String>,
+}
+
+/// Normalizes a bridge line, returning it with the pluggable transport it uses, if any
+fn parse_bridge(line: &str) -> Result<(String, Option<&'static str>), Error> {
+    let line = line.trim();
+    let line = line.strip_prefix("Bridge ").unwrap_or(line).trim();
+    let mut words = line.split_ascii_whitespace();
+    let first = words.next().unwrap_or_default();
+    let (transport, addr) = if first.parse::<SocketAddr>().is_ok() {
+        (None, Some(first))
+    } else {
+        let transport = TRANSPORTS
+            .iter()
+            .find(|(t, _)| *t == first)
+            .map(|(t, _)| *t)
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!(
+                        "{} is not a supported transport, use one of {}",
+                        first,
+                        TRANSPORTS.iter().map(|(t, _)| *t).join(", ")
+                    ),
+                    ErrorKind::InvalidRequest,
+                )
+            })?;
+        (Some(transport), words.next())
+    };
+    if addr.map_or(true, |addr| addr.parse::<SocketAddr>().is_err()) {
+        return Err(Error::new(
+            eyre!("{} is not a bridge line", line),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok((line.split_ascii_whitespace().join(" "), transport))
+}
+
+/// The torrc StartOS manages, which connects through the enabled bridges
+fn torrc(bridges: &TorBridges) -> String {
+    let mut res = TORRC_BASE.to_owned();
+    if !bridges.enabled || bridges.bridges.is_empty() {
+        return res;
+    }
+    // lines are checked when they are added
+    let lines = bridges
+        .bridges
+        .iter()
+        .filter_map(|line| parse_bridge(line).ok())
+        .collect::<Vec<_>>();
+    res.push_str("UseBridges 1\n");
+    for (transport, exec) in TRANSPORTS {
+        if lines.iter().any(|(_, t)| t == &Some(*transport)) {
+            res.push_str(&format!(
+                "ClientTransportPlugin {} exec {}\n",
+                transport, exec
+            ));
+        }
+    }
+    for (line, _) in &lines {
+        res.push_str(&format!("Bridge {}\n", line));
+    }
+    res
+}
 
 enum ErrorLogSeverity {
     Fatal { wipe_state: bool },
@@ -58,11 +152,135 @@ fn random_key() {
     println!("x'{}'", hex::encode(rand::random::<[u8; 32]>()));
 }
 
-#[command(subcommands(list_services, logs, reset))]
+#[command(subcommands(
+    list_services,
+    logs,
+    reset,
+    bridges,
+    import_key,
+    super::vanity::vanity_generate,
+    super::vanity::vanity_cancel
+))]
 pub fn tor() -> Result<(), Error> {
     Ok(())
 }
 
+#[command(subcommands(add, remove, enable, disable, list))]
+pub fn bridges() -> Result<(), Error> {
+    Ok(())
+}
+
+async fn update_bridges<F: FnOnce(&mut TorBridges) -> Result<(), Error>>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<(), Error> {
+    let mut db = ctx.db.handle();
+    let mut bridges = crate::db::DatabaseModel::new()
+        .server_info()
+        .tor_bridges()
+        .get_mut(&mut db)
+        .await?;
+    f(&mut bridges)?;
+    if bridges.enabled && bridges.bridges.is_empty() {
+        return Err(Error::new(
+            eyre!("Add a bridge with `net tor bridges add` first"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    ctx.net_controller.tor.set_bridges(bridges.clone()).await?;
+    bridges.save(&mut db).await?;
+    Ok(())
+}
+
+/// Adds `bridge`, a line as given by bridges.torproject.org, to those Tor connects through.
+/// Bridges may be plain or use the obfs4 or snowflake pluggable transports.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn add(#[context] ctx: RpcContext, #[arg] bridge: String) -> Result<(), Error> {
+    let (bridge, _) = parse_bridge(&bridge)?;
+    update_bridges(&ctx, |bridges| {
+        if !bridges.bridges.contains(&bridge) {
+            bridges.bridges.push(bridge);
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] bridge: String) -> Result<(), Error> {
+    let (bridge, _) = parse_bridge(&bridge)?;
+    update_bridges(&ctx, |bridges| {
+        let len = bridges.bridges.len();
+        bridges.bridges.retain(|b| b != &bridge);
+        if bridges.bridges.len() == len {
+            return Err(Error::new(
+                eyre!("{} is not a configured bridge", bridge),
+                ErrorKind::NotFound,
+            ));
+        }
+        if bridges.bridges.is_empty() {
+            bridges.enabled = false;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Connects to the Tor network only through the configured bridges, for networks that block
+/// direct connections to it
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update_bridges(&ctx, |bridges| {
+        bridges.enabled = true;
+        Ok(())
+    })
+    .await
+}
+
+/// Connects to the Tor network directly again, keeping the bridges for later
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update_bridges(&ctx, |bridges| {
+        bridges.enabled = false;
+        Ok(())
+    })
+    .await
+}
+
+fn display_bridges(arg: TorBridges, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    println!("{}", if arg.enabled { "enabled" } else { "disabled" });
+    let mut table = Table::new();
+    for bridge in &arg.bridges {
+        table.add_row(row![bridge]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_bridges), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<TorBridges, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .tor_bridges()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned())
+}
+
 #[command(display(display_none))]
 pub async fn reset(
     #[context] ctx: RpcContext,
@@ -90,7 +308,11 @@ fn display_services(services: Vec<OnionAddressV3>, matches: &ArgMatches) {
     table.print_tty(false).unwrap();
 }
 
-#[command(rename = "list-services", display(display_services))]
+#[command(
+    rename = "list-services",
+    display(display_services),
+    metadata(read_only = true)
+)]
 pub async fn list_services(
     #[context] ctx: RpcContext,
     #[allow(unused_variables)]
@@ -103,7 +325,8 @@ pub async fn list_services(
 #[command(
     custom_cli(cli_logs(async, context(CliContext))),
     subcommands(self(logs_nofollow(async)), logs_follow),
-    display(display_none)
+    display(display_none),
+    metadata(read_only = true)
 )]
 pub async fn logs(
     #[arg(short = 'l', long = "limit")] limit: Option<usize>,
@@ -142,7 +365,12 @@ pub async fn logs_nofollow(
     fetch_logs(LogSource::Service(SYSTEMD_UNIT), limit, cursor, before).await
 }
 
-#[command(rpc_only, rename = "follow", display(display_none))]
+#[command(
+    rpc_only,
+    rename = "follow",
+    display(display_none),
+    metadata(read_only = true)
+)]
 pub async fn logs_follow(
     #[context] ctx: RpcContext,
     #[parent_data] (limit, _, _, _): (Option<usize>, Option<String>, bool, bool),
@@ -150,22 +378,178 @@ pub async fn logs_follow(
     follow_logs(ctx, LogSource::Service(SYSTEMD_UNIT), limit).await
 }
 
+pub(super) async fn check_tor_interface(
+    ctx: &RpcContext,
+    package: &PackageId,
+    interface: &InterfaceId,
+) -> Result<(), Error> {
+    let manifest = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|p| p.installed())
+        .map(|i| i.manifest())
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .ok_or_else(|| Error::new(eyre!("{} is not installed", package), ErrorKind::NotFound))?;
+    if manifest
+        .interfaces
+        .0
+        .get(interface)
+        .map_or(true, |i| i.tor_config.is_none())
+    {
+        return Err(Error::new(
+            eyre!("{} has no Tor interface {}", package, interface),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+/// Replaces the onion key of `interface`, with the addresses derived from it
+pub(super) async fn replace_key(
+    ctx: &RpcContext,
+    package: &PackageId,
+    interface: &InterfaceId,
+    key: [u8; 64],
+) -> Result<OnionAddressV3, Error> {
+    let key_vec = key.to_vec();
+    sqlx::query!(
+        "INSERT INTO tor (package, interface, key) VALUES ($1, $2, $3) ON CONFLICT (package, interface) DO UPDATE SET key = EXCLUDED.key",
+        **package,
+        **interface,
+        key_vec,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let onion = TorSecretKeyV3::from(key).public().get_onion_address();
+    let mut db = ctx.db.handle();
+    let mut tx = db.begin().await?;
+    let installed = crate::db::DatabaseModel::new()
+        .package_data()
+        .idx_model(package)
+        .and_then(|m| m.installed());
+    if let Some(addresses) = installed
+        .clone()
+        .and_then(|i| i.interface_addresses().idx_model(interface))
+        .check(&mut tx)
+        .await?
+    {
+        let mut tor_address = addresses.clone().tor_address().get_mut(&mut tx).await?;
+        if tor_address.is_some() {
+            *tor_address = Some(onion.to_string());
+            tor_address.save(&mut tx).await?;
+        }
+        let mut lan_address = addresses.lan_address().get_mut(&mut tx).await?;
+        if lan_address.is_some() {
+            *lan_address = Some(format!("{}.local", onion.get_address_without_dot_onion()));
+            lan_address.save(&mut tx).await?;
+        }
+    }
+    // the running service is still bound to the old key
+    let mut status = installed
+        .map(|i| i.status().main())
+        .get_mut(&mut tx)
+        .await?;
+    if matches!(&*status, Some(MainStatus::Running { .. })) {
+        *status = Some(MainStatus::Restarting);
+        status.save(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(onion)
+}
+
+/// The expanded ed25519 key in `key`: the contents of a `hs_ed25519_secret_key` file of tor, in
+/// base64 or hex, or the `ED25519-V3:` key of its control port
+fn parse_onion_key(key: &str) -> Result<[u8; 64], Error> {
+    let key = key.trim();
+    let key = key.strip_prefix("ED25519-V3:").unwrap_or(key);
+    // hex is valid base64 too, so whichever decodes to a key
+    let bytes = [base64::decode(key).ok(), hex::decode(key).ok()]
+        .into_iter()
+        .flatten()
+        .find_map(|bytes| match bytes.len() {
+            64 => Some(bytes),
+            96 if bytes.starts_with(HS_SECRET_KEY_HEADER) => Some(bytes[32..].to_vec()),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("The key is not a v3 onion service key"),
+                ErrorKind::InvalidRequest,
+            )
+        })?;
+    // the scalar of an expanded key is clamped
+    if bytes[0] & 0b111 != 0 || bytes[31] & 0b1100_0000 != 0b0100_0000 {
+        return Err(Error::new(
+            eyre!("The key is not a valid ed25519 key"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let mut res = [0; 64];
+    res.copy_from_slice(&bytes);
+    Ok(res)
+}
+
 fn event_handler(_event: AsyncEvent<'static>) -> BoxFuture<'static, Result<(), ConnError>> {
     async move { Ok(()) }.boxed()
 }
 
+/// Gives `interface` of `package` the onion address of `key`, as used by another tor, so it keeps
+/// the address it is known by. Restarts the package if it is running, and the current address
+/// stops working.
+#[command(
+    rename = "import-key",
+    display(display_serializable),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn import_key(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg] key: String,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<OnionAddressV3, Error> {
+    let key = parse_onion_key(&key)?;
+    check_tor_interface(&ctx, &package, &interface).await?;
+    replace_key(&ctx, &package, &interface, key).await
+}
+
 pub struct TorController(TorControl);
 impl TorController {
-    pub fn new(tor_control: SocketAddr, tor_socks: SocketAddr) -> Self {
-        TorController(TorControl::new(tor_control, tor_socks))
+    pub fn new(tor_control: SocketAddr, tor_socks: SocketAddr, bridges: TorBridges) -> Self {
+        TorController(TorControl::new(tor_control, tor_socks, bridges))
     }
 
+    /// Restarts tor with `bridges` rendered into its torrc
+    pub async fn set_bridges(&self, bridges: TorBridges) -> Result<(), Error> {
+        *self.0.bridges.write().await = bridges;
+        self.reset(
+            false,
+            Error::new(eyre!("Tor bridges changed"), ErrorKind::Tor),
+        )
+        .await
+    }
+
+    /// `max_streams` caps the streams of each circuit to the onion service of `key`, closing
+    /// the circuits over it, as of the next time it is published
     pub async fn add(
         &self,
         key: TorSecretKeyV3,
         external: u16,
         target: SocketAddr,
+        max_streams: Option<u16>,
     ) -> Result<Arc<()>, Error> {
+        {
+            let mut stream_limits = self.0.stream_limits.write().await;
+            match max_streams {
+                Some(max) => stream_limits.insert(key.as_bytes(), max),
+                None => stream_limits.remove(&key.as_bytes()),
+            };
+        }
         let (reply, res) = oneshot::channel();
         self.0
             .send
@@ -205,6 +589,28 @@ impl TorController {
             .ok_or_else(|| Error::new(eyre!("TorControl died"), ErrorKind::Tor))
     }
 
+    /// The `status/bootstrap-phase` of tor, e.g.
+    /// `NOTICE BOOTSTRAP PROGRESS=100 TAG=done SUMMARY="Done"`
+    pub async fn bootstrap_phase(&self) -> Result<String, Error> {
+        self.get_info("status/bootstrap-phase").await
+    }
+
+    /// The answer of tor to `GETINFO query`
+    pub async fn get_info(&self, query: &str) -> Result<String, Error> {
+        let (reply, res) = oneshot::channel();
+        self.0
+            .send
+            .send(TorCommand::GetInfo {
+                query: query.into(),
+                reply,
+            })
+            .ok()
+            .ok_or_else(|| Error::new(eyre!("TorControl died"), ErrorKind::Tor))?;
+        res.await
+            .ok()
+            .ok_or_else(|| Error::new(eyre!("TorControl died"), ErrorKind::Tor))?
+    }
+
     pub async fn list_services(&self) -> Result<Vec<OnionAddressV3>, Error> {
         let (reply, res) = oneshot::channel();
         self.0
@@ -260,6 +666,8 @@ async fn torctl(
     services: &mut BTreeMap<[u8; 64], BTreeMap<u16, BTreeMap<SocketAddr, Weak<()>>>>,
     wipe_state: &AtomicBool,
     health_timeout: &mut Duration,
+    bridges: &RwLock<TorBridges>,
+    stream_limits: &RwLock<BTreeMap<[u8; 64], u16>>,
 ) -> Result<(), Error> {
     let bootstrap = async {
         if Command::new("systemctl")
@@ -299,6 +707,7 @@ async fn torctl(
             .arg("/var/lib/tor")
             .invoke(ErrorKind::Filesystem)
             .await?;
+        tokio::fs::write(TORRC_PATH, torrc(&*bridges.read().await)).await?;
         Command::new("systemctl")
             .arg("start")
             .arg("tor")
@@ -447,8 +856,16 @@ async fn torctl(
             .collect::<Vec<_>>();
         if !bindings.is_empty() {
             services.insert(key.as_bytes(), service);
+            let max_streams = stream_limits.read().await.get(&key.as_bytes()).copied();
             connection
-                .add_onion_v3(&key, false, false, false, None, &mut bindings.iter())
+                .add_onion_v3(
+                    &key,
+                    false,
+                    false,
+                    max_streams.is_some(),
+                    max_streams,
+                    &mut bindings.iter(),
+                )
                 .await?;
         }
     }
@@ -494,8 +911,16 @@ async fn torctl(
                     services.insert(key.as_bytes(), service);
                     reply.send(rc).unwrap_or_default();
                     rm_res?;
+                    let max_streams = stream_limits.read().await.get(&key.as_bytes()).copied();
                     connection
-                        .add_onion_v3(&key, false, false, false, None, &mut bindings.iter())
+                        .add_onion_v3(
+                            &key,
+                            false,
+                            false,
+                            max_streams.is_some(),
+                            max_streams,
+                            &mut bindings.iter(),
+                        )
                         .await?;
                 }
                 TorCommand::GC { key, external } => {
@@ -542,13 +967,15 @@ async fn torctl(
                                 }
                                 rm_res?;
                                 if !bindings.is_empty() {
+                                    let max_streams =
+                                        stream_limits.read().await.get(&key.as_bytes()).copied();
                                     connection
                                         .add_onion_v3(
                                             &key,
                                             false,
                                             false,
-                                            false,
-                                            None,
+                                            max_streams.is_some(),
+                                            max_streams,
                                             &mut bindings.iter(),
                                         )
                                         .await?;
@@ -652,10 +1079,17 @@ async fn torctl(
 struct TorControl {
     _thread: NonDetachingJoinHandle<()>,
     send: mpsc::UnboundedSender<TorCommand>,
+    bridges: Arc<RwLock<TorBridges>>,
+    /// The `MaxStreams` of the onion services, by key, see `net rate-limit`
+    stream_limits: Arc<RwLock<BTreeMap<[u8; 64], u16>>>,
 }
 impl TorControl {
-    pub fn new(tor_control: SocketAddr, tor_socks: SocketAddr) -> Self {
+    pub fn new(tor_control: SocketAddr, tor_socks: SocketAddr, bridges: TorBridges) -> Self {
         let (send, mut recv) = mpsc::unbounded_channel();
+        let bridges = Arc::new(RwLock::new(bridges));
+        let torrc_bridges = bridges.clone();
+        let stream_limits = Arc::new(RwLock::new(BTreeMap::new()));
+        let torctl_stream_limits = stream_limits.clone();
         Self {
             _thread: tokio::spawn(async move {
                 let mut services = BTreeMap::new();
@@ -668,6 +1102,8 @@ impl TorControl {
                     &mut services,
                     &wipe_state,
                     &mut health_timeout,
+                    &torrc_bridges,
+                    &torctl_stream_limits,
                 )
                 .await
                 {
@@ -678,6 +1114,8 @@ impl TorControl {
             })
             .into(),
             send,
+            bridges,
+            stream_limits,
         }
     }
 }
@@ -737,3 +1175,36 @@ async fn test() {
         .await
         .unwrap();
 }
+
+#[test]
+fn bridges_torrc() {
+    let (obfs4, transport) = parse_bridge(
+        "Bridge obfs4 192.0.2.1:443 0123456789ABCDEF0123456789ABCDEF01234567 cert=c2VjcmV0 iat-mode=0",
+    )
+    .unwrap();
+    assert_eq!(transport, Some("obfs4"));
+    assert!(parse_bridge("meek 192.0.2.1:443 0123456789ABCDEF").is_err());
+    assert!(parse_bridge("obfs4 example.com 0123456789ABCDEF").is_err());
+    let torrc = torrc(&TorBridges {
+        enabled: true,
+        bridges: vec![obfs4, "192.0.2.2:9001".into()],
+    });
+    assert!(torrc.starts_with(TORRC_BASE));
+    assert!(torrc.contains("UseBridges 1\nClientTransportPlugin obfs4 exec /usr/bin/obfs4proxy\n"));
+    assert!(!torrc.contains("snowflake"));
+    assert!(torrc.ends_with("Bridge 192.0.2.2:9001\n"));
+}
+
+#[test]
+fn onion_keys() {
+    let key = TorSecretKeyV3::generate().as_bytes();
+    let file = [HS_SECRET_KEY_HEADER, &key[..]].concat();
+    assert_eq!(parse_onion_key(&base64::encode(&file)).unwrap(), key);
+    assert_eq!(parse_onion_key(&hex::encode(&key)).unwrap(), key);
+    assert_eq!(
+        parse_onion_key(&format!("ED25519-V3:{}", base64::encode(&key))).unwrap(),
+        key
+    );
+    assert!(parse_onion_key(&base64::encode(&key[..32])).is_err());
+    assert!(parse_onion_key(&base64::encode([0xff; 64])).is_err());
+}
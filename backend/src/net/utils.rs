@@ -1,12 +1,15 @@
+use std::collections::BTreeMap;
 use std::convert::Infallible;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::path::Path;
+use std::sync::{Mutex, RwLock};
 
 use async_stream::try_stream;
 use color_eyre::eyre::eyre;
 use futures::stream::BoxStream;
 use futures::{StreamExt, TryStreamExt};
 use ipnet::{Ipv4Net, Ipv6Net};
+use lazy_static::lazy_static;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Command;
 
@@ -49,8 +52,74 @@ pub async fn get_iface_ipv4_addr(iface: &str) -> Result<Option<(Ipv4Addr, Ipv4Ne
     .transpose()?)
 }
 
-pub async fn get_iface_ipv6_addr(iface: &str) -> Result<Option<(Ipv6Addr, Ipv6Net)>, Error> {
-    Ok(parse_iface_ip(&String::from_utf8(
+/// Whether `ip` is a unique local address (fc00::/7), which is only routable within the LAN
+pub fn is_ula(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xfe00 == 0xfc00
+}
+
+lazy_static! {
+    static ref ON_LINK_V6: RwLock<Vec<Ipv6Net>> = RwLock::new(Vec::new());
+}
+
+/// Sets the global IPv6 prefixes of the networks this server is on, whose devices are as much
+/// on its LAN as the ones using a unique local address
+pub fn set_on_link_v6(prefixes: Vec<Ipv6Net>) {
+    *ON_LINK_V6.write().unwrap() = prefixes;
+}
+
+/// Whether `ip` is on a network of this server rather than the internet: its LAN, a VPN into
+/// it, or a tailnet, which uses the shared address space
+pub fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let octets = ip.octets();
+            if octets[..10] == [0; 10] && octets[10..12] == [0xff, 0xff] {
+                is_private(&Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]).into())
+            } else {
+                ip.is_loopback()
+                    || is_ula(ip)
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+                    || ON_LINK_V6
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .any(|net| net.contains(ip))
+            }
+        }
+    }
+}
+
+/// The addresses in `ip -6 -o addr show` output that other devices can reach this server at:
+/// link-local addresses need a scope to be used, and temporary (privacy) addresses rotate
+fn parse_iface_ipv6(output: &str) -> Result<Vec<(Ipv6Addr, Ipv6Net)>, Error> {
+    let stable = output
+        .lines()
+        .filter(|line| {
+            !line
+                .split_ascii_whitespace()
+                .any(|flag| flag == "temporary" || flag == "deprecated" || flag == "tentative")
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    parse_iface_ip(&stable)?
+        .into_iter()
+        .map(|s| Ok::<_, Error>((s.split("/").next().unwrap().parse()?, s.parse()?)))
+        .filter(|res| {
+            res.as_ref().map_or(true, |(ip, _): &(Ipv6Addr, _)| {
+                ip.segments()[0] & 0xffc0 != 0xfe80
+            })
+        })
+        .collect()
+}
+
+pub async fn get_iface_ipv6_addrs(iface: &str) -> Result<Vec<(Ipv6Addr, Ipv6Net)>, Error> {
+    parse_iface_ipv6(&String::from_utf8(
         Command::new("ip")
             .arg("-6")
             .arg("-o")
@@ -59,11 +128,7 @@ pub async fn get_iface_ipv6_addr(iface: &str) -> Result<Option<(Ipv6Addr, Ipv6Ne
             .arg(iface)
             .invoke(crate::ErrorKind::Network)
             .await?,
-    )?)?
-    .into_iter()
-    .find(|ip| !ip.starts_with("fe80::"))
-    .map(|s| Ok::<_, Error>((s.split("/").next().unwrap().parse()?, s.parse()?)))
-    .transpose()?)
+    )?)
 }
 
 pub async fn iface_is_physical(iface: &str) -> bool {
@@ -164,3 +229,96 @@ impl hyper::server::accept::Accept for TcpListeners {
         std::task::Poll::Pending
     }
 }
+
+lazy_static! {
+    static ref PROXIED_PEERS: Mutex<BTreeMap<SocketAddr, (SocketAddr, bool)>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// The loopback address the onion services of StartOS itself are forwarded to. Connections
+/// accepted on it arrived over Tor, whatever the client put in its headers.
+pub const TOR_LOOPBACK: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+
+/// Whether a connection accepted on `local` was forwarded by Tor
+pub fn accepted_via_tor(local: SocketAddr) -> bool {
+    match local.ip() {
+        IpAddr::V4(ip) => ip == TOR_LOOPBACK,
+        IpAddr::V6(ip) => ip.to_ipv4_mapped() == Some(TOR_LOOPBACK),
+    }
+}
+
+/// The address of the client that originated a request, as inserted into the request extensions
+/// by the web server. Connections forwarded by the vhost controller are traced back to the client
+/// that connected to it rather than reported as coming from localhost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr(pub SocketAddr);
+
+/// Inserted into the request extensions by the web server for the connections forwarded by the
+/// vhost controller, which terminated TLS for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tls;
+
+/// Inserted into the request extensions by the web server for the connections that were accepted
+/// on [`TOR_LOOPBACK`], directly or through the vhost controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViaTor;
+
+/// Keeps a proxied connection resolvable through [`resolve_peer`] for as long as it is held
+pub struct ProxiedPeerGuard(SocketAddr);
+impl Drop for ProxiedPeerGuard {
+    fn drop(&mut self) {
+        PROXIED_PEERS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Records that the outgoing connection bound to `local` is carrying traffic for `peer`, which
+/// was accepted over Tor if `tor` is set
+pub fn register_proxied_peer(local: SocketAddr, peer: SocketAddr, tor: bool) -> ProxiedPeerGuard {
+    PROXIED_PEERS.lock().unwrap().insert(local, (peer, tor));
+    ProxiedPeerGuard(local)
+}
+
+/// The client behind the connection from `addr`, and whether it was accepted over Tor by the
+/// vhost controller. `None` if the connection was not proxied.
+pub fn resolve_peer(addr: SocketAddr) -> Option<(SocketAddr, bool)> {
+    PROXIED_PEERS.lock().unwrap().get(&addr).copied()
+}
+
+#[test]
+fn ipv6_addrs() {
+    let addrs = parse_iface_ipv6(
+        "2: eth0    inet6 2001:db8::8a2e:370:7334/64 scope global temporary dynamic \\       valid_lft 86000sec preferred_lft 14000sec
+2: eth0    inet6 2001:db8::1/64 scope global dynamic mngtmpaddr noprefixroute \\       valid_lft 86000sec preferred_lft 14000sec
+2: eth0    inet6 fd12:3456:789a::1/64 scope global dynamic mngtmpaddr noprefixroute \\       valid_lft 86000sec preferred_lft 14000sec
+2: eth0    inet6 fe80::1/64 scope link \\       valid_lft forever preferred_lft forever",
+    )
+    .unwrap();
+    assert_eq!(
+        addrs.iter().map(|(ip, _)| *ip).collect::<Vec<_>>(),
+        vec![
+            "2001:db8::1".parse::<Ipv6Addr>().unwrap(),
+            "fd12:3456:789a::1".parse().unwrap()
+        ]
+    );
+    assert!(!is_ula(&addrs[0].0));
+    assert!(is_ula(&addrs[1].0));
+}
+
+#[test]
+fn private_addrs() {
+    for ip in [
+        "192.168.1.20",
+        "100.101.102.103",
+        "::ffff:10.0.0.2",
+        "fd7a:115c:a1e0::1",
+    ] {
+        assert!(is_private(&ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in ["1.1.1.1", "100.128.0.1", "::ffff:8.8.8.8", "2001:db8::1"] {
+        assert!(!is_private(&ip.parse().unwrap()), "{}", ip);
+    }
+    set_on_link_v6(vec!["2001:db8::/64".parse().unwrap()]);
+    assert!(is_private(&"2001:db8::1".parse().unwrap()));
+    assert!(!is_private(&"2001:db8:1::1".parse().unwrap()));
+    set_on_link_v6(Vec::new());
+}
@@ -0,0 +1,254 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::eyre;
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey};
+use lazy_static::lazy_static;
+use models::InterfaceId;
+use rpc_toolkit::command;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::db::model::VanityProgress;
+use crate::net::tor::{check_tor_interface, replace_key};
+use crate::notifications::NotificationLevel;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::{Error, ErrorKind};
+
+/// Each character takes 32 times as long to find: 7 is about a day on a Raspberry Pi 4
+const MAX_PREFIX_LEN: usize = 7;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// Attempts between checks for cancellation
+const BATCH: u64 = 1024;
+
+lazy_static! {
+    static ref CANCEL: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+}
+
+fn check_prefix(prefix: &str) -> Result<(), Error> {
+    if prefix.is_empty() || prefix.len() > MAX_PREFIX_LEN {
+        return Err(Error::new(
+            eyre!("The prefix must be 1 to {} characters long", MAX_PREFIX_LEN),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    if let Some(c) = prefix.chars().find(|c| !matches!(c, 'a'..='z' | '2'..='7')) {
+        return Err(Error::new(
+            eyre!("Onion addresses cannot contain {:?}, only a-z and 2-7", c),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    Ok(())
+}
+
+/// The onion address of `public` begins with the base32 of its bytes: 5 bits per character
+fn has_prefix(public: &PublicKey, prefix: &str) -> bool {
+    let len = (prefix.len() * 5 + 7) / 8;
+    base32::encode(
+        base32::Alphabet::RFC4648 { padding: false },
+        &public.as_bytes()[..len],
+    )
+    .to_lowercase()
+    .starts_with(prefix)
+}
+
+/// Generates keys until one has `prefix`, or `stop` is set
+fn mine(prefix: &str, attempts: &AtomicU64, stop: &AtomicBool) -> Option<[u8; 64]> {
+    while !stop.load(Ordering::Relaxed) {
+        for _ in 0..BATCH {
+            let secret = SecretKey::from_bytes(&rand::random::<[u8; 32]>()).unwrap();
+            let expanded = ExpandedSecretKey::from(&secret);
+            if has_prefix(&PublicKey::from(&expanded), prefix) {
+                stop.store(true, Ordering::Relaxed);
+                return Some(expanded.to_bytes());
+            }
+        }
+        attempts.fetch_add(BATCH, Ordering::Relaxed);
+    }
+    None
+}
+
+async fn run(
+    ctx: &RpcContext,
+    mut progress: VanityProgress,
+    stop: Arc<AtomicBool>,
+) -> Result<Option<String>, Error> {
+    let attempts = Arc::new(AtomicU64::new(0));
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut miners = (0..threads)
+        .map(|_| {
+            let prefix = progress.prefix.clone();
+            let attempts = attempts.clone();
+            let stop = stop.clone();
+            tokio::task::spawn_blocking(move || mine(&prefix, &attempts, &stop))
+        })
+        .collect::<Vec<_>>();
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    while !stop.load(Ordering::Relaxed) {
+        interval.tick().await;
+        progress.attempts = attempts.load(Ordering::Relaxed);
+        if let Err(e) = crate::db::DatabaseModel::new()
+            .server_info()
+            .status_info()
+            .vanity_progress()
+            .put(&mut ctx.db.handle(), &progress)
+            .await
+        {
+            stop.store(true, Ordering::Relaxed);
+            return Err(e.into());
+        }
+    }
+    let mut found = None;
+    for miner in miners.drain(..) {
+        if let Some(key) = miner.await.map_err(|e| {
+            Error::new(
+                eyre!("{}", e).wrap_err("Vanity miner panicked!"),
+                ErrorKind::Unknown,
+            )
+        })? {
+            found = Some(key);
+        }
+    }
+    match found {
+        Some(key) => Ok(Some(
+            replace_key(ctx, &progress.package, &progress.interface, key)
+                .await?
+                .to_string(),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Searches for an onion key whose address begins with `prefix` for `interface` of `package`,
+/// in the background with progress in the server status. Once found, it replaces the current
+/// key, changing the Tor and LAN addresses of the interface, and restarts the package if it is
+/// running. The old addresses stop working.
+#[command(
+    rename = "vanity-generate",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn vanity_generate(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg] interface: InterfaceId,
+    #[arg(long = "prefix")] prefix: String,
+) -> Result<(), Error> {
+    let prefix = prefix.to_lowercase();
+    check_prefix(&prefix)?;
+    check_tor_interface(&ctx, &package, &interface).await?;
+    let stop = Arc::new(AtomicBool::new(false));
+    {
+        let mut cancel = CANCEL.lock().unwrap();
+        if cancel.is_some() {
+            return Err(Error::new(
+                eyre!("A vanity address is already being generated"),
+                ErrorKind::InvalidRequest,
+            ));
+        }
+        *cancel = Some(stop.clone());
+    }
+    let progress = VanityProgress {
+        package: package.clone(),
+        interface: interface.clone(),
+        expected: 32_u64.pow(prefix.len() as u32),
+        prefix,
+        attempts: 0,
+        started_at: Utc::now(),
+    };
+
+    tokio::spawn(async move {
+        let res = run(&ctx, progress, stop).await;
+        *CANCEL.lock().unwrap() = None;
+        let mut db = ctx.db.handle();
+        let cleared = async {
+            let mut status = crate::db::DatabaseModel::new()
+                .server_info()
+                .status_info()
+                .get_mut(&mut db)
+                .await?;
+            status.vanity_progress = None;
+            status.save(&mut db).await
+        };
+        if let Err(e) = cleared.await {
+            let e = Error::from(e);
+            tracing::error!("Error Clearing Vanity Progress: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+        let (level, title, message) = match res {
+            Ok(Some(onion)) => (
+                NotificationLevel::Success,
+                "Vanity Address Ready",
+                format!("{} of {} is now at {}", interface, package, onion),
+            ),
+            Ok(None) => return,
+            Err(e) => {
+                tracing::error!("Error Generating Vanity Address: {}", e);
+                tracing::debug!("{:?}", e);
+                (
+                    NotificationLevel::Error,
+                    "Vanity Address Failed",
+                    format!("Generating an address for {} failed: {}", package, e),
+                )
+            }
+        };
+        if let Err(e) = ctx
+            .notification_manager
+            .notify(
+                &mut db,
+                Some(package.clone()),
+                level,
+                title.to_owned(),
+                message,
+                (),
+                None,
+            )
+            .await
+        {
+            tracing::error!("Failed to notify: {}", e);
+            tracing::debug!("{:?}", e);
+        }
+    });
+    Ok(())
+}
+
+/// Stops the search started with `net tor vanity-generate`, keeping the current address
+#[command(
+    rename = "vanity-cancel",
+    display(display_none),
+    metadata(sync_db = true, admin = true)
+)]
+pub async fn vanity_cancel() -> Result<(), Error> {
+    match &*CANCEL.lock().unwrap() {
+        Some(stop) => {
+            stop.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        None => Err(Error::new(
+            eyre!("No vanity address is being generated"),
+            ErrorKind::NotFound,
+        )),
+    }
+}
+
+#[test]
+fn prefixes() {
+    assert!(check_prefix("start9").is_ok());
+    assert!(check_prefix("bitcoin1").is_err());
+    assert!(check_prefix("b1tc").is_err());
+    assert!(check_prefix("").is_err());
+    let expanded = ExpandedSecretKey::from(&SecretKey::from_bytes(&[7; 32]).unwrap());
+    let onion = torut::onion::TorSecretKeyV3::from(expanded.to_bytes())
+        .public()
+        .get_onion_address()
+        .get_address_without_dot_onion();
+    let public = PublicKey::from(&expanded);
+    for len in 1..=MAX_PREFIX_LEN {
+        assert!(has_prefix(&public, &onion[..len]));
+    }
+    assert!(!has_prefix(&public, "2222222"));
+}
@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::Infallible;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
@@ -10,31 +10,179 @@ use helpers::NonDetachingJoinHandle;
 use http::{Response, Uri};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Body;
-use models::ResultExt;
+use models::{InterfaceId, ResultExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{Mutex, RwLock};
 use tokio_rustls::rustls::server::Acceptor;
 use tokio_rustls::rustls::{RootCertStore, ServerConfig};
 use tokio_rustls::{LazyConfigAcceptor, TlsConnector};
 
+use crate::net::access_log::{AccessLog, AccessLogger};
+use crate::net::acme::ACME_TLS_ALPN;
+use crate::net::bandwidth::{BandwidthMonitor, CountingStream, Traffic};
+use crate::net::exposure::Exposure;
+use crate::net::https::{enforceable, HttpsPolicy, HttpsSettings};
 use crate::net::keys::Key;
+use crate::net::proxy_auth::ProxyAuth;
+use crate::net::proxy_directives::{relay, ProxyDirectives};
+use crate::net::rate_limit::{ConnectionSlot, RateLimit, RateLimiter};
 use crate::net::ssl::SslManager;
-use crate::net::utils::SingleAccept;
+use crate::net::utils::{
+    accepted_via_tor, is_private, register_proxied_peer, SingleAccept, TOR_LOOPBACK,
+};
+use crate::s9pk::manifest::PackageId;
 use crate::util::io::{BackTrackingReader, TimeoutStream};
 use crate::Error;
 
 // not allowed: <=1024, >=32768, 5355, 5432, 9050, 6010, 9051, 5353
 
+/// How the connections to an interface are handled, see `net proxy-auth`, `net proxy`,
+/// `net exposure`, `net rate-limit` and `net access-log`
+#[derive(Clone, Default)]
+struct InterfaceProxy {
+    auth: Option<ProxyAuth>,
+    directives: Option<ProxyDirectives>,
+    exposure: Exposure,
+    limiter: Option<Arc<RateLimiter>>,
+    access_log: Option<Arc<AccessLogger>>,
+}
+impl InterfaceProxy {
+    /// Whether the requests are read one at a time, to apply something to them
+    fn relays(&self) -> bool {
+        self.auth.is_some()
+            || self.directives.is_some()
+            || self.access_log.is_some()
+            || self
+                .limiter
+                .as_ref()
+                .map_or(false, |l| l.limit().requests_per_minute.is_some())
+    }
+    fn is_empty(&self) -> bool {
+        !self.relays() && self.exposure == Exposure::default() && self.limiter.is_none()
+    }
+}
+
+type ProxyMap = BTreeMap<(PackageId, InterfaceId), InterfaceProxy>;
+
 pub struct VHostController {
     ssl: Arc<SslManager>,
+    bandwidth: Arc<BandwidthMonitor>,
+    proxies: Arc<RwLock<ProxyMap>>,
+    https: Arc<RwLock<HttpsSettings>>,
+    access_logger: Arc<AccessLogger>,
     servers: Mutex<BTreeMap<u16, VHostServer>>,
+    tor_gates: Mutex<BTreeMap<SocketAddr, TorGate>>,
 }
 impl VHostController {
-    pub fn new(ssl: Arc<SslManager>) -> Self {
+    pub fn new(ssl: Arc<SslManager>, bandwidth: Arc<BandwidthMonitor>) -> Self {
         Self {
             ssl,
+            bandwidth,
+            proxies: Default::default(),
+            https: Default::default(),
+            access_logger: Arc::new(AccessLogger::new()),
             servers: Mutex::new(BTreeMap::new()),
+            tor_gates: Mutex::new(BTreeMap::new()),
+        }
+    }
+    async fn update_proxy<F: FnOnce(&mut InterfaceProxy)>(
+        &self,
+        interface: (PackageId, InterfaceId),
+        f: F,
+    ) {
+        let mut writable = self.proxies.write().await;
+        let mut proxy = writable.remove(&interface).unwrap_or_default();
+        f(&mut proxy);
+        if !proxy.is_empty() {
+            writable.insert(interface, proxy);
+        }
+    }
+    /// Applies to the connections made from now on, on every port of `interface`
+    pub async fn set_auth(&self, interface: (PackageId, InterfaceId), auth: Option<ProxyAuth>) {
+        self.update_proxy(interface, |p| p.auth = auth).await
+    }
+    pub async fn remove_package_auth(&self, package: &PackageId) {
+        let mut writable = self.proxies.write().await;
+        for ((p, _), proxy) in writable.iter_mut() {
+            if p == package {
+                proxy.auth = None;
+            }
         }
+        writable.retain(|_, proxy| !proxy.is_empty());
+    }
+    /// Applies to the connections made from now on, on every port of `interface`
+    pub async fn set_directives(
+        &self,
+        interface: (PackageId, InterfaceId),
+        directives: Option<ProxyDirectives>,
+    ) {
+        self.update_proxy(interface, |p| p.directives = directives)
+            .await
+    }
+    /// Applies to the connections made from now on, on every port of `interface`. The
+    /// addresses it is served at only change as it is bound again.
+    pub async fn set_exposure(
+        &self,
+        interface: (PackageId, InterfaceId),
+        exposure: Option<Exposure>,
+    ) {
+        self.update_proxy(interface, |p| p.exposure = exposure.unwrap_or_default())
+            .await
+    }
+    pub(super) async fn exposure(&self, interface: &(PackageId, InterfaceId)) -> Exposure {
+        self.proxies
+            .read()
+            .await
+            .get(interface)
+            .map(|p| p.exposure.clone())
+            .unwrap_or_default()
+    }
+    /// Applies to the connections made from now on, on every port of `interface`. The
+    /// connections already open are no longer counted.
+    pub async fn set_rate_limit(
+        &self,
+        interface: (PackageId, InterfaceId),
+        limit: Option<RateLimit>,
+    ) {
+        self.update_proxy(interface, |p| {
+            p.limiter = limit.map(|l| Arc::new(RateLimiter::new(l)))
+        })
+        .await
+    }
+    pub(super) async fn rate_limit(&self, interface: &(PackageId, InterfaceId)) -> RateLimit {
+        self.proxies
+            .read()
+            .await
+            .get(interface)
+            .and_then(|p| p.limiter.as_ref())
+            .map(|l| l.limit().clone())
+            .unwrap_or_default()
+    }
+    /// Whether reaching the container of `interface` directly would skip something the proxy
+    /// enforces for it
+    pub(super) async fn guarded(&self, interface: &(PackageId, InterfaceId)) -> bool {
+        self.proxies
+            .read()
+            .await
+            .get(interface)
+            .map_or(false, |p| p.relays() || p.limiter.is_some())
+    }
+    /// Applies to the connections made from now on, on every port of `interface`
+    pub async fn set_access_log(&self, interface: (PackageId, InterfaceId), enabled: bool) {
+        let logger = Some(self.access_logger.clone()).filter(|_| enabled);
+        self.update_proxy(interface, |p| p.access_log = logger)
+            .await
+    }
+    /// Applies to the connections made from now on
+    pub async fn set_https(&self, settings: HttpsSettings) {
+        *self.https.write().await = settings;
+    }
+    pub(super) async fn https_policy(
+        &self,
+        interface: Option<&(PackageId, InterfaceId)>,
+    ) -> HttpsPolicy {
+        self.https.read().await.policy(interface).clone()
     }
     pub async fn add(
         &self,
@@ -48,7 +196,14 @@ impl VHostController {
         let server = if let Some(server) = writable.remove(&external) {
             server
         } else {
-            VHostServer::new(external, self.ssl.clone()).await?
+            VHostServer::new(
+                external,
+                self.ssl.clone(),
+                self.proxies.clone(),
+                self.https.clone(),
+                self.bandwidth.clone(),
+            )
+            .await?
         };
         let rc = server
             .add(
@@ -73,6 +228,105 @@ impl VHostController {
         }
         Ok(())
     }
+    /// Where the onion services of `interface` forward to instead of `target`, for their
+    /// connections to count towards its `max-connections`
+    pub(super) async fn add_tor_gate(
+        &self,
+        interface: (PackageId, InterfaceId),
+        target: SocketAddr,
+    ) -> Result<(SocketAddr, Arc<()>), Error> {
+        let mut writable = self.tor_gates.lock().await;
+        writable.retain(|_, gate| gate.rc.strong_count() > 0);
+        if let Some(gate) = writable.get(&target) {
+            if let Some(rc) = Weak::upgrade(&gate.rc) {
+                return Ok((gate.local, rc));
+            }
+        }
+        let rc = Arc::new(());
+        let gate =
+            TorGate::new(interface, target, self.proxies.clone(), Arc::downgrade(&rc)).await?;
+        let local = gate.local;
+        writable.insert(target, gate);
+        Ok((local, rc))
+    }
+    pub(super) async fn gc_tor_gates(&self) {
+        self.tor_gates
+            .lock()
+            .await
+            .retain(|_, gate| gate.rc.strong_count() > 0);
+    }
+    /// The TCP ports being served, which `net firewall` opens
+    pub(super) async fn ports(&self) -> BTreeSet<u16> {
+        self.servers.lock().await.keys().copied().collect()
+    }
+    /// The ports only serving interfaces closed to the clearnet, which `net firewall` only
+    /// opens to private addresses
+    pub(super) async fn private_ports(&self) -> BTreeSet<u16> {
+        let proxies = self.proxies.read().await;
+        let mut res = BTreeSet::new();
+        for (port, server) in self.servers.lock().await.iter() {
+            let mapping = match Weak::upgrade(&server.mapping) {
+                Some(mapping) => mapping,
+                None => continue,
+            };
+            let mapping = mapping.read().await;
+            let mut targets = mapping
+                .values()
+                .flatten()
+                .filter(|(_, rc)| rc.strong_count() > 0)
+                .map(|(target, _)| target)
+                .peekable();
+            if targets.peek().is_some()
+                && targets.all(|target| {
+                    target.key.interface().map_or(false, |interface| {
+                        proxies
+                            .get(&interface)
+                            .map_or(false, |p| !p.exposure.clearnet)
+                    })
+                })
+            {
+                res.insert(*port);
+            }
+        }
+        res
+    }
+}
+
+async fn proxy<C, T>(
+    client: &mut C,
+    target: &mut T,
+    peer: IpAddr,
+    proxying: Option<&InterfaceProxy>,
+    hsts: Option<&str>,
+    slot: Option<&ConnectionSlot>,
+    log: Option<&AccessLog>,
+    traffic: Option<Arc<Traffic>>,
+) -> std::io::Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let client = &mut CountingStream::new(client, traffic);
+    match proxying {
+        Some(proxying) => {
+            let default = ProxyDirectives::default();
+            relay(
+                client,
+                target,
+                peer,
+                proxying.auth.as_ref(),
+                proxying.directives.as_ref().unwrap_or(&default),
+                hsts,
+                slot,
+                log,
+            )
+            .await
+        }
+        None => {
+            tokio::io::copy_bidirectional(client, target).await?;
+            Ok(())
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -86,14 +340,120 @@ struct TargetInfo {
 pub enum AlpnInfo {
     Reflect,
     Specified(Vec<Vec<u8>>),
+    /// TLS is not terminated: connections are routed by SNI and passed on as they are, for
+    /// targets that hold their own certificates
+    Passthrough,
+}
+
+type Mapping = BTreeMap<Option<String>, BTreeMap<TargetInfo, Weak<()>>>;
+
+/// How plain HTTP requests for `host` are redirected to HTTPS: permanently if the policy of
+/// its target says so and it has a publicly trusted certificate, temporarily otherwise
+async fn redirect_status(
+    mapping: &RwLock<Mapping>,
+    https: &RwLock<HttpsSettings>,
+    ssl: &SslManager,
+    host: Option<&str>,
+) -> http::StatusCode {
+    let name = match host.filter(|host| enforceable(host)) {
+        Some(host) => host.split(':').next().unwrap_or(host).to_owned(),
+        None => return http::StatusCode::TEMPORARY_REDIRECT,
+    };
+    let interface = match mapping
+        .read()
+        .await
+        .get(&Some(name.clone()))
+        .into_iter()
+        .flatten()
+        .find(|(_, rc)| rc.strong_count() > 0)
+    {
+        Some((target, _)) => target.key.interface(),
+        None => return http::StatusCode::TEMPORARY_REDIRECT,
+    };
+    if https.read().await.policy(interface.as_ref()).redirect
+        && ssl.acme.cert(&name).await.is_some()
+    {
+        http::StatusCode::PERMANENT_REDIRECT
+    } else {
+        http::StatusCode::TEMPORARY_REDIRECT
+    }
+}
+
+/// Passes the connections of onion services on to their target, unless they are over the
+/// `max-connections` of its interface. Every connection over Tor comes from Tor itself, so the
+/// limits of each client do not apply: the onion service holds circuits to theirs.
+struct TorGate {
+    rc: Weak<()>,
+    local: SocketAddr,
+    _thread: NonDetachingJoinHandle<()>,
+}
+impl TorGate {
+    async fn new(
+        interface: (PackageId, InterfaceId),
+        target: SocketAddr,
+        proxies: Arc<RwLock<ProxyMap>>,
+        rc: Weak<()>,
+    ) -> Result<Self, Error> {
+        let listener = TcpListener::bind((TOR_LOOPBACK, 0))
+            .await
+            .with_kind(crate::ErrorKind::Network)?;
+        let local = listener.local_addr()?;
+        Ok(Self {
+            rc,
+            local,
+            _thread: tokio::spawn(async move {
+                loop {
+                    let mut stream = match listener.accept().await {
+                        Ok((stream, _)) => stream,
+                        Err(e) => {
+                            tracing::error!("Error accepting Tor connection to {target}: {e}");
+                            tracing::debug!("{e:?}");
+                            continue;
+                        }
+                    };
+                    // held until the connection is closed
+                    let slot = match proxies
+                        .read()
+                        .await
+                        .get(&interface)
+                        .and_then(|p| p.limiter.as_ref())
+                    {
+                        Some(limiter) => match limiter.connect_anonymous() {
+                            Some(slot) => Some(slot),
+                            None => continue,
+                        },
+                        None => None,
+                    };
+                    tokio::spawn(async move {
+                        let _slot = slot;
+                        if let Err(e) = async {
+                            let mut target_stream = TcpStream::connect(target).await?;
+                            tokio::io::copy_bidirectional(&mut stream, &mut target_stream).await
+                        }
+                        .await
+                        {
+                            tracing::debug!("Error proxying Tor connection to {target}: {e:?}");
+                        }
+                    });
+                }
+            })
+            .into(),
+        })
+    }
 }
 
 struct VHostServer {
-    mapping: Weak<RwLock<BTreeMap<Option<String>, BTreeMap<TargetInfo, Weak<()>>>>>,
+    mapping: Weak<RwLock<Mapping>>,
     _thread: NonDetachingJoinHandle<()>,
 }
 impl VHostServer {
-    async fn new(port: u16, ssl: Arc<SslManager>) -> Result<Self, Error> {
+    async fn new(
+        port: u16,
+        ssl: Arc<SslManager>,
+        proxies: Arc<RwLock<ProxyMap>>,
+        https: Arc<RwLock<HttpsSettings>>,
+        bandwidth: Arc<BandwidthMonitor>,
+    ) -> Result<Self, Error> {
         // check if port allowed
         let listener = TcpListener::bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port))
             .await
@@ -104,13 +464,17 @@ impl VHostServer {
             _thread: tokio::spawn(async move {
                 loop {
                     match listener.accept().await {
-                        Ok((stream, _)) => {
+                        Ok((stream, peer)) => {
+                            let via_tor = stream.local_addr().map_or(false, accepted_via_tor);
                             let stream =
                                 Box::pin(TimeoutStream::new(stream, Duration::from_secs(300)));
                             let mut stream = BackTrackingReader::new(stream);
                             stream.start_buffering();
                             let mapping = mapping.clone();
                             let ssl = ssl.clone();
+                            let proxies = proxies.clone();
+                            let https = https.clone();
+                            let bandwidth = bandwidth.clone();
                             tokio::spawn(async move {
                                 if let Err(e) = async {
                                     let mid = match LazyConfigAcceptor::new(
@@ -122,33 +486,52 @@ impl VHostServer {
                                         Ok(a) => a,
                                         Err(_) => {
                                             stream.rewind();
+                                            let (mapping, https, ssl) =
+                                                (mapping.clone(), https.clone(), ssl.clone());
                                             return hyper::server::Server::builder(
                                                 SingleAccept::new(stream),
                                             )
-                                            .serve(make_service_fn(|_| async {
-                                                Ok::<_, Infallible>(service_fn(|req| async move {
-                                                    let host = req
-                                                        .headers()
-                                                        .get(http::header::HOST)
-                                                        .and_then(|host| host.to_str().ok());
-                                                    let uri = Uri::from_parts({
-                                                        let mut parts =
-                                                            req.uri().to_owned().into_parts();
-                                                        parts.authority = host
-                                                            .map(FromStr::from_str)
-                                                            .transpose()?;
-                                                        parts
-                                                    })?;
-                                                    Response::builder()
-                                                        .status(
-                                                            http::StatusCode::TEMPORARY_REDIRECT,
-                                                        )
-                                                        .header(
-                                                            http::header::LOCATION,
-                                                            uri.to_string(),
-                                                        )
-                                                        .body(Body::default())
-                                                }))
+                                            .serve(make_service_fn(move |_| {
+                                                let (mapping, https, ssl) =
+                                                    (mapping.clone(), https.clone(), ssl.clone());
+                                                async move {
+                                                    Ok::<_, Infallible>(service_fn(move |req| {
+                                                        let (mapping, https, ssl) = (
+                                                            mapping.clone(),
+                                                            https.clone(),
+                                                            ssl.clone(),
+                                                        );
+                                                        async move {
+                                                            let host = req
+                                                                .headers()
+                                                                .get(http::header::HOST)
+                                                                .and_then(|host| {
+                                                                    host.to_str().ok()
+                                                                });
+                                                            let status = redirect_status(
+                                                                &mapping, &https, &ssl, host,
+                                                            )
+                                                            .await;
+                                                            let uri = Uri::from_parts({
+                                                                let mut parts = req
+                                                                    .uri()
+                                                                    .to_owned()
+                                                                    .into_parts();
+                                                                parts.authority = host
+                                                                    .map(FromStr::from_str)
+                                                                    .transpose()?;
+                                                                parts
+                                                            })?;
+                                                            Response::builder()
+                                                                .status(status)
+                                                                .header(
+                                                                    http::header::LOCATION,
+                                                                    uri.to_string(),
+                                                                )
+                                                                .body(Body::default())
+                                                        }
+                                                    }))
+                                                }
                                             }))
                                             .await
                                             .with_kind(crate::ErrorKind::Network);
@@ -156,6 +539,40 @@ impl VHostServer {
                                     };
                                     let target_name =
                                         mid.client_hello().server_name().map(|s| s.to_owned());
+                                    let acme_cert = match &target_name {
+                                        Some(name) => ssl.acme.cert(name).await,
+                                        None => None,
+                                    };
+                                    // browsers must still reach the `.local` and onion
+                                    // addresses over plain HTTP
+                                    let enforced = acme_cert.is_some()
+                                        && target_name.as_deref().map_or(false, enforceable);
+                                    if mid
+                                        .client_hello()
+                                        .alpn()
+                                        .into_iter()
+                                        .flatten()
+                                        .any(|proto| proto == ACME_TLS_ALPN)
+                                    {
+                                        let challenge = match &target_name {
+                                            Some(name) => ssl.acme.challenge_cert(name).await,
+                                            None => None,
+                                        };
+                                        if let Some(challenge) = challenge {
+                                            // the validation only needs the handshake
+                                            let mut cfg = ServerConfig::builder()
+                                                .with_safe_defaults()
+                                                .with_no_client_auth()
+                                                .with_single_cert(
+                                                    challenge.rustls_chain()?,
+                                                    challenge.rustls_key()?,
+                                                )
+                                                .with_kind(crate::ErrorKind::OpenSsl)?;
+                                            cfg.alpn_protocols = vec![ACME_TLS_ALPN.to_vec()];
+                                            mid.into_stream(Arc::new(cfg)).await?;
+                                            return Ok(());
+                                        }
+                                    }
                                     let target = {
                                         let mapping = mapping.read().await;
                                         mapping
@@ -165,6 +582,7 @@ impl VHostServer {
                                             .find(|(_, rc)| rc.strong_count() > 0)
                                             .or_else(|| {
                                                 if target_name
+                                                    .as_deref()
                                                     .map(|s| s.parse::<IpAddr>().is_ok())
                                                     .unwrap_or(true)
                                                 {
@@ -180,50 +598,152 @@ impl VHostServer {
                                             .map(|(target, _)| target.clone())
                                     };
                                     if let Some(target) = target {
+                                        let (proxying, traffic) = match target.key.interface() {
+                                            Some(interface) => (
+                                                proxies.read().await.get(&interface).cloned(),
+                                                Some(bandwidth.traffic(interface)),
+                                            ),
+                                            None => (None, None),
+                                        };
+                                        if proxying.as_ref().map_or(false, |p| !p.exposure.clearnet)
+                                            && !is_private(&peer.ip())
+                                        {
+                                            return Ok(());
+                                        }
+                                        // held until the connection is closed
+                                        let slot = match proxying
+                                            .as_ref()
+                                            .and_then(|p| p.limiter.as_ref())
+                                        {
+                                            Some(limiter) => match limiter.connect(peer.ip()) {
+                                                Some(slot) => Some(slot),
+                                                None => return Ok(()),
+                                            },
+                                            None => None,
+                                        };
+                                        let log = proxying
+                                            .as_ref()
+                                            .and_then(|p| p.access_log.clone())
+                                            .zip(target.key.interface())
+                                            .map(|(logger, interface)| {
+                                                AccessLog::new(logger, interface, peer.ip())
+                                            });
+                                        let hsts = if enforced {
+                                            https
+                                                .read()
+                                                .await
+                                                .policy(target.key.interface().as_ref())
+                                                .hsts
+                                                .as_ref()
+                                                .map(|hsts| hsts.header())
+                                        } else {
+                                            None
+                                        };
+                                        let proxying = proxying.filter(|p| p.relays());
+                                        // the responses are read one at a time to set the header,
+                                        // over HTTP/1.1 as long as the target speaks it
+                                        let hsts_only = proxying.is_none() && hsts.is_some();
+                                        let mut proxying = proxying.or_else(|| {
+                                            hsts.as_ref().map(|_| InterfaceProxy::default())
+                                        });
+                                        let idle_timeout = proxying
+                                            .as_ref()
+                                            .and_then(|p| p.directives.as_ref())
+                                            .and_then(|d| d.idle_timeout());
+                                        if target.connect_ssl == Err(AlpnInfo::Passthrough) {
+                                            if proxying.as_ref().map_or(false, |p| {
+                                                p.auth.is_some() || p.directives.is_some()
+                                            }) {
+                                                tracing::warn!(
+                                                    "Passing a connection to {} through without its proxy auth and directives",
+                                                    target.addr
+                                                );
+                                            }
+                                            // the handshake is replayed to the target, which
+                                            // holds the certificate
+                                            if let Some(log) = &log {
+                                                log.connection(target_name.as_deref());
+                                            }
+                                            drop(mid);
+                                            stream.rewind();
+                                            if let Some(timeout) = idle_timeout {
+                                                stream.get_mut().as_mut().set_timeout(timeout);
+                                            }
+                                            let mut tcp_stream =
+                                                TcpStream::connect(target.addr).await?;
+                                            let _peer_guard = register_proxied_peer(
+                                                tcp_stream.local_addr()?,
+                                                peer,
+                                                via_tor,
+                                            );
+                                            return tokio::io::copy_bidirectional(
+                                                &mut CountingStream::new(&mut stream, traffic),
+                                                &mut tcp_stream,
+                                            )
+                                            .await
+                                            .map_or_else(
+                                                |e| match e.kind() {
+                                                    std::io::ErrorKind::UnexpectedEof => Ok(()),
+                                                    _ => Err(e.into()),
+                                                },
+                                                |_| Ok(()),
+                                            );
+                                        }
+                                        // the requests are read one at a time to be proxied
+                                        let http1 = vec![b"http/1.1".to_vec()];
                                         let mut tcp_stream =
                                             TcpStream::connect(target.addr).await?;
+                                        let _peer_guard = register_proxied_peer(
+                                            tcp_stream.local_addr()?,
+                                            peer,
+                                            via_tor,
+                                        );
                                         let key =
                                             ssl.with_certs(target.key, target.addr.ip()).await?;
                                         let cfg = ServerConfig::builder()
                                             .with_safe_defaults()
                                             .with_no_client_auth();
-                                        let mut cfg =
-                                            if mid.client_hello().signature_schemes().contains(
-                                                &tokio_rustls::rustls::SignatureScheme::ED25519,
-                                            ) {
-                                                cfg.with_single_cert(
-                                                    key.fullchain_ed25519()
-                                                        .into_iter()
-                                                        .map(|c| {
-                                                            Ok(tokio_rustls::rustls::Certificate(
-                                                                c.to_der()?,
-                                                            ))
-                                                        })
-                                                        .collect::<Result<_, Error>>()?,
-                                                    tokio_rustls::rustls::PrivateKey(
-                                                        key.key()
-                                                            .openssl_key_ed25519()
-                                                            .private_key_to_der()?,
-                                                    ),
-                                                )
-                                            } else {
-                                                cfg.with_single_cert(
-                                                    key.fullchain_nistp256()
-                                                        .into_iter()
-                                                        .map(|c| {
-                                                            Ok(tokio_rustls::rustls::Certificate(
-                                                                c.to_der()?,
-                                                            ))
-                                                        })
-                                                        .collect::<Result<_, Error>>()?,
-                                                    tokio_rustls::rustls::PrivateKey(
-                                                        key.key()
-                                                            .openssl_key_nistp256()
-                                                            .private_key_to_der()?,
-                                                    ),
-                                                )
-                                            }
-                                            .with_kind(crate::ErrorKind::OpenSsl)?;
+                                        let mut cfg = if let Some(cert) = acme_cert {
+                                            cfg.with_single_cert(
+                                                cert.rustls_chain()?,
+                                                cert.rustls_key()?,
+                                            )
+                                        } else if mid.client_hello().signature_schemes().contains(
+                                            &tokio_rustls::rustls::SignatureScheme::ED25519,
+                                        ) {
+                                            cfg.with_single_cert(
+                                                key.fullchain_ed25519()
+                                                    .into_iter()
+                                                    .map(|c| {
+                                                        Ok(tokio_rustls::rustls::Certificate(
+                                                            c.to_der()?,
+                                                        ))
+                                                    })
+                                                    .collect::<Result<_, Error>>()?,
+                                                tokio_rustls::rustls::PrivateKey(
+                                                    key.key()
+                                                        .openssl_key_ed25519()
+                                                        .private_key_to_der()?,
+                                                ),
+                                            )
+                                        } else {
+                                            cfg.with_single_cert(
+                                                key.fullchain_nistp256()
+                                                    .into_iter()
+                                                    .map(|c| {
+                                                        Ok(tokio_rustls::rustls::Certificate(
+                                                            c.to_der()?,
+                                                        ))
+                                                    })
+                                                    .collect::<Result<_, Error>>()?,
+                                                tokio_rustls::rustls::PrivateKey(
+                                                    key.key()
+                                                        .openssl_key_nistp256()
+                                                        .private_key_to_der()?,
+                                                ),
+                                            )
+                                        }
+                                        .with_kind(crate::ErrorKind::OpenSsl)?;
                                         match target.connect_ssl {
                                             Ok(()) => {
                                                 let mut client_cfg =
@@ -239,13 +759,18 @@ impl VHostServer {
                                                             store
                                                         })
                                                         .with_no_client_auth();
-                                                client_cfg.alpn_protocols = mid
-                                                    .client_hello()
-                                                    .alpn()
-                                                    .into_iter()
-                                                    .flatten()
-                                                    .map(|x| x.to_vec())
-                                                    .collect();
+                                                client_cfg.alpn_protocols = if proxying.is_some()
+                                                    && !hsts_only
+                                                {
+                                                    http1
+                                                } else {
+                                                    mid.client_hello()
+                                                        .alpn()
+                                                        .into_iter()
+                                                        .flatten()
+                                                        .map(|x| x.to_vec())
+                                                        .collect()
+                                                };
                                                 let mut target_stream =
                                                     TlsConnector::from(Arc::new(client_cfg))
                                                         .connect_with(
@@ -267,41 +792,107 @@ impl VHostServer {
                                                         )
                                                         .await
                                                         .with_kind(crate::ErrorKind::OpenSsl)?;
+                                                if hsts_only
+                                                    && target_stream.get_ref().1.alpn_protocol()
+                                                        == Some(&b"h2"[..])
+                                                {
+                                                    // not downgraded just for the header
+                                                    proxying = None;
+                                                }
                                                 let mut tls_stream =
                                                     mid.into_stream(Arc::new(cfg)).await?;
                                                 tls_stream.get_mut().0.stop_buffering();
-                                                tokio::io::copy_bidirectional(
+                                                if let Some(timeout) = idle_timeout {
+                                                    tls_stream
+                                                        .get_mut()
+                                                        .0
+                                                        .get_mut()
+                                                        .as_mut()
+                                                        .set_timeout(timeout);
+                                                }
+                                                proxy(
                                                     &mut tls_stream,
                                                     &mut target_stream,
+                                                    peer.ip(),
+                                                    proxying.as_ref(),
+                                                    hsts.as_deref(),
+                                                    slot.as_ref(),
+                                                    log.as_ref(),
+                                                    traffic,
                                                 )
                                                 .await
                                             }
                                             Err(AlpnInfo::Reflect) => {
-                                                for proto in
-                                                    mid.client_hello().alpn().into_iter().flatten()
-                                                {
-                                                    cfg.alpn_protocols.push(proto.into());
+                                                if proxying.is_some() {
+                                                    cfg.alpn_protocols = http1;
+                                                } else {
+                                                    for proto in mid
+                                                        .client_hello()
+                                                        .alpn()
+                                                        .into_iter()
+                                                        .flatten()
+                                                    {
+                                                        cfg.alpn_protocols.push(proto.into());
+                                                    }
                                                 }
                                                 let mut tls_stream =
                                                     mid.into_stream(Arc::new(cfg)).await?;
                                                 tls_stream.get_mut().0.stop_buffering();
-                                                tokio::io::copy_bidirectional(
+                                                if let Some(timeout) = idle_timeout {
+                                                    tls_stream
+                                                        .get_mut()
+                                                        .0
+                                                        .get_mut()
+                                                        .as_mut()
+                                                        .set_timeout(timeout);
+                                                }
+                                                proxy(
                                                     &mut tls_stream,
                                                     &mut tcp_stream,
+                                                    peer.ip(),
+                                                    proxying.as_ref(),
+                                                    hsts.as_deref(),
+                                                    slot.as_ref(),
+                                                    log.as_ref(),
+                                                    traffic,
                                                 )
                                                 .await
                                             }
                                             Err(AlpnInfo::Specified(alpn)) => {
-                                                cfg.alpn_protocols = alpn;
+                                                if hsts_only
+                                                    && !alpn.is_empty()
+                                                    && !alpn.contains(&http1[0])
+                                                {
+                                                    // not downgraded just for the header
+                                                    proxying = None;
+                                                }
+                                                cfg.alpn_protocols =
+                                                    if proxying.is_some() { http1 } else { alpn };
                                                 let mut tls_stream =
                                                     mid.into_stream(Arc::new(cfg)).await?;
                                                 tls_stream.get_mut().0.stop_buffering();
-                                                tokio::io::copy_bidirectional(
+                                                if let Some(timeout) = idle_timeout {
+                                                    tls_stream
+                                                        .get_mut()
+                                                        .0
+                                                        .get_mut()
+                                                        .as_mut()
+                                                        .set_timeout(timeout);
+                                                }
+                                                proxy(
                                                     &mut tls_stream,
                                                     &mut tcp_stream,
+                                                    peer.ip(),
+                                                    proxying.as_ref(),
+                                                    hsts.as_deref(),
+                                                    slot.as_ref(),
+                                                    log.as_ref(),
+                                                    traffic,
                                                 )
                                                 .await
                                             }
+                                            // passed through above
+                                            Err(AlpnInfo::Passthrough) => Ok(()),
                                         }
                                         .map_or_else(
                                             |e| match e.kind() {
@@ -4,6 +4,7 @@ use std::net::SocketAddr;
 use futures::future::ready;
 use futures::FutureExt;
 use helpers::NonDetachingJoinHandle;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
 use tokio::sync::oneshot;
@@ -12,6 +13,7 @@ use crate::context::{DiagnosticContext, InstallContext, RpcContext, SetupContext
 use crate::net::static_server::{
     diag_ui_file_router, install_ui_file_router, main_ui_server_router, setup_ui_file_router,
 };
+use crate::net::utils::{accepted_via_tor, resolve_peer, ClientAddr, Tls, ViaTor};
 use crate::net::HttpHandler;
 use crate::Error;
 
@@ -26,9 +28,26 @@ impl WebServer {
             let server = Server::bind(&bind)
                 .http1_preserve_header_case(true)
                 .http1_title_case_headers(true)
-                .serve(make_service_fn(move |_| {
+                .serve(make_service_fn(move |conn: &AddrStream| {
                     let router = router.clone();
-                    ready(Ok::<_, Infallible>(service_fn(move |req| router(req))))
+                    let remote = conn.remote_addr();
+                    let tor = accepted_via_tor(conn.local_addr());
+                    ready(Ok::<_, Infallible>(service_fn(move |mut req| {
+                        // resolved per request, since the vhost controller only registers the
+                        // connection once it has been accepted here
+                        let (peer, tor) = match resolve_peer(remote) {
+                            Some((peer, proxied_tor)) => {
+                                req.extensions_mut().insert(Tls);
+                                (peer, proxied_tor)
+                            }
+                            None => (remote, tor),
+                        };
+                        if tor {
+                            req.extensions_mut().insert(ViaTor);
+                        }
+                        req.extensions_mut().insert(ClientAddr(peer));
+                        router(req)
+                    })))
                 }))
                 .with_graceful_shutdown(shutdown_recv.map(|_| ()));
             if let Err(e) = server.await {
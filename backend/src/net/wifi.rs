@@ -14,6 +14,7 @@ use tokio::sync::RwLock;
 use tracing::instrument;
 
 use crate::context::RpcContext;
+use crate::net::access_point::{AP_CONNECTION, SETUP_AP_CONNECTION};
 use crate::util::serde::{display_serializable, IoFormat};
 use crate::util::{display_none, Invoke};
 use crate::{Error, ErrorKind};
@@ -31,7 +32,15 @@ pub fn wifi_manager(ctx: &RpcContext) -> Result<&WifiManager, Error> {
     }
 }
 
-#[command(subcommands(add, connect, delete, get, country, available))]
+#[command(subcommands(
+    add,
+    connect,
+    delete,
+    get,
+    country,
+    available,
+    super::access_point::access_point
+))]
 pub async fn wifi() -> Result<(), Error> {
     Ok(())
 }
@@ -292,7 +301,7 @@ fn display_wifi_list(info: Vec<WifiListOut>, matches: &ArgMatches) {
     table_global.print_tty(false).unwrap();
 }
 
-#[command(display(display_wifi_info))]
+#[command(display(display_wifi_info), metadata(read_only = true))]
 #[instrument(skip_all)]
 pub async fn get(
     #[context] ctx: RpcContext,
@@ -346,7 +355,7 @@ pub async fn get(
     })
 }
 
-#[command(rename = "get", display(display_wifi_list))]
+#[command(rename = "get", display(display_wifi_list), metadata(read_only = true))]
 #[instrument(skip_all)]
 pub async fn get_available(
     #[context] ctx: RpcContext,
@@ -584,7 +593,9 @@ impl WpaCli {
                 let uuid = NetworkId(cs.next()?.to_owned());
                 let connection_type = cs.next()?;
                 let device = cs.next();
-                if !connection_type.contains("wireless") {
+                if !connection_type.contains("wireless")
+                    || [AP_CONNECTION, SETUP_AP_CONNECTION].contains(&name.0.as_str())
+                {
                     return None;
                 }
                 let info = WifiInfo {
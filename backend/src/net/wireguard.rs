@@ -0,0 +1,514 @@
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use helpers::NonDetachingJoinHandle;
+use openssl::pkey::{Id, PKey, Private};
+use patch_db::DbHandle;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::net::interface::RawProtocol;
+use crate::net::net_controller::NetController;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::{display_none, Invoke};
+use crate::{Error, ErrorKind, ResultExt};
+
+pub const WG_INTERFACE: &str = "wg-start9";
+/// The address of this server on the VPN, peers get the rest of its /24
+pub const WG_IP: Ipv4Addr = Ipv4Addr::new(10, 232, 0, 1);
+const WG_CONFIG_PATH: &str = "/run/embassy/wireguard.conf";
+const DEFAULT_PORT: u16 = 51820;
+const KEEPALIVE_SECS: u16 = 25;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WireguardPeer {
+    pub address: Ipv4Addr,
+    pub public_key: String,
+    pub added_at: DateTime<Utc>,
+}
+
+/// See `net wireguard`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[serde(default)]
+pub struct WireguardSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// The host devices reach this server at, e.g. its public IP or a clearnet domain
+    pub endpoint: Option<String>,
+    pub public_key: Option<String>,
+    pub peers: BTreeMap<String, WireguardPeer>,
+}
+impl Default for WireguardSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_PORT,
+            endpoint: None,
+            public_key: None,
+            peers: BTreeMap::new(),
+        }
+    }
+}
+
+/// Keeps the resolver answering `.local` names to peers alive while the VPN is up
+#[derive(Default)]
+pub struct WireguardController {
+    dns: Mutex<Option<NonDetachingJoinHandle<Result<(), Error>>>>,
+}
+
+fn generate_key() -> Result<PKey<Private>, Error> {
+    Ok(PKey::generate_x25519()?)
+}
+
+fn private_key(key: &PKey<Private>) -> Result<String, Error> {
+    Ok(base64::encode(key.raw_private_key()?))
+}
+
+fn public_key(key: &PKey<Private>) -> Result<String, Error> {
+    Ok(base64::encode(key.raw_public_key()?))
+}
+
+fn parse_key(key: &str) -> Result<PKey<Private>, Error> {
+    Ok(PKey::private_key_from_raw_bytes(
+        &base64::decode(key).with_kind(ErrorKind::Deserialization)?,
+        Id::X25519,
+    )?)
+}
+
+async fn server_key(secrets: &PgPool) -> Result<PKey<Private>, Error> {
+    if let Some(r) = sqlx::query!("SELECT key FROM wireguard_server WHERE id = 0")
+        .fetch_optional(secrets)
+        .await?
+    {
+        return parse_key(&r.key);
+    }
+    let key = generate_key()?;
+    sqlx::query!(
+        "INSERT INTO wireguard_server (id, key) VALUES (0, $1) ON CONFLICT (id) DO NOTHING",
+        private_key(&key)?
+    )
+    .execute(secrets)
+    .await?;
+    Ok(key)
+}
+
+fn server_config(key: &PKey<Private>, settings: &WireguardSettings) -> Result<String, Error> {
+    let mut conf = format!(
+        "[Interface]\nPrivateKey = {}\nListenPort = {}\n",
+        private_key(key)?,
+        settings.port
+    );
+    for peer in settings.peers.values() {
+        conf += &format!(
+            "\n[Peer]\nPublicKey = {}\nAllowedIPs = {}/32\n",
+            peer.public_key, peer.address
+        );
+    }
+    Ok(conf)
+}
+
+/// What a device imports to join the VPN as `peer`, as text or a QR code
+fn peer_config(
+    key: &PKey<Private>,
+    peer: &WireguardPeer,
+    settings: &WireguardSettings,
+) -> Result<String, Error> {
+    let endpoint = settings.endpoint.as_ref().ok_or_else(|| {
+        Error::new(
+            eyre!("Set the host devices reach this server at with `net wireguard enable --endpoint` first"),
+            ErrorKind::InvalidRequest,
+        )
+    })?;
+    Ok(format!(
+        "[Interface]\nPrivateKey = {}\nAddress = {}/32\nDNS = {}\n\n[Peer]\nPublicKey = {}\nEndpoint = {}:{}\nAllowedIPs = {}/32\nPersistentKeepalive = {}\n",
+        private_key(key)?,
+        peer.address,
+        WG_IP,
+        settings.public_key.as_deref().unwrap_or_default(),
+        endpoint,
+        settings.port,
+        WG_IP,
+        KEEPALIVE_SECS,
+    ))
+}
+
+/// The first address of the VPN no peer has
+fn next_address(settings: &WireguardSettings) -> Result<Ipv4Addr, Error> {
+    let [a, b, c, _] = WG_IP.octets();
+    (2..=254)
+        .map(|d| Ipv4Addr::new(a, b, c, d))
+        .find(|ip| !settings.peers.values().any(|p| &p.address == ip))
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("Every address of the VPN is taken, remove a peer first"),
+                ErrorKind::InvalidRequest,
+            )
+        })
+}
+
+async fn interface_exists() -> bool {
+    Command::new("ip")
+        .arg("link")
+        .arg("show")
+        .arg(WG_INTERFACE)
+        .invoke(ErrorKind::Wireguard)
+        .await
+        .is_ok()
+}
+
+/// Brings the VPN up or down to match `settings`
+#[instrument(skip_all)]
+async fn apply(
+    net: &NetController,
+    secrets: &PgPool,
+    settings: &WireguardSettings,
+) -> Result<(), Error> {
+    let mut dns = net.wireguard.dns.lock().await;
+    if !settings.enabled {
+        dns.take();
+        if interface_exists().await {
+            Command::new("ip")
+                .arg("link")
+                .arg("del")
+                .arg(WG_INTERFACE)
+                .invoke(ErrorKind::Wireguard)
+                .await?;
+        }
+        net.firewall.set_service("wireguard", None).await;
+        return net.sync_firewall().await;
+    }
+
+    if !interface_exists().await {
+        Command::new("ip")
+            .arg("link")
+            .arg("add")
+            .arg("dev")
+            .arg(WG_INTERFACE)
+            .arg("type")
+            .arg("wireguard")
+            .invoke(ErrorKind::Wireguard)
+            .await?;
+    }
+    Command::new("ip")
+        .arg("address")
+        .arg("replace")
+        .arg(format!("{}/24", WG_IP))
+        .arg("dev")
+        .arg(WG_INTERFACE)
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    let conf = server_config(&server_key(secrets).await?, settings)?;
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(WG_CONFIG_PATH)
+        .await?;
+    file.write_all(conf.as_bytes()).await?;
+    file.sync_all().await?;
+    drop(file);
+    let res = Command::new("wg")
+        .arg("syncconf")
+        .arg(WG_INTERFACE)
+        .arg(WG_CONFIG_PATH)
+        .invoke(ErrorKind::Wireguard)
+        .await;
+    tokio::fs::remove_file(WG_CONFIG_PATH).await?;
+    res?;
+    Command::new("ip")
+        .arg("link")
+        .arg("set")
+        .arg(WG_INTERFACE)
+        .arg("up")
+        .invoke(ErrorKind::Wireguard)
+        .await?;
+    if dns.is_none() {
+        *dns = Some(net.dns.serve_lan((WG_IP, 53).into(), WG_IP).await?);
+    }
+    net.firewall
+        .set_service("wireguard", Some((RawProtocol::Udp, settings.port)))
+        .await;
+    net.sync_firewall().await
+}
+
+/// Brings the VPN up after a restart if it is enabled
+pub async fn load<Db: DbHandle>(
+    net: &NetController,
+    secrets: &PgPool,
+    db: &mut Db,
+) -> Result<(), Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .wireguard()
+        .get(db)
+        .await?
+        .into_owned();
+    apply(net, secrets, &settings).await
+}
+
+async fn update<F: FnOnce(&mut WireguardSettings) -> Result<T, Error>, T>(
+    ctx: &RpcContext,
+    f: F,
+) -> Result<T, Error> {
+    let mut db = ctx.db.handle();
+    let mut settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .wireguard()
+        .get_mut(&mut db)
+        .await?;
+    let res = f(&mut settings)?;
+    apply(&ctx.net_controller, &ctx.secret_store, &settings).await?;
+    settings.save(&mut db).await?;
+    Ok(res)
+}
+
+#[command(subcommands(enable, disable, peer))]
+pub fn wireguard() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Runs a WireGuard VPN on `--port`, 51820 unless given, whose peers reach the LAN addresses of
+/// this server and its services, at `--endpoint`. Forward the port to this server to use it from
+/// outside the LAN.
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn enable(
+    #[context] ctx: RpcContext,
+    #[arg(long = "port")] port: Option<u16>,
+    #[arg(long = "endpoint")] endpoint: Option<String>,
+) -> Result<(), Error> {
+    let public_key = public_key(&server_key(&ctx.secret_store).await?)?;
+    update(&ctx, |settings| {
+        settings.enabled = true;
+        settings.public_key = Some(public_key);
+        if let Some(port) = port {
+            settings.port = port;
+        }
+        if endpoint.is_some() {
+            settings.endpoint = endpoint;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Takes the VPN down, keeping its peers for when it is enabled again
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn disable(#[context] ctx: RpcContext) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        settings.enabled = false;
+        Ok(())
+    })
+    .await
+}
+
+#[command(subcommands(add, remove, list, config))]
+pub fn peer() -> Result<(), Error> {
+    Ok(())
+}
+
+fn display_config(arg: String, matches: &ArgMatches) {
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+    if matches.is_present("qr") {
+        // inverted, as terminals are usually light on dark
+        let code = qrcode::QrCode::new(arg.as_bytes()).unwrap();
+        println!(
+            "{}",
+            code.render::<qrcode::render::unicode::Dense1x2>()
+                .dark_color(qrcode::render::unicode::Dense1x2::Light)
+                .light_color(qrcode::render::unicode::Dense1x2::Dark)
+                .build()
+        );
+        return;
+    }
+    print!("{}", arg);
+}
+
+/// Enrolls a device as `name`, returning the config to import on it, as a QR code with `--qr`
+#[command(display(display_config), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] name: String,
+    #[allow(unused_variables)]
+    #[arg(long = "qr", default)]
+    qr: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<String, Error> {
+    let key = generate_key()?;
+    let public_key = public_key(&key)?;
+    if crate::db::DatabaseModel::new()
+        .server_info()
+        .wireguard()
+        .get(&mut ctx.db.handle())
+        .await?
+        .peers
+        .contains_key(&name)
+    {
+        return Err(Error::new(
+            eyre!("{} is already a peer", name),
+            ErrorKind::Duplicate,
+        ));
+    }
+    // the key is stored first, so that no peer is ever saved whose config cannot be exported
+    sqlx::query!(
+        "INSERT INTO wireguard_peers (name, key) VALUES ($1, $2)",
+        name,
+        private_key(&key)?
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    let added = update(&ctx, |settings| {
+        if settings.peers.contains_key(&name) {
+            return Err(Error::new(
+                eyre!("{} is already a peer", name),
+                ErrorKind::Duplicate,
+            ));
+        }
+        let peer = WireguardPeer {
+            address: next_address(settings)?,
+            public_key,
+            added_at: Utc::now(),
+        };
+        settings.peers.insert(name.clone(), peer.clone());
+        Ok((peer, settings.clone()))
+    })
+    .await;
+    let (peer, settings) = match added {
+        Ok(a) => a,
+        Err(e) => {
+            sqlx::query!("DELETE FROM wireguard_peers WHERE name = $1", name)
+                .execute(&ctx.secret_store)
+                .await?;
+            return Err(e);
+        }
+    };
+    peer_config(&key, &peer, &settings)
+}
+
+#[command(display(display_none), metadata(sync_db = true, admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] name: String) -> Result<(), Error> {
+    update(&ctx, |settings| {
+        settings
+            .peers
+            .remove(&name)
+            .map(|_| ())
+            .ok_or_else(|| Error::new(eyre!("{} is not a peer", name), ErrorKind::NotFound))
+    })
+    .await?;
+    sqlx::query!("DELETE FROM wireguard_peers WHERE name = $1", name)
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+fn display_peers(arg: BTreeMap<String, WireguardPeer>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "NAME", "ADDRESS", "PUBLIC KEY", "ADDED"]);
+    for (name, peer) in &arg {
+        table.add_row(row![
+            name,
+            peer.address.to_string(),
+            &peer.public_key,
+            peer.added_at.to_rfc3339(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_peers), metadata(read_only = true))]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<BTreeMap<String, WireguardPeer>, Error> {
+    Ok(crate::db::DatabaseModel::new()
+        .server_info()
+        .wireguard()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned()
+        .peers)
+}
+
+/// The config to import on the device enrolled as `name` again, as a QR code with `--qr`
+#[command(display(display_config), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn config(
+    #[context] ctx: RpcContext,
+    #[arg] name: String,
+    #[allow(unused_variables)]
+    #[arg(long = "qr", default)]
+    qr: bool,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<String, Error> {
+    let settings = crate::db::DatabaseModel::new()
+        .server_info()
+        .wireguard()
+        .get(&mut ctx.db.handle())
+        .await?
+        .into_owned();
+    let peer = settings
+        .peers
+        .get(&name)
+        .ok_or_else(|| Error::new(eyre!("{} is not a peer", name), ErrorKind::NotFound))?;
+    let key = sqlx::query!("SELECT key FROM wireguard_peers WHERE name = $1", name)
+        .fetch_one(&ctx.secret_store)
+        .await?
+        .key;
+    peer_config(&parse_key(&key)?, peer, &settings)
+}
+
+#[test]
+fn keys() {
+    let key = generate_key().unwrap();
+    let parsed = parse_key(&private_key(&key).unwrap()).unwrap();
+    assert_eq!(public_key(&parsed).unwrap(), public_key(&key).unwrap());
+    assert_eq!(public_key(&key).unwrap().len(), 44);
+
+    let mut settings = WireguardSettings::default();
+    assert_eq!(
+        next_address(&settings).unwrap(),
+        Ipv4Addr::new(10, 232, 0, 2)
+    );
+    settings.peers.insert(
+        "phone".into(),
+        WireguardPeer {
+            address: Ipv4Addr::new(10, 232, 0, 2),
+            public_key: public_key(&key).unwrap(),
+            added_at: Utc::now(),
+        },
+    );
+    assert_eq!(
+        next_address(&settings).unwrap(),
+        Ipv4Addr::new(10, 232, 0, 3)
+    );
+    assert!(peer_config(&key, &settings.peers["phone"], &settings).is_err());
+}
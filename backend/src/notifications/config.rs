@@ -0,0 +1,127 @@
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, Postgres};
+use tracing::instrument;
+
+use super::quiet::QuietHours;
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::util::time::parse_time;
+use crate::Error;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationConfig {
+    /// Notifications older than this many days are pruned. 0 keeps them forever.
+    pub retention_days: u32,
+    /// Only this many of the newest notifications are kept. 0 keeps all of them.
+    pub max_rows: u32,
+    /// Holds `info` and `success` notifications back from the external channels and sends them
+    /// together once a day
+    pub digest: bool,
+    /// When set, notifications are queued during this window and sent once it is over
+    pub quiet_hours: Option<QuietHours>,
+}
+
+#[command(subcommands(get, set, set_quiet_hours, clear_quiet_hours))]
+pub fn config() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config<Ex>(secrets: &mut Ex) -> Result<NotificationConfig, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let config = sqlx::query!(
+        "SELECT retention_days, max_rows, digest, quiet_start, quiet_end, quiet_errors FROM notification_config WHERE id = 0"
+    )
+    .fetch_one(&mut *secrets)
+    .await?;
+    Ok(NotificationConfig {
+        retention_days: config.retention_days as u32,
+        max_rows: config.max_rows as u32,
+        digest: config.digest,
+        quiet_hours: match (config.quiet_start, config.quiet_end) {
+            (Some(start), Some(end)) => {
+                Some(QuietHours::from_minutes(start, end, config.quiet_errors))
+            }
+            _ => None,
+        },
+    })
+}
+
+#[command(display(display_serializable), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<NotificationConfig, Error> {
+    load_config(&mut ctx.secret_store.acquire().await?).await
+}
+
+/// Updates the settings that are given, leaving the rest as they are
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "retention-days", long = "retention-days")] retention_days: Option<u32>,
+    #[arg(rename = "max-rows", long = "max-rows")] max_rows: Option<u32>,
+    #[arg(long = "digest")] digest: Option<bool>,
+) -> Result<(), Error> {
+    let retention_days = retention_days.map(|d| d as i32);
+    let max_rows = max_rows.map(|r| r as i32);
+    sqlx::query!(
+        "UPDATE notification_config SET retention_days = COALESCE($1, retention_days), max_rows = COALESCE($2, max_rows), digest = COALESCE($3, digest) WHERE id = 0",
+        retention_days,
+        max_rows,
+        digest,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    super::retention::prune(&ctx.secret_store, &mut ctx.db.handle()).await
+}
+
+/// Queues notifications between `start` and `end` (`HH:MM`, UTC) and sends them afterwards
+#[command(
+    rename = "set-quiet-hours",
+    display(display_none),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn set_quiet_hours(
+    #[context] ctx: RpcContext,
+    #[arg] start: String,
+    #[arg] end: String,
+    #[arg(rename = "allow-errors", long = "allow-errors")] allow_errors: bool,
+) -> Result<(), Error> {
+    let start = parse_time(&start)?;
+    let end = parse_time(&end)?;
+    sqlx::query!(
+        "UPDATE notification_config SET quiet_start = $1, quiet_end = $2, quiet_errors = $3 WHERE id = 0",
+        start,
+        end,
+        allow_errors,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Turns quiet hours off. Anything queued is sent at the next maintenance run.
+#[command(
+    rename = "clear-quiet-hours",
+    display(display_none),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn clear_quiet_hours(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE notification_config SET quiet_start = NULL, quiet_end = NULL WHERE id = 0"
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use reqwest::Client;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::{send_to_channels, NotificationLevel, Outgoing};
+use crate::{Error, ErrorKind, ResultExt};
+
+/// Queues the notification for the next digest if the digest is enabled and the notification is
+/// of low enough severity. Returns whether it was queued.
+#[instrument(skip_all)]
+pub async fn hold(secrets: &PgPool, notification: &Outgoing) -> Result<bool, Error> {
+    if notification.level > NotificationLevel::Info {
+        return Ok(false);
+    }
+    if !sqlx::query!("SELECT digest FROM notification_config WHERE id = 0")
+        .fetch_one(secrets)
+        .await?
+        .digest
+    {
+        return Ok(false);
+    }
+    let notification = serde_json::to_string(notification).with_kind(ErrorKind::Serialization)?;
+    sqlx::query!(
+        "INSERT INTO notification_digest (notification) VALUES ($1)",
+        notification
+    )
+    .execute(secrets)
+    .await?;
+    Ok(true)
+}
+
+/// Combines the queued notifications into one, oldest first
+fn summarize(queued: Vec<Outgoing>) -> Outgoing {
+    let level = queued
+        .iter()
+        .map(|n| n.level.clone())
+        .max()
+        .unwrap_or(NotificationLevel::Info);
+    let message = queued
+        .iter()
+        .map(|n| match &n.package_id {
+            Some(package_id) => {
+                format!("[{}] {} ({}): {}", n.level, n.title, package_id, n.message)
+            }
+            None => format!("[{}] {}: {}", n.level, n.title, n.message),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut digest = Outgoing::new(
+        None,
+        level,
+        format!("Daily Digest: {} Notifications", queued.len()),
+        message,
+    );
+    digest.data = serde_json::to_value(&queued).unwrap_or_default();
+    digest
+}
+
+/// Sends the queued notifications as a single digest once a day has passed since the last one,
+/// waiting for quiet hours to end. Anything still queued after the digest is turned off is sent
+/// straight away.
+#[instrument(skip_all)]
+pub async fn send_if_due(secrets: &PgPool, client: &Client) -> Result<(), Error> {
+    if super::quiet::is_quiet(secrets).await? {
+        return Ok(());
+    }
+    let config = sqlx::query!(
+        "SELECT digest, last_digest_at < CURRENT_TIMESTAMP - interval '1 day' AS \"due!\" FROM notification_config WHERE id = 0"
+    )
+    .fetch_one(secrets)
+    .await?;
+    if config.digest && !config.due {
+        return Ok(());
+    }
+    let mut last_id = None;
+    let mut queued = Vec::new();
+    for row in sqlx::query!("SELECT id, notification FROM notification_digest ORDER BY id")
+        .fetch_all(secrets)
+        .await?
+    {
+        last_id = Some(row.id);
+        match serde_json::from_str(&row.notification) {
+            Ok(a) => queued.push(a),
+            Err(e) => tracing::warn!("Dropping unreadable digest entry {}: {}", row.id, e),
+        }
+    }
+    sqlx::query!("UPDATE notification_config SET last_digest_at = CURRENT_TIMESTAMP WHERE id = 0")
+        .execute(secrets)
+        .await?;
+    let last_id = if let Some(last_id) = last_id {
+        last_id
+    } else {
+        return Ok(());
+    };
+    sqlx::query!("DELETE FROM notification_digest WHERE id <= $1", last_id)
+        .execute(secrets)
+        .await?;
+    if !queued.is_empty() {
+        send_to_channels(secrets, client, &summarize(queued)).await;
+    }
+    Ok(())
+}
+
+#[test]
+fn summary() {
+    let digest = summarize(vec![
+        Outgoing::new(
+            None,
+            NotificationLevel::Success,
+            "Backup Complete".to_owned(),
+            "All services backed up".to_owned(),
+        ),
+        Outgoing::new(
+            Some("bitcoind".parse().unwrap()),
+            NotificationLevel::Info,
+            "Synced".to_owned(),
+            "Caught up to tip".to_owned(),
+        ),
+    ]);
+    assert_eq!(digest.level, NotificationLevel::Info);
+    assert_eq!(digest.title, "Daily Digest: 2 Notifications");
+    assert_eq!(
+        digest.message,
+        "[success] Backup Complete: All services backed up\n[info] Synced (bitcoind): Caught up to tip"
+    );
+}
@@ -0,0 +1,366 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres};
+use tracing::instrument;
+
+use super::{NotificationLevel, Outgoing};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_SUBJECT_TEMPLATE: &str = "[{level}] {title}";
+const DEFAULT_BODY_TEMPLATE: &str = "{message}\n\nPackage: {package}\nTime: {time}";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmtpSecurity {
+    /// Implicit TLS, usually on port 465
+    Tls,
+    /// Plaintext upgraded with STARTTLS, usually on port 587
+    Starttls,
+    /// No encryption. Only sensible for a relay on a trusted network.
+    None,
+}
+impl SmtpSecurity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SmtpSecurity::Tls => "tls",
+            SmtpSecurity::Starttls => "starttls",
+            SmtpSecurity::None => "none",
+        }
+    }
+    fn default_port(&self) -> u16 {
+        match self {
+            SmtpSecurity::Tls => 465,
+            SmtpSecurity::Starttls => 587,
+            SmtpSecurity::None => 25,
+        }
+    }
+}
+impl fmt::Display for SmtpSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+impl FromStr for SmtpSecurity {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tls" => Ok(SmtpSecurity::Tls),
+            "starttls" => Ok(SmtpSecurity::Starttls),
+            "none" => Ok(SmtpSecurity::None),
+            s => Err(Error::new(
+                eyre!("Invalid SMTP Security: {}", s),
+                ErrorKind::ParseDbField,
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EmailRoute {
+    pub level: NotificationLevel,
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    pub from: String,
+    /// Templates may use `{title}`, `{message}`, `{level}`, `{package}` and `{time}`
+    pub subject_template: String,
+    pub body_template: String,
+    /// Notifications are emailed to every address routed for their level
+    pub routes: Vec<EmailRoute>,
+}
+
+#[command(subcommands(get, set, remove, add_route, remove_route, test))]
+pub fn email() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config<Ex>(secrets: &mut Ex) -> Result<Option<SmtpConfig>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    let config = if let Some(config) = sqlx::query!(
+        "SELECT host, port, security, username, password, from_address, subject_template, body_template FROM smtp_config WHERE id = 0"
+    )
+    .fetch_optional(&mut *secrets)
+    .await?
+    {
+        config
+    } else {
+        return Ok(None);
+    };
+    let mut routes = Vec::new();
+    for route in sqlx::query!("SELECT level, address FROM email_route ORDER BY level, address")
+        .fetch_all(&mut *secrets)
+        .await?
+    {
+        routes.push(EmailRoute {
+            level: route.level.parse()?,
+            address: route.address,
+        });
+    }
+    Ok(Some(SmtpConfig {
+        host: config.host,
+        port: config.port as u16,
+        security: config.security.parse()?,
+        username: config.username,
+        password: config.password,
+        from: config.from_address,
+        subject_template: config.subject_template,
+        body_template: config.body_template,
+        routes,
+    }))
+}
+
+/// Fills in the `{placeholder}`s of a template. Values are inserted in a single pass, so a title
+/// or message containing a placeholder is left as written.
+fn render(template: &str, notification: &Outgoing) -> String {
+    let mut res = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        res.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = if let Some(end) = rest.find('}') {
+            end
+        } else {
+            break;
+        };
+        match &rest[1..end] {
+            "title" => res.push_str(&notification.title),
+            "message" => res.push_str(&notification.message),
+            "level" => res.push_str(&notification.level.to_string()),
+            "package" => res.push_str(
+                notification
+                    .package_id
+                    .as_ref()
+                    .map(|p| p.as_str())
+                    .unwrap_or("N/A"),
+            ),
+            "time" => res.push_str(&notification.created_at.to_rfc2822()),
+            _ => res.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    res
+}
+
+#[instrument(skip_all)]
+async fn send(config: &SmtpConfig, to: &[&str], notification: &Outgoing) -> Result<(), Error> {
+    let mut message = Message::builder()
+        .from(
+            config
+                .from
+                .parse::<Mailbox>()
+                .with_kind(ErrorKind::InvalidRequest)?,
+        )
+        .subject(render(&config.subject_template, notification));
+    for address in to {
+        let address = address
+            .parse::<Mailbox>()
+            .with_kind(ErrorKind::InvalidRequest)?;
+        message = message.to(address);
+    }
+    let message = message
+        .body(render(&config.body_template, notification))
+        .with_kind(ErrorKind::InvalidRequest)?;
+    let mut transport = match config.security {
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .with_kind(ErrorKind::Network)?,
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .with_kind(ErrorKind::Network)?
+        }
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+    }
+    .port(config.port)
+    .timeout(Some(SMTP_TIMEOUT));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    transport
+        .build()
+        .send(message)
+        .await
+        .with_kind(ErrorKind::Network)?;
+    Ok(())
+}
+
+/// Emails a notification to the addresses routed for its level, if SMTP is configured
+#[instrument(skip_all)]
+pub async fn deliver(secrets: &PgPool, notification: &Outgoing) -> Result<(), Error> {
+    let config = if let Some(config) = load_config(&mut secrets.acquire().await?).await? {
+        config
+    } else {
+        return Ok(());
+    };
+    let to: Vec<&str> = config
+        .routes
+        .iter()
+        .filter(|r| r.level == notification.level)
+        .map(|r| r.address.as_str())
+        .collect();
+    if to.is_empty() {
+        return Ok(());
+    }
+    send(&config, &to, notification).await
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<SmtpConfig>, Error> {
+    load_config(&mut ctx.secret_store.acquire().await?).await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] host: String,
+    #[arg] port: Option<u16>,
+    #[arg] security: Option<SmtpSecurity>,
+    #[arg] username: Option<String>,
+    #[arg] password: Option<String>,
+    #[arg] from: String,
+    #[arg(rename = "subject-template")] subject_template: Option<String>,
+    #[arg(rename = "body-template")] body_template: Option<String>,
+) -> Result<(), Error> {
+    from.parse::<Mailbox>()
+        .with_kind(ErrorKind::InvalidRequest)?;
+    let security = security.unwrap_or(SmtpSecurity::Starttls);
+    let port = port.unwrap_or_else(|| security.default_port()) as i32;
+    let security = security.as_str();
+    let subject_template = subject_template.unwrap_or_else(|| DEFAULT_SUBJECT_TEMPLATE.to_owned());
+    let body_template = body_template.unwrap_or_else(|| DEFAULT_BODY_TEMPLATE.to_owned());
+    sqlx::query!(
+        "INSERT INTO smtp_config (id, host, port, security, username, password, from_address, subject_template, body_template) VALUES (0, $1, $2, $3, $4, $5, $6, $7, $8) ON CONFLICT (id) DO UPDATE SET host = EXCLUDED.host, port = EXCLUDED.port, security = EXCLUDED.security, username = EXCLUDED.username, password = EXCLUDED.password, from_address = EXCLUDED.from_address, subject_template = EXCLUDED.subject_template, body_template = EXCLUDED.body_template",
+        host,
+        port,
+        security,
+        username,
+        password,
+        from,
+        subject_template,
+        body_template,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM smtp_config")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+#[command(rename = "add-route", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add_route(
+    #[context] ctx: RpcContext,
+    #[arg] level: NotificationLevel,
+    #[arg] address: String,
+) -> Result<(), Error> {
+    address
+        .parse::<Mailbox>()
+        .with_kind(ErrorKind::InvalidRequest)?;
+    let level = level.to_string();
+    sqlx::query!(
+        "INSERT INTO email_route (level, address) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        level,
+        address,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(rename = "remove-route", display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove_route(
+    #[context] ctx: RpcContext,
+    #[arg] level: NotificationLevel,
+    #[arg] address: String,
+) -> Result<(), Error> {
+    let level = level.to_string();
+    if sqlx::query!(
+        "DELETE FROM email_route WHERE level = $1 AND address = $2",
+        level,
+        address,
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("No route for {} notifications to {}", level, address),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+/// Sends a test email to `address` with the current configuration, regardless of routing
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn test(#[context] ctx: RpcContext, #[arg] address: String) -> Result<(), Error> {
+    let config = load_config(&mut ctx.secret_store.acquire().await?)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("SMTP is not configured"), ErrorKind::NotFound))?;
+    send(
+        &config,
+        &[address.as_str()],
+        &Outgoing::new(
+            None,
+            NotificationLevel::Info,
+            "Test Notification".to_owned(),
+            "Email notifications are working.".to_owned(),
+        ),
+    )
+    .await
+}
+
+#[test]
+fn render_template() {
+    let notification = Outgoing::new(
+        None,
+        NotificationLevel::Error,
+        "Backup Failed {message}".to_owned(),
+        "Drive not found".to_owned(),
+    );
+    assert_eq!(
+        render("[{level}] {title} ({package}) {unknown}", &notification),
+        "[error] Backup Failed {message} (N/A) {unknown}"
+    );
+    assert_eq!(render("{message} {", &notification), "Drive not found {");
+}
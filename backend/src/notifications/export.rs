@@ -0,0 +1,202 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use rpc_toolkit::command;
+use rpc_toolkit::hyper::{Body, Response};
+use rpc_toolkit::yajrc::RpcError;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use crate::context::{CliContext, RpcContext};
+use crate::core::rpc_continuations::{RequestGuid, RpcContinuation};
+use crate::util::display_none;
+use crate::{Error, ErrorKind, ResultExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Json
+    }
+}
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+impl FromStr for ExportFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(Error::new(
+                eyre!("Unknown Export Format: {}", s),
+                ErrorKind::InvalidRequest,
+            )),
+        }
+    }
+}
+
+const CSV_HEADER: &str =
+    "id,created-at,package-id,code,level,title,message,data,read-at,occurrences,last-seen\n";
+
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Writes every notification since `since`, oldest first. Fetch the returned guid from
+/// `/rest/rpc/<guid>` within 30 seconds to receive them.
+#[command(
+    custom_cli(cli_export(async, context(CliContext))),
+    display(display_none),
+    metadata(read_only = true)
+)]
+#[instrument(skip_all)]
+pub async fn export(
+    #[context] ctx: RpcContext,
+    #[arg(long = "format")] format: Option<ExportFormat>,
+    #[arg(long = "since")] since: Option<DateTime<Utc>>,
+) -> Result<RequestGuid, Error> {
+    let format = format.unwrap_or_default();
+    let since = since.map(|t| t.naive_utc());
+    let secrets = ctx.secret_store.clone();
+    let guid = RequestGuid::new();
+    let handler = Box::new(move |_| {
+        async move {
+            let body = try_stream! {
+                if format == ExportFormat::Csv {
+                    yield CSV_HEADER.to_owned();
+                }
+                let mut rows = sqlx::query!(
+                    "SELECT id, package_id, created_at, code, level, title, message, data, read_at, occurrences, last_seen FROM notifications WHERE $1::timestamp IS NULL OR created_at >= $1 ORDER BY id",
+                    since
+                )
+                .fetch(&secrets);
+                while let Some(row) = rows.next().await {
+                    let row = row?;
+                    let created_at = DateTime::<Utc>::from_utc(row.created_at, Utc).to_rfc3339();
+                    let read_at = row
+                        .read_at
+                        .map(|t| DateTime::<Utc>::from_utc(t, Utc).to_rfc3339());
+                    let last_seen = DateTime::<Utc>::from_utc(row.last_seen, Utc).to_rfc3339();
+                    yield match format {
+                        ExportFormat::Csv => format!(
+                            "{},{},{},{},{},{},{},{},{},{},{}\n",
+                            row.id,
+                            created_at,
+                            row.package_id.as_deref().map(csv_field).unwrap_or_default(),
+                            row.code,
+                            row.level,
+                            csv_field(&row.title),
+                            csv_field(&row.message),
+                            row.data.as_deref().map(csv_field).unwrap_or_default(),
+                            read_at.unwrap_or_default(),
+                            row.occurrences,
+                            last_seen,
+                        ),
+                        ExportFormat::Json => {
+                            let data = row
+                                .data
+                                .as_deref()
+                                .map(serde_json::from_str::<serde_json::Value>)
+                                .transpose()
+                                .with_kind(ErrorKind::ParseDbField)?;
+                            let mut line = serde_json::to_string(&serde_json::json!({
+                                "id": row.id,
+                                "created-at": created_at,
+                                "package-id": row.package_id,
+                                "code": row.code,
+                                "level": row.level,
+                                "title": row.title,
+                                "message": row.message,
+                                "data": data.unwrap_or(serde_json::Value::Null),
+                                "read-at": read_at,
+                                "occurrences": row.occurrences,
+                                "last-seen": last_seen,
+                            }))
+                            .with_kind(ErrorKind::Serialization)?;
+                            line.push('\n');
+                            line
+                        }
+                    };
+                }
+            };
+            Response::builder()
+                .header(
+                    "Content-Type",
+                    match format {
+                        ExportFormat::Csv => "text/csv",
+                        ExportFormat::Json => "application/x-ndjson",
+                    },
+                )
+                .body(Body::wrap_stream(body.map_err(|e: Error| {
+                    std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+                })))
+                .with_kind(ErrorKind::Network)
+        }
+        .boxed()
+    });
+    ctx.add_continuation(
+        guid.clone(),
+        RpcContinuation::rest(handler, Duration::from_secs(30)),
+    )
+    .await;
+    Ok(guid)
+}
+
+#[instrument(skip_all)]
+async fn cli_export(
+    ctx: CliContext,
+    format: Option<ExportFormat>,
+    since: Option<DateTime<Utc>>,
+) -> Result<(), RpcError> {
+    let guid = rpc_toolkit::command_helpers::call_remote(
+        ctx.clone(),
+        "notification.export",
+        serde_json::json!({ "format": format, "since": since }),
+        PhantomData::<RequestGuid>,
+    )
+    .await?
+    .result?;
+    let mut res = ctx
+        .client
+        .get(format!("{}rest/rpc/{}", ctx.base_url, guid))
+        .send()
+        .await?
+        .error_for_status()?;
+    let mut stdout = tokio::io::stdout();
+    while let Some(chunk) = res.chunk().await? {
+        stdout.write_all(&chunk).await?;
+    }
+    stdout.flush().await?;
+    Ok(())
+}
+
+#[test]
+fn csv_escaping() {
+    assert_eq!(csv_field("Backup Complete"), "Backup Complete");
+    assert_eq!(
+        csv_field("Failed: \"disk full\", retrying"),
+        "\"Failed: \"\"disk full\"\", retrying\""
+    );
+}
@@ -0,0 +1,195 @@
+//! Delivery of notifications to a Matrix room through the client-server API.
+//!
+//! Messages are sent as plain `m.room.message` events: StartOS does not implement Olm or Megolm,
+//! so they are not end-to-end encrypted, and an encrypted room shows them as unencrypted. Their
+//! contents are visible to the homeserver, so use one you trust.
+
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use reqwest::{Client, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres};
+use tracing::instrument;
+
+use super::{NotificationLevel, Outgoing};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const MATRIX_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MatrixConfig {
+    pub homeserver: Url,
+    #[serde(default, skip_serializing)]
+    pub access_token: String,
+    pub room_id: String,
+    pub min_level: NotificationLevel,
+}
+
+#[command(
+    subcommands(get, set, remove, test),
+    about = "Delivers notifications to a Matrix room, without end-to-end encryption"
+)]
+pub fn matrix() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config<Ex>(secrets: &mut Ex) -> Result<Option<MatrixConfig>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    sqlx::query!(
+        "SELECT homeserver, access_token, room_id, min_level FROM matrix_config WHERE id = 0"
+    )
+    .fetch_optional(&mut *secrets)
+    .await?
+    .map(|r| {
+        Ok(MatrixConfig {
+            homeserver: r.homeserver.parse().with_kind(ErrorKind::ParseUrl)?,
+            access_token: r.access_token,
+            room_id: r.room_id,
+            min_level: r.min_level.parse()?,
+        })
+    })
+    .transpose()
+}
+
+fn text(notification: &Outgoing) -> String {
+    let mut res = format!("[{}] {}", notification.level, notification.title);
+    if let Some(package_id) = &notification.package_id {
+        res.push_str(&format!(" ({})", package_id));
+    }
+    res.push('\n');
+    res.push_str(&notification.message);
+    res
+}
+
+/// Posts the notification to the room as an `m.text` message from the account owning the
+/// access token, unencrypted, see the module docs.
+#[instrument(skip_all)]
+async fn send(
+    client: &Client,
+    config: &MatrixConfig,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    let url = format!(
+        "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+        config.homeserver.as_str().trim_end_matches('/'),
+        urlencoding::encode(&config.room_id),
+        uuid::Uuid::new_v4(),
+    );
+    client
+        .put(url)
+        .timeout(MATRIX_TIMEOUT)
+        .bearer_auth(&config.access_token)
+        .json(&serde_json::json!({
+            "msgtype": "m.text",
+            "body": text(notification),
+        }))
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .error_for_status()
+        .with_kind(ErrorKind::Network)?;
+    Ok(())
+}
+
+/// Sends a notification to the configured room, if Matrix is set up and the notification is at
+/// or above its level
+#[instrument(skip_all)]
+pub async fn deliver(
+    secrets: &PgPool,
+    client: &Client,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    match load_config(&mut secrets.acquire().await?).await? {
+        Some(config) if notification.level >= config.min_level => {
+            send(client, &config, notification).await
+        }
+        _ => Ok(()),
+    }
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<MatrixConfig>, Error> {
+    load_config(&mut ctx.secret_store.acquire().await?).await
+}
+
+#[command(
+    about = "Sends notifications to a Matrix room. They are not end-to-end encrypted, even in an encrypted room.",
+    display(display_none),
+    metadata(admin = true)
+)]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg] homeserver: Url,
+    #[arg(rename = "access-token")] access_token: String,
+    #[arg(rename = "room-id")] room_id: String,
+    #[arg(rename = "min-level")] min_level: Option<NotificationLevel>,
+) -> Result<(), Error> {
+    if !matches!(homeserver.scheme(), "http" | "https") {
+        return Err(Error::new(
+            eyre!("Homeserver URL must use the http or https scheme"),
+            ErrorKind::ParseUrl,
+        ));
+    }
+    if !room_id.starts_with('!') || !room_id.contains(':') {
+        return Err(Error::new(
+            eyre!("Room ID must be of the form !room:server"),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let homeserver = homeserver.to_string();
+    let min_level = min_level.unwrap_or(NotificationLevel::Success).to_string();
+    sqlx::query!(
+        "INSERT INTO matrix_config (id, homeserver, access_token, room_id, min_level) VALUES (0, $1, $2, $3, $4) ON CONFLICT (id) DO UPDATE SET homeserver = EXCLUDED.homeserver, access_token = EXCLUDED.access_token, room_id = EXCLUDED.room_id, min_level = EXCLUDED.min_level",
+        homeserver,
+        access_token,
+        room_id,
+        min_level,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM matrix_config")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+/// Sends a test message to the configured room, regardless of its level
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn test(#[context] ctx: RpcContext) -> Result<(), Error> {
+    let config = load_config(&mut ctx.secret_store.acquire().await?)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("Matrix is not configured"), ErrorKind::NotFound))?;
+    send(
+        &ctx.client,
+        &config,
+        &Outgoing::new(
+            None,
+            NotificationLevel::Info,
+            "Test Notification".to_owned(),
+            "Matrix notifications are working.".to_owned(),
+        ),
+    )
+    .await
+}
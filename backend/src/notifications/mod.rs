@@ -0,0 +1,838 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use patch_db::{DbHandle, LockType};
+use reqwest::Client;
+use rpc_toolkit::command;
+use sqlx::PgPool;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::{broadcast, Mutex};
+use tracing::instrument;
+
+use crate::auth::NewDeviceLogin;
+use crate::backup::BackupReport;
+use crate::context::RpcContext;
+use crate::install::update_all::UpdateReport;
+use crate::s9pk::manifest::PackageId;
+use crate::shutdown::Shutdown;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+pub mod config;
+pub mod digest;
+pub mod email;
+pub mod export;
+pub mod matrix;
+pub mod quiet;
+pub mod retention;
+pub mod rule;
+pub mod stream;
+pub mod telegram;
+pub mod webhook;
+
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How many notifications a slow stream subscriber may fall behind before it misses some
+const NOTIFICATION_STREAM_CAPACITY: usize = 32;
+/// How many notifications a package's scripts may raise per `PACKAGE_NOTIFICATION_WINDOW`
+pub const PACKAGE_NOTIFICATION_LIMIT: u32 = 10;
+pub const PACKAGE_NOTIFICATION_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+#[command(subcommands(
+    list,
+    codes,
+    mark_read,
+    mark_all_read,
+    delete,
+    delete_before,
+    create,
+    export::export,
+    config::config,
+    rule::rule,
+    email::email,
+    webhook::webhook,
+    matrix::matrix,
+    telegram::telegram,
+))]
+pub async fn notification() -> Result<(), Error> {
+    Ok(())
+}
+
+/// One page of `notification.list`, newest first
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationPage {
+    pub notifications: Vec<Notification>,
+    /// Pass back as `cursor` to get the next page. `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+fn encode_cursor(id: i32) -> String {
+    base64::encode_config(id.to_string(), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str) -> Result<i32, Error> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .ok()
+        .and_then(|c| String::from_utf8(c).ok())
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(|| {
+            Error::new(
+                eyre!("Invalid Notification Cursor: {}", cursor),
+                ErrorKind::InvalidRequest,
+            )
+        })
+}
+
+fn display_notifications(arg: NotificationPage, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ID", "CREATED", "LEVEL", "PACKAGE", "TITLE", "DETAILS"]);
+    for notification in &arg.notifications {
+        let title = if notification.occurrences > 1 {
+            format!("{} (x{})", notification.title, notification.occurrences)
+        } else {
+            notification.title.clone()
+        };
+        table.add_row(row![
+            notification.id,
+            notification.created_at.to_rfc3339(),
+            notification.level,
+            notification
+                .package_id
+                .as_ref()
+                .map_or("N/A", |p| p.as_str()),
+            title,
+            notification
+                .summary()
+                .unwrap_or_else(|| notification.message.clone()),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+    if let Some(cursor) = arg.next_cursor {
+        println!("More: --cursor {}", cursor);
+    }
+}
+
+#[command(display(display_notifications), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[arg] cursor: Option<String>,
+    #[arg] limit: Option<u32>,
+    #[arg(long = "level")] level: Option<NotificationLevel>,
+    #[arg(long = "package")] package: Option<PackageId>,
+    #[arg(long = "code")] code: Option<NotificationCode>,
+    #[arg(long = "since")] since: Option<DateTime<Utc>>,
+    #[arg(long = "until")] until: Option<DateTime<Utc>>,
+    #[arg(long = "search")] search: Option<String>,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<NotificationPage, Error> {
+    let limit = limit.unwrap_or(40);
+    let cursor = cursor.as_deref().map(decode_cursor).transpose()?;
+    let level = level.map(|l| l.to_string());
+    let package = package.as_ref().map(|p| p.as_str());
+    let code = code.map(|c| c.0);
+    let since = since.map(|t| t.naive_utc());
+    let until = until.map(|t| t.naive_utc());
+    // one extra row tells us whether there is another page
+    let mut records = sqlx::query!(
+        "SELECT id, package_id, created_at, code, level, title, message, data, read_at, actions, occurrences, last_seen FROM notifications WHERE ($1::integer IS NULL OR id < $1) AND ($2::text IS NULL OR level = $2) AND ($3::text IS NULL OR package_id = $3) AND ($4::integer IS NULL OR code = $4) AND ($5::timestamp IS NULL OR created_at >= $5) AND ($6::timestamp IS NULL OR created_at < $6) AND ($7::text IS NULL OR to_tsvector('english', title || ' ' || message) @@ plainto_tsquery('english', $7)) ORDER BY id DESC LIMIT $8",
+        cursor,
+        level,
+        package,
+        code,
+        since,
+        until,
+        search,
+        limit as i64 + 1
+    ).fetch_all(&ctx.secret_store).await?;
+    let next_cursor = if records.len() > limit as usize {
+        records.truncate(limit as usize);
+        records.last().map(|r| encode_cursor(r.id))
+    } else {
+        None
+    };
+    let notifications = records
+        .into_iter()
+        .map(|r| {
+            Ok(Notification {
+                id: r.id as u32,
+                package_id: r.package_id.and_then(|p| p.parse().ok()),
+                created_at: DateTime::from_utc(r.created_at, Utc),
+                code: r.code as u32,
+                level: match r.level.parse::<NotificationLevel>() {
+                    Ok(a) => a,
+                    Err(e) => return Err(e.into()),
+                },
+                title: r.title,
+                message: r.message,
+                data: match r.data {
+                    None => serde_json::Value::Null,
+                    Some(v) => match v.parse::<serde_json::Value>() {
+                        Ok(a) => a,
+                        Err(e) => {
+                            return Err(Error::new(
+                                eyre!("Invalid Notification Data: {}", e),
+                                ErrorKind::ParseDbField,
+                            ))
+                        }
+                    },
+                },
+                read_at: r.read_at.map(|t| DateTime::from_utc(t, Utc)),
+                occurrences: r.occurrences as u32,
+                last_seen: DateTime::from_utc(r.last_seen, Utc),
+                actions: match serde_json::from_str(&r.actions) {
+                    Ok(a) => a,
+                    Err(e) => {
+                        return Err(Error::new(
+                            eyre!("Invalid Notification Actions: {}", e),
+                            ErrorKind::ParseDbField,
+                        ))
+                    }
+                },
+            })
+        })
+        .collect::<Result<Vec<Notification>, Error>>()?;
+    Ok(NotificationPage {
+        notifications,
+        next_cursor,
+    })
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationCodeInfo {
+    pub code: i32,
+    pub name: String,
+}
+
+fn display_codes(arg: Vec<NotificationCodeInfo>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "CODE", "NAME"]);
+    for code in arg {
+        table.add_row(row![code.code, &code.name]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+/// Lists the notification codes that `list --code` accepts
+#[command(display(display_codes), metadata(read_only = true))]
+pub async fn codes(
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<NotificationCodeInfo>, Error> {
+    Ok(NOTIFICATION_CODES
+        .iter()
+        .map(|(code, name)| NotificationCodeInfo {
+            code: *code,
+            name: (*name).to_owned(),
+        })
+        .collect())
+}
+
+/// Recomputes the unread badge count from the notifications that have not been marked read
+#[instrument(skip_all)]
+async fn sync_unread_count<Db: DbHandle>(secrets: &PgPool, db: &mut Db) -> Result<(), Error> {
+    let model = crate::db::DatabaseModel::new()
+        .server_info()
+        .unread_notification_count();
+    // held across the count so a concurrent `notify` cannot be lost
+    model.lock(db, LockType::Write).await?;
+    let count =
+        sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM notifications WHERE read_at IS NULL")
+            .fetch_one(secrets)
+            .await?
+            .count;
+    model.put(db, &(count as u64)).await?;
+    Ok(())
+}
+
+#[command(rename = "mark-read", display(display_none))]
+#[instrument(skip_all)]
+pub async fn mark_read(
+    #[context] ctx: RpcContext,
+    #[arg(parse(parse_comma_separated))] ids: Vec<i32>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        "UPDATE notifications SET read_at = CURRENT_TIMESTAMP WHERE id = ANY($1) AND read_at IS NULL",
+        &ids
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    sync_unread_count(&ctx.secret_store, &mut ctx.db.handle()).await
+}
+
+#[command(rename = "mark-all-read", display(display_none))]
+#[instrument(skip_all)]
+pub async fn mark_all_read(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("UPDATE notifications SET read_at = CURRENT_TIMESTAMP WHERE read_at IS NULL")
+        .execute(&ctx.secret_store)
+        .await?;
+    sync_unread_count(&ctx.secret_store, &mut ctx.db.handle()).await
+}
+
+fn parse_comma_separated(arg: &str, _: &ArgMatches) -> Result<Vec<i32>, Error> {
+    arg.split(',')
+        .map(|s| {
+            s.trim().parse().map_err(|_| {
+                Error::new(
+                    eyre!("Invalid Notification ID: {}", s),
+                    ErrorKind::Deserialization,
+                )
+            })
+        })
+        .collect()
+}
+
+#[command(display(display_none))]
+pub async fn delete(#[context] ctx: RpcContext, #[arg] id: i32) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM notifications WHERE id = $1", id)
+        .execute(&ctx.secret_store)
+        .await?;
+    sync_unread_count(&ctx.secret_store, &mut ctx.db.handle()).await
+}
+
+#[command(rename = "delete-before", display(display_none))]
+pub async fn delete_before(#[context] ctx: RpcContext, #[arg] before: i32) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM notifications WHERE id < $1", before)
+        .execute(&ctx.secret_store)
+        .await?;
+    sync_unread_count(&ctx.secret_store, &mut ctx.db.handle()).await
+}
+
+#[command(display(display_none))]
+pub async fn create(
+    #[context] ctx: RpcContext,
+    #[arg] package: Option<PackageId>,
+    #[arg] level: NotificationLevel,
+    #[arg] title: String,
+    #[arg] message: String,
+) -> Result<(), Error> {
+    ctx.notification_manager
+        .notify(
+            &mut ctx.db.handle(),
+            package,
+            level,
+            title,
+            message,
+            (),
+            None,
+        )
+        .await
+}
+
+/// Levels are ordered by severity, from `Success` up to `Error`
+#[derive(
+    Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum NotificationLevel {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+impl fmt::Display for NotificationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotificationLevel::Success => write!(f, "success"),
+            NotificationLevel::Info => write!(f, "info"),
+            NotificationLevel::Warning => write!(f, "warning"),
+            NotificationLevel::Error => write!(f, "error"),
+        }
+    }
+}
+pub struct InvalidNotificationLevel(String);
+impl From<InvalidNotificationLevel> for crate::Error {
+    fn from(val: InvalidNotificationLevel) -> Self {
+        Error::new(
+            eyre!("Invalid Notification Level: {}", val.0),
+            ErrorKind::ParseDbField,
+        )
+    }
+}
+impl FromStr for NotificationLevel {
+    type Err = InvalidNotificationLevel;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            s if s == "success" => Ok(NotificationLevel::Success),
+            s if s == "info" => Ok(NotificationLevel::Info),
+            s if s == "warning" => Ok(NotificationLevel::Warning),
+            s if s == "error" => Ok(NotificationLevel::Error),
+            s => Err(InvalidNotificationLevel(s.to_string())),
+        }
+    }
+}
+impl fmt::Display for InvalidNotificationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Notification Level: {}", self.0)
+    }
+}
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Notification {
+    id: u32,
+    package_id: Option<PackageId>, // TODO change for package id newtype
+    created_at: DateTime<Utc>,
+    code: u32,
+    level: NotificationLevel,
+    title: String,
+    message: String,
+    data: serde_json::Value,
+    read_at: Option<DateTime<Utc>>,
+    actions: Vec<NotificationAction>,
+    /// How many times it was raised again within its debounce interval, including the first
+    occurrences: u32,
+    last_seen: DateTime<Utc>,
+}
+
+impl Notification {
+    /// The `data` of the notification as `T`, if it is of that type
+    pub fn data_as<T: NotificationType>(&self) -> Option<Result<T, Error>> {
+        if self.code as i32 != T::CODE {
+            return None;
+        }
+        Some(serde_json::from_value(self.data.clone()).with_kind(ErrorKind::Deserialization))
+    }
+    /// A one line description of the `data`, for the types that have one
+    fn summary(&self) -> Option<String> {
+        fn summarize<T: NotificationType>(notification: &Notification) -> Option<String> {
+            notification.data_as::<T>()?.ok()?.summary()
+        }
+        summarize::<BackupReport>(self)
+            .or_else(|| summarize::<NewDeviceLogin>(self))
+            .or_else(|| summarize::<UpdateReport>(self))
+    }
+}
+
+/// Something the user can do about a notification straight from the UI or CLI
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum NotificationAction {
+    /// Calls `method` with `params` over RPC
+    #[serde(rename_all = "kebab-case")]
+    Rpc {
+        label: String,
+        method: String,
+        params: serde_json::Value,
+    },
+    /// Opens a page of the UI
+    #[serde(rename_all = "kebab-case")]
+    Route { label: String, route: String },
+}
+
+/// A notification as handed to the external delivery channels
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Outgoing {
+    pub package_id: Option<PackageId>,
+    pub created_at: DateTime<Utc>,
+    pub code: i32,
+    pub level: NotificationLevel,
+    pub title: String,
+    pub message: String,
+    pub data: serde_json::Value,
+}
+impl Outgoing {
+    pub fn new(
+        package_id: Option<PackageId>,
+        level: NotificationLevel,
+        title: String,
+        message: String,
+    ) -> Self {
+        Outgoing {
+            package_id,
+            created_at: Utc::now(),
+            code: <() as NotificationType>::CODE,
+            level,
+            title,
+            message,
+            data: serde_json::Value::Null,
+        }
+    }
+}
+
+pub trait NotificationType:
+    serde::Serialize + for<'de> serde::Deserialize<'de> + std::fmt::Debug
+{
+    const CODE: i32;
+    /// The name of `CODE` in the API, e.g. `backup-report`
+    const NAME: &'static str;
+    fn actions(&self) -> Vec<NotificationAction> {
+        Vec::new()
+    }
+    fn summary(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Every `NotificationType`, by code and name
+pub const NOTIFICATION_CODES: &[(i32, &str)] = &[
+    (
+        <() as NotificationType>::CODE,
+        <() as NotificationType>::NAME,
+    ),
+    (BackupReport::CODE, BackupReport::NAME),
+    (NewDeviceLogin::CODE, NewDeviceLogin::NAME),
+    (UpdateReport::CODE, UpdateReport::NAME),
+];
+
+/// A notification code, given either by number or by name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct NotificationCode(pub i32);
+impl FromStr for NotificationCode {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(code) = s.parse() {
+            return Ok(NotificationCode(code));
+        }
+        NOTIFICATION_CODES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(code, _)| NotificationCode(*code))
+            .ok_or_else(|| {
+                Error::new(
+                    eyre!("Unknown Notification Code: {}", s),
+                    ErrorKind::InvalidRequest,
+                )
+            })
+    }
+}
+impl<'de> serde::Deserialize<'de> for NotificationCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Code {
+            Number(i32),
+            Name(String),
+        }
+        match Code::deserialize(deserializer)? {
+            Code::Number(code) => Ok(NotificationCode(code)),
+            Code::Name(name) => name.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+impl NotificationType for () {
+    const CODE: i32 = 0;
+    const NAME: &'static str = "general";
+}
+impl NotificationType for BackupReport {
+    const CODE: i32 = 1;
+    const NAME: &'static str = "backup-report";
+    fn summary(&self) -> Option<String> {
+        Some(self.describe())
+    }
+    fn actions(&self) -> Vec<NotificationAction> {
+        if self.has_failures() {
+            // the backup password has to be entered again, so send the user to the backup page
+            vec![NotificationAction::Route {
+                label: "Retry Backup".to_owned(),
+                route: "/system/backup".to_owned(),
+            }]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Fixed window rate limit on the notifications a package raises itself
+#[derive(Debug, Clone, Copy)]
+struct PackageQuota {
+    window_start: Instant,
+    used: u32,
+}
+impl PackageQuota {
+    fn new(now: Instant) -> Self {
+        PackageQuota {
+            window_start: now,
+            used: 0,
+        }
+    }
+    fn take(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.window_start) >= PACKAGE_NOTIFICATION_WINDOW {
+            *self = PackageQuota::new(now);
+        }
+        if self.used < PACKAGE_NOTIFICATION_LIMIT {
+            self.used += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub struct NotificationManager {
+    sqlite: PgPool,
+    client: Client,
+    /// When each kind of notification was last issued, and its row
+    cache: Mutex<HashMap<(Option<PackageId>, NotificationLevel, String), (i64, i32)>>,
+    created: broadcast::Sender<Arc<Notification>>,
+    package_quotas: std::sync::Mutex<HashMap<PackageId, PackageQuota>>,
+}
+impl NotificationManager {
+    pub fn new(sqlite: PgPool, client: Client) -> Self {
+        NotificationManager {
+            sqlite,
+            client,
+            cache: Mutex::new(HashMap::new()),
+            created: broadcast::channel(NOTIFICATION_STREAM_CAPACITY).0,
+            package_quotas: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+    /// Counts a notification raised by the package's own scripts against its rate limit.
+    /// Returns whether it is within the limit.
+    pub fn take_package_quota(&self, package_id: &PackageId) -> bool {
+        let now = Instant::now();
+        self.package_quotas
+            .lock()
+            .unwrap()
+            .entry(package_id.clone())
+            .or_insert_with(|| PackageQuota::new(now))
+            .take(now)
+    }
+    /// Receives every notification as it is created
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Notification>> {
+        self.created.subscribe()
+    }
+    #[instrument(skip_all)]
+    pub async fn notify<Db: DbHandle, T: NotificationType>(
+        &self,
+        db: &mut Db,
+        package_id: Option<PackageId>,
+        level: NotificationLevel,
+        title: String,
+        message: String,
+        subtype: T,
+        debounce_interval: Option<u32>,
+    ) -> Result<(), Error> {
+        if let Some(package_id) = &package_id {
+            if !rule::permits(&self.sqlite, package_id, &level).await? {
+                return Ok(());
+            }
+        }
+        let key = (package_id.clone(), level.clone(), title.clone());
+        if let Some(id) = self.duplicate_of(&key, debounce_interval).await {
+            if sqlx::query!(
+                "UPDATE notifications SET occurrences = occurrences + 1, last_seen = CURRENT_TIMESTAMP WHERE id = $1",
+                id
+            )
+            .execute(&self.sqlite)
+            .await?
+            .rows_affected()
+                > 0
+            {
+                return Ok(());
+            }
+        }
+        let mut count = crate::db::DatabaseModel::new()
+            .server_info()
+            .unread_notification_count()
+            .get_mut(db)
+            .await?;
+        let sql_package_id = package_id.as_ref().map(|p| &**p);
+        let sql_code = T::CODE;
+        let sql_level = format!("{}", level);
+        let data = serde_json::to_value(&subtype).with_kind(crate::ErrorKind::Serialization)?;
+        let sql_data = serde_json::to_string(&data).with_kind(crate::ErrorKind::Serialization)?;
+        let actions = subtype.actions();
+        let sql_actions =
+            serde_json::to_string(&actions).with_kind(crate::ErrorKind::Serialization)?;
+        let created = sqlx::query!(
+        "INSERT INTO notifications (package_id, code, level, title, message, data, actions) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id, created_at, last_seen",
+        sql_package_id,
+        sql_code as i32,
+        sql_level,
+        title,
+        message,
+        sql_data,
+        sql_actions
+    ).fetch_one(&self.sqlite).await?;
+        self.cache
+            .lock()
+            .await
+            .insert(key, (Utc::now().timestamp(), created.id));
+        *count += 1;
+        count.save(db).await?;
+        let created_at = DateTime::from_utc(created.created_at, Utc);
+        // nobody listening is not an error
+        let _ = self.created.send(Arc::new(Notification {
+            id: created.id as u32,
+            package_id: package_id.clone(),
+            created_at,
+            code: sql_code as u32,
+            level: level.clone(),
+            title: title.clone(),
+            message: message.clone(),
+            data: data.clone(),
+            read_at: None,
+            actions,
+            occurrences: 1,
+            last_seen: DateTime::from_utc(created.last_seen, Utc),
+        }));
+        self.deliver(Outgoing {
+            package_id,
+            created_at,
+            code: sql_code,
+            level,
+            title,
+            message,
+            data,
+        });
+        Ok(())
+    }
+    /// Sends the notification out over the configured channels in the background, so a slow or
+    /// unreachable server never holds up the caller. Low severity notifications are held back
+    /// for the daily digest instead while it is enabled, and everything is queued during quiet
+    /// hours.
+    fn deliver(&self, notification: Outgoing) {
+        let secrets = self.sqlite.clone();
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            match digest::hold(&secrets, &notification).await {
+                Ok(true) => return,
+                Ok(false) => (),
+                Err(e) => {
+                    tracing::error!("Error Adding Notification to Digest: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            match quiet::hold(&secrets, &notification).await {
+                Ok(true) => return,
+                Ok(false) => (),
+                Err(e) => {
+                    tracing::error!("Error Queueing Notification for Quiet Hours: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            send_to_channels(&secrets, &client, &notification).await
+        });
+    }
+    /// The notification that a repeat within `debounce_interval` seconds should be folded into
+    async fn duplicate_of(
+        &self,
+        key: &(Option<PackageId>, NotificationLevel, String),
+        debounce_interval: Option<u32>,
+    ) -> Option<i32> {
+        let guard = self.cache.lock().await;
+        let (last_issued, id) = guard.get(key)?;
+        let interval = debounce_interval?;
+        if last_issued + interval as i64 > Utc::now().timestamp() {
+            Some(*id)
+        } else {
+            None
+        }
+    }
+}
+
+/// Delivers a notification over every external channel that is configured for its level,
+/// logging the ones that fail
+async fn send_to_channels(secrets: &PgPool, client: &Client, notification: &Outgoing) {
+    let (email, webhook, matrix, telegram) = tokio::join!(
+        email::deliver(secrets, notification),
+        webhook::deliver(secrets, client, notification),
+        matrix::deliver(secrets, client, notification),
+        telegram::deliver(secrets, client, notification),
+    );
+    if let Err(e) = email {
+        tracing::error!("Error Sending Notification Email: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+    if let Err(e) = webhook {
+        tracing::error!("Error Sending Notification Webhooks: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+    if let Err(e) = matrix {
+        tracing::error!("Error Sending Notification to Matrix: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+    if let Err(e) = telegram {
+        tracing::error!("Error Sending Notification to Telegram: {}", e);
+        tracing::debug!("{:?}", e);
+    }
+}
+
+/// Runs the notification housekeeping every few minutes until the server shuts down: pruning
+/// old notifications, sending what was queued during quiet hours once they are over, and sending
+/// the daily digest when it is due
+pub async fn launch_maintenance_task(ctx: &RpcContext, mut shutdown: Receiver<Option<Shutdown>>) {
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(e) = retention::prune(&ctx.secret_store, &mut ctx.db.handle()).await {
+                    tracing::error!("Error Pruning Notifications: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+                if let Err(e) = quiet::flush_if_over(&ctx.secret_store, &ctx.client).await {
+                    tracing::error!("Error Sending Notifications Queued for Quiet Hours: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+                if let Err(e) = digest::send_if_due(&ctx.secret_store, &ctx.client).await {
+                    tracing::error!("Error Sending Notification Digest: {}", e);
+                    tracing::debug!("{:?}", e);
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[test]
+fn serialization() {
+    println!(
+        "{}",
+        serde_json::json!({ "test": "abcdefg", "num": 32, "nested": { "inner": null, "xyz": [0,2,4]}})
+    )
+}
+
+#[test]
+fn cursor() {
+    assert_eq!(decode_cursor(&encode_cursor(1234)).unwrap(), 1234);
+    assert!(decode_cursor("not a cursor").is_err());
+}
+
+#[test]
+fn notification_code() {
+    assert_eq!(
+        "backup-report".parse::<NotificationCode>().unwrap(),
+        NotificationCode(BackupReport::CODE)
+    );
+    assert_eq!(
+        serde_json::from_str::<NotificationCode>("2").unwrap(),
+        NotificationCode(NewDeviceLogin::CODE)
+    );
+    assert!("backup".parse::<NotificationCode>().is_err());
+}
+
+#[test]
+fn package_quota() {
+    let start = Instant::now();
+    let mut quota = PackageQuota::new(start);
+    for _ in 0..PACKAGE_NOTIFICATION_LIMIT {
+        assert!(quota.take(start));
+    }
+    assert!(!quota.take(start + Duration::from_secs(60)));
+    assert!(quota.take(start + PACKAGE_NOTIFICATION_WINDOW));
+}
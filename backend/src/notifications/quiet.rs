@@ -0,0 +1,101 @@
+use chrono::{NaiveTime, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::config::load_config;
+use super::{send_to_channels, NotificationLevel, Outgoing};
+use crate::util::time::TimeWindow;
+use crate::{Error, ErrorKind, ResultExt};
+
+/// A daily window during which notifications are queued instead of sent out
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct QuietHours {
+    #[serde(flatten)]
+    pub window: TimeWindow,
+    /// Whether `error` notifications are still sent during quiet hours
+    pub allow_errors: bool,
+}
+impl QuietHours {
+    pub fn from_minutes(start: i32, end: i32, allow_errors: bool) -> Self {
+        QuietHours {
+            window: TimeWindow::from_minutes(start, end),
+            allow_errors,
+        }
+    }
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        self.window.contains(time)
+    }
+}
+
+/// Whether it is currently quiet hours
+#[instrument(skip_all)]
+pub async fn is_quiet(secrets: &PgPool) -> Result<bool, Error> {
+    Ok(load_config(&mut secrets.acquire().await?)
+        .await?
+        .quiet_hours
+        .map_or(false, |q| q.contains(Utc::now().time())))
+}
+
+/// Queues the notification until quiet hours are over, unless it is an error that is allowed
+/// through. Returns whether it was queued.
+#[instrument(skip_all)]
+pub async fn hold(secrets: &PgPool, notification: &Outgoing) -> Result<bool, Error> {
+    let quiet_hours = if let Some(quiet_hours) = load_config(&mut secrets.acquire().await?)
+        .await?
+        .quiet_hours
+    {
+        quiet_hours
+    } else {
+        return Ok(false);
+    };
+    if !quiet_hours.contains(Utc::now().time())
+        || (quiet_hours.allow_errors && notification.level == NotificationLevel::Error)
+    {
+        return Ok(false);
+    }
+    let notification = serde_json::to_string(notification).with_kind(ErrorKind::Serialization)?;
+    sqlx::query!(
+        "INSERT INTO notification_outbox (notification) VALUES ($1)",
+        notification
+    )
+    .execute(secrets)
+    .await?;
+    Ok(true)
+}
+
+/// Sends everything queued during quiet hours, oldest first, once they are over
+#[instrument(skip_all)]
+pub async fn flush_if_over(secrets: &PgPool, client: &Client) -> Result<(), Error> {
+    if is_quiet(secrets).await? {
+        return Ok(());
+    }
+    let queued = sqlx::query!("SELECT id, notification FROM notification_outbox ORDER BY id")
+        .fetch_all(secrets)
+        .await?;
+    let last_id = if let Some(last) = queued.last() {
+        last.id
+    } else {
+        return Ok(());
+    };
+    sqlx::query!("DELETE FROM notification_outbox WHERE id <= $1", last_id)
+        .execute(secrets)
+        .await?;
+    for row in queued {
+        match serde_json::from_str::<Outgoing>(&row.notification) {
+            Ok(notification) => send_to_channels(secrets, client, &notification).await,
+            Err(e) => tracing::warn!("Dropping unreadable queued notification {}: {}", row.id, e),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn quiet_window() {
+    let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    let overnight = QuietHours::from_minutes(22 * 60, 7 * 60, true);
+    assert!(overnight.contains(at(23, 30)));
+    assert!(!overnight.contains(at(12, 0)));
+}
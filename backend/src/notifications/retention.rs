@@ -0,0 +1,39 @@
+use patch_db::DbHandle;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::config::load_config;
+use super::sync_unread_count;
+use crate::Error;
+
+/// Deletes the notifications that fall outside the retention policy
+#[instrument(skip_all)]
+pub async fn prune<Db: DbHandle>(secrets: &PgPool, db: &mut Db) -> Result<(), Error> {
+    let config = load_config(&mut secrets.acquire().await?).await?;
+    let mut pruned = 0;
+    if config.retention_days > 0 {
+        let retention_days = config.retention_days as i32;
+        pruned += sqlx::query!(
+            "DELETE FROM notifications WHERE created_at < CURRENT_TIMESTAMP - make_interval(days => $1)",
+            retention_days
+        )
+        .execute(secrets)
+        .await?
+        .rows_affected();
+    }
+    if config.max_rows > 0 {
+        let max_rows = config.max_rows as i64;
+        pruned += sqlx::query!(
+            "DELETE FROM notifications WHERE id <= (SELECT id FROM notifications ORDER BY id DESC OFFSET $1 LIMIT 1)",
+            max_rows
+        )
+        .execute(secrets)
+        .await?
+        .rows_affected();
+    }
+    if pruned > 0 {
+        tracing::info!("Pruned {} notifications", pruned);
+        sync_unread_count(secrets, db).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::NotificationLevel;
+use crate::context::RpcContext;
+use crate::s9pk::manifest::PackageId;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NotificationRule {
+    pub package_id: PackageId,
+    /// Only notifications at or above this level are kept. `None` mutes the package.
+    pub min_level: Option<NotificationLevel>,
+}
+
+#[command(subcommands(list, mute, filter, clear))]
+pub fn rule() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Whether a notification from `package_id` at `level` passes the package's rule, if it has one
+#[instrument(skip_all)]
+pub async fn permits(
+    secrets: &PgPool,
+    package_id: &PackageId,
+    level: &NotificationLevel,
+) -> Result<bool, Error> {
+    let package_id = &**package_id;
+    Ok(
+        match sqlx::query!(
+            "SELECT min_level FROM notification_rule WHERE package_id = $1",
+            package_id
+        )
+        .fetch_optional(secrets)
+        .await?
+        {
+            None => true,
+            Some(rule) => match rule.min_level {
+                None => false,
+                Some(min_level) => level >= &min_level.parse::<NotificationLevel>()?,
+            },
+        },
+    )
+}
+
+fn display_rules(arg: Vec<NotificationRule>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "PACKAGE", "MIN LEVEL"]);
+    for rule in arg {
+        table.add_row(row![
+            &*rule.package_id,
+            &rule
+                .min_level
+                .map_or_else(|| "muted".to_owned(), |l| l.to_string()),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_rules), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<NotificationRule>, Error> {
+    sqlx::query!("SELECT package_id, min_level FROM notification_rule ORDER BY package_id")
+        .fetch_all(&ctx.secret_store)
+        .await?
+        .into_iter()
+        .map(|r| {
+            Ok(NotificationRule {
+                package_id: r.package_id.parse()?,
+                min_level: r.min_level.map(|l| l.parse()).transpose()?,
+            })
+        })
+        .collect()
+}
+
+async fn set_rule(
+    ctx: &RpcContext,
+    package_id: &PackageId,
+    min_level: Option<NotificationLevel>,
+) -> Result<(), Error> {
+    let package_id = &**package_id;
+    let min_level = min_level.map(|l| l.to_string());
+    sqlx::query!(
+        "INSERT INTO notification_rule (package_id, min_level) VALUES ($1, $2) ON CONFLICT (package_id) DO UPDATE SET min_level = EXCLUDED.min_level",
+        package_id,
+        min_level,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+/// Drops every notification from the package
+#[command(display(display_none))]
+#[instrument(skip_all)]
+pub async fn mute(#[context] ctx: RpcContext, #[arg] package: PackageId) -> Result<(), Error> {
+    set_rule(&ctx, &package, None).await
+}
+
+/// Drops notifications from the package below `min-level`
+#[command(display(display_none))]
+#[instrument(skip_all)]
+pub async fn filter(
+    #[context] ctx: RpcContext,
+    #[arg] package: PackageId,
+    #[arg(rename = "min-level")] min_level: NotificationLevel,
+) -> Result<(), Error> {
+    set_rule(&ctx, &package, Some(min_level)).await
+}
+
+/// Removes the package's rule, so all of its notifications are kept again
+#[command(display(display_none))]
+#[instrument(skip_all)]
+pub async fn clear(#[context] ctx: RpcContext, #[arg] package: PackageId) -> Result<(), Error> {
+    let package_id = &*package;
+    if sqlx::query!(
+        "DELETE FROM notification_rule WHERE package_id = $1",
+        package_id
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("No notification rule for {}", package),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use futures::{FutureExt, SinkExt, StreamExt};
+use rpc_toolkit::hyper::upgrade::Upgraded;
+use rpc_toolkit::hyper::{Body, Request, Response};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::instrument;
+
+use super::{Notification, NotificationLevel};
+use crate::context::RpcContext;
+use crate::db::subscribe_to_session_kill;
+use crate::middleware::auth::{HasValidSession, HashSessionToken};
+use crate::s9pk::manifest::PackageId;
+use crate::{Error, ResultExt};
+
+/// Narrows the stream with the query string, e.g. `?min-level=warning&package=bitcoind`
+#[derive(Debug, Default)]
+struct StreamFilter {
+    min_level: Option<NotificationLevel>,
+    package: Option<PackageId>,
+}
+impl StreamFilter {
+    fn from_query(query: Option<&str>) -> Result<Self, Error> {
+        let mut filter = StreamFilter::default();
+        for (k, v) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match &*k {
+                "min-level" => filter.min_level = Some(v.parse()?),
+                "package" => filter.package = Some(v.parse()?),
+                _ => (),
+            }
+        }
+        Ok(filter)
+    }
+    fn matches(&self, notification: &Notification) -> bool {
+        self.min_level
+            .as_ref()
+            .map_or(true, |l| &notification.level >= l)
+            && self
+                .package
+                .as_ref()
+                .map_or(true, |p| notification.package_id.as_ref() == Some(p))
+    }
+}
+
+#[instrument(skip_all)]
+async fn deal_with_messages(
+    ctx: RpcContext,
+    token: HashSessionToken,
+    filter: StreamFilter,
+    mut stream: WebSocketStream<Upgraded>,
+) -> Result<(), Error> {
+    let mut kill = subscribe_to_session_kill(&ctx, token).await;
+    let mut created = ctx.notification_manager.subscribe();
+    let mut timer = tokio::time::interval(tokio::time::Duration::from_secs(5));
+    loop {
+        futures::select! {
+            _ = (&mut kill).fuse() => {
+                tracing::info!("Closing WebSocket: Reason: Session Terminated");
+                stream
+                    .close(Some(CloseFrame {
+                        code: CloseCode::Error,
+                        reason: "UNAUTHORIZED".into(),
+                    }))
+                    .await
+                    .with_kind(crate::ErrorKind::Network)?;
+                return Ok(())
+            }
+            notification = created.recv().fuse() => {
+                let notification = match notification {
+                    Ok(a) => a,
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("Notification stream skipped {} notifications", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if filter.matches(&notification) {
+                    stream
+                        .send(Message::Text(serde_json::to_string(&*notification).with_kind(crate::ErrorKind::Serialization)?))
+                        .await
+                        .with_kind(crate::ErrorKind::Network)?;
+                }
+            }
+            message = stream.next().fuse() => {
+                let message = message.transpose().with_kind(crate::ErrorKind::Network)?;
+                if message.is_none() {
+                    tracing::info!("Closing WebSocket: Stream Finished");
+                    return Ok(())
+                }
+            }
+            _ = timer.tick().fuse() => {
+                stream
+                    .send(Message::Ping(vec![]))
+                    .await
+                    .with_kind(crate::ErrorKind::Network)?;
+            }
+        }
+    }
+}
+
+/// Handles `/ws/notifications`, which pushes each new notification to the client as JSON
+pub async fn subscribe(ctx: RpcContext, req: Request<Body>) -> Result<Response<Body>, Error> {
+    let (parts, body) = req.into_parts();
+    let session = match async {
+        let token = HashSessionToken::from_request_parts(&parts)?;
+        HasValidSession::from_request_parts(&parts, &ctx).await?;
+        Ok::<_, Error>(token)
+    }
+    .await
+    {
+        Ok(a) => Some(a),
+        Err(e) => {
+            if e.kind != crate::ErrorKind::Authorization {
+                tracing::error!("Error Authenticating Websocket: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+            None
+        }
+    };
+    let filter = StreamFilter::from_query(parts.uri.query());
+    let req = Request::from_parts(parts, body);
+    let (res, ws_fut) = hyper_ws_listener::create_ws(req).with_kind(crate::ErrorKind::Network)?;
+    if let Some(ws_fut) = ws_fut {
+        tokio::task::spawn(async move {
+            let res = async {
+                let mut stream = ws_fut
+                    .await
+                    .with_kind(crate::ErrorKind::Network)?
+                    .with_kind(crate::ErrorKind::Unknown)?;
+                match (session, filter) {
+                    (Some(token), Ok(filter)) => {
+                        deal_with_messages(ctx, token, filter, stream).await
+                    }
+                    (None, _) => stream
+                        .close(Some(CloseFrame {
+                            code: CloseCode::Error,
+                            reason: "UNAUTHORIZED".into(),
+                        }))
+                        .await
+                        .with_kind(crate::ErrorKind::Network),
+                    (_, Err(e)) => stream
+                        .close(Some(CloseFrame {
+                            code: CloseCode::Invalid,
+                            reason: e.source.to_string().into(),
+                        }))
+                        .await
+                        .with_kind(crate::ErrorKind::Network),
+                }
+            }
+            .await;
+            if let Err(e) = res {
+                tracing::error!("WebSocket Closed: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        });
+    }
+
+    Ok(res)
+}
+
+#[test]
+fn stream_filter() {
+    let filter = StreamFilter::from_query(Some("min-level=warning&package=bitcoind")).unwrap();
+    assert_eq!(filter.min_level, Some(NotificationLevel::Warning));
+    assert_eq!(filter.package, Some("bitcoind".parse().unwrap()));
+    assert!(StreamFilter::from_query(Some("min-level=loud")).is_err());
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use color_eyre::eyre::eyre;
+use reqwest::Client;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::{Executor, PgPool, Postgres};
+use tracing::instrument;
+
+use super::{NotificationLevel, Outgoing};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const TELEGRAM_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TelegramConfig {
+    #[serde(default, skip_serializing)]
+    pub bot_token: String,
+    /// Numeric chat ID, or `@channelname` for a public channel
+    pub chat_id: String,
+    pub min_level: NotificationLevel,
+}
+
+#[command(subcommands(get, set, remove, test))]
+pub fn telegram() -> Result<(), Error> {
+    Ok(())
+}
+
+pub async fn load_config<Ex>(secrets: &mut Ex) -> Result<Option<TelegramConfig>, Error>
+where
+    for<'a> &'a mut Ex: Executor<'a, Database = Postgres>,
+{
+    sqlx::query!("SELECT bot_token, chat_id, min_level FROM telegram_config WHERE id = 0")
+        .fetch_optional(&mut *secrets)
+        .await?
+        .map(|r| {
+            Ok(TelegramConfig {
+                bot_token: r.bot_token,
+                chat_id: r.chat_id,
+                min_level: r.min_level.parse()?,
+            })
+        })
+        .transpose()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Renders the notification using Telegram's HTML formatting: the level as an emoji, the title in
+/// bold and the package in italics
+fn format(notification: &Outgoing) -> String {
+    let icon = match notification.level {
+        NotificationLevel::Success => "\u{2705}",
+        NotificationLevel::Info => "\u{2139}\u{fe0f}",
+        NotificationLevel::Warning => "\u{26a0}\u{fe0f}",
+        NotificationLevel::Error => "\u{274c}",
+    };
+    let mut res = format!("{} <b>{}</b>", icon, escape(&notification.title));
+    if let Some(package_id) = &notification.package_id {
+        res.push_str(&format!("\n<i>{}</i>", escape(package_id)));
+    }
+    res.push_str("\n\n");
+    res.push_str(&escape(&notification.message));
+    res
+}
+
+#[instrument(skip_all)]
+async fn send(
+    client: &Client,
+    config: &TelegramConfig,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    let res = client
+        .post(format!(
+            "https://api.telegram.org/bot{}/sendMessage",
+            config.bot_token
+        ))
+        .timeout(TELEGRAM_TIMEOUT)
+        .json(&serde_json::json!({
+            "chat_id": config.chat_id,
+            "text": format(notification),
+            "parse_mode": "HTML",
+            "disable_web_page_preview": true,
+        }))
+        .send()
+        .await
+        // the request URL contains the bot token, so keep it out of the error
+        .map_err(|e| e.without_url())
+        .with_kind(ErrorKind::Network)?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let description = res
+            .json::<serde_json::Value>()
+            .await
+            .ok()
+            .and_then(|v| v["description"].as_str().map(|s| s.to_owned()))
+            .unwrap_or_default();
+        return Err(Error::new(
+            eyre!("Telegram returned {}: {}", status, description),
+            ErrorKind::Network,
+        ));
+    }
+    Ok(())
+}
+
+/// Sends a notification to the configured chat, if Telegram is set up and the notification is at
+/// or above its level
+#[instrument(skip_all)]
+pub async fn deliver(
+    secrets: &PgPool,
+    client: &Client,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    match load_config(&mut secrets.acquire().await?).await? {
+        Some(config) if notification.level >= config.min_level => {
+            send(client, &config, notification).await
+        }
+        _ => Ok(()),
+    }
+}
+
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn get(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Option<TelegramConfig>, Error> {
+    load_config(&mut ctx.secret_store.acquire().await?).await
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn set(
+    #[context] ctx: RpcContext,
+    #[arg(rename = "bot-token")] bot_token: String,
+    #[arg(rename = "chat-id")] chat_id: String,
+    #[arg(rename = "min-level")] min_level: Option<NotificationLevel>,
+) -> Result<(), Error> {
+    if !bot_token.contains(':') || bot_token.contains('/') {
+        return Err(Error::new(
+            eyre!("Bot token must be of the form 123456:ABC..."),
+            ErrorKind::InvalidRequest,
+        ));
+    }
+    let min_level = min_level.unwrap_or(NotificationLevel::Success).to_string();
+    sqlx::query!(
+        "INSERT INTO telegram_config (id, bot_token, chat_id, min_level) VALUES (0, $1, $2, $3) ON CONFLICT (id) DO UPDATE SET bot_token = EXCLUDED.bot_token, chat_id = EXCLUDED.chat_id, min_level = EXCLUDED.min_level",
+        bot_token,
+        chat_id,
+        min_level,
+    )
+    .execute(&ctx.secret_store)
+    .await?;
+    Ok(())
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext) -> Result<(), Error> {
+    sqlx::query!("DELETE FROM telegram_config")
+        .execute(&ctx.secret_store)
+        .await?;
+    Ok(())
+}
+
+/// Sends a test message to the configured chat, regardless of its level
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn test(#[context] ctx: RpcContext) -> Result<(), Error> {
+    let config = load_config(&mut ctx.secret_store.acquire().await?)
+        .await?
+        .ok_or_else(|| Error::new(eyre!("Telegram is not configured"), ErrorKind::NotFound))?;
+    send(
+        &ctx.client,
+        &config,
+        &Outgoing::new(
+            None,
+            NotificationLevel::Info,
+            "Test Notification".to_owned(),
+            "Telegram notifications are working.".to_owned(),
+        ),
+    )
+    .await
+}
+
+#[test]
+fn format_message() {
+    let notification = Outgoing::new(
+        Some("bitcoind".parse().unwrap()),
+        NotificationLevel::Warning,
+        "Low <disk>".to_owned(),
+        "Less than 10% & falling".to_owned(),
+    );
+    assert_eq!(
+        format(&notification),
+        "\u{26a0}\u{fe0f} <b>Low &lt;disk&gt;</b>\n<i>bitcoind</i>\n\nLess than 10% &amp; falling"
+    );
+}
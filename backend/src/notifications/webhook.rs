@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use futures::future::join_all;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Url};
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::{NotificationLevel, Outgoing};
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind, ResultExt};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// `sha256=` followed by the hex encoded HMAC-SHA256 of the request body, keyed with the
+/// webhook's secret
+pub const SIGNATURE_HEADER: &str = "X-StartOS-Signature";
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Webhook {
+    pub id: i32,
+    pub url: Url,
+    pub signed: bool,
+    pub min_level: NotificationLevel,
+    pub created_at: DateTime<Utc>,
+}
+
+#[command(subcommands(list, add, remove, test))]
+pub fn webhook() -> Result<(), Error> {
+    Ok(())
+}
+
+fn sign(secret: &str, body: &[u8]) -> Result<String, Error> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|_| Error::new(eyre!("Invalid Webhook Secret"), ErrorKind::InvalidRequest))?;
+    mac.update(body);
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+#[instrument(skip_all)]
+async fn post(
+    client: &Client,
+    url: &str,
+    secret: Option<&str>,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    let body = serde_json::to_vec(notification).with_kind(ErrorKind::Serialization)?;
+    let mut req = client
+        .post(url)
+        .timeout(WEBHOOK_TIMEOUT)
+        .header("content-type", "application/json");
+    if let Some(secret) = secret {
+        req = req.header(SIGNATURE_HEADER, sign(secret, &body)?);
+    }
+    req.body(body)
+        .send()
+        .await
+        .with_kind(ErrorKind::Network)?
+        .error_for_status()
+        .with_kind(ErrorKind::Network)?;
+    Ok(())
+}
+
+/// Posts a notification to every webhook registered for its level. A failing webhook is logged
+/// and does not prevent delivery to the others.
+#[instrument(skip_all)]
+pub async fn deliver(
+    secrets: &PgPool,
+    client: &Client,
+    notification: &Outgoing,
+) -> Result<(), Error> {
+    let mut hooks = Vec::new();
+    for hook in sqlx::query!("SELECT url, secret, min_level FROM notification_webhook")
+        .fetch_all(secrets)
+        .await?
+    {
+        if notification.level >= hook.min_level.parse::<NotificationLevel>()? {
+            hooks.push((hook.url, hook.secret));
+        }
+    }
+    for ((url, _), res) in hooks.iter().zip(
+        join_all(
+            hooks
+                .iter()
+                .map(|(url, secret)| post(client, url, secret.as_deref(), notification)),
+        )
+        .await,
+    ) {
+        if let Err(e) = res {
+            tracing::warn!("Webhook {} failed: {}", url, e);
+            tracing::debug!("{:?}", e);
+        }
+    }
+    Ok(())
+}
+
+fn display_webhooks(arg: Vec<Webhook>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "ID", "URL", "SIGNED", "MIN LEVEL", "CREATED AT"]);
+    for hook in arg {
+        table.add_row(row![
+            &format!("{}", hook.id),
+            hook.url.as_str(),
+            &format!("{}", hook.signed),
+            &format!("{}", hook.min_level),
+            &format!("{}", hook.created_at),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_webhooks), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<Webhook>, Error> {
+    sqlx::query!(
+        "SELECT id, url, secret IS NOT NULL AS \"signed!\", min_level, created_at FROM notification_webhook ORDER BY id"
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| {
+        Ok(Webhook {
+            id: r.id,
+            url: r.url.parse().with_kind(ErrorKind::ParseUrl)?,
+            signed: r.signed,
+            min_level: r.min_level.parse()?,
+            created_at: DateTime::from_utc(r.created_at, Utc),
+        })
+    })
+    .collect()
+}
+
+/// Registers a webhook that receives every notification at or above `min-level` as JSON.
+/// With a secret, each request carries `sha256=<hex HMAC of its body>` in the
+/// `X-StartOS-Signature` header.
+#[command(display(display_serializable), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] url: Url,
+    #[arg] secret: Option<String>,
+    #[arg(rename = "min-level")] min_level: Option<NotificationLevel>,
+) -> Result<i32, Error> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(Error::new(
+            eyre!("Webhook URL must use the http or https scheme"),
+            ErrorKind::ParseUrl,
+        ));
+    }
+    let url = url.to_string();
+    let min_level = min_level.unwrap_or(NotificationLevel::Success).to_string();
+    Ok(sqlx::query!(
+        "INSERT INTO notification_webhook (url, secret, min_level) VALUES ($1, $2, $3) RETURNING id",
+        url,
+        secret,
+        min_level,
+    )
+    .fetch_one(&ctx.secret_store)
+    .await?
+    .id)
+}
+
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] id: i32) -> Result<(), Error> {
+    if sqlx::query!("DELETE FROM notification_webhook WHERE id = $1", id)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected()
+        == 0
+    {
+        return Err(Error::new(
+            eyre!("Webhook {} Not Found", id),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+/// Posts a test notification to a single webhook, regardless of its level
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn test(#[context] ctx: RpcContext, #[arg] id: i32) -> Result<(), Error> {
+    let hook = sqlx::query!(
+        "SELECT url, secret FROM notification_webhook WHERE id = $1",
+        id
+    )
+    .fetch_optional(&ctx.secret_store)
+    .await?
+    .ok_or_else(|| Error::new(eyre!("Webhook {} Not Found", id), ErrorKind::NotFound))?;
+    post(
+        &ctx.client,
+        &hook.url,
+        hook.secret.as_deref(),
+        &Outgoing::new(
+            None,
+            NotificationLevel::Info,
+            "Test Notification".to_owned(),
+            "Webhook notifications are working.".to_owned(),
+        ),
+    )
+    .await
+}
+
+#[test]
+fn signature() {
+    assert_eq!(
+        sign("secret", b"{}").unwrap(),
+        "sha256=77325902caca812dc259733aacd046b73817372c777b8d95b402647474516e13"
+    );
+}
@@ -1,9 +1,9 @@
 use std::borrow::Cow;
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::ffi::{OsStr, OsString};
 use std::net::Ipv4Addr;
-use std::os::unix::prelude::FileTypeExt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
 use async_stream::stream;
@@ -11,8 +11,8 @@ use bollard::container::RemoveContainerOptions;
 use chrono::format::Item;
 use color_eyre::eyre::eyre;
 use color_eyre::Report;
-use futures::future::{BoxFuture, Either as EitherFuture};
-use futures::{FutureExt, TryStreamExt};
+use futures::future::Either as EitherFuture;
+use futures::TryStreamExt;
 use helpers::{NonDetachingJoinHandle, UnixRpcClient};
 use models::{Id, ImageId};
 use nix::sys::signal;
@@ -26,14 +26,190 @@ use tracing::instrument;
 
 use super::ProcedureName;
 use crate::context::RpcContext;
+use crate::install::devices::Device;
+use crate::install::resources::{effective, ResourceLimits};
 use crate::s9pk::manifest::{PackageId, SYSTEM_PACKAGE_ID};
 use crate::util::serde::{Duration as SerdeDuration, IoFormat};
-use crate::util::Version;
+use crate::util::{Invoke, Version};
 use crate::volume::{VolumeId, Volumes};
 use crate::{Error, ResultExt, HOST_IP};
 
 pub const NET_TLD: &str = "embassy";
 
+/// The engine containers are run with. Each speaks the docker CLI and API, so this only covers
+/// where they differ.
+pub trait ContainerRuntime: Send + Sync {
+    /// The name of its CLI
+    fn name(&self) -> &'static str;
+    /// The socket of its docker compatible API
+    fn socket(&self) -> &Path;
+    /// Its CLI, talking to the same engine as `socket`
+    fn command(&self) -> tokio::process::Command;
+    /// The systemd units serving it, which the OS manages
+    fn services(&self) -> &'static [&'static str];
+    /// Where it keeps images and containers, which the OS mounts from the data drive
+    fn data_dir(&self) -> Option<&'static Path>;
+    /// For `run`, to give a container every NVIDIA GPU
+    fn gpu_args(&self) -> Vec<OsString>;
+    /// Whether its containers run without root, see `PodmanRuntime`
+    fn rootless(&self) -> bool {
+        false
+    }
+    /// The driver options of the networks of the packages, which must not route to each other
+    fn network_options(&self) -> HashMap<&'static str, &'static str>;
+    /// A client of its API, see `RpcContext::docker`
+    fn connect(&self) -> Result<bollard::Docker, Error> {
+        Ok(bollard::Docker::connect_with_unix(
+            &self.socket().display().to_string(),
+            120,
+            bollard::API_DEFAULT_VERSION,
+        )?)
+    }
+}
+
+/// The docker daemon, which runs as root
+pub struct DockerRuntime {
+    socket: PathBuf,
+}
+impl DockerRuntime {
+    pub fn new(socket: Option<PathBuf>) -> Self {
+        Self {
+            socket: socket.unwrap_or_else(|| PathBuf::from("/var/run/docker.sock")),
+        }
+    }
+}
+impl ContainerRuntime for DockerRuntime {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+    fn socket(&self) -> &Path {
+        &self.socket
+    }
+    fn command(&self) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new("docker");
+        cmd.arg(format!("--host=unix://{}", self.socket.display()));
+        cmd
+    }
+    fn services(&self) -> &'static [&'static str] {
+        &["docker"]
+    }
+    fn data_dir(&self) -> Option<&'static Path> {
+        Some(Path::new("/var/lib/docker"))
+    }
+    fn gpu_args(&self) -> Vec<OsString> {
+        vec!["--gpus=all".into()]
+    }
+    fn network_options(&self) -> HashMap<&'static str, &'static str> {
+        // docker isolates its bridges from each other
+        HashMap::new()
+    }
+}
+
+/// Podman, which has no daemon: its API is served on demand by `podman.socket`. Given the
+/// socket of the API of a rootless Podman, everything goes through that instead, and the
+/// containers have no more privileges than its user. That has its limits:
+/// - services cannot bind ports below 1024 unless `net.ipv4.ip_unprivileged_port_start` is
+///   lowered
+/// - no devices can be given to packages, so those asking for a GPU cannot use it
+/// - the binfmt handlers for running the images of other architectures are not installed
+/// - its user manages its images and containers, which are kept in its home rather than the
+///   data drive
+pub struct PodmanRuntime {
+    remote: Option<PathBuf>,
+}
+impl PodmanRuntime {
+    const ROOT_SOCKET: &'static str = "/run/podman/podman.sock";
+
+    pub fn new(socket: Option<PathBuf>) -> Self {
+        Self {
+            remote: socket.filter(|s| s.as_path() != Path::new(Self::ROOT_SOCKET)),
+        }
+    }
+}
+impl ContainerRuntime for PodmanRuntime {
+    fn name(&self) -> &'static str {
+        "podman"
+    }
+    fn socket(&self) -> &Path {
+        self.remote
+            .as_deref()
+            .unwrap_or_else(|| Path::new(Self::ROOT_SOCKET))
+    }
+    fn command(&self) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new("podman");
+        if let Some(remote) = &self.remote {
+            cmd.arg(format!("--url=unix://{}", remote.display()));
+        }
+        cmd
+    }
+    fn services(&self) -> &'static [&'static str] {
+        if self.remote.is_some() {
+            // run as its user, who manages them
+            &[]
+        } else {
+            &["podman.socket"]
+        }
+    }
+    fn data_dir(&self) -> Option<&'static Path> {
+        if self.remote.is_some() {
+            None
+        } else {
+            Some(Path::new("/var/lib/containers"))
+        }
+    }
+    fn gpu_args(&self) -> Vec<OsString> {
+        vec!["--device=nvidia.com/gpu=all".into()]
+    }
+    fn rootless(&self) -> bool {
+        self.remote.is_some()
+    }
+    fn network_options(&self) -> HashMap<&'static str, &'static str> {
+        // podman routes between its bridges unless told otherwise
+        [("isolate", "true")].into_iter().collect()
+    }
+}
+
+/// Which runtime to use, see `container-runtime` in the config. Docker unless built with the
+/// `podman` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerRuntimeKind {
+    Docker,
+    Podman,
+}
+impl Default for ContainerRuntimeKind {
+    fn default() -> Self {
+        if cfg!(feature = "podman") {
+            Self::Podman
+        } else {
+            Self::Docker
+        }
+    }
+}
+impl ContainerRuntimeKind {
+    pub fn runtime(self, socket: Option<PathBuf>) -> Arc<dyn ContainerRuntime> {
+        match self {
+            Self::Docker => Arc::new(DockerRuntime::new(socket)),
+            Self::Podman => Arc::new(PodmanRuntime::new(socket)),
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref RUNTIME: std::sync::RwLock<Arc<dyn ContainerRuntime>> =
+        std::sync::RwLock::new(ContainerRuntimeKind::default().runtime(None));
+}
+
+/// The runtime containers are run with
+pub fn runtime() -> Arc<dyn ContainerRuntime> {
+    RUNTIME.read().unwrap().clone()
+}
+
+/// Sets the runtime, once the config is loaded
+pub fn set_runtime(runtime: Arc<dyn ContainerRuntime>) {
+    *RUNTIME.write().unwrap() = runtime;
+}
+
 lazy_static::lazy_static! {
     pub static ref SYSTEM_IMAGES: BTreeSet<ImageId> = {
         let mut set = BTreeSet::new();
@@ -67,12 +243,35 @@ pub struct DockerContainer {
     #[serde(default)]
     pub sigterm_timeout: Option<SerdeDuration>,
     #[serde(default)]
+    pub cpu_shares: Option<u32>,
+    #[serde(default)]
+    pub memory_limit_mb: Option<usize>,
+    #[serde(default)]
     pub system: bool,
+    /// Asks for `/dev/dri`, as `devices: [dri]` does
     #[serde(default)]
     pub gpu_acceleration: bool,
+    /// Given once approved, see `package devices`
+    #[serde(default)]
+    pub devices: BTreeSet<Device>,
 }
 
 impl DockerContainer {
+    pub fn devices(&self) -> BTreeSet<Device> {
+        let mut devices = self.devices.clone();
+        if self.gpu_acceleration {
+            devices.insert(Device::Dri);
+        }
+        devices
+    }
+
+    pub fn resources(&self) -> ResourceLimits {
+        ResourceLimits {
+            cpu_shares: self.cpu_shares,
+            memory_limit_mb: self.memory_limit_mb,
+        }
+    }
+
     /// We created a new exec runner, where we are going to be passing the commands for it to run.
     /// Idea is that we are going to send it command and get the inputs be filtered back from the manager.
     /// Then we could in theory run commands without the cost of running the docker exec which is known to have
@@ -157,7 +356,15 @@ pub struct DockerProcedure {
     #[serde(default)]
     pub shm_size_mb: Option<usize>, // TODO: use postfix sizing? like 1k vs 1m vs 1g
     #[serde(default)]
+    pub cpu_shares: Option<u32>,
+    #[serde(default)]
+    pub memory_limit_mb: Option<usize>,
+    /// Asks for `/dev/dri`, as `devices: [dri]` does
+    #[serde(default)]
     pub gpu_acceleration: bool,
+    /// Given once approved, see `package devices`
+    #[serde(default)]
+    pub devices: BTreeSet<Device>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
@@ -188,7 +395,25 @@ impl DockerProcedure {
             io_format: injectable.io_format,
             sigterm_timeout: injectable.sigterm_timeout,
             shm_size_mb: container.shm_size_mb,
+            cpu_shares: container.cpu_shares,
+            memory_limit_mb: container.memory_limit_mb,
             gpu_acceleration: container.gpu_acceleration,
+            devices: container.devices.clone(),
+        }
+    }
+
+    pub fn devices(&self) -> BTreeSet<Device> {
+        let mut devices = self.devices.clone();
+        if self.gpu_acceleration {
+            devices.insert(Device::Dri);
+        }
+        devices
+    }
+
+    pub fn resources(&self) -> ResourceLimits {
+        ResourceLimits {
+            cpu_shares: self.cpu_shares,
+            memory_limit_mb: self.memory_limit_mb,
         }
     }
 
@@ -214,6 +439,7 @@ impl DockerProcedure {
         if expected_io && self.io_format.is_none() {
             color_eyre::eyre::bail!("expected io-format");
         }
+        self.resources().validate()?;
         Ok(())
     }
 
@@ -230,17 +456,21 @@ impl DockerProcedure {
     ) -> Result<Result<O, (i32, String)>, Error> {
         let name = name.docker_name();
         let name: Option<&str> = name.as_ref().map(|x| &**x);
-        let mut cmd = tokio::process::Command::new("docker");
+        let mut cmd = runtime().command();
         let container_name = Self::container_name(pkg_id, name);
-        cmd.arg("run")
+        let network = crate::net::isolation::ensure_network(&ctx.docker, pkg_id).await?;
+        // created first, to be attached to the networks of its dependencies before it starts
+        cmd.arg("create")
             .arg("--rm")
-            .arg("--network=start9")
+            .arg(format!("--network={}", network))
             .arg(format!("--add-host=embassy:{}", Ipv4Addr::from(HOST_IP)))
             .arg("--name")
             .arg(&container_name)
             .arg(format!("--hostname={}", &container_name))
-            .arg("--no-healthcheck")
-            .kill_on_drop(true);
+            .arg("--no-healthcheck");
+        if let Some(dns) = ctx.net_controller.container_dns().await {
+            cmd.arg(format!("--dns={}", dns));
+        }
         match ctx
             .docker
             .remove_container(
@@ -261,6 +491,27 @@ impl DockerProcedure {
             Err(e) => Err(e),
         }?;
         cmd.args(self.docker_args(ctx, pkg_id, pkg_version, volumes).await?);
+        tracing::trace!(
+            "{}",
+            format!("{:?}", cmd)
+                .split(r#"" ""#)
+                .collect::<Vec<&str>>()
+                .join(" ")
+        );
+        cmd.invoke(crate::ErrorKind::Docker).await?;
+        let _networks = crate::net::isolation::ProcedureNetworks::attach(
+            &ctx.net_controller,
+            &ctx.docker,
+            pkg_id,
+            &container_name,
+        )
+        .await?;
+        let mut cmd = runtime().command();
+        cmd.arg("start")
+            .arg("--attach")
+            .arg("--interactive")
+            .arg(&container_name)
+            .kill_on_drop(true);
         let input_buf = if let (Some(input), Some(format)) = (&input, &self.io_format) {
             cmd.stdin(std::process::Stdio::piped());
             Some(format.to_vec(input)?)
@@ -269,13 +520,6 @@ impl DockerProcedure {
         };
         cmd.stdout(std::process::Stdio::piped());
         cmd.stderr(std::process::Stdio::piped());
-        tracing::trace!(
-            "{}",
-            format!("{:?}", cmd)
-                .split(r#"" ""#)
-                .collect::<Vec<&str>>()
-                .join(" ")
-        );
         let mut handle = cmd.spawn().with_kind(crate::ErrorKind::Docker)?;
         let id = handle.id();
         let timeout_fut = if let Some(timeout) = timeout {
@@ -374,6 +618,14 @@ impl DockerProcedure {
                     signal::kill(Pid::from_raw(id as i32), signal::SIGKILL)
                         .with_kind(crate::ErrorKind::Docker)?;
                 }
+                // it would keep its addresses on the networks of its dependencies unfiltered
+                if let Err(e) = ctx
+                    .docker
+                    .kill_container::<String>(&container_name, None)
+                    .await
+                {
+                    tracing::warn!("Failed to kill {}: {}", container_name, e);
+                }
                 return Ok(Err((143, "Timed out. Retrying soon...".to_owned())));
             }
         };
@@ -410,7 +662,7 @@ impl DockerProcedure {
     ) -> Result<Result<O, (i32, String)>, Error> {
         let name = name.docker_name();
         let name: Option<&str> = name.as_deref();
-        let mut cmd = tokio::process::Command::new("docker");
+        let mut cmd = runtime().command();
 
         cmd.arg("exec");
 
@@ -561,7 +813,7 @@ impl DockerProcedure {
         input: Option<I>,
         timeout: Option<Duration>,
     ) -> Result<Result<O, (i32, String)>, Error> {
-        let mut cmd = tokio::process::Command::new("docker");
+        let mut cmd = runtime().command();
         cmd.arg("run").arg("--rm").arg("--network=none");
         cmd.args(
             self.docker_args(ctx, pkg_id, pkg_version, &volumes.to_readonly())
@@ -714,32 +966,19 @@ impl DockerProcedure {
             res.push(OsStr::new("--shm-size").into());
             res.push(OsString::from(format!("{}m", shm_size_mb)).into());
         }
-        if self.gpu_acceleration {
-            fn get_devices<'a>(
-                path: &'a Path,
-                res: &'a mut Vec<PathBuf>,
-            ) -> BoxFuture<'a, Result<(), Error>> {
-                async move {
-                    let mut read_dir = tokio::fs::read_dir(path).await?;
-                    while let Some(entry) = read_dir.next_entry().await? {
-                        let fty = entry.metadata().await?.file_type();
-                        if fty.is_block_device() || fty.is_char_device() {
-                            res.push(entry.path());
-                        } else if fty.is_dir() {
-                            get_devices(&*entry.path(), res).await?;
-                        }
-                    }
-                    Ok(())
-                }
-                .boxed()
-            }
-            let mut devices = Vec::new();
-            get_devices(Path::new("/dev/dri"), &mut devices).await?;
-            for device in devices {
-                res.push(OsStr::new("--device").into());
-                res.push(OsString::from(device).into());
-            }
-        }
+        res.extend(
+            effective(ctx, pkg_id, self.resources())
+                .await?
+                .docker_args()
+                .into_iter()
+                .map(|a| OsString::from(a).into()),
+        );
+        res.extend(
+            crate::install::devices::docker_args(ctx, pkg_id, self.devices())
+                .await?
+                .into_iter()
+                .map(Cow::from),
+        );
         res.push(OsStr::new("--interactive").into());
         res.push(OsStr::new("--log-driver=journald").into());
         res.push(OsStr::new("--entrypoint").into());
@@ -826,7 +1065,7 @@ impl LongRunning {
         LongRunning::cleanup_previous_container(ctx, container_name).await?;
 
         let image_architecture = {
-            let mut cmd = tokio::process::Command::new("docker");
+            let mut cmd = runtime().command();
             cmd.arg("image")
                 .arg("inspect")
                 .arg("--format")
@@ -841,9 +1080,10 @@ impl LongRunning {
             arch.replace('\'', "").trim().to_string()
         };
 
-        let mut cmd = tokio::process::Command::new("docker");
+        let network = crate::net::isolation::ensure_network(&ctx.docker, pkg_id).await?;
+        let mut cmd = runtime().command();
         cmd.arg("run")
-            .arg("--network=start9")
+            .arg(format!("--network={}", network))
             .arg(format!("--add-host=embassy:{}", Ipv4Addr::from(HOST_IP)))
             .arg("--mount")
             .arg(format!(
@@ -862,6 +1102,9 @@ impl LongRunning {
             .arg("-i")
             .arg("--rm")
             .kill_on_drop(true);
+        if let Some(dns) = ctx.net_controller.container_dns().await {
+            cmd.arg(format!("--dns={}", dns));
+        }
 
         for (volume_id, dst) in &docker.mounts {
             let volume = if let Some(v) = volumes.get(volume_id) {
@@ -883,6 +1126,12 @@ impl LongRunning {
         if let Some(shm_size_mb) = docker.shm_size_mb {
             cmd.arg("--shm-size").arg(format!("{}m", shm_size_mb));
         }
+        cmd.args(
+            effective(ctx, pkg_id, docker.resources())
+                .await?
+                .docker_args(),
+        );
+        cmd.args(crate::install::devices::docker_args(ctx, pkg_id, docker.devices()).await?);
         cmd.arg("--log-driver=journald");
         if docker.system {
             cmd.arg(docker.image.for_package(&*SYSTEM_PACKAGE_ID, None));
@@ -1005,4 +1254,18 @@ mod tests {
         assert_eq!(CAPACITY_IN, ring.value.capacity());
         assert_eq!(CAPACITY_IN, ring.value.len());
     }
+    #[test]
+    fn podman_remote() {
+        let root = PodmanRuntime::new(Some(PodmanRuntime::ROOT_SOCKET.into()));
+        assert!(root.remote.is_none());
+        assert_eq!(root.services(), ["podman.socket"]);
+        let rootless = PodmanRuntime::new(Some("/run/user/1000/podman/podman.sock".into()));
+        assert_eq!(
+            rootless.socket(),
+            Path::new("/run/user/1000/podman/podman.sock")
+        );
+        assert!(rootless.services().is_empty());
+        assert!(rootless.rootless());
+        assert!(rootless.data_dir().is_none());
+    }
 }
@@ -6,7 +6,7 @@ use color_eyre::eyre::eyre;
 use embassy_container_init::{ProcessGroupId, SignalGroup, SignalGroupParams};
 use helpers::UnixRpcClient;
 pub use js_engine::JsError;
-use js_engine::{JsExecutionEnvironment, PathForVolumeId};
+use js_engine::{JsExecutionEnvironment, Notifier, PathForVolumeId};
 use models::{ErrorKind, VolumeId};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -14,6 +14,9 @@ use tracing::instrument;
 
 use super::ProcedureName;
 use crate::context::RpcContext;
+use crate::notifications::{
+    NotificationLevel, PACKAGE_NOTIFICATION_LIMIT, PACKAGE_NOTIFICATION_WINDOW,
+};
 use crate::s9pk::manifest::PackageId;
 use crate::util::{GeneralGuard, Version};
 use crate::volume::Volumes;
@@ -45,6 +48,49 @@ impl PathForVolumeId for Volumes {
     }
 }
 
+/// Lets a package's scripts raise notifications as that package, within its rate limit
+pub struct PackageNotifier {
+    pub ctx: RpcContext,
+    pub package_id: PackageId,
+}
+impl Notifier for PackageNotifier {
+    fn notify(&self, level: &str, title: String, message: String) -> Result<(), String> {
+        let level: NotificationLevel = level.parse().map_err(|e| format!("{}", e))?;
+        if !self
+            .ctx
+            .notification_manager
+            .take_package_quota(&self.package_id)
+        {
+            return Err(format!(
+                "Rate limit exceeded: at most {} notifications per {} minutes",
+                PACKAGE_NOTIFICATION_LIMIT,
+                PACKAGE_NOTIFICATION_WINDOW.as_secs() / 60
+            ));
+        }
+        let ctx = self.ctx.clone();
+        let package_id = self.package_id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = ctx
+                .notification_manager
+                .notify(
+                    &mut ctx.db.handle(),
+                    Some(package_id),
+                    level,
+                    title,
+                    message,
+                    (),
+                    None,
+                )
+                .await
+            {
+                tracing::error!("Failed to issue Notification: {}", e);
+                tracing::debug!("{:?}", e);
+            }
+        });
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct JsProcedure {
@@ -69,6 +115,7 @@ impl JsProcedure {
         timeout: Option<Duration>,
         gid: ProcessGroupId,
         rpc_client: Option<Arc<UnixRpcClient>>,
+        notifier: Option<Arc<dyn Notifier>>,
     ) -> Result<Result<O, (i32, String)>, Error> {
         let cleaner_client = rpc_client.clone();
         let cleaner = GeneralGuard::new(move || {
@@ -95,6 +142,7 @@ impl JsProcedure {
                 rpc_client,
             )
             .await?
+            .with_notifier(notifier)
             .run_action(name, input, self.args.clone());
             let output: Option<ErrorValue> = match timeout {
                 Some(timeout_duration) => tokio::time::timeout(timeout_duration, running_action)
@@ -212,6 +260,7 @@ async fn js_action_execute() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -269,6 +318,7 @@ async fn js_action_execute_error() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap();
@@ -315,6 +365,7 @@ async fn js_action_fetch() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -363,6 +414,7 @@ async fn js_test_slow() {
                 timeout,
                 ProcessGroupId(0),
                 None,
+                None,
             ) => { a.unwrap().unwrap(); },
         _ = tokio::time::sleep(Duration::from_secs(1)) => ()
     }
@@ -412,6 +464,7 @@ async fn js_action_var_arg() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -458,6 +511,7 @@ async fn js_action_test_rename() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -504,6 +558,7 @@ async fn js_action_test_deep_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -549,6 +604,7 @@ async fn js_action_test_deep_dir_escape() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -594,6 +650,7 @@ async fn js_action_test_zero_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -639,6 +696,7 @@ async fn js_action_test_read_dir() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -685,6 +743,7 @@ async fn js_rsync() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -731,6 +790,7 @@ async fn js_disk_usage() {
             timeout,
             ProcessGroupId(0),
             None,
+            None,
         )
         .await
         .unwrap()
@@ -13,7 +13,8 @@ pub fn display_properties(response: Value, _: &ArgMatches) {
     println!("{}", response);
 }
 
-#[command(display(display_properties))]
+/// Only for admins, as properties hold secrets such as credentials
+#[command(display(display_properties), metadata(read_only = true, admin = true))]
 pub async fn properties(#[context] ctx: RpcContext, #[arg] id: PackageId) -> Result<Value, Error> {
     Ok(fetch_properties(ctx, id).await?)
 }
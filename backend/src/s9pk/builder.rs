@@ -1,9 +1,12 @@
+use async_compression::tokio::bufread::ZstdEncoder;
 use sha2_old::{Digest, Sha512};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::io::{
+    AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt, BufReader, SeekFrom,
+};
 use tracing::instrument;
 use typed_builder::TypedBuilder;
 
-use super::header::{FileSection, Header};
+use super::header::{FileSection, Header, VERSION_ZSTD};
 use super::manifest::Manifest;
 use super::SIG_CONTEXT;
 use crate::util::io::to_cbor_async_writer;
@@ -29,7 +32,23 @@ pub struct S9pkPacker<
     docker_images: RDockerImages,
     assets: RAssets,
     scripts: Option<RScripts>,
+    /// Compress the docker images and assets, which only newer versions of the OS can read
+    #[builder(default)]
+    compress: bool,
 }
+
+async fn copy_section<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    src: &mut R,
+    dst: &mut W,
+    compress: bool,
+) -> std::io::Result<u64> {
+    if compress {
+        tokio::io::copy(&mut ZstdEncoder::new(BufReader::new(src)), dst).await
+    } else {
+        tokio::io::copy(src, dst).await
+    }
+}
+
 impl<
         'a,
         W: AsyncWriteExt + AsyncSeekExt + Unpin,
@@ -97,7 +116,7 @@ impl<
         };
         position = new_pos;
         // docker_images
-        tokio::io::copy(&mut self.docker_images, &mut writer)
+        copy_section(&mut self.docker_images, &mut writer, self.compress)
             .await
             .with_ctx(|_| (crate::ErrorKind::Filesystem, "Copying Docker Images"))?;
         let new_pos = writer.inner_mut().stream_position().await?;
@@ -107,7 +126,7 @@ impl<
         };
         position = new_pos;
         // assets
-        tokio::io::copy(&mut self.assets, &mut writer)
+        copy_section(&mut self.assets, &mut writer, self.compress)
             .await
             .with_ctx(|_| (crate::ErrorKind::Filesystem, "Copying Assets"))?;
         let new_pos = writer.inner_mut().stream_position().await?;
@@ -132,6 +151,9 @@ impl<
         // header
         let (hash, _) = writer.finish();
         self.writer.seek(SeekFrom::Start(header_pos)).await?;
+        if self.compress {
+            header.version = VERSION_ZSTD;
+        }
         header.pubkey = key.public.clone();
         header.signature = key.sign_prehashed(hash, Some(SIG_CONTEXT))?;
         header
@@ -1,12 +1,11 @@
 use std::borrow::Cow;
 use std::collections::BTreeSet;
-use std::io::SeekFrom;
 use std::path::Path;
 
 use color_eyre::eyre::eyre;
 use futures::{FutureExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncRead, AsyncSeek, AsyncSeekExt};
+use tokio::io::AsyncRead;
 use tokio_tar::{Archive, Entry};
 
 use crate::util::io::from_cbor_async_reader;
@@ -25,9 +24,12 @@ pub enum DockerReader<R: AsyncRead + Unpin> {
     SingleArch(#[pin] R),
     MultiArch(#[pin] Entry<Archive<R>>),
 }
-impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> DockerReader<R> {
-    pub async fn new(mut rdr: R) -> Result<Self, Error> {
-        let arch = if let Some(multiarch) = tokio_tar::Archive::new(&mut rdr)
+/// The architectures of a fat docker image section, or `None` if it only holds one
+pub async fn read_multiarch<R: AsyncRead + Unpin>(
+    rdr: &mut R,
+) -> Result<Option<DockerMultiArch>, Error> {
+    Ok(
+        if let Some(multiarch) = tokio_tar::Archive::new(rdr)
             .entries()?
             .try_filter_map(|e| {
                 async move {
@@ -42,16 +44,23 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> DockerReader<R> {
             .try_next()
             .await?
         {
-            let multiarch: DockerMultiArch = from_cbor_async_reader(multiarch).await?;
-            Some(if multiarch.available.contains(&**ARCH) {
+            Some(from_cbor_async_reader(multiarch).await?)
+        } else {
+            None
+        },
+    )
+}
+
+impl<R: AsyncRead + Unpin> DockerReader<R> {
+    /// `multiarch` is what [read_multiarch] found in a previous read of `rdr`
+    pub async fn new(rdr: R, multiarch: Option<DockerMultiArch>) -> Result<Self, Error> {
+        let arch = multiarch.map(|multiarch| {
+            if multiarch.available.contains(&**ARCH) {
                 Cow::Borrowed(&**ARCH)
             } else {
                 Cow::Owned(multiarch.default)
-            })
-        } else {
-            None
-        };
-        rdr.seek(SeekFrom::Start(0)).await?;
+            }
+        });
         if let Some(arch) = arch {
             if let Some(image) = tokio_tar::Archive::new(rdr)
                 .entries()?
@@ -81,7 +90,7 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> DockerReader<R> {
         }
     }
 }
-impl<R: AsyncRead + Unpin + Send + Sync> AsyncRead for DockerReader<R> {
+impl<R: AsyncRead + Unpin> AsyncRead for DockerReader<R> {
     fn poll_read(
         self: std::pin::Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
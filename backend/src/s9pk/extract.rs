@@ -0,0 +1,137 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
+use futures::TryStreamExt;
+use rpc_toolkit::command;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use crate::s9pk::reader::S9pkReader;
+use crate::util::display_none;
+use crate::util::serde::IoFormat;
+use crate::{Error, ErrorKind};
+
+/// A part of an s9pk, named the way it is laid out when packed
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Manifest,
+    License,
+    Instructions,
+    Icon,
+    DockerImages,
+    Assets,
+    /// A single file from the assets tarball
+    Asset(PathBuf),
+    Scripts,
+}
+impl std::str::FromStr for Entry {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim_start_matches("./") {
+            "manifest" | "manifest.json" => Entry::Manifest,
+            "LICENSE" | "LICENSE.md" => Entry::License,
+            "instructions" | "INSTRUCTIONS.md" => Entry::Instructions,
+            s if s == "icon" || s.starts_with("icon.") => Entry::Icon,
+            "docker-images" | "image.tar" => Entry::DockerImages,
+            "assets" | "assets.tar" => Entry::Assets,
+            "scripts" | "scripts/embassy.js" => Entry::Scripts,
+            s if s.starts_with("assets/") => {
+                Entry::Asset(Path::new(s.trim_start_matches("assets/")).to_owned())
+            }
+            _ => {
+                return Err(Error::new(
+                    eyre!("Unknown entry {}. Must be one of manifest.json, LICENSE.md, INSTRUCTIONS.md, icon, image.tar, assets.tar, assets/<path>, scripts/embassy.js.", s),
+                    ErrorKind::InvalidRequest,
+                ))
+            }
+        })
+    }
+}
+
+async fn copy_to<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
+    mut src: R,
+    dst: &mut W,
+) -> Result<(), Error> {
+    tokio::io::copy(&mut src, dst).await?;
+    Ok(())
+}
+
+async fn extract_to<W: AsyncWrite + Unpin>(
+    rdr: &mut S9pkReader,
+    entry: &Entry,
+    dst: &mut W,
+) -> Result<(), Error> {
+    match entry {
+        Entry::Manifest => {
+            dst.write_all(&IoFormat::JsonPretty.to_vec(&rdr.manifest().await?)?)
+                .await?
+        }
+        Entry::License => copy_to(rdr.license().await?, dst).await?,
+        Entry::Instructions => copy_to(rdr.instructions().await?, dst).await?,
+        Entry::Icon => copy_to(rdr.icon().await?, dst).await?,
+        Entry::DockerImages => copy_to(rdr.docker_images().await?, dst).await?,
+        Entry::Assets => copy_to(rdr.assets().await?, dst).await?,
+        Entry::Asset(path) => {
+            let mut tar = tokio_tar::Archive::new(rdr.assets().await?);
+            let mut entries = tar.entries()?;
+            let mut found = false;
+            while let Some(file) = entries.try_next().await? {
+                let file_path = file.path()?;
+                // tarballs packed from `.` prefix every path with it
+                if file_path.strip_prefix(".").unwrap_or(&file_path) == path.as_path() {
+                    drop(file_path);
+                    copy_to(file, dst).await?;
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Err(Error::new(
+                    eyre!("assets/{} is not in the package", path.display()),
+                    ErrorKind::NotFound,
+                ));
+            }
+        }
+        Entry::Scripts => match rdr.scripts().await? {
+            Some(scripts) => copy_to(scripts, dst).await?,
+            None => {
+                return Err(Error::new(
+                    eyre!("Package has no scripts"),
+                    ErrorKind::NotFound,
+                ))
+            }
+        },
+    }
+    dst.flush().await?;
+    Ok(())
+}
+
+/// Writes one part of an s9pk to stdout, or to `output`, without installing it
+#[command(cli_only, display(display_none))]
+pub async fn extract(
+    #[arg] path: PathBuf,
+    #[arg] entry: String,
+    #[arg(short = 'o', long = "output")] output: Option<PathBuf>,
+    #[arg(rename = "no-verify", long = "no-verify")] no_verify: bool,
+) -> Result<(), Error> {
+    let entry: Entry = entry.parse()?;
+    let mut rdr = S9pkReader::open(path, !no_verify).await?;
+    if let Some(output) = output {
+        let mut file = tokio::fs::File::create(&output).await?;
+        extract_to(&mut rdr, &entry, &mut file).await?;
+        file.sync_all().await?;
+    } else {
+        extract_to(&mut rdr, &entry, &mut tokio::io::stdout()).await?;
+    }
+    Ok(())
+}
+
+#[test]
+fn entries() {
+    assert_eq!("manifest.json".parse::<Entry>().unwrap(), Entry::Manifest);
+    assert_eq!("icon.png".parse::<Entry>().unwrap(), Entry::Icon);
+    assert_eq!(
+        "./assets/config/nginx.conf".parse::<Entry>().unwrap(),
+        Entry::Asset(PathBuf::from("config/nginx.conf"))
+    );
+    assert!("volumes".parse::<Entry>().is_err());
+}
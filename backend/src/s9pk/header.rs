@@ -8,9 +8,12 @@ use crate::Error;
 
 pub const MAGIC: [u8; 2] = [59, 59];
 pub const VERSION: u8 = 1;
+/// Like [VERSION], but the docker images and assets sections are zstd compressed
+pub const VERSION_ZSTD: u8 = 2;
 
 #[derive(Debug)]
 pub struct Header {
+    pub version: u8,
     pub pubkey: PublicKey,
     pub signature: Signature,
     pub table_of_contents: TableOfContents,
@@ -18,6 +21,7 @@ pub struct Header {
 impl Header {
     pub fn placeholder() -> Self {
         Header {
+            version: VERSION,
             pubkey: PublicKey::default(),
             signature: Signature::from_bytes(&[0; 64]).expect("Invalid ed25519 signature"),
             table_of_contents: Default::default(),
@@ -26,7 +30,7 @@ impl Header {
     // MUST BE SAME SIZE REGARDLESS OF DATA
     pub async fn serialize<W: AsyncWriteExt + Unpin>(&self, mut writer: W) -> std::io::Result<()> {
         writer.write_all(&MAGIC).await?;
-        writer.write_all(&[VERSION]).await?;
+        writer.write_all(&[self.version]).await?;
         writer.write_all(self.pubkey.as_bytes()).await?;
         writer.write_all(self.signature.as_ref()).await?;
         self.table_of_contents.serialize(writer).await?;
@@ -43,7 +47,7 @@ impl Header {
         }
         let mut version = [0];
         reader.read_exact(&mut version).await?;
-        if version[0] != VERSION {
+        if version[0] != VERSION && version[0] != VERSION_ZSTD {
             return Err(Error::new(
                 eyre!("Unknown Version: {}", version[0]),
                 crate::ErrorKind::ParseS9pk,
@@ -59,6 +63,7 @@ impl Header {
         let table_of_contents = TableOfContents::deserialize(reader).await?;
 
         Ok(Header {
+            version: version[0],
             pubkey,
             signature,
             table_of_contents,
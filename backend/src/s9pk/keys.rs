@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use clap::ArgMatches;
+use color_eyre::eyre::eyre;
+use ed25519_dalek::PublicKey;
+use rpc_toolkit::command;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use crate::context::RpcContext;
+use crate::util::display_none;
+use crate::util::serde::{display_serializable, IoFormat};
+use crate::{Error, ErrorKind};
+
+pub(crate) fn encode_key(key: &PublicKey) -> String {
+    base32::encode(base32::Alphabet::RFC4648 { padding: true }, key.as_bytes())
+}
+
+/// Parses a developer key the way it is shown for installed packages
+pub(crate) fn parse_key(key: &str) -> Result<PublicKey, Error> {
+    let bytes =
+        base32::decode(base32::Alphabet::RFC4648 { padding: true }, key).ok_or_else(|| {
+            Error::new(
+                eyre!("Developer key must be an RFC4648 base32 string"),
+                ErrorKind::InvalidSignature,
+            )
+        })?;
+    PublicKey::from_bytes(&bytes).map_err(|e| Error::new(e, ErrorKind::InvalidSignature))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TrustedKey {
+    pub key: String,
+    pub name: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[command(subcommands(add, list, remove))]
+pub fn keys() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Trusts packages signed with `key` for sideloading
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn add(
+    #[context] ctx: RpcContext,
+    #[arg] key: String,
+    #[arg(long = "name")] name: Option<String>,
+) -> Result<(), Error> {
+    let key = encode_key(&parse_key(&key)?);
+    let added = sqlx::query!(
+        "INSERT INTO trusted_developer_keys (pubkey, name) VALUES ($1, $2) ON CONFLICT (pubkey) DO NOTHING",
+        key,
+        name,
+    )
+    .execute(&ctx.secret_store)
+    .await?
+    .rows_affected();
+    if added == 0 {
+        return Err(Error::new(
+            eyre!("Developer key is already trusted"),
+            ErrorKind::Duplicate,
+        ));
+    }
+    Ok(())
+}
+
+fn display_keys(arg: Vec<TrustedKey>, matches: &ArgMatches) {
+    use prettytable::*;
+
+    if matches.is_present("format") {
+        return display_serializable(arg, matches);
+    }
+
+    let mut table = Table::new();
+    table.add_row(row![bc => "KEY", "NAME", "ADDED AT"]);
+    for key in arg {
+        table.add_row(row![
+            &key.key,
+            key.name.as_deref().unwrap_or("N/A"),
+            key.created_at.to_rfc3339(),
+        ]);
+    }
+    table.print_tty(false).unwrap();
+}
+
+#[command(display(display_keys), metadata(read_only = true))]
+#[instrument(skip_all)]
+pub async fn list(
+    #[context] ctx: RpcContext,
+    #[allow(unused_variables)]
+    #[arg(long = "format")]
+    format: Option<IoFormat>,
+) -> Result<Vec<TrustedKey>, Error> {
+    Ok(sqlx::query!(
+        "SELECT pubkey, name, created_at FROM trusted_developer_keys ORDER BY created_at"
+    )
+    .fetch_all(&ctx.secret_store)
+    .await?
+    .into_iter()
+    .map(|r| TrustedKey {
+        key: r.pubkey,
+        name: r.name,
+        created_at: DateTime::from_utc(r.created_at, Utc),
+    })
+    .collect())
+}
+
+/// Stops trusting `key`. Packages it signed stay installed.
+#[command(display(display_none), metadata(admin = true))]
+#[instrument(skip_all)]
+pub async fn remove(#[context] ctx: RpcContext, #[arg] key: String) -> Result<(), Error> {
+    let key = encode_key(&parse_key(&key)?);
+    let removed = sqlx::query!("DELETE FROM trusted_developer_keys WHERE pubkey = $1", key)
+        .execute(&ctx.secret_store)
+        .await?
+        .rows_affected();
+    if removed == 0 {
+        return Err(Error::new(
+            eyre!("Developer key is not trusted"),
+            ErrorKind::NotFound,
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless `key` has been added to the trusted developer keys
+#[instrument(skip_all)]
+pub async fn ensure_trusted(secrets: &PgPool, key: &PublicKey) -> Result<(), Error> {
+    let encoded = encode_key(key);
+    if sqlx::query!(
+        "SELECT EXISTS (SELECT 1 FROM trusted_developer_keys WHERE pubkey = $1) AS \"trusted!\"",
+        encoded
+    )
+    .fetch_one(secrets)
+    .await?
+    .trusted
+    {
+        Ok(())
+    } else {
+        Err(Error::new(
+            eyre!(
+                "Package is signed by unknown developer key {}. Trust it with `package keys add {}` to install it.",
+                encoded,
+                encoded
+            ),
+            ErrorKind::InvalidSignature,
+        ))
+    }
+}
+
+#[test]
+fn key_encoding() {
+    // the ed25519 base point
+    let key = "LBTGMZTGMZTGMZTGMZTGMZTGMZTGMZTGMZTGMZTGMZTGMZTGMZTA====";
+    assert_eq!(encode_key(&parse_key(key).unwrap()), key);
+    assert!(parse_key("not a key").is_err());
+}
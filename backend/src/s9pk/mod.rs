@@ -23,8 +23,10 @@ use crate::{Error, ErrorKind, ResultExt};
 
 pub mod builder;
 pub mod docker;
+pub mod extract;
 pub mod git_hash;
 pub mod header;
+pub mod keys;
 pub mod manifest;
 pub mod reader;
 
@@ -32,7 +34,15 @@ pub const SIG_CONTEXT: &'static [u8] = b"s9pk";
 
 #[command(cli_only, display(display_none))]
 #[instrument(skip_all)]
-pub async fn pack(#[context] ctx: SdkContext, #[arg] path: Option<PathBuf>) -> Result<(), Error> {
+pub async fn pack(
+    #[context] ctx: SdkContext,
+    #[arg] path: Option<PathBuf>,
+    #[arg(
+        long = "compress",
+        help = "Compress images and assets with zstd. Older versions of embassyOS cannot install the result"
+    )]
+    compress: bool,
+) -> Result<(), Error> {
     use tokio::fs::File;
 
     let path = if let Some(path) = path {
@@ -187,6 +197,7 @@ pub async fn pack(#[context] ctx: SdkContext, #[arg] path: Option<PathBuf>) -> R
                 (false, false) => None
             }
         })
+        .compress(compress)
         .build()
         .pack(&ctx.developer_key()?)
         .await?;
@@ -1,4 +1,5 @@
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsStr;
 use std::io::SeekFrom;
 use std::ops::Range;
 use std::path::Path;
@@ -6,6 +7,7 @@ use std::pin::Pin;
 use std::str::FromStr;
 use std::task::{Context, Poll};
 
+use async_compression::tokio::bufread::ZstdDecoder;
 use color_eyre::eyre::eyre;
 use digest_old::Output;
 use ed25519_dalek::PublicKey;
@@ -13,14 +15,14 @@ use futures::TryStreamExt;
 use models::ImageId;
 use sha2_old::{Digest, Sha512};
 use tokio::fs::File;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, ReadBuf};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, BufReader, ReadBuf};
 use tracing::instrument;
 
-use super::header::{FileSection, Header, TableOfContents};
+use super::header::{FileSection, Header, TableOfContents, VERSION_ZSTD};
 use super::manifest::{Manifest, PackageId};
 use super::SIG_CONTEXT;
 use crate::install::progress::InstallProgressTracker;
-use crate::s9pk::docker::DockerReader;
+use crate::s9pk::docker::{read_multiarch, DockerMultiArch, DockerReader};
 use crate::util::Version;
 use crate::{Error, ResultExt};
 
@@ -84,6 +86,54 @@ impl<'a, R: AsyncSeek + Unpin> AsyncSeek for ReadHandle<'a, R> {
     }
 }
 
+/// Reads the tags in the `manifest.json` of a `docker save` tarball
+async fn read_image_tags<R: AsyncRead + Unpin>(rdr: R) -> Result<Vec<ImageTag>, Error> {
+    let mut tar = tokio_tar::Archive::new(rdr);
+    let mut entries = tar.entries()?;
+    while let Some(mut entry) = entries.try_next().await? {
+        if &*entry.path()? != Path::new("manifest.json") {
+            continue;
+        }
+        let mut buf = Vec::with_capacity(entry.header().size()? as usize);
+        entry.read_to_end(&mut buf).await?;
+        #[derive(serde::Deserialize)]
+        struct ManEntry {
+            #[serde(rename = "RepoTags")]
+            tags: Vec<String>,
+        }
+        let man_entries = serde_json::from_slice::<Vec<ManEntry>>(&buf)
+            .with_ctx(|_| (crate::ErrorKind::Deserialization, "manifest.json"))?;
+        return man_entries
+            .iter()
+            .flat_map(|e| &e.tags)
+            .map(|t| t.parse())
+            .collect();
+    }
+    Err(Error::new(
+        eyre!("image.tar missing manifest.json"),
+        crate::ErrorKind::ParseS9pk,
+    ))
+}
+
+/// A section of an s9pk, decompressed as it is read if the s9pk is compressed
+#[pin_project::pin_project(project = SectionReaderProject)]
+pub enum SectionReader<'a, R = File> {
+    Plain(#[pin] ReadHandle<'a, R>),
+    Zstd(#[pin] ZstdDecoder<BufReader<ReadHandle<'a, R>>>),
+}
+impl<'a, R: AsyncRead + Unpin> AsyncRead for SectionReader<'a, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.project() {
+            SectionReaderProject::Plain(r) => r.poll_read(cx, buf),
+            SectionReaderProject::Zstd(r) => r.poll_read(cx, buf),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ImageTag {
     pub package_id: PackageId,
@@ -148,6 +198,8 @@ pub struct S9pkReader<R: AsyncRead + AsyncSeek + Unpin + Send + Sync = File> {
     hash: Option<Output<Sha512>>,
     hash_string: Option<String>,
     developer_key: PublicKey,
+    /// The docker images and assets are zstd compressed
+    compressed: bool,
     toc: TableOfContents,
     pos: u64,
     rdr: R,
@@ -184,6 +236,30 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
             .into_iter()
             .map(|i| i.validate(&man.id, &man.version).map(|_| i.image_id))
             .collect::<Result<BTreeSet<ImageId>, _>>()?;
+        if let Some(multiarch) = self.architectures().await? {
+            if !multiarch.available.contains(&multiarch.default) {
+                return Err(Error::new(
+                    eyre!("Default architecture {} has no images", multiarch.default),
+                    crate::ErrorKind::ValidateS9pk,
+                ));
+            }
+            for (arch, tags) in self.image_tags_by_arch().await? {
+                if tags
+                    .into_iter()
+                    .map(|t| t.image_id)
+                    .collect::<BTreeSet<_>>()
+                    != validated_image_ids
+                {
+                    return Err(Error::new(
+                        eyre!(
+                            "Images for {} do not match those of the other architectures",
+                            arch
+                        ),
+                        crate::ErrorKind::ValidateS9pk,
+                    ));
+                }
+            }
+        }
         man.description.validate()?;
         man.actions
             .0
@@ -227,6 +303,13 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
                 false,
             )
             .with_ctx(|_| (crate::ErrorKind::ValidateS9pk, "Main"))?;
+        if let Some(containers) = containers {
+            containers
+                .main
+                .resources()
+                .validate()
+                .with_ctx(|_| (crate::ErrorKind::ValidateS9pk, "Containers"))?;
+        }
         man.migrations.validate(
             containers,
             &man.eos_version,
@@ -288,6 +371,35 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
     }
     #[instrument(skip_all)]
     pub async fn image_tags(&mut self) -> Result<Vec<ImageTag>, Error> {
+        read_image_tags(self.docker_images().await?).await
+    }
+    /// `None` unless the package has images for several architectures
+    pub async fn architectures(&mut self) -> Result<Option<DockerMultiArch>, Error> {
+        read_multiarch(&mut self.section(self.toc.docker_images).await?).await
+    }
+    /// The image tags of each architecture of a fat package
+    #[instrument(skip_all)]
+    pub async fn image_tags_by_arch(&mut self) -> Result<BTreeMap<String, Vec<ImageTag>>, Error> {
+        let mut tar = tokio_tar::Archive::new(self.section(self.toc.docker_images).await?);
+        let mut entries = tar.entries()?;
+        let mut by_arch = BTreeMap::new();
+        while let Some(entry) = entries.try_next().await? {
+            let path = entry.path()?.into_owned();
+            if path.extension() != Some(OsStr::new("tar")) {
+                continue;
+            }
+            let arch = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_owned();
+            by_arch.insert(arch, read_image_tags(entry).await?);
+        }
+        Ok(by_arch)
+    }
+    /// The docker image id of each image tag, from the config digests in `manifest.json`
+    #[instrument(skip_all)]
+    pub async fn image_ids(&mut self) -> Result<BTreeMap<String, String>, Error> {
         let mut tar = tokio_tar::Archive::new(self.docker_images().await?);
         let mut entries = tar.entries()?;
         while let Some(mut entry) = entries.try_next().await? {
@@ -298,16 +410,22 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
             entry.read_to_end(&mut buf).await?;
             #[derive(serde::Deserialize)]
             struct ManEntry {
+                #[serde(rename = "Config")]
+                config: String,
                 #[serde(rename = "RepoTags")]
                 tags: Vec<String>,
             }
             let man_entries = serde_json::from_slice::<Vec<ManEntry>>(&buf)
                 .with_ctx(|_| (crate::ErrorKind::Deserialization, "manifest.json"))?;
-            return man_entries
-                .iter()
-                .flat_map(|e| &e.tags)
-                .map(|t| t.parse())
-                .collect();
+            return Ok(man_entries
+                .into_iter()
+                .flat_map(|e| {
+                    // `<digest>.json`, or `blobs/sha256/<digest>` for OCI layouts
+                    let digest = e.config.rsplit('/').next().unwrap_or_default();
+                    let id = format!("sha256:{}", digest.trim_end_matches(".json"));
+                    e.tags.into_iter().map(move |t| (t, id.clone()))
+                })
+                .collect());
         }
         Err(Error::new(
             eyre!("image.tar missing manifest.json"),
@@ -349,6 +467,7 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
             hash_string,
             hash,
             developer_key: header.pubkey,
+            compressed: header.version == VERSION_ZSTD,
             toc: header.table_of_contents,
             pos,
             rdr,
@@ -409,12 +528,28 @@ impl<R: AsyncRead + AsyncSeek + Unpin + Send + Sync> S9pkReader<R> {
         Ok(self.read_handle(self.toc.icon).await?)
     }
 
-    pub async fn docker_images<'a>(&'a mut self) -> Result<DockerReader<ReadHandle<'a, R>>, Error> {
-        DockerReader::new(self.read_handle(self.toc.docker_images).await?).await
+    async fn section<'a>(
+        &'a mut self,
+        section: FileSection,
+    ) -> Result<SectionReader<'a, R>, Error> {
+        let compressed = self.compressed;
+        let rdr = self.read_handle(section).await?;
+        Ok(if compressed {
+            SectionReader::Zstd(ZstdDecoder::new(BufReader::new(rdr)))
+        } else {
+            SectionReader::Plain(rdr)
+        })
+    }
+
+    pub async fn docker_images<'a>(
+        &'a mut self,
+    ) -> Result<DockerReader<SectionReader<'a, R>>, Error> {
+        let multiarch = self.architectures().await?;
+        DockerReader::new(self.section(self.toc.docker_images).await?, multiarch).await
     }
 
-    pub async fn assets<'a>(&'a mut self) -> Result<ReadHandle<'a, R>, Error> {
-        Ok(self.read_handle(self.toc.assets).await?)
+    pub async fn assets<'a>(&'a mut self) -> Result<SectionReader<'a, R>, Error> {
+        self.section(self.toc.assets).await
     }
 
     pub async fn scripts<'a>(&'a mut self) -> Result<Option<ReadHandle<'a, R>>, Error> {
@@ -18,6 +18,7 @@ use torut::onion::OnionAddressV3;
 use tracing::instrument;
 
 use crate::account::AccountInfo;
+use crate::auth::recovery::generate_codes;
 use crate::backup::restore::recover_full_embassy;
 use crate::backup::target::BackupTargetFS;
 use crate::context::rpc::RpcContextConfig;
@@ -147,6 +148,7 @@ pub async fn attach(
                 tor_address: format!("https://{}", tor_addr),
                 lan_address: hostname.lan_address(),
                 root_ca: String::from_utf8(root_ca.to_pem()?)?,
+                recovery_codes: None,
             }));
             *ctx.setup_status.write().await = Some(Ok(SetupStatus {
                 bytes_transferred: 0,
@@ -278,6 +280,21 @@ pub async fn execute(
         {
             Ok((guid, hostname, tor_addr, root_ca)) => {
                 tracing::info!("Setup Complete!");
+                let recovery_codes = match async {
+                    let secret_store = ctx.secret_store().await?;
+                    let codes = generate_codes(&secret_store).await?;
+                    secret_store.close().await;
+                    Ok::<_, Error>(codes)
+                }
+                .await
+                {
+                    Ok(a) => Some(a),
+                    Err(e) => {
+                        tracing::error!("Error Generating Recovery Codes: {}", e);
+                        tracing::debug!("{:?}", e);
+                        None
+                    }
+                };
                 *ctx.setup_result.write().await = Some((
                     guid,
                     SetupResult {
@@ -287,6 +304,7 @@ pub async fn execute(
                             root_ca.to_pem().expect("failed to serialize root ca"),
                         )
                         .expect("invalid pem string"),
+                        recovery_codes,
                     },
                 ));
                 *ctx.setup_status.write().await = Some(Ok(SetupStatus {
@@ -6,6 +6,7 @@ use rpc_toolkit::command;
 use crate::context::RpcContext;
 use crate::disk::main::export;
 use crate::init::{STANDBY_MODE_PATH, SYSTEM_REBUILD_PATH};
+use crate::procedure::docker::runtime;
 use crate::sound::SHUTDOWN;
 use crate::util::{display_none, Invoke};
 use crate::{Error, ErrorKind, OS_ARCH};
@@ -43,14 +44,16 @@ impl Shutdown {
                 tracing::error!("Error Stopping Journald: {}", e);
                 tracing::debug!("{:?}", e);
             }
-            if let Err(e) = Command::new("systemctl")
-                .arg("stop")
-                .arg("docker")
-                .invoke(crate::ErrorKind::Docker)
-                .await
-            {
-                tracing::error!("Error Stopping Docker: {}", e);
-                tracing::debug!("{:?}", e);
+            for service in runtime().services() {
+                if let Err(e) = Command::new("systemctl")
+                    .arg("stop")
+                    .arg(service)
+                    .invoke(crate::ErrorKind::Docker)
+                    .await
+                {
+                    tracing::error!("Error Stopping {}: {}", service, e);
+                    tracing::debug!("{:?}", e);
+                }
             }
             if let Some(guid) = &self.disk_guid {
                 if let Err(e) = export(guid, &self.datadir).await {
@@ -88,7 +91,7 @@ impl Shutdown {
     }
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn shutdown(#[context] ctx: RpcContext) -> Result<(), Error> {
     ctx.shutdown
         .send(Some(Shutdown {
@@ -101,7 +104,7 @@ pub async fn shutdown(#[context] ctx: RpcContext) -> Result<(), Error> {
     Ok(())
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn restart(#[context] ctx: RpcContext) -> Result<(), Error> {
     ctx.shutdown
         .send(Some(Shutdown {
@@ -114,7 +117,7 @@ pub async fn restart(#[context] ctx: RpcContext) -> Result<(), Error> {
     Ok(())
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn rebuild(#[context] ctx: RpcContext) -> Result<(), Error> {
     tokio::fs::write(SYSTEM_REBUILD_PATH, b"").await?;
     restart(ctx).await
@@ -56,7 +56,7 @@ pub fn ssh() -> Result<(), Error> {
     Ok(())
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 #[instrument(skip_all)]
 pub async fn add(#[context] ctx: RpcContext, #[arg] key: PubKey) -> Result<SshKeyResponse, Error> {
     let pool = &ctx.secret_store;
@@ -91,7 +91,7 @@ pub async fn add(#[context] ctx: RpcContext, #[arg] key: PubKey) -> Result<SshKe
         Some(_) => Err(Error::new(eyre!("Duplicate ssh key"), ErrorKind::Duplicate)),
     }
 }
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 #[instrument(skip_all)]
 pub async fn delete(#[context] ctx: RpcContext, #[arg] fingerprint: String) -> Result<(), Error> {
     let pool = &ctx.secret_store;
@@ -141,7 +141,7 @@ fn display_all_ssh_keys(all: Vec<SshKeyResponse>, matches: &ArgMatches) {
     table.print_tty(false).unwrap();
 }
 
-#[command(display(display_all_ssh_keys))]
+#[command(display(display_all_ssh_keys), metadata(read_only = true))]
 #[instrument(skip_all)]
 pub async fn list(
     #[context] ctx: RpcContext,
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
 use chrono::Utc;
@@ -57,7 +58,7 @@ pub async fn enable_zram() -> Result<(), Error> {
     Ok(())
 }
 
-#[command(display(display_none))]
+#[command(display(display_none), metadata(admin = true))]
 pub async fn zram(#[context] ctx: RpcContext, #[arg] enable: bool) -> Result<(), Error> {
     let mut db = ctx.db.handle();
     let mut zram = crate::db::DatabaseModel::new()
@@ -84,7 +85,7 @@ pub async fn zram(#[context] ctx: RpcContext, #[arg] enable: bool) -> Result<(),
     Ok(())
 }
 
-#[command]
+#[command(metadata(read_only = true))]
 pub async fn time() -> Result<String, Error> {
     Ok(Utc::now().to_rfc3339())
 }
@@ -92,7 +93,8 @@ pub async fn time() -> Result<String, Error> {
 #[command(
     custom_cli(cli_logs(async, context(CliContext))),
     subcommands(self(logs_nofollow(async)), logs_follow),
-    display(display_none)
+    display(display_none),
+    metadata(read_only = true)
 )]
 pub async fn logs(
     #[arg(short = 'l', long = "limit")] limit: Option<usize>,
@@ -131,7 +133,12 @@ pub async fn logs_nofollow(
     fetch_logs(LogSource::Service(SYSTEMD_UNIT), limit, cursor, before).await
 }
 
-#[command(rpc_only, rename = "follow", display(display_none))]
+#[command(
+    rpc_only,
+    rename = "follow",
+    display(display_none),
+    metadata(read_only = true)
+)]
 pub async fn logs_follow(
     #[context] ctx: RpcContext,
     #[parent_data] (limit, _, _, _): (Option<usize>, Option<String>, bool, bool),
@@ -143,7 +150,8 @@ pub async fn logs_follow(
     rename = "kernel-logs",
     custom_cli(cli_kernel_logs(async, context(CliContext))),
     subcommands(self(kernel_logs_nofollow(async)), kernel_logs_follow),
-    display(display_none)
+    display(display_none),
+    metadata(read_only = true)
 )]
 pub async fn kernel_logs(
     #[arg(short = 'l', long = "limit")] limit: Option<usize>,
@@ -182,7 +190,12 @@ pub async fn kernel_logs_nofollow(
     fetch_logs(LogSource::Kernel, limit, cursor, before).await
 }
 
-#[command(rpc_only, rename = "follow", display(display_none))]
+#[command(
+    rpc_only,
+    rename = "follow",
+    display(display_none),
+    metadata(read_only = true)
+)]
 pub async fn kernel_logs_follow(
     #[context] ctx: RpcContext,
     #[parent_data] (limit, _, _, _): (Option<usize>, Option<String>, bool, bool),
@@ -358,9 +371,13 @@ pub struct Metrics {
     cpu: MetricsCpu,
     #[serde(rename = "Disk")]
     disk: MetricsDisk,
+    /// By package, see `net bandwidth`
+    #[serde(rename = "Bandwidth (30 Days)")]
+    #[serde(default)]
+    pub bandwidth: BTreeMap<String, MebiBytes>,
 }
 
-#[command(display(display_serializable))]
+#[command(display(display_serializable), metadata(read_only = true))]
 pub async fn metrics(
     #[context] ctx: RpcContext,
     #[allow(unused_variables)]
@@ -454,6 +471,7 @@ pub async fn launch_metrics_task<F: FnMut() -> Receiver<Option<Shutdown>>>(
             memory: init_mem,
             cpu: init_cpu,
             disk: init_disk,
+            bandwidth: BTreeMap::new(),
         })
     }
 
@@ -355,6 +355,9 @@ impl<T> BackTrackingReader<T> {
     pub fn unwrap(self) -> T {
         self.reader
     }
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.reader
+    }
 }
 
 impl<T: AsyncRead> AsyncRead for BackTrackingReader<T> {
@@ -611,6 +614,11 @@ impl<S: AsyncRead + AsyncWrite> TimeoutStream<S> {
             stream,
         }
     }
+    pub fn set_timeout(self: std::pin::Pin<&mut Self>, timeout: Duration) {
+        let this = self.project();
+        *this.timeout = timeout;
+        this.sleep.reset(Instant::now() + timeout);
+    }
 }
 impl<S: AsyncRead + AsyncWrite> AsyncRead for TimeoutStream<S> {
     fn poll_read(
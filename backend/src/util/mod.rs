@@ -29,6 +29,7 @@ pub mod io;
 pub mod logger;
 pub mod lshw;
 pub mod serde;
+pub mod time;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Never {}
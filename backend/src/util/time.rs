@@ -0,0 +1,53 @@
+use chrono::{NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, ErrorKind, ResultExt};
+
+/// A daily window, in UTC, which wraps past midnight when `end` is before `start`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+impl TimeWindow {
+    /// Takes the bounds in minutes after midnight
+    pub fn from_minutes(start: i32, end: i32) -> Self {
+        let time = |m: i32| {
+            NaiveTime::from_num_seconds_from_midnight_opt(m.rem_euclid(24 * 60) as u32 * 60, 0)
+                .unwrap_or_default()
+        };
+        TimeWindow {
+            start: time(start),
+            end: time(end),
+        }
+    }
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// Parses `HH:MM` into minutes after midnight
+pub fn parse_time(time: &str) -> Result<i32, Error> {
+    let time = NaiveTime::parse_from_str(time, "%H:%M").with_kind(ErrorKind::ParseTimestamp)?;
+    Ok((time.num_seconds_from_midnight() / 60) as i32)
+}
+
+#[test]
+fn time_window() {
+    let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    let overnight = TimeWindow::from_minutes(parse_time("22:00").unwrap(), 7 * 60);
+    assert!(overnight.contains(at(23, 30)));
+    assert!(overnight.contains(at(3, 0)));
+    assert!(!overnight.contains(at(7, 0)));
+    assert!(!overnight.contains(at(12, 0)));
+    let afternoon = TimeWindow::from_minutes(13 * 60, 14 * 60 + 30);
+    assert!(afternoon.contains(at(14, 0)));
+    assert!(!afternoon.contains(at(14, 30)));
+    assert!(!afternoon.contains(at(9, 0)));
+    assert!(parse_time("25:00").is_err());
+}
@@ -82,6 +82,12 @@ export interface Dependency<T> {
     | {
         type: 'required'
       }
+    | {
+        type: 'optional'
+      }
+    | {
+        type: 'suggests'
+      }
   description: string | null
   config: T
 }
@@ -6,6 +6,7 @@ import { Component, Input } from '@angular/core'
 })
 export class DownloadDocComponent {
   @Input() lanAddress!: string
+  @Input() recoveryCodes: string[] = []
 
   get crtName(): string {
     const hostname = new URL(this.lanAddress).hostname
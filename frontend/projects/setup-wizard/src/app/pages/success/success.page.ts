@@ -18,6 +18,7 @@ export class SuccessPage {
   torAddress?: string
   lanAddress?: string
   cert?: string
+  recoveryCodes: string[] = []
 
   tileSize = 16
   // a higher fade factor will make the characters fade quicker
@@ -47,6 +48,7 @@ export class SuccessPage {
     this.ngZone.runOutsideAngular(() => this.initMatrix())
     try {
       const ret = await this.api.complete()
+      this.recoveryCodes = ret['recovery-codes'] || []
       if (!this.isKiosk) {
         this.torAddress = ret['tor-address']
         this.lanAddress = ret['lan-address'].replace(/^https:/, 'http:')
@@ -49,6 +49,7 @@ export type CompleteRes = {
   'tor-address': string
   'lan-address': string
   'root-ca': string
+  'recovery-codes': string[] | null
 }
 
 export type DiskBackupTarget = {
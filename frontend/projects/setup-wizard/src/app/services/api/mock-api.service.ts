@@ -152,6 +152,11 @@ export class MockApiService extends ApiService {
       'tor-address': 'https://asdafsadasdasasdasdfasdfasdf.onion',
       'lan-address': 'https://adjective-noun.local',
       'root-ca': encodeBase64(rootCA),
+      'recovery-codes': [
+        'k3q7-m2xp-9fta',
+        'w8rn-4hcz-tl6e',
+        'p5ds-yb2u-3vkg',
+      ],
     }
   }
 
@@ -94,6 +94,7 @@ export class MenuComponent {
       Object.entries(marketplace).reduce((list, [_, store]) => {
         store?.packages.forEach(({ manifest: { id, version } }) => {
           if (
+            !local[id]?.installed?.held &&
             this.emver.compare(
               version,
               local[id]?.installed?.manifest.version || '',
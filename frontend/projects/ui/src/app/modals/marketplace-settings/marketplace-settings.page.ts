@@ -96,6 +96,10 @@ export class MarketplaceSettingsPage {
     { url, name }: { url: string; name?: string },
     canDelete = false,
   ) {
+    const torOnly = await firstValueFrom(
+      this.patch.watch$('server-info', 'tor-only-registries'),
+    )
+    const isTorOnly = !!torOnly?.some(r => sameUrl(r, url))
     const buttons: ActionSheetButton[] = [
       {
         text: 'Connect',
@@ -103,6 +107,12 @@ export class MarketplaceSettingsPage {
           this.connect(url)
         },
       },
+      {
+        text: isTorOnly ? 'Stop Routing Over Tor' : 'Route Over Tor Only',
+        handler: () => {
+          this.setTorOnly(url, !isTorOnly)
+        },
+      },
     ]
 
     if (canDelete) {
@@ -144,6 +154,19 @@ export class MarketplaceSettingsPage {
     await alert.present()
   }
 
+  private async setTorOnly(registry: string, enable: boolean): Promise<void> {
+    const loader = await this.loadingCtrl.create({ message: 'Saving...' })
+    await loader.present()
+
+    try {
+      await this.api.setRegistryTorOnly({ registry, enable })
+    } catch (e: any) {
+      this.errToast.present(e)
+    } finally {
+      loader.dismiss()
+    }
+  }
+
   private async connect(
     url: string,
     loader?: HTMLIonLoadingElement,
@@ -55,6 +55,12 @@ export class AppInterfacesPage {
           'tor-address': uiAddresses['tor-address']
             ? 'http://' + uiAddresses['tor-address']
             : '',
+          'clearnet-addresses': (uiAddresses['clearnet-addresses'] || []).map(
+            domain => 'https://' + domain,
+          ),
+          'i2p-address': uiAddresses['i2p-address']
+            ? 'http://' + uiAddresses['i2p-address']
+            : '',
         },
       }
     }
@@ -73,6 +79,12 @@ export class AppInterfacesPage {
               ? // leave http for services
                 'http://' + addresses['tor-address']
               : '',
+            'clearnet-addresses': (addresses['clearnet-addresses'] || []).map(
+              domain => 'https://' + domain,
+            ),
+            'i2p-address': addresses['i2p-address']
+              ? 'http://' + addresses['i2p-address']
+              : '',
           },
         }
       })
@@ -3,7 +3,11 @@ import { CommonModule } from '@angular/common'
 import { Routes, RouterModule } from '@angular/router'
 import { IonicModule } from '@ionic/angular'
 import { AppShowPage } from './app-show.page'
-import { EmverPipesModule, ResponsiveColModule } from '@start9labs/shared'
+import {
+  EmverPipesModule,
+  ResponsiveColModule,
+  UnitConversionPipesModule,
+} from '@start9labs/shared'
 import { StatusComponentModule } from 'src/app/components/status/status.component.module'
 import { AppConfigPageModule } from 'src/app/modals/app-config/app-config.module'
 import { LaunchablePipeModule } from 'src/app/pipes/launchable/launchable.module'
@@ -15,6 +19,7 @@ import { AppShowDependenciesComponent } from './components/app-show-dependencies
 import { AppShowMenuComponent } from './components/app-show-menu/app-show-menu.component'
 import { AppShowHealthChecksComponent } from './components/app-show-health-checks/app-show-health-checks.component'
 import { AppShowAdditionalComponent } from './components/app-show-additional/app-show-additional.component'
+import { AppShowStorageComponent } from './components/app-show-storage/app-show-storage.component'
 import { HealthColorPipe } from './pipes/health-color.pipe'
 import { ToHealthChecksPipe } from './pipes/to-health-checks.pipe'
 import { ToButtonsPipe } from './pipes/to-buttons.pipe'
@@ -45,6 +50,7 @@ const routes: Routes = [
     AppShowMenuComponent,
     AppShowHealthChecksComponent,
     AppShowAdditionalComponent,
+    AppShowStorageComponent,
   ],
   imports: [
     CommonModule,
@@ -56,6 +62,7 @@ const routes: Routes = [
     LaunchablePipeModule,
     UiPipeModule,
     ResponsiveColModule,
+    UnitConversionPipesModule,
   ],
 })
 export class AppShowPageModule {}
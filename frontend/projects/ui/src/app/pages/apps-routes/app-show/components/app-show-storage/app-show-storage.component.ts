@@ -0,0 +1,21 @@
+import { ChangeDetectionStrategy, Component, Input } from '@angular/core'
+import { PackageDiskUsage } from 'src/app/services/patch-db/data-model'
+
+@Component({
+  selector: 'app-show-storage',
+  templateUrl: 'app-show-storage.component.html',
+  changeDetection: ChangeDetectionStrategy.OnPush,
+})
+export class AppShowStorageComponent {
+  @Input()
+  usage!: PackageDiskUsage
+
+  get total(): number {
+    return (
+      Object.values(this.usage.volumes).reduce((a, b) => a + b, 0) +
+      this.usage.images +
+      this.usage.archives +
+      this.usage.rollback
+    )
+  }
+}
@@ -15,6 +15,8 @@ export interface DependencyInfo {
   icon: string
   version: string
   errorText: string
+  // the package works without it
+  optional: boolean
   actionText: string
   action: () => any
 }
@@ -85,6 +87,7 @@ export class ToDependenciesPipe implements PipeTransform {
     }
 
     const depInfo = pkg.installed?.['dependency-info'][id]
+    const { type } = pkg.manifest.dependencies[id].requirement
 
     return {
       id,
@@ -92,6 +95,7 @@ export class ToDependenciesPipe implements PipeTransform {
       title: depInfo?.manifest?.title || id,
       icon: depInfo?.icon || '',
       errorText,
+      optional: type === 'optional' || type === 'suggests',
       actionText,
       action,
     }
@@ -4,6 +4,7 @@ import { CommonModule } from '@angular/common'
 import { FormsModule } from '@angular/forms'
 import { IonicModule } from '@ionic/angular'
 import { LoginPage } from './login.page'
+import { OidcCallbackPage } from './oidc-callback/oidc-callback.page'
 import { SharedPipesModule } from '@start9labs/shared'
 
 const routes: Routes = [
@@ -11,6 +12,10 @@ const routes: Routes = [
     path: '',
     component: LoginPage,
   },
+  {
+    path: 'oidc',
+    component: OidcCallbackPage,
+  },
 ]
 
 @NgModule({
@@ -21,6 +26,6 @@ const routes: Routes = [
     SharedPipesModule,
     RouterModule.forChild(routes),
   ],
-  declarations: [LoginPage],
+  declarations: [LoginPage, OidcCallbackPage],
 })
 export class LoginPageModule {}
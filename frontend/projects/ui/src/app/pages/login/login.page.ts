@@ -11,6 +11,7 @@ import { ConfigService } from 'src/app/services/config.service'
   styleUrls: ['./login.page.scss'],
 })
 export class LoginPage {
+  username = ''
   password = ''
   unmasked = false
   error = ''
@@ -39,6 +40,25 @@ export class LoginPage {
     this.loader?.dismiss()
   }
 
+  async loginWithSso() {
+    this.error = ''
+
+    this.loader = await this.loadingCtrl.create({
+      message: 'Redirecting...',
+    })
+    await this.loader.present()
+
+    try {
+      const url = await this.api.startOidcLogin({
+        'redirect-uri': `${window.location.origin}/login/oidc`,
+      })
+      window.location.assign(url)
+    } catch (e: any) {
+      this.error = e.message
+      this.loader.dismiss()
+    }
+  }
+
   toggleMask() {
     this.unmasked = !this.unmasked
   }
@@ -58,6 +78,7 @@ export class LoginPage {
         return
       }
       await this.api.login({
+        username: this.username.trim() || null,
         password: this.secure
           ? this.password
           : await this.api.encrypt(this.password),
@@ -69,7 +90,7 @@ export class LoginPage {
       this.router.navigate([''], { replaceUrl: true })
     } catch (e: any) {
       // code 7 is for incorrect password
-      this.error = e.code === 7 ? 'Invalid Password' : e.message
+      this.error = e.code === 7 ? 'Invalid Username or Password' : e.message
     } finally {
       this.loader.dismiss()
     }
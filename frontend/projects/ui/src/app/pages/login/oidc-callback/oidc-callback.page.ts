@@ -0,0 +1,49 @@
+import { Component } from '@angular/core'
+import { getPlatforms } from '@ionic/angular'
+import { ActivatedRoute, Router } from '@angular/router'
+import { ApiService } from 'src/app/services/api/embassy-api.service'
+import { AuthService } from 'src/app/services/auth.service'
+
+// where the OIDC provider sends the browser back to, see `auth.oidc.start`
+@Component({
+  selector: 'oidc-callback',
+  templateUrl: './oidc-callback.page.html',
+  styleUrls: ['../login.page.scss'],
+})
+export class OidcCallbackPage {
+  error = ''
+
+  constructor(
+    private readonly route: ActivatedRoute,
+    private readonly router: Router,
+    private readonly authService: AuthService,
+    private readonly api: ApiService,
+  ) {}
+
+  async ngOnInit() {
+    const params = this.route.snapshot.queryParamMap
+    const code = params.get('code')
+    const state = params.get('state')
+
+    if (!code || !state) {
+      this.error =
+        params.get('error_description') ||
+        params.get('error') ||
+        'The provider did not complete the login'
+      return
+    }
+
+    try {
+      await this.api.completeOidcLogin({
+        code,
+        state,
+        metadata: { platforms: getPlatforms() },
+      })
+
+      this.authService.setVerified()
+      this.router.navigate([''], { replaceUrl: true })
+    } catch (e: any) {
+      this.error = e.message
+    }
+  }
+}
@@ -3,7 +3,7 @@ import { ActivatedRoute } from '@angular/router'
 import { ModalController } from '@ionic/angular'
 import { AbstractMarketplaceService } from '@start9labs/marketplace'
 import { PatchDB } from 'patch-db-client'
-import { map } from 'rxjs'
+import { combineLatest, map } from 'rxjs'
 import { MarketplaceSettingsPage } from 'src/app/modals/marketplace-settings/marketplace-settings.page'
 import { ConfigService } from 'src/app/services/config.service'
 import { MarketplaceService } from 'src/app/services/marketplace.service'
@@ -31,6 +31,12 @@ export class MarketplaceListPage {
 
   readonly localPkgs$ = this.patch.watch$('package-data')
 
+  // when the cached data of the selected registry was fetched, if it is unreachable
+  readonly offlineSince$ = combineLatest([
+    this.marketplaceService.getSelectedHost$(),
+    this.patch.watch$('server-info'),
+  ]).pipe(map(([{ url }, info]) => info['offline-registries']?.[url]))
+
   readonly details$ = this.marketplaceService.getSelectedHost$().pipe(
     map(({ url, name }) => {
       const { start9, community } = this.config.marketplace
@@ -4,7 +4,11 @@ import {
   Inject,
   Input,
 } from '@angular/core'
-import { AlertController, LoadingController } from '@ionic/angular'
+import {
+  AlertController,
+  LoadingController,
+  ModalController,
+} from '@ionic/angular'
 import {
   AbstractMarketplaceService,
   MarketplacePkg,
@@ -13,6 +17,7 @@ import {
   Emver,
   ErrorToastService,
   isEmptyObject,
+  MarkdownComponent,
   sameUrl,
 } from '@start9labs/shared'
 import {
@@ -55,6 +60,7 @@ export class MarketplaceShowControlsComponent {
     @Inject(AbstractMarketplaceService)
     private readonly marketplaceService: MarketplaceService,
     private readonly loadingCtrl: LoadingController,
+    private readonly modalCtrl: ModalController,
     private readonly emver: Emver,
     private readonly errToast: ErrorToastService,
     private readonly embassyApi: ApiService,
@@ -194,7 +200,72 @@ export class MarketplaceShowControlsComponent {
     await alert.present()
   }
 
+  private async presentAlertLicense(url: string): Promise<boolean> {
+    return new Promise(async resolve => {
+      const alert = await this.alertCtrl.create({
+        header: 'License',
+        message: `Installing ${this.pkg.manifest.title} requires accepting its license.`,
+        buttons: [
+          {
+            text: 'Cancel',
+            role: 'cancel',
+            handler: () => {
+              resolve(false)
+            },
+          },
+          {
+            text: 'View',
+            handler: () => {
+              this.presentModalLicense(url)
+              return false
+            },
+          },
+          {
+            text: 'Accept',
+            handler: () => {
+              resolve(true)
+            },
+            cssClass: 'enter-click',
+          },
+        ],
+      })
+
+      await alert.present()
+    })
+  }
+
+  private async presentModalLicense(url: string) {
+    const modal = await this.modalCtrl.create({
+      componentProps: {
+        title: 'License',
+        content: this.marketplaceService.fetchStatic$(
+          this.pkg.manifest.id,
+          'license',
+          url,
+        ),
+      },
+      component: MarkdownComponent,
+    })
+
+    await modal.present()
+  }
+
+  // updates to a license that changed are refused by the server instead
+  private async hasAcceptedLicense(): Promise<boolean> {
+    const serverInfo = await firstValueFrom(this.patch.watch$('server-info'))
+    return !!serverInfo['license-acceptances']?.some(
+      a => a['package-id'] === this.pkg.manifest.id,
+    )
+  }
+
   private async install(url: string, loader?: HTMLIonLoadingElement) {
+    const accepted = await this.hasAcceptedLicense()
+    if (!accepted) {
+      await loader?.dismiss()
+      loader = undefined
+      if (!(await this.presentAlertLicense(url))) return
+    }
+
     const message = 'Beginning Install...'
     if (loader) {
       loader.message = message
@@ -206,7 +277,7 @@ export class MarketplaceShowControlsComponent {
     const { id, version } = this.pkg.manifest
 
     try {
-      await this.marketplaceService.installPackage(id, version, url)
+      await this.marketplaceService.installPackage(id, version, url, !accepted)
     } catch (e: any) {
       this.errToast.present(e)
     } finally {
@@ -4,6 +4,7 @@ import {
   ServerNotifications,
   NotificationLevel,
   ServerNotification,
+  NotificationAction,
 } from 'src/app/services/api/api.types'
 import {
   AlertController,
@@ -25,7 +26,7 @@ import { first } from 'rxjs'
 export class NotificationsPage {
   loading = true
   notifications: ServerNotifications = []
-  beforeCursor?: number
+  cursor?: string
   needInfinite = false
   fromToast = !!this.route.snapshot.queryParamMap.get('toast')
   readonly perPage = 40
@@ -54,17 +55,16 @@ export class NotificationsPage {
 
   async getNotifications(): Promise<ServerNotifications> {
     try {
-      const notifications = await this.embassyApi.getNotifications({
-        before: this.beforeCursor,
+      const page = await this.embassyApi.getNotifications({
+        cursor: this.cursor,
         limit: this.perPage,
       })
 
-      if (!notifications) return []
+      this.cursor = page['next-cursor'] ?? undefined
+      this.needInfinite = !!this.cursor
+      this.markRead(page.notifications)
 
-      this.beforeCursor = notifications[notifications.length - 1]?.id
-      this.needInfinite = notifications.length >= this.perPage
-
-      return notifications
+      return page.notifications
     } catch (e: any) {
       this.errToast.present(e)
     }
@@ -72,6 +72,37 @@ export class NotificationsPage {
     return []
   }
 
+  private async markRead(notifications: ServerNotifications): Promise<void> {
+    const ids = notifications.filter(n => !n['read-at']).map(n => n.id)
+    if (!ids.length) return
+
+    try {
+      await this.embassyApi.markNotificationsRead({ ids })
+    } catch (e: any) {
+      // viewers are not permitted to change the read state, which only affects the badge
+    }
+  }
+
+  async runAction(action: NotificationAction): Promise<void> {
+    if (action.type !== 'rpc') return
+
+    const loader = await this.loadingCtrl.create({
+      message: `${action.label}...`,
+    })
+    await loader.present()
+
+    try {
+      await this.embassyApi.runNotificationAction({
+        method: action.method,
+        params: action.params,
+      })
+    } catch (e: any) {
+      this.errToast.present(e)
+    } finally {
+      loader.dismiss()
+    }
+  }
+
   async delete(id: number, index: number): Promise<void> {
     const loader = await this.loadingCtrl.create({
       message: 'Deleting...',
@@ -81,7 +112,6 @@ export class NotificationsPage {
     try {
       await this.embassyApi.deleteNotification({ id })
       this.notifications.splice(index, 1)
-      this.beforeCursor = this.notifications[this.notifications.length - 1]?.id
     } catch (e: any) {
       this.errToast.present(e)
     } finally {
@@ -170,7 +200,7 @@ export class NotificationsPage {
         before: this.notifications[0].id + 1,
       })
       this.notifications = []
-      this.beforeCursor = undefined
+      this.cursor = undefined
     } catch (e: any) {
       this.errToast.present(e)
     } finally {
@@ -80,6 +80,11 @@ const routes: Routes = [
     loadChildren: () =>
       import('./wifi/wifi.module').then(m => m.WifiPageModule),
   },
+  {
+    path: 'wireguard',
+    loadChildren: () =>
+      import('./wireguard/wireguard.module').then(m => m.WireguardPageModule),
+  },
   {
     path: 'experimental-features',
     loadChildren: () =>
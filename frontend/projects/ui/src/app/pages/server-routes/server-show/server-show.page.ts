@@ -519,6 +519,17 @@ export class ServerShowPage {
         detail: true,
         disabled$: of(false),
       },
+      {
+        title: 'WireGuard',
+        description: 'Connect your devices to your server over a VPN',
+        icon: 'shield-checkmark-outline',
+        action: () =>
+          this.navCtrl.navigateForward(['wireguard'], {
+            relativeTo: this.route,
+          }),
+        detail: true,
+        disabled$: of(false),
+      },
       {
         title: 'WiFi',
         description: 'Add or remove WiFi networks',
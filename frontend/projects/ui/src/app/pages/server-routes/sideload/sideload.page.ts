@@ -4,7 +4,7 @@ import { ApiService } from 'src/app/services/api/embassy-api.service'
 import { Manifest } from 'src/app/services/patch-db/data-model'
 import { ConfigService } from 'src/app/services/config.service'
 import cbor from 'cbor'
-import { ErrorToastService } from '@start9labs/shared'
+import { ErrorToastService, pauseFor } from '@start9labs/shared'
 
 interface Positions {
   [key: string]: [bigint, bigint] // [position, length]
@@ -12,6 +12,8 @@ interface Positions {
 
 const MAGIC = new Uint8Array([59, 59])
 const VERSION = new Uint8Array([1])
+const CHUNK_SIZE = 8 * 1024 * 1024
+const ATTEMPTS = 5
 
 @Component({
   selector: 'sideload',
@@ -91,14 +93,7 @@ export class SideloadPage {
     })
     await loader.present()
     try {
-      const guid = await this.api.sideloadPackage({
-        manifest: this.toUpload.manifest!,
-        icon: this.toUpload.icon!,
-      })
-      this.api
-        .uploadPackage(guid, this.toUpload.file!)
-        .catch(e => console.error(e))
-
+      await this.uploadChunks(this.toUpload.file!, loader)
       this.navCtrl.navigateRoot('/services')
     } catch (e: any) {
       this.errToast.present(e)
@@ -108,6 +103,34 @@ export class SideloadPage {
     }
   }
 
+  // resumes from wherever the server got to when a chunk fails
+  private async uploadChunks(file: File, loader: HTMLIonLoadingElement) {
+    const id = await this.api.initUpload({ size: file.size })
+    let offset = 0
+    let attempt = 0
+    while (offset < file.size) {
+      const chunk = file.slice(offset, offset + CHUNK_SIZE)
+      try {
+        const guid = await this.api.appendUpload({
+          id,
+          offset,
+          sha256: await sha256(chunk),
+        })
+        await this.api.uploadPackage(guid, chunk)
+        offset += chunk.size
+        attempt = 0
+        loader.message = `Uploading package: ${Math.floor(
+          (100 * offset) / file.size,
+        )}%`
+      } catch (e: any) {
+        if (++attempt > ATTEMPTS) throw e
+        await pauseFor(attempt * 5000)
+        offset = (await this.api.getUploadStatus({ id })).received
+      }
+    }
+    await this.api.commitUpload({ id })
+  }
+
   async parseS9pk(file: File) {
     const positions: Positions = {}
     // magic=2bytes, version=1bytes, pubkey=32bytes, signature=64bytes, toc_length=4bytes = 103byte is starting point
@@ -226,6 +249,15 @@ async function readBlobToArrayBuffer(
   })
 }
 
+// browsers only hash in secure contexts, which plain http over LAN or tor is not
+async function sha256(data: Blob): Promise<string | undefined> {
+  if (!window.crypto?.subtle) return undefined
+  const hash = await crypto.subtle.digest('SHA-256', await blobToBuffer(data))
+  return Array.from(new Uint8Array(hash))
+    .map(b => b.toString(16).padStart(2, '0'))
+    .join('')
+}
+
 function compare(a: Uint8Array, b: Uint8Array) {
   for (let i = 0; i < a.length; i++) {
     if (a[i] !== b[i]) return false
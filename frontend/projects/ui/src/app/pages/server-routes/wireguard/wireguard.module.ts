@@ -0,0 +1,26 @@
+import { NgModule } from '@angular/core'
+import { CommonModule } from '@angular/common'
+import { IonicModule } from '@ionic/angular'
+import { RouterModule, Routes } from '@angular/router'
+import { WireguardPage } from './wireguard.page'
+import { SharedPipesModule } from '@start9labs/shared'
+import { QRComponentModule } from 'src/app/components/qr/qr.component.module'
+
+const routes: Routes = [
+  {
+    path: '',
+    component: WireguardPage,
+  },
+]
+
+@NgModule({
+  imports: [
+    CommonModule,
+    IonicModule,
+    RouterModule.forChild(routes),
+    SharedPipesModule,
+    QRComponentModule,
+  ],
+  declarations: [WireguardPage],
+})
+export class WireguardPageModule {}
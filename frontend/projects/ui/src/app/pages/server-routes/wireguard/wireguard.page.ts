@@ -0,0 +1,131 @@
+import { Component } from '@angular/core'
+import {
+  AlertController,
+  LoadingController,
+  ModalController,
+} from '@ionic/angular'
+import { PatchDB } from 'patch-db-client'
+import { ErrorToastService } from '@start9labs/shared'
+import { ApiService } from 'src/app/services/api/embassy-api.service'
+import { DataModel } from 'src/app/services/patch-db/data-model'
+import { QRComponent } from 'src/app/components/qr/qr.component'
+import {
+  GenericInputComponent,
+  GenericInputOptions,
+} from 'src/app/modals/generic-input/generic-input.component'
+
+@Component({
+  selector: 'wireguard',
+  templateUrl: 'wireguard.page.html',
+  styleUrls: ['wireguard.page.scss'],
+})
+export class WireguardPage {
+  readonly wireguard$ = this.patch.watch$('server-info', 'wireguard')
+
+  constructor(
+    private readonly loadingCtrl: LoadingController,
+    private readonly modalCtrl: ModalController,
+    private readonly errToast: ErrorToastService,
+    private readonly alertCtrl: AlertController,
+    private readonly embassyApi: ApiService,
+    private readonly patch: PatchDB<DataModel>,
+  ) {}
+
+  async presentModalAdd() {
+    const options: GenericInputOptions = {
+      title: 'Add Device',
+      message:
+        'Enter a name for the device. Scan the QR code shown next with the WireGuard app on it to join the VPN.',
+      label: 'Name',
+      submitFn: (name: string) => this.add(name),
+    }
+
+    const modal = await this.modalCtrl.create({
+      component: GenericInputComponent,
+      componentProps: { options },
+      cssClass: 'alertlike-modal',
+    })
+    await modal.present()
+  }
+
+  async add(name: string): Promise<void> {
+    const loader = await this.loadingCtrl.create({
+      message: 'Adding...',
+    })
+    await loader.present()
+
+    try {
+      const config = await this.embassyApi.addWireguardPeer({ name })
+      await this.showQR(config)
+    } finally {
+      loader.dismiss()
+    }
+  }
+
+  async presentConfig(name: string): Promise<void> {
+    const loader = await this.loadingCtrl.create({
+      message: 'Loading...',
+    })
+    await loader.present()
+
+    try {
+      const config = await this.embassyApi.getWireguardConfig({ name })
+      await this.showQR(config)
+    } catch (e: any) {
+      this.errToast.present(e)
+    } finally {
+      loader.dismiss()
+    }
+  }
+
+  async presentAlertRemove(name: string) {
+    const alert = await this.alertCtrl.create({
+      header: 'Caution',
+      message: `Are you sure you want to remove ${name}? It will no longer be able to connect.`,
+      buttons: [
+        {
+          text: 'Cancel',
+          role: 'cancel',
+        },
+        {
+          text: 'Remove',
+          handler: () => {
+            this.remove(name)
+          },
+          cssClass: 'enter-click',
+        },
+      ],
+    })
+    await alert.present()
+  }
+
+  async remove(name: string): Promise<void> {
+    const loader = await this.loadingCtrl.create({
+      message: 'Removing...',
+    })
+    await loader.present()
+
+    try {
+      await this.embassyApi.removeWireguardPeer({ name })
+    } catch (e: any) {
+      this.errToast.present(e)
+    } finally {
+      loader.dismiss()
+    }
+  }
+
+  asIsOrder() {
+    return 0
+  }
+
+  private async showQR(text: string): Promise<void> {
+    const modal = await this.modalCtrl.create({
+      component: QRComponent,
+      componentProps: {
+        text,
+      },
+      cssClass: 'qr-modal',
+    })
+    await modal.present()
+  }
+}
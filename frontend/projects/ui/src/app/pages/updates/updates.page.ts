@@ -179,6 +179,7 @@ export class FilterUpdatesPipe implements PipeTransform {
   ): MarketplacePkg[] {
     return pkgs.filter(
       ({ manifest }) =>
+        !local[manifest.id]?.installed?.held &&
         this.emver.compare(
           manifest.version,
           local[manifest.id]?.installed?.manifest.version || '',
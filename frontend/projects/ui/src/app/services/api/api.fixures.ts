@@ -43,6 +43,42 @@ export module Mock {
     '0.19.0': 'release notes for Bitcoin 0.19.0',
   }
 
+  export const WireguardConfig: RR.GetWireguardConfigRes = `[Interface]
+PrivateKey = cGhvbmVwcml2YXRla2V5cGhvbmVwcml2YXRla2V5MDA=
+Address = 10.232.0.2/32
+DNS = 10.232.0.1
+
+[Peer]
+PublicKey = c2VydmVycHVibGlja2V5c2VydmVycHVibGlja2V5MDA=
+Endpoint = 203.0.113.7:51820
+AllowedIPs = 10.232.0.1/32
+PersistentKeepalive = 25
+`
+
+  export const NetworkReport: RR.DiagnoseNetworkRes = {
+    dns: {
+      status: 'passed',
+      message: 'start9.com resolves to 104.21.64.1, 172.67.180.2',
+    },
+    gateway: {
+      status: 'passed',
+      message: 'The default gateway 192.168.1.1 answers',
+    },
+    tor: { status: 'passed', message: 'Tor is bootstrapped' },
+    mdns: {
+      status: 'passed',
+      message: 'adjective-noun.local resolves to 192.168.1.10',
+    },
+    'public-ip': '203.0.113.7',
+    ports: {
+      '443': { status: 'passed', message: '203.0.113.7:443 is reachable' },
+      '8333': {
+        status: 'failed',
+        message: '203.0.113.7:8333 does not answer',
+      },
+    },
+  }
+
   export const MockManifestBitcoind: Manifest = {
     id: 'bitcoind',
     title: 'Bitcoin Core',
@@ -778,6 +814,10 @@ export module Mock {
       id: 1,
       'package-id': null,
       'created-at': '2019-12-26T14:20:30.872Z',
+      actions: [],
+      occurrences: 1,
+      'last-seen': '2019-12-26T14:20:30.872Z',
+      'read-at': null,
       code: 1,
       level: NotificationLevel.Success,
       title: 'Backup Complete',
@@ -798,6 +838,10 @@ export module Mock {
       id: 2,
       'package-id': null,
       'created-at': '2019-12-26T14:20:30.872Z',
+      actions: [],
+      occurrences: 1,
+      'last-seen': '2019-12-26T14:20:30.872Z',
+      'read-at': null,
       code: 2,
       level: NotificationLevel.Warning,
       title: 'SSH Key Added',
@@ -808,6 +852,10 @@ export module Mock {
       id: 3,
       'package-id': null,
       'created-at': '2019-12-26T14:20:30.872Z',
+      actions: [],
+      occurrences: 1,
+      'last-seen': '2019-12-26T14:20:30.872Z',
+      'read-at': null,
       code: 3,
       level: NotificationLevel.Info,
       title: 'SSH Key Removed',
@@ -818,6 +866,10 @@ export module Mock {
       id: 4,
       'package-id': 'bitcoind',
       'created-at': '2019-12-26T14:20:30.872Z',
+      actions: [],
+      occurrences: 1,
+      'last-seen': '2019-12-26T14:20:30.872Z',
+      'read-at': null,
       code: 4,
       level: NotificationLevel.Error,
       title: 'Service Crashed',
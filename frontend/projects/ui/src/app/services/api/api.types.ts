@@ -5,7 +5,8 @@ import { ConfigSpec } from 'src/app/pkg-config/config-types'
 import {
   DataModel,
   DependencyError,
-  Manifest,
+  MainStatus,
+  ReleaseChannel,
 } from 'src/app/services/patch-db/data-model'
 import { StartOSDiskInfo, LogsRes, ServerLogsReq } from '@start9labs/shared'
 
@@ -22,11 +23,22 @@ export module RR {
   // auth
 
   export type LoginReq = {
+    username: string | null // null for the master password
     password: Encrypted | string
     metadata: SessionMetadata
   } // auth.login - unauthed
   export type loginRes = null
 
+  export type StartOidcLoginReq = { 'redirect-uri': string } // auth.oidc.start - unauthed
+  export type StartOidcLoginRes = string // the url of the provider to send the browser to
+
+  export type CompleteOidcLoginReq = {
+    code: string
+    state: string
+    metadata: SessionMetadata
+  } // auth.oidc.callback - unauthed
+  export type CompleteOidcLoginRes = null
+
   export type LogoutReq = {} // auth.logout
   export type LogoutRes = null
 
@@ -93,10 +105,25 @@ export module RR {
   // notification
 
   export type GetNotificationsReq = {
-    before?: number
+    cursor?: string
     limit?: number
+    level?: NotificationLevel
+    package?: string
+    code?: number
+    since?: string
+    until?: string
+    search?: string
   } // notification.list
-  export type GetNotificationsRes = ServerNotification<number>[]
+  export type GetNotificationsRes = {
+    notifications: ServerNotification<number>[]
+    'next-cursor': string | null
+  }
+
+  export type MarkNotificationsReadReq = { ids: number[] } // notification.mark-read
+  export type MarkNotificationsReadRes = null
+
+  export type RunNotificationActionReq = { method: string; params: any } // the action's method
+  export type RunNotificationActionRes = any
 
   export type DeleteNotificationReq = { id: number } // notification.delete
   export type DeleteNotificationRes = null
@@ -198,12 +225,17 @@ export module RR {
     'version-spec'?: string
     'version-priority'?: 'min' | 'max'
     'marketplace-url': string
+    instance?: string
+    'accept-license'?: boolean
   } // package.install
   export type InstallPackageRes = null
 
   export type DryUpdatePackageReq = { id: string; version: string } // package.update.dry
   export type DryUpdatePackageRes = Breakages
 
+  export type GetDependencyGraphReq = {} // package.dependency-graph
+  export type GetDependencyGraphRes = DependencyGraph
+
   export type GetPackageConfigReq = { id: string } // package.config.get
   export type GetPackageConfigRes = { spec: ConfigSpec; config: object }
 
@@ -238,9 +270,23 @@ export module RR {
   export type StopPackageReq = { id: string } // package.stop
   export type StopPackageRes = null
 
-  export type UninstallPackageReq = { id: string } // package.uninstall
+  export type UninstallPackageReq = {
+    id: string
+    'keep-data'?: boolean
+    'archive-data'?: string // backup target id
+    password?: Encrypted // required with archive-data
+  } // package.uninstall
   export type UninstallPackageRes = null
 
+  export type SetPackageLabelReq = { id: string; label?: string } // package.label
+  export type SetPackageLabelRes = null
+
+  export type GetAvailableUpdatesReq = {} // package.updates.available
+  export type GetAvailableUpdatesRes = {
+    updates: { [id: string]: AvailableUpdate }
+    errors: { [id: string]: string } // registry could not be asked
+  }
+
   export type DryConfigureDependencyReq = {
     'dependency-id': string
     'dependent-id': string
@@ -251,11 +297,21 @@ export module RR {
     spec: ConfigSpec
   }
 
-  export type SideloadPackageReq = {
-    manifest: Manifest
-    icon: string // base64
-  }
-  export type SideloadPacakgeRes = string //guid
+  export type InitUploadReq = { size: number; sha256?: string } // package.upload.init
+  export type InitUploadRes = string // upload id
+
+  export type AppendUploadReq = {
+    id: string
+    offset: number
+    sha256?: string // of the chunk
+  } // package.upload.append
+  export type AppendUploadRes = string // guid to POST the chunk to
+
+  export type GetUploadStatusReq = { id: string } // package.upload.status
+  export type GetUploadStatusRes = { size: number; received: number }
+
+  export type CommitUploadReq = { id: string } // package.upload.commit
+  export type CommitUploadRes = null
 
   // marketplace
 
@@ -277,6 +333,90 @@ export module RR {
 
   export type GetReleaseNotesReq = { id: string }
   export type GetReleaseNotesRes = { [version: string]: string }
+
+  export type SearchMarketplaceReq = {
+    query?: string
+    category?: string
+    page?: number
+    'per-page'?: number
+    registries?: string[] // all known hosts if empty
+  } // marketplace.search
+  export type SearchMarketplaceRes = {
+    packages: { registry: string; package: MarketplacePkg }[]
+    errors: { [registry: string]: string }
+  }
+
+  export type GetMarketplaceCategoriesReq = { registries?: string[] } // marketplace.categories
+  export type GetMarketplaceCategoriesRes = {
+    categories: { [category: string]: string[] } // registries with the category
+    errors: { [registry: string]: string }
+  }
+
+  export type SetRegistryChannelReq = {
+    registry: string
+    channel: ReleaseChannel
+  } // marketplace.channel.set-registry
+  export type SetRegistryChannelRes = null
+
+  export type SetPackageChannelReq = {
+    id: string
+    channel: ReleaseChannel | null // follow the registry
+  } // marketplace.channel.set-package
+  export type SetPackageChannelRes = null
+
+  export type SetRegistryTorOnlyReq = { registry: string; enable: boolean } // marketplace.tor-only
+  export type SetRegistryTorOnlyRes = null
+
+  export type ServeRegistryReq = { enable: boolean; 'tor-only': boolean } // marketplace.serve
+  export type ServeRegistryRes = null
+
+  // wireguard
+
+  export type AddWireguardPeerReq = { name: string } // net.wireguard.peer.add
+  export type AddWireguardPeerRes = string // config to import, e.g. as a QR code
+
+  export type RemoveWireguardPeerReq = { name: string } // net.wireguard.peer.remove
+  export type RemoveWireguardPeerRes = null
+
+  export type GetWireguardConfigReq = { name: string } // net.wireguard.peer.config
+  export type GetWireguardConfigRes = string
+
+  // tailscale
+
+  export type SetTailnetExposureReq = {
+    package: string
+    interface: string
+    enable: boolean
+  } // net.tailscale.expose
+  export type SetTailnetExposureRes = null
+
+  // diagnostics
+
+  export type DiagnoseNetworkReq = {} // net.diagnose
+  export type DiagnoseNetworkRes = NetworkReport
+}
+
+export interface NetworkCheck {
+  status: 'passed' | 'failed' | 'unknown'
+  message: string
+}
+
+export interface NetworkReport {
+  dns: NetworkCheck
+  gateway: NetworkCheck
+  tor: NetworkCheck
+  mdns: NetworkCheck
+  'public-ip': string | null
+  ports: { [port: string]: NetworkCheck }
+}
+
+export interface AvailableUpdate {
+  title: string
+  current: string
+  latest: string
+  'marketplace-url': string
+  channel: ReleaseChannel
+  held: boolean
 }
 
 export interface MarketplaceEOS {
@@ -418,8 +558,25 @@ export interface ServerNotification<T extends number> {
   title: string
   message: string
   data: NotificationData<T>
+  actions: NotificationAction[]
+  occurrences: number
+  'last-seen': string
+  'read-at': string | null
 }
 
+export type NotificationAction =
+  | {
+      type: 'rpc'
+      label: string
+      method: string
+      params: any
+    }
+  | {
+      type: 'route'
+      label: string
+      route: string
+    }
+
 export enum NotificationLevel {
   Success = 'success',
   Info = 'info',
@@ -431,6 +588,8 @@ export type NotificationData<T> = T extends 0
   ? null
   : T extends 1
   ? BackupReport
+  : T extends 3
+  ? UpdateReport
   : any
 
 export interface BackupReport {
@@ -440,11 +599,37 @@ export interface BackupReport {
   }
   packages: {
     [id: string]: {
+      label?: string | null
       error: string | null
     }
   }
 }
 
+export interface DependencyGraph {
+  nodes: {
+    [id: string]: {
+      title: string
+      version: string
+      configured: boolean
+      main: MainStatus
+    }
+  }
+  edges: {
+    dependent: string
+    dependency: string
+    version: string | null
+    requirement: { type: 'opt-in' | 'opt-out' | 'required'; how?: string } | null
+    'health-checks': string[]
+    error: DependencyError | null
+  }[]
+}
+
+export interface UpdateReport {
+  updated: { [id: string]: string } // version
+  failed: { [id: string]: string } // error
+  skipped: { [id: string]: string } // reason
+}
+
 export interface AvailableWifi {
   ssid: string
   strength: number
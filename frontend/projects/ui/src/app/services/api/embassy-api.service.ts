@@ -45,6 +45,14 @@ export abstract class ApiService {
 
   abstract login(params: RR.LoginReq): Promise<RR.loginRes>
 
+  abstract startOidcLogin(
+    params: RR.StartOidcLoginReq,
+  ): Promise<RR.StartOidcLoginRes>
+
+  abstract completeOidcLogin(
+    params: RR.CompleteOidcLoginReq,
+  ): Promise<RR.CompleteOidcLoginRes>
+
   abstract logout(params: RR.LogoutReq): Promise<RR.LogoutRes>
 
   abstract getSessions(params: RR.GetSessionsReq): Promise<RR.GetSessionsRes>
@@ -129,12 +137,70 @@ export abstract class ApiService {
 
   abstract getEos(): Promise<RR.GetMarketplaceEosRes>
 
+  abstract searchMarketplace(
+    params: RR.SearchMarketplaceReq,
+  ): Promise<RR.SearchMarketplaceRes>
+
+  abstract getMarketplaceCategories(
+    params: RR.GetMarketplaceCategoriesReq,
+  ): Promise<RR.GetMarketplaceCategoriesRes>
+
+  abstract setRegistryChannel(
+    params: RR.SetRegistryChannelReq,
+  ): Promise<RR.SetRegistryChannelRes>
+
+  abstract setPackageChannel(
+    params: RR.SetPackageChannelReq,
+  ): Promise<RR.SetPackageChannelRes>
+
+  abstract setRegistryTorOnly(
+    params: RR.SetRegistryTorOnlyReq,
+  ): Promise<RR.SetRegistryTorOnlyRes>
+
+  abstract serveRegistry(
+    params: RR.ServeRegistryReq,
+  ): Promise<RR.ServeRegistryRes>
+
+  // wireguard
+
+  abstract addWireguardPeer(
+    params: RR.AddWireguardPeerReq,
+  ): Promise<RR.AddWireguardPeerRes>
+
+  abstract removeWireguardPeer(
+    params: RR.RemoveWireguardPeerReq,
+  ): Promise<RR.RemoveWireguardPeerRes>
+
+  abstract getWireguardConfig(
+    params: RR.GetWireguardConfigReq,
+  ): Promise<RR.GetWireguardConfigRes>
+
+  // tailscale
+
+  abstract setTailnetExposure(
+    params: RR.SetTailnetExposureReq,
+  ): Promise<RR.SetTailnetExposureRes>
+
+  // diagnostics
+
+  abstract diagnoseNetwork(
+    params: RR.DiagnoseNetworkReq,
+  ): Promise<RR.DiagnoseNetworkRes>
+
   // notification
 
   abstract getNotifications(
     params: RR.GetNotificationsReq,
   ): Promise<RR.GetNotificationsRes>
 
+  abstract markNotificationsRead(
+    params: RR.MarkNotificationsReadReq,
+  ): Promise<RR.MarkNotificationsReadRes>
+
+  abstract runNotificationAction(
+    params: RR.RunNotificationActionReq,
+  ): Promise<RR.RunNotificationActionRes>
+
   abstract deleteNotification(
     params: RR.DeleteNotificationReq,
   ): Promise<RR.DeleteNotificationRes>
@@ -246,11 +312,25 @@ export abstract class ApiService {
     params: RR.UninstallPackageReq,
   ): Promise<RR.UninstallPackageRes>
 
+  abstract setPackageLabel(
+    params: RR.SetPackageLabelReq,
+  ): Promise<RR.SetPackageLabelRes>
+
+  abstract getAvailableUpdates(
+    params: RR.GetAvailableUpdatesReq,
+  ): Promise<RR.GetAvailableUpdatesRes>
+
   abstract dryConfigureDependency(
     params: RR.DryConfigureDependencyReq,
   ): Promise<RR.DryConfigureDependencyRes>
 
-  abstract sideloadPackage(
-    params: RR.SideloadPackageReq,
-  ): Promise<RR.SideloadPacakgeRes>
+  abstract initUpload(params: RR.InitUploadReq): Promise<RR.InitUploadRes>
+
+  abstract appendUpload(params: RR.AppendUploadReq): Promise<RR.AppendUploadRes>
+
+  abstract getUploadStatus(
+    params: RR.GetUploadStatusReq,
+  ): Promise<RR.GetUploadStatusRes>
+
+  abstract commitUpload(params: RR.CommitUploadReq): Promise<RR.CommitUploadRes>
 }
@@ -82,6 +82,18 @@ export class LiveApiService extends ApiService {
     return this.rpcRequest({ method: 'auth.login', params }, false)
   }
 
+  async startOidcLogin(
+    params: RR.StartOidcLoginReq,
+  ): Promise<RR.StartOidcLoginRes> {
+    return this.rpcRequest({ method: 'auth.oidc.start', params }, false)
+  }
+
+  async completeOidcLogin(
+    params: RR.CompleteOidcLoginReq,
+  ): Promise<RR.CompleteOidcLoginRes> {
+    return this.rpcRequest({ method: 'auth.oidc.callback', params }, false)
+  }
+
   async logout(params: RR.LogoutReq): Promise<RR.LogoutRes> {
     return this.rpcRequest({ method: 'auth.logout', params })
   }
@@ -231,6 +243,81 @@ export class LiveApiService extends ApiService {
     )
   }
 
+  async searchMarketplace(
+    params: RR.SearchMarketplaceReq,
+  ): Promise<RR.SearchMarketplaceRes> {
+    return this.rpcRequest({ method: 'marketplace.search', params })
+  }
+
+  async getMarketplaceCategories(
+    params: RR.GetMarketplaceCategoriesReq,
+  ): Promise<RR.GetMarketplaceCategoriesRes> {
+    return this.rpcRequest({ method: 'marketplace.categories', params })
+  }
+
+  async setRegistryChannel(
+    params: RR.SetRegistryChannelReq,
+  ): Promise<RR.SetRegistryChannelRes> {
+    return this.rpcRequest({
+      method: 'marketplace.channel.set-registry',
+      params,
+    })
+  }
+
+  async setPackageChannel(
+    params: RR.SetPackageChannelReq,
+  ): Promise<RR.SetPackageChannelRes> {
+    return this.rpcRequest({ method: 'marketplace.channel.set-package', params })
+  }
+
+  async setRegistryTorOnly(
+    params: RR.SetRegistryTorOnlyReq,
+  ): Promise<RR.SetRegistryTorOnlyRes> {
+    return this.rpcRequest({ method: 'marketplace.tor-only', params })
+  }
+
+  async serveRegistry(
+    params: RR.ServeRegistryReq,
+  ): Promise<RR.ServeRegistryRes> {
+    return this.rpcRequest({ method: 'marketplace.serve', params })
+  }
+
+  // wireguard
+
+  async addWireguardPeer(
+    params: RR.AddWireguardPeerReq,
+  ): Promise<RR.AddWireguardPeerRes> {
+    return this.rpcRequest({ method: 'net.wireguard.peer.add', params })
+  }
+
+  async removeWireguardPeer(
+    params: RR.RemoveWireguardPeerReq,
+  ): Promise<RR.RemoveWireguardPeerRes> {
+    return this.rpcRequest({ method: 'net.wireguard.peer.remove', params })
+  }
+
+  async getWireguardConfig(
+    params: RR.GetWireguardConfigReq,
+  ): Promise<RR.GetWireguardConfigRes> {
+    return this.rpcRequest({ method: 'net.wireguard.peer.config', params })
+  }
+
+  // tailscale
+
+  async setTailnetExposure(
+    params: RR.SetTailnetExposureReq,
+  ): Promise<RR.SetTailnetExposureRes> {
+    return this.rpcRequest({ method: 'net.tailscale.expose', params })
+  }
+
+  // diagnostics
+
+  async diagnoseNetwork(
+    params: RR.DiagnoseNetworkReq,
+  ): Promise<RR.DiagnoseNetworkRes> {
+    return this.rpcRequest({ method: 'net.diagnose', params })
+  }
+
   // notification
 
   async getNotifications(
@@ -239,6 +326,18 @@ export class LiveApiService extends ApiService {
     return this.rpcRequest({ method: 'notification.list', params })
   }
 
+  async markNotificationsRead(
+    params: RR.MarkNotificationsReadReq,
+  ): Promise<RR.MarkNotificationsReadRes> {
+    return this.rpcRequest({ method: 'notification.mark-read', params })
+  }
+
+  async runNotificationAction(
+    params: RR.RunNotificationActionReq,
+  ): Promise<RR.RunNotificationActionRes> {
+    return this.rpcRequest({ method: params.method, params: params.params })
+  }
+
   async deleteNotification(
     params: RR.DeleteNotificationReq,
   ): Promise<RR.DeleteNotificationRes> {
@@ -422,6 +521,18 @@ export class LiveApiService extends ApiService {
     return this.rpcRequest({ method: 'package.uninstall', params })
   }
 
+  async setPackageLabel(
+    params: RR.SetPackageLabelReq,
+  ): Promise<RR.SetPackageLabelRes> {
+    return this.rpcRequest({ method: 'package.label', params })
+  }
+
+  async getAvailableUpdates(
+    params: RR.GetAvailableUpdatesReq,
+  ): Promise<RR.GetAvailableUpdatesRes> {
+    return this.rpcRequest({ method: 'package.updates.available', params })
+  }
+
   async dryConfigureDependency(
     params: RR.DryConfigureDependencyReq,
   ): Promise<RR.DryConfigureDependencyRes> {
@@ -431,13 +542,22 @@ export class LiveApiService extends ApiService {
     })
   }
 
-  async sideloadPackage(
-    params: RR.SideloadPackageReq,
-  ): Promise<RR.SideloadPacakgeRes> {
-    return this.rpcRequest({
-      method: 'package.sideload',
-      params,
-    })
+  async initUpload(params: RR.InitUploadReq): Promise<RR.InitUploadRes> {
+    return this.rpcRequest({ method: 'package.upload.init', params })
+  }
+
+  async appendUpload(params: RR.AppendUploadReq): Promise<RR.AppendUploadRes> {
+    return this.rpcRequest({ method: 'package.upload.append', params })
+  }
+
+  async getUploadStatus(
+    params: RR.GetUploadStatusReq,
+  ): Promise<RR.GetUploadStatusRes> {
+    return this.rpcRequest({ method: 'package.upload.status', params })
+  }
+
+  async commitUpload(params: RR.CommitUploadReq): Promise<RR.CommitUploadRes> {
+    return this.rpcRequest({ method: 'package.upload.commit', params })
   }
 
   private openWebsocket<T>(config: WebSocketSubjectConfig<T>): Observable<T> {
@@ -141,6 +141,25 @@ export class MockApiService extends ApiService {
     return null
   }
 
+  async startOidcLogin(
+    params: RR.StartOidcLoginReq,
+  ): Promise<RR.StartOidcLoginRes> {
+    await pauseFor(2000)
+    return `${params['redirect-uri']}?code=mock-code&state=mock-state`
+  }
+
+  async completeOidcLogin(
+    params: RR.CompleteOidcLoginReq,
+  ): Promise<RR.CompleteOidcLoginRes> {
+    await pauseFor(2000)
+
+    setTimeout(() => {
+      this.mockWsSource$.next({ id: 1, value: mockPatchData })
+    }, 2000)
+
+    return null
+  }
+
   async logout(params: RR.LogoutReq): Promise<RR.LogoutRes> {
     await pauseFor(2000)
     return null
@@ -383,11 +402,176 @@ export class MockApiService extends ApiService {
     return Mock.MarketplaceEos
   }
 
+  async searchMarketplace(
+    params: RR.SearchMarketplaceReq,
+  ): Promise<RR.SearchMarketplaceRes> {
+    await pauseFor(2000)
+    const query = params.query?.toLowerCase()
+    return {
+      packages: Mock.MarketplacePkgsList.filter(
+        pkg =>
+          (!query || pkg.manifest.title.toLowerCase().includes(query)) &&
+          (!params.category || pkg.categories.includes(params.category)),
+      ).map(pkg => ({
+        registry: 'https://registry.start9.com/',
+        package: pkg,
+      })),
+      errors: {},
+    }
+  }
+
+  async getMarketplaceCategories(
+    params: RR.GetMarketplaceCategoriesReq,
+  ): Promise<RR.GetMarketplaceCategoriesRes> {
+    await pauseFor(2000)
+    return {
+      categories: {
+        bitcoin: ['https://registry.start9.com/'],
+        lightning: ['https://registry.start9.com/'],
+        featured: ['https://registry.start9.com/'],
+      },
+      errors: {},
+    }
+  }
+
+  async setRegistryChannel(
+    params: RR.SetRegistryChannelReq,
+  ): Promise<RR.SetRegistryChannelRes> {
+    await pauseFor(2000)
+    // json pointer escaping
+    const key = params.registry.replace(/~/g, '~0').replace(/\//g, '~1')
+    const patch = [
+      {
+        op: PatchOp.ADD,
+        path: `/server-info/release-channels/registries/${key}`,
+        value: params.channel,
+      },
+    ]
+    return this.withRevision(patch, null)
+  }
+
+  async setPackageChannel(
+    params: RR.SetPackageChannelReq,
+  ): Promise<RR.SetPackageChannelRes> {
+    await pauseFor(2000)
+    const path = `/server-info/release-channels/packages/${params.id}`
+    const patch = params.channel
+      ? [{ op: PatchOp.ADD, path, value: params.channel }]
+      : [{ op: PatchOp.REMOVE, path }]
+    return this.withRevision(patch as Operation<string>[], null)
+  }
+
+  async setRegistryTorOnly(
+    params: RR.SetRegistryTorOnlyReq,
+  ): Promise<RR.SetRegistryTorOnlyRes> {
+    await pauseFor(2000)
+    const patch = [
+      {
+        op: PatchOp.REPLACE,
+        path: '/server-info/tor-only-registries',
+        value: params.enable ? [params.registry] : [],
+      },
+    ]
+    return this.withRevision(patch, null)
+  }
+
+  async serveRegistry(
+    params: RR.ServeRegistryReq,
+  ): Promise<RR.ServeRegistryRes> {
+    await pauseFor(2000)
+    const patch = [
+      {
+        op: PatchOp.REPLACE,
+        path: '/server-info/registry-server',
+        value: {
+          enabled: params.enable,
+          'tor-only': params.enable && params['tor-only'],
+        },
+      },
+    ]
+    return this.withRevision(patch, null)
+  }
+
+  // wireguard
+
+  async addWireguardPeer(
+    params: RR.AddWireguardPeerReq,
+  ): Promise<RR.AddWireguardPeerRes> {
+    await pauseFor(2000)
+    const patch = [
+      {
+        op: PatchOp.ADD,
+        path: `/server-info/wireguard/peers/${params.name}`,
+        value: {
+          address: '10.232.0.2',
+          'public-key': 'Vf0vq3m2xKOU/mGdEik5yK4bH0lu1DKC0Jy9T3pJ3l4=',
+          'added-at': new Date().toISOString(),
+        },
+      },
+    ]
+    return this.withRevision(patch, Mock.WireguardConfig)
+  }
+
+  async removeWireguardPeer(
+    params: RR.RemoveWireguardPeerReq,
+  ): Promise<RR.RemoveWireguardPeerRes> {
+    await pauseFor(2000)
+    const patch: RemoveOperation[] = [
+      {
+        op: PatchOp.REMOVE,
+        path: `/server-info/wireguard/peers/${params.name}`,
+      },
+    ]
+    return this.withRevision(patch, null)
+  }
+
+  async getWireguardConfig(
+    params: RR.GetWireguardConfigReq,
+  ): Promise<RR.GetWireguardConfigRes> {
+    await pauseFor(2000)
+    return Mock.WireguardConfig
+  }
+
+  // tailscale
+
+  async setTailnetExposure(
+    params: RR.SetTailnetExposureReq,
+  ): Promise<RR.SetTailnetExposureRes> {
+    await pauseFor(2000)
+    const patch = [
+      {
+        op: PatchOp.REPLACE,
+        path: `/server-info/tailscale/exposed/${params.package}`,
+        value: params.enable ? [params.interface] : [],
+      },
+    ]
+    return this.withRevision(patch, null)
+  }
+
+  // diagnostics
+
+  async diagnoseNetwork(
+    params: RR.DiagnoseNetworkReq,
+  ): Promise<RR.DiagnoseNetworkRes> {
+    await pauseFor(4000)
+    return Mock.NetworkReport
+  }
+
   // notification
 
   async getNotifications(
     params: RR.GetNotificationsReq,
   ): Promise<RR.GetNotificationsRes> {
+    await pauseFor(2000)
+    return {
+      notifications: Mock.Notifications,
+      'next-cursor': null,
+    }
+  }
+
+  async markNotificationsRead(
+    params: RR.MarkNotificationsReadReq,
+  ): Promise<RR.MarkNotificationsReadRes> {
     await pauseFor(2000)
     const patch = [
       {
@@ -396,7 +580,14 @@ export class MockApiService extends ApiService {
         value: 0,
       },
     ]
-    return this.withRevision(patch, Mock.Notifications)
+    return this.withRevision(patch, null)
+  }
+
+  async runNotificationAction(
+    params: RR.RunNotificationActionReq,
+  ): Promise<RR.RunNotificationActionRes> {
+    await pauseFor(2000)
+    return null
   }
 
   async deleteNotification(
@@ -932,6 +1123,39 @@ export class MockApiService extends ApiService {
     return this.withRevision(patch)
   }
 
+  async setPackageLabel(
+    params: RR.SetPackageLabelReq,
+  ): Promise<RR.SetPackageLabelRes> {
+    await pauseFor(2000)
+    const patch = [
+      {
+        op: PatchOp.REPLACE,
+        path: `/package-data/${params.id}/installed/label`,
+        value: params.label || null,
+      },
+    ]
+    return this.withRevision(patch)
+  }
+
+  async getAvailableUpdates(
+    params: RR.GetAvailableUpdatesReq,
+  ): Promise<RR.GetAvailableUpdatesRes> {
+    await pauseFor(2000)
+    return {
+      updates: {
+        lnd: {
+          title: 'Lightning Network Daemon',
+          current: '0.11.0',
+          latest: '0.11.1',
+          'marketplace-url': 'https://registry.start9.com/',
+          channel: 'stable',
+          held: false,
+        },
+      },
+      errors: {},
+    }
+  }
+
   async dryConfigureDependency(
     params: RR.DryConfigureDependencyReq,
   ): Promise<RR.DryConfigureDependencyRes> {
@@ -943,13 +1167,28 @@ export class MockApiService extends ApiService {
     }
   }
 
-  async sideloadPackage(
-    params: RR.SideloadPackageReq,
-  ): Promise<RR.SideloadPacakgeRes> {
+  async initUpload(params: RR.InitUploadReq): Promise<RR.InitUploadRes> {
     await pauseFor(2000)
     return '4120e092-05ab-4de2-9fbd-c3f1f4b1df9e' // no significance, randomly generated
   }
 
+  async appendUpload(params: RR.AppendUploadReq): Promise<RR.AppendUploadRes> {
+    await pauseFor(500)
+    return 'bb7f5b1c-4a3e-4e57-8d1e-4f0a2c6e9d3a' // no significance, randomly generated
+  }
+
+  async getUploadStatus(
+    params: RR.GetUploadStatusReq,
+  ): Promise<RR.GetUploadStatusRes> {
+    await pauseFor(500)
+    return { size: 0, received: 0 }
+  }
+
+  async commitUpload(params: RR.CommitUploadReq): Promise<RR.CommitUploadRes> {
+    await pauseFor(2000)
+    return null
+  }
+
   private async updateProgress(id: string): Promise<void> {
     const progress = { ...PROGRESS }
     const phases = [
@@ -55,7 +55,8 @@ export const mockPatchData: DataModel = {
       },
       wlan0: {
         ipv4: '10.0.90.12',
-        ipv6: 'FE80:CD00:0000:0CDE:1257:0000:211E:729CD',
+        ipv6: '2001:db8:cd00:cde:1257:0:211e:729c',
+        'ipv6-ula': 'fd12:3456:789a:0:1257:0:211e:729c',
       },
     },
     'last-wifi-region': null,
@@ -74,6 +75,8 @@ export const mockPatchData: DataModel = {
     'ca-fingerprint': 'SHA-256: 63 2B 11 99 44 40 17 DF 37 FC C3 DF 0F 3D 15',
     'system-start-time': new Date(new Date().valueOf() - 360042).toUTCString(),
     zram: false,
+    'tor-only-registries': [],
+    'release-channels': { registries: {}, packages: {} },
   },
   'package-data': {
     bitcoind: {
@@ -203,11 +203,13 @@ export class MarketplaceService implements AbstractMarketplaceService {
     id: string,
     version: string,
     url: string,
+    acceptLicense = false,
   ): Promise<void> {
     const params: RR.InstallPackageReq = {
       id,
       'version-spec': `=${version}`,
       'marketplace-url': url,
+      'accept-license': acceptLicense,
     }
 
     await this.api.installPackage(params)
@@ -78,12 +78,226 @@ export interface ServerInfo {
   'ca-fingerprint': string
   'system-start-time': string
   zram: boolean
+  'license-acceptances'?: LicenseAcceptance[]
+  'registry-mirrors'?: { [registry: string]: MirrorStatus[] } // registry itself first
+  'offline-registries'?: { [registry: string]: string } // when their cached data was fetched
+  'tor-only-registries'?: string[]
+  'tor-bridges'?: TorBridges
+  'release-channels'?: ReleaseChannels // stable unless listed
+  'version-constraints'?: { [id: string]: string } // emver ranges
+  'registry-server'?: RegistryServer
+  acme?: AcmeSettings
+  wireguard?: WireguardSettings
+  tailscale?: TailscaleSettings
+  ddns?: DdnsSettings
+  'proxy-directives'?: {
+    [packageId: string]: { [interfaceId: string]: ProxyDirectives }
+  }
+  mdns?: MdnsSettings
+  'ip-config'?: { [iface: string]: IpConfig }
+  firewall?: FirewallSettings
+  'encrypted-dns'?: EncryptedDnsSettings
+  exposure?: {
+    [packageId: string]: { [interfaceId: string]: Exposure } // reachable everywhere if absent
+  }
+  https?: HttpsSettings
+  egress?: EgressSettings
+  'rate-limits'?: {
+    [packageId: string]: { [interfaceId: string]: RateLimit } // unlimited if absent
+  }
+  'access-logs'?: {
+    [packageId: string]: string[] // interface ids whose requests are logged
+  }
+  'access-point'?: {
+    enabled: boolean // brought up on the Wi-Fi interface while offline
+    ssid: string | null
+  }
+  isolation?: IsolationSettings
+}
+
+export interface ProxyDirectives {
+  headers: { [name: string]: string } // set on every request
+  'max-body-size': number | null // bytes
+  'idle-timeout': number | null // seconds
+  'path-rewrites': { [from: string]: string } // path prefixes
+}
+
+export interface MdnsSettings {
+  aliases: { [alias: string]: { package: string; interface: string } } // served at '{alias}.local'
+  services: { [name: string]: MdnsService }
+}
+
+export interface MdnsService {
+  package: string
+  interface: string
+  type: string // e.g. '_http._tcp'
+  port: number
+}
+
+export interface IpConfig {
+  ipv4: string | null // e.g. '192.168.1.10/24', from DHCP if null
+  'ipv4-gateway': string | null
+  ipv6: string | null
+  'ipv6-gateway': string | null
+  dns: string[] // from the network if empty
+}
+
+export interface FirewallSettings {
+  enabled: boolean
+  rules: FirewallRule[] // checked in order
+}
+
+export interface FirewallRule {
+  action: 'allow' | 'deny'
+  protocol: 'tcp' | 'udp'
+  port: number | null // every port if null
+  source: string | null // e.g. '192.168.1.0/24', every address if null
+}
+
+export interface IsolationSettings {
+  rules: IsolationRule[] // checked in order, dependencies are reachable without one
+}
+
+export interface IsolationRule {
+  action: 'allow' | 'deny'
+  package: string
+  target: string
+  interface: string | null // every interface of target if null
+}
+
+export interface EncryptedDnsSettings {
+  enabled: boolean
+  protocol: 'tls' | 'https'
+  providers: {
+    address: string
+    hostname: string // the name its certificate is for
+  }[] // asked in order
+  fallback: boolean // unencrypted if no provider answers
+}
+
+export interface Exposure {
+  lan: boolean
+  tor: boolean
+  clearnet: boolean
+}
+
+export interface RateLimit {
+  'requests-per-minute': number | null // per client
+  burst: number | null // the rate if null
+  'max-connections': number | null
+  'max-client-connections': number | null // streams per circuit over tor
+}
+
+export interface HttpsSettings {
+  global: HttpsPolicy // for the OS, and interfaces without their own
+  interfaces: {
+    [packageId: string]: { [interfaceId: string]: HttpsPolicy }
+  }
+}
+
+export interface HttpsPolicy {
+  redirect: boolean // permanently, only at domains with a trusted certificate
+  hsts: {
+    'max-age': number // seconds
+    'include-subdomains': boolean
+  } | null
+}
+
+export interface EgressSettings {
+  tunnel: {
+    address: string // e.g. '10.64.1.2/32'
+    dns: string | null
+    'public-key': string // of the VPN server
+    endpoint: string
+  } | null
+  packages: { [packageId: string]: 'tor' | 'wireguard' } // direct if absent
+  down: ('tor' | 'wireguard')[] // their packages are cut off until they are back
+}
+
+export interface TorBridges {
+  enabled: boolean
+  bridges: string[] // bridge lines, e.g. 'obfs4 192.0.2.1:443 <fingerprint> cert=... iat-mode=0'
+}
+
+export interface DdnsSettings {
+  hostnames: { [hostname: string]: DdnsHost }
+}
+
+export interface DdnsHost {
+  provider: 'duckdns' | 'cloudflare' | 'http'
+  ipv6: boolean
+  'ipv4-address': string | null
+  'ipv6-address': string | null
+  'updated-at': string | null
+  error: string | null
+}
+
+export interface TailscaleSettings {
+  enabled: boolean
+  'login-server': string | null // headscale
+  hostname: string | null
+  exposed: { [packageId: string]: string[] } // interface ids
+}
+
+export interface WireguardSettings {
+  enabled: boolean
+  port: number
+  endpoint: string | null
+  'public-key': string | null
+  peers: { [name: string]: WireguardPeer }
+}
+
+export interface WireguardPeer {
+  address: string
+  'public-key': string
+  'added-at': string
+}
+
+export interface AcmeSettings {
+  provider: string | null
+  email: string | null
+  domains: { [domain: string]: AcmeDomain }
+  'dns-providers'?: { [zone: string]: 'cloudflare' | 'desec' | 'rfc2136' }
+}
+
+export interface AcmeDomain {
+  challenge: 'http-01' | 'tls-alpn-01' | 'dns-01'
+  target: { package: string; interface: string } | null // StartOS UI when null
+  'expires-at': string | null
+  error: string | null
+}
+
+export interface RegistryServer {
+  enabled: boolean
+  'tor-only': boolean
+}
+
+export type ReleaseChannel = 'stable' | 'beta'
+
+export interface ReleaseChannels {
+  registries: { [registry: string]: ReleaseChannel }
+  packages: { [id: string]: ReleaseChannel } // overrides their registry
+}
+
+export interface MirrorStatus {
+  url: string
+  reachable: boolean | null
+  'checked-at': string | null
+  error: string | null
+}
+
+export interface LicenseAcceptance {
+  'package-id': string
+  version: string
+  sha256: string // of the accepted LICENSE.md
+  'accepted-at': string
 }
 
 export interface IpInfo {
   [iface: string]: {
     ipv4: string | null
     ipv6: string | null
+    'ipv6-ula'?: string | null
   }
 }
 
@@ -95,6 +309,16 @@ export interface ServerStatusInfo {
   }
   updated: boolean
   'update-progress': { size: number | null; downloaded: number } | null
+  'vanity-progress'?: VanityProgress | null
+}
+
+export interface VanityProgress {
+  package: string
+  interface: string
+  prefix: string
+  attempts: number
+  expected: number // on average
+  'started-at': string
 }
 
 export enum ServerStatus {
@@ -137,12 +361,37 @@ export interface InstalledPackageDataEntry {
     }
   }
   'interface-addresses': {
-    [id: string]: { 'tor-address': string; 'lan-address': string }
+    [id: string]: {
+      'tor-address': string
+      'lan-address': string
+      'clearnet-addresses'?: string[]
+      'i2p-address'?: string | null
+    }
   }
   'marketplace-url': string | null
+  held?: boolean
+  'disk-usage'?: PackageDiskUsage | null
+  'instance-of'?: string | null
+  label?: string | null
+  'resource-limits'?: ResourceLimits // overrides the manifest
+  'approved-devices'?: ('dri' | 'nvidia')[] | null
   'developer-key': string
 }
 
+export interface ResourceLimits {
+  'cpu-shares': number | null // relative to 1024
+  'memory-limit-mb': number | null
+}
+
+export interface PackageDiskUsage {
+  volumes: { [id: string]: number }
+  images: number
+  archives: number
+  rollback: number
+  backup: number | null
+  'measured-at': string
+}
+
 export interface CurrentDependencyInfo {
   pointers: any[]
   'health-checks': string[] // array of health check IDs
@@ -243,6 +492,7 @@ export interface InterfaceDef {
   description: string
   'tor-config': TorConfig | null
   'lan-config': LanConfig | null
+  'raw-config'?: RawConfig | null
   ui: boolean
   protocols: string[]
 }
@@ -252,7 +502,11 @@ export interface TorConfig {
 }
 
 export type LanConfig = {
-  [port: number]: { ssl: boolean; mapping: number }
+  [port: number]: { ssl: boolean; mapping: number; passthrough?: boolean }
+}
+
+export type RawConfig = {
+  [port: number]: { internal: number; protocols: ('tcp' | 'udp')[] }
 }
 
 export interface BackupActions {
@@ -416,4 +670,19 @@ export interface InstallProgress {
   readonly 'validation-complete': boolean
   readonly unpacked: number
   readonly 'unpack-complete': boolean
+  readonly phase?: InstallPhase
+  readonly phases?: Partial<Record<InstallPhase, PhaseProgress>>
+}
+
+export type InstallPhase =
+  | 'download'
+  | 'verify'
+  | 'unpack'
+  | 'load-images'
+  | 'configure'
+
+export interface PhaseProgress {
+  readonly done: number
+  readonly total: number | null
+  readonly complete: boolean
 }
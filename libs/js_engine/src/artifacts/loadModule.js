@@ -193,6 +193,12 @@ const diskUsage = async ({
   return { used, total }
 }
 
+const notify = ({
+  level = requireParam("level"),
+  title = requireParam("title"),
+  message = requireParam("message"),
+} = requireParam("options")) => Deno.core.opAsync("notify", level, title, message);
+
 const currentFunction = Deno.core.ops.current_function();
 const input = Deno.core.ops.get_input();
 const variable_args = Deno.core.ops.get_variable_args();
@@ -223,6 +229,7 @@ const effects = {
   runRsync,
   readDir,
   diskUsage,
+  notify,
 };
 
 const defaults = {
@@ -36,6 +36,11 @@ pub trait PathForVolumeId: Send + Sync {
     fn readonly(&self, volume_id: &VolumeId) -> bool;
 }
 
+/// Raises notifications on behalf of the package running the script
+pub trait Notifier: Send + Sync {
+    fn notify(&self, level: &str, title: String, message: String) -> Result<(), String>;
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct JsCode(Arc<str>);
 
@@ -103,6 +108,7 @@ struct JsContext {
     variable_args: Vec<serde_json::Value>,
     container_process_gid: ProcessGroupId,
     container_rpc_client: Option<Arc<UnixRpcClient>>,
+    notifier: Option<Arc<dyn Notifier>>,
     rsyncs: Arc<Mutex<(usize, BTreeMap<usize, Rsync>)>>,
 }
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -185,6 +191,7 @@ pub struct JsExecutionEnvironment {
     volumes: Arc<dyn PathForVolumeId>,
     container_process_gid: ProcessGroupId,
     container_rpc_client: Option<Arc<UnixRpcClient>>,
+    notifier: Option<Arc<dyn Notifier>>,
 }
 
 impl JsExecutionEnvironment {
@@ -230,12 +237,17 @@ impl JsExecutionEnvironment {
             sandboxed: false,
             container_process_gid,
             container_rpc_client,
+            notifier: None,
         })
     }
     pub fn read_only_effects(mut self) -> Self {
         self.sandboxed = true;
         self
     }
+    pub fn with_notifier(mut self, notifier: Option<Arc<dyn Notifier>>) -> Self {
+        self.notifier = notifier;
+        self
+    }
 
     pub async fn run_action<I: Serialize, O: for<'de> Deserialize<'de>>(
         self,
@@ -303,6 +315,7 @@ impl JsExecutionEnvironment {
             fns::rsync::decl(),
             fns::rsync_wait::decl(),
             fns::rsync_progress::decl(),
+            fns::notify::decl(),
         ]
     }
 
@@ -334,6 +347,7 @@ impl JsExecutionEnvironment {
             variable_args,
             container_process_gid: self.container_process_gid,
             container_rpc_client: self.container_rpc_client.clone(),
+            notifier: self.notifier.clone(),
             rsyncs: Default::default(),
         };
         let ext = Extension::builder("embassy")
@@ -1273,6 +1287,29 @@ mod fns {
         }
     }
 
+    #[op]
+    async fn notify(
+        state: Rc<RefCell<OpState>>,
+        level: String,
+        title: String,
+        message: String,
+    ) -> Result<(), AnyError> {
+        let ctx = {
+            let state = state.borrow();
+            state.borrow::<JsContext>().clone()
+        };
+        if ctx.sandboxed {
+            bail!("Will not run notify in sandboxed mode");
+        }
+        let notifier = match ctx.notifier {
+            Some(a) => a,
+            None => bail!("Notifications are not available to this procedure"),
+        };
+        notifier
+            .notify(&level, title, message)
+            .map_err(|e| anyhow!("{}", e))
+    }
+
     #[op]
     async fn sleep(time_ms: u64) -> Result<(), AnyError> {
         tokio::time::sleep(Duration::from_millis(time_ms)).await;
@@ -77,6 +77,11 @@ pub enum ErrorKind {
     OpenSsh = 66,
     Zram = 67,
     Lshw = 68,
+    Acme = 69,
+    Wireguard = 70,
+    Tailscale = 71,
+    Ddns = 72,
+    Firewall = 73,
 }
 impl ErrorKind {
     pub fn as_str(&self) -> &'static str {
@@ -150,6 +155,11 @@ impl ErrorKind {
             OpenSsh => "OpenSSH Error",
             Zram => "Zram Error",
             Lshw => "LSHW Error",
+            Acme => "ACME Error",
+            Wireguard => "WireGuard Error",
+            Tailscale => "Tailscale Error",
+            Ddns => "Dynamic DNS Error",
+            Firewall => "Firewall Error",
         }
     }
 }